@@ -0,0 +1,71 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Benchmarks the operations of the backends through the public API.
+//!
+//! Each backend is benchmarked only if its environment variable is set:
+//!
+//! | Backend | Environment variable |
+//! | --- | --- |
+//! | VBoxManage | `HVCTRL_BENCH_VBOXMANAGE_VM` (the VM name) |
+//! | vmrun | `HVCTRL_BENCH_VMRUN_VM` (the path of the .vmx file) |
+//! | Hyper-V cmdlets | `HVCTRL_BENCH_HYPERVCMD_VM` (the VM name) |
+//!
+//! `HVCTRL_BENCH_SNAPSHOT=1` also benchmarks taking snapshots, which creates and deletes snapshots of the VMs.
+use criterion::{criterion_group, criterion_main, Criterion};
+#[allow(unused_imports)]
+use hvctrl::{bench::SNAPSHOT_PREFIX, types::*};
+use std::time::{Duration, Instant};
+
+#[allow(dead_code)]
+fn bench_backend<T: VmCmd + PowerCmd + SnapshotCmd>(
+    c: &mut Criterion,
+    backend: &str,
+    cmd: &T,
+) {
+    let mut group = c.benchmark_group(backend);
+    group.sample_size(10);
+    group
+        .bench_function("is_running", |b| b.iter(|| cmd.is_running().unwrap()));
+    group.bench_function("list_vms", |b| b.iter(|| cmd.list_vms().unwrap()));
+    if std::env::var("HVCTRL_BENCH_SNAPSHOT").as_deref() == Ok("1") {
+        group.bench_function("take_snapshot", |b| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for i in 0..iters {
+                    let name = format!("{}{}", SNAPSHOT_PREFIX, i);
+                    let s = Instant::now();
+                    cmd.take_snapshot(&name).unwrap();
+                    total += s.elapsed();
+                    cmd.delete_snapshot(&name).unwrap();
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+#[allow(unused_variables)]
+fn backends(c: &mut Criterion) {
+    #[cfg(feature = "vboxmanage")]
+    if let Ok(vm) = std::env::var("HVCTRL_BENCH_VBOXMANAGE_VM") {
+        let mut cmd = hvctrl::virtualbox::VBoxManage::new();
+        cmd.vm_name(vm);
+        bench_backend(c, "vboxmanage", &cmd);
+    }
+    #[cfg(feature = "vmrun")]
+    if let Ok(vm) = std::env::var("HVCTRL_BENCH_VMRUN_VM") {
+        let mut cmd = hvctrl::vmware::VmRun::new();
+        cmd.vm_path(vm);
+        bench_backend(c, "vmrun", &cmd);
+    }
+    #[cfg(all(windows, feature = "hypervcmd"))]
+    if let Ok(vm) = std::env::var("HVCTRL_BENCH_HYPERVCMD_VM") {
+        let mut cmd = hvctrl::hyperv::HyperVCmd::new();
+        cmd.vm_name(vm);
+        bench_backend(c, "hypervcmd", &cmd);
+    }
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
@@ -38,7 +38,7 @@ fn list_vmware_vms(exec_path: Option<&String>) {
         })
 }
 
-#[cfg(feature = "hypervcmd")]
+#[cfg(all(windows, feature = "hypervcmd"))]
 fn list_hyperv_vms(exec_path: Option<&String>) {
     use hvctrl::{hyperv::HyperVCmd, types::VmCmd};
     let mut cmd = HyperVCmd::new();
@@ -58,11 +58,12 @@ fn list_hyperv_vms(exec_path: Option<&String>) {
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
+    #[allow(unused_variables)]
     let exec_path = args.get(1);
     #[cfg(feature = "vboxmanage")]
     list_vbox_vms(exec_path);
     #[cfg(feature = "vmrun")]
     list_vmware_vms(exec_path);
-    #[cfg(feature = "hypervcmd")]
+    #[cfg(all(windows, feature = "hypervcmd"))]
     list_hyperv_vms(exec_path);
 }
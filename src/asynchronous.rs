@@ -0,0 +1,349 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Async variants of the controller traits.
+//!
+//! [`VmRest`](crate::vmware::VmRest) implements [`AsyncPowerCmd`] with the async API of reqwest.
+//! Other controllers can be used through [`Blocking`], which runs each command of the underlying tool on the blocking thread pool of tokio and waits for the VM with tokio timers, so polling loops such as [`AsyncPowerCmd::stop`] don't occupy a thread.
+//!
+//! If both [`PowerCmd`] and [`AsyncPowerCmd`] are in scope, call the methods with the trait name (e.g., `AsyncPowerCmd::start(&cmd)`).
+#![cfg(feature = "async")]
+use crate::types::*;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A boxed future returned by the async traits.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An async variant of [`PowerCmd`].
+pub trait AsyncPowerCmd: Sync {
+    /// Starts the VM and waits for the VM to start.
+    fn start(&self) -> BoxFuture<'_, VmResult<()>>;
+    /// Stops the VM softly and waits for the VM to stop.
+    ///
+    /// See [`PowerCmd::stop`] for details.
+    fn stop(&self, timeout: Option<Duration>) -> BoxFuture<'_, VmResult<()>>;
+    /// Stops the VM hardly and waits for the VM to stop.
+    fn hard_stop(&self) -> BoxFuture<'_, VmResult<()>>;
+    /// Suspends the VM and waits for the VM to suspend.
+    fn suspend(&self) -> BoxFuture<'_, VmResult<()>>;
+    /// Resumes the suspended VM.
+    fn resume(&self) -> BoxFuture<'_, VmResult<()>>;
+    /// Returns `true` if the VM is running.
+    fn is_running(&self) -> BoxFuture<'_, VmResult<bool>>;
+    /// Reboots the VM softly and waits for the VM to start.
+    fn reboot(&self, timeout: Option<Duration>) -> BoxFuture<'_, VmResult<()>>;
+    /// Reboots the VM hardly and waits for the VM to start.
+    fn hard_reboot(&self) -> BoxFuture<'_, VmResult<()>>;
+    /// Pauses the VM and waits for the VM to pause.
+    fn pause(&self) -> BoxFuture<'_, VmResult<()>>;
+    /// Unpauses the VM and waits for the VM to unpause.
+    fn unpause(&self) -> BoxFuture<'_, VmResult<()>>;
+    /// Waits for the VM to stop.
+    ///
+    /// If the VM is still running after `timeout`, returns [`ErrorKind::Timeout`].
+    fn wait_for_stop(
+        &self,
+        timeout: Option<Duration>,
+    ) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            let s = Instant::now();
+            while self.is_running().await? {
+                if let Some(timeout) = timeout {
+                    if s.elapsed() >= timeout {
+                        return vmerr!(ErrorKind::Timeout);
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// An async variant of [`SnapshotCmd`].
+pub trait AsyncSnapshotCmd {
+    /// Returns snapshots of a VM.
+    fn list_snapshots(&self) -> BoxFuture<'_, VmResult<Vec<Snapshot>>>;
+    /// Takes a snapshot of a VM.
+    fn take_snapshot<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>>;
+    /// Reverts the current VM state to a snapshot of the VM.
+    fn revert_snapshot<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>>;
+    /// Deletes a snapshot of a VM.
+    fn delete_snapshot<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>>;
+}
+
+/// An async variant of [`GuestCmd`].
+pub trait AsyncGuestCmd {
+    /// Executes a command on guest.
+    fn exec_cmd<'a>(
+        &'a self,
+        guest_args: &'a [&'a str],
+    ) -> BoxFuture<'a, VmResult<()>>;
+    /// Copies a file from a guest to a host.
+    fn copy_from_guest_to_host<'a>(
+        &'a self,
+        from_guest_path: &'a str,
+        to_host_path: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>>;
+    /// Copies a file from a host to a guest.
+    fn copy_from_host_to_guest<'a>(
+        &'a self,
+        from_host_path: &'a str,
+        to_guest_path: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>>;
+}
+
+/// Wraps a blocking controller to implement the async traits.
+///
+/// Each command of the controller runs with [`tokio::task::spawn_blocking`].
+/// [`AsyncPowerCmd::stop`] only sends the shutdown request on the blocking thread pool and waits for the VM to stop with tokio timers.
+#[derive(Debug)]
+pub struct Blocking<C> {
+    cmd: Arc<C>,
+}
+
+impl<C> Clone for Blocking<C> {
+    fn clone(&self) -> Self {
+        Self {
+            cmd: self.cmd.clone(),
+        }
+    }
+}
+
+impl<C: Send + Sync + 'static> Blocking<C> {
+    pub fn new(cmd: C) -> Self { Self { cmd: Arc::new(cmd) } }
+
+    pub fn get_ref(&self) -> &C { &self.cmd }
+
+    /// Runs `f` with the controller on the blocking thread pool.
+    pub async fn run<T, F>(&self, f: F) -> VmResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&C) -> VmResult<T> + Send + 'static,
+    {
+        let cmd = self.cmd.clone();
+        match tokio::task::spawn_blocking(move || f(&cmd)).await {
+            Ok(x) => x,
+            Err(x) => vmerr!(ErrorKind::ExecutionFailed(x.to_string())),
+        }
+    }
+}
+
+impl<C: PowerCmd + Send + Sync + 'static> AsyncPowerCmd for Blocking<C> {
+    fn start(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(self.run(|x| x.start()))
+    }
+
+    fn stop(&self, timeout: Option<Duration>) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            let s = Instant::now();
+            // A zero timeout makes the controller return right after sending the request.
+            match self.run(|x| x.stop(Duration::ZERO)).await {
+                Err(x) if x == VmError::from(ErrorKind::Timeout) => {
+                    self.wait_for_stop(
+                        timeout.map(|x| x.saturating_sub(s.elapsed())),
+                    )
+                    .await
+                }
+                x => x,
+            }
+        })
+    }
+
+    fn hard_stop(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(self.run(|x| x.hard_stop()))
+    }
+
+    fn suspend(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(self.run(|x| x.suspend()))
+    }
+
+    fn resume(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(self.run(|x| x.resume()))
+    }
+
+    fn is_running(&self) -> BoxFuture<'_, VmResult<bool>> {
+        Box::pin(self.run(|x| x.is_running()))
+    }
+
+    fn reboot(&self, timeout: Option<Duration>) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            self.stop(timeout).await?;
+            self.start().await
+        })
+    }
+
+    fn hard_reboot(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(self.run(|x| x.hard_reboot()))
+    }
+
+    fn pause(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(self.run(|x| x.pause()))
+    }
+
+    fn unpause(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(self.run(|x| x.unpause()))
+    }
+}
+
+impl<C: SnapshotCmd + Send + Sync + 'static> AsyncSnapshotCmd for Blocking<C> {
+    fn list_snapshots(&self) -> BoxFuture<'_, VmResult<Vec<Snapshot>>> {
+        Box::pin(self.run(|x| x.list_snapshots()))
+    }
+
+    fn take_snapshot<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>> {
+        let name = name.to_string();
+        Box::pin(self.run(move |x| x.take_snapshot(&name)))
+    }
+
+    fn revert_snapshot<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>> {
+        let name = name.to_string();
+        Box::pin(self.run(move |x| x.revert_snapshot(&name)))
+    }
+
+    fn delete_snapshot<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>> {
+        let name = name.to_string();
+        Box::pin(self.run(move |x| x.delete_snapshot(&name)))
+    }
+}
+
+impl<C: GuestCmd + Send + Sync + 'static> AsyncGuestCmd for Blocking<C> {
+    fn exec_cmd<'a>(
+        &'a self,
+        guest_args: &'a [&'a str],
+    ) -> BoxFuture<'a, VmResult<()>> {
+        let args: Vec<String> =
+            guest_args.iter().map(|x| x.to_string()).collect();
+        Box::pin(self.run(move |x| {
+            let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+            x.exec_cmd(&args)
+        }))
+    }
+
+    fn copy_from_guest_to_host<'a>(
+        &'a self,
+        from_guest_path: &'a str,
+        to_host_path: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>> {
+        let (from, to) =
+            (from_guest_path.to_string(), to_host_path.to_string());
+        Box::pin(self.run(move |x| x.copy_from_guest_to_host(&from, &to)))
+    }
+
+    fn copy_from_host_to_guest<'a>(
+        &'a self,
+        from_host_path: &'a str,
+        to_guest_path: &'a str,
+    ) -> BoxFuture<'a, VmResult<()>> {
+        let (from, to) =
+            (from_host_path.to_string(), to_guest_path.to_string());
+        Box::pin(self.run(move |x| x.copy_from_host_to_guest(&from, &to)))
+    }
+}
+
+#[test]
+fn test_blocking_stop() {
+    use std::sync::Mutex;
+
+    /// A VM which keeps running for two more polls after a shutdown request.
+    struct FakeCmd {
+        polls: Mutex<Option<u32>>,
+    }
+
+    impl PowerCmd for FakeCmd {
+        fn start(&self) -> VmResult<()> {
+            *self.polls.lock().unwrap() = None;
+            Ok(())
+        }
+
+        fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+            *self.polls.lock().unwrap() = Some(2);
+            self.wait_for_stop(timeout.into())
+        }
+
+        fn hard_stop(&self) -> VmResult<()> {
+            *self.polls.lock().unwrap() = Some(0);
+            Ok(())
+        }
+
+        fn suspend(&self) -> VmResult<()> {
+            vmerr!(ErrorKind::unsupported::<Self>("suspend"))
+        }
+
+        fn resume(&self) -> VmResult<()> {
+            vmerr!(ErrorKind::unsupported::<Self>("resume"))
+        }
+
+        fn is_running(&self) -> VmResult<bool> {
+            let mut polls = self.polls.lock().unwrap();
+            match *polls {
+                None => Ok(true),
+                Some(0) => Ok(false),
+                Some(x) => {
+                    *polls = Some(x - 1);
+                    Ok(true)
+                }
+            }
+        }
+
+        fn reboot<D: Into<Option<Duration>>>(&self, _: D) -> VmResult<()> {
+            vmerr!(ErrorKind::unsupported::<Self>("reboot"))
+        }
+
+        fn hard_reboot(&self) -> VmResult<()> {
+            vmerr!(ErrorKind::unsupported::<Self>("hard_reboot"))
+        }
+
+        fn pause(&self) -> VmResult<()> {
+            vmerr!(ErrorKind::unsupported::<Self>("pause"))
+        }
+
+        fn unpause(&self) -> VmResult<()> {
+            vmerr!(ErrorKind::unsupported::<Self>("unpause"))
+        }
+    }
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    let cmd = Blocking::new(FakeCmd {
+        polls: Mutex::new(None),
+    });
+    rt.block_on(async {
+        assert_eq!(cmd.is_running().await, Ok(true));
+        assert_eq!(cmd.stop(Some(Duration::from_secs(5))).await, Ok(()));
+        assert_eq!(cmd.is_running().await, Ok(false));
+        cmd.start().await.unwrap();
+        assert_eq!(
+            cmd.stop(Some(Duration::ZERO)).await,
+            vmerr!(ErrorKind::Timeout)
+        );
+        assert_eq!(
+            cmd.suspend().await,
+            vmerr!(ErrorKind::unsupported::<FakeCmd>("suspend"))
+        );
+    });
+}
@@ -0,0 +1,157 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Latency measurement of controller operations.
+//!
+//! [`measure`] runs an operation repeatedly through the public API and summarizes the latencies in a [`BenchResult`], so the costs of backends can be compared and regressions in command construction or response parsing can be caught.
+//!
+//! The `backends` benchmark runs the same operations with [criterion](https://docs.rs/criterion) against the VMs given by environment variables:
+//!
+//! ```text
+//! HVCTRL_BENCH_VBOXMANAGE_VM=MyVM HVCTRL_BENCH_VMRUN_VM=C:\VMs\MyVM\MyVM.vmx cargo bench --features bench,vboxmanage,vmrun
+//! ```
+//!
+//! `HVCTRL_BENCH_SNAPSHOT=1` also benchmarks taking snapshots, which creates and deletes snapshots of the VMs.
+#![cfg(feature = "bench")]
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// The prefix of the names of the snapshots taken by [`bench_take_snapshot`].
+pub const SNAPSHOT_PREFIX: &str = "hvctrl-bench-";
+
+/// Represents the latencies of an operation.
+///
+/// The durations are of the successful runs, and are zero if no run succeeded.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct BenchResult {
+    pub name: String,
+    /// The number of successful runs.
+    pub iterations: usize,
+    /// The number of failed runs.
+    pub errors: usize,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+    pub median: Duration,
+}
+
+impl BenchResult {
+    /// Summarizes the latencies of the successful runs.
+    pub fn from_durations(
+        name: &str,
+        mut durations: Vec<Duration>,
+        errors: usize,
+    ) -> Self {
+        durations.sort();
+        let iterations = durations.len();
+        let mut ret = Self {
+            name: name.to_string(),
+            iterations,
+            errors,
+            ..Default::default()
+        };
+        if iterations == 0 {
+            return ret;
+        }
+        ret.min = durations[0];
+        ret.max = durations[iterations - 1];
+        ret.mean = durations.iter().sum::<Duration>() / iterations as u32;
+        ret.median = if iterations % 2 == 0 {
+            (durations[iterations / 2 - 1] + durations[iterations / 2]) / 2
+        } else {
+            durations[iterations / 2]
+        };
+        ret
+    }
+}
+
+/// Runs `f` `iterations` times and measures the latency of each run.
+pub fn measure<T, F: FnMut() -> VmResult<T>>(
+    name: &str,
+    iterations: usize,
+    mut f: F,
+) -> BenchResult {
+    let mut durations = Vec::with_capacity(iterations);
+    let mut errors = 0;
+    for _ in 0..iterations {
+        let s = Instant::now();
+        match f() {
+            Ok(_) => durations.push(s.elapsed()),
+            Err(x) => {
+                debug!("{} failed: {}", name, x);
+                errors += 1;
+            }
+        }
+    }
+    BenchResult::from_durations(name, durations, errors)
+}
+
+/// Measures [`VmCmd::list_vms`].
+pub fn bench_list_vms<T: VmCmd>(cmd: &T, iterations: usize) -> BenchResult {
+    measure("list_vms", iterations, || cmd.list_vms())
+}
+
+/// Measures [`PowerCmd::is_running`].
+pub fn bench_is_running<T: PowerCmd>(
+    cmd: &T,
+    iterations: usize,
+) -> BenchResult {
+    measure("is_running", iterations, || cmd.is_running())
+}
+
+/// Measures [`SnapshotCmd::take_snapshot`].
+///
+/// Each snapshot is named with [`SNAPSHOT_PREFIX`] and deleted after its measurement.
+/// Stops at the first snapshot which cannot be deleted and returns the error.
+pub fn bench_take_snapshot<T: SnapshotCmd>(
+    cmd: &T,
+    iterations: usize,
+) -> VmResult<BenchResult> {
+    let mut durations = Vec::with_capacity(iterations);
+    let mut errors = 0;
+    for i in 0..iterations {
+        let name = format!("{}{}", SNAPSHOT_PREFIX, i);
+        let s = Instant::now();
+        match cmd.take_snapshot(&name) {
+            Ok(()) => {
+                durations.push(s.elapsed());
+                cmd.delete_snapshot(&name)?;
+            }
+            Err(x) => {
+                debug!("take_snapshot failed: {}", x);
+                errors += 1;
+            }
+        }
+    }
+    Ok(BenchResult::from_durations(
+        "take_snapshot",
+        durations,
+        errors,
+    ))
+}
+
+#[test]
+fn test_bench_result() {
+    let ms = Duration::from_millis;
+    let r = BenchResult::from_durations("a", vec![ms(30), ms(10), ms(20)], 1);
+    assert_eq!(r.iterations, 3);
+    assert_eq!(r.errors, 1);
+    assert_eq!(
+        (r.min, r.max, r.mean, r.median),
+        (ms(10), ms(30), ms(20), ms(20))
+    );
+    let r = BenchResult::from_durations("a", vec![ms(40), ms(10)], 0);
+    assert_eq!(r.median, ms(25));
+    let r = BenchResult::from_durations("a", vec![], 2);
+    assert_eq!((r.iterations, r.mean), (0, Duration::ZERO));
+    let mut n = 0;
+    let r = measure("b", 4, || {
+        n += 1;
+        if n % 2 == 0 {
+            Ok(())
+        } else {
+            vmerr!(ErrorKind::Timeout)
+        }
+    });
+    assert_eq!((r.name.as_str(), r.iterations, r.errors), ("b", 2, 2));
+}
@@ -0,0 +1,397 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! [ch-remote](https://github.com/cloud-hypervisor/cloud-hypervisor/blob/main/docs/api.md) controller.
+//!
+//! ch-remote sends requests to the REST API of a running cloud-hypervisor process through its `--api-socket`.
+//! One API socket controls one VM.
+use crate::{exec_cmd, tool_path_from_env, types::*};
+use serde_json::Value;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// Represents a cloud-hypervisor VM controlled with ch-remote.
+///
+/// Snapshots are saved as directories in the directory set by [`ChRemote::snapshot_dir`].
+#[derive(Clone, Debug)]
+pub struct ChRemote {
+    executable_path: String,
+    api_socket: String,
+    snapshot_dir: Option<String>,
+    hooks: CommandHooks,
+}
+
+impl Default for ChRemote {
+    fn default() -> Self { Self::new() }
+}
+
+impl ChRemote {
+    pub fn new() -> Self {
+        Self {
+            executable_path: tool_path_from_env(
+                "HVCTRL_CH_REMOTE_PATH",
+                "ch-remote",
+            ),
+            api_socket: "/tmp/cloud-hypervisor.sock".to_string(),
+            snapshot_dir: None,
+            hooks: CommandHooks::new(),
+        }
+    }
+
+    impl_setter!(
+        /// Sets the path to ch-remote.
+        executable_path: String
+    );
+
+    pub fn get_executable_path(&self) -> &str { &self.executable_path }
+
+    impl_setter!(
+        /// Sets the path to the API socket passed to cloud-hypervisor with `--api-socket`.
+        api_socket: String
+    );
+
+    pub fn get_api_socket(&self) -> &str { &self.api_socket }
+
+    impl_setter!(@opt
+        /// Sets the directory where snapshots are saved.
+        snapshot_dir: String
+    );
+
+    pub fn get_snapshot_dir(&self) -> Option<&str> {
+        self.snapshot_dir.as_deref()
+    }
+
+    impl_hooks!();
+
+    fn handle_error(s: &str) -> VmError {
+        if s.contains("Connection refused")
+            || s.contains("No such file or directory")
+        {
+            // cloud-hypervisor is not running.
+            return VmError::from(ErrorKind::InvalidPowerState(
+                VmPowerState::Stopped,
+            ));
+        }
+        if let Some(x) = parse_invalid_state_transition(s) {
+            return VmError::from(ErrorKind::InvalidPowerState(x));
+        }
+        VmError::from(Repr::Unknown(format!("Unknown error: {}", s)))
+    }
+
+    /// Executes `ch-remote <command> <args>` and returns stdout.
+    pub fn exec(&self, command: &str, args: &[&str]) -> VmResult<String> {
+        let hook_args: Vec<String> =
+            args.iter().map(|x| x.to_string()).collect();
+        self.hooks.run(command, &hook_args, || {
+            let mut cmd = Command::new(&self.executable_path);
+            cmd.args(["--api-socket", &self.api_socket, command]);
+            cmd.args(args);
+            let o = exec_cmd(&mut cmd)?;
+            let errors = self.hooks.classify_stderr(
+                command,
+                &o.stderr,
+                o.status.success(),
+            );
+            o.check_status(if !errors.trim().is_empty() {
+                Err(Self::handle_error(errors.trim()))
+            } else {
+                Ok(o.stdout.clone())
+            })
+        })
+    }
+
+    /// Gets the VM information with `info`.
+    pub fn info(&self) -> VmResult<Value> {
+        let s = self.exec("info", &[])?;
+        serde_json::from_str(&s)
+            .map_err(|_| VmError::from(ErrorKind::UnexpectedResponse(s)))
+    }
+
+    /// Gets the power state of the VM.
+    ///
+    /// If cloud-hypervisor is not running, returns [`VmPowerState::Stopped`].
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        match self.info() {
+            Ok(x) => match x["state"].as_str() {
+                Some(x) => Ok(to_power_state(x)),
+                None => vmerr!(ErrorKind::UnexpectedResponse(x.to_string())),
+            },
+            Err(x) if x.get_invalid_state() == Some(VmPowerState::Stopped) => {
+                Ok(VmPowerState::Stopped)
+            }
+            Err(x) => Err(x),
+        }
+    }
+
+    /// Gets the power state, the number of boot vCPUs and the memory size of the VM with `info`.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        Ok(parse_info(&self.info()?))
+    }
+
+    fn retrieve_snapshot_dir(&self) -> VmResult<&str> {
+        self.snapshot_dir.as_deref().ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(
+                "snapshot_dir".to_string(),
+            ))
+        })
+    }
+
+    fn snapshot_path(&self, name: &str) -> VmResult<PathBuf> {
+        Ok(Path::new(self.retrieve_snapshot_dir()?).join(name))
+    }
+
+    fn existing_snapshot_path(&self, name: &str) -> VmResult<PathBuf> {
+        let path = self.snapshot_path(name)?;
+        if !path.join("config.json").is_file() {
+            return vmerr!(ErrorKind::SnapshotNotFound);
+        }
+        Ok(path)
+    }
+
+    fn is_running_result(&self) -> VmResult<()> {
+        match self.get_power_state()? {
+            VmPowerState::Running => Ok(()),
+            x => vmerr!(ErrorKind::InvalidPowerState(x)),
+        }
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+}
+
+impl PowerCmd for ChRemote {
+    /// Boots the VM, or resumes the VM if it is paused.
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            match self.get_power_state()? {
+                VmPowerState::Paused => self.exec("resume", &[]),
+                VmPowerState::Running => {
+                    vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running))
+                }
+                _ => self.exec("boot", &[]),
+            }?;
+            Ok(())
+        })
+    }
+
+    /// Presses the ACPI power button with `power-button`.
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.is_running_result()?;
+            self.exec("power-button", &[])?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
+    }
+
+    /// Shuts down the VM with `shutdown` without notifying the guest.
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Paused,
+            ])?;
+            self.exec("shutdown", &[])?;
+            Ok(())
+        })
+    }
+
+    /// Due to the specification of cloud-hypervisor, ChRemote does not support this function.
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "suspend",
+            "Use pause instead"
+        ))
+    }
+
+    /// Due to the specification of cloud-hypervisor, ChRemote does not support this function.
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "resume",
+            "Use unpause instead"
+        ))
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()?.is_running())
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.stop(timeout)?;
+        self.start()
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.exec("reboot", &[])?;
+        Ok(())
+    }
+
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.exec("pause", &[])?;
+        Ok(())
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.check_power_state(&[VmPowerState::Paused])?;
+        self.exec("resume", &[])?;
+        Ok(())
+    }
+}
+
+impl SnapshotCmd for ChRemote {
+    /// Lists the directories containing a snapshot in the snapshot directory.
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
+        let dir = self.retrieve_snapshot_dir()?;
+        if !Path::new(dir).is_dir() {
+            return Ok(vec![]);
+        }
+        let mut ret = vec![];
+        for x in std::fs::read_dir(dir)? {
+            let x = x?;
+            if x.path().join("config.json").is_file() {
+                ret.push(Snapshot {
+                    id: None,
+                    name: Some(x.file_name().to_string_lossy().into_owned()),
+                    detail: None,
+                    parent_id: None,
+                    created_at: None,
+                    is_current: None,
+                    online: None,
+                });
+            }
+        }
+        ret.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ret)
+    }
+
+    /// Pauses the VM, saves a snapshot with `snapshot` and resumes the VM.
+    ///
+    /// An existing snapshot with the same name is overwritten.
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        let path = self.snapshot_path(name)?;
+        let was_running = self.get_power_state()? == VmPowerState::Running;
+        std::fs::create_dir_all(&path)?;
+        if was_running {
+            self.pause()?;
+        }
+        let r = self.exec("snapshot", &[&file_url(&path)]);
+        if was_running {
+            self.unpause()?;
+        }
+        r?;
+        Ok(())
+    }
+
+    /// Deletes the current VM, restores the snapshot with `restore` and resumes the VM.
+    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("revert_snapshot");
+        let path = self.existing_snapshot_path(name)?;
+        self.exec("delete", &[])?;
+        self.exec("restore", &[&format!("source_url={}", file_url(&path))])?;
+        self.unpause()
+    }
+
+    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
+        self.hooks.settle_deleted(|| {
+            let path = self.existing_snapshot_path(name)?;
+            std::fs::remove_dir_all(path)?;
+            Ok(())
+        })
+    }
+}
+
+fn file_url(path: &Path) -> String { format!("file://{}", path.display()) }
+
+fn to_power_state(s: &str) -> VmPowerState {
+    match s {
+        "Running" => VmPowerState::Running,
+        "Paused" | "BreakPoint" => VmPowerState::Paused,
+        "Created" | "Shutdown" => VmPowerState::Stopped,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+/// Parses the output of `info`.
+fn parse_info(v: &Value) -> VmInfo {
+    VmInfo {
+        memory_mb: v["config"]["memory"]["size"]
+            .as_u64()
+            .map(|x| x / (1024 * 1024)),
+        cpu_count: v["config"]["cpus"]["boot_vcpus"].as_u64().map(|x| x as u32),
+        power_state: v["state"].as_str().map(to_power_state),
+        ..VmInfo::default()
+    }
+}
+
+/// Parses the current state in `InvalidStateTransition(<from>, <to>)` of an error message.
+fn parse_invalid_state_transition(s: &str) -> Option<VmPowerState> {
+    let s = &s[s.find("InvalidStateTransition(")? + 23..];
+    let from = s.split(',').next()?.trim();
+    Some(to_power_state(from))
+}
+
+#[test]
+fn test_parse_invalid_state_transition() {
+    let s = "Error running command: Server responded with an error: \
+             InternalServerError: \
+             ApiError(VmResume(InvalidStateTransition(Running, Running)))";
+    assert_eq!(
+        parse_invalid_state_transition(s),
+        Some(VmPowerState::Running)
+    );
+    assert_eq!(
+        ChRemote::handle_error(s).get_invalid_state(),
+        Some(VmPowerState::Running)
+    );
+    assert_eq!(
+        ChRemote::handle_error(
+            "Error running command: Error opening HTTP socket: No such file \
+             or directory (os error 2)"
+        )
+        .get_invalid_state(),
+        Some(VmPowerState::Stopped)
+    );
+    assert_eq!(parse_invalid_state_transition("Error"), None);
+}
+
+#[test]
+fn test_parse_info() {
+    let v = serde_json::json!({
+        "config": {
+            "cpus": { "boot_vcpus": 2, "max_vcpus": 4 },
+            "memory": { "size": 1073741824u64 }
+        },
+        "state": "Paused"
+    });
+    let info = parse_info(&v);
+    assert_eq!(info.cpu_count, Some(2));
+    assert_eq!(info.memory_mb, Some(1024));
+    assert_eq!(info.power_state, Some(VmPowerState::Paused));
+    assert_eq!(info.name, None);
+}
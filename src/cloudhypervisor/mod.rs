@@ -0,0 +1,9 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Cloud Hypervisor controllers.
+
+#[cfg(feature = "chremote")]
+pub mod chremote;
+
+#[cfg(feature = "chremote")]
+pub use chremote::*;
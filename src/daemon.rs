@@ -0,0 +1,684 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! A local IPC service which exposes VM operations to other processes.
+//!
+//! [`Daemon`] listens on a Unix domain socket (a named pipe on Windows) and handles newline-delimited JSON requests with a controller, so tools written in other languages can drive VMs through one privileged service.
+//!
+//! # Protocol
+//!
+//! Each request is a JSON object on one line.
+//! `vm` is the name of the VM to be manipulated, `name` is a snapshot name and `timeout` is in seconds.
+//!
+//! ```json
+//! {"op": "take_snapshot", "vm": "MyVM", "name": "snap1"}
+//! ```
+//!
+//! Each response is a JSON object on one line.
+//!
+//! ```json
+//! {"ok": true, "result": null}
+//! {"ok": false, "error": "..."}
+//! ```
+//!
+//! Supported `op`s are `list_vms`, `start`, `stop`, `hard_stop`, `suspend`, `resume`, `is_running`, `reboot`, `hard_reboot`, `pause`, `unpause`, `list_snapshots`, `take_snapshot`, `revert_snapshot`, `delete_snapshot` and `snapshot_disk_usage`.
+//!
+//! Access to the Unix domain socket is controlled by the permissions of the socket file.
+//! The named pipe rejects remote clients and only the user running the daemon can connect to it.
+//! A request longer than [`MAX_REQUEST_SIZE`] bytes closes the connection.
+//! A client which sends no request or doesn't receive a response for [`IDLE_TIMEOUT`] is disconnected, so an idle client can't hold the daemon.
+#![cfg(feature = "daemon")]
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    time::Duration,
+};
+
+/// The default address of [`Daemon`].
+#[cfg(windows)]
+pub const DEFAULT_ADDRESS: &str = r"\\.\pipe\hvctrl";
+/// The default address of [`Daemon`].
+#[cfg(not(windows))]
+pub const DEFAULT_ADDRESS: &str = "/tmp/hvctrl.sock";
+
+/// The maximum size of a request line.
+pub const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+
+/// The read and write timeout of a connection.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Represents a request to [`Daemon`].
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Request {
+    pub op: String,
+    /// The name of the VM to be manipulated.
+    pub vm: Option<String>,
+    /// The snapshot name for `take_snapshot`, `revert_snapshot` and `delete_snapshot`.
+    pub name: Option<String>,
+    /// The timeout in seconds for `stop` and `reboot`.
+    pub timeout: Option<u64>,
+}
+
+/// Represents a response from [`Daemon`].
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub(crate) fn from_result(r: VmResult<Value>) -> Self {
+        match r {
+            Ok(x) => Self {
+                ok: true,
+                result: Some(x),
+                error: None,
+            },
+            Err(e) => Self::error(e.to_string()),
+        }
+    }
+
+    pub(crate) fn error(s: String) -> Self {
+        Self {
+            ok: false,
+            result: None,
+            error: Some(s),
+        }
+    }
+}
+
+/// Serves requests with a controller.
+///
+/// Connections are served one by one, so operations never run concurrently.
+#[derive(Clone, Debug)]
+pub struct Daemon<C> {
+    cmd: C,
+    address: String,
+}
+
+impl<C> Daemon<C>
+where
+    C: VmCmd + PowerCmd + SnapshotCmd + Clone,
+{
+    /// Creates a daemon which serves requests with clones of `cmd`.
+    pub fn new(cmd: C) -> Self {
+        Self {
+            cmd,
+            address: DEFAULT_ADDRESS.to_string(),
+        }
+    }
+
+    impl_setter!(
+        /// Sets the path to the Unix domain socket, or the name of the named pipe on Windows.
+        address: String
+    );
+
+    pub fn get_address(&self) -> &str { &self.address }
+
+    /// Handles a request.
+    pub fn handle(&self, req: &Request) -> Response {
+        Response::from_result(self.dispatch(req))
+    }
+
+    fn dispatch(&self, req: &Request) -> VmResult<Value> {
+        let mut cmd = self.cmd.clone();
+        if let Some(vm) = &req.vm {
+            cmd.set_vm_by_name(vm)?;
+        }
+        let timeout = req.timeout.map(Duration::from_secs);
+        let name = || {
+            req.name.as_deref().ok_or_else(|| {
+                VmError::from(ErrorKind::InvalidParameter("name".to_string()))
+            })
+        };
+        let unit = |r: VmResult<()>| r.map(|_| Value::Null);
+        match req.op.as_str() {
+            "list_vms" => Ok(serde_json::to_value(cmd.list_vms()?)?),
+            "start" => unit(cmd.start()),
+            "stop" => unit(cmd.stop(timeout)),
+            "hard_stop" => unit(cmd.hard_stop()),
+            "suspend" => unit(cmd.suspend()),
+            "resume" => unit(cmd.resume()),
+            "is_running" => Ok(Value::Bool(cmd.is_running()?)),
+            "reboot" => unit(cmd.reboot(timeout)),
+            "hard_reboot" => unit(cmd.hard_reboot()),
+            "pause" => unit(cmd.pause()),
+            "unpause" => unit(cmd.unpause()),
+            "list_snapshots" => {
+                Ok(serde_json::to_value(cmd.list_snapshots()?)?)
+            }
+            "take_snapshot" => unit(cmd.take_snapshot(name()?)),
+            "revert_snapshot" => unit(cmd.revert_snapshot(name()?)),
+            "delete_snapshot" => unit(cmd.delete_snapshot(name()?)),
+            "snapshot_disk_usage" => {
+                Ok(serde_json::to_value(cmd.snapshot_disk_usage()?)?)
+            }
+            op => vmerr!(ErrorKind::InvalidParameter(format!(
+                "Unknown op: {}",
+                op
+            ))),
+        }
+    }
+
+    /// Serves requests read from `stream` until it is closed.
+    ///
+    /// Returns [`ErrorKind::InvalidParameter`] if a request is longer than [`MAX_REQUEST_SIZE`] bytes.
+    pub fn serve_stream<S: Read + Write>(&self, stream: S) -> VmResult<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let n =
+                (&mut reader).take(MAX_REQUEST_SIZE).read_line(&mut line)?;
+            if n == 0 {
+                return Ok(());
+            }
+            if n as u64 == MAX_REQUEST_SIZE && !line.ends_with('\n') {
+                return vmerr!(ErrorKind::InvalidParameter(
+                    "Too long request".to_string()
+                ));
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let resp = match serde_json::from_str::<Request>(&line) {
+                Ok(req) => self.handle(&req),
+                Err(e) => Response::error(format!("Invalid request: {}", e)),
+            };
+            let mut s = serde_json::to_string(&resp)?;
+            s.push('\n');
+            let w = reader.get_mut();
+            w.write_all(s.as_bytes())?;
+            w.flush()?;
+        }
+    }
+
+    /// Listens on the Unix domain socket and serves requests forever.
+    ///
+    /// Fails if a file already exists at the address.
+    #[cfg(unix)]
+    pub fn serve(&self) -> VmResult<()> {
+        use std::os::unix::net::UnixListener;
+        let listener = UnixListener::bind(&self.address)?;
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = stream
+                .set_read_timeout(Some(IDLE_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(IDLE_TIMEOUT)))
+            {
+                warn!("Failed to set the timeout of a connection: {}", e);
+                continue;
+            }
+            if let Err(e) = self.serve_stream(&stream) {
+                warn!("Failed to serve a connection: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the named pipe and serves requests forever.
+    ///
+    /// The named pipe rejects remote clients, and its DACL only allows the current user.
+    /// Fails if the named pipe already exists, so another process can't serve clients under the same name.
+    #[cfg(windows)]
+    pub fn serve(&self) -> VmResult<()> {
+        use windows_sys::Win32::{
+            Foundation::INVALID_HANDLE_VALUE,
+            Security::SECURITY_ATTRIBUTES,
+            Storage::FileSystem::{
+                FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED,
+                PIPE_ACCESS_DUPLEX,
+            },
+            System::Pipes::{
+                CreateNamedPipeW, PIPE_READMODE_BYTE,
+                PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE,
+                PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            },
+        };
+        let name: Vec<u16> =
+            self.address.encode_utf16().chain(Some(0)).collect();
+        let sd = SecurityDescriptor::current_user()?;
+        let sa = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: sd.0,
+            bInheritHandle: 0,
+        };
+        let create = |first: bool| -> VmResult<Pipe> {
+            let mut mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED;
+            if first {
+                mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+            }
+            let h = unsafe {
+                CreateNamedPipeW(
+                    name.as_ptr(),
+                    mode,
+                    PIPE_TYPE_BYTE
+                        | PIPE_READMODE_BYTE
+                        | PIPE_WAIT
+                        | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    4096,
+                    4096,
+                    0,
+                    &sa,
+                )
+            };
+            if h == INVALID_HANDLE_VALUE {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(Pipe::new(h)?)
+        };
+        let mut next = create(true)?;
+        loop {
+            let connected = next.connect();
+            // Creates the next instance before closing this one, so the name is never released.
+            let mut pipe = std::mem::replace(&mut next, create(false)?);
+            let r = match connected {
+                Ok(_) => self.serve_stream(&mut pipe),
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = r {
+                warn!("Failed to serve a connection: {}", e);
+            }
+        }
+    }
+}
+
+/// An instance of a named pipe opened for overlapped I/O, whose reads and writes time out after [`IDLE_TIMEOUT`].
+#[cfg(windows)]
+struct Pipe {
+    handle: windows_sys::Win32::Foundation::HANDLE,
+    event: windows_sys::Win32::Foundation::HANDLE,
+}
+
+#[cfg(windows)]
+impl Pipe {
+    /// Takes the ownership of `handle`.
+    fn new(
+        handle: windows_sys::Win32::Foundation::HANDLE,
+    ) -> std::io::Result<Self> {
+        use windows_sys::Win32::{
+            Foundation::CloseHandle, System::Threading::CreateEventW,
+        };
+        let event =
+            unsafe { CreateEventW(std::ptr::null(), 1, 0, std::ptr::null()) };
+        if event == 0 {
+            let e = std::io::Error::last_os_error();
+            unsafe { CloseHandle(handle) };
+            return Err(e);
+        }
+        Ok(Self { handle, event })
+    }
+
+    fn overlapped(&self) -> windows_sys::Win32::System::IO::OVERLAPPED {
+        let mut ov: windows_sys::Win32::System::IO::OVERLAPPED =
+            unsafe { std::mem::zeroed() };
+        ov.hEvent = self.event;
+        ov
+    }
+
+    /// Waits for a client to connect.
+    fn connect(&self) -> std::io::Result<()> {
+        use windows_sys::Win32::{
+            Foundation::{ERROR_IO_PENDING, ERROR_PIPE_CONNECTED},
+            System::Pipes::ConnectNamedPipe,
+        };
+        let mut ov = self.overlapped();
+        if unsafe { ConnectNamedPipe(self.handle, &mut ov) } != 0 {
+            return Ok(());
+        }
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(x) if x == ERROR_PIPE_CONNECTED as i32 => Ok(()),
+            Some(x) if x == ERROR_IO_PENDING as i32 => {
+                self.wait(&ov, None).map(|_| ())
+            }
+            _ => Err(e),
+        }
+    }
+
+    /// Waits for the operation of `ov` to complete and returns the number of the transferred bytes.
+    ///
+    /// Cancels the operation and returns [`std::io::ErrorKind::TimedOut`] if it doesn't complete in `timeout`.
+    fn wait(
+        &self,
+        ov: &windows_sys::Win32::System::IO::OVERLAPPED,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<usize> {
+        use windows_sys::Win32::{
+            Foundation::WAIT_TIMEOUT,
+            System::{
+                Threading::INFINITE,
+                IO::{CancelIoEx, GetOverlappedResult, GetOverlappedResultEx},
+            },
+        };
+        let ms = timeout.map_or(INFINITE, |x| {
+            x.as_millis().min(INFINITE as u128 - 1) as u32
+        });
+        let mut n = 0;
+        if unsafe { GetOverlappedResultEx(self.handle, ov, &mut n, ms, 0) } != 0
+        {
+            return Ok(n as usize);
+        }
+        let e = std::io::Error::last_os_error();
+        if e.raw_os_error() != Some(WAIT_TIMEOUT as i32) {
+            return Err(e);
+        }
+        // `ov` must outlive the operation, so waits for the cancellation.
+        unsafe {
+            CancelIoEx(self.handle, ov);
+            if GetOverlappedResult(self.handle, ov, &mut n, 1) != 0 {
+                return Ok(n as usize);
+            }
+        }
+        Err(std::io::ErrorKind::TimedOut.into())
+    }
+
+    /// Waits for the operation of `ov` started by `ReadFile` or `WriteFile`, which returned `ok`.
+    fn complete(
+        &self,
+        ok: i32,
+        ov: &windows_sys::Win32::System::IO::OVERLAPPED,
+    ) -> std::io::Result<usize> {
+        use windows_sys::Win32::Foundation::ERROR_IO_PENDING;
+        if ok == 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+                return Err(e);
+            }
+        }
+        self.wait(ov, Some(IDLE_TIMEOUT))
+    }
+}
+
+#[cfg(windows)]
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use windows_sys::Win32::{
+            Foundation::ERROR_BROKEN_PIPE, Storage::FileSystem::ReadFile,
+        };
+        let mut ov = self.overlapped();
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let ok = unsafe {
+            ReadFile(
+                self.handle,
+                buf.as_mut_ptr(),
+                len,
+                std::ptr::null_mut(),
+                &mut ov,
+            )
+        };
+        match self.complete(ok, &ov) {
+            // The client closed the pipe.
+            Err(e) if e.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => {
+                Ok(0)
+            }
+            r => r,
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use windows_sys::Win32::Storage::FileSystem::WriteFile;
+        let mut ov = self.overlapped();
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let ok = unsafe {
+            WriteFile(
+                self.handle,
+                buf.as_ptr(),
+                len,
+                std::ptr::null_mut(),
+                &mut ov,
+            )
+        };
+        self.complete(ok, &ov)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+#[cfg(windows)]
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        use windows_sys::Win32::Foundation::CloseHandle;
+        unsafe {
+            CloseHandle(self.handle);
+            CloseHandle(self.event);
+        }
+    }
+}
+
+/// A security descriptor allocated by `ConvertStringSecurityDescriptorToSecurityDescriptorW`.
+#[cfg(windows)]
+struct SecurityDescriptor(windows_sys::Win32::Security::PSECURITY_DESCRIPTOR);
+
+#[cfg(windows)]
+impl SecurityDescriptor {
+    /// Creates a security descriptor whose DACL only allows the user of the current process.
+    fn current_user() -> VmResult<Self> {
+        use windows_sys::Win32::{
+            Foundation::{CloseHandle, LocalFree, HANDLE},
+            Security::{
+                Authorization::{
+                    ConvertSidToStringSidW,
+                    ConvertStringSecurityDescriptorToSecurityDescriptorW,
+                    SDDL_REVISION_1,
+                },
+                GetTokenInformation, TokenUser, TOKEN_QUERY, TOKEN_USER,
+            },
+            System::Threading::{GetCurrentProcess, OpenProcessToken},
+        };
+        let last_error = || VmError::from(std::io::Error::last_os_error());
+        unsafe {
+            let mut token: HANDLE = 0;
+            if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token)
+                == 0
+            {
+                return Err(last_error());
+            }
+            let mut len = 0;
+            GetTokenInformation(
+                token,
+                TokenUser,
+                std::ptr::null_mut(),
+                0,
+                &mut len,
+            );
+            // u64 keeps TOKEN_USER aligned.
+            let mut buf = vec![0u64; (len as usize + 7) / 8];
+            let ok = GetTokenInformation(
+                token,
+                TokenUser,
+                buf.as_mut_ptr() as _,
+                len,
+                &mut len,
+            );
+            let e = last_error();
+            CloseHandle(token);
+            if ok == 0 {
+                return Err(e);
+            }
+            let user = &*(buf.as_ptr() as *const TOKEN_USER);
+            let mut p = std::ptr::null_mut();
+            if ConvertSidToStringSidW(user.User.Sid, &mut p) == 0 {
+                return Err(last_error());
+            }
+            let sid = String::from_utf16_lossy(std::slice::from_raw_parts(
+                p,
+                (0..).take_while(|&i| *p.add(i) != 0).count(),
+            ));
+            LocalFree(p as _);
+            // Protected DACL which grants the user full access.
+            let sddl: Vec<u16> = format!("D:P(A;;GA;;;{})", sid)
+                .encode_utf16()
+                .chain(Some(0))
+                .collect();
+            let mut sd = std::ptr::null_mut();
+            if ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut sd,
+                std::ptr::null_mut(),
+            ) == 0
+            {
+                return Err(last_error());
+            }
+            Ok(Self(sd))
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for SecurityDescriptor {
+    fn drop(&mut self) {
+        unsafe { windows_sys::Win32::Foundation::LocalFree(self.0) };
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct FakeCmd {
+    vm: Option<String>,
+    running: std::rc::Rc<std::cell::Cell<bool>>,
+}
+
+#[cfg(test)]
+impl VmCmd for FakeCmd {
+    fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        Ok(vec![Vm {
+            id: Some("1".to_string()),
+            name: Some("vm1".to_string()),
+            path: None,
+        }])
+    }
+
+    fn set_vm_by_id(&mut self, _: &str) -> VmResult<Vm> {
+        vmerr!(ErrorKind::unsupported::<Self>("set_vm_by_id"))
+    }
+
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        if name != "vm1" {
+            return vmerr!(ErrorKind::VmNotFound);
+        }
+        self.vm = Some(name.to_string());
+        Ok(Vm {
+            id: Some("1".to_string()),
+            name: Some(name.to_string()),
+            path: None,
+        })
+    }
+
+    fn set_vm_by_path(&mut self, _: &str) -> VmResult<Vm> {
+        vmerr!(ErrorKind::unsupported::<Self>("set_vm_by_path"))
+    }
+}
+
+#[cfg(test)]
+impl PowerCmd for FakeCmd {
+    fn start(&self) -> VmResult<()> {
+        self.running.set(true);
+        Ok(())
+    }
+
+    fn stop<D: Into<Option<Duration>>>(&self, _: D) -> VmResult<()> {
+        self.hard_stop()
+    }
+
+    fn hard_stop(&self) -> VmResult<()> {
+        self.running.set(false);
+        Ok(())
+    }
+
+    fn suspend(&self) -> VmResult<()> { self.hard_stop() }
+
+    fn resume(&self) -> VmResult<()> { self.start() }
+
+    fn is_running(&self) -> VmResult<bool> { Ok(self.running.get()) }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, _: D) -> VmResult<()> {
+        self.start()
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> { self.start() }
+
+    fn pause(&self) -> VmResult<()> { self.hard_stop() }
+
+    fn unpause(&self) -> VmResult<()> { self.start() }
+}
+
+#[cfg(test)]
+impl SnapshotCmd for FakeCmd {
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> { Ok(vec![]) }
+
+    fn take_snapshot(&self, _: &str) -> VmResult<()> { Ok(()) }
+
+    fn revert_snapshot(&self, _: &str) -> VmResult<()> {
+        vmerr!(ErrorKind::SnapshotNotFound)
+    }
+
+    fn delete_snapshot(&self, _: &str) -> VmResult<()> {
+        vmerr!(ErrorKind::SnapshotNotFound)
+    }
+}
+
+#[test]
+fn test_serve_stream() {
+    let daemon = Daemon::new(FakeCmd::default());
+    let input = "{\"op\": \"start\", \"vm\": \"vm1\"}\n{\"op\": \
+                 \"is_running\", \"vm\": \"vm1\"}\n\n{\"op\": \
+                 \"take_snapshot\"}\n{\"op\": \"start\", \"vm\": \
+                 \"vm2\"}\n{\"op\": \"unknown\"}\nnot json\n";
+    let mut stream = std::io::Cursor::new(input.as_bytes().to_vec());
+    let mut output = vec![];
+    {
+        struct Duplex<'a>(&'a mut std::io::Cursor<Vec<u8>>, &'a mut Vec<u8>);
+        impl Read for Duplex<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.0.read(buf)
+            }
+        }
+        impl Write for Duplex<'_> {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.1.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+        daemon
+            .serve_stream(Duplex(&mut stream, &mut output))
+            .unwrap();
+        let mut long = std::io::Cursor::new(vec![b' '; 1 << 20]);
+        assert_eq!(
+            daemon.serve_stream(Duplex(&mut long, &mut vec![])),
+            vmerr!(ErrorKind::InvalidParameter("Too long request".to_string()))
+        );
+    }
+    let resp: Vec<Response> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|x| serde_json::from_str(x).unwrap())
+        .collect();
+    assert_eq!(resp.len(), 6);
+    assert!(resp[0].ok);
+    assert_eq!(resp[1].result, Some(Value::Bool(true)));
+    assert!(!resp[2].ok);
+    assert!(!resp[3].ok);
+    assert!(resp[4].error.as_deref().unwrap().contains("Unknown op"));
+    assert!(resp[5]
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("Invalid request"));
+}
@@ -0,0 +1,466 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Declarative reconciliation of VMs.
+//!
+//! [`ensure`] reads the current state of a VM, applies only the changes needed to reach a [`VmDesiredState`] and returns them as [`VmChange`]s.
+//! Controllers which do not implement all of the required traits can use [`ensure_power`], [`ensure_snapshot`], [`ensure_nics`] and [`ensure_shared_folders`] individually.
+//!
+//! [`VmDesiredState`] implements `Deserialize`, so it can be loaded from a config file.
+//!
+//! ```json
+//! {"power": "Running", "snapshot": "clean", "nics": [{"id": "1", "ty": "NAT"}]}
+//! ```
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The default time [`ensure_power`] waits for the guest to shut down before stopping the VM hardly.
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Represents the desired state of a VM. `None` fields are left as they are.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct VmDesiredState {
+    /// The power state.
+    ///
+    /// [`VmPowerState::NotRunning`] is satisfied by any state other than running.
+    /// Controllers which only report whether the VM is running (see [`PowerCmd::get_power_state`]) satisfy [`VmPowerState::Stopped`], [`VmPowerState::Suspended`] and [`VmPowerState::Paused`] with any state other than running.
+    pub power: Option<VmPowerState>,
+    /// The seconds to wait for the guest to shut down before stopping the VM hardly.
+    ///
+    /// `None` is [`DEFAULT_STOP_TIMEOUT`].
+    pub stop_timeout: Option<u64>,
+    /// The name of the snapshot which must exist. It is taken if missing.
+    pub snapshot: Option<String>,
+    /// All NICs of the VM. NICs which are not in the list are removed.
+    pub nics: Option<Vec<Nic>>,
+    /// All shared folders of the VM. Shared folders which are not in the list are deleted.
+    pub shared_folders: Option<Vec<SharedFolder>>,
+}
+
+/// Represents a change applied by [`ensure`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum VmChange {
+    /// The power state was changed from `from`, which is reported by [`PowerCmd::get_power_state`].
+    Power {
+        from: VmPowerState,
+        to: VmPowerState,
+    },
+    TakeSnapshot(String),
+    AddNic(Nic),
+    UpdateNic(Nic),
+    RemoveNic(Nic),
+    MountSharedFolder(SharedFolder),
+    DeleteSharedFolder(SharedFolder),
+}
+
+/// Applies `desired` to `vm` and returns the applied changes.
+///
+/// The VM is stopped, suspended or paused first and started last, so the NICs, the shared folders and the snapshot are changed while the VM is not running if `desired.power` is not running.
+pub fn ensure<T: PowerCmd + SnapshotCmd + NicCmd + SharedFolderCmd>(
+    vm: &T,
+    desired: &VmDesiredState,
+) -> VmResult<Vec<VmChange>> {
+    let mut ret = vec![];
+    let power = desired.power.filter(|x| *x != VmPowerState::Running);
+    let stop_timeout = desired.stop_timeout.map(Duration::from_secs);
+    if let Some(x) = power {
+        ret.extend(ensure_power(vm, x, stop_timeout)?);
+    }
+    if let Some(x) = &desired.nics {
+        ret.extend(ensure_nics(vm, x)?);
+    }
+    if let Some(x) = &desired.shared_folders {
+        ret.extend(ensure_shared_folders(vm, x)?);
+    }
+    if let Some(x) = &desired.snapshot {
+        ret.extend(ensure_snapshot(vm, x)?);
+    }
+    if desired.power == Some(VmPowerState::Running) {
+        ret.extend(ensure_power(vm, VmPowerState::Running, stop_timeout)?);
+    }
+    Ok(ret)
+}
+
+/// Changes the power state of `vm` to `desired` if needed.
+///
+/// A VM which is not running is started with [`PowerCmd::start`], [`PowerCmd::resume`] or [`PowerCmd::unpause`] first if `desired` is not reachable from its state directly, e.g., a suspended VM is resumed and then paused.
+/// A VM which does not shut down in `stop_timeout` ([`DEFAULT_STOP_TIMEOUT`] if `None`) is stopped with [`PowerCmd::hard_stop`].
+pub fn ensure_power<T: PowerCmd, D: Into<Option<Duration>>>(
+    vm: &T,
+    desired: VmPowerState,
+    stop_timeout: D,
+) -> VmResult<Option<VmChange>> {
+    let stop_timeout = stop_timeout.into().unwrap_or(DEFAULT_STOP_TIMEOUT);
+    let from = vm.get_power_state()?;
+    let calls = power_calls(from, desired)?;
+    if calls.is_empty() {
+        return Ok(None);
+    }
+    for x in calls {
+        match x {
+            PowerCall::Start => vm.start()?,
+            PowerCall::Resume => vm.resume()?,
+            PowerCall::Unpause => vm.unpause()?,
+            PowerCall::Stop => match vm.stop(stop_timeout) {
+                Err(e)
+                    if matches!(
+                        e.get_repr(),
+                        Repr::Simple(ErrorKind::Timeout)
+                    ) =>
+                {
+                    vm.hard_stop()?
+                }
+                x => x?,
+            },
+            PowerCall::Suspend => vm.suspend()?,
+            PowerCall::Pause => vm.pause()?,
+        }
+    }
+    Ok(Some(VmChange::Power { from, to: desired }))
+}
+
+/// Takes the snapshot named `name` if it does not exist.
+pub fn ensure_snapshot<T: SnapshotCmd>(
+    vm: &T,
+    name: &str,
+) -> VmResult<Option<VmChange>> {
+    if vm
+        .list_snapshots()?
+        .iter()
+        .any(|x| x.name.as_deref() == Some(name))
+    {
+        return Ok(None);
+    }
+    vm.take_snapshot(name)?;
+    Ok(Some(VmChange::TakeSnapshot(name.to_string())))
+}
+
+/// Makes the NICs of `vm` match `desired`. See [`diff_nics`] for details.
+pub fn ensure_nics<T: NicCmd>(
+    vm: &T,
+    desired: &[Nic],
+) -> VmResult<Vec<VmChange>> {
+    let changes = diff_nics(&vm.list_nics()?, desired);
+    for x in &changes {
+        match x {
+            VmChange::AddNic(nic) => vm.add_nic(nic)?,
+            VmChange::UpdateNic(nic) => vm.update_nic(nic)?,
+            VmChange::RemoveNic(nic) => vm.remove_nic(nic)?,
+            _ => unreachable!(),
+        }
+    }
+    Ok(changes)
+}
+
+/// Makes the shared folders of `vm` match `desired`. See [`diff_shared_folders`] for details.
+pub fn ensure_shared_folders<T: SharedFolderCmd>(
+    vm: &T,
+    desired: &[SharedFolder],
+) -> VmResult<Vec<VmChange>> {
+    let changes = diff_shared_folders(&vm.list_shared_folders()?, desired);
+    for x in &changes {
+        match x {
+            VmChange::MountSharedFolder(shfs) => {
+                vm.mount_shared_folder(shfs)?
+            }
+            VmChange::DeleteSharedFolder(shfs) => {
+                vm.delete_shared_folder(shfs)?
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(changes)
+}
+
+/// Represents a call of [`PowerCmd`] made by [`ensure_power`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum PowerCall {
+    Start,
+    Resume,
+    Unpause,
+    Stop,
+    Suspend,
+    Pause,
+}
+
+/// Returns the calls which change the power state from `current` to `desired`.
+///
+/// The result is empty if `desired` is already satisfied.
+fn power_calls(
+    current: VmPowerState,
+    desired: VmPowerState,
+) -> VmResult<Vec<PowerCall>> {
+    use VmPowerState::*;
+    if desired == Unknown {
+        return vmerr!(ErrorKind::InvalidParameter(
+            "Unknown is not a desired power state".to_string()
+        ));
+    }
+    if current.matches(desired) || (current == NotRunning && desired != Running)
+    {
+        return Ok(vec![]);
+    }
+    let mut ret = vec![];
+    match current {
+        Running => {}
+        Stopped | NotRunning => ret.push(PowerCall::Start),
+        Suspended => ret.push(PowerCall::Resume),
+        Paused => ret.push(PowerCall::Unpause),
+        Unknown => return vmerr!(ErrorKind::InvalidPowerState(Unknown)),
+    }
+    match desired {
+        Running => {}
+        NotRunning | Stopped => ret.push(PowerCall::Stop),
+        Suspended => ret.push(PowerCall::Suspend),
+        Paused => ret.push(PowerCall::Pause),
+        Unknown => unreachable!(),
+    }
+    Ok(ret)
+}
+
+/// Returns the changes needed to make the NICs `current` match `desired`.
+///
+/// A NIC in `desired` corresponds to the NIC in `current` with the same ID, or with the same MAC address or name if it has no ID.
+/// A NIC which has none of them corresponds to a remaining NIC with the same type, or to any remaining NIC if it has no type either.
+/// Corresponding NICs are updated if any field of the desired NIC differs, other NICs in `desired` are added and the rest of `current` are removed.
+pub fn diff_nics(current: &[Nic], desired: &[Nic]) -> Vec<VmChange> {
+    let mut matched = vec![false; current.len()];
+    let mut found = vec![None; desired.len()];
+    // Matches the NICs which have an identity first, so the rest can't take their NICs.
+    for fallback in [false, true] {
+        for (x, f) in desired.iter().zip(found.iter_mut()) {
+            let has_identity =
+                x.id.is_some() || x.mac_address.is_some() || x.name.is_some();
+            if has_identity == fallback {
+                continue;
+            }
+            *f = (0..current.len()).find(|&i| {
+                let c = &current[i];
+                !matched[i]
+                    && match (&x.id, &x.mac_address, &x.name) {
+                        (Some(id), _, _) => c.id.as_ref() == Some(id),
+                        (None, Some(mac), _) => c
+                            .mac_address
+                            .as_deref()
+                            .map_or(false, |y| y.eq_ignore_ascii_case(mac)),
+                        (None, None, Some(name)) => {
+                            c.name.as_ref() == Some(name)
+                        }
+                        (None, None, None) => x.ty.is_none() || x.ty == c.ty,
+                    }
+            });
+            if let Some(i) = *f {
+                matched[i] = true;
+            }
+        }
+    }
+    let mut updates = vec![];
+    let mut adds = vec![];
+    for (x, f) in desired.iter().zip(found) {
+        let i = match f {
+            Some(i) => i,
+            None => {
+                adds.push(VmChange::AddNic(x.clone()));
+                continue;
+            }
+        };
+        let c = &current[i];
+        let differs = |a: &Option<String>, b: &Option<String>| {
+            a.is_some() && !eq_ignore_case(a, b)
+        };
+        if differs(&x.name, &c.name)
+            || differs(&x.mac_address, &c.mac_address)
+            || (x.ty.is_some() && x.ty != c.ty)
+        {
+            updates.push(VmChange::UpdateNic(Nic {
+                id: c.id.clone(),
+                name: x.name.clone().or_else(|| c.name.clone()),
+                ty: x.ty.clone().or_else(|| c.ty.clone()),
+                mac_address: x
+                    .mac_address
+                    .clone()
+                    .or_else(|| c.mac_address.clone()),
+            }));
+        }
+    }
+    // Removes first to free the slots.
+    let mut ret: Vec<VmChange> = current
+        .iter()
+        .zip(matched)
+        .filter(|(_, m)| !m)
+        .map(|(c, _)| VmChange::RemoveNic(c.clone()))
+        .collect();
+    ret.extend(updates);
+    ret.extend(adds);
+    ret
+}
+
+/// Returns the changes needed to make the shared folders `current` match `desired`.
+///
+/// A shared folder in `desired` corresponds to the one in `current` with the same ID, or with the same name if it has no ID.
+/// Since shared folders cannot be updated, a corresponding shared folder which differs is deleted and mounted again.
+pub fn diff_shared_folders(
+    current: &[SharedFolder],
+    desired: &[SharedFolder],
+) -> Vec<VmChange> {
+    let mut matched = vec![false; current.len()];
+    let mut mounts = vec![];
+    for x in desired {
+        let found = current.iter().enumerate().position(|(i, c)| {
+            !matched[i]
+                && match (&x.id, &x.name) {
+                    (Some(id), _) => c.id.as_ref() == Some(id),
+                    (None, Some(name)) => c.name.as_ref() == Some(name),
+                    (None, None) => false,
+                }
+        });
+        if let Some(i) = found {
+            matched[i] = true;
+            let c = &current[i];
+            if (x.host_path.is_none() || x.host_path == c.host_path)
+                && (x.guest_path.is_none() || x.guest_path == c.guest_path)
+                && x.is_readonly == c.is_readonly
+            {
+                continue;
+            }
+            matched[i] = false;
+        }
+        mounts.push(VmChange::MountSharedFolder(x.clone()));
+    }
+    let mut ret: Vec<VmChange> = current
+        .iter()
+        .zip(matched)
+        .filter(|(_, m)| !m)
+        .map(|(c, _)| VmChange::DeleteSharedFolder(c.clone()))
+        .collect();
+    ret.extend(mounts);
+    ret
+}
+
+fn eq_ignore_case(a: &Option<String>, b: &Option<String>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => a == b,
+    }
+}
+
+#[test]
+fn test_power_calls() {
+    use PowerCall::*;
+    use VmPowerState::*;
+    assert_eq!(power_calls(Stopped, Running), Ok(vec![Start]));
+    assert_eq!(power_calls(NotRunning, Running), Ok(vec![Start]));
+    assert_eq!(power_calls(Running, Running), Ok(vec![]));
+    assert_eq!(power_calls(Running, Stopped), Ok(vec![Stop]));
+    assert_eq!(power_calls(Running, Suspended), Ok(vec![Suspend]));
+    assert_eq!(power_calls(Running, NotRunning), Ok(vec![Stop]));
+    assert_eq!(power_calls(Stopped, Stopped), Ok(vec![]));
+    assert_eq!(power_calls(Suspended, NotRunning), Ok(vec![]));
+    assert_eq!(power_calls(NotRunning, Paused), Ok(vec![]));
+    assert_eq!(power_calls(Paused, Running), Ok(vec![Unpause]));
+    assert_eq!(power_calls(Suspended, Running), Ok(vec![Resume]));
+    assert_eq!(power_calls(Suspended, Paused), Ok(vec![Resume, Pause]));
+    assert_eq!(power_calls(Paused, Suspended), Ok(vec![Unpause, Suspend]));
+    assert_eq!(power_calls(Paused, Stopped), Ok(vec![Unpause, Stop]));
+    assert_eq!(power_calls(Stopped, Paused), Ok(vec![Start, Pause]));
+    assert!(power_calls(Running, Unknown).is_err());
+    assert!(power_calls(Unknown, Running).is_err());
+}
+
+#[test]
+fn test_diff_nics() {
+    let nic = |id: Option<&str>, mac: Option<&str>, ty: Option<NicType>| Nic {
+        id: id.map(|x| x.to_string()),
+        name: None,
+        ty,
+        mac_address: mac.map(|x| x.to_string()),
+    };
+    let current = [
+        nic(Some("1"), Some("080027000001"), Some(NicType::NAT)),
+        nic(Some("2"), Some("080027000002"), Some(NicType::HostOnly)),
+        nic(Some("3"), Some("080027000003"), Some(NicType::Bridge)),
+    ];
+    let desired = [
+        nic(Some("1"), None, Some(NicType::NAT)),
+        nic(None, Some("080027000002"), Some(NicType::Bridge)),
+        nic(None, None, Some(NicType::NAT)),
+    ];
+    let changes = diff_nics(&current, &desired);
+    assert_eq!(changes.len(), 3);
+    assert!(
+        matches!(&changes[0], VmChange::RemoveNic(x) if x.id.as_deref() == Some("3"))
+    );
+    match &changes[1] {
+        VmChange::UpdateNic(x) => {
+            assert_eq!(x.id.as_deref(), Some("2"));
+            assert_eq!(x.ty, Some(NicType::Bridge));
+            assert_eq!(x.mac_address.as_deref(), Some("080027000002"));
+        }
+        x => panic!("{:?}", x),
+    }
+    assert!(matches!(&changes[2], VmChange::AddNic(x) if x.id.is_none()));
+    assert!(diff_nics(&current, &current).is_empty());
+}
+
+#[test]
+fn test_diff_nics_without_identity() {
+    let nic = |id: Option<&str>, ty: Option<NicType>| Nic {
+        id: id.map(|x| x.to_string()),
+        name: None,
+        ty,
+        mac_address: None,
+    };
+    let mut current = vec![
+        nic(Some("1"), Some(NicType::Bridge)),
+        nic(Some("2"), Some(NicType::NAT)),
+    ];
+    let desired = [
+        nic(None, Some(NicType::NAT)),
+        nic(None, Some(NicType::HostOnly)),
+        nic(None, None),
+        nic(Some("2"), None),
+    ];
+    let changes = diff_nics(&current, &desired);
+    assert_eq!(changes.len(), 2);
+    assert!(
+        matches!(&changes[0], VmChange::AddNic(x) if x.ty == Some(NicType::NAT))
+    );
+    assert!(
+        matches!(&changes[1], VmChange::AddNic(x) if x.ty == Some(NicType::HostOnly))
+    );
+    for (i, x) in changes.into_iter().enumerate() {
+        if let VmChange::AddNic(mut x) = x {
+            x.id = Some(format!("new{}", i));
+            current.push(x);
+        }
+    }
+    assert!(diff_nics(&current, &desired).is_empty());
+}
+
+#[test]
+fn test_diff_shared_folders() {
+    let shfs = |name: &str, host_path: &str, is_readonly: bool| SharedFolder {
+        id: None,
+        name: Some(name.to_string()),
+        guest_path: None,
+        host_path: Some(host_path.to_string()),
+        is_readonly,
+    };
+    let current = [shfs("a", "/a", false), shfs("b", "/b", false)];
+    let desired = [shfs("a", "/a", true), shfs("c", "/c", false)];
+    let changes = diff_shared_folders(&current, &desired);
+    let names: Vec<_> = changes
+        .iter()
+        .map(|x| match x {
+            VmChange::DeleteSharedFolder(x) => {
+                format!("-{}", x.name.as_ref().unwrap())
+            }
+            VmChange::MountSharedFolder(x) => {
+                format!("+{}", x.name.as_ref().unwrap())
+            }
+            x => panic!("{:?}", x),
+        })
+        .collect();
+    assert_eq!(names, ["-a", "-b", "+a", "+c"]);
+    assert!(diff_shared_folders(&current, &current).is_empty());
+}
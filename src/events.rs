@@ -0,0 +1,264 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Subscriptions to the power state and snapshot changes of VMs.
+//!
+//! [`watch`], [`watch_power_state`] and [`watch_snapshots`] poll a controller in a background thread and deliver [`VmEvent`]s over a channel, so a program can react to a guest crashing without polling [`PowerCmd::is_running`] by itself.
+//!
+//! `HyperVWmi::subscribe_power_state` receives the changes from WMI events instead of polling.
+//! The other tools, including the VMware REST API, have no push notification, so their events are delivered by polling.
+use crate::types::*;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Represents an event of a VM.
+#[derive(Debug, Clone)]
+pub enum VmEvent {
+    /// The power state of the VM changed.
+    ///
+    /// The first event of a subscription has `from: None` and reports the current state.
+    PowerStateChanged {
+        from: Option<VmPowerState>,
+        to: VmPowerState,
+    },
+    /// A snapshot was taken.
+    SnapshotTaken(Snapshot),
+    /// A snapshot was deleted.
+    SnapshotDeleted(Snapshot),
+    /// Getting the state of the VM failed.
+    ///
+    /// The subscription keeps polling after an error.
+    Error(VmError),
+}
+
+/// Receives [`VmEvent`]s from a background thread.
+///
+/// Dropping the subscription stops the thread after its current poll.
+#[derive(Debug)]
+pub struct Subscription {
+    rx: Receiver<VmEvent>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Subscription {
+    /// Runs `f` in a new thread with the sender of the events and the flag which is set when the subscription is dropped.
+    pub(crate) fn spawn<F>(f: F) -> Self
+    where
+        F: FnOnce(Sender<VmEvent>, Arc<AtomicBool>) + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let s = stop.clone();
+        thread::spawn(move || f(tx, s));
+        Self { rx, stop }
+    }
+
+    /// Runs `poll` every `interval` in a new thread until the subscription is dropped.
+    fn spawn_polling<F>(interval: Duration, mut poll: F) -> Self
+    where
+        F: FnMut() -> Vec<VmEvent> + Send + 'static,
+    {
+        Self::spawn(move |tx, stop| {
+            while !stop.load(Ordering::Relaxed) {
+                for ev in poll() {
+                    if tx.send(ev).is_err() {
+                        return;
+                    }
+                }
+                thread::sleep(interval);
+            }
+        })
+    }
+
+    /// Blocks until an event arrives.
+    ///
+    /// Returns `None` if the thread has stopped.
+    pub fn recv(&self) -> Option<VmEvent> { self.rx.recv().ok() }
+
+    /// Blocks until an event arrives or `timeout` elapses.
+    ///
+    /// Returns `None` on timeout or if the thread has stopped.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<VmEvent> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
+    /// Returns an event if one has arrived.
+    pub fn try_recv(&self) -> Option<VmEvent> { self.rx.try_recv().ok() }
+
+    /// Returns an iterator which blocks for each event and ends when the thread stops.
+    pub fn iter(&self) -> mpsc::Iter<'_, VmEvent> { self.rx.iter() }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) { self.stop.store(true, Ordering::Relaxed); }
+}
+
+/// Tracks the power state of a VM and reports its changes.
+#[derive(Debug, Default)]
+struct PowerStateTracker {
+    last: Option<VmPowerState>,
+}
+
+impl PowerStateTracker {
+    fn update(&mut self, r: VmResult<VmPowerState>) -> Option<VmEvent> {
+        match r {
+            Ok(x) if Some(x) == self.last => None,
+            Ok(x) => Some(VmEvent::PowerStateChanged {
+                from: self.last.replace(x),
+                to: x,
+            }),
+            Err(e) => Some(VmEvent::Error(e)),
+        }
+    }
+}
+
+/// Tracks the snapshots of a VM and reports the taken and deleted snapshots.
+///
+/// Snapshots are identified by their IDs, or by their names if the tool doesn't report IDs.
+#[derive(Debug, Default)]
+struct SnapshotTracker {
+    last: Option<Vec<Snapshot>>,
+}
+
+impl SnapshotTracker {
+    fn key(x: &Snapshot) -> Option<&str> {
+        x.id.as_deref().or(x.name.as_deref())
+    }
+
+    fn update(&mut self, r: VmResult<Vec<Snapshot>>) -> Vec<VmEvent> {
+        let cur = match r {
+            Ok(x) => x,
+            Err(e) => return vec![VmEvent::Error(e)],
+        };
+        let last = match self.last.replace(cur.clone()) {
+            Some(x) => x,
+            None => return vec![],
+        };
+        let contains =
+            |v: &[Snapshot], x| v.iter().any(|y| Self::key(y) == Some(x));
+        let deleted = last
+            .iter()
+            .filter(|x| Self::key(x).map_or(false, |k| !contains(&cur, k)))
+            .cloned()
+            .map(VmEvent::SnapshotDeleted);
+        let taken = cur
+            .iter()
+            .filter(|x| Self::key(x).map_or(false, |k| !contains(&last, k)))
+            .cloned()
+            .map(VmEvent::SnapshotTaken);
+        deleted.chain(taken).collect()
+    }
+}
+
+/// Polls the power state and the snapshots of the VM of `cmd` every `interval`.
+pub fn watch<T>(cmd: T, interval: Duration) -> Subscription
+where
+    T: PowerCmd + SnapshotCmd + Send + 'static,
+{
+    let mut power = PowerStateTracker::default();
+    let mut snapshots = SnapshotTracker::default();
+    Subscription::spawn_polling(interval, move || {
+        let mut ret: Vec<_> =
+            power.update(cmd.get_power_state()).into_iter().collect();
+        ret.extend(snapshots.update(cmd.list_snapshots()));
+        ret
+    })
+}
+
+/// Polls the power state of the VM of `cmd` every `interval`.
+pub fn watch_power_state<T>(cmd: T, interval: Duration) -> Subscription
+where
+    T: PowerCmd + Send + 'static,
+{
+    let mut power = PowerStateTracker::default();
+    Subscription::spawn_polling(interval, move || {
+        power.update(cmd.get_power_state()).into_iter().collect()
+    })
+}
+
+/// Polls the snapshots of the VM of `cmd` every `interval`.
+pub fn watch_snapshots<T>(cmd: T, interval: Duration) -> Subscription
+where
+    T: SnapshotCmd + Send + 'static,
+{
+    let mut snapshots = SnapshotTracker::default();
+    Subscription::spawn_polling(interval, move || {
+        snapshots.update(cmd.list_snapshots())
+    })
+}
+
+#[test]
+fn test_power_state_tracker() {
+    use VmPowerState::*;
+    let mut t = PowerStateTracker::default();
+    assert!(matches!(
+        t.update(Ok(Running)),
+        Some(VmEvent::PowerStateChanged {
+            from: None,
+            to: Running
+        })
+    ));
+    assert!(t.update(Ok(Running)).is_none());
+    assert!(matches!(
+        t.update(vmerr!(ErrorKind::Timeout)),
+        Some(VmEvent::Error(_))
+    ));
+    assert!(matches!(
+        t.update(Ok(Stopped)),
+        Some(VmEvent::PowerStateChanged {
+            from: Some(Running),
+            to: Stopped
+        })
+    ));
+}
+
+#[test]
+fn test_snapshot_tracker() {
+    let snap = |id: Option<&str>, name: &str| Snapshot {
+        id: id.map(|x| x.to_string()),
+        name: Some(name.to_string()),
+        ..Default::default()
+    };
+    let mut t = SnapshotTracker::default();
+    assert!(t.update(Ok(vec![snap(Some("1"), "a")])).is_empty());
+    let evs = t.update(Ok(vec![snap(Some("2"), "a"), snap(None, "b")]));
+    let evs: Vec<_> = evs
+        .iter()
+        .map(|x| match x {
+            VmEvent::SnapshotTaken(x) => ("taken", x.name.as_deref()),
+            VmEvent::SnapshotDeleted(x) => ("deleted", x.name.as_deref()),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(
+        evs,
+        [
+            ("deleted", Some("a")),
+            ("taken", Some("a")),
+            ("taken", Some("b"))
+        ]
+    );
+    assert!(t
+        .update(Ok(vec![snap(Some("2"), "a"), snap(None, "b")]))
+        .is_empty());
+}
+
+#[test]
+fn test_subscription() {
+    let mut n = 0;
+    let sub =
+        Subscription::spawn_polling(Duration::from_millis(1), move || {
+            n += 1;
+            vec![VmEvent::Error(VmError::from(ErrorKind::Timeout)); n % 2]
+        });
+    assert!(matches!(
+        sub.recv_timeout(Duration::from_secs(5)),
+        Some(VmEvent::Error(_))
+    ));
+}
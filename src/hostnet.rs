@@ -0,0 +1,184 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Host-side virtual networks.
+//!
+//! [`HostNetworkCmd`] creates, lists and deletes the networks of the host which VMs are attached to, such as host-only interfaces and NAT networks of VirtualBox, virtual switches of Hyper-V and vmnets of VMware.
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+
+/// Represents the kind of a host network.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum HostNetworkKind {
+    /// A network between the host and VMs.
+    HostOnly,
+    /// A host-only network whose traffic to the outside is translated by the host.
+    Nat,
+    /// A network between VMs, which the host is not connected to.
+    Internal,
+    /// A network bridged to a physical network of the host.
+    Bridged,
+}
+
+/// Represents a network of a host.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HostNetwork {
+    /// The name of the network.
+    ///
+    /// Some tools name new networks by themselves, e.g., host-only interfaces of VirtualBox.
+    pub name: String,
+    pub kind: HostNetworkKind,
+    /// The IPv4 network in CIDR notation, e.g., `192.168.56.0/24`.
+    pub subnet: Option<String>,
+    /// The address of the host on the network. `None` on creation is the first address of `subnet`.
+    pub host_ip: Option<String>,
+    /// Whether the DHCP server of the tool serves the network. `None` if unknown.
+    pub dhcp: Option<bool>,
+}
+
+impl HostNetwork {
+    pub fn new<S: Into<String>>(name: S, kind: HostNetworkKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            subnet: None,
+            host_ip: None,
+            dhcp: None,
+        }
+    }
+
+    /// Returns the network address and the prefix length of `subnet`.
+    ///
+    /// Returns [`ErrorKind::InvalidParameter`] if `subnet` is not specified or is not an IPv4 network in CIDR notation.
+    pub fn parse_subnet(&self) -> VmResult<(Ipv4Addr, u8)> {
+        self.subnet.as_deref().and_then(parse_cidr).ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(format!(
+                "subnet must be an IPv4 network in CIDR notation: {:?}",
+                self.subnet
+            )))
+        })
+    }
+
+    /// Returns `host_ip`, or the first address of `subnet` if it is not specified.
+    pub fn host_ip_or_default(&self) -> VmResult<Ipv4Addr> {
+        match &self.host_ip {
+            Some(x) => x.parse().map_err(|_| {
+                VmError::from(ErrorKind::InvalidParameter(format!(
+                    "host_ip is not an IPv4 address: {}",
+                    x
+                )))
+            }),
+            None => {
+                let (net, _) = self.parse_subnet()?;
+                Ok(Ipv4Addr::from(u32::from(net) + 1))
+            }
+        }
+    }
+}
+
+/// A trait for managing the networks of a host.
+pub trait HostNetworkCmd {
+    /// Returns the networks of the host.
+    fn list_host_networks(&self) -> VmResult<Vec<HostNetwork>>;
+    /// Creates a network and returns it with the name given by the tool.
+    fn create_host_network(&self, net: &HostNetwork) -> VmResult<HostNetwork>;
+    /// Deletes the network of `net.name` and `net.kind`.
+    fn delete_host_network(&self, net: &HostNetwork) -> VmResult<()>;
+}
+
+/// Parses an IPv4 network in CIDR notation, e.g., `192.168.56.0/24`, and returns the network address and the prefix length.
+pub fn parse_cidr(s: &str) -> Option<(Ipv4Addr, u8)> {
+    let (ip, prefix) = s.trim().split_once('/')?;
+    let ip: Ipv4Addr = ip.parse().ok()?;
+    let prefix: u8 = prefix.parse().ok().filter(|x| *x <= 32)?;
+    let net = u32::from(ip) & u32::from(prefix_to_netmask(prefix));
+    Some((Ipv4Addr::from(net), prefix))
+}
+
+/// Returns the network of `ip` and `prefix` in CIDR notation, e.g., `192.168.56.1` and `24` to `192.168.56.0/24`.
+pub fn to_cidr(ip: Ipv4Addr, prefix: u8) -> String {
+    let net = u32::from(ip) & u32::from(prefix_to_netmask(prefix));
+    format!("{}/{}", Ipv4Addr::from(net), prefix)
+}
+
+/// Converts a prefix length to a netmask, e.g., `24` to `255.255.255.0`.
+pub fn prefix_to_netmask(prefix: u8) -> Ipv4Addr {
+    match prefix {
+        0 => Ipv4Addr::UNSPECIFIED,
+        x => Ipv4Addr::from(u32::MAX << (32 - u32::from(x.min(32)))),
+    }
+}
+
+/// Converts a netmask to a prefix length, e.g., `255.255.255.0` to `24`.
+///
+/// Returns `None` if `mask` is not contiguous.
+pub fn netmask_to_prefix(mask: Ipv4Addr) -> Option<u8> {
+    let x = u32::from(mask);
+    let prefix = x.leading_ones();
+    if x.checked_shl(prefix).unwrap_or(0) != 0 {
+        return None;
+    }
+    Some(prefix as u8)
+}
+
+/// Returns the address of a DHCP server and the lowest and highest addresses it leases in the network of `net` and `prefix`.
+///
+/// The first address is left for the host, and the server takes the second address.
+#[allow(dead_code)]
+pub(crate) fn dhcp_range(
+    net: Ipv4Addr,
+    prefix: u8,
+) -> Option<(Ipv4Addr, Ipv4Addr, Ipv4Addr)> {
+    if prefix > 29 {
+        return None;
+    }
+    let net = u32::from(net);
+    let broadcast = net | !u32::from(prefix_to_netmask(prefix));
+    Some((
+        Ipv4Addr::from(net + 2),
+        Ipv4Addr::from(net + 3),
+        Ipv4Addr::from(broadcast - 1),
+    ))
+}
+
+#[test]
+fn test_cidr() {
+    let ip = |s: &str| s.parse::<Ipv4Addr>().unwrap();
+    assert_eq!(
+        parse_cidr("192.168.56.1/24"),
+        Some((ip("192.168.56.0"), 24))
+    );
+    assert_eq!(parse_cidr("10.0.0.0/8"), Some((ip("10.0.0.0"), 8)));
+    assert_eq!(parse_cidr("10.0.0.0/33"), None);
+    assert_eq!(parse_cidr("10.0.0.0"), None);
+    assert_eq!(to_cidr(ip("172.16.5.4"), 12), "172.16.0.0/12");
+    assert_eq!(prefix_to_netmask(24), ip("255.255.255.0"));
+    assert_eq!(prefix_to_netmask(0), ip("0.0.0.0"));
+    assert_eq!(prefix_to_netmask(32), ip("255.255.255.255"));
+    assert_eq!(netmask_to_prefix(ip("255.255.240.0")), Some(20));
+    assert_eq!(netmask_to_prefix(ip("255.255.255.255")), Some(32));
+    assert_eq!(netmask_to_prefix(ip("0.0.0.0")), Some(0));
+    assert_eq!(netmask_to_prefix(ip("255.0.255.0")), None);
+    assert_eq!(
+        dhcp_range(ip("192.168.56.0"), 24),
+        Some((ip("192.168.56.2"), ip("192.168.56.3"), ip("192.168.56.254")))
+    );
+    assert_eq!(dhcp_range(ip("192.168.56.0"), 30), None);
+}
+
+#[test]
+fn test_host_network() {
+    let mut net = HostNetwork::new("lab", HostNetworkKind::Nat);
+    assert!(net.parse_subnet().is_err());
+    assert!(net.host_ip_or_default().is_err());
+    net.subnet = Some("10.10.0.0/16".to_string());
+    assert_eq!(
+        net.host_ip_or_default().unwrap(),
+        "10.10.0.1".parse::<Ipv4Addr>().unwrap()
+    );
+    net.host_ip = Some("10.10.0.254".to_string());
+    assert_eq!(
+        net.host_ip_or_default().unwrap(),
+        "10.10.0.254".parse::<Ipv4Addr>().unwrap()
+    );
+}
@@ -0,0 +1,526 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Hyper-V Host Compute Service (HCS) controller.
+//!
+//! Controls compute systems through the [HCS APIs](https://docs.microsoft.com/en-us/virtualization/api/hcs/overview) in `computecore.dll` without spawning PowerShell or connecting to WMI.
+//! The APIs return HRESULTs and JSON documents, so the results don't depend on the display language of Windows.
+//!
+//! HCS only knows compute systems which have been created, so VMs managed by the Hyper-V Virtual Machine Management Service that are turned off are not listed.
+//! Use [`HyperVCmd`](super::HyperVCmd) or [`HyperVWmi`](super::HyperVWmi) to boot such VMs.
+use crate::types::*;
+use serde::Deserialize;
+use std::{
+    ffi::c_void,
+    time::{Duration, Instant},
+};
+
+type Hresult = i32;
+type HcsOperation = *mut c_void;
+type HcsSystem = *mut c_void;
+
+#[link(name = "computecore")]
+extern "system" {
+    fn HcsCreateOperation(
+        context: *const c_void,
+        callback: *const c_void,
+    ) -> HcsOperation;
+    fn HcsCloseOperation(operation: HcsOperation);
+    fn HcsWaitForOperationResult(
+        operation: HcsOperation,
+        timeout_ms: u32,
+        result_document: *mut *mut u16,
+    ) -> Hresult;
+    fn HcsEnumerateComputeSystems(
+        query: *const u16,
+        operation: HcsOperation,
+    ) -> Hresult;
+    fn HcsOpenComputeSystem(
+        id: *const u16,
+        requested_access: u32,
+        compute_system: *mut HcsSystem,
+    ) -> Hresult;
+    fn HcsCloseComputeSystem(compute_system: HcsSystem);
+    fn HcsStartComputeSystem(
+        compute_system: HcsSystem,
+        operation: HcsOperation,
+        options: *const u16,
+    ) -> Hresult;
+    fn HcsShutDownComputeSystem(
+        compute_system: HcsSystem,
+        operation: HcsOperation,
+        options: *const u16,
+    ) -> Hresult;
+    fn HcsTerminateComputeSystem(
+        compute_system: HcsSystem,
+        operation: HcsOperation,
+        options: *const u16,
+    ) -> Hresult;
+    fn HcsPauseComputeSystem(
+        compute_system: HcsSystem,
+        operation: HcsOperation,
+        options: *const u16,
+    ) -> Hresult;
+    fn HcsResumeComputeSystem(
+        compute_system: HcsSystem,
+        operation: HcsOperation,
+        options: *const u16,
+    ) -> Hresult;
+    fn HcsGetComputeSystemProperties(
+        compute_system: HcsSystem,
+        operation: HcsOperation,
+        property_query: *const u16,
+    ) -> Hresult;
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn LocalFree(mem: *mut c_void) -> *mut c_void;
+}
+
+const INFINITE: u32 = 0xFFFFFFFF;
+const GENERIC_ALL: u32 = 0x10000000;
+
+const E_ACCESSDENIED: Hresult = 0x80070005_u32 as Hresult;
+const HCS_E_HYPERV_NOT_INSTALLED: Hresult = 0x80370102_u32 as Hresult;
+const HCS_E_INVALID_STATE: Hresult = 0x80370105_u32 as Hresult;
+const HCS_E_SYSTEM_NOT_FOUND: Hresult = 0x8037010E_u32 as Hresult;
+const HCS_E_SYSTEM_ALREADY_EXISTS: Hresult = 0x8037010F_u32 as Hresult;
+const HCS_E_SYSTEM_ALREADY_STOPPED: Hresult = 0x80370110_u32 as Hresult;
+const HCS_E_OPERATION_TIMEOUT: Hresult = 0x80370118_u32 as Hresult;
+const HCS_E_SERVICE_NOT_AVAILABLE: Hresult = 0x80370114_u32 as Hresult;
+const HCS_E_ACCESS_DENIED: Hresult = 0x8037011B_u32 as Hresult;
+
+/// Represents a compute system in the result of `HcsEnumerateComputeSystems` and `HcsGetComputeSystemProperties`.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ComputeSystem {
+    id: String,
+    name: Option<String>,
+    state: Option<String>,
+}
+
+/// Represents Hyper-V HCS controller.
+#[derive(Clone, Debug)]
+pub struct HyperVHcs {
+    vm_id: Option<String>,
+    hooks: CommandHooks,
+}
+
+impl Default for HyperVHcs {
+    fn default() -> Self { Self::new() }
+}
+
+/// Closes an HCS operation handle on drop.
+struct Operation(HcsOperation);
+
+impl Operation {
+    fn new() -> VmResult<Self> {
+        let op =
+            unsafe { HcsCreateOperation(std::ptr::null(), std::ptr::null()) };
+        if op.is_null() {
+            return vmerr!(ErrorKind::ExecutionFailed(
+                "HcsCreateOperation failed".to_string()
+            ));
+        }
+        Ok(Self(op))
+    }
+
+    /// Waits for the operation and returns the result document.
+    fn wait(&self) -> VmResult<String> {
+        let mut doc: *mut u16 = std::ptr::null_mut();
+        let hr =
+            unsafe { HcsWaitForOperationResult(self.0, INFINITE, &mut doc) };
+        let doc = if doc.is_null() {
+            String::new()
+        } else {
+            unsafe {
+                let s = from_wide_ptr(doc);
+                LocalFree(doc as *mut c_void);
+                s
+            }
+        };
+        check_hresult(hr)?;
+        Ok(doc)
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) { unsafe { HcsCloseOperation(self.0) } }
+}
+
+/// Closes an HCS compute system handle on drop.
+struct System(HcsSystem);
+
+impl Drop for System {
+    fn drop(&mut self) { unsafe { HcsCloseComputeSystem(self.0) } }
+}
+
+impl HyperVHcs {
+    pub fn new() -> Self {
+        Self {
+            vm_id: None,
+            hooks: CommandHooks::new(),
+        }
+    }
+
+    impl_setter!(@opt
+        /// Sets the ID of the compute system to be manipulated. It is the same as the VMId of a Hyper-V VM.
+        vm_id: String
+    );
+
+    pub fn get_vm_id(&self) -> Option<&str> { self.vm_id.as_deref() }
+
+    impl_hooks!();
+
+    fn retrieve_vm(&self) -> VmResult<&str> {
+        self.vm_id
+            .as_deref()
+            .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
+    }
+
+    fn open(&self) -> VmResult<System> {
+        let id = to_wide(self.retrieve_vm()?);
+        let mut system: HcsSystem = std::ptr::null_mut();
+        check_hresult(unsafe {
+            HcsOpenComputeSystem(id.as_ptr(), GENERIC_ALL, &mut system)
+        })?;
+        Ok(System(system))
+    }
+
+    /// Calls an HCS function which takes a compute system, an operation and a JSON document, and waits for the result.
+    fn call(
+        &self,
+        name: &str,
+        f: unsafe extern "system" fn(
+            HcsSystem,
+            HcsOperation,
+            *const u16,
+        ) -> Hresult,
+        document: Option<&str>,
+    ) -> VmResult<String> {
+        let args: Vec<String> = std::iter::once(self.retrieve_vm()?)
+            .chain(document)
+            .map(|x| x.to_string())
+            .collect();
+        self.hooks.run(name, &args, || {
+            let system = self.open()?;
+            let op = Operation::new()?;
+            let document = document.map(to_wide);
+            check_hresult(unsafe {
+                f(
+                    system.0,
+                    op.0,
+                    document
+                        .as_ref()
+                        .map(|x| x.as_ptr())
+                        .unwrap_or(std::ptr::null()),
+                )
+            })?;
+            op.wait()
+        })
+    }
+
+    /// Gets the power state of the compute system.
+    ///
+    /// If the compute system doesn't exist, returns [`VmPowerState::Stopped`].
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        match self.call(
+            "HcsGetComputeSystemProperties",
+            HcsGetComputeSystemProperties,
+            None,
+        ) {
+            Ok(s) => {
+                let cs: ComputeSystem = crate::deserialize(&s)?;
+                Ok(to_power_state(cs.state.as_deref().unwrap_or_default()))
+            }
+            Err(e) if e == VmError::from(ErrorKind::VmNotFound) => {
+                Ok(VmPowerState::Stopped)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+}
+
+impl VmCmd for HyperVHcs {
+    /// Lists the virtual machine compute systems.
+    fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        let query = r#"{"Types":["VirtualMachine"]}"#;
+        let s = self.hooks.run(
+            "HcsEnumerateComputeSystems",
+            &[query.to_string()],
+            || {
+                let op = Operation::new()?;
+                let query = to_wide(query);
+                check_hresult(unsafe {
+                    HcsEnumerateComputeSystems(query.as_ptr(), op.0)
+                })?;
+                op.wait()
+            },
+        )?;
+        parse_compute_systems(&s)
+    }
+
+    /// `id` is the compute system ID, which is the same as VMId of `Get-VM|select VMId`.
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
+        for vm in self.list_vms()? {
+            if vm.id.as_deref().map_or(false, |x| uuid_eq(x, id)) {
+                self.vm_id(vm.id.clone());
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
+        for vm in self.list_vms()? {
+            if vm.name.as_deref() == Some(name) {
+                self.vm_id(vm.id.clone());
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    /// Due to the specification of HCS, HyperVHcs does not support this function.
+    fn set_vm_by_path(&mut self, _: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_vm_by_path",
+            "Use set_vm_by_name or set_vm_by_id instead"
+        ))
+    }
+
+    /// Reports the name and the power state of the compute system.
+    fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let s = self.call(
+            "HcsGetComputeSystemProperties",
+            HcsGetComputeSystemProperties,
+            None,
+        )?;
+        let cs: ComputeSystem = crate::deserialize(&s)?;
+        Ok(VmInfo {
+            name: cs.name,
+            power_state: cs.state.as_deref().map(to_power_state),
+            ..VmInfo::default()
+        })
+    }
+}
+
+impl PowerCmd for HyperVHcs {
+    /// Starts the compute system, or resumes it if it is paused.
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| match self.get_power_state()? {
+            VmPowerState::Running => {
+                vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running))
+            }
+            VmPowerState::Paused => self.unpause(),
+            _ => {
+                self.call(
+                    "HcsStartComputeSystem",
+                    HcsStartComputeSystem,
+                    None,
+                )?;
+                Ok(())
+            }
+        })
+    }
+
+    /// Shuts down the guest OS with `HcsShutDownComputeSystem`.
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            let state = self.get_power_state()?;
+            if state != VmPowerState::Running {
+                return vmerr!(ErrorKind::InvalidPowerState(state));
+            }
+            self.call(
+                "HcsShutDownComputeSystem",
+                HcsShutDownComputeSystem,
+                None,
+            )?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
+    }
+
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Paused,
+            ])?;
+            self.call(
+                "HcsTerminateComputeSystem",
+                HcsTerminateComputeSystem,
+                None,
+            )
+            .map_err(|e| {
+                if e == VmError::from(ErrorKind::VmNotFound) {
+                    VmError::from(ErrorKind::InvalidPowerState(
+                        VmPowerState::Stopped,
+                    ))
+                } else {
+                    e
+                }
+            })?;
+            Ok(())
+        })
+    }
+
+    /// Due to the specification of HCS, HyperVHcs does not support this function.
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "suspend",
+            "Use pause instead"
+        ))
+    }
+
+    /// Due to the specification of HCS, HyperVHcs does not support this function.
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "resume",
+            "Use unpause instead"
+        ))
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()?.is_running())
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.stop(timeout)?;
+        self.start()
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.hard_stop()?;
+        self.start()
+    }
+
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.call("HcsPauseComputeSystem", HcsPauseComputeSystem, None)?;
+        Ok(())
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.check_power_state(&[VmPowerState::Paused])?;
+        self.call("HcsResumeComputeSystem", HcsResumeComputeSystem, None)?;
+        Ok(())
+    }
+}
+
+fn to_wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Converts a null-terminated UTF-16 string into a `String`.
+///
+/// # Safety
+///
+/// `p` must point to a null-terminated UTF-16 string.
+unsafe fn from_wide_ptr(p: *const u16) -> String {
+    let mut len = 0;
+    while *p.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(std::slice::from_raw_parts(p, len))
+}
+
+fn to_power_state(s: &str) -> VmPowerState {
+    match s {
+        "Running" => VmPowerState::Running,
+        "Paused" => VmPowerState::Paused,
+        "Created" | "Stopped" => VmPowerState::Stopped,
+        "SavedAsTemplate" => VmPowerState::Suspended,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+/// Converts an HRESULT returned by the HCS APIs into an error.
+fn check_hresult(hr: Hresult) -> VmResult<()> {
+    match hr {
+        x if x >= 0 => Ok(()),
+        E_ACCESSDENIED | HCS_E_ACCESS_DENIED => {
+            vmerr!(ErrorKind::PrivilegesRequired)
+        }
+        HCS_E_SYSTEM_NOT_FOUND => vmerr!(ErrorKind::VmNotFound),
+        HCS_E_SYSTEM_ALREADY_EXISTS => {
+            vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running))
+        }
+        HCS_E_SYSTEM_ALREADY_STOPPED => {
+            vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Stopped))
+        }
+        HCS_E_INVALID_STATE => {
+            vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Unknown))
+        }
+        HCS_E_OPERATION_TIMEOUT => vmerr!(ErrorKind::Timeout),
+        HCS_E_HYPERV_NOT_INSTALLED | HCS_E_SERVICE_NOT_AVAILABLE => {
+            vmerr!(ErrorKind::ServiceIsNotRunning)
+        }
+        x => vmerr!(ErrorKind::ExecutionFailed(format!(
+            "HRESULT 0x{:08X}",
+            x as u32
+        ))),
+    }
+}
+
+fn parse_compute_systems(s: &str) -> VmResult<Vec<Vm>> {
+    let systems: Vec<ComputeSystem> = crate::deserialize(s)?;
+    Ok(systems
+        .into_iter()
+        .map(|x| Vm {
+            id: Some(x.id),
+            name: x.name,
+            path: None,
+        })
+        .collect())
+}
+
+#[test]
+fn test_parse_compute_systems() {
+    let s = r#"[{"Id":"0F6E5A6C-1B2B-4C3D-8E9F-A0B1C2D3E4F5","SystemType":"VirtualMachine","Name":"MyVM","Owner":"VMMS","RuntimeId":"0f6e5a6c-1b2b-4c3d-8e9f-a0b1c2d3e4f5","State":"Running"}]"#;
+    assert_eq!(
+        parse_compute_systems(s).unwrap(),
+        vec![Vm {
+            id: Some("0F6E5A6C-1B2B-4C3D-8E9F-A0B1C2D3E4F5".to_string()),
+            name: Some("MyVM".to_string()),
+            path: None,
+        }]
+    );
+    assert_eq!(parse_compute_systems("[]").unwrap(), vec![]);
+    assert_eq!(to_power_state("Paused"), VmPowerState::Paused);
+    assert_eq!(check_hresult(0), Ok(()));
+    assert_eq!(
+        check_hresult(HCS_E_SYSTEM_ALREADY_STOPPED),
+        vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Stopped))
+    );
+    assert_eq!(
+        check_hresult(HCS_E_SYSTEM_NOT_FOUND),
+        vmerr!(ErrorKind::VmNotFound)
+    );
+}
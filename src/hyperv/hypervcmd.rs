@@ -3,16 +3,26 @@
 //! Hyper-V cmdlets controller.
 //!
 //! Note: [In Windows Server 2012 R2, virtual machine snapshots were renamed to virtual machine checkpoints](https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-server-2012-r2-and-2012/dn818483(v=ws.11))
-use crate::{deserialize, exec_cmd_astr, types::*};
-use serde::Deserialize;
-use std::{ffi::OsStr, process::Command, time::Duration};
+use crate::{
+    deserialize, exec_cmd_astr,
+    hostnet::{self, HostNetwork, HostNetworkCmd, HostNetworkKind},
+    join_windows_args, tool_path_from_env,
+    types::*,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
 
 /// Escapes an argument.
 ///
 /// Surrounds the argument with single quotes and escapes single quotes.
 pub fn escape_pwsh<S: AsRef<str>>(s: S) -> String {
     let s = s.as_ref();
-    let mut ret = String::with_capacity(s.as_bytes().len() + 2);
+    let mut ret = String::with_capacity(s.len() + 2);
     ret.push('\'');
     for ch in s.chars() {
         if ch == '\'' {
@@ -24,6 +34,199 @@ pub fn escape_pwsh<S: AsRef<str>>(s: S) -> String {
     ret
 }
 
+/// Represents a warning written by a cmdlet.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum PsWarning {
+    /// The virtual machine is already in the specified state.
+    AlreadyInSpecifiedState,
+    /// Other warnings.
+    Other(String),
+}
+
+impl PsWarning {
+    /// Returns the warning message without `WARNING: `.
+    pub fn message(&self) -> &str {
+        match self {
+            Self::AlreadyInSpecifiedState => ALREADY_IN_SPECIFIED_STATE,
+            Self::Other(x) => x,
+        }
+    }
+}
+
+const ALREADY_IN_SPECIFIED_STATE: &str =
+    "The virtual machine is already in the specified state.";
+
+/// Represents the type of a virtual switch created by `New-VMSwitch`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum VmSwitchType {
+    /// A switch between VMs.
+    Private,
+    /// A switch between the host and VMs.
+    Internal,
+}
+
+impl VmSwitchType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Private => "Private",
+            Self::Internal => "Internal",
+        }
+    }
+}
+
+/// Returns true if `Status` of `Get-VM` reports a merge, e.g., `Merging disks (35%)`.
+fn is_merging_status(s: &str) -> bool { s.starts_with("Merging disks") }
+
+/// Converts `Name` (e.g., `COM 1`) and `Path` of `Get-VMComPort` into [`SerialPort`].
+///
+/// Hyper-V connects COM ports only to named pipes, and a port without a pipe is disconnected.
+fn to_serial_port(name: &str, path: &str) -> Option<SerialPort> {
+    Some(SerialPort {
+        number: name.strip_prefix("COM")?.trim().parse().ok()?,
+        mode: if path.is_empty() {
+            SerialPortMode::Disconnected
+        } else {
+            SerialPortMode::PipeServer(path.to_string())
+        },
+    })
+}
+
+/// Converts a virtual switch written by [`raw::get_vm_switches`] into [`HostNetwork`].
+///
+/// An internal switch is a NAT network if it has a NetNat of the same name.
+fn to_host_network(
+    name: &str,
+    switch_type: &str,
+    nat_prefix: Option<&str>,
+    ip: Option<&str>,
+    prefix_len: Option<u8>,
+) -> Option<HostNetwork> {
+    let kind = match (switch_type, nat_prefix) {
+        ("Private", _) => HostNetworkKind::Internal,
+        ("Internal", Some(_)) => HostNetworkKind::Nat,
+        ("Internal", None) => HostNetworkKind::HostOnly,
+        ("External", _) => HostNetworkKind::Bridged,
+        _ => return None,
+    };
+    let subnet = match (nat_prefix, ip, prefix_len) {
+        (Some(x), _, _) => Some(x.to_string()),
+        (None, Some(ip), Some(len)) => {
+            ip.parse().ok().map(|ip| hostnet::to_cidr(ip, len))
+        }
+        _ => None,
+    };
+    Some(HostNetwork {
+        name: name.to_string(),
+        kind,
+        subnet,
+        host_ip: ip.map(|x| x.to_string()),
+        dhcp: Some(false),
+    })
+}
+
+/// Converts `State` of `Get-VM` to [`VmPowerState`].
+fn to_power_state(state: u8) -> VmPowerState {
+    macro_rules! m {
+        ($x:ident) => {
+            state == PowerShellVmState::$x as u8
+        };
+    }
+    if m!(Running) || m!(RunningCritical) {
+        VmPowerState::Running
+    } else if m!(Off) || m!(OffCritical) {
+        VmPowerState::Stopped
+    } else if m!(Saved) || m!(SavedCritical) || m!(FastSaved) {
+        VmPowerState::Suspended
+    } else if m!(Paused) || m!(PausedCritical) {
+        VmPowerState::Paused
+    } else {
+        VmPowerState::Unknown
+    }
+}
+
+/// Returns the generation of a VM which boots with `firmware`.
+fn to_generation(firmware: Firmware) -> u8 {
+    match firmware {
+        Firmware::Bios => 1,
+        Firmware::Efi => 2,
+    }
+}
+
+/// Parses the VM information written by [`raw_unescaped::get_vm_info_unescaped`].
+///
+/// Generation 2 VMs boot with UEFI.
+fn parse_vm_info(s: &str) -> VmResult<VmInfo> {
+    #[derive(Deserialize)]
+    struct Response {
+        #[serde(alias = "Name")]
+        name: String,
+        #[serde(alias = "Generation")]
+        generation: u32,
+        #[serde(alias = "MemoryStartup")]
+        memory_startup: u64,
+        #[serde(alias = "ProcessorCount")]
+        processor_count: u32,
+        #[serde(alias = "State")]
+        state: u8,
+        #[serde(alias = "ConfigurationLocation")]
+        configuration_location: Option<String>,
+        #[serde(alias = "IntegrationServicesVersion")]
+        integration_services_version: Option<String>,
+    }
+    if s.trim().is_empty() {
+        return vmerr!(ErrorKind::VmNotFound);
+    }
+    let r = deserialize::<Response>(s)?;
+    Ok(VmInfo {
+        name: Some(r.name),
+        guest_os: None,
+        memory_mb: Some(r.memory_startup / 1024 / 1024),
+        cpu_count: Some(r.processor_count),
+        firmware: Some(if r.generation >= 2 {
+            Firmware::Efi
+        } else {
+            Firmware::Bios
+        }),
+        generation: Some(r.generation),
+        power_state: Some(to_power_state(r.state)),
+        config_path: r.configuration_location,
+        tools_version: r.integration_services_version.filter(|x| !x.is_empty()),
+    })
+}
+
+/// Parses the VM created by [`raw_unescaped::clone_vm_unescaped`], [`raw_unescaped::new_linked_clone_vm_unescaped`] or [`raw_unescaped::import_vm_unescaped`].
+fn parse_cloned_vm(s: &str) -> VmResult<Vm> {
+    #[derive(Deserialize)]
+    struct Response {
+        #[serde(alias = "VMId")]
+        id: String,
+        #[serde(alias = "Name")]
+        name: String,
+        #[serde(alias = "Path")]
+        path: String,
+    }
+    if s.trim().is_empty() {
+        return vmerr!(ErrorKind::VmNotFound);
+    }
+    let r = deserialize::<Response>(s)?;
+    Ok(Vm {
+        id: Some(r.id),
+        name: Some(r.name),
+        path: Some(r.path),
+    })
+}
+
+/// Parses `WARNING: ` lines written by a cmdlet.
+pub fn parse_warnings(s: &str) -> Vec<PsWarning> {
+    s.lines()
+        .filter_map(|x| x.trim().strip_prefix("WARNING: "))
+        .map(|x| match x {
+            ALREADY_IN_SPECIFIED_STATE => PsWarning::AlreadyInSpecifiedState,
+            x => PsWarning::Other(x.to_string()),
+        })
+        .collect()
+}
+
 /// Represents Hyper-V powershell command executor.
 #[derive(Clone, Debug)]
 pub struct HyperVCmd {
@@ -31,15 +234,24 @@ pub struct HyperVCmd {
     vm_name: Option<String>,
     guest_username: Option<String>,
     guest_password: Option<String>,
+    guest_credentials: GuestCredentials,
+    merge_timeout: Option<Duration>,
+    hooks: CommandHooks,
 }
 
 impl Default for HyperVCmd {
     fn default() -> Self {
         Self {
-            executable_path: "powershell".to_string(),
+            executable_path: tool_path_from_env(
+                "HVCTRL_POWERSHELL_PATH",
+                "powershell",
+            ),
             vm_name: None,
             guest_username: None,
             guest_password: None,
+            guest_credentials: GuestCredentials::new(),
+            merge_timeout: None,
+            hooks: CommandHooks::new(),
         }
     }
 }
@@ -52,7 +264,7 @@ struct PsCommand {
 impl PsCommand {
     fn new(pwsh_path: &str, cmdlet_name: &'static str) -> Self {
         let mut cmd = Command::new(pwsh_path);
-        cmd.args(&[
+        cmd.args([
             "-NoProfile",
             "-NoLogo",
             "-Command",
@@ -70,7 +282,7 @@ impl PsCommand {
         password: &str,
     ) -> Self {
         let mut cmd = Command::new(pwsh_path);
-        cmd.args(&[
+        cmd.args([
             "-NoProfile",
             "-NoLogo",
             "-Command",
@@ -88,17 +300,17 @@ impl PsCommand {
         username: &str,
         password: &str,
     ) -> &mut Self {
-        self.cmd.args(&[
+        self.cmd.args([
             "$password = ConvertTo-SecureString",
             password,
             "-AsPlainText -Force;",
         ]);
-        self.cmd.args(&[
+        self.cmd.args([
             "$cred = New-Object System.Management.Automation.PSCredential (",
             username,
             ", $password);",
         ]);
-        self.cmd.args(&[
+        self.cmd.args([
             "$sess = New-PSSession -VMName",
             vm,
             "-Credential $cred;",
@@ -132,12 +344,12 @@ impl PsCommand {
     }
 
     fn exec(&mut self) -> VmResult<String> {
-        let (stdout, stderr) = exec_cmd_astr(&mut self.cmd)?;
-        if !stderr.is_empty() {
-            Self::check(stderr, self.cmdlet_name)
+        let o = exec_cmd_astr(&mut self.cmd)?;
+        o.check_status(if !o.stderr.is_empty() {
+            Self::check(o.stderr.clone(), self.cmdlet_name)
         } else {
-            Ok(stdout)
-        }
+            Ok(o.stdout.clone())
+        })
     }
 
     #[inline]
@@ -225,6 +437,346 @@ impl HyperVCmd {
 
     pub fn get_vm_name(&self) -> Option<&str> { self.vm_name.as_deref() }
 
+    /// Sets the guest credentials per VM name.
+    ///
+    /// A credential registered for the VM is used instead of the guest username and password.
+    /// OS families are ignored because Hyper-V doesn't report the guest OS of a stopped VM.
+    /// The domain of a credential is prepended to the username as `DOMAIN\user`.
+    pub fn guest_credentials(
+        &mut self,
+        guest_credentials: GuestCredentials,
+    ) -> &mut Self {
+        // Escapes the credentials on input like the other parameters.
+        self.guest_credentials = guest_credentials.map(
+            |k| escape_pwsh(k),
+            |x: &GuestCredential| {
+                let username = match &x.domain {
+                    Some(d) => format!("{}\\{}", d, x.username),
+                    None => x.username.clone(),
+                };
+                GuestCredential {
+                    username: escape_pwsh(username),
+                    password: escape_pwsh(&x.password),
+                    domain: None,
+                }
+            },
+        );
+        self
+    }
+
+    impl_setter!(@opt
+    /// Makes [`SnapshotCmd::delete_snapshot`] wait up to the duration for the differencing disks to be merged.
+    ///
+    /// `Remove-VMSnapshot` returns before the merge completes, so disk operations right after deleting a checkpoint may fail.
+        merge_timeout: Duration
+    );
+
+    pub fn get_merge_timeout(&self) -> Option<Duration> { self.merge_timeout }
+
+    impl_hooks!();
+
+    /// Calls `f` with the hooks of the cmdlet named `cmdlet_name`.
+    fn hooked<T, F: FnOnce() -> VmResult<T>>(
+        &self,
+        cmdlet_name: &str,
+        args: &[&str],
+        f: F,
+    ) -> VmResult<T> {
+        let args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+        self.hooks.run(cmdlet_name, &args, f)
+    }
+
+    /// Shuts down the guest OS with the Shutdown integration service and waits for the VM to stop.
+    ///
+    /// Unlike [`PowerCmd::stop`], returns [`ErrorKind::ServiceIsNotRunning`] if the Shutdown integration service is disabled or the guest has no heartbeat.
+    pub fn shutdown_guest_via_integration<D: Into<Option<Duration>>>(
+        &self,
+        timeout: D,
+    ) -> VmResult<()> {
+        let timeout = timeout.into();
+        let s = Instant::now();
+        let vm = self.retrieve_vm()?;
+        let state = self.hooked("Get-VM", &[vm], || unsafe {
+            raw_unescaped::get_power_state_unescaped(&self.executable_path, vm)
+        })?;
+        if state != VmPowerState::Running {
+            return vmerr!(ErrorKind::InvalidPowerState(state));
+        }
+        let status =
+            self.hooked("Get-VMIntegrationService", &[vm], || unsafe {
+                raw_unescaped::get_shutdown_service_status_unescaped(
+                    &self.executable_path,
+                    vm,
+                )
+            })?;
+        if !status.enabled || !status.has_heartbeat {
+            return vmerr!(ErrorKind::ServiceIsNotRunning);
+        }
+        self.hooked("Stop-VM", &[vm], || unsafe {
+            raw_unescaped::stop_vm_unescaped(
+                &self.executable_path,
+                &[vm],
+                false,
+                false,
+            )
+        })?;
+        self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+    }
+
+    /// Waits for the differencing disks of the VM to be merged.
+    ///
+    /// Returns [`ErrorKind::Timeout`] if the merge doesn't complete in `timeout`.
+    pub fn wait_for_merge<D: Into<Option<Duration>>>(
+        &self,
+        timeout: D,
+    ) -> VmResult<()> {
+        let timeout = timeout.into();
+        let s = Instant::now();
+        let vm = self.retrieve_vm()?;
+        loop {
+            let merging = self.hooked("Get-VM", &[vm], || unsafe {
+                raw_unescaped::is_merging_disks_unescaped(
+                    &self.executable_path,
+                    vm,
+                )
+            })?;
+            if !merging {
+                return Ok(());
+            }
+            if let Some(x) = timeout {
+                if s.elapsed() >= x {
+                    return vmerr!(ErrorKind::Timeout);
+                }
+            }
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    /// Writes a `.rdp` file which connects to the console of the VM in an enhanced session.
+    ///
+    /// See [`rdp_file_content`](super::rdp_file_content) for details.
+    pub fn generate_rdp_file<P: AsRef<Path>>(&self, path: P) -> VmResult<()> {
+        let vm = self.retrieve_vm()?;
+        let id = self
+            .list_vms()?
+            .into_iter()
+            .find(|x| x.name.as_deref().map(escape_pwsh).as_deref() == Some(vm))
+            .and_then(|x| x.id)
+            .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))?;
+        let content = super::rdp_file_content(&super::local_host_name(), &id);
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Gets users allowed to connect to the console of the VM with `Get-VMConnectAccess`.
+    ///
+    /// Users in the Hyper-V Administrators group can connect regardless of this list.
+    pub fn list_connect_access(&self) -> VmResult<Vec<VmConnectAccess>> {
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VMConnectAccess", &[vm], || unsafe {
+            raw_unescaped::get_vm_connect_access_unescaped(
+                &self.executable_path,
+                vm,
+            )
+        })
+    }
+
+    /// Allows `user_name` to connect to the console of the VM with `Grant-VMConnectAccess`.
+    pub fn grant_connect_access(&self, user_name: &str) -> VmResult<()> {
+        let vm = self.retrieve_vm()?;
+        let user_name = escape_pwsh(user_name);
+        self.hooked("Grant-VMConnectAccess", &[vm, &user_name], || unsafe {
+            raw_unescaped::grant_vm_connect_access_unescaped(
+                &self.executable_path,
+                vm,
+                &user_name,
+            )
+        })
+    }
+
+    /// Disallows `user_name` to connect to the console of the VM with `Revoke-VMConnectAccess`.
+    pub fn revoke_connect_access(&self, user_name: &str) -> VmResult<()> {
+        let vm = self.retrieve_vm()?;
+        let user_name = escape_pwsh(user_name);
+        self.hooked("Revoke-VMConnectAccess", &[vm, &user_name], || unsafe {
+            raw_unescaped::revoke_vm_connect_access_unescaped(
+                &self.executable_path,
+                vm,
+                &user_name,
+            )
+        })
+    }
+
+    /// Creates a VM named `name` from `template` in one call.
+    ///
+    /// Copies the VHDX image of the template to the default virtual hard disk directory of the host, creates the VM with `New-VM` and applies the processor and firmware settings.
+    /// Returns [`ErrorKind::HostFileExists`] if a disk image for `name` already exists.
+    /// If any step fails, the copied disk image and the created VM are removed.
+    pub fn create_from_template(
+        &self,
+        template: &VmTemplate,
+        name: &str,
+    ) -> VmResult<()> {
+        if !template.vhd_path.is_file() {
+            return vmerr!(ErrorKind::HostFileNotFound);
+        }
+        let vm = escape_pwsh(name);
+        let dir = self.hooked("Get-VMHost", &[], || unsafe {
+            raw_unescaped::get_vm_host_vhd_path_unescaped(&self.executable_path)
+        })?;
+        let dst = Path::new(&dir).join(format!("{}.vhdx", name));
+        if dst.exists() {
+            return vmerr!(ErrorKind::HostFileExists);
+        }
+        std::fs::copy(&template.vhd_path, &dst)?;
+        let vhd = escape_pwsh(dst.to_string_lossy());
+        let switch = template.switch_name.as_deref().map(escape_pwsh);
+        let generation = template.generation.unwrap_or(2);
+        let remove_vhd = || {
+            if let Err(e) = std::fs::remove_file(&dst) {
+                warn!("Failed to remove {}: {}", dst.display(), e);
+            }
+        };
+        if let Err(e) = self.hooked("New-VM", &[&vm, &vhd], || unsafe {
+            raw_unescaped::new_vm_unescaped(
+                &self.executable_path,
+                &vm,
+                generation,
+                template.memory_startup_bytes,
+                &vhd,
+                switch.as_deref(),
+            )
+        }) {
+            remove_vhd();
+            return Err(e);
+        }
+        let r = self.configure_template_vm(template, &vm, generation);
+        if r.is_err() {
+            // Removes the VM with its disks, including the copied one.
+            if let Err(e) = self.hooked("Remove-VM", &[&vm], || unsafe {
+                raw_unescaped::delete_vm_unescaped(&self.executable_path, &vm)
+            }) {
+                warn!("Failed to remove the VM {}: {}", name, e);
+            }
+            if dst.exists() {
+                remove_vhd();
+            }
+        }
+        r
+    }
+
+    /// Applies the processor and firmware settings of `template` to the escaped VM `vm`.
+    fn configure_template_vm(
+        &self,
+        template: &VmTemplate,
+        vm: &str,
+        generation: u8,
+    ) -> VmResult<()> {
+        if template.processor_count.is_some() {
+            let tuning = CpuTuning {
+                count: template.processor_count,
+                ..CpuTuning::default()
+            };
+            self.hooked("Set-VMProcessor", &[vm], || unsafe {
+                raw_unescaped::set_vm_processor_unescaped(
+                    &self.executable_path,
+                    [vm],
+                    &tuning,
+                )
+            })?;
+        }
+        if generation >= 2
+            && (template.secure_boot.is_some()
+                || template.secure_boot_template.is_some())
+        {
+            let sb_template =
+                template.secure_boot_template.as_deref().map(escape_pwsh);
+            self.hooked("Set-VMFirmware", &[vm], || unsafe {
+                raw_unescaped::set_vm_firmware_unescaped(
+                    &self.executable_path,
+                    vm,
+                    template.secure_boot,
+                    sb_template.as_deref(),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Gets the directories of the checkpoint files and the smart paging file of the VM.
+    pub fn get_file_locations(&self) -> VmResult<VmFileLocations> {
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VM", &[vm], || unsafe {
+            raw_unescaped::get_vm_file_locations_unescaped(
+                &self.executable_path,
+                vm,
+            )
+        })
+    }
+
+    /// Moves the directories of the checkpoint files and the smart paging file of the VM, e.g., to a fast scratch disk.
+    ///
+    /// Hyper-V uses the new directories for files created afterwards.
+    pub fn set_file_locations(
+        &self,
+        locations: &VmFileLocations,
+    ) -> VmResult<()> {
+        let vm = self.retrieve_vm()?;
+        let checkpoint = locations
+            .checkpoint_file_location
+            .as_deref()
+            .map(escape_pwsh);
+        let smart_paging =
+            locations.smart_paging_file_path.as_deref().map(escape_pwsh);
+        self.hooked("Set-VM", &[vm], || unsafe {
+            raw_unescaped::set_vm_file_locations_unescaped(
+                &self.executable_path,
+                vm,
+                checkpoint.as_deref(),
+                smart_paging.as_deref(),
+            )
+        })
+    }
+
+    /// Gets the IPv4 and IPv6 addresses of all network adapters of the VM.
+    ///
+    /// The interfaces are the MAC addresses of the adapters.
+    pub fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        let _op = self.hooks.operation("get_ip_addresses");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VMNetworkAdapter", &[vm], || unsafe {
+            raw_unescaped::get_vm_network_adapter_ip_addresses_unescaped(
+                &self.executable_path,
+                vm,
+            )
+        })
+    }
+
+    /// Gets the power state of the VM.
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VM", &[vm], || unsafe {
+            raw_unescaped::get_power_state_unescaped(&self.executable_path, vm)
+        })
+    }
+
+    /// Gets the detailed information of the VM.
+    ///
+    /// `memory_mb` is the startup memory and `tools_version` is the version of the integration services.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VM", &[vm], || unsafe {
+            raw_unescaped::get_vm_info_unescaped(&self.executable_path, vm)
+        })
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+
     fn retrieve_vm(&self) -> VmResult<&str> {
         // self.vm_name is escaped on input.
         self.vm_name
@@ -232,7 +784,15 @@ impl HyperVCmd {
             .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
     }
 
+    /// Returns the credential registered for the current VM.
+    fn find_guest_credential(&self) -> Option<&GuestCredential> {
+        self.guest_credentials.get_for_vm(self.vm_name.as_deref()?)
+    }
+
     fn retrieve_username(&self) -> VmResult<&str> {
+        if let Some(x) = self.find_guest_credential() {
+            return Ok(&x.username);
+        }
         // self.username is escaped on input.
         self.guest_username
             .as_deref()
@@ -240,6 +800,9 @@ impl HyperVCmd {
     }
 
     fn retrieve_password(&self) -> VmResult<&str> {
+        if let Some(x) = self.find_guest_credential() {
+            return Ok(&x.password);
+        }
         // self.password is escaped on input.
         self.guest_password
             .as_deref()
@@ -261,107 +824,157 @@ impl HyperVCmd {
 
 impl VmCmd for HyperVCmd {
     fn list_vms(&self) -> VmResult<Vec<Vm>> {
-        raw::get_vm(&self.executable_path)
+        let _op = self.hooks.operation("list_vms");
+        self.hooked("Get-VM", &[], || raw::get_vm(&self.executable_path))
     }
 
     /// `id` is VMId which can be obtained with `Get-VM|select VMId`.
-    fn set_vm_by_id(&mut self, id: &str) -> VmResult<()> {
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
         for vm in self.list_vms()? {
-            if id == vm.id.as_deref().expect("VMId does not exist") {
-                self.vm_name(vm.name);
-                return Ok(());
+            if uuid_eq(id, vm.id.as_deref().expect("VMId does not exist")) {
+                self.vm_name(vm.name.clone());
+                return Ok(vm);
             }
         }
         vmerr!(ErrorKind::VmNotFound)
     }
 
-    fn set_vm_by_name(&mut self, name: &str) -> VmResult<()> {
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
         for vm in self.list_vms()? {
             if name == vm.name.as_deref().expect("Name does not exist") {
-                self.vm_name(vm.name);
-                return Ok(());
+                self.vm_name(vm.name.clone());
+                return Ok(vm);
             }
         }
         vmerr!(ErrorKind::VmNotFound)
     }
 
     /// Due to the specification of Hyper-V, HyperVCmd does not support this function.
-    fn set_vm_by_path(&mut self, _: &str) -> VmResult<()> {
-        vmerr!(ErrorKind::UnsupportedCommand)
+    fn set_vm_by_path(&mut self, _: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_vm_by_path",
+            "Use set_vm_by_name or set_vm_by_id instead"
+        ))
     }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
 }
 
 impl PowerCmd for HyperVCmd {
     fn start(&self) -> VmResult<()> {
-        unsafe {
-            raw_unescaped::start_vm_unescaped(
-                &self.executable_path,
-                &[self.retrieve_vm()?],
-            )
-        }
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            self.check_power_state(&[
+                VmPowerState::Stopped,
+                VmPowerState::Suspended,
+            ])?;
+            let vm = self.retrieve_vm()?;
+            self.hooked("Start-VM", &[vm], || unsafe {
+                raw_unescaped::start_vm_unescaped(&self.executable_path, &[vm])
+            })
+            .and_then(|warnings| {
+                if warnings.contains(&PsWarning::AlreadyInSpecifiedState) {
+                    return vmerr!(ErrorKind::InvalidPowerState(
+                        VmPowerState::Running
+                    ));
+                }
+                // The VM has started, so the other warnings are reported to the warning callbacks.
+                for x in &warnings {
+                    self.hooks.report_warning("Start-VM", x.message());
+                }
+                Ok(())
+            })
+        })
     }
 
-    fn stop<D: Into<Option<Duration>>>(&self, _timeout: D) -> VmResult<()> {
-        unsafe {
-            raw_unescaped::stop_vm_unescaped(
-                &self.executable_path,
-                &[self.retrieve_vm()?],
-                false,
-                false,
-            )
-        }
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.check_power_state(&[VmPowerState::Running])?;
+            let vm = self.retrieve_vm()?;
+            self.hooked("Stop-VM", &[vm], || unsafe {
+                raw_unescaped::stop_vm_unescaped(
+                    &self.executable_path,
+                    &[vm],
+                    false,
+                    false,
+                )
+            })?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
     }
 
     fn hard_stop(&self) -> VmResult<()> {
-        unsafe {
-            raw_unescaped::stop_vm_unescaped(
-                &self.executable_path,
-                &[self.retrieve_vm()?],
-                true,
-                false,
-            )
-        }
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Paused,
+            ])?;
+            let vm = self.retrieve_vm()?;
+            self.hooked("Stop-VM", &[vm, "-TurnOff"], || unsafe {
+                raw_unescaped::stop_vm_unescaped(
+                    &self.executable_path,
+                    &[vm],
+                    true,
+                    false,
+                )
+            })
+        })
     }
 
     fn suspend(&self) -> VmResult<()> {
-        unsafe {
-            raw_unescaped::suspend_vm_unescaped(
-                &self.executable_path,
-                &[self.retrieve_vm()?],
-            )
-        }
+        let _op = self.hooks.operation("suspend");
+        self.check_power_state(&[VmPowerState::Running])?;
+        let vm = self.retrieve_vm()?;
+        self.hooked("Suspend-VM", &[vm], || unsafe {
+            raw_unescaped::suspend_vm_unescaped(&self.executable_path, &[vm])
+        })
     }
     fn resume(&self) -> VmResult<()> {
-        unsafe {
-            raw_unescaped::resume_vm_unescaped(
-                &self.executable_path,
-                &[self.retrieve_vm()?],
-            )
-        }
+        let _op = self.hooks.operation("resume");
+        self.check_power_state(&[VmPowerState::Paused])?;
+        let vm = self.retrieve_vm()?;
+        self.hooked("Resume-VM", &[vm], || unsafe {
+            raw_unescaped::resume_vm_unescaped(&self.executable_path, &[vm])
+        })
     }
 
     fn is_running(&self) -> VmResult<bool> {
-        unsafe {
-            Ok(raw_unescaped::get_power_state_unescaped(
-                &self.executable_path,
-                self.retrieve_vm()?,
-            )? == VmPowerState::Running)
-        }
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()?.is_running())
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
     }
 
     fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
         self.stop(timeout)?;
         self.start()
     }
 
     fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
         self.hard_stop()?;
         self.start()
     }
 
-    fn pause(&self) -> VmResult<()> { self.suspend() }
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.suspend()
+    }
 
-    fn unpause(&self) -> VmResult<()> { self.resume() }
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.resume()
+    }
 }
 
 #[test]
@@ -377,56 +990,379 @@ fn test_escape_pwsh() {
     assert_eq!("'MSEdge - $a`'", escape_pwsh("MSEdge - $a`"));
 }
 
+#[test]
+fn test_parse_warnings() {
+    let s = "WARNING: The virtual machine is already in the specified \
+             state.\r\nWARNING: The virtual machine 'vm2' could not be \
+             started.\r\n";
+    assert_eq!(
+        vec![
+            PsWarning::AlreadyInSpecifiedState,
+            PsWarning::Other(
+                "The virtual machine 'vm2' could not be started.".to_string()
+            ),
+        ],
+        parse_warnings(s)
+    );
+    assert_eq!(
+        parse_warnings(s)
+            .iter()
+            .map(PsWarning::message)
+            .collect::<Vec<_>>(),
+        [
+            "The virtual machine is already in the specified state.",
+            "The virtual machine 'vm2' could not be started."
+        ]
+    );
+    assert!(parse_warnings("").is_empty());
+}
+
+#[test]
+fn test_vm_template_from_definition() {
+    let t = VmTemplate::from_definition(
+        r#"{"vhdPath": "ubuntu.vhdx", "generation": 2, "processorCount": 4,
+            "secureBoot": true,
+            "secureBootTemplate": "MicrosoftUEFICertificateAuthority"}"#,
+        "templates",
+    )
+    .unwrap();
+    assert_eq!(Path::new("templates").join("ubuntu.vhdx"), t.vhd_path);
+    assert_eq!(Some(2), t.generation);
+    assert_eq!(Some(4), t.processor_count);
+    assert_eq!(None, t.memory_startup_bytes);
+    assert_eq!(Some(true), t.secure_boot);
+    let t = VmTemplate::from_definition(
+        r#"{"switchName": "Default Switch"}"#,
+        "templates",
+    )
+    .unwrap();
+    assert_eq!(PathBuf::new(), t.vhd_path);
+    assert_eq!(Some("Default Switch".to_string()), t.switch_name);
+}
+
+#[test]
+fn test_parse_cloned_vm() {
+    let vm = parse_cloned_vm(
+        r#"{"VMId": "1e6a5e4c-2b4a-4d3a-9a1c-0c8e2a6f7b11", "Name": "clone",
+            "Path": "C:\\ProgramData\\Microsoft\\Windows\\Hyper-V\\clone"}"#,
+    )
+    .unwrap();
+    assert_eq!(Some("clone"), vm.name.as_deref());
+    assert_eq!(
+        Some(r"C:\ProgramData\Microsoft\Windows\Hyper-V\clone"),
+        vm.path.as_deref()
+    );
+    assert_eq!(
+        vmerr!(ErrorKind::VmNotFound),
+        parse_cloned_vm("").map(|_| ())
+    );
+}
+
+#[test]
+fn test_parse_vm_info() {
+    let info = parse_vm_info(
+        r#"{"Name": "vm1", "Generation": 2, "MemoryStartup": 2147483648,
+            "ProcessorCount": 4, "State": 2,
+            "ConfigurationLocation": "C:\\ProgramData\\Microsoft\\Windows\\Hyper-V",
+            "IntegrationServicesVersion": ""}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        info,
+        VmInfo {
+            name: Some("vm1".to_string()),
+            guest_os: None,
+            memory_mb: Some(2048),
+            cpu_count: Some(4),
+            firmware: Some(Firmware::Efi),
+            generation: Some(2),
+            power_state: Some(VmPowerState::Running),
+            config_path: Some(
+                r"C:\ProgramData\Microsoft\Windows\Hyper-V".to_string()
+            ),
+            tools_version: None,
+        }
+    );
+    assert_eq!(vmerr!(ErrorKind::VmNotFound), parse_vm_info(""));
+}
+
+#[test]
+fn test_is_merging_status() {
+    assert!(is_merging_status("Merging disks (35%)"));
+    assert!(!is_merging_status("Operating normally"));
+    assert!(!is_merging_status(""));
+}
+
 impl SnapshotCmd for HyperVCmd {
     fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
-        unsafe {
-            raw_unescaped::get_vm_snapshot_unescaped(
-                &self.executable_path,
-                self.retrieve_vm()?,
-            )
-        }
+        let _op = self.hooks.operation("list_snapshots");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VMSnapshot", &[vm], || unsafe {
+            raw_unescaped::get_vm_snapshot_unescaped(&self.executable_path, vm)
+        })
     }
 
     fn take_snapshot(&self, name: &str) -> VmResult<()> {
-        unsafe {
+        let _op = self.hooks.operation("take_snapshot");
+        let vm = self.retrieve_vm()?;
+        let name = escape_pwsh(name);
+        self.hooked("Checkpoint-VM", &[vm, &name], || unsafe {
             raw_unescaped::checkpoint_vm_unescaped(
                 &self.executable_path,
-                &[self.retrieve_vm()?],
-                &escape_pwsh(name),
+                &[vm],
+                &name,
             )
-        }
+        })
     }
 
     fn revert_snapshot(&self, name: &str) -> VmResult<()> {
-        unsafe {
+        let _op = self.hooks.operation("revert_snapshot");
+        let vm = self.retrieve_vm()?;
+        let name = escape_pwsh(name);
+        self.hooked("Restore-VMSnapshot", &[vm, &name], || unsafe {
             raw_unescaped::restore_vm_snapshot_unescaped(
                 &self.executable_path,
-                self.retrieve_vm()?,
-                &escape_pwsh(name),
+                vm,
+                &name,
             )
-        }
+        })
     }
 
     fn delete_snapshot(&self, name: &str) -> VmResult<()> {
-        // Remove-VMSnapshot does not change the response regardless of whether a snapshot exists or not.
-        let sn = self.list_snapshots()?;
-        if !sn.iter().any(|x| x.name.as_deref() == Some(name)) {
-            // The snapshot named `name` doesn't exist.
-            return vmerr!(ErrorKind::SnapshotNotFound);
-        }
-        unsafe {
-            raw_unescaped::remove_vm_snapshot_unescaped(
+        let _op = self.hooks.operation("delete_snapshot");
+        self.hooks.settle_deleted(|| {
+            // Remove-VMSnapshot does not change the response regardless of whether a snapshot exists or not.
+            let sn = self.list_snapshots()?;
+            if !sn.iter().any(|x| x.name.as_deref() == Some(name)) {
+                // The snapshot named `name` doesn't exist.
+                return vmerr!(ErrorKind::SnapshotNotFound);
+            }
+            let vm = self.retrieve_vm()?;
+            let name = escape_pwsh(name);
+            self.hooked("Remove-VMSnapshot", &[vm, &name], || unsafe {
+                raw_unescaped::remove_vm_snapshot_unescaped(
+                    &self.executable_path,
+                    &[vm],
+                    &name,
+                )
+            })?;
+            match self.merge_timeout {
+                Some(x) => self.wait_for_merge(x),
+                None => Ok(()),
+            }
+        })
+    }
+
+    fn snapshot_disk_usage(&self) -> VmResult<Vec<SnapshotDiskUsage>> {
+        let _op = self.hooks.operation("snapshot_disk_usage");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VMSnapshot", &[vm], || unsafe {
+            raw_unescaped::get_vm_snapshot_disk_usage_unescaped(
                 &self.executable_path,
-                &[self.retrieve_vm()?],
-                &escape_pwsh(name),
+                vm,
             )
+        })
+    }
+
+    /// The current snapshot is the parent checkpoint of the VM.
+    fn snapshot_tree(&self) -> VmResult<SnapshotTree> {
+        let _op = self.hooks.operation("snapshot_tree");
+        let snapshots = self.list_snapshots()?;
+        let vm = self.retrieve_vm()?;
+        let current_id = self.hooked("Get-VM", &[vm], || unsafe {
+            raw_unescaped::get_vm_current_snapshot_id_unescaped(
+                &self.executable_path,
+                vm,
+            )
+        })?;
+        Ok(SnapshotTree::from_snapshots(
+            snapshots,
+            current_id.as_deref(),
+        ))
+    }
+}
+
+/// A full clone exports the VM (or the snapshot) to a temporary directory and imports a copy of it.
+/// A linked clone creates a new VM whose disks are differencing disks of the disks of the snapshot, so it requires `options.snapshot`.
+impl CloneCmd for HyperVCmd {
+    fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+        let _op = self.hooks.operation("clone_vm");
+        let vm = self.retrieve_vm()?;
+        let name = escape_pwsh(&options.name);
+        let snapshot = options.snapshot.as_deref().map(escape_pwsh);
+        let path = options.path.as_deref().map(escape_pwsh);
+        match options.ty {
+            CloneType::Full => {
+                self.hooked("Import-VM", &[vm, &name], || unsafe {
+                    raw_unescaped::clone_vm_unescaped(
+                        &self.executable_path,
+                        vm,
+                        &name,
+                        snapshot.as_deref(),
+                        path.as_deref(),
+                    )
+                })
+            }
+            CloneType::Linked => {
+                let snapshot = snapshot.ok_or_else(|| {
+                    VmError::from(ErrorKind::InvalidParameter(
+                        "A linked clone requires a snapshot".to_string(),
+                    ))
+                })?;
+                self.hooked("New-VM", &[vm, &name], || unsafe {
+                    raw_unescaped::new_linked_clone_vm_unescaped(
+                        &self.executable_path,
+                        vm,
+                        &name,
+                        &snapshot,
+                        path.as_deref(),
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Samples the disk and network throughput with the Hyper-V performance counters, which takes about a second.
+impl MetricsCmd for HyperVCmd {
+    fn get_metrics(&self) -> VmResult<VmMetrics> {
+        let _op = self.hooks.operation("get_metrics");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-Counter", &[vm], || unsafe {
+            raw_unescaped::get_vm_metrics_unescaped(&self.executable_path, vm)
+        })
+    }
+}
+
+impl VmLifecycleCmd for HyperVCmd {
+    /// [`Firmware::Efi`] creates a generation 2 VM and `spec.guest_os` is ignored.
+    fn create_vm(&self, spec: &VmSpec) -> VmResult<Vm> {
+        let _op = self.hooks.operation("create_vm");
+        let name = escape_pwsh(&spec.name);
+        let path = spec.path.as_deref().map(escape_pwsh);
+        self.hooked("New-VM", &[&name], || unsafe {
+            raw_unescaped::create_vm_unescaped(
+                &self.executable_path,
+                &name,
+                path.as_deref(),
+                spec.firmware.map(to_generation),
+                spec.memory_mb.map(|x| x * 1024 * 1024),
+                spec.cpu_count,
+            )
+        })
+    }
+
+    /// `path` is the path to the `.vmcx` file, which is registered in place.
+    fn register_vm(&self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("register_vm");
+        let path = escape_pwsh(path);
+        self.hooked("Import-VM", &[&path], || unsafe {
+            raw_unescaped::register_vm_unescaped(&self.executable_path, &path)
+        })
+    }
+
+    /// Due to the specification of Hyper-V, HyperVCmd does not support this function because `Remove-VM` deletes the configuration files.
+    fn unregister_vm(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unregister_vm");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "unregister_vm",
+            "Use export_vm and delete_vm instead"
+        ))
+    }
+
+    /// Removes the VM with `Remove-VM` and deletes the virtual hard disks of the VM and its checkpoints.
+    fn delete_vm(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_vm");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Remove-VM", &[vm], || unsafe {
+            raw_unescaped::delete_vm_unescaped(&self.executable_path, vm)
+        })
+    }
+}
+
+/// Hyper-V supports only [`ExportFormat::Native`], which exports the VM to the directory `path`.
+impl ImportExportCmd for HyperVCmd {
+    fn export_vm(&self, path: &str, format: ExportFormat) -> VmResult<()> {
+        let _op = self.hooks.operation("export_vm");
+        if format != ExportFormat::Native {
+            return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "export_vm",
+                "Use ExportFormat::Native instead"
+            ));
         }
+        let vm = self.retrieve_vm()?;
+        let path = escape_pwsh(path);
+        self.hooked("Export-VM", &[vm, &path], || unsafe {
+            raw_unescaped::export_vm_unescaped(&self.executable_path, vm, &path)
+        })
+    }
+
+    /// Imports a copy of the first VM found in the directory `path` with a new ID.
+    fn import_vm(&self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("import_vm");
+        let path = escape_pwsh(path);
+        self.hooked("Import-VM", &[&path], || unsafe {
+            raw_unescaped::import_vm_unescaped(&self.executable_path, &path)
+        })
     }
 }
 
 impl GuestCmd for HyperVCmd {
-    fn exec_cmd(&self, _guest_args: &[&str]) -> VmResult<()> {
-        unimplemented!("exec_cmd of HyperVCmd is not implemented")
+    fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
+        let _op = self.hooks.operation("exec_cmd");
+        self.exec_cmd_output(guest_args)?;
+        Ok(())
+    }
+
+    /// Runs a program in the guest with PowerShell Direct.
+    fn exec_cmd_output(&self, guest_args: &[&str]) -> VmResult<GuestOutput> {
+        let _op = self.hooks.operation("exec_cmd_output");
+        self.exec_cmd_with(guest_args, &GuestExecOptions::default())
+    }
+
+    /// Runs a program in the guest with PowerShell Direct.
+    ///
+    /// The program runs with the full token of the guest user, so `elevated` needs no extra step.
+    /// Returns [`ErrorKind::UnsupportedCommand`] if `interactive` or `active_window` is set, and [`ErrorKind::Timeout`] if the program is terminated by `timeout`.
+    fn exec_cmd_with(
+        &self,
+        guest_args: &[&str],
+        opts: &GuestExecOptions,
+    ) -> VmResult<GuestOutput> {
+        let _op = self.hooks.operation("exec_cmd_with");
+        if opts.interactive || opts.active_window {
+            return vmerr!(ErrorKind::unsupported::<Self>("exec_cmd_with"));
+        }
+        let (program, args) = match guest_args.split_first() {
+            Some(x) => x,
+            None => {
+                return vmerr!(ErrorKind::InvalidParameter(
+                    "guest_args".to_string()
+                ))
+            }
+        };
+        let vm = self.retrieve_vm()?;
+        let program = escape_pwsh(program);
+        let args = escape_pwsh(join_windows_args(args));
+        let cwd = opts.cwd.as_deref().map(escape_pwsh);
+        let env: Vec<(String, String)> = opts
+            .env
+            .iter()
+            .map(|(k, v)| (escape_pwsh(k), escape_pwsh(v)))
+            .collect();
+        self.hooked("Invoke-Command", &[vm, &program, &args], || unsafe {
+            raw_unescaped::invoke_program_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                &program,
+                &args,
+                cwd.as_deref(),
+                &env,
+                opts.timeout,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
     }
 
     fn copy_from_guest_to_host(
@@ -434,16 +1370,20 @@ impl GuestCmd for HyperVCmd {
         from_guest_path: &str,
         to_host_path: &str,
     ) -> VmResult<()> {
-        unsafe {
+        let _op = self.hooks.operation("copy_from_guest_to_host");
+        let vm = self.retrieve_vm()?;
+        let src_path = escape_pwsh(from_guest_path);
+        let dst_path = escape_pwsh(to_host_path);
+        self.hooked("Copy-Item", &[vm, &src_path, &dst_path], || unsafe {
             raw_unescaped::copy_vm_file_from_guest_to_host_unescaped(
                 &self.executable_path,
-                self.retrieve_vm()?,
-                &escape_pwsh(from_guest_path),
-                &escape_pwsh(to_host_path),
+                vm,
+                &src_path,
+                &dst_path,
                 self.retrieve_username()?,
                 self.retrieve_password()?,
             )
-        }
+        })
     }
 
     fn copy_from_host_to_guest(
@@ -451,18 +1391,523 @@ impl GuestCmd for HyperVCmd {
         from_host_path: &str,
         to_guest_path: &str,
     ) -> VmResult<()> {
-        unsafe {
+        let _op = self.hooks.operation("copy_from_host_to_guest");
+        let vm = self.retrieve_vm()?;
+        let src_path = escape_pwsh(from_host_path);
+        let dst_path = escape_pwsh(to_guest_path);
+        self.hooked("Copy-VMFile", &[vm, &src_path, &dst_path], || unsafe {
             raw_unescaped::copy_vm_file_unescaped(
                 &self.executable_path,
-                &[self.retrieve_vm()?],
-                &escape_pwsh(from_host_path),
-                &escape_pwsh(to_guest_path),
+                &[vm],
+                &src_path,
+                &dst_path,
                 true,
             )
+        })
+    }
+}
+
+impl ConfigCmd for HyperVCmd {
+    fn get_cpu_tuning(&self) -> VmResult<CpuTuning> {
+        let _op = self.hooks.operation("get_cpu_tuning");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VMProcessor", &[vm], || unsafe {
+            raw_unescaped::get_vm_processor_unescaped(&self.executable_path, vm)
+        })
+    }
+
+    fn set_cpu_tuning(&self, tuning: &CpuTuning) -> VmResult<()> {
+        let _op = self.hooks.operation("set_cpu_tuning");
+        if *tuning == CpuTuning::default() {
+            return Ok(());
+        }
+        let vm = self.retrieve_vm()?;
+        self.hooked("Set-VMProcessor", &[vm], || unsafe {
+            raw_unescaped::set_vm_processor_unescaped(
+                &self.executable_path,
+                &[vm],
+                tuning,
+            )
+        })
+    }
+
+    /// Returns the startup memory of the VM.
+    fn get_memory_size(&self) -> VmResult<u64> {
+        let _op = self.hooks.operation("get_memory_size");
+        let vm = self.retrieve_vm()?;
+        let bytes = self.hooked("Get-VM", &[vm], || unsafe {
+            raw_unescaped::get_vm_memory_startup_unescaped(
+                &self.executable_path,
+                vm,
+            )
+        })?;
+        Ok(bytes / (1024 * 1024))
+    }
+
+    /// Sets the startup memory of the VM.
+    fn set_memory_size(&self, size_mb: u64) -> VmResult<()> {
+        let _op = self.hooks.operation("set_memory_size");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Set-VM", &[vm], || unsafe {
+            raw_unescaped::set_vm_memory_startup_unescaped(
+                &self.executable_path,
+                vm,
+                size_mb * 1024 * 1024,
+            )
+        })
+    }
+}
+
+/// Hyper-V connects COM ports only to named pipes, so [`SerialPortMode::Off`] and [`SerialPortMode::Disconnected`] disconnect the port and the modes other than [`SerialPortMode::PipeServer`] are not supported.
+impl SerialPortCmd for HyperVCmd {
+    fn list_serial_ports(&self) -> VmResult<Vec<SerialPort>> {
+        let _op = self.hooks.operation("list_serial_ports");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Get-VMComPort", &[vm], || unsafe {
+            raw_unescaped::get_vm_com_ports_unescaped(&self.executable_path, vm)
+        })
+    }
+
+    fn set_serial_port(&self, n: usize, mode: &SerialPortMode) -> VmResult<()> {
+        let _op = self.hooks.operation("set_serial_port");
+        let vm = self.retrieve_vm()?;
+        let path = match mode {
+            SerialPortMode::Off | SerialPortMode::Disconnected => None,
+            SerialPortMode::PipeServer(x) => Some(escape_pwsh(x)),
+            _ => {
+                return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                    "set_serial_port",
+                    "Use SerialPortMode::PipeServer instead"
+                ))
+            }
+        };
+        let n_str = n.to_string();
+        let mut args = vec![vm, n_str.as_str()];
+        args.extend(path.as_deref());
+        self.hooked("Set-VMComPort", &args, || unsafe {
+            raw_unescaped::set_vm_com_port_unescaped(
+                &self.executable_path,
+                vm,
+                n,
+                path.as_deref(),
+            )
+        })
+    }
+}
+
+/// Manages virtual switches.
+///
+/// A host-only network is an internal switch, and a NAT network is an internal switch with a NetNat of the same name.
+/// Hyper-V has no DHCP server, so the guests of the networks need static addresses.
+impl HostNetworkCmd for HyperVCmd {
+    fn list_host_networks(&self) -> VmResult<Vec<HostNetwork>> {
+        let _op = self.hooks.operation("list_host_networks");
+        self.hooked("Get-VMSwitch", &[], || {
+            raw::get_vm_switches(&self.executable_path)
+        })
+    }
+
+    fn create_host_network(&self, net: &HostNetwork) -> VmResult<HostNetwork> {
+        let _op = self.hooks.operation("create_host_network");
+        if net.dhcp == Some(true) {
+            return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "create_host_network",
+                "Assign static addresses to the guests"
+            ));
+        }
+        let ty = match net.kind {
+            HostNetworkKind::HostOnly | HostNetworkKind::Nat => {
+                VmSwitchType::Internal
+            }
+            HostNetworkKind::Internal => VmSwitchType::Private,
+            HostNetworkKind::Bridged => {
+                return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                    "create_host_network",
+                    "Create an external switch with New-VMSwitch \
+                     -NetAdapterName"
+                ))
+            }
+        };
+        // Validate the parameters before creating the switch.
+        let subnet = match (net.kind, &net.subnet) {
+            (HostNetworkKind::Nat, _)
+            | (HostNetworkKind::HostOnly, Some(_)) => {
+                Some((net.parse_subnet()?, net.host_ip_or_default()?))
+            }
+            _ => None,
+        };
+        let name = escape_pwsh(&net.name);
+        self.hooked("New-VMSwitch", &[&name, ty.as_str()], || unsafe {
+            raw_unescaped::new_vm_switch_unescaped(
+                &self.executable_path,
+                &name,
+                ty,
+            )
+        })?;
+        let mut ret = HostNetwork {
+            dhcp: Some(false),
+            ..net.clone()
+        };
+        if let Some(((subnet, prefix), host_ip)) = subnet {
+            let ip = host_ip.to_string();
+            let prefix_str = prefix.to_string();
+            self.hooked(
+                "New-NetIPAddress",
+                &[&name, &ip, &prefix_str],
+                || unsafe {
+                    raw_unescaped::new_net_ip_address_unescaped(
+                        &self.executable_path,
+                        &name,
+                        &ip,
+                        prefix,
+                    )
+                },
+            )?;
+            let cidr = hostnet::to_cidr(subnet, prefix);
+            if net.kind == HostNetworkKind::Nat {
+                self.hooked("New-NetNat", &[&name, &cidr], || unsafe {
+                    raw_unescaped::new_net_nat_unescaped(
+                        &self.executable_path,
+                        &name,
+                        &cidr,
+                    )
+                })?;
+            }
+            ret.subnet = Some(cidr);
+            ret.host_ip = Some(ip);
+        }
+        Ok(ret)
+    }
+
+    fn delete_host_network(&self, net: &HostNetwork) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_host_network");
+        let name = escape_pwsh(&net.name);
+        if net.kind == HostNetworkKind::Nat {
+            self.hooked("Remove-NetNat", &[&name], || unsafe {
+                raw_unescaped::remove_net_nat_unescaped(
+                    &self.executable_path,
+                    &name,
+                )
+            })?;
+        }
+        self.hooked("Remove-VMSwitch", &[&name], || unsafe {
+            raw_unescaped::remove_vm_switch_unescaped(
+                &self.executable_path,
+                &name,
+            )
+        })
+    }
+}
+
+/// Uses the first DVD drive of the VM.
+impl MediaCmd for HyperVCmd {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        let vm = self.retrieve_vm()?;
+        let path = escape_pwsh(path);
+        self.hooked("Set-VMDvdDrive", &[vm, &path], || unsafe {
+            raw_unescaped::set_vm_dvd_drive_unescaped(
+                &self.executable_path,
+                vm,
+                Some(&path),
+            )
+        })
+    }
+
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Set-VMDvdDrive", &[vm], || unsafe {
+            raw_unescaped::set_vm_dvd_drive_unescaped(
+                &self.executable_path,
+                vm,
+                None,
+            )
+        })
+    }
+}
+
+/// [`PassthroughType::GpuPartition`] is GPU partitioning and [`PassthroughType::Pci`] is Discrete Device Assignment.
+///
+/// Before adding a device with Discrete Device Assignment, the device must be dismounted from the host with [`raw::dismount_vm_host_assignable_device`].
+impl PassthroughCmd for HyperVCmd {
+    fn list_passthrough_devices(&self) -> VmResult<Vec<PassthroughDevice>> {
+        let _op = self.hooks.operation("list_passthrough_devices");
+        let vm = self.retrieve_vm()?;
+        let mut ret =
+            self.hooked("Get-VMGpuPartitionAdapter", &[vm], || unsafe {
+                raw_unescaped::get_vm_gpu_partition_adapter_unescaped(
+                    &self.executable_path,
+                    vm,
+                )
+            })?;
+        ret.extend(self.hooked(
+            "Get-VMAssignableDevice",
+            &[vm],
+            || unsafe {
+                raw_unescaped::get_vm_assignable_device_unescaped(
+                    &self.executable_path,
+                    vm,
+                )
+            },
+        )?);
+        Ok(ret)
+    }
+
+    fn add_passthrough_device(&self, dev: &PassthroughDevice) -> VmResult<()> {
+        let _op = self.hooks.operation("add_passthrough_device");
+        let vm = self.retrieve_vm()?;
+        let host = dev.host_location.as_ref().map(escape_pwsh);
+        match (&dev.ty, host) {
+            (Some(PassthroughType::GpuPartition), host) => {
+                let args = [Some(vm), host.as_deref()];
+                let args: Vec<&str> = args.iter().flatten().copied().collect();
+                self.hooked("Add-VMGpuPartitionAdapter", &args, || unsafe {
+                    raw_unescaped::add_vm_gpu_partition_adapter_unescaped(
+                        &self.executable_path,
+                        vm,
+                        host.as_deref(),
+                    )
+                })
+            }
+            (Some(PassthroughType::Pci), Some(host)) => {
+                self.hooked("Add-VMAssignableDevice", &[vm, &host], || unsafe {
+                    raw_unescaped::add_vm_assignable_device_unescaped(
+                        &self.executable_path,
+                        vm,
+                        &host,
+                    )
+                })
+            }
+            (Some(PassthroughType::Pci), None) => {
+                vmerr!(ErrorKind::InvalidParameter(
+                    "host_location is required".to_string()
+                ))
+            }
+            (None, _) => {
+                vmerr!(ErrorKind::InvalidParameter(
+                    "ty is required".to_string()
+                ))
+            }
+        }
+    }
+
+    fn remove_passthrough_device(
+        &self,
+        dev: &PassthroughDevice,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_passthrough_device");
+        let vm = self.retrieve_vm()?;
+        match &dev.ty {
+            Some(PassthroughType::GpuPartition) => {
+                let id = dev.id.as_ref().map(escape_pwsh);
+                let args = [Some(vm), id.as_deref()];
+                let args: Vec<&str> = args.iter().flatten().copied().collect();
+                self.hooked("Remove-VMGpuPartitionAdapter", &args, || unsafe {
+                    raw_unescaped::remove_vm_gpu_partition_adapter_unescaped(
+                        &self.executable_path,
+                        vm,
+                        id.as_deref(),
+                    )
+                })
+            }
+            Some(PassthroughType::Pci) => {
+                let host = match &dev.host_location {
+                    Some(x) => escape_pwsh(x),
+                    None => {
+                        return vmerr!(ErrorKind::InvalidParameter(
+                            "host_location is required".to_string()
+                        ))
+                    }
+                };
+                self.hooked(
+                    "Remove-VMAssignableDevice",
+                    &[vm, &host],
+                    || unsafe {
+                        raw_unescaped::remove_vm_assignable_device_unescaped(
+                            &self.executable_path,
+                            vm,
+                            &host,
+                        )
+                    },
+                )
+            }
+            None => {
+                vmerr!(ErrorKind::InvalidParameter(
+                    "ty is required".to_string()
+                ))
+            }
         }
     }
 }
 
+/// Creates and removes the directory with PSSession.
+impl GuestTempDirCmd for HyperVCmd {
+    fn create_temp_dir_in_guest(&self) -> VmResult<String> {
+        let _op = self.hooks.operation("create_temp_dir_in_guest");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Invoke-Command", &[vm], || unsafe {
+            raw_unescaped::create_temp_dir_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+
+    fn remove_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_dir_in_guest");
+        let vm = self.retrieve_vm()?;
+        let path = escape_pwsh(guest_path);
+        self.hooked("Invoke-Command", &[vm, &path], || unsafe {
+            raw_unescaped::remove_dir_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                &path,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+}
+
+/// Manages the guest processes with PSSession.
+impl GuestNetworkCmd for HyperVCmd {
+    fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        Self::get_ip_addresses(self)
+    }
+}
+
+impl GuestProcessCmd for HyperVCmd {
+    fn list_processes_in_guest(&self) -> VmResult<Vec<ProcInfo>> {
+        let _op = self.hooks.operation("list_processes_in_guest");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Invoke-Command", &[vm], || unsafe {
+            raw_unescaped::get_process_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+
+    fn kill_process_in_guest(&self, pid: u32) -> VmResult<()> {
+        let _op = self.hooks.operation("kill_process_in_guest");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Invoke-Command", &[vm], || unsafe {
+            raw_unescaped::stop_process_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                pid,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+}
+
+/// Manipulates the guest filesystem with PSSession.
+impl GuestFsCmd for HyperVCmd {
+    fn make_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("make_dir_in_guest");
+        let vm = self.retrieve_vm()?;
+        let path = escape_pwsh(guest_path);
+        self.hooked("Invoke-Command", &[vm, &path], || unsafe {
+            raw_unescaped::make_dir_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                &path,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+
+    fn remove_file_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_file_in_guest");
+        let vm = self.retrieve_vm()?;
+        let path = escape_pwsh(guest_path);
+        self.hooked("Invoke-Command", &[vm, &path], || unsafe {
+            raw_unescaped::remove_file_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                &path,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+
+    fn stat_in_guest(
+        &self,
+        guest_path: &str,
+    ) -> VmResult<Option<GuestFileInfo>> {
+        let _op = self.hooks.operation("stat_in_guest");
+        let vm = self.retrieve_vm()?;
+        let path = escape_pwsh(guest_path);
+        self.hooked("Invoke-Command", &[vm, &path], || unsafe {
+            raw_unescaped::get_item_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                &path,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+
+    fn move_in_guest(
+        &self,
+        from_guest_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("move_in_guest");
+        let vm = self.retrieve_vm()?;
+        let from_path = escape_pwsh(from_guest_path);
+        let to_path = escape_pwsh(to_guest_path);
+        self.hooked("Invoke-Command", &[vm, &from_path, &to_path], || unsafe {
+            raw_unescaped::move_item_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                &from_path,
+                &to_path,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+
+    fn list_dir_in_guest(&self, guest_path: &str) -> VmResult<Vec<String>> {
+        let _op = self.hooks.operation("list_dir_in_guest");
+        let vm = self.retrieve_vm()?;
+        let path = escape_pwsh(guest_path);
+        self.hooked("Invoke-Command", &[vm, &path], || unsafe {
+            raw_unescaped::get_child_item_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                &path,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+
+    fn make_temp_file_in_guest(&self) -> VmResult<String> {
+        let _op = self.hooks.operation("make_temp_file_in_guest");
+        let vm = self.retrieve_vm()?;
+        self.hooked("Invoke-Command", &[vm], || unsafe {
+            raw_unescaped::make_temp_file_in_guest_unescaped(
+                &self.executable_path,
+                vm,
+                self.retrieve_username()?,
+                self.retrieve_password()?,
+            )
+        })
+    }
+}
+
 #[repr(u8)]
 /// Represents `[Microsoft.HyperV.Powershell.VMOperationalStatus]`.
 pub enum PowerShellVmState {
@@ -497,9 +1942,146 @@ pub enum PowerShellVmState {
     FastSavingCritical,
 }
 
+#[repr(u8)]
+/// Represents `[Microsoft.HyperV.PowerShell.VMHeartbeatStatus]`.
+pub enum PowerShellHeartbeatStatus {
+    Unknown = 0,
+    Disabled,
+    NoContact,
+    Error,
+    LostCommunication,
+    OkApplicationsUnknown,
+    OkApplicationsHealthy,
+    OkApplicationsCritical,
+    Paused,
+}
+
+/// Represents the status of the Shutdown integration service of a VM.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ShutdownServiceStatus {
+    /// `true` if the Shutdown integration service is enabled.
+    pub enabled: bool,
+    /// `true` if the guest responds to the heartbeat.
+    pub has_heartbeat: bool,
+}
+
+/// Represents a user allowed to connect to the console of a VM, listed by `Get-VMConnectAccess`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct VmConnectAccess {
+    /// The user name (e.g., `DOMAIN\user`).
+    pub user_name: String,
+    /// The SID of the user.
+    pub user_id: Option<String>,
+}
+
+/// Represents the directories where Hyper-V stores the checkpoint files and the smart paging file of a VM.
+///
+/// `None` fields are left unchanged by [`HyperVCmd::set_file_locations`].
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize,
+)]
+pub struct VmFileLocations {
+    /// The directory of the checkpoint files, which is `-CheckpointFileLocation` (alias `-SnapshotFileLocation`) of `Set-VM`.
+    pub checkpoint_file_location: Option<String>,
+    /// The directory of the smart paging file used while the VM boots with less memory than its startup memory.
+    pub smart_paging_file_path: Option<String>,
+}
+
+/// Represents a template to create a VM with [`HyperVCmd::create_from_template`].
+///
+/// `None` fields are left to the defaults of `New-VM`.
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize,
+)]
+#[serde(default)]
+pub struct VmTemplate {
+    /// The path to the VHDX image copied for each new VM.
+    #[serde(alias = "vhdPath")]
+    pub vhd_path: PathBuf,
+    /// The generation of the VM. 2 if `None`.
+    pub generation: Option<u8>,
+    #[serde(alias = "memoryStartupBytes")]
+    pub memory_startup_bytes: Option<u64>,
+    #[serde(alias = "processorCount")]
+    pub processor_count: Option<u32>,
+    /// The name of the virtual switch the network adapter connects to.
+    #[serde(alias = "switchName")]
+    pub switch_name: Option<String>,
+    #[serde(alias = "secureBoot")]
+    pub secure_boot: Option<bool>,
+    /// The secure boot template (e.g., `MicrosoftUEFICertificateAuthority` for Linux guests).
+    #[serde(alias = "secureBootTemplate")]
+    pub secure_boot_template: Option<String>,
+}
+
+impl VmTemplate {
+    /// The name of the definition file looked up in a template directory.
+    pub const DEFINITION_FILE_NAME: &'static str = "template.json";
+
+    /// Creates a template which only copies the VHDX image at `vhd_path`.
+    pub fn new<P: Into<PathBuf>>(vhd_path: P) -> Self {
+        Self {
+            vhd_path: vhd_path.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Loads a template from a definition file or a template directory.
+    ///
+    /// A template directory contains a VHDX image and optionally [`Self::DEFINITION_FILE_NAME`].
+    /// A relative `vhd_path` in a definition is resolved against the directory of the definition.
+    pub fn load<P: AsRef<Path>>(path: P) -> VmResult<Self> {
+        let path = path.as_ref();
+        if !path.is_dir() {
+            let s = std::fs::read_to_string(path)?;
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            return Self::from_definition(&s, dir);
+        }
+        let def = path.join(Self::DEFINITION_FILE_NAME);
+        let mut ret = if def.is_file() {
+            Self::from_definition(&std::fs::read_to_string(def)?, path)?
+        } else {
+            Self::default()
+        };
+        if ret.vhd_path.as_os_str().is_empty() {
+            let mut vhds = std::fs::read_dir(path)?
+                .filter_map(|x| x.ok().map(|x| x.path()))
+                .filter(|x| {
+                    x.extension()
+                        .map_or(false, |x| x.eq_ignore_ascii_case("vhdx"))
+                });
+            ret.vhd_path = match (vhds.next(), vhds.next()) {
+                (Some(x), None) => x,
+                _ => {
+                    return vmerr!(ErrorKind::InvalidParameter(format!(
+                        "{} must contain exactly one VHDX image",
+                        path.display()
+                    )))
+                }
+            };
+        }
+        Ok(ret)
+    }
+
+    /// Parses a JSON template definition, resolving a relative `vhd_path` against `dir`.
+    pub fn from_definition<P: AsRef<Path>>(s: &str, dir: P) -> VmResult<Self> {
+        let mut ret: Self = deserialize(s)?;
+        if !ret.vhd_path.as_os_str().is_empty() && ret.vhd_path.is_relative() {
+            ret.vhd_path = dir.as_ref().join(&ret.vhd_path);
+        }
+        Ok(ret)
+    }
+}
+
 pub mod raw {
     use crate::{
-        hyperv::{escape_pwsh, hypervcmd::PsCommand, raw_unescaped, HyperVCmd},
+        hostnet::HostNetwork,
+        hyperv::{
+            escape_pwsh,
+            hypervcmd::{to_generation, to_host_network, PsCommand},
+            raw_unescaped, HyperVCmd, PsWarning, ShutdownServiceStatus,
+            VmConnectAccess, VmFileLocations, VmSwitchType,
+        },
         types::*,
         VmResult,
     };
@@ -545,10 +2127,21 @@ pub mod raw {
         }
     }
 
+    /// Gets the detailed information of a VM.
+    ///
+    /// For more information, See [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    pub fn get_vm_info(pwsh_path: &str, vm: &str) -> VmResult<VmInfo> {
+        unsafe {
+            raw_unescaped::get_vm_info_unescaped(pwsh_path, &escape_pwsh(vm))
+        }
+    }
+
     /// Starts VMs.
     ///
+    /// Returns the warnings written by Start-VM (e.g., some VMs are already running).
+    ///
     /// For more information, See [Start-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/start-vm).
-    pub fn start_vm(pwsh_path: &str, vms: &[&str]) -> VmResult<()> {
+    pub fn start_vm(pwsh_path: &str, vms: &[&str]) -> VmResult<Vec<PsWarning>> {
         unsafe {
             raw_unescaped::start_vm_unescaped(
                 pwsh_path,
@@ -633,337 +2226,2605 @@ pub mod raw {
         }
     }
 
-    /// Gets a list of checkpoints of a VM.
+    /// Gets the status of the Shutdown integration service of a VM.
     ///
-    /// For more information, See [Get-VMSnapshot](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmsnapshot).
-    pub fn get_vm_snapshot(
+    /// For more information, See [Get-VMIntegrationService](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmintegrationservice).
+    pub fn get_shutdown_service_status(
         pwsh_path: &str,
         vm: &str,
-    ) -> VmResult<Vec<Snapshot>> {
+    ) -> VmResult<ShutdownServiceStatus> {
         unsafe {
-            raw_unescaped::get_vm_snapshot_unescaped(
+            raw_unescaped::get_shutdown_service_status_unescaped(
                 pwsh_path,
                 &escape_pwsh(vm),
             )
         }
     }
 
-    /// Creates a checkpoint named `name` of VMs.
+    /// Returns true if the differencing disks of a VM are being merged.
+    pub fn is_merging_disks(pwsh_path: &str, vm: &str) -> VmResult<bool> {
+        unsafe {
+            raw_unescaped::is_merging_disks_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Sets the ISO image at `path` to the first DVD drive of a VM, or ejects the medium if `path` is `None`.
     ///
-    /// For more information, See [Checkpoint-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/checkpoint-vm).
-    pub fn checkpoint_vm<I>(pwsh_path: &str, vms: I, name: &str) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
+    /// For more information, See [Set-VMDvdDrive](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmdvddrive).
+    pub fn set_vm_dvd_drive(
+        pwsh_path: &str,
+        vm: &str,
+        path: Option<&str>,
+    ) -> VmResult<()> {
         unsafe {
-            raw_unescaped::checkpoint_vm_unescaped(
+            raw_unescaped::set_vm_dvd_drive_unescaped(
                 pwsh_path,
-                vms.into_iter().map(escape_pwsh),
-                &escape_pwsh(name),
+                &escape_pwsh(vm),
+                path.map(escape_pwsh).as_deref(),
             )
         }
     }
 
-    /// Restores a VM checkpoint named `name`.
+    /// Gets the default directory for virtual hard disks of the host.
+    pub fn get_vm_host_vhd_path(pwsh_path: &str) -> VmResult<String> {
+        unsafe { raw_unescaped::get_vm_host_vhd_path_unescaped(pwsh_path) }
+    }
+
+    /// Creates a VM which boots from the existing virtual hard disk at `vhd_path`.
     ///
-    /// For more information, See [Restore-VMSnapshot](https://docs.microsoft.com/ja-jp/powershell/module/hyper-v/restore-vmsnapshot).
-    pub fn restore_vm_snapshot(
+    /// For more information, See [New-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/new-vm).
+    pub fn new_vm(
+        pwsh_path: &str,
+        vm: &str,
+        generation: u8,
+        memory_startup_bytes: Option<u64>,
+        vhd_path: &str,
+        switch_name: Option<&str>,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::new_vm_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                generation,
+                memory_startup_bytes,
+                &escape_pwsh(vhd_path),
+                switch_name.map(escape_pwsh).as_deref(),
+            )
+        }
+    }
+
+    /// Configures the secure boot of a generation 2 VM.
+    ///
+    /// For more information, See [Set-VMFirmware](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmfirmware).
+    pub fn set_vm_firmware(
+        pwsh_path: &str,
+        vm: &str,
+        secure_boot: Option<bool>,
+        secure_boot_template: Option<&str>,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::set_vm_firmware_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                secure_boot,
+                secure_boot_template.map(escape_pwsh).as_deref(),
+            )
+        }
+    }
+
+    /// Gets the CPU usage and the assigned memory of a VM, and its disk and network throughput from the performance counters.
+    ///
+    /// For more information, See [Get-Counter](https://docs.microsoft.com/en-us/powershell/module/microsoft.powershell.diagnostics/get-counter).
+    pub fn get_vm_metrics(pwsh_path: &str, vm: &str) -> VmResult<VmMetrics> {
+        unsafe {
+            raw_unescaped::get_vm_metrics_unescaped(pwsh_path, &escape_pwsh(vm))
+        }
+    }
+
+    /// Gets the IP addresses of the network adapters of a VM.
+    ///
+    /// For more information, See [Get-VMNetworkAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmnetworkadapter).
+    pub fn get_vm_network_adapter_ip_addresses(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<GuestIpAddress>> {
+        unsafe {
+            raw_unescaped::get_vm_network_adapter_ip_addresses_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Gets the virtual switches of the host with the NetNats and the host addresses of the internal switches.
+    ///
+    /// For more information, See [Get-VMSwitch](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmswitch) and [Get-NetNat](https://docs.microsoft.com/en-us/powershell/module/netnat/get-netnat).
+    pub fn get_vm_switches(pwsh_path: &str) -> VmResult<Vec<HostNetwork>> {
+        let s = PsCommand::new(pwsh_path, "Get-VMSwitch")
+            .arg(
+                "|ForEach-Object {$nat = Get-NetNat -Name $_.Name \
+                 -ErrorAction SilentlyContinue; $ip = Get-NetIPAddress \
+                 -InterfaceAlias ('vEthernet (' + $_.Name + ')') \
+                 -AddressFamily IPv4 -ErrorAction \
+                 SilentlyContinue|Select-Object -First 1; \
+                 [pscustomobject]@{Name = $_.Name; SwitchType = \
+                 $_.SwitchType.ToString(); NatPrefix = \
+                 $nat.InternalIPInterfaceAddressPrefix; IPAddress = \
+                 $ip.IPAddress; PrefixLength = \
+                 $ip.PrefixLength}}|ConvertTo-Json",
+            )
+            .exec()?;
+        if s.is_empty() {
+            return Ok(vec![]);
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Name")]
+            name: String,
+            #[serde(alias = "SwitchType")]
+            switch_type: String,
+            #[serde(alias = "NatPrefix")]
+            nat_prefix: Option<String>,
+            #[serde(alias = "IPAddress")]
+            ip_address: Option<String>,
+            #[serde(alias = "PrefixLength")]
+            prefix_length: Option<u8>,
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        Ok(resp
+            .iter()
+            .filter_map(|x| {
+                to_host_network(
+                    &x.name,
+                    &x.switch_type,
+                    x.nat_prefix.as_deref(),
+                    x.ip_address.as_deref(),
+                    x.prefix_length,
+                )
+            })
+            .collect())
+    }
+
+    /// Creates a virtual switch.
+    ///
+    /// For more information, See [New-VMSwitch](https://docs.microsoft.com/en-us/powershell/module/hyper-v/new-vmswitch).
+    pub fn new_vm_switch(
         pwsh_path: &str,
-        vm_name: &str,
         name: &str,
+        ty: VmSwitchType,
     ) -> VmResult<()> {
         unsafe {
-            raw_unescaped::restore_vm_snapshot_unescaped(
+            raw_unescaped::new_vm_switch_unescaped(
                 pwsh_path,
-                &escape_pwsh(vm_name),
                 &escape_pwsh(name),
+                ty,
             )
         }
     }
 
-    /// Removes a VM checkpoint named `name` from VMs.
+    /// Removes a virtual switch.
     ///
-    /// For more information, See [Remove-VMSnapshot](https://docs.microsoft.com/ja-jp/powershell/module/hyper-v/remove-vmsnapshot).
-    pub fn remove_vm_snapshot<I>(
+    /// For more information, See [Remove-VMSwitch](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vmswitch).
+    pub fn remove_vm_switch(pwsh_path: &str, name: &str) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::remove_vm_switch_unescaped(
+                pwsh_path,
+                &escape_pwsh(name),
+            )
+        }
+    }
+
+    /// Assigns `ip` with `prefix_length` to the host interface of the internal switch `switch`.
+    ///
+    /// For more information, See [New-NetIPAddress](https://docs.microsoft.com/en-us/powershell/module/nettcpip/new-netipaddress).
+    pub fn new_net_ip_address(
+        pwsh_path: &str,
+        switch: &str,
+        ip: &str,
+        prefix_length: u8,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::new_net_ip_address_unescaped(
+                pwsh_path,
+                &escape_pwsh(switch),
+                &escape_pwsh(ip),
+                prefix_length,
+            )
+        }
+    }
+
+    /// Creates a NetNat which translates the addresses of `prefix` in CIDR notation.
+    ///
+    /// For more information, See [New-NetNat](https://docs.microsoft.com/en-us/powershell/module/netnat/new-netnat).
+    pub fn new_net_nat(
         pwsh_path: &str,
-        vms: I,
         name: &str,
-    ) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
+        prefix: &str,
+    ) -> VmResult<()> {
         unsafe {
-            raw_unescaped::remove_vm_snapshot_unescaped(
+            raw_unescaped::new_net_nat_unescaped(
                 pwsh_path,
-                vms.into_iter().map(escape_pwsh),
                 &escape_pwsh(name),
+                &escape_pwsh(prefix),
             )
         }
     }
-}
 
-pub mod raw_unescaped {
-    use crate::{
-        deserialize,
-        hyperv::{hypervcmd::PsCommand, *},
-        types::*,
-        VmResult,
-    };
-    use serde::Deserialize;
-    use std::ffi::OsStr;
+    /// Removes a NetNat.
+    ///
+    /// For more information, See [Remove-NetNat](https://docs.microsoft.com/en-us/powershell/module/netnat/remove-netnat).
+    pub fn remove_net_nat(pwsh_path: &str, name: &str) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::remove_net_nat_unescaped(
+                pwsh_path,
+                &escape_pwsh(name),
+            )
+        }
+    }
 
-    /// Gets the power state of a VM.
+    /// Gets the COM ports of a VM.
     ///
-    /// # Safety
+    /// For more information, See [Get-VMComPort](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmcomport).
+    pub fn get_vm_com_ports(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<SerialPort>> {
+        unsafe {
+            raw_unescaped::get_vm_com_ports_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Connects the COM port `number` of a VM to the named pipe at `path`, or disconnects it if `path` is `None`.
     ///
-    /// This function doesn't escape `vm`, which can lead to command injection.
+    /// For more information, See [Set-VMComPort](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmcomport).
+    pub fn set_vm_com_port(
+        pwsh_path: &str,
+        vm: &str,
+        number: usize,
+        path: Option<&str>,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::set_vm_com_port_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                number,
+                path.map(escape_pwsh).as_deref(),
+            )
+        }
+    }
+
+    /// Gets the directories of the checkpoint files and the smart paging file of a VM.
     ///
-    /// Please be sure to escape `vm` before calling this function.
-    pub unsafe fn get_power_state_unescaped(
+    /// For more information, See [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    pub fn get_vm_file_locations(
         pwsh_path: &str,
         vm: &str,
-    ) -> VmResult<VmPowerState> {
-        let s = PsCommand::new(pwsh_path, "Get-VM")
-            .args(&[vm, "|select State|ConvertTo-Json"])
-            .exec()?;
-        #[derive(Deserialize)]
-        struct Response {
-            #[serde(alias = "State")]
-            state: u8,
+    ) -> VmResult<VmFileLocations> {
+        unsafe {
+            raw_unescaped::get_vm_file_locations_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
         }
-        let state = deserialize::<Response>(&s)?.state;
-        macro_rules! m {
-            ($x:ident) => {
-                state == PowerShellVmState::$x as u8
-            };
+    }
+
+    /// Sets the directories of the checkpoint files and the smart paging file of a VM.
+    ///
+    /// For more information, See [Set-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vm).
+    pub fn set_vm_file_locations(
+        pwsh_path: &str,
+        vm: &str,
+        checkpoint_file_location: Option<&str>,
+        smart_paging_file_path: Option<&str>,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::set_vm_file_locations_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                checkpoint_file_location.map(escape_pwsh).as_deref(),
+                smart_paging_file_path.map(escape_pwsh).as_deref(),
+            )
         }
-        Ok(if m!(Running) || m!(RunningCritical) {
-            VmPowerState::Running
-        } else if m!(Off) || m!(OffCritical) {
-            VmPowerState::Stopped
-        } else if m!(Saved) || m!(SavedCritical) || m!(FastSaved) {
-            VmPowerState::Suspended
-        } else if m!(Paused) || m!(PausedCritical) {
-            VmPowerState::Paused
-        } else {
-            VmPowerState::Unknown
-        })
     }
 
-    /// Starts VMs.
+    /// Creates a full clone named `name` of a VM or its snapshot with [Export-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/export-vm) and [Import-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/import-vm).
     ///
-    /// For more information, See [Start-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/start-vm).
+    /// The new VM is stored in `<path>/<name>`, where `path` defaults to the default directory for VMs of the host.
+    pub fn clone_vm(
+        pwsh_path: &str,
+        vm: &str,
+        name: &str,
+        snapshot: Option<&str>,
+        path: Option<&str>,
+    ) -> VmResult<Vm> {
+        unsafe {
+            raw_unescaped::clone_vm_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                &escape_pwsh(name),
+                snapshot.map(escape_pwsh).as_deref(),
+                path.map(escape_pwsh).as_deref(),
+            )
+        }
+    }
+
+    /// Creates a VM named `name` whose disks are differencing disks of the disks of a snapshot of a VM.
     ///
-    /// # Safety
+    /// The new VM is stored in `<path>/<name>`, where `path` defaults to the default directory for VMs of the host.
+    pub fn new_linked_clone_vm(
+        pwsh_path: &str,
+        vm: &str,
+        name: &str,
+        snapshot: &str,
+        path: Option<&str>,
+    ) -> VmResult<Vm> {
+        unsafe {
+            raw_unescaped::new_linked_clone_vm_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                &escape_pwsh(name),
+                &escape_pwsh(snapshot),
+                path.map(escape_pwsh).as_deref(),
+            )
+        }
+    }
+
+    /// Exports a VM to the directory `path`.
     ///
-    /// This function doesn't escape `vms`, which can lead to command injection.
+    /// For more information, See [Export-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/export-vm).
+    pub fn export_vm(pwsh_path: &str, vm: &str, path: &str) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::export_vm_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                &escape_pwsh(path),
+            )
+        }
+    }
+
+    /// Imports a copy of the first VM found in the directory `path` with a new ID.
     ///
-    /// Please be sure to escape `vms` before calling this function.
-    pub unsafe fn start_vm_unescaped<I>(pwsh_path: &str, vms: I) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
-        let res = PsCommand::new(pwsh_path, "Start-VM")
-            .arg_array_unescaped(vms)
-            .exec()?;
-        if res.starts_with(
-            "WARNING: The virtual machine is already in the specified state.",
-        ) {
-            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
+    /// For more information, See [Import-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/import-vm).
+    pub fn import_vm(pwsh_path: &str, path: &str) -> VmResult<Vm> {
+        unsafe {
+            raw_unescaped::import_vm_unescaped(pwsh_path, &escape_pwsh(path))
         }
-        Ok(())
     }
 
-    /// Stops VMs.
+    /// Creates a VM without disks.
     ///
-    /// For more information, See [Stop-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/stop-vm).
+    /// [`Firmware::Efi`] creates a generation 2 VM and `spec.guest_os` is ignored.
+    ///
+    /// For more information, See [New-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/new-vm).
+    pub fn create_vm(pwsh_path: &str, spec: &VmSpec) -> VmResult<Vm> {
+        unsafe {
+            raw_unescaped::create_vm_unescaped(
+                pwsh_path,
+                &escape_pwsh(&spec.name),
+                spec.path.as_deref().map(escape_pwsh).as_deref(),
+                spec.firmware.map(to_generation),
+                spec.memory_mb.map(|x| x * 1024 * 1024),
+                spec.cpu_count,
+            )
+        }
+    }
+
+    /// Registers the VM whose configuration file is at `path` in place.
+    ///
+    /// For more information, See [Import-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/import-vm).
+    pub fn register_vm(pwsh_path: &str, path: &str) -> VmResult<Vm> {
+        unsafe {
+            raw_unescaped::register_vm_unescaped(pwsh_path, &escape_pwsh(path))
+        }
+    }
+
+    /// Removes a VM and deletes its virtual hard disks.
+    ///
+    /// For more information, See [Remove-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vm).
+    pub fn delete_vm(pwsh_path: &str, vm: &str) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::delete_vm_unescaped(pwsh_path, &escape_pwsh(vm))
+        }
+    }
+
+    /// Gets the processor configuration of a VM.
+    ///
+    /// For more information, See [Get-VMProcessor](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmprocessor).
+    pub fn get_vm_processor(pwsh_path: &str, vm: &str) -> VmResult<CpuTuning> {
+        unsafe {
+            raw_unescaped::get_vm_processor_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Configures the processors of VMs.
+    ///
+    /// For more information, See [Set-VMProcessor](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmprocessor).
+    pub fn set_vm_processor(
+        pwsh_path: &str,
+        vms: &[&str],
+        tuning: &CpuTuning,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::set_vm_processor_unescaped(
+                pwsh_path,
+                vms.iter().map(escape_pwsh),
+                tuning,
+            )
+        }
+    }
+
+    /// Gets the startup memory of a VM in bytes.
+    ///
+    /// For more information, See [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    pub fn get_vm_memory_startup(pwsh_path: &str, vm: &str) -> VmResult<u64> {
+        unsafe {
+            raw_unescaped::get_vm_memory_startup_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Sets the startup memory of a VM in bytes.
+    ///
+    /// For more information, See [Set-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vm).
+    pub fn set_vm_memory_startup(
+        pwsh_path: &str,
+        vm: &str,
+        bytes: u64,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::set_vm_memory_startup_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                bytes,
+            )
+        }
+    }
+
+    /// Gets GPU partition adapters of a VM.
+    ///
+    /// For more information, See [Get-VMGpuPartitionAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmgpupartitionadapter).
+    pub fn get_vm_gpu_partition_adapter(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<PassthroughDevice>> {
+        unsafe {
+            raw_unescaped::get_vm_gpu_partition_adapter_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Adds a GPU partition adapter to a VM.
+    ///
+    /// If `instance_path` is `None`, Hyper-V chooses a partitionable GPU.
+    ///
+    /// For more information, See [Add-VMGpuPartitionAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/add-vmgpupartitionadapter).
+    pub fn add_vm_gpu_partition_adapter(
+        pwsh_path: &str,
+        vm: &str,
+        instance_path: Option<&str>,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::add_vm_gpu_partition_adapter_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                instance_path.map(escape_pwsh).as_deref(),
+            )
+        }
+    }
+
+    /// Removes a GPU partition adapter from a VM.
+    ///
+    /// If `adapter_id` is `None`, removes all GPU partition adapters.
+    ///
+    /// For more information, See [Remove-VMGpuPartitionAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vmgpupartitionadapter).
+    pub fn remove_vm_gpu_partition_adapter(
+        pwsh_path: &str,
+        vm: &str,
+        adapter_id: Option<&str>,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::remove_vm_gpu_partition_adapter_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                adapter_id.map(escape_pwsh).as_deref(),
+            )
+        }
+    }
+
+    /// Gets devices assigned to a VM with Discrete Device Assignment.
+    ///
+    /// For more information, See [Get-VMAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmassignabledevice).
+    pub fn get_vm_assignable_device(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<PassthroughDevice>> {
+        unsafe {
+            raw_unescaped::get_vm_assignable_device_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Assigns a host device at `location_path` to a VM.
+    ///
+    /// For more information, See [Add-VMAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/add-vmassignabledevice).
+    pub fn add_vm_assignable_device(
+        pwsh_path: &str,
+        vm: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::add_vm_assignable_device_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                &escape_pwsh(location_path),
+            )
+        }
+    }
+
+    /// Removes a device at `location_path` from a VM.
+    ///
+    /// For more information, See [Remove-VMAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vmassignabledevice).
+    pub fn remove_vm_assignable_device(
+        pwsh_path: &str,
+        vm: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::remove_vm_assignable_device_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                &escape_pwsh(location_path),
+            )
+        }
+    }
+
+    /// Dismounts a device at `location_path` from the host so that it can be assigned to a VM.
+    ///
+    /// For more information, See [Dismount-VMHostAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/dismount-vmhostassignabledevice).
+    pub fn dismount_vm_host_assignable_device(
+        pwsh_path: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::dismount_vm_host_assignable_device_unescaped(
+                pwsh_path,
+                &escape_pwsh(location_path),
+            )
+        }
+    }
+
+    /// Mounts a device at `location_path` to the host again.
+    ///
+    /// For more information, See [Mount-VMHostAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/mount-vmhostassignabledevice).
+    pub fn mount_vm_host_assignable_device(
+        pwsh_path: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::mount_vm_host_assignable_device_unescaped(
+                pwsh_path,
+                &escape_pwsh(location_path),
+            )
+        }
+    }
+
+    /// Gets users allowed to connect to the console of a VM.
+    ///
+    /// For more information, See [Get-VMConnectAccess](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmconnectaccess).
+    pub fn get_vm_connect_access(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<VmConnectAccess>> {
+        unsafe {
+            raw_unescaped::get_vm_connect_access_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Allows `user_name` to connect to the console of a VM.
+    ///
+    /// For more information, See [Grant-VMConnectAccess](https://docs.microsoft.com/en-us/powershell/module/hyper-v/grant-vmconnectaccess).
+    pub fn grant_vm_connect_access(
+        pwsh_path: &str,
+        vm: &str,
+        user_name: &str,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::grant_vm_connect_access_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                &escape_pwsh(user_name),
+            )
+        }
+    }
+
+    /// Disallows `user_name` to connect to the console of a VM.
+    ///
+    /// For more information, See [Revoke-VMConnectAccess](https://docs.microsoft.com/en-us/powershell/module/hyper-v/revoke-vmconnectaccess).
+    pub fn revoke_vm_connect_access(
+        pwsh_path: &str,
+        vm: &str,
+        user_name: &str,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::revoke_vm_connect_access_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+                &escape_pwsh(user_name),
+            )
+        }
+    }
+
+    /// Gets a list of checkpoints of a VM.
+    ///
+    /// For more information, See [Get-VMSnapshot](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmsnapshot).
+    pub fn get_vm_snapshot(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<Snapshot>> {
+        unsafe {
+            raw_unescaped::get_vm_snapshot_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Gets the ID of the checkpoint the current state of a VM is based on.
+    ///
+    /// For more information, See [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    pub fn get_vm_current_snapshot_id(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Option<String>> {
+        unsafe {
+            raw_unescaped::get_vm_current_snapshot_id_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Gets the disk space used by each checkpoint of a VM.
+    ///
+    /// The sizes are `FileSize` of the AVHDX files reported by [Get-VHD](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vhd).
+    pub fn get_vm_snapshot_disk_usage(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<SnapshotDiskUsage>> {
+        unsafe {
+            raw_unescaped::get_vm_snapshot_disk_usage_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm),
+            )
+        }
+    }
+
+    /// Creates a checkpoint named `name` of VMs.
+    ///
+    /// For more information, See [Checkpoint-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/checkpoint-vm).
+    pub fn checkpoint_vm<I>(pwsh_path: &str, vms: I, name: &str) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        unsafe {
+            raw_unescaped::checkpoint_vm_unescaped(
+                pwsh_path,
+                vms.into_iter().map(escape_pwsh),
+                &escape_pwsh(name),
+            )
+        }
+    }
+
+    /// Restores a VM checkpoint named `name`.
+    ///
+    /// For more information, See [Restore-VMSnapshot](https://docs.microsoft.com/ja-jp/powershell/module/hyper-v/restore-vmsnapshot).
+    pub fn restore_vm_snapshot(
+        pwsh_path: &str,
+        vm_name: &str,
+        name: &str,
+    ) -> VmResult<()> {
+        unsafe {
+            raw_unescaped::restore_vm_snapshot_unescaped(
+                pwsh_path,
+                &escape_pwsh(vm_name),
+                &escape_pwsh(name),
+            )
+        }
+    }
+
+    /// Removes a VM checkpoint named `name` from VMs.
+    ///
+    /// For more information, See [Remove-VMSnapshot](https://docs.microsoft.com/ja-jp/powershell/module/hyper-v/remove-vmsnapshot).
+    pub fn remove_vm_snapshot<I>(
+        pwsh_path: &str,
+        vms: I,
+        name: &str,
+    ) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        unsafe {
+            raw_unescaped::remove_vm_snapshot_unescaped(
+                pwsh_path,
+                vms.into_iter().map(escape_pwsh),
+                &escape_pwsh(name),
+            )
+        }
+    }
+}
+
+pub mod raw_unescaped {
+    use crate::{
+        deserialize,
+        hyperv::{
+            hypervcmd::{
+                is_merging_status, parse_cloned_vm, parse_vm_info,
+                to_power_state, to_serial_port, PsCommand,
+            },
+            *,
+        },
+        parse_timestamp,
+        types::*,
+        VmResult,
+    };
+    use serde::Deserialize;
+    use std::{ffi::OsStr, time::Duration};
+
+    /// Gets the power state of a VM.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_power_state_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<VmPowerState> {
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([vm, "|select State|ConvertTo-Json"])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "State")]
+            state: u8,
+        }
+        Ok(to_power_state(deserialize::<Response>(&s)?.state))
+    }
+
+    /// Gets the detailed information of a VM.
+    ///
+    /// `guest_os` is not reported by [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_vm_info_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<VmInfo> {
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([
+                vm,
+                "|select Name, Generation, MemoryStartup, ProcessorCount, \
+                 State, ConfigurationLocation, \
+                 @{n='IntegrationServicesVersion';e={\"$($_.\
+                 IntegrationServicesVersion)\"}}|ConvertTo-Json",
+            ])
+            .exec()?;
+        parse_vm_info(&s)
+    }
+
+    /// Starts VMs.
+    ///
+    /// Returns the warnings written by Start-VM (e.g., some VMs are already running).
+    ///
+    /// For more information, See [Start-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/start-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vms` before calling this function.
+    pub unsafe fn start_vm_unescaped<I>(
+        pwsh_path: &str,
+        vms: I,
+    ) -> VmResult<Vec<PsWarning>>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        let res = PsCommand::new(pwsh_path, "Start-VM")
+            .arg_array_unescaped(vms)
+            .exec()?;
+        Ok(parse_warnings(&res))
+    }
+
+    /// Stops VMs.
+    ///
+    /// For more information, See [Stop-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/stop-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vms` before calling this function.
+    pub unsafe fn stop_vm_unescaped<I>(
+        pwsh_path: &str,
+        vms: I,
+        turn_off: bool,
+        use_save: bool,
+    ) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        let mut cmd = PsCommand::new(pwsh_path, "Stop-VM");
+        cmd.arg("-Force");
+        cmd.arg_array_unescaped(vms);
+        if turn_off {
+            cmd.arg("-TurnOff");
+        }
+        if use_save {
+            cmd.arg("-Save");
+        }
+        let s = cmd.exec()?;
+        if s.starts_with(
+            "WARNING: The virtual machine is already in the specified state.",
+        ) {
+            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Stopped));
+        }
+        Ok(())
+    }
+
+    /// Suspends VMs.
+    ///
+    /// For more information, See [Suspend-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/suspend-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vms` before calling this function.
+    pub unsafe fn suspend_vm_unescaped<I>(
+        pwsh_path: &str,
+        vms: I,
+    ) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        let res = PsCommand::new(pwsh_path, "Suspend-VM")
+            .arg_array_unescaped(vms)
+            .exec()?;
+        if res.starts_with(
+            "WARNING: The virtual machine is already in the specified state.",
+        ) {
+            return vmerr!(ErrorKind::InvalidPowerState(
+                VmPowerState::Suspended
+            ));
+        }
+        Ok(())
+    }
+
+    /// Resumes VMs.
+    ///
+    /// For more information, See [Resume-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/resume-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vms` before calling this function.
+    pub unsafe fn resume_vm_unescaped<I>(
+        pwsh_path: &str,
+        vms: I,
+    ) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        let s = PsCommand::new(pwsh_path, "Resume-VM")
+            .arg_array_unescaped(vms)
+            .exec()?;
+        if s.starts_with(
+            "WARNING: The virtual machine is already in the specified state.",
+        ) {
+            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
+        }
+        Ok(())
+    }
+
+    /// Restarts VMs.
+    ///
+    /// For more information, See [Restart-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/restart-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vms` before calling this function.
+    pub unsafe fn restart_vm_unchecked<I>(
+        pwsh_path: &str,
+        vms: I,
+    ) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        PsCommand::new(pwsh_path, "Restart-VM")
+            .arg("-Confirm:$false")
+            .arg_array_unescaped(vms)
+            .exec()?;
+        Ok(())
+    }
+
+    /// Copies a file between from the host to guests.
+    ///
+    /// For more information, See [Copy-VMFile](https://docs.microsoft.com/en-us/powershell/module/hyper-v/copy-vmfile).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, `src_path` and `dst_path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn copy_vm_file_unescaped<I>(
+        pwsh_path: &str,
+        vms: I,
+        src_path: &str,
+        dst_path: &str,
+        create_full_path: bool,
+    ) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        let mut cmd = PsCommand::new(pwsh_path, "Copy-VMFile");
+        cmd.arg_array_unescaped(vms);
+        cmd.args([
+            "-Force",
+            "-SourcePath",
+            src_path,
+            "-DestinationPath",
+            dst_path,
+            "-FileSource Host",
+        ]);
+        if create_full_path {
+            cmd.arg("-CreateFullPath");
+        }
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Copies a file between from a guest to the host with PSSession.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, `src_path`, `dst_path`, `username` and `password`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn copy_vm_file_from_guest_to_host_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        src_path: &str,
+        dst_path: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Copy-Item",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-FromSession $sess -Path",
+            src_path,
+            "-Destination",
+            dst_path,
+            "; Remove-PSSession $sess;",
+        ]);
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Returns true if the differencing disks of a VM are being merged.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn is_merging_disks_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<bool> {
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([vm, "|select Status|ConvertTo-Json"])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Status")]
+            status: Option<String>,
+        }
+        Ok(is_merging_status(
+            deserialize::<Response>(&s)?.status.as_deref().unwrap_or(""),
+        ))
+    }
+
+    /// Gets the status of the Shutdown integration service of a VM.
+    ///
+    /// For more information, See [Get-VMIntegrationService](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmintegrationservice).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_shutdown_service_status_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<ShutdownServiceStatus> {
+        // The ID of the Shutdown integration service doesn't depend on the display language.
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([
+                vm,
+                "|select @{n='Enabled';e={($_|Get-VMIntegrationService|? \
+                 {$_.Id -like \
+                 '*\\9F8233AC-BE49-4C79-8EE3-E7E1985B2077'}).Enabled}}, \
+                 Heartbeat|ConvertTo-Json",
+            ])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Enabled")]
+            enabled: Option<bool>,
+            #[serde(alias = "Heartbeat")]
+            heartbeat: Option<u8>,
+        }
+        let resp = deserialize::<Response>(&s)?;
+        Ok(ShutdownServiceStatus {
+            enabled: resp.enabled.unwrap_or(false),
+            has_heartbeat: matches!(
+                resp.heartbeat,
+                Some(x) if x == PowerShellHeartbeatStatus::OkApplicationsUnknown as u8
+                    || x == PowerShellHeartbeatStatus::OkApplicationsHealthy as u8
+                    || x == PowerShellHeartbeatStatus::OkApplicationsCritical as u8
+            ),
+        })
+    }
+
+    /// Sets the ISO image at `path` to the first DVD drive of a VM, or ejects the medium if `path` is `None`.
+    ///
+    /// For more information, See [Set-VMDvdDrive](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmdvddrive).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn set_vm_dvd_drive_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        path: Option<&str>,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Get-VMDvdDrive")
+            .args([
+                "-VMName",
+                vm,
+                "|select -First 1|Set-VMDvdDrive -Path",
+                path.unwrap_or("$null"),
+            ])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Gets the default directory for virtual hard disks of the host.
+    ///
+    /// For more information, See [Get-VMHost](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmhost).
+    ///
+    /// # Safety
+    ///
+    /// This function is marked unsafe for consistency; it takes no parameters to escape.
+    pub unsafe fn get_vm_host_vhd_path_unescaped(
+        pwsh_path: &str,
+    ) -> VmResult<String> {
+        let s = PsCommand::new(pwsh_path, "Get-VMHost")
+            .arg("|select -ExpandProperty VirtualHardDiskPath")
+            .exec()?;
+        Ok(s.trim().to_string())
+    }
+
+    /// Creates a VM which boots from the existing virtual hard disk at `vhd_path`.
+    ///
+    /// For more information, See [New-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/new-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `vhd_path` and `switch_name`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn new_vm_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        generation: u8,
+        memory_startup_bytes: Option<u64>,
+        vhd_path: &str,
+        switch_name: Option<&str>,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new(pwsh_path, "New-VM");
+        cmd.args([
+            "-Name",
+            vm,
+            "-Generation",
+            &generation.to_string(),
+            "-VHDPath",
+            vhd_path,
+        ]);
+        if let Some(x) = memory_startup_bytes {
+            cmd.args(["-MemoryStartupBytes", &x.to_string()]);
+        }
+        if let Some(x) = switch_name {
+            cmd.args(["-SwitchName", x]);
+        }
+        cmd.arg("|Out-Null").exec()?;
+        Ok(())
+    }
+
+    /// Configures the secure boot of a generation 2 VM.
+    ///
+    /// For more information, See [Set-VMFirmware](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmfirmware).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `secure_boot_template`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn set_vm_firmware_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        secure_boot: Option<bool>,
+        secure_boot_template: Option<&str>,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new(pwsh_path, "Set-VMFirmware");
+        cmd.args(["-VMName", vm]);
+        if let Some(x) = secure_boot {
+            cmd.args(["-EnableSecureBoot", if x { "On" } else { "Off" }]);
+        }
+        if let Some(x) = secure_boot_template {
+            cmd.args(["-SecureBootTemplate", x]);
+        }
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Gets the CPU usage and the assigned memory of a VM, and its disk and network throughput from the performance counters.
+    ///
+    /// For more information, See [Get-Counter](https://docs.microsoft.com/en-us/powershell/module/microsoft.powershell.diagnostics/get-counter).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_vm_metrics_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<VmMetrics> {
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([
+                vm,
+                "|ForEach-Object {$vm = $_; $disks = Get-VMHardDiskDrive -VM \
+                 $vm|ForEach-Object {$_.Path -replace '\\\\', '-'}; $samples \
+                 = (Get-Counter -Counter '\\Hyper-V Virtual Storage \
+                 Device(*)\\Read Bytes/sec', '\\Hyper-V Virtual Storage \
+                 Device(*)\\Write Bytes/sec', '\\Hyper-V Virtual Network \
+                 Adapter(*)\\Bytes Received/sec', '\\Hyper-V Virtual Network \
+                 Adapter(*)\\Bytes Sent/sec' -ErrorAction \
+                 SilentlyContinue).CounterSamples|Where-Object {$disks \
+                 -contains $_.InstanceName -or \
+                 $_.InstanceName.StartsWith($vm.Name.ToLower() + '_')}; $sum \
+                 = {param($c) ($samples|Where-Object {$_.Path -like ('*' + \
+                 $c)}|Measure-Object -Property CookedValue -Sum).Sum}; \
+                 [pscustomobject]@{CPUUsage = $vm.CPUUsage; MemoryAssigned = \
+                 $vm.MemoryAssigned; DiskRead = & $sum 'Read Bytes/sec'; \
+                 DiskWrite = & $sum 'Write Bytes/sec'; NetworkRx = & $sum \
+                 'Bytes Received/sec'; NetworkTx = & $sum 'Bytes \
+                 Sent/sec'}}|ConvertTo-Json",
+            ])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "CPUUsage")]
+            cpu_usage: Option<f64>,
+            #[serde(alias = "MemoryAssigned")]
+            memory_assigned: Option<u64>,
+            #[serde(alias = "DiskRead")]
+            disk_read: Option<f64>,
+            #[serde(alias = "DiskWrite")]
+            disk_write: Option<f64>,
+            #[serde(alias = "NetworkRx")]
+            network_rx: Option<f64>,
+            #[serde(alias = "NetworkTx")]
+            network_tx: Option<f64>,
+        }
+        let resp = deserialize::<Response>(&s)?;
+        Ok(VmMetrics {
+            cpu_percent: resp.cpu_usage,
+            memory_used_bytes: resp.memory_assigned,
+            disk_read_bytes_per_sec: resp.disk_read.map(|x| x as u64),
+            disk_write_bytes_per_sec: resp.disk_write.map(|x| x as u64),
+            network_rx_bytes_per_sec: resp.network_rx.map(|x| x as u64),
+            network_tx_bytes_per_sec: resp.network_tx.map(|x| x as u64),
+        })
+    }
+
+    /// Gets the IP addresses of the network adapters of a VM.
+    ///
+    /// For more information, See [Get-VMNetworkAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmnetworkadapter).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_vm_network_adapter_ip_addresses_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<GuestIpAddress>> {
+        let s = PsCommand::new(pwsh_path, "Get-VMNetworkAdapter")
+            .args([
+                "-VMName",
+                vm,
+                "|select MacAddress, IPAddresses|ConvertTo-Json",
+            ])
+            .exec()?;
+        if s.is_empty() {
+            return Ok(vec![]);
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "MacAddress")]
+            mac_address: Option<String>,
+            #[serde(alias = "IPAddresses", default)]
+            ip_addresses: Vec<String>,
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        Ok(resp
+            .iter()
+            .flat_map(|x| {
+                x.ip_addresses.iter().filter_map(move |ip| {
+                    GuestIpAddress::parse(x.mac_address.as_deref(), ip)
+                })
+            })
+            .collect())
+    }
+
+    /// Creates a virtual switch.
+    ///
+    /// For more information, See [New-VMSwitch](https://docs.microsoft.com/en-us/powershell/module/hyper-v/new-vmswitch).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `name`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `name` before calling this function.
+    pub unsafe fn new_vm_switch_unescaped(
+        pwsh_path: &str,
+        name: &str,
+        ty: VmSwitchType,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "New-VMSwitch")
+            .args(["-Name", name, "-SwitchType", ty.as_str()])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Removes a virtual switch.
+    ///
+    /// For more information, See [Remove-VMSwitch](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vmswitch).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `name`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `name` before calling this function.
+    pub unsafe fn remove_vm_switch_unescaped(
+        pwsh_path: &str,
+        name: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Remove-VMSwitch")
+            .args(["-Name", name, "-Force"])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Assigns `ip` with `prefix_length` to the host interface of the internal switch `switch`.
+    ///
+    /// For more information, See [New-NetIPAddress](https://docs.microsoft.com/en-us/powershell/module/nettcpip/new-netipaddress).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `switch` and `ip`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn new_net_ip_address_unescaped(
+        pwsh_path: &str,
+        switch: &str,
+        ip: &str,
+        prefix_length: u8,
+    ) -> VmResult<()> {
+        let alias = format!("('vEthernet (' + {} + ')')", switch);
+        PsCommand::new(pwsh_path, "New-NetIPAddress")
+            .args([
+                "-IPAddress",
+                ip,
+                "-PrefixLength",
+                &prefix_length.to_string(),
+                "-InterfaceAlias",
+                &alias,
+            ])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Creates a NetNat which translates the addresses of `prefix` in CIDR notation.
+    ///
+    /// For more information, See [New-NetNat](https://docs.microsoft.com/en-us/powershell/module/netnat/new-netnat).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `name` and `prefix`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn new_net_nat_unescaped(
+        pwsh_path: &str,
+        name: &str,
+        prefix: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "New-NetNat")
+            .args(["-Name", name, "-InternalIPInterfaceAddressPrefix", prefix])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Removes a NetNat.
+    ///
+    /// For more information, See [Remove-NetNat](https://docs.microsoft.com/en-us/powershell/module/netnat/remove-netnat).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `name`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `name` before calling this function.
+    pub unsafe fn remove_net_nat_unescaped(
+        pwsh_path: &str,
+        name: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Remove-NetNat")
+            .args(["-Name", name, "-Confirm:$false"])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Gets the COM ports of a VM.
+    ///
+    /// For more information, See [Get-VMComPort](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmcomport).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_vm_com_ports_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<SerialPort>> {
+        let s = PsCommand::new(pwsh_path, "Get-VMComPort")
+            .args(["-VMName", vm, "|select Name, Path|ConvertTo-Json"])
+            .exec()?;
+        if s.is_empty() {
+            return Ok(vec![]);
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Name")]
+            name: String,
+            #[serde(alias = "Path")]
+            path: Option<String>,
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        Ok(resp
+            .iter()
+            .filter_map(|x| {
+                to_serial_port(&x.name, x.path.as_deref().unwrap_or(""))
+            })
+            .collect())
+    }
+
+    /// Connects the COM port `number` of a VM to the named pipe at `path`, or disconnects it if `path` is `None`.
+    ///
+    /// For more information, See [Set-VMComPort](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmcomport).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn set_vm_com_port_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        number: usize,
+        path: Option<&str>,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Set-VMComPort")
+            .args([
+                "-VMName",
+                vm,
+                "-Number",
+                &number.to_string(),
+                "-Path",
+                path.unwrap_or("''"),
+            ])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Gets the directories of the checkpoint files and the smart paging file of a VM.
+    ///
+    /// For more information, See [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_vm_file_locations_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<VmFileLocations> {
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([
+                vm,
+                "|select CheckpointFileLocation, \
+                 SmartPagingFilePath|ConvertTo-Json",
+            ])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "CheckpointFileLocation")]
+            checkpoint_file_location: Option<String>,
+            #[serde(alias = "SmartPagingFilePath")]
+            smart_paging_file_path: Option<String>,
+        }
+        let resp = deserialize::<Response>(&s)?;
+        Ok(VmFileLocations {
+            checkpoint_file_location: resp.checkpoint_file_location,
+            smart_paging_file_path: resp.smart_paging_file_path,
+        })
+    }
+
+    /// Sets the directories of the checkpoint files and the smart paging file of a VM.
+    ///
+    /// `None` parameters are left unchanged.
+    ///
+    /// For more information, See [Set-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `checkpoint_file_location` and `smart_paging_file_path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn set_vm_file_locations_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        checkpoint_file_location: Option<&str>,
+        smart_paging_file_path: Option<&str>,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new(pwsh_path, "Set-VM");
+        cmd.args(["-Name", vm]);
+        if let Some(x) = checkpoint_file_location {
+            cmd.args(["-SnapshotFileLocation", x]);
+        }
+        if let Some(x) = smart_paging_file_path {
+            cmd.args(["-SmartPagingFilePath", x]);
+        }
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Creates a full clone named `name` of a VM or its snapshot with [Export-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/export-vm) and [Import-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/import-vm).
+    ///
+    /// The new VM is stored in `<path>/<name>`, where `path` defaults to the default directory for VMs of the host.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `name`, `snapshot` and `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn clone_vm_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        name: &str,
+        snapshot: Option<&str>,
+        path: Option<&str>,
+    ) -> VmResult<Vm> {
+        let mut cmd = PsCommand::new(pwsh_path, "Get-VM");
+        cmd.args([
+            vm,
+            "|ForEach-Object {$tmp = Join-Path ([IO.Path]::GetTempPath()) \
+             ([guid]::NewGuid());",
+        ]);
+        match snapshot {
+            Some(x) => cmd.args([
+                "Get-VMSnapshot -VM $_ -Name",
+                x,
+                "|Export-VMSnapshot -Path $tmp -ErrorAction Stop;",
+            ]),
+            None => cmd.arg("Export-VM -VM $_ -Path $tmp -ErrorAction Stop;"),
+        };
+        cmd.args([
+            "$dst = Join-Path",
+            path.unwrap_or("(Get-VMHost).VirtualMachinePath"),
+            name,
+            "; $cfg = Get-ChildItem -Path $tmp -Recurse -Filter *.vmcx|select \
+             -First 1; $new = Import-VM -Path $cfg.FullName -Copy \
+             -GenerateNewId -VirtualMachinePath $dst -VhdDestinationPath \
+             (Join-Path $dst 'Virtual Hard Disks') -SnapshotFilePath $dst \
+             -SmartPagingFilePath $dst -ErrorAction Stop; Remove-Item -Path \
+             $tmp -Recurse -Force; Rename-VM -VM $new -NewName",
+            name,
+            "-PassThru}|select VMId, Name, Path|ConvertTo-Json",
+        ]);
+        parse_cloned_vm(&cmd.exec()?)
+    }
+
+    /// Creates a VM named `name` whose disks are differencing disks of the disks of a snapshot of a VM.
+    ///
+    /// The new VM has the generation, the startup memory, the processor count, the secure boot settings and the switch of the first network adapter of the snapshot.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `name`, `snapshot` and `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn new_linked_clone_vm_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        name: &str,
+        snapshot: &str,
+        path: Option<&str>,
+    ) -> VmResult<Vm> {
+        let s = PsCommand::new(pwsh_path, "Get-VMSnapshot")
+            .args([
+                "-VMName",
+                vm,
+                "-Name",
+                snapshot,
+                "|ForEach-Object {$s = $_; $src = Get-VM -Id $s.VMId; $dst = \
+                 Join-Path",
+                path.unwrap_or("(Get-VMHost).VirtualMachinePath"),
+                name,
+                "; $disks = New-Item -ItemType Directory -Force -Path \
+                 (Join-Path $dst 'Virtual Hard Disks'); $new = New-VM -Name",
+                name,
+                "-Generation $src.Generation -MemoryStartupBytes \
+                 $s.MemoryStartup -Path $dst -NoVHD -ErrorAction Stop; \
+                 Set-VMProcessor -VM $new -Count $s.ProcessorCount; $i = 0; \
+                 $s.HardDrives|ForEach-Object {$vhd = Join-Path \
+                 $disks.FullName ('{0}-{1}.vhdx' -f $new.Name, $i); $i++; \
+                 New-VHD -Path $vhd -ParentPath $_.Path -Differencing \
+                 -ErrorAction Stop|Out-Null; Add-VMHardDiskDrive -VM $new \
+                 -ControllerType $_.ControllerType -ControllerNumber \
+                 $_.ControllerNumber -ControllerLocation \
+                 $_.ControllerLocation -Path $vhd}; $sw = \
+                 $s.NetworkAdapters|select -First 1 -ExpandProperty \
+                 SwitchName; if ($sw) {Get-VMNetworkAdapter -VM \
+                 $new|Connect-VMNetworkAdapter -SwitchName $sw}; if \
+                 ($src.Generation -eq 2) {$fw = Get-VMFirmware -VM $src; \
+                 Set-VMFirmware -VM $new -EnableSecureBoot $fw.SecureBoot \
+                 -SecureBootTemplateId $fw.SecureBootTemplateId \
+                 -FirstBootDevice (Get-VMHardDiskDrive -VM $new|select -First \
+                 1)}; $new}|select VMId, Name, Path|ConvertTo-Json",
+            ])
+            .exec()?;
+        parse_cloned_vm(&s)
+    }
+
+    /// Exports a VM to the directory `path`.
+    ///
+    /// For more information, See [Export-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/export-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn export_vm_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        path: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Export-VM")
+            .args(["-Name", vm, "-Path", path])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Imports a copy of the first VM found in the directory `path` with a new ID.
+    ///
+    /// For more information, See [Import-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/import-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn import_vm_unescaped(
+        pwsh_path: &str,
+        path: &str,
+    ) -> VmResult<Vm> {
+        let s = PsCommand::new(pwsh_path, "Get-ChildItem")
+            .args([
+                "-Path",
+                path,
+                "-Recurse -Filter *.vmcx|select -First 1|ForEach-Object \
+                 {Import-VM -Path $_.FullName -Copy -GenerateNewId \
+                 -ErrorAction Stop}|select VMId, Name, Path|ConvertTo-Json",
+            ])
+            .exec()?;
+        parse_cloned_vm(&s)
+    }
+
+    /// Creates a VM without disks.
+    ///
+    /// For more information, See [New-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/new-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn create_vm_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        path: Option<&str>,
+        generation: Option<u8>,
+        memory_startup_bytes: Option<u64>,
+        processor_count: Option<u32>,
+    ) -> VmResult<Vm> {
+        let mut cmd = PsCommand::new(pwsh_path, "New-VM");
+        cmd.args(["-Name", vm, "-NoVHD"]);
+        if let Some(x) = path {
+            cmd.args(["-Path", x]);
+        }
+        if let Some(x) = generation {
+            cmd.args(["-Generation", &x.to_string()]);
+        }
+        if let Some(x) = memory_startup_bytes {
+            cmd.args(["-MemoryStartupBytes", &x.to_string()]);
+        }
+        cmd.arg("-ErrorAction Stop");
+        if let Some(x) = processor_count {
+            cmd.arg(format!(
+                "|ForEach-Object {{Set-VMProcessor -VM $_ -Count {}; $_}}",
+                x
+            ));
+        }
+        let s = cmd.arg("|select VMId, Name, Path|ConvertTo-Json").exec()?;
+        parse_cloned_vm(&s)
+    }
+
+    /// Registers the VM whose configuration file is at `path` in place.
+    ///
+    /// For more information, See [Import-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/import-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `path` before calling this function.
+    pub unsafe fn register_vm_unescaped(
+        pwsh_path: &str,
+        path: &str,
+    ) -> VmResult<Vm> {
+        let s = PsCommand::new(pwsh_path, "Import-VM")
+            .args([
+                "-Path",
+                path,
+                "-ErrorAction Stop|select VMId, Name, Path|ConvertTo-Json",
+            ])
+            .exec()?;
+        parse_cloned_vm(&s)
+    }
+
+    /// Removes a VM and deletes the virtual hard disks of the VM and its checkpoints.
+    ///
+    /// Parent disks of other VMs, e.g., the disks a linked clone is based on, are left.
+    ///
+    /// For more information, See [Remove-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn delete_vm_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Get-VM")
+            .args([
+                vm,
+                "-ErrorAction Stop|ForEach-Object {$d = \
+                 @($_|Get-VMHardDiskDrive) + \
+                 @($_|Get-VMSnapshot|Get-VMHardDiskDrive)|ForEach-Object \
+                 {$_.Path}|Sort-Object -Unique; $_|Remove-VM -Force; \
+                 $d|Remove-Item -ErrorAction Stop}",
+            ])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Gets the processor configuration of a VM.
+    ///
+    /// For more information, See [Get-VMProcessor](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmprocessor).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_vm_processor_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<CpuTuning> {
+        let s = PsCommand::new(pwsh_path, "Get-VMProcessor")
+            .args([
+                vm,
+                "|select Count, Reserve, Maximum, \
+                 RelativeWeight|ConvertTo-Json",
+            ])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Count")]
+            count: u32,
+            #[serde(alias = "Reserve")]
+            reserve: u32,
+            #[serde(alias = "Maximum")]
+            maximum: u32,
+            #[serde(alias = "RelativeWeight")]
+            relative_weight: u32,
+        }
+        let resp = deserialize::<Response>(&s)?;
+        Ok(CpuTuning {
+            count: Some(resp.count),
+            reserve: Some(resp.reserve),
+            maximum: Some(resp.maximum),
+            relative_weight: Some(resp.relative_weight),
+        })
+    }
+
+    /// Configures the processors of VMs.
+    ///
+    /// `None` fields of `tuning` are left unchanged.
+    ///
+    /// For more information, See [Set-VMProcessor](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vmprocessor).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vms`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vms` before calling this function.
+    pub unsafe fn set_vm_processor_unescaped<I>(
+        pwsh_path: &str,
+        vms: I,
+        tuning: &CpuTuning,
+    ) -> VmResult<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str> + AsRef<OsStr>,
+    {
+        let mut cmd = PsCommand::new(pwsh_path, "Set-VMProcessor");
+        cmd.arg_array_unescaped(vms);
+        for (name, value) in [
+            ("-Count", tuning.count),
+            ("-Reserve", tuning.reserve),
+            ("-Maximum", tuning.maximum),
+            ("-RelativeWeight", tuning.relative_weight),
+        ] {
+            if let Some(x) = value {
+                cmd.args([name, &x.to_string()]);
+            }
+        }
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Gets the startup memory of a VM in bytes.
+    ///
+    /// For more information, See [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn get_vm_memory_startup_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<u64> {
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([vm, "|select MemoryStartup|ConvertTo-Json"])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "MemoryStartup")]
+            memory_startup: u64,
+        }
+        Ok(deserialize::<Response>(&s)?.memory_startup)
+    }
+
+    /// Sets the startup memory of a VM in bytes.
+    ///
+    /// For more information, See [Set-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/set-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape `vm` before calling this function.
+    pub unsafe fn set_vm_memory_startup_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        bytes: u64,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Set-VM")
+            .args(["-Name", vm, "-MemoryStartupBytes", &bytes.to_string()])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Gets GPU partition adapters of a VM.
+    ///
+    /// For more information, See [Get-VMGpuPartitionAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmgpupartitionadapter).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn get_vm_gpu_partition_adapter_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<PassthroughDevice>> {
+        let s = PsCommand::new(pwsh_path, "Get-VMGpuPartitionAdapter")
+            .args(["-VMName", vm, "|select Id, InstancePath|ConvertTo-Json"])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Id")]
+            id: String,
+            #[serde(alias = "InstancePath")]
+            instance_path: Option<String>,
+        }
+        if s.is_empty() {
+            // No adapter.
+            return Ok(vec![]);
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        Ok(resp
+            .into_iter()
+            .map(|x| PassthroughDevice {
+                id: Some(x.id),
+                ty: Some(PassthroughType::GpuPartition),
+                host_location: x.instance_path,
+                guest_location: None,
+            })
+            .collect())
+    }
+
+    /// Adds a GPU partition adapter to a VM.
+    ///
+    /// For more information, See [Add-VMGpuPartitionAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/add-vmgpupartitionadapter).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `instance_path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn add_vm_gpu_partition_adapter_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        instance_path: Option<&str>,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new(pwsh_path, "Add-VMGpuPartitionAdapter");
+        cmd.args(["-VMName", vm]);
+        if let Some(x) = instance_path {
+            cmd.args(["-InstancePath", x]);
+        }
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Removes a GPU partition adapter from a VM.
+    ///
+    /// For more information, See [Remove-VMGpuPartitionAdapter](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vmgpupartitionadapter).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `adapter_id`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn remove_vm_gpu_partition_adapter_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        adapter_id: Option<&str>,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new(pwsh_path, "Remove-VMGpuPartitionAdapter");
+        cmd.args(["-VMName", vm]);
+        if let Some(x) = adapter_id {
+            cmd.args(["-AdapterId", x]);
+        }
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Gets devices assigned to a VM with Discrete Device Assignment.
+    ///
+    /// For more information, See [Get-VMAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmassignabledevice).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn get_vm_assignable_device_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<PassthroughDevice>> {
+        let s = PsCommand::new(pwsh_path, "Get-VMAssignableDevice")
+            .args([
+                "-VMName",
+                vm,
+                "|select InstanceID, LocationPath|ConvertTo-Json",
+            ])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "InstanceID")]
+            id: String,
+            #[serde(alias = "LocationPath")]
+            location_path: String,
+        }
+        if s.is_empty() {
+            // No device.
+            return Ok(vec![]);
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        Ok(resp
+            .into_iter()
+            .map(|x| PassthroughDevice {
+                id: Some(x.id),
+                ty: Some(PassthroughType::Pci),
+                host_location: Some(x.location_path),
+                guest_location: None,
+            })
+            .collect())
+    }
+
+    /// Assigns a host device at `location_path` to a VM.
+    ///
+    /// For more information, See [Add-VMAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/add-vmassignabledevice).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `location_path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn add_vm_assignable_device_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Add-VMAssignableDevice")
+            .args(["-VMName", vm, "-LocationPath", location_path])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Removes a device at `location_path` from a VM.
+    ///
+    /// For more information, See [Remove-VMAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/remove-vmassignabledevice).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `location_path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn remove_vm_assignable_device_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Remove-VMAssignableDevice")
+            .args(["-VMName", vm, "-LocationPath", location_path])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Dismounts a device at `location_path` from the host.
+    ///
+    /// For more information, See [Dismount-VMHostAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/dismount-vmhostassignabledevice).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `location_path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn dismount_vm_host_assignable_device_unescaped(
+        pwsh_path: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Dismount-VMHostAssignableDevice")
+            .args(["-Force -LocationPath", location_path])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Mounts a device at `location_path` to the host.
+    ///
+    /// For more information, See [Mount-VMHostAssignableDevice](https://docs.microsoft.com/en-us/powershell/module/hyper-v/mount-vmhostassignabledevice).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `location_path`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn mount_vm_host_assignable_device_unescaped(
+        pwsh_path: &str,
+        location_path: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Mount-VMHostAssignableDevice")
+            .args(["-LocationPath", location_path])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Gets users allowed to connect to the console of a VM.
+    ///
+    /// For more information, See [Get-VMConnectAccess](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vmconnectaccess).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn get_vm_connect_access_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<VmConnectAccess>> {
+        let s = PsCommand::new(pwsh_path, "Get-VMConnectAccess")
+            .args(["-VMName", vm, "|select UserName, UserId|ConvertTo-Json"])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "UserName")]
+            user_name: String,
+            #[serde(alias = "UserId")]
+            user_id: Option<String>,
+        }
+        if s.is_empty() {
+            // No user.
+            return Ok(vec![]);
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        Ok(resp
+            .into_iter()
+            .map(|x| VmConnectAccess {
+                user_name: x.user_name,
+                user_id: x.user_id,
+            })
+            .collect())
+    }
+
+    /// Allows `user_name` to connect to the console of a VM.
+    ///
+    /// For more information, See [Grant-VMConnectAccess](https://docs.microsoft.com/en-us/powershell/module/hyper-v/grant-vmconnectaccess).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `user_name`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn grant_vm_connect_access_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        user_name: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Grant-VMConnectAccess")
+            .args(["-VMName", vm, "-UserName", user_name])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Disallows `user_name` to connect to the console of a VM.
+    ///
+    /// For more information, See [Revoke-VMConnectAccess](https://docs.microsoft.com/en-us/powershell/module/hyper-v/revoke-vmconnectaccess).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm` and `user_name`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn revoke_vm_connect_access_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        user_name: &str,
+    ) -> VmResult<()> {
+        PsCommand::new(pwsh_path, "Revoke-VMConnectAccess")
+            .args(["-VMName", vm, "-UserName", user_name])
+            .exec()?;
+        Ok(())
+    }
+
+    /// Creates a temporary directory in a guest with PSSession.
+    ///
+    /// Returns the path to the directory.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `username` and `password`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn create_temp_dir_in_guest_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<String> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { (New-Item -ItemType Directory \
+             -Path (Join-Path ([IO.Path]::GetTempPath()) \
+             ([IO.Path]::GetRandomFileName()))).FullName }",
+            "; Remove-PSSession $sess;",
+        ]);
+        Ok(cmd.exec()?.trim().to_string())
+    }
+
+    /// Runs a program in a guest with PSSession and captures its output.
+    ///
+    /// `env` is a list of `(name, value)`. The program is killed and [`ErrorKind::Timeout`] is returned if it doesn't exit in `timeout`.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape the parameters, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn invoke_program_in_guest_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        program: &str,
+        arguments: &str,
+        cwd: Option<&str>,
+        env: &[(String, String)],
+        timeout: Option<Duration>,
+        username: &str,
+        password: &str,
+    ) -> VmResult<GuestOutput> {
+        let env = env
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join(";");
+        let timeout = timeout.map_or(-1, |x| x.as_millis() as i64);
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { param($f, $a, $d, $t, $env) $psi = \
+             New-Object System.Diagnostics.ProcessStartInfo $f, $a; \
+             $psi.UseShellExecute = $false; $psi.RedirectStandardOutput = \
+             $true; $psi.RedirectStandardError = $true; if ($d) { \
+             $psi.WorkingDirectory = $d }; foreach ($k in $env.Keys) { \
+             $psi.EnvironmentVariables[$k] = $env[$k] }; $p = \
+             [System.Diagnostics.Process]::Start($psi); $o = \
+             $p.StandardOutput.ReadToEndAsync(); $e = \
+             $p.StandardError.ReadToEndAsync(); $x = $p.WaitForExit($t); if \
+             ($x) { $p.WaitForExit() } else { $p.Kill() }; [pscustomobject]@{ \
+             stdout = $o.Result; stderr = $e.Result; exit_code = if ($x) { \
+             $p.ExitCode } else { $null }; timed_out = -not $x } } \
+             -ArgumentList",
+            program,
+            ",",
+            arguments,
+            ",",
+            cwd.unwrap_or("$null"),
+            ",",
+            &timeout.to_string(),
+            &format!(", @{{{}}}", env),
+            "|select stdout, stderr, exit_code, timed_out|ConvertTo-Json; \
+             Remove-PSSession $sess;",
+        ]);
+        let s = cmd.exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            stdout: Option<String>,
+            stderr: Option<String>,
+            exit_code: Option<i32>,
+            timed_out: bool,
+        }
+        let resp: Response = deserialize(&s)?;
+        if resp.timed_out {
+            return vmerr!(ErrorKind::Timeout);
+        }
+        Ok(GuestOutput {
+            stdout: resp.stdout.unwrap_or_default(),
+            stderr: resp.stderr.unwrap_or_default(),
+            exit_code: resp.exit_code,
+        })
+    }
+
+    /// Removes a directory and its contents in a guest with PSSession.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `path`, `username` and `password`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn remove_dir_in_guest_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        path: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { Remove-Item -Recurse -Force \
+             -LiteralPath $args[0] } -ArgumentList",
+            path,
+            "; Remove-PSSession $sess;",
+        ]);
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Gets the processes in a guest with PSSession.
+    ///
+    /// The owners are empty unless the guest user is an administrator.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `username` and `password`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn get_process_in_guest_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<Vec<ProcInfo>> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { try { Get-Process -IncludeUserName \
+             -ErrorAction Stop } catch { Get-Process } }",
+            "|select Id, UserName, Path, ProcessName|ConvertTo-Json; \
+             Remove-PSSession $sess;",
+        ]);
+        let s = cmd.exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Id")]
+            id: u32,
+            #[serde(alias = "UserName")]
+            user_name: Option<String>,
+            #[serde(alias = "Path")]
+            path: Option<String>,
+            #[serde(alias = "ProcessName")]
+            process_name: String,
+        }
+        if s.trim().is_empty() {
+            return Ok(vec![]);
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        Ok(resp
+            .into_iter()
+            .map(|x| ProcInfo {
+                pid: x.id,
+                owner: x.user_name.unwrap_or_default(),
+                cmd: x.path.unwrap_or(x.process_name),
+            })
+            .collect())
+    }
+
+    /// Stops the process of `pid` in a guest with PSSession.
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, `username` and `password`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn stop_process_in_guest_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+        pid: u32,
+        username: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { Stop-Process -Force -Id $args[0] } \
+             -ArgumentList",
+            &pid.to_string(),
+            "; Remove-PSSession $sess;",
+        ]);
+        cmd.exec()?;
+        Ok(())
+    }
+
+    /// Creates a directory in a guest with PSSession.
     ///
     /// # Safety
     ///
-    /// This function doesn't escape `vms`, which can lead to command injection.
+    /// This function doesn't escape `vm`, `path`, `username` and `password`, which can lead to command injection.
     ///
-    /// Please be sure to escape `vms` before calling this function.
-    pub unsafe fn stop_vm_unescaped<I>(
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn make_dir_in_guest_unescaped(
         pwsh_path: &str,
-        vms: I,
-        turn_off: bool,
-        use_save: bool,
-    ) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
-        let mut cmd = PsCommand::new(pwsh_path, "Stop-VM");
-        cmd.arg("-Force");
-        cmd.arg_array_unescaped(vms);
-        if turn_off {
-            cmd.arg("-TurnOff");
-        }
-        if use_save {
-            cmd.arg("-Save");
-        }
-        let s = cmd.exec()?;
-        if s.starts_with(
-            "WARNING: The virtual machine is already in the specified state.",
-        ) {
-            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Stopped));
-        }
+        vm: &str,
+        path: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { New-Item -ItemType Directory -Path \
+             $args[0] | Out-Null } -ArgumentList",
+            path,
+            "; Remove-PSSession $sess;",
+        ]);
+        cmd.exec()?;
         Ok(())
     }
 
-    /// Suspends VMs.
-    ///
-    /// For more information, See [Suspend-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/suspend-vm).
+    /// Removes a file in a guest with PSSession.
     ///
     /// # Safety
     ///
-    /// This function doesn't escape `vms`, which can lead to command injection.
+    /// This function doesn't escape `vm`, `path`, `username` and `password`, which can lead to command injection.
     ///
-    /// Please be sure to escape `vms` before calling this function.
-    pub unsafe fn suspend_vm_unescaped<I>(
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn remove_file_in_guest_unescaped(
         pwsh_path: &str,
-        vms: I,
-    ) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
-        let res = PsCommand::new(pwsh_path, "Suspend-VM")
-            .arg_array_unescaped(vms)
-            .exec()?;
-        if res.starts_with(
-            "WARNING: The virtual machine is already in the specified state.",
-        ) {
-            return vmerr!(ErrorKind::InvalidPowerState(
-                VmPowerState::Suspended
-            ));
-        }
+        vm: &str,
+        path: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { Remove-Item -Force -LiteralPath \
+             $args[0] } -ArgumentList",
+            path,
+            "; Remove-PSSession $sess;",
+        ]);
+        cmd.exec()?;
         Ok(())
     }
 
-    /// Resumes VMs.
+    /// Gets the information of a file in a guest with PSSession.
     ///
-    /// For more information, See [Resume-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/resume-vm).
+    /// Returns `None` if the file doesn't exist.
     ///
     /// # Safety
     ///
-    /// This function doesn't escape `vms`, which can lead to command injection.
+    /// This function doesn't escape `vm`, `path`, `username` and `password`, which can lead to command injection.
     ///
-    /// Please be sure to escape `vms` before calling this function.
-    pub unsafe fn resume_vm_unescaped<I>(
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn get_item_in_guest_unescaped(
         pwsh_path: &str,
-        vms: I,
-    ) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
-        let s = PsCommand::new(pwsh_path, "Resume-VM")
-            .arg_array_unescaped(vms)
-            .exec()?;
-        if s.starts_with(
-            "WARNING: The virtual machine is already in the specified state.",
-        ) {
-            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
+        vm: &str,
+        path: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<Option<GuestFileInfo>> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { $i = Get-Item -Force -LiteralPath \
+             $args[0] -ErrorAction SilentlyContinue; if ($i) { \
+             [pscustomobject]@{ is_dir = $i.PSIsContainer; is_link = \
+             [bool]$i.LinkType; size = $i.Length } } } -ArgumentList",
+            path,
+            "|ConvertTo-Json; Remove-PSSession $sess;",
+        ]);
+        let s = cmd.exec()?;
+        if s.trim().is_empty() {
+            return Ok(None);
         }
-        Ok(())
+        #[derive(Deserialize)]
+        struct Response {
+            is_dir: bool,
+            is_link: bool,
+            size: Option<u64>,
+        }
+        let resp: Response = deserialize(&s)?;
+        let file_type = match (resp.is_link, resp.is_dir) {
+            (true, _) => GuestFileType::Symlink,
+            (_, true) => GuestFileType::Directory,
+            _ => GuestFileType::File,
+        };
+        Ok(Some(GuestFileInfo {
+            file_type,
+            size: resp.size,
+        }))
     }
 
-    /// Restarts VMs.
-    ///
-    /// For more information, See [Restart-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/restart-vm).
+    /// Moves a file or a directory in a guest with PSSession.
     ///
     /// # Safety
     ///
-    /// This function doesn't escape `vms`, which can lead to command injection.
+    /// This function doesn't escape `vm`, `from_path`, `to_path`, `username` and `password`, which can lead to command injection.
     ///
-    /// Please be sure to escape `vms` before calling this function.
-    pub unsafe fn restart_vm_unchecked<I>(
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn move_item_in_guest_unescaped(
         pwsh_path: &str,
-        vms: I,
-    ) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
-        PsCommand::new(pwsh_path, "Restart-VM")
-            .arg("-Confirm:$false")
-            .arg_array_unescaped(vms)
-            .exec()?;
+        vm: &str,
+        from_path: &str,
+        to_path: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { Move-Item -LiteralPath $args[0] \
+             -Destination $args[1] } -ArgumentList",
+            from_path,
+            ",",
+            to_path,
+            "; Remove-PSSession $sess;",
+        ]);
+        cmd.exec()?;
         Ok(())
     }
 
-    /// Copies a file between from the host to guests.
-    ///
-    /// For more information, See [Copy-VMFile](https://docs.microsoft.com/en-us/powershell/module/hyper-v/copy-vmfile).
+    /// Gets the names of the entries in a directory in a guest with PSSession.
     ///
     /// # Safety
     ///
-    /// This function doesn't escape `vms`, `src_path` and `dst_path`, which can lead to command injection.
+    /// This function doesn't escape `vm`, `path`, `username` and `password`, which can lead to command injection.
     ///
     /// Please be sure to escape the parameters before calling this function.
-    pub unsafe fn copy_vm_file_unescaped<I>(
+    pub unsafe fn get_child_item_in_guest_unescaped(
         pwsh_path: &str,
-        vms: I,
-        src_path: &str,
-        dst_path: &str,
-        create_full_path: bool,
-    ) -> VmResult<()>
-    where
-        I: IntoIterator,
-        I::Item: AsRef<str> + AsRef<OsStr>,
-    {
-        let mut cmd = PsCommand::new(pwsh_path, "Copy-VMFile");
-        cmd.arg_array_unescaped(vms);
-        cmd.args(&[
-            "-Force",
-            "-SourcePath",
-            src_path,
-            "-DestinationPath",
-            dst_path,
-            "-FileSource Host",
+        vm: &str,
+        path: &str,
+        username: &str,
+        password: &str,
+    ) -> VmResult<Vec<String>> {
+        let mut cmd = PsCommand::new_with_session(
+            pwsh_path,
+            "Invoke-Command",
+            vm,
+            username,
+            password,
+        );
+        cmd.args([
+            "-Session $sess -ScriptBlock { Get-ChildItem -Force -LiteralPath \
+             $args[0] | foreach { $_.Name } } -ArgumentList",
+            path,
+            "; Remove-PSSession $sess;",
         ]);
-        if create_full_path {
-            cmd.arg("-CreateFullPath");
-        }
-        cmd.exec()?;
-        Ok(())
+        let s = cmd.exec()?;
+        Ok(s.lines()
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect())
     }
 
-    /// Copies a file between from a guest to the host with PSSession.
+    /// Creates an empty temporary file in a guest with PSSession and returns the path to it.
     ///
     /// # Safety
     ///
-    /// This function doesn't escape `vms`, `src_path`, `dst_path`, `username` and `password`, which can lead to command injection.
+    /// This function doesn't escape `vm`, `username` and `password`, which can lead to command injection.
     ///
     /// Please be sure to escape the parameters before calling this function.
-    pub unsafe fn copy_vm_file_from_guest_to_host_unescaped(
+    pub unsafe fn make_temp_file_in_guest_unescaped(
         pwsh_path: &str,
         vm: &str,
-        src_path: &str,
-        dst_path: &str,
         username: &str,
         password: &str,
-    ) -> VmResult<()> {
+    ) -> VmResult<String> {
         let mut cmd = PsCommand::new_with_session(
             pwsh_path,
-            "Copy-Item",
+            "Invoke-Command",
             vm,
             username,
             password,
         );
-        cmd.args(&[
-            "-FromSession $sess -Path",
-            src_path,
-            "-Destination",
-            dst_path,
+        cmd.args([
+            "-Session $sess -ScriptBlock { [IO.Path]::GetTempFileName() }",
             "; Remove-PSSession $sess;",
         ]);
-        cmd.exec()?;
-        Ok(())
+        Ok(cmd.exec()?.trim().to_string())
     }
 
     /// Gets the list of checkpoints of a VM.
@@ -980,7 +4841,12 @@ pub mod raw_unescaped {
         vm: &str,
     ) -> VmResult<Vec<Snapshot>> {
         let s = PsCommand::new(pwsh_path, "Get-VMSnapshot")
-            .args(&[vm, "|select Id, Name, Notes|ConvertTo-Json"])
+            .args([
+                vm,
+                "|select Id, Name, Notes, ParentSnapshotId, CreationTime, \
+                 @{n='State';e={\"$($_.State)\"}}, @{n='IsCurrent';e={$_.Id \
+                 -eq (Get-VM -Id $_.VMId).ParentSnapshotId}}|ConvertTo-Json",
+            ])
             .exec()?;
         #[derive(Deserialize)]
         struct Response {
@@ -990,6 +4856,16 @@ pub mod raw_unescaped {
             name: String,
             #[serde(alias = "Notes")]
             detail: String,
+            #[serde(alias = "ParentSnapshotId")]
+            parent_id: Option<String>,
+            /// `/Date(...)/` in Windows PowerShell and ISO 8601 in PowerShell 7.
+            #[serde(alias = "CreationTime")]
+            creation_time: Option<String>,
+            /// The state of the VM saved in the checkpoint, e.g., `Off`.
+            #[serde(alias = "State")]
+            state: Option<String>,
+            #[serde(alias = "IsCurrent")]
+            is_current: Option<bool>,
         }
         if s.is_empty() {
             // No snapshot.
@@ -1002,10 +4878,114 @@ pub mod raw_unescaped {
                 id: Some(x.id.clone()),
                 name: Some(x.name.clone()),
                 detail: Some(x.detail.clone()),
+                parent_id: x.parent_id.clone(),
+                created_at: x
+                    .creation_time
+                    .as_deref()
+                    .and_then(parse_timestamp),
+                is_current: x.is_current,
+                online: x
+                    .state
+                    .as_deref()
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x != "Off"),
             })
             .collect())
     }
 
+    /// Gets the ID of the checkpoint the current state of a VM is based on.
+    ///
+    /// For more information, See [Get-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vm).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn get_vm_current_snapshot_id_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Option<String>> {
+        let s = PsCommand::new(pwsh_path, "Get-VM")
+            .args([vm, "|select ParentSnapshotId|ConvertTo-Json"])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "ParentSnapshotId")]
+            parent_snapshot_id: Option<String>,
+        }
+        Ok(deserialize::<Response>(&s)?.parent_snapshot_id)
+    }
+
+    /// Gets the disk space used by each checkpoint of a VM.
+    ///
+    /// The sizes are `FileSize` of the AVHDX files reported by [Get-VHD](https://docs.microsoft.com/en-us/powershell/module/hyper-v/get-vhd).
+    ///
+    /// # Safety
+    ///
+    /// This function doesn't escape `vm`, which can lead to command injection.
+    ///
+    /// Please be sure to escape the parameters before calling this function.
+    pub unsafe fn get_vm_snapshot_disk_usage_unescaped(
+        pwsh_path: &str,
+        vm: &str,
+    ) -> VmResult<Vec<SnapshotDiskUsage>> {
+        let s = PsCommand::new(pwsh_path, "Get-VMSnapshot")
+            .args([
+                vm,
+                "|ForEach-Object {$s = $_; $s.HardDrives|ForEach-Object \
+                 {[PSCustomObject]@{Id = $s.Id; Name = $s.Name; Notes = \
+                 $s.Notes; Path = $_.Path; FileSize = (Get-VHD -Path \
+                 $_.Path).FileSize}}}|ConvertTo-Json",
+            ])
+            .exec()?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "Id")]
+            id: String,
+            #[serde(alias = "Name")]
+            name: String,
+            #[serde(alias = "Notes")]
+            detail: String,
+            #[serde(alias = "Path")]
+            path: String,
+            #[serde(alias = "FileSize")]
+            file_size: u64,
+        }
+        if s.is_empty() {
+            // No snapshot.
+            return Ok(vec![]);
+        }
+        let resp = HyperVCmd::deserialize_resp::<Response>(&s)?;
+        let mut ret: Vec<SnapshotDiskUsage> = vec![];
+        for x in resp {
+            // Each disk of a checkpoint is a separate element.
+            match ret
+                .iter_mut()
+                .find(|u| u.snapshot.id.as_deref() == Some(x.id.as_str()))
+            {
+                Some(u) => {
+                    u.disks.push(x.path);
+                    u.size += x.file_size;
+                }
+                None => ret.push(SnapshotDiskUsage {
+                    snapshot: Snapshot {
+                        id: Some(x.id),
+                        name: Some(x.name),
+                        detail: Some(x.detail),
+                        parent_id: None,
+                        created_at: None,
+                        is_current: None,
+                        online: None,
+                    },
+                    disks: vec![x.path],
+                    size: x.file_size,
+                }),
+            }
+        }
+        Ok(ret)
+    }
+
     /// Creates a checkpoint named `name` of VMs.
     ///
     /// For more information, See [Checkpoint-VM](https://docs.microsoft.com/en-us/powershell/module/hyper-v/checkpoint-vm).
@@ -1026,7 +5006,7 @@ pub mod raw_unescaped {
     {
         PsCommand::new(pwsh_path, "Checkpoint-VM")
             .arg_array_unescaped(vms)
-            .args(&["-SnapshotName", name])
+            .args(["-SnapshotName", name])
             .exec()?;
         Ok(())
     }
@@ -1046,7 +5026,7 @@ pub mod raw_unescaped {
         name: &str,
     ) -> VmResult<()> {
         PsCommand::new(pwsh_path, "Restore-VMSnapshot")
-            .args(&["-VMName", vm_name, "-Confirm:$false -Name", name])
+            .args(["-VMName", vm_name, "-Confirm:$false -Name", name])
             .exec()?;
         Ok(())
     }
@@ -1071,8 +5051,67 @@ pub mod raw_unescaped {
     {
         PsCommand::new(pwsh_path, "Remove-VMSnapshot")
             .arg_array_unescaped(vms)
-            .args(&["-Confirm:$false -Name", name])
+            .args(["-Confirm:$false -Name", name])
             .exec()?;
         Ok(())
     }
 }
+
+#[test]
+fn test_to_serial_port() {
+    assert_eq!(
+        to_serial_port("COM 2", r"\\.\pipe\vm"),
+        Some(SerialPort {
+            number: 2,
+            mode: SerialPortMode::PipeServer(r"\\.\pipe\vm".to_string())
+        })
+    );
+    assert_eq!(
+        to_serial_port("COM 1", ""),
+        Some(SerialPort {
+            number: 1,
+            mode: SerialPortMode::Disconnected
+        })
+    );
+    assert_eq!(to_serial_port("LPT 1", ""), None);
+}
+
+#[test]
+fn test_to_host_network() {
+    assert_eq!(
+        to_host_network(
+            "lab",
+            "Internal",
+            Some("192.168.100.0/24"),
+            Some("192.168.100.1"),
+            Some(24)
+        ),
+        Some(HostNetwork {
+            name: "lab".to_string(),
+            kind: HostNetworkKind::Nat,
+            subnet: Some("192.168.100.0/24".to_string()),
+            host_ip: Some("192.168.100.1".to_string()),
+            dhcp: Some(false),
+        })
+    );
+    let net = to_host_network(
+        "Default Switch",
+        "Internal",
+        None,
+        Some("172.20.16.1"),
+        Some(20),
+    )
+    .unwrap();
+    assert_eq!(net.kind, HostNetworkKind::HostOnly);
+    assert_eq!(net.subnet.as_deref(), Some("172.20.16.0/20"));
+    let net = to_host_network("vms", "Private", None, None, None).unwrap();
+    assert_eq!(net.kind, HostNetworkKind::Internal);
+    assert_eq!(net.subnet, None);
+    assert_eq!(
+        to_host_network("ext", "External", None, None, None)
+            .unwrap()
+            .kind,
+        HostNetworkKind::Bridged
+    );
+    assert_eq!(to_host_network("x", "Unknown", None, None, None), None);
+}
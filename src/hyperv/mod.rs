@@ -2,8 +2,50 @@
 // This source code is licensed under the MIT or Apache-2.0 license.
 //! Hyper-V controllers.
 #![cfg(windows)]
+#[cfg(feature = "hypervhcs")]
+pub mod hcs;
 #[cfg(feature = "hypervcmd")]
 pub mod hypervcmd;
+#[cfg(feature = "hypervwmi")]
+pub mod wmi;
 
+#[cfg(feature = "hypervwmi")]
+pub use self::wmi::HyperVWmi;
+#[cfg(feature = "hypervhcs")]
+pub use hcs::HyperVHcs;
 #[cfg(feature = "hypervcmd")]
 pub use hypervcmd::*;
+
+/// The port of the Hyper-V VMBus console used by vmconnect.
+pub const VMCONNECT_PORT: u16 = 2179;
+
+/// Creates the content of a `.rdp` file which connects to the console of the VM specified by `vm_id` in an enhanced session.
+///
+/// `host` is the Hyper-V host name. The connection goes through the VMBus, so it does not require network access to the guest.
+pub fn rdp_file_content(host: &str, vm_id: &str) -> String {
+    [
+        format!("full address:s:{}", host),
+        format!("server port:i:{}", VMCONNECT_PORT),
+        format!("pcb:s:{};EnhancedMode=1", vm_id),
+        "negotiate security layer:i:0".to_string(),
+        "prompt for credentials on client:i:1".to_string(),
+        String::new(),
+    ]
+    .join("\r\n")
+}
+
+/// Gets the name of this host to be written in `.rdp` files.
+#[allow(dead_code)]
+fn local_host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+#[test]
+fn test_rdp_file_content() {
+    let s = rdp_file_content("HOST", "0f6e5a6c-1b2b-4c3d-8e9f-a0b1c2d3e4f5");
+    assert!(s.starts_with("full address:s:HOST\r\nserver port:i:2179\r\n"));
+    assert!(s.contains(
+        "\r\npcb:s:0f6e5a6c-1b2b-4c3d-8e9f-a0b1c2d3e4f5;EnhancedMode=1\r\n"
+    ));
+    assert!(s.ends_with("\r\n"));
+}
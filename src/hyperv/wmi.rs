@@ -0,0 +1,1124 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Hyper-V WMI controller.
+//!
+//! Controls VMs through the [`Msvm_*` classes](https://docs.microsoft.com/en-us/windows/win32/hyperv_v2/windows-virtualization-portal) in the `root\virtualization\v2` namespace without spawning PowerShell.
+use crate::{
+    encode_png,
+    events::{Subscription, VmEvent},
+    types::*,
+};
+use ::wmi::{
+    query::quote_and_escape_wql_str, result_enumerator::IWbemClassWrapper,
+    COMLibrary, Variant, WMIConnection, WMIError,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+const NAMESPACE: &str = "root\\virtualization\\v2";
+
+/// `ReturnValue` of a method which completed synchronously.
+const RETURN_COMPLETED: u32 = 0;
+/// `ReturnValue` of a method which started an asynchronous job.
+const RETURN_JOB_STARTED: u32 = 4096;
+
+const REALIZED_SYSTEM: &str = "Microsoft:Hyper-V:System:Realized";
+const REALIZED_SNAPSHOT: &str = "Microsoft:Hyper-V:Snapshot:Realized";
+
+/// The size of a thumbnail image used if the resolution of the console is unknown.
+const DEFAULT_THUMBNAIL_SIZE: (u16, u16) = (640, 480);
+
+/// Represents Hyper-V WMI controller.
+#[derive(Clone, Debug)]
+pub struct HyperVWmi {
+    vm_id: Option<String>,
+    hooks: CommandHooks,
+}
+
+impl Default for HyperVWmi {
+    fn default() -> Self { Self::new() }
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_ComputerSystem", rename_all = "PascalCase")]
+struct ComputerSystem {
+    #[serde(rename = "__Path")]
+    path: String,
+    enabled_state: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_ComputerSystem", rename_all = "PascalCase")]
+struct EnabledState {
+    enabled_state: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "__InstanceModificationEvent", rename_all = "PascalCase")]
+struct ComputerSystemModification {
+    previous_instance: EnabledState,
+    target_instance: EnabledState,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_VirtualSystemSettingData", rename_all = "PascalCase")]
+struct VirtualSystemSettingData {
+    #[serde(rename = "__Path")]
+    path: String,
+    #[serde(rename = "InstanceID")]
+    instance_id: String,
+    element_name: String,
+    virtual_system_identifier: String,
+    /// `Microsoft:Hyper-V:SubType:1` or `Microsoft:Hyper-V:SubType:2`.
+    virtual_system_sub_type: Option<String>,
+    configuration_data_root: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_ProcessorSettingData", rename_all = "PascalCase")]
+struct ProcessorSettingData {
+    virtual_quantity: u64,
+    reservation: u64,
+    limit: u64,
+    weight: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_MemorySettingData", rename_all = "PascalCase")]
+struct MemorySettingData {
+    virtual_quantity: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_VideoHead", rename_all = "PascalCase")]
+struct VideoHead {
+    current_horizontal_resolution: Option<u32>,
+    current_vertical_resolution: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Msvm_ConcreteJob", rename_all = "PascalCase")]
+struct ConcreteJob {
+    job_state: u16,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InstancePath {
+    #[serde(rename = "__Path")]
+    path: String,
+}
+
+fn wmi_error(e: WMIError) -> VmError {
+    VmError::from(ErrorKind::ExecutionFailed(e.to_string()))
+}
+
+/// Converts `EnabledState` of `Msvm_ComputerSystem` into [`VmPowerState`].
+fn to_power_state(enabled_state: u16) -> VmPowerState {
+    match enabled_state {
+        2 => VmPowerState::Running,
+        3 => VmPowerState::Stopped,
+        6 => VmPowerState::Suspended,
+        9 => VmPowerState::Paused,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+/// Converts `VirtualSystemSubType` to the generation of the VM.
+fn to_generation(sub_type: &str) -> Option<u32> {
+    sub_type
+        .strip_prefix("Microsoft:Hyper-V:SubType:")
+        .and_then(|x| x.parse().ok())
+}
+
+/// Converts `ReturnValue` of the `Msvm_*` method `method` into an error.
+fn check_return_value(method: &str, v: u32) -> VmResult<()> {
+    match v {
+        RETURN_COMPLETED => Ok(()),
+        32769 => vmerr!(ErrorKind::PermissionDenied),
+        32770 => vmerr!(ErrorKind::unsupported::<HyperVWmi>(method)),
+        32773 => vmerr!(ErrorKind::InvalidParameter(v.to_string())),
+        32775 => vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Unknown)),
+        v => vmerr!(ErrorKind::ExecutionFailed(format!(
+            "The method returned {}",
+            v
+        ))),
+    }
+}
+
+/// Converts RGB565 pixels in little endian into 8-bit RGB pixels.
+fn rgb565_to_rgb(data: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(data.len() / 2 * 3);
+    for px in data.chunks_exact(2) {
+        let v = u16::from_le_bytes([px[0], px[1]]) as u32;
+        ret.push((((v >> 11) & 0x1F) * 255 / 31) as u8);
+        ret.push((((v >> 5) & 0x3F) * 255 / 63) as u8);
+        ret.push(((v & 0x1F) * 255 / 31) as u8);
+    }
+    ret
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            ch => ret.push(ch),
+        }
+    }
+    ret
+}
+
+/// Builds an embedded instance in the CIM-DTD 2.0 format with string properties.
+fn embedded_instance(class_name: &str, props: &[(&str, &str)]) -> String {
+    let mut ret =
+        format!("<INSTANCE CLASSNAME=\"{}\">", escape_xml(class_name));
+    for (name, value) in props {
+        ret.push_str(&format!(
+            "<PROPERTY NAME=\"{}\" \
+             TYPE=\"string\"><VALUE>{}</VALUE></PROPERTY>",
+            escape_xml(name),
+            escape_xml(value)
+        ));
+    }
+    ret.push_str("</INSTANCE>");
+    ret
+}
+
+impl HyperVWmi {
+    pub fn new() -> Self {
+        Self {
+            vm_id: None,
+            hooks: CommandHooks::new(),
+        }
+    }
+
+    impl_setter!(@opt
+        /// Sets the VMId of the VM to be manipulated.
+        vm_id: String
+    );
+
+    pub fn get_vm_id(&self) -> Option<&str> { self.vm_id.as_deref() }
+
+    impl_hooks!();
+
+    fn retrieve_vm(&self) -> VmResult<&str> {
+        self.vm_id
+            .as_deref()
+            .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
+    }
+
+    fn connect(&self) -> VmResult<WMIConnection> {
+        let com = COMLibrary::new().map_err(wmi_error)?;
+        WMIConnection::with_namespace_path(NAMESPACE, com).map_err(wmi_error)
+    }
+
+    fn query<T: DeserializeOwned>(
+        &self,
+        con: &WMIConnection,
+        query: String,
+    ) -> VmResult<Vec<T>> {
+        let args = [query];
+        self.hooks.run("ExecQuery", &args, || {
+            con.raw_query(&args[0]).map_err(wmi_error)
+        })
+    }
+
+    /// Calls `method` of the instance at `object_path` and waits for the job it started.
+    fn invoke(
+        &self,
+        con: &WMIConnection,
+        class_name: &str,
+        object_path: &str,
+        method: &str,
+        params: Vec<(&str, Variant)>,
+    ) -> VmResult<()> {
+        let args: Vec<String> = std::iter::once(object_path.to_string())
+            .chain(params.iter().map(|(k, v)| format!("{}={:?}", k, v)))
+            .collect();
+        self.hooks.run(method, &args, || {
+            let out = Self::exec_method(
+                con,
+                class_name,
+                object_path,
+                method,
+                params,
+            )?;
+            match Self::get_u32(&out, "ReturnValue")? {
+                RETURN_JOB_STARTED => {
+                    match out.get_property("Job").map_err(wmi_error)? {
+                        Variant::String(job) => self.wait_for_job(con, &job),
+                        x => vmerr!(ErrorKind::UnexpectedResponse(format!(
+                            "{:?}",
+                            x
+                        ))),
+                    }
+                }
+                v => check_return_value(method, v),
+            }
+        })
+    }
+
+    /// Calls `method` of the instance at `object_path` and returns its output parameters.
+    fn exec_method(
+        con: &WMIConnection,
+        class_name: &str,
+        object_path: &str,
+        method: &str,
+        params: Vec<(&str, Variant)>,
+    ) -> VmResult<IWbemClassWrapper> {
+        let params: HashMap<String, Variant> = params
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        con.exec_method_native_wrapper(class_name, object_path, method, params)
+            .map_err(wmi_error)?
+            .ok_or_else(|| {
+                VmError::from(ErrorKind::UnexpectedResponse(
+                    "No output parameters".to_string(),
+                ))
+            })
+    }
+
+    fn get_u32(obj: &IWbemClassWrapper, name: &str) -> VmResult<u32> {
+        match obj.get_property(name).map_err(wmi_error)? {
+            Variant::UI4(x) => Ok(x),
+            Variant::I4(x) => Ok(x as u32),
+            x => vmerr!(ErrorKind::UnexpectedResponse(format!("{:?}", x))),
+        }
+    }
+
+    fn wait_for_job(&self, con: &WMIConnection, job: &str) -> VmResult<()> {
+        loop {
+            let job: ConcreteJob = con.get_by_path(job).map_err(wmi_error)?;
+            match job.job_state {
+                // New, Starting, Running, Suspended or Shutting Down.
+                2..=6 => std::thread::sleep(Duration::from_millis(200)),
+                // Completed.
+                7 => return Ok(()),
+                _ => {
+                    return vmerr!(ErrorKind::ExecutionFailed(
+                        job.error_description.unwrap_or_default()
+                    ))
+                }
+            }
+        }
+    }
+
+    fn computer_system(&self, con: &WMIConnection) -> VmResult<ComputerSystem> {
+        let vm = self.retrieve_vm()?;
+        self.query::<ComputerSystem>(
+            con,
+            format!(
+                "SELECT * FROM Msvm_ComputerSystem WHERE Name = {}",
+                quote_and_escape_wql_str(vm)
+            ),
+        )?
+        .pop()
+        .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+
+    fn service_path(
+        &self,
+        con: &WMIConnection,
+        class_name: &str,
+    ) -> VmResult<String> {
+        self.query::<InstancePath>(
+            con,
+            format!("SELECT __Path FROM {}", class_name),
+        )?
+        .pop()
+        .map(|x| x.path)
+        .ok_or_else(|| VmError::from(ErrorKind::ServiceIsNotRunning))
+    }
+
+    fn system_setting_data(
+        &self,
+        con: &WMIConnection,
+    ) -> VmResult<VirtualSystemSettingData> {
+        let vm = self.retrieve_vm()?;
+        self.query::<VirtualSystemSettingData>(
+            con,
+            format!(
+                "SELECT * FROM Msvm_VirtualSystemSettingData WHERE \
+                 VirtualSystemIdentifier = {} AND VirtualSystemType = {}",
+                quote_and_escape_wql_str(vm),
+                quote_and_escape_wql_str(REALIZED_SYSTEM)
+            ),
+        )?
+        .pop()
+        .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+
+    /// Returns the current resolution of the console, or `None` if the VM is not running.
+    fn console_resolution(
+        &self,
+        con: &WMIConnection,
+    ) -> VmResult<Option<(u16, u16)>> {
+        let vm = self.retrieve_vm()?;
+        Ok(self
+            .query::<VideoHead>(
+                con,
+                format!(
+                    "SELECT * FROM Msvm_VideoHead WHERE SystemName = {}",
+                    quote_and_escape_wql_str(vm)
+                ),
+            )?
+            .into_iter()
+            .find_map(|x| {
+                match (
+                    x.current_horizontal_resolution,
+                    x.current_vertical_resolution,
+                ) {
+                    (Some(w), Some(h)) if w != 0 && h != 0 => Some((
+                        w.min(u16::MAX as u32) as u16,
+                        h.min(u16::MAX as u32) as u16,
+                    )),
+                    _ => None,
+                }
+            }))
+    }
+
+    /// Gets the console of the VM scaled to `width` x `height` with [GetVirtualSystemThumbnailImage](https://docs.microsoft.com/en-us/windows/win32/hyperv_v2/getvirtualsystemthumbnailimage-msvm-virtualsystemmanagementservice) as a PNG image.
+    pub fn get_thumbnail_image(
+        &self,
+        width: u16,
+        height: u16,
+    ) -> VmResult<Vec<u8>> {
+        let con = self.connect()?;
+        let vssd = self.system_setting_data(&con)?;
+        let service =
+            self.service_path(&con, "Msvm_VirtualSystemManagementService")?;
+        let args = [
+            vssd.path.clone(),
+            format!("WidthPixels={}", width),
+            format!("HeightPixels={}", height),
+        ];
+        let data =
+            self.hooks
+                .run("GetVirtualSystemThumbnailImage", &args, || {
+                    let out = Self::exec_method(
+                        &con,
+                        "Msvm_VirtualSystemManagementService",
+                        &service,
+                        "GetVirtualSystemThumbnailImage",
+                        vec![
+                            ("TargetSystem", Variant::String(vssd.path)),
+                            ("WidthPixels", Variant::UI2(width)),
+                            ("HeightPixels", Variant::UI2(height)),
+                        ],
+                    )?;
+                    check_return_value(
+                        "GetVirtualSystemThumbnailImage",
+                        Self::get_u32(&out, "ReturnValue")?,
+                    )?;
+                    match out.get_property("ImageData").map_err(wmi_error)? {
+                        Variant::Array(x) => x
+                            .into_iter()
+                            .map(|x| match x {
+                                Variant::UI1(x) => Ok(x),
+                                x => vmerr!(ErrorKind::UnexpectedResponse(
+                                    format!("{:?}", x)
+                                )),
+                            })
+                            .collect::<VmResult<Vec<u8>>>(),
+                        x => vmerr!(ErrorKind::UnexpectedResponse(format!(
+                            "{:?}",
+                            x
+                        ))),
+                    }
+                })?;
+        let pixels = width as usize * height as usize;
+        if data.len() < pixels * 2 {
+            return vmerr!(ErrorKind::UnexpectedResponse(format!(
+                "ImageData has {} bytes",
+                data.len()
+            )));
+        }
+        Ok(encode_png(
+            width as u32,
+            height as u32,
+            &rgb565_to_rgb(&data[..pixels * 2]),
+        ))
+    }
+
+    /// Returns the path of `Msvm_Keyboard` of the VM.
+    fn keyboard_path(&self, con: &WMIConnection) -> VmResult<String> {
+        let vm = self.retrieve_vm()?;
+        self.query::<InstancePath>(
+            con,
+            format!(
+                "SELECT __Path FROM Msvm_Keyboard WHERE SystemName = {}",
+                quote_and_escape_wql_str(vm)
+            ),
+        )?
+        .pop()
+        .map(|x| x.path)
+        .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+
+    /// Types ASCII `text` on the console with [TypeText](https://docs.microsoft.com/en-us/windows/win32/hyperv_v2/typetext-msvm-keyboard).
+    pub fn type_text(&self, text: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("type_text");
+        if !text.is_ascii() {
+            return vmerr!(ErrorKind::InvalidParameter(text.to_string()));
+        }
+        let con = self.connect()?;
+        let path = self.keyboard_path(&con)?;
+        self.invoke(
+            &con,
+            "Msvm_Keyboard",
+            &path,
+            "TypeText",
+            vec![("asciiText", Variant::String(text.to_string()))],
+        )
+    }
+
+    /// Presses `keys` in order with [PressKey](https://docs.microsoft.com/en-us/windows/win32/hyperv_v2/presskey-msvm-keyboard) and releases them in reverse order with `ReleaseKey`.
+    pub fn send_keys(&self, keys: &[Key]) -> VmResult<()> {
+        let _op = self.hooks.operation("send_keys");
+        let codes = keys
+            .iter()
+            .map(|k| {
+                k.virtual_key_code().ok_or_else(|| {
+                    VmError::from(ErrorKind::InvalidParameter(format!(
+                        "{:?} has no virtual-key code",
+                        k
+                    )))
+                })
+            })
+            .collect::<VmResult<Vec<u32>>>()?;
+        let con = self.connect()?;
+        let path = self.keyboard_path(&con)?;
+        let call = |method: &str, code: u32| {
+            self.invoke(
+                &con,
+                "Msvm_Keyboard",
+                &path,
+                method,
+                vec![("keyCode", Variant::UI4(code))],
+            )
+        };
+        for x in &codes {
+            call("PressKey", *x)?;
+        }
+        for x in codes.iter().rev() {
+            call("ReleaseKey", *x)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the power state of the VM.
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        let con = self.connect()?;
+        Ok(to_power_state(self.computer_system(&con)?.enabled_state))
+    }
+
+    /// Subscribes to the power state changes of the VM with WMI events instead of polling.
+    ///
+    /// The first event reports the current state.
+    /// The thread waiting for the events stops at the next event after the subscription is dropped.
+    pub fn subscribe_power_state(&self) -> VmResult<Subscription> {
+        let initial = self.get_power_state()?;
+        let args = [format!(
+            "SELECT * FROM __InstanceModificationEvent WITHIN 1 WHERE \
+             TargetInstance ISA 'Msvm_ComputerSystem' AND TargetInstance.Name \
+             = {}",
+            quote_and_escape_wql_str(self.retrieve_vm()?)
+        )];
+        let this = self.clone();
+        Ok(Subscription::spawn(move |tx, stop| {
+            let first = VmEvent::PowerStateChanged {
+                from: None,
+                to: initial,
+            };
+            if tx.send(first).is_err() {
+                return;
+            }
+            let con = match this.connect() {
+                Ok(x) => x,
+                Err(e) => {
+                    let _ = tx.send(VmEvent::Error(e));
+                    return;
+                }
+            };
+            let events = this.hooks.run("ExecNotificationQuery", &args, || {
+                con.raw_notification::<ComputerSystemModification>(&args[0])
+                    .map_err(wmi_error)
+            });
+            let events = match events {
+                Ok(x) => x,
+                Err(e) => {
+                    let _ = tx.send(VmEvent::Error(e));
+                    return;
+                }
+            };
+            for x in events {
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                let ev = match x {
+                    Ok(x) => {
+                        let from =
+                            to_power_state(x.previous_instance.enabled_state);
+                        let to =
+                            to_power_state(x.target_instance.enabled_state);
+                        if from == to {
+                            continue;
+                        }
+                        VmEvent::PowerStateChanged {
+                            from: Some(from),
+                            to,
+                        }
+                    }
+                    Err(e) => VmEvent::Error(wmi_error(e)),
+                };
+                if tx.send(ev).is_err() {
+                    return;
+                }
+            }
+        }))
+    }
+
+    /// Gets the detailed information of the VM.
+    ///
+    /// `memory_mb` is the startup memory, and `guest_os` and `tools_version` are not reported.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let con = self.connect()?;
+        let vssd = self.system_setting_data(&con)?;
+        let generation = vssd
+            .virtual_system_sub_type
+            .as_deref()
+            .and_then(to_generation);
+        Ok(VmInfo {
+            name: Some(vssd.element_name),
+            memory_mb: Some(self.memory_setting_data(&con)?.virtual_quantity),
+            cpu_count: Some(
+                self.processor_setting_data(&con)?.virtual_quantity as u32,
+            ),
+            firmware: generation.map(|x| {
+                if x >= 2 {
+                    Firmware::Efi
+                } else {
+                    Firmware::Bios
+                }
+            }),
+            generation,
+            power_state: Some(to_power_state(
+                self.computer_system(&con)?.enabled_state,
+            )),
+            config_path: vssd.configuration_data_root,
+            ..Default::default()
+        })
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+
+    /// Writes a `.rdp` file which connects to the console of the VM in an enhanced session.
+    ///
+    /// See [`rdp_file_content`](super::rdp_file_content) for details.
+    pub fn generate_rdp_file<P: AsRef<Path>>(&self, path: P) -> VmResult<()> {
+        let content = super::rdp_file_content(
+            &super::local_host_name(),
+            self.retrieve_vm()?,
+        );
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Requests the VM to change its state with [RequestStateChange](https://docs.microsoft.com/en-us/windows/win32/hyperv_v2/requeststatechange-msvm-computersystem).
+    pub fn request_state_change(&self, requested_state: u16) -> VmResult<()> {
+        let con = self.connect()?;
+        let cs = self.computer_system(&con)?;
+        self.invoke(
+            &con,
+            "Msvm_ComputerSystem",
+            &cs.path,
+            "RequestStateChange",
+            vec![("RequestedState", Variant::UI2(requested_state))],
+        )
+        .map_err(|e| match e.get_invalid_state() {
+            Some(_) => VmError::from(ErrorKind::InvalidPowerState(
+                to_power_state(cs.enabled_state),
+            )),
+            None => e,
+        })
+    }
+
+    fn snapshots(
+        &self,
+        con: &WMIConnection,
+    ) -> VmResult<Vec<VirtualSystemSettingData>> {
+        let vm = self.retrieve_vm()?;
+        self.query(
+            con,
+            format!(
+                "SELECT * FROM Msvm_VirtualSystemSettingData WHERE \
+                 VirtualSystemIdentifier = {} AND VirtualSystemType = {}",
+                quote_and_escape_wql_str(vm),
+                quote_and_escape_wql_str(REALIZED_SNAPSHOT)
+            ),
+        )
+    }
+
+    fn find_snapshot(
+        &self,
+        con: &WMIConnection,
+        name: &str,
+    ) -> VmResult<VirtualSystemSettingData> {
+        self.snapshots(con)?
+            .into_iter()
+            .find(|x| x.element_name == name)
+            .ok_or_else(|| VmError::from(ErrorKind::SnapshotNotFound))
+    }
+
+    fn processor_setting_data(
+        &self,
+        con: &WMIConnection,
+    ) -> VmResult<ProcessorSettingData> {
+        let vm = self.retrieve_vm()?;
+        self.query::<ProcessorSettingData>(
+            con,
+            format!(
+                "SELECT * FROM Msvm_ProcessorSettingData WHERE InstanceID \
+                 LIKE {}",
+                quote_and_escape_wql_str(format!("Microsoft:{}\\%", vm))
+            ),
+        )?
+        .pop()
+        .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+
+    fn memory_setting_data(
+        &self,
+        con: &WMIConnection,
+    ) -> VmResult<MemorySettingData> {
+        let vm = self.retrieve_vm()?;
+        self.query::<MemorySettingData>(
+            con,
+            format!(
+                "SELECT * FROM Msvm_MemorySettingData WHERE InstanceID LIKE {}",
+                quote_and_escape_wql_str(format!("Microsoft:{}\\%", vm))
+            ),
+        )?
+        .pop()
+        .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+}
+
+impl VmCmd for HyperVWmi {
+    fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        let con = self.connect()?;
+        Ok(self
+            .query::<VirtualSystemSettingData>(
+                &con,
+                format!(
+                    "SELECT * FROM Msvm_VirtualSystemSettingData WHERE \
+                     VirtualSystemType = {}",
+                    quote_and_escape_wql_str(REALIZED_SYSTEM)
+                ),
+            )?
+            .into_iter()
+            .map(|x| Vm {
+                id: Some(x.virtual_system_identifier),
+                name: Some(x.element_name),
+                path: None,
+            })
+            .collect())
+    }
+
+    /// `id` is VMId which can be obtained with `Get-VM|select VMId`.
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
+        for vm in self.list_vms()? {
+            if vm.id.as_deref().map_or(false, |x| uuid_eq(x, id)) {
+                self.vm_id(vm.id.clone());
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
+        for vm in self.list_vms()? {
+            if vm.name.as_deref() == Some(name) {
+                self.vm_id(vm.id.clone());
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    /// Due to the specification of Hyper-V, HyperVWmi does not support this function.
+    fn set_vm_by_path(&mut self, _: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_vm_by_path",
+            "Use set_vm_by_name or set_vm_by_id instead"
+        ))
+    }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
+}
+
+impl PowerCmd for HyperVWmi {
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            self.check_power_state(&[
+                VmPowerState::Stopped,
+                VmPowerState::Suspended,
+                VmPowerState::Paused,
+            ])?;
+            self.request_state_change(2)
+        })
+    }
+
+    /// Shuts down the guest OS with the Shutdown integration service.
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            let vm = self.retrieve_vm()?;
+            let con = self.connect()?;
+            let state =
+                to_power_state(self.computer_system(&con)?.enabled_state);
+            if state != VmPowerState::Running {
+                return vmerr!(ErrorKind::InvalidPowerState(state));
+            }
+            let sc = self
+                .query::<InstancePath>(
+                    &con,
+                    format!(
+                        "SELECT __Path FROM Msvm_ShutdownComponent WHERE \
+                         SystemName = {}",
+                        quote_and_escape_wql_str(vm)
+                    ),
+                )?
+                .pop()
+                .ok_or_else(|| VmError::from(ErrorKind::ServiceIsNotRunning))?;
+            self.invoke(
+                &con,
+                "Msvm_ShutdownComponent",
+                &sc.path,
+                "InitiateShutdown",
+                vec![
+                    ("Force", Variant::Bool(false)),
+                    ("Reason", Variant::String("hvctrl".to_string())),
+                ],
+            )?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
+    }
+
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Paused,
+            ])?;
+            self.request_state_change(3)
+        })
+    }
+
+    /// Saves the VM state.
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        self.check_power_state(&[VmPowerState::Running, VmPowerState::Paused])?;
+        self.request_state_change(6)
+    }
+
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        self.check_power_state(&[
+            VmPowerState::Suspended,
+            VmPowerState::Paused,
+        ])?;
+        self.request_state_change(2)
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()? == VmPowerState::Running)
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.stop(timeout)?;
+        self.start()
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.request_state_change(11)
+    }
+
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.request_state_change(9)
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.check_power_state(&[VmPowerState::Paused])?;
+        self.request_state_change(2)
+    }
+}
+
+impl SnapshotCmd for HyperVWmi {
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
+        let con = self.connect()?;
+        Ok(self
+            .snapshots(&con)?
+            .into_iter()
+            .map(|x| Snapshot {
+                id: Some(x.instance_id),
+                name: Some(x.element_name),
+                detail: None,
+                parent_id: None,
+                created_at: None,
+                is_current: None,
+                online: None,
+            })
+            .collect())
+    }
+
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        let con = self.connect()?;
+        let cs = self.computer_system(&con)?;
+        let service =
+            self.service_path(&con, "Msvm_VirtualSystemSnapshotService")?;
+        let before: Vec<String> = self
+            .snapshots(&con)?
+            .into_iter()
+            .map(|x| x.instance_id)
+            .collect();
+        self.invoke(
+            &con,
+            "Msvm_VirtualSystemSnapshotService",
+            &service,
+            "CreateSnapshot",
+            vec![
+                ("AffectedSystem", Variant::String(cs.path)),
+                // Full snapshot.
+                ("SnapshotType", Variant::UI2(2)),
+            ],
+        )?;
+        // CreateSnapshot names a snapshot after the VM and the current time, so renames it.
+        let created = self
+            .snapshots(&con)?
+            .into_iter()
+            .find(|x| !before.contains(&x.instance_id))
+            .ok_or_else(|| VmError::from(ErrorKind::SnapshotNotFound))?;
+        let service =
+            self.service_path(&con, "Msvm_VirtualSystemManagementService")?;
+        self.invoke(
+            &con,
+            "Msvm_VirtualSystemManagementService",
+            &service,
+            "ModifySystemSettings",
+            vec![(
+                "SystemSettings",
+                Variant::String(embedded_instance(
+                    "Msvm_VirtualSystemSettingData",
+                    &[
+                        ("InstanceID", &created.instance_id),
+                        ("ElementName", name),
+                    ],
+                )),
+            )],
+        )
+    }
+
+    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("revert_snapshot");
+        let con = self.connect()?;
+        let snapshot = self.find_snapshot(&con, name)?;
+        let service =
+            self.service_path(&con, "Msvm_VirtualSystemSnapshotService")?;
+        self.invoke(
+            &con,
+            "Msvm_VirtualSystemSnapshotService",
+            &service,
+            "ApplySnapshot",
+            vec![("Snapshot", Variant::String(snapshot.path))],
+        )
+    }
+
+    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
+        self.hooks.settle_deleted(|| {
+            let con = self.connect()?;
+            let snapshot = self.find_snapshot(&con, name)?;
+            let service =
+                self.service_path(&con, "Msvm_VirtualSystemSnapshotService")?;
+            self.invoke(
+                &con,
+                "Msvm_VirtualSystemSnapshotService",
+                &service,
+                "DestroySnapshot",
+                vec![("AffectedSnapshot", Variant::String(snapshot.path))],
+            )
+        })
+    }
+}
+
+/// The `wmi` crate cannot pass the arrays of embedded instances that `Msvm_GuestFileService` requires, so HyperVWmi does not support these functions.
+impl GuestCmd for HyperVWmi {
+    fn exec_cmd(&self, _guest_args: &[&str]) -> VmResult<()> {
+        let _op = self.hooks.operation("exec_cmd");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "exec_cmd",
+            "Use HyperVCmd instead"
+        ))
+    }
+
+    fn copy_from_guest_to_host(
+        &self,
+        _from_guest_path: &str,
+        _to_host_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_guest_to_host");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "copy_from_guest_to_host",
+            "Use HyperVCmd instead"
+        ))
+    }
+
+    fn copy_from_host_to_guest(
+        &self,
+        _from_host_path: &str,
+        _to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_host_to_guest");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "copy_from_host_to_guest",
+            "Use HyperVCmd instead"
+        ))
+    }
+}
+
+/// Captures the console at its current resolution with [`HyperVWmi::get_thumbnail_image`].
+impl ScreenshotCmd for HyperVWmi {
+    fn capture_screen(&self) -> VmResult<Vec<u8>> {
+        let _op = self.hooks.operation("capture_screen");
+        let (width, height) = self
+            .console_resolution(&self.connect()?)?
+            .unwrap_or(DEFAULT_THUMBNAIL_SIZE);
+        self.get_thumbnail_image(width, height)
+    }
+}
+
+impl KeyboardCmd for HyperVWmi {
+    fn type_text(&self, text: &str) -> VmResult<()> {
+        Self::type_text(self, text)
+    }
+
+    fn send_keys(&self, keys: &[Key]) -> VmResult<()> {
+        Self::send_keys(self, keys)
+    }
+}
+
+/// `reserve` and `maximum` are percentages, and `relative_weight` is the weight from 1 to 10000 as in `Set-VMProcessor`.
+impl ConfigCmd for HyperVWmi {
+    fn get_cpu_tuning(&self) -> VmResult<CpuTuning> {
+        let _op = self.hooks.operation("get_cpu_tuning");
+        let con = self.connect()?;
+        let psd = self.processor_setting_data(&con)?;
+        // Reservation and Limit are in units of 1/1000 of a percent.
+        Ok(CpuTuning {
+            count: Some(psd.virtual_quantity as u32),
+            reserve: Some((psd.reservation / 1000) as u32),
+            maximum: Some((psd.limit / 1000) as u32),
+            relative_weight: Some(psd.weight),
+        })
+    }
+
+    /// `Msvm_VirtualSystemManagementService.ModifyResourceSettings` takes an array of embedded instances, which the `wmi` crate cannot pass.
+    fn set_cpu_tuning(&self, _tuning: &CpuTuning) -> VmResult<()> {
+        let _op = self.hooks.operation("set_cpu_tuning");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_cpu_tuning",
+            "Use HyperVCmd instead"
+        ))
+    }
+
+    /// Returns the startup memory of the VM.
+    fn get_memory_size(&self) -> VmResult<u64> {
+        let _op = self.hooks.operation("get_memory_size");
+        let con = self.connect()?;
+        Ok(self.memory_setting_data(&con)?.virtual_quantity)
+    }
+
+    /// Unsupported for the same reason as `set_cpu_tuning`.
+    fn set_memory_size(&self, _size_mb: u64) -> VmResult<()> {
+        let _op = self.hooks.operation("set_memory_size");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_memory_size",
+            "Use HyperVCmd instead"
+        ))
+    }
+}
+
+#[test]
+fn test_to_generation() {
+    assert_eq!(Some(1), to_generation("Microsoft:Hyper-V:SubType:1"));
+    assert_eq!(Some(2), to_generation("Microsoft:Hyper-V:SubType:2"));
+    assert_eq!(None, to_generation(""));
+}
+
+#[test]
+fn test_to_power_state() {
+    assert_eq!(VmPowerState::Running, to_power_state(2));
+    assert_eq!(VmPowerState::Stopped, to_power_state(3));
+    assert_eq!(VmPowerState::Suspended, to_power_state(6));
+    assert_eq!(VmPowerState::Paused, to_power_state(9));
+    assert_eq!(VmPowerState::Unknown, to_power_state(32770));
+}
+
+#[test]
+fn test_rgb565_to_rgb() {
+    assert_eq!(
+        rgb565_to_rgb(&[0x00, 0xF8, 0xE0, 0x07, 0x1F, 0x00, 0x00]),
+        vec![255, 0, 0, 0, 255, 0, 0, 0, 255]
+    );
+}
+
+#[test]
+fn test_embedded_instance() {
+    assert_eq!(
+        "<INSTANCE CLASSNAME=\"Msvm_VirtualSystemSettingData\"><PROPERTY \
+         NAME=\"ElementName\" TYPE=\"string\"><VALUE>a&lt;b&gt; &amp; \
+         &quot;c&quot;</VALUE></PROPERTY></INSTANCE>",
+        embedded_instance(
+            "Msvm_VirtualSystemSettingData",
+            &[("ElementName", "a<b> & \"c\"")]
+        )
+    );
+}
@@ -0,0 +1,71 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! JSON output of the results of controllers.
+//!
+//! Every type returned by the list and info APIs implements [`Serialize`], so it can be converted with [`ToJson::to_json`].
+//! [`format_result`] formats a [`VmResult`] in the same shape as the responses of [`daemon`](crate::daemon).
+//!
+//! ```json
+//! {"ok": true, "result": [{"id": "...", "name": "MyVM", "path": null}]}
+//! {"ok": false, "error": "..."}
+//! ```
+use crate::types::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Converts a value into JSON.
+pub trait ToJson {
+    fn to_json(&self) -> VmResult<String>;
+    fn to_json_pretty(&self) -> VmResult<String>;
+}
+
+impl<T: Serialize + ?Sized> ToJson for T {
+    fn to_json(&self) -> VmResult<String> { Ok(serde_json::to_string(self)?) }
+
+    fn to_json_pretty(&self) -> VmResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Converts `r` into `{"ok": true, "result": ...}` or `{"ok": false, "error": "..."}`.
+pub fn result_to_value<T: Serialize>(r: &VmResult<T>) -> Value {
+    match r.as_ref().map(serde_json::to_value) {
+        Ok(Ok(x)) => json!({"ok": true, "result": x}),
+        Ok(Err(e)) => {
+            json!({"ok": false, "error": VmError::from(e).to_string()})
+        }
+        Err(e) => json!({"ok": false, "error": e.to_string()}),
+    }
+}
+
+/// Formats `r` as a single line of JSON.
+///
+/// See [`result_to_value`] for the format.
+pub fn format_result<T: Serialize>(r: &VmResult<T>, pretty: bool) -> String {
+    let v = result_to_value(r);
+    if pretty {
+        format!("{:#}", v)
+    } else {
+        v.to_string()
+    }
+}
+
+#[test]
+fn test_format_result() {
+    let vm = Vm {
+        name: Some("MyVM".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        format_result(&Ok(vec![vm]), false),
+        r#"{"ok":true,"result":[{"id":null,"name":"MyVM","path":null}]}"#
+    );
+    let r: VmResult<()> = vmerr!(ErrorKind::VmNotFound);
+    let v = result_to_value(&r);
+    assert_eq!(v["ok"], false);
+    assert_eq!(v["error"], vmerr!(@r ErrorKind::VmNotFound).to_string());
+    assert_eq!(
+        VmPowerState::Running.to_json(),
+        Ok(r#""Running""#.to_string())
+    );
+}
@@ -12,8 +12,51 @@
 //!     - [VBoxManage](https://www.virtualbox.org/manual/ch08.html)
 //! - [VMWare Workstation Player](https://www.vmware.com/products/workstation-player.html)
 //!     - [VMRest](https://code.vmware.com/apis/413)
+//!     - [vmcli](https://docs.vmware.com/en/VMware-Workstation-Pro/index.html)
+//! - [VMware vSphere (ESXi/vCenter)](https://www.vmware.com/products/vsphere.html)
+//!     - [vSphere Automation REST API](https://developer.vmware.com/apis/vsphere-automation/latest/)
 //! - [Hyper-V](https://docs.microsoft.com/en-us/virtualization/hyper-v-on-windows/about/)
 //!     - [Hyper-V cmdlets](https://docs.microsoft.com/en-us/powershell/module/hyper-v/?view=win10-ps)
+//!     - [Host Compute Service](https://docs.microsoft.com/en-us/virtualization/api/hcs/overview)
+//!     - [Hyper-V WMI provider](https://docs.microsoft.com/en-us/windows/win32/hyperv_v2/windows-virtualization-portal)
+//! - [QEMU](https://www.qemu.org/)
+//!     - [QMP](https://www.qemu.org/docs/master/interop/qemu-qmp-ref.html)
+//! - [Parallels Desktop](https://www.parallels.com/products/desktop/)
+//!     - [prlctl](https://download.parallels.com/desktop/v17/docs/en_US/Parallels%20Desktop%20Pro%20Edition%20Command-Line%20Reference.pdf)
+//! - [Cloud Hypervisor](https://www.cloudhypervisor.org/)
+//!     - [ch-remote](https://github.com/cloud-hypervisor/cloud-hypervisor/blob/main/docs/api.md)
+//!
+//! # JSON output
+//!
+//! The results of the list and info APIs implement `Serialize`, and [`json`] converts them and [`VmResult`](types::VmResult)s into JSON for scripts.
+//!
+//! # Desired state
+//!
+//! [`ensure::ensure`] applies a [`ensure::VmDesiredState`] such as the power state, a snapshot, NICs and shared folders to a VM, changing only what differs, and reports the changes.
+//!
+//! # Events
+//!
+//! [`events`] delivers the power state and snapshot changes of a VM over a channel.
+//!
+//! # Host networks
+//!
+//! [`hostnet::HostNetworkCmd`] creates, lists and deletes host-only, NAT and internal networks of the host.
+//!
+//! # Async
+//!
+//! The `async` feature provides async variants of the controller traits in [`asynchronous`].
+//!
+//! # Benchmark
+//!
+//! The `bench` feature provides [`bench`], which measures the latency of operations such as `is_running`, `list_vms` and `take_snapshot` to compare backends.
+//!
+//! # Daemon
+//!
+//! The `daemon` feature provides [`daemon::Daemon`], which exposes VM operations over a Unix domain socket or a named pipe with a small JSON protocol.
+//!
+//! # Server
+//!
+//! The `server` feature provides [`server::Server`], which exposes VM operations and guest operations over HTTP for remote orchestration.
 //!
 //! # License
 //!
@@ -21,17 +64,31 @@
 #[macro_use]
 pub mod types;
 
+pub mod asynchronous;
+pub mod bench;
+pub mod cloudhypervisor;
+pub mod daemon;
+pub mod ensure;
+pub mod events;
+pub mod hostnet;
 pub mod hyperv;
+pub mod json;
+pub mod parallels;
+pub mod qemu;
+pub mod server;
 pub mod virtualbox;
 pub mod vmware;
 
 #[macro_use]
 extern crate log;
 
-use crate::types::{ErrorKind, VmError, VmResult};
+use crate::types::{ErrorKind, Repr, VmError, VmResult};
 use log::Level;
 use serde::Deserialize;
-use std::{io::Write, process::Command};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, ExitStatus, Stdio},
+};
 #[cfg(windows)]
 use windy::AString;
 
@@ -41,23 +98,54 @@ pub(crate) fn deserialize<'a, T: Deserialize<'a>>(s: &'a str) -> VmResult<T> {
         .map_err(|x| vmerr!(@r ErrorKind::UnexpectedResponse(x.to_string())))
 }
 
+/// The output of a command executed with [`exec_cmd`].
+#[allow(dead_code)]
+#[derive(Debug)]
+pub(crate) struct CmdOutput {
+    pub(crate) stdout: String,
+    pub(crate) stderr: String,
+    pub(crate) status: ExitStatus,
+}
+
+impl CmdOutput {
+    /// Returns `r` as is if the command exited successfully.
+    ///
+    /// Otherwise, an unrecognized error or output is turned into [`ErrorKind::ExecutionFailed`] carrying the exit code.
+    #[allow(dead_code)]
+    pub(crate) fn check_status(&self, r: VmResult<String>) -> VmResult<String> {
+        if self.status.success() {
+            return r;
+        }
+        let msg = match r {
+            Ok(s) => s.trim().to_string(),
+            Err(e) => match e.get_repr() {
+                Repr::Unknown(s) => s.clone(),
+                _ => return Err(e),
+            },
+        };
+        Err(VmError::from(ErrorKind::ExecutionFailed(msg))
+            .with_exit_code(self.status.code()))
+    }
+}
+
 #[cfg(windows)]
 #[allow(dead_code)]
-pub(crate) fn exec_cmd_astr(cmd: &mut Command) -> VmResult<(String, String)> {
+pub(crate) fn exec_cmd_astr(cmd: &mut Command) -> VmResult<CmdOutput> {
     dbg_cmd(cmd);
     match cmd.output() {
         Ok(o) => unsafe {
-            Ok((
-                AString::new_unchecked(o.stdout).to_string_lossy(),
-                AString::new_unchecked(o.stderr).to_string_lossy(),
-            ))
+            Ok(CmdOutput {
+                stdout: AString::new_unchecked(o.stdout).to_string_lossy(),
+                stderr: AString::new_unchecked(o.stderr).to_string_lossy(),
+                status: o.status,
+            })
         },
         Err(x) => vmerr!(ErrorKind::ExecutionFailed(x.to_string())),
     }
 }
 
 #[allow(dead_code)]
-pub(crate) fn exec_cmd(cmd: &mut Command) -> VmResult<(String, String)> {
+pub(crate) fn exec_cmd(cmd: &mut Command) -> VmResult<CmdOutput> {
     #[cfg(windows)]
     {
         exec_cmd_astr(cmd)
@@ -68,21 +156,186 @@ pub(crate) fn exec_cmd(cmd: &mut Command) -> VmResult<(String, String)> {
     }
 }
 
-/// Executes `cmd` and Returns `(stdout, stderr)`.
+/// Executes `cmd` like [`exec_cmd`], calling `on_stderr` with each chunk of stderr as it is written.
+///
+/// Used for the progress which long-running commands write to stderr.
+#[allow(dead_code)]
+pub(crate) fn exec_cmd_with_stderr<F: FnMut(&[u8])>(
+    cmd: &mut Command,
+    mut on_stderr: F,
+) -> VmResult<CmdOutput> {
+    let failed = |x: std::io::Error| vmerr!(@r ErrorKind::ExecutionFailed(x.to_string()));
+    dbg_cmd(cmd);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    // Reads stdout in another thread so that the child doesn't block on a full pipe.
+    let mut stdout = child.stdout.take().unwrap();
+    let stdout = std::thread::spawn(move || {
+        let mut v = vec![];
+        stdout.read_to_end(&mut v).map(|_| v)
+    });
+    let mut stderr = vec![];
+    let mut err = child.stderr.take().unwrap();
+    let mut buf = [0; 256];
+    loop {
+        let n = err.read(&mut buf).map_err(failed)?;
+        if n == 0 {
+            break;
+        }
+        on_stderr(&buf[..n]);
+        stderr.extend_from_slice(&buf[..n]);
+    }
+    let status = child.wait().map_err(failed)?;
+    let stdout = stdout
+        .join()
+        .map_err(
+            |_| vmerr!(@r Repr::Unknown("Failed to read stdout".to_string())),
+        )?
+        .map_err(failed)?;
+    Ok(CmdOutput {
+        stdout: decode_output(stdout)?,
+        stderr: decode_output(stderr)?,
+        status,
+    })
+}
+
+/// Executes `cmd` like [`exec_cmd`], writing `input` to its stdin.
+///
+/// Used for passing secrets such as passwords, which should not appear in the arguments.
 #[allow(dead_code)]
-pub(crate) fn exec_cmd_utf8(cmd: &mut Command) -> VmResult<(String, String)> {
+pub(crate) fn exec_cmd_with_input(
+    cmd: &mut Command,
+    input: &[u8],
+) -> VmResult<CmdOutput> {
+    let failed = |x: std::io::Error| vmerr!(@r ErrorKind::ExecutionFailed(x.to_string()));
+    dbg_cmd(cmd);
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    // Closes stdin after writing so that the child reads EOF.
+    let written = child.stdin.take().unwrap().write_all(input);
+    let o = child.wait_with_output().map_err(failed)?;
+    // The child may exit without reading stdin.
+    if let Err(x) = written {
+        if x.kind() != std::io::ErrorKind::BrokenPipe {
+            return Err(failed(x));
+        }
+    }
+    Ok(CmdOutput {
+        stdout: decode_output(o.stdout)?,
+        stderr: decode_output(o.stderr)?,
+        status: o.status,
+    })
+}
+
+/// Executes `cmd` and calls `on_line` with each line written to stdout until it returns `false`, and then kills the child.
+///
+/// Used for the commands which keep writing their output until they are interrupted.
+/// `stdout` of the returned output is empty because the lines have been passed to `on_line`.
+#[allow(dead_code)]
+pub(crate) fn exec_cmd_lines<F: FnMut(&str) -> bool>(
+    cmd: &mut Command,
+    mut on_line: F,
+) -> VmResult<CmdOutput> {
+    let failed = |x: std::io::Error| vmerr!(@r ErrorKind::ExecutionFailed(x.to_string()));
+    dbg_cmd(cmd);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(failed)?;
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr = std::thread::spawn(move || {
+        let mut v = vec![];
+        stderr.read_to_end(&mut v).map(|_| v)
+    });
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = vec![];
+    loop {
+        line.clear();
+        if stdout.read_until(b'\n', &mut line).map_err(failed)? == 0 {
+            break;
+        }
+        let s = decode_output(line.clone())?;
+        if !on_line(s.trim_end_matches(|c| c == '\r' || c == '\n')) {
+            // The child may have exited by itself.
+            let _ = child.kill();
+            break;
+        }
+    }
+    let status = child.wait().map_err(failed)?;
+    let stderr = stderr
+        .join()
+        .map_err(
+            |_| vmerr!(@r Repr::Unknown("Failed to read stderr".to_string())),
+        )?
+        .map_err(failed)?;
+    Ok(CmdOutput {
+        stdout: String::new(),
+        stderr: decode_output(stderr)?,
+        status,
+    })
+}
+
+/// Decodes the output of a command in the ANSI code page on Windows and in UTF-8 otherwise.
+#[allow(dead_code)]
+fn decode_output(v: Vec<u8>) -> VmResult<String> {
+    #[cfg(windows)]
+    {
+        Ok(unsafe { AString::new_unchecked(v) }.to_string_lossy())
+    }
+    #[cfg(not(windows))]
+    {
+        String::from_utf8(v)
+            .map_err(|e| VmError::from(ErrorKind::FromUtf8Error(e)))
+    }
+}
+
+/// Executes `cmd` and Returns its stdout, stderr and exit status.
+#[allow(dead_code)]
+pub(crate) fn exec_cmd_utf8(cmd: &mut Command) -> VmResult<CmdOutput> {
     dbg_cmd(cmd);
     match cmd.output() {
-        Ok(o) => Ok((
-            String::from_utf8(o.stdout)
+        Ok(o) => Ok(CmdOutput {
+            stdout: String::from_utf8(o.stdout)
                 .map_err(|e| VmError::from(ErrorKind::FromUtf8Error(e)))?,
-            String::from_utf8(o.stderr)
+            stderr: String::from_utf8(o.stderr)
                 .map_err(|e| VmError::from(ErrorKind::FromUtf8Error(e)))?,
-        )),
+            status: o.status,
+        }),
         Err(x) => vmerr!(ErrorKind::ExecutionFailed(x.to_string())),
     }
 }
 
+/// Returns the arguments of `cmd` with the values following `secret_flags` masked.
+#[allow(dead_code)]
+pub(crate) fn sanitized_args(
+    cmd: &Command,
+    secret_flags: &[&str],
+) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut is_secret = false;
+    for arg in cmd.get_args() {
+        let arg = arg.to_string_lossy();
+        if is_secret {
+            ret.push("********".to_string());
+            is_secret = false;
+            continue;
+        }
+        is_secret = secret_flags.contains(&arg.as_ref());
+        ret.push(arg.into_owned());
+    }
+    ret
+}
+
 #[allow(dead_code)]
 pub(crate) fn get_filename(p: &str) -> &str {
     for (i, c) in p.chars().rev().enumerate() {
@@ -93,6 +346,240 @@ pub(crate) fn get_filename(p: &str) -> &str {
     p
 }
 
+/// Joins `args` into a Windows command line, quoting them as `CommandLineToArgvW` parses.
+#[allow(dead_code)]
+pub(crate) fn join_windows_args(args: &[&str]) -> String {
+    let mut ret = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i != 0 {
+            ret.push(' ');
+        }
+        if !arg.is_empty()
+            && !arg.contains(|c| c == ' ' || c == '\t' || c == '"')
+        {
+            ret.push_str(arg);
+            continue;
+        }
+        ret.push('"');
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            match c {
+                '\\' => backslashes += 1,
+                '"' => {
+                    ret.extend(
+                        std::iter::repeat('\\').take(backslashes * 2 + 1),
+                    );
+                    backslashes = 0;
+                }
+                _ => {
+                    ret.extend(std::iter::repeat('\\').take(backslashes));
+                    backslashes = 0;
+                }
+            }
+            if c != '\\' {
+                ret.push(c);
+            }
+        }
+        ret.extend(std::iter::repeat('\\').take(backslashes * 2));
+        ret.push('"');
+    }
+    ret
+}
+
+/// Joins `args` into a POSIX shell command line, quoting each of them with single quotes.
+#[allow(dead_code)]
+pub(crate) fn join_sh_args(args: &[&str]) -> String {
+    args.iter()
+        .map(|x| format!("'{}'", x.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the path to a tool set in the environment variable `path_var` (e.g., `HVCTRL_VMRUN_PATH`), or `default` if it is not set.
+#[allow(dead_code)]
+pub(crate) fn tool_path_from_env(path_var: &str, default: &str) -> String {
+    tool_path_from_env_or_dirs(path_var, &[], "", default)
+}
+
+/// Returns the path to a tool set in the environment variable `path_var`.
+///
+/// Otherwise, returns `file_name` in the directory set in the first of `dir_vars` (e.g., `VBOX_INSTALL_PATH`), or `default` if none of them is set.
+#[allow(dead_code)]
+pub(crate) fn tool_path_from_env_or_dirs(
+    path_var: &str,
+    dir_vars: &[&str],
+    file_name: &str,
+    default: &str,
+) -> String {
+    resolve_tool_path(
+        |x| std::env::var(x).ok(),
+        path_var,
+        dir_vars,
+        file_name,
+        default,
+    )
+}
+
+#[allow(dead_code)]
+fn resolve_tool_path<F: Fn(&str) -> Option<String>>(
+    get_var: F,
+    path_var: &str,
+    dir_vars: &[&str],
+    file_name: &str,
+    default: &str,
+) -> String {
+    let get = |x: &str| get_var(x).filter(|x| !x.trim().is_empty());
+    if let Some(x) = get(path_var) {
+        return x;
+    }
+    dir_vars
+        .iter()
+        .find_map(|x| get(x))
+        .map(|x| {
+            std::path::Path::new(&x)
+                .join(file_name)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Calls `f` with a path of a new file in the host temp directory, and returns the content `f` wrote to it.
+#[allow(dead_code)]
+pub(crate) fn read_host_temp_file<F: FnOnce(&str) -> VmResult<()>>(
+    name: &str,
+    f: F,
+) -> VmResult<Vec<u8>> {
+    let host_path = std::env::temp_dir().join(format!(
+        "hvctrl-{}-{}",
+        std::process::id(),
+        name
+    ));
+    let host_path = host_path.to_string_lossy();
+    let r = f(&host_path).and_then(|_| Ok(std::fs::read(host_path.as_ref())?));
+    let _ = std::fs::remove_file(host_path.as_ref());
+    r
+}
+
+/// Encodes 8-bit RGB pixels into a PNG image.
+///
+/// The image data is stored in uncompressed deflate blocks.
+#[allow(dead_code)]
+pub(crate) fn encode_png(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    fn chunk(png: &mut Vec<u8>, ty: &[u8], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(ty);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    let stride = width as usize * 3;
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in rgb.chunks(stride).take(height as usize) {
+        // Filter type None.
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut idat = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        idat.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        idat.push(blocks.peek().is_none() as u8);
+        idat.extend_from_slice(&len.to_le_bytes());
+        idat.extend_from_slice(&(!len).to_le_bytes());
+        idat.extend_from_slice(block);
+    }
+    idat.extend_from_slice(&adler32(&raw).to_be_bytes());
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8-bit depth, truecolor, deflate, adaptive filtering and no interlace.
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &idat);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &x in data {
+        a = (a + x as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Parses a timestamp such as `2021-01-02T03:04:05Z`, `2021-01-02T03:04:05.678+09:00` or `/Date(1609556645000)/` of PowerShell into seconds since the Unix epoch.
+///
+/// Returns `None` if the timestamp has no time zone.
+#[allow(dead_code)]
+pub(crate) fn parse_timestamp(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if let Some(x) = s.strip_prefix("/Date(").and_then(|x| x.strip_suffix(")/"))
+    {
+        // The milliseconds are in UTC even if an offset such as `+0900` follows.
+        let ms = x.split(|c| c == '+' || c == '-').next()?;
+        return ms.parse::<u64>().ok().map(|x| x / 1000);
+    }
+    let b = s.as_bytes();
+    if !s.is_ascii()
+        || b.len() < 20
+        || b[4] != b'-'
+        || b[7] != b'-'
+        || (b[10] != b'T' && b[10] != b' ')
+        || b[13] != b':'
+        || b[16] != b':'
+    {
+        return None;
+    }
+    let num = |x: &str| x.parse::<i64>().ok();
+    let (y, m, d) = (num(&s[0..4])?, num(&s[5..7])?, num(&s[8..10])?);
+    let secs =
+        num(&s[11..13])? * 3600 + num(&s[14..16])? * 60 + num(&s[17..19])?;
+    let offset = match s[19..]
+        .trim_start_matches(|c: char| c == '.' || c.is_ascii_digit())
+    {
+        "Z" | "z" => 0,
+        x if x.len() == 6 && x.as_bytes()[3] == b':' => {
+            let offset = num(&x[1..3])? * 3600 + num(&x[4..6])? * 60;
+            match &x[..1] {
+                "+" => offset,
+                "-" => -offset,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    // Days from 1970-01-01 to the date in the proleptic Gregorian calendar.
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+    u64::try_from(days * 86400 + secs - offset).ok()
+}
+
 #[allow(dead_code)]
 pub(crate) fn dbg_cmd(cmd: &Command) {
     if log_enabled!(Level::Debug) {
@@ -118,3 +605,140 @@ fn test_get_filename() {
     assert_eq!(get_filename(r"/home/user/test.txt"), "test.txt");
     assert_eq!(get_filename(r"/tmp/"), "");
 }
+
+#[test]
+fn test_sanitized_args() {
+    let mut cmd = Command::new("vmrun");
+    cmd.args(["-T", "ws", "-gu", "user", "-gp", "pass", "start", "a.vmx"]);
+    assert_eq!(
+        sanitized_args(&cmd, &["-gp", "-vp"]),
+        vec!["-T", "ws", "-gu", "user", "-gp", "********", "start", "a.vmx"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_check_status() {
+    use std::os::unix::process::ExitStatusExt;
+    let output = |raw| CmdOutput {
+        stdout: String::new(),
+        stderr: String::new(),
+        status: ExitStatus::from_raw(raw),
+    };
+    let ok = output(0);
+    assert_eq!(ok.check_status(Ok("a".to_string())), Ok("a".to_string()));
+    let failed = output(2 << 8);
+    let e = failed.check_status(Ok(" oops\n".to_string())).unwrap_err();
+    assert_eq!(
+        e.get_repr(),
+        &Repr::Simple(ErrorKind::ExecutionFailed("oops".to_string()))
+    );
+    assert_eq!(e.get_exit_code(), Some(2));
+    let e = failed
+        .check_status(vmerr!(Repr::Unknown("unknown".to_string())))
+        .unwrap_err();
+    assert_eq!(
+        e.get_repr(),
+        &Repr::Simple(ErrorKind::ExecutionFailed("unknown".to_string()))
+    );
+    assert_eq!(e.get_exit_code(), Some(2));
+    assert_eq!(
+        failed.check_status(vmerr!(ErrorKind::VmNotFound)),
+        vmerr!(ErrorKind::VmNotFound)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_cmd_with_input() {
+    let o = exec_cmd_with_input(&mut Command::new("cat"), b"secret").unwrap();
+    assert_eq!(o.stdout, "secret");
+    assert!(o.status.success());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_cmd_lines() {
+    let mut lines = vec![];
+    let o = exec_cmd_lines(&mut Command::new("yes"), |x| {
+        lines.push(x.to_string());
+        lines.len() < 3
+    })
+    .unwrap();
+    assert_eq!(lines, ["y", "y", "y"]);
+    assert!(!o.status.success());
+}
+
+#[test]
+fn test_join_windows_args() {
+    assert_eq!(
+        join_windows_args(&["cmd.exe", "/c", "dir"]),
+        "cmd.exe /c dir"
+    );
+    assert_eq!(
+        join_windows_args(&[r"C:\Program Files\a.exe", ""]),
+        r#""C:\Program Files\a.exe" """#
+    );
+    assert_eq!(join_windows_args(&[r#"a"b"#]), r#""a\"b""#);
+    assert_eq!(join_windows_args(&[r"a b\"]), r#""a b\\""#);
+}
+
+#[test]
+fn test_join_sh_args() {
+    assert_eq!(join_sh_args(&["echo", "a b"]), "'echo' 'a b'");
+    assert_eq!(join_sh_args(&["it's"]), r"'it'\''s'");
+}
+
+#[test]
+fn test_resolve_tool_path() {
+    let vars = |x: &str| match x {
+        "HVCTRL_A_PATH" => Some("/opt/a".to_string()),
+        "EMPTY" => Some(String::new()),
+        "A_INSTALL_PATH" => Some("/usr/lib/a".to_string()),
+        _ => None,
+    };
+    assert_eq!(
+        resolve_tool_path(vars, "HVCTRL_A_PATH", &["A_INSTALL_PATH"], "a", "a"),
+        "/opt/a"
+    );
+    assert_eq!(
+        resolve_tool_path(vars, "NONE", &["EMPTY", "A_INSTALL_PATH"], "a", "b"),
+        std::path::Path::new("/usr/lib/a")
+            .join("a")
+            .to_string_lossy()
+    );
+    assert_eq!(resolve_tool_path(vars, "EMPTY", &["NONE"], "a", "b"), "b");
+}
+
+#[test]
+fn test_parse_timestamp() {
+    assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(parse_timestamp("2021-01-02T03:04:05Z"), Some(1609556645));
+    assert_eq!(
+        parse_timestamp("2021-01-02T12:04:05.1234567+09:00"),
+        Some(1609556645)
+    );
+    assert_eq!(
+        parse_timestamp("2021-01-01 23:04:05-04:00"),
+        Some(1609556645)
+    );
+    assert_eq!(parse_timestamp("2024-02-29T00:00:00Z"), Some(1709164800));
+    assert_eq!(parse_timestamp("/Date(1609556645000)/"), Some(1609556645));
+    assert_eq!(
+        parse_timestamp("/Date(1609556645000+0900)/"),
+        Some(1609556645)
+    );
+    assert_eq!(parse_timestamp("2021-01-02T03:04:05"), None);
+    assert_eq!(parse_timestamp("unknown"), None);
+}
+
+#[test]
+fn test_encode_png() {
+    assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    let png = encode_png(2, 1, &[255, 0, 0, 0, 0, 255]);
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[12..16], b"IHDR");
+    assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+    assert_eq!(&png[png.len() - 12..], b"\0\0\0\0IEND\xAE\x42\x60\x82");
+}
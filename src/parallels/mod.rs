@@ -0,0 +1,9 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! Parallels Desktop controllers.
+
+#[cfg(feature = "prlctl")]
+pub mod prlctl;
+
+#[cfg(feature = "prlctl")]
+pub use prlctl::*;
@@ -0,0 +1,772 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! [prlctl](https://download.parallels.com/desktop/v17/docs/en_US/Parallels%20Desktop%20Pro%20Edition%20Command-Line%20Reference.pdf) controller.
+use crate::{
+    deserialize, exec_cmd, sanitized_args, tool_path_from_env, types::*,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Represents prlctl command executor.
+#[derive(Clone, Debug)]
+pub struct Prlctl {
+    executable_path: String,
+    vm_name: Option<String>,
+    guest_username: Option<String>,
+    guest_password: Option<String>,
+    hooks: CommandHooks,
+}
+
+impl Default for Prlctl {
+    fn default() -> Self { Self::new() }
+}
+
+/// Escapes an argument for POSIX shells.
+fn escape_sh(s: &str) -> String { format!("'{}'", s.replace('\'', r#"'\''"#)) }
+
+impl Prlctl {
+    pub fn new() -> Self {
+        Self {
+            executable_path: tool_path_from_env("HVCTRL_PRLCTL_PATH", "prlctl"),
+            vm_name: None,
+            guest_username: None,
+            guest_password: None,
+            hooks: CommandHooks::new(),
+        }
+    }
+
+    impl_setter!(
+        /// Sets the path to prlctl.
+        executable_path: String
+    );
+
+    pub fn get_executable_path(&self) -> &str { &self.executable_path }
+
+    impl_setter!(@opt
+        /// Sets the VM name or ID to be manipulated.
+        vm_name: String
+    );
+
+    pub fn get_vm_name(&self) -> Option<&str> { self.vm_name.as_deref() }
+
+    impl_setter!(@opt
+        /// Sets the guest username for `prlctl exec`.
+        guest_username: String
+    );
+
+    pub fn get_guest_username(&self) -> Option<&str> {
+        self.guest_username.as_deref()
+    }
+
+    impl_setter!(@opt
+        /// Sets the guest password for `prlctl exec`.
+        guest_password: String
+    );
+
+    pub fn get_guest_password(&self) -> Option<&str> {
+        self.guest_password.as_deref()
+    }
+
+    impl_hooks!();
+
+    fn build_auth(&self) -> Vec<&str> {
+        let mut v = Vec::with_capacity(4);
+        if let Some(x) = &self.guest_username {
+            v.extend(&["--user", x]);
+            if let Some(x) = &self.guest_password {
+                v.extend(&["--password", x]);
+            }
+        }
+        v
+    }
+
+    #[inline]
+    fn handle_error(s: &str) -> VmError {
+        use ErrorKind::*;
+        use VmPowerState::*;
+        if s.contains("could not be found") || s.contains("does not exist") {
+            return if s.contains("snapshot") {
+                VmError::from(SnapshotNotFound)
+            } else {
+                VmError::from(VmNotFound)
+            };
+        }
+        if s.contains("is already running") {
+            return VmError::from(InvalidPowerState(Running));
+        }
+        if s.contains("is not running") || s.contains("is stopped") {
+            return VmError::from(InvalidPowerState(NotRunning));
+        }
+        if s.contains("is suspended") {
+            return VmError::from(InvalidPowerState(Suspended));
+        }
+        if s.contains("Parallels Tools are not installed")
+            || s.contains("Parallels Tools are not running")
+        {
+            return VmError::from(ServiceIsNotRunning);
+        }
+        if s.contains("authentication") || s.contains("Unable to log in") {
+            return VmError::from(GuestAuthenticationFailed);
+        }
+        VmError::from(Repr::Unknown(format!("Unknown error: {}", s)))
+    }
+
+    /// Returns the operation name and the sanitized arguments passed to the hooks.
+    fn hook_args(cmd: &Command) -> (String, Vec<String>) {
+        let mut args = sanitized_args(cmd, &["--password"]);
+        let op = if args.is_empty() {
+            String::new()
+        } else {
+            args.remove(0)
+        };
+        (op, args)
+    }
+
+    fn exec(&self, cmd: &mut Command) -> VmResult<String> {
+        let (op, args) = Self::hook_args(cmd);
+        self.hooks.run(&op, &args, || {
+            let o = exec_cmd(cmd)?;
+            let errors =
+                self.hooks
+                    .classify_stderr(&op, &o.stderr, o.status.success());
+            o.check_status(if !errors.trim().is_empty() {
+                Err(Self::handle_error(errors.trim()))
+            } else {
+                Ok(o.stdout.clone())
+            })
+        })
+    }
+
+    /// Executes `cmd` with `input` as stdin and returns stdout as bytes.
+    fn exec_bytes(
+        &self,
+        cmd: &mut Command,
+        input: Option<&[u8]>,
+    ) -> VmResult<Vec<u8>> {
+        let (op, args) = Self::hook_args(cmd);
+        self.hooks.run(&op, &args, || {
+            crate::dbg_cmd(cmd);
+            let mut child = cmd
+                .stdin(if input.is_some() {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                })
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|x| {
+                    VmError::from(ErrorKind::ExecutionFailed(x.to_string()))
+                })?;
+            if let (Some(input), Some(mut stdin)) = (input, child.stdin.take())
+            {
+                stdin.write_all(input)?;
+            }
+            let o = child.wait_with_output()?;
+            let stderr = String::from_utf8_lossy(&o.stderr);
+            if !stderr.trim().is_empty() {
+                Err(Self::handle_error(stderr.trim()))
+            } else {
+                Ok(o.stdout)
+            }
+        })
+    }
+
+    #[inline]
+    fn cmd(&self) -> Command { Command::new(&self.executable_path) }
+
+    fn get_vm(&self) -> VmResult<&str> {
+        self.vm_name
+            .as_deref()
+            .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
+    }
+
+    /// Gets the prlctl version.
+    pub fn version(&self) -> VmResult<String> {
+        Ok(self.exec(self.cmd().arg("--version"))?.trim().to_string())
+    }
+
+    /// Gets a list of VMs.
+    pub fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        let s =
+            self.exec(self.cmd().args(["list", "--all", "--info", "--json"]))?;
+        #[derive(Deserialize)]
+        struct Response {
+            #[serde(alias = "ID")]
+            id: String,
+            #[serde(alias = "Name")]
+            name: String,
+            #[serde(alias = "Home")]
+            home: Option<String>,
+        }
+        Ok(deserialize::<Vec<Response>>(&s)?
+            .into_iter()
+            .map(|x| Vm {
+                id: Some(x.id),
+                name: Some(x.name),
+                path: x.home,
+            })
+            .collect())
+    }
+
+    /// Gets the power state of the VM.
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        let s = self.exec(self.cmd().args(["status", self.get_vm()?]))?;
+        Ok(parse_status(&s))
+    }
+
+    /// Gets the detailed information of the VM with `prlctl list --info --json`.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let s = self.exec(self.cmd().args([
+            "list",
+            "--info",
+            "--json",
+            self.get_vm()?,
+        ]))?;
+        parse_vm_info(&s)
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+
+    pub fn start_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["start", self.get_vm()?]))?;
+        Ok(())
+    }
+
+    /// Stops the VM.
+    ///
+    /// If `kill` is `true`, the VM is forcibly stopped.
+    pub fn stop_vm(&self, kill: bool) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["stop", self.get_vm()?]);
+        if kill {
+            cmd.arg("--kill");
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    pub fn reset_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["reset", self.get_vm()?]))?;
+        Ok(())
+    }
+
+    pub fn suspend_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["suspend", self.get_vm()?]))?;
+        Ok(())
+    }
+
+    pub fn pause_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["pause", self.get_vm()?]))?;
+        Ok(())
+    }
+
+    /// Resumes the suspended or paused VM.
+    pub fn resume_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["resume", self.get_vm()?]))?;
+        Ok(())
+    }
+
+    /// Inserts the image at `path` into the `cdrom0` drive and connects it.
+    pub fn set_cdrom_image(&self, path: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "set",
+            self.get_vm()?,
+            "--device-set",
+            "cdrom0",
+            "--image",
+            path,
+            "--connect",
+        ]))?;
+        Ok(())
+    }
+
+    /// Disconnects the `cdrom0` drive.
+    pub fn disconnect_cdrom(&self) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "set",
+            self.get_vm()?,
+            "--device-set",
+            "cdrom0",
+            "--disconnect",
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets a list of snapshots in order of creation.
+    pub fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
+        let s = self.exec(self.cmd().args([
+            "snapshot-list",
+            self.get_vm()?,
+            "--json",
+        ]))?;
+        parse_snapshot_list(&s)
+    }
+
+    pub fn take_snapshot(
+        &self,
+        name: &str,
+        description: Option<&str>,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        let mut cmd = self.cmd();
+        cmd.args(["snapshot", self.get_vm()?, "--name", name]);
+        if let Some(x) = description {
+            cmd.args(["--description", x]);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    fn get_snapshot_id(&self, name: &str) -> VmResult<String> {
+        self.list_snapshots()?
+            .into_iter()
+            .find(|x| x.name.as_deref() == Some(name))
+            .and_then(|x| x.id)
+            .ok_or_else(|| VmError::from(ErrorKind::SnapshotNotFound))
+    }
+
+    /// Reverts the VM to the snapshot named `name`.
+    pub fn switch_snapshot(&self, name: &str) -> VmResult<()> {
+        let id = self.get_snapshot_id(name)?;
+        self.exec(self.cmd().args([
+            "snapshot-switch",
+            self.get_vm()?,
+            "--id",
+            &id,
+        ]))?;
+        Ok(())
+    }
+
+    /// Deletes the snapshot named `name`.
+    pub fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
+        let id = self.get_snapshot_id(name)?;
+        self.exec(self.cmd().args([
+            "snapshot-delete",
+            self.get_vm()?,
+            "--id",
+            &id,
+        ]))?;
+        Ok(())
+    }
+
+    /// Executes a command in the guest and returns the output.
+    ///
+    /// Requires Parallels Tools.
+    pub fn exec_in_guest(&self, guest_args: &[&str]) -> VmResult<String> {
+        let mut cmd = self.cmd();
+        cmd.args(["exec", self.get_vm()?]);
+        cmd.args(self.build_auth());
+        cmd.args(guest_args);
+        self.exec(&mut cmd)
+    }
+
+    /// Copies a file from the guest to the host through `prlctl exec`.
+    ///
+    /// Due to the specification of prlctl, this function supports only guests that have `cat`.
+    pub fn copy_from(
+        &self,
+        from_guest_path: &str,
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["exec", self.get_vm()?]);
+        cmd.args(self.build_auth());
+        cmd.args(["cat", from_guest_path]);
+        let data = self.exec_bytes(&mut cmd, None)?;
+        std::fs::write(to_host_path, data)?;
+        Ok(())
+    }
+
+    /// Copies a file from the host to the guest through `prlctl exec`.
+    ///
+    /// Due to the specification of prlctl, this function supports only guests that have a POSIX shell.
+    pub fn copy_to(
+        &self,
+        from_host_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let data = std::fs::read(from_host_path)
+            .map_err(|_| VmError::from(ErrorKind::HostFileNotFound))?;
+        let mut cmd = self.cmd();
+        cmd.args(["exec", self.get_vm()?]);
+        cmd.args(self.build_auth());
+        cmd.args(["sh", "-c", &format!("cat > {}", escape_sh(to_guest_path))]);
+        self.exec_bytes(&mut cmd, Some(&data))?;
+        Ok(())
+    }
+}
+
+/// Parses the output of `prlctl status`, e.g., `VM MyVM exist running`.
+fn parse_status(s: &str) -> VmPowerState {
+    match s.split_whitespace().last() {
+        Some("running") => VmPowerState::Running,
+        Some("stopped") => VmPowerState::Stopped,
+        Some("suspended") => VmPowerState::Suspended,
+        Some("paused") => VmPowerState::Paused,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+/// Parses the output of `prlctl list --info --json <vm>`.
+///
+/// The memory size is reported as a string such as `2048Mb`.
+fn parse_vm_info(s: &str) -> VmResult<VmInfo> {
+    let v: Value = deserialize(s)?;
+    let v = match v.as_array() {
+        Some(x) => x.first().ok_or(ErrorKind::VmNotFound)?,
+        None => &v,
+    };
+    let hw = &v["Hardware"];
+    Ok(VmInfo {
+        name: v["Name"].as_str().map(|x| x.to_string()),
+        guest_os: v["OS"].as_str().map(|x| x.to_string()),
+        memory_mb: hw["memory"]["size"]
+            .as_str()
+            .and_then(|x| x.trim_end_matches("Mb").parse().ok()),
+        cpu_count: hw["cpu"]["cpus"].as_u64().map(|x| x as u32),
+        firmware: v["BIOS type"].as_str().map(|x| {
+            if x.starts_with("efi") {
+                Firmware::Efi
+            } else {
+                Firmware::Bios
+            }
+        }),
+        generation: None,
+        power_state: v["State"].as_str().map(parse_status),
+        config_path: v["Home"].as_str().map(|x| x.to_string()),
+        tools_version: v["GuestTools"]["version"]
+            .as_str()
+            .map(|x| x.to_string()),
+    })
+}
+
+/// Parses the output of `prlctl snapshot-list --json`.
+fn parse_snapshot_list(s: &str) -> VmResult<Vec<Snapshot>> {
+    #[derive(Deserialize)]
+    struct Response {
+        name: String,
+        #[serde(default)]
+        date: String,
+        #[serde(default)]
+        parent: String,
+        current: Option<bool>,
+        /// The power state of the VM when the snapshot was taken, e.g., `poweroff`.
+        state: Option<String>,
+    }
+    if s.trim().is_empty() {
+        // No snapshot.
+        return Ok(vec![]);
+    }
+    let resp = deserialize::<HashMap<String, Response>>(s)?;
+    let mut v: Vec<(String, Response)> = resp.into_iter().collect();
+    v.sort_by(|a, b| a.1.date.cmp(&b.1.date));
+    Ok(v.into_iter()
+        .map(|(id, x)| Snapshot {
+            id: Some(id),
+            name: Some(x.name),
+            detail: None,
+            parent_id: Some(x.parent).filter(|x| !x.is_empty()),
+            // `date` is in the local time of the host.
+            created_at: None,
+            is_current: x.current,
+            online: x.state.map(|x| x != "poweroff"),
+        })
+        .collect())
+}
+
+impl VmCmd for Prlctl {
+    fn list_vms(&self) -> VmResult<Vec<Vm>> { self.list_vms() }
+
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
+        for vm in self.list_vms()? {
+            if vm.id.as_deref().map_or(false, |x| uuid_eq(x, id)) {
+                self.vm_name = vm.id.clone();
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
+        for vm in self.list_vms()? {
+            if vm.name.as_deref() == Some(name) {
+                self.vm_name = vm.id.clone();
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    /// `path` is the path to a `pvm` bundle.
+    fn set_vm_by_path(&mut self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        let path = path.trim_end_matches('/');
+        for vm in self.list_vms()? {
+            if vm.path.as_deref().map(|x| x.trim_end_matches('/')) == Some(path)
+            {
+                self.vm_name = vm.id.clone();
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
+}
+
+impl PowerCmd for Prlctl {
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| match self.get_power_state()? {
+            VmPowerState::Running => {
+                vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running))
+            }
+            VmPowerState::Suspended | VmPowerState::Paused => self.resume_vm(),
+            _ => self.start_vm(),
+        })
+    }
+
+    /// Shuts down the guest OS gracefully.
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.check_power_state(&[VmPowerState::Running])?;
+            self.stop_vm(false)?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
+    }
+
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Paused,
+            ])?;
+            self.stop_vm(true)
+        })
+    }
+
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        self.check_power_state(&[VmPowerState::Running, VmPowerState::Paused])?;
+        self.suspend_vm()
+    }
+
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        self.check_power_state(&[
+            VmPowerState::Suspended,
+            VmPowerState::Paused,
+        ])?;
+        self.resume_vm()
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()?.is_running())
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.stop(timeout)?;
+        self.start_vm()
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.check_power_state(&[VmPowerState::Running, VmPowerState::Paused])?;
+        self.reset_vm()
+    }
+
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.pause_vm()
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.check_power_state(&[VmPowerState::Paused])?;
+        self.resume_vm()
+    }
+}
+
+impl SnapshotCmd for Prlctl {
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        Self::list_snapshots(self)
+    }
+
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        Self::take_snapshot(self, name, None)
+    }
+
+    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("revert_snapshot");
+        self.switch_snapshot(name)
+    }
+
+    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        self.hooks
+            .settle_deleted(|| Self::delete_snapshot(self, name))
+    }
+}
+
+/// Due to the specification of prlctl, supports only guests that have `mktemp` and `rm`.
+impl MediaCmd for Prlctl {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        self.set_cdrom_image(path)
+    }
+
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        self.disconnect_cdrom()
+    }
+}
+
+impl GuestTempDirCmd for Prlctl {
+    fn create_temp_dir_in_guest(&self) -> VmResult<String> {
+        let _op = self.hooks.operation("create_temp_dir_in_guest");
+        Ok(self
+            .exec_in_guest(&["mktemp", "-d", "-t", "hvctrl.XXXXXXXX"])?
+            .trim()
+            .to_string())
+    }
+
+    fn remove_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_dir_in_guest");
+        self.exec_in_guest(&["rm", "-rf", guest_path])?;
+        Ok(())
+    }
+}
+
+impl GuestCmd for Prlctl {
+    fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
+        let _op = self.hooks.operation("exec_cmd");
+        self.exec_in_guest(guest_args)?;
+        Ok(())
+    }
+
+    fn copy_from_guest_to_host(
+        &self,
+        from_guest_path: &str,
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_guest_to_host");
+        self.copy_from(from_guest_path, to_host_path)
+    }
+
+    fn copy_from_host_to_guest(
+        &self,
+        from_host_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_host_to_guest");
+        self.copy_to(from_host_path, to_guest_path)
+    }
+}
+
+#[test]
+fn test_parse_status() {
+    assert_eq!(
+        parse_status("VM MyVM exist running\n"),
+        VmPowerState::Running
+    );
+    assert_eq!(
+        parse_status("VM My VM exist stopped\n"),
+        VmPowerState::Stopped
+    );
+    assert_eq!(parse_status(""), VmPowerState::Unknown);
+}
+
+#[test]
+fn test_parse_vm_info() {
+    let s = r#"[
+  {
+    "ID": "{a1d8c5d2-0c6d-4a1e-9e40-2b3f0c1d2e3f}",
+    "Name": "Ubuntu",
+    "State": "running",
+    "OS": "ubuntu",
+    "Home": "/Users/user/Parallels/Ubuntu.pvm/",
+    "GuestTools": {"state": "installed", "version": "17.1.0-51516"},
+    "BIOS type": "efi-arm64",
+    "Hardware": {
+      "cpu": {"cpus": 2, "VT-x": true},
+      "memory": {"size": "2048Mb", "auto": "off"}
+    }
+  }
+]"#;
+    assert_eq!(
+        parse_vm_info(s).unwrap(),
+        VmInfo {
+            name: Some("Ubuntu".to_string()),
+            guest_os: Some("ubuntu".to_string()),
+            memory_mb: Some(2048),
+            cpu_count: Some(2),
+            firmware: Some(Firmware::Efi),
+            generation: None,
+            power_state: Some(VmPowerState::Running),
+            config_path: Some("/Users/user/Parallels/Ubuntu.pvm/".to_string()),
+            tools_version: Some("17.1.0-51516".to_string()),
+        }
+    );
+    assert!(parse_vm_info("[]").is_err());
+}
+
+#[test]
+fn test_parse_snapshot_list() {
+    let s = r#"{
+  "{b2d8c5d2-0c6d-4a1e-9e40-2b3f0c1d2e3f}": {
+    "name": "second",
+    "date": "2021-02-01 10:00:00",
+    "state": "poweroff",
+    "current": true,
+    "parent": "{a1d8c5d2-0c6d-4a1e-9e40-2b3f0c1d2e3f}"
+  },
+  "{a1d8c5d2-0c6d-4a1e-9e40-2b3f0c1d2e3f}": {
+    "name": "first",
+    "date": "2021-01-01 10:00:00",
+    "state": "poweroff",
+    "current": false,
+    "parent": ""
+  }
+}"#;
+    let v = parse_snapshot_list(s).unwrap();
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].name.as_deref(), Some("first"));
+    assert_eq!(
+        v[1].id.as_deref(),
+        Some("{b2d8c5d2-0c6d-4a1e-9e40-2b3f0c1d2e3f}")
+    );
+    assert_eq!(v[0].parent_id, None);
+    assert_eq!(v[1].parent_id, v[0].id);
+    assert_eq!(v[0].is_current, Some(false));
+    assert_eq!(v[1].is_current, Some(true));
+    assert_eq!(v[1].online, Some(false));
+    assert!(parse_snapshot_list("").unwrap().is_empty());
+    assert_eq!(escape_sh("a'b"), r#"'a'\''b'"#);
+}
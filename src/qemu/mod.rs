@@ -0,0 +1,9 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! QEMU controllers.
+
+#[cfg(feature = "qmp")]
+pub mod qmp;
+
+#[cfg(feature = "qmp")]
+pub use qmp::*;
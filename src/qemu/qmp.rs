@@ -0,0 +1,745 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! [QMP](https://www.qemu.org/docs/master/interop/qemu-qmp-ref.html) controller.
+use crate::{tool_path_from_env, types::*};
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// The message of the error returned when QEMU closes the QMP connection.
+const CONNECTION_CLOSED: &str = "The QMP connection was closed";
+
+trait Stream: std::io::Read + Write {}
+
+impl<T: std::io::Read + Write> Stream for T {}
+
+/// Represents a QEMU instance controlled over a QMP socket.
+///
+/// Each command opens a new connection to the QMP socket.
+#[derive(Clone, Debug)]
+pub struct Qmp {
+    executable_path: String,
+    address: String,
+    launch_args: Vec<String>,
+    read_timeout: Duration,
+    hooks: CommandHooks,
+}
+
+impl Default for Qmp {
+    fn default() -> Self { Self::new() }
+}
+
+impl Qmp {
+    pub fn new() -> Self {
+        Self {
+            executable_path: tool_path_from_env(
+                "HVCTRL_QEMU_PATH",
+                "qemu-system-x86_64",
+            ),
+            address: "tcp:127.0.0.1:4444".to_string(),
+            launch_args: vec![],
+            read_timeout: Duration::from_secs(5 * 60),
+            hooks: CommandHooks::new(),
+        }
+    }
+
+    impl_setter!(
+        /// Sets the path to `qemu-system-*` used by [`Qmp::launch`].
+        executable_path: String
+    );
+
+    pub fn get_executable_path(&self) -> &str { &self.executable_path }
+
+    impl_setter!(
+        /// Sets the address of the QMP socket in the `-qmp` syntax of QEMU, e.g., `tcp:127.0.0.1:4444` or `unix:/tmp/qmp.sock`.
+        address: String
+    );
+
+    pub fn get_address(&self) -> &str { &self.address }
+
+    /// Sets the arguments passed to `qemu-system-*` when [`Qmp::launch`] launches QEMU.
+    ///
+    /// `-qmp` is added automatically.
+    pub fn launch_args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.launch_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn get_launch_args(&self) -> &[String] { &self.launch_args }
+
+    impl_setter!(
+        /// Sets the maximum time to wait for a response from QEMU. The default is 5 minutes.
+        ///
+        /// A command which doesn't respond in time fails with [`ErrorKind::Timeout`].
+        read_timeout: Duration
+    );
+
+    pub fn get_read_timeout(&self) -> Duration { self.read_timeout }
+
+    impl_hooks!();
+
+    fn connect(&self) -> VmResult<Box<dyn Stream>> {
+        let to_err = |x: std::io::Error| match x.kind() {
+            std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::NotFound => VmError::from(
+                ErrorKind::InvalidPowerState(VmPowerState::Stopped),
+            ),
+            _ => VmError::from(ErrorKind::ExecutionFailed(x.to_string())),
+        };
+        if let Some(addr) = self.address.strip_prefix("tcp:") {
+            let s = TcpStream::connect(addr).map_err(to_err)?;
+            s.set_read_timeout(Some(self.read_timeout))?;
+            return Ok(Box::new(s));
+        }
+        #[cfg(unix)]
+        if let Some(path) = self.address.strip_prefix("unix:") {
+            let s = std::os::unix::net::UnixStream::connect(path)
+                .map_err(to_err)?;
+            s.set_read_timeout(Some(self.read_timeout))?;
+            return Ok(Box::new(s));
+        }
+        vmerr!(ErrorKind::InvalidParameter(self.address.clone()))
+    }
+
+    /// Reads a response, skipping asynchronous events.
+    fn read_response<R: BufRead>(r: &mut R) -> VmResult<Value> {
+        loop {
+            let mut s = String::new();
+            if r.read_line(&mut s).map_err(Self::io_error)? == 0 {
+                return vmerr!(ErrorKind::UnexpectedResponse(
+                    CONNECTION_CLOSED.to_string()
+                ));
+            }
+            let v: Value = serde_json::from_str(&s).map_err(|_| {
+                VmError::from(ErrorKind::UnexpectedResponse(s.clone()))
+            })?;
+            if v.get("event").is_none() {
+                return Ok(v);
+            }
+        }
+    }
+
+    /// Converts an error of the QMP connection.
+    fn io_error(x: std::io::Error) -> VmError {
+        use std::io::ErrorKind as K;
+        match x.kind() {
+            K::WouldBlock | K::TimedOut => VmError::from(ErrorKind::Timeout),
+            K::ConnectionReset
+            | K::ConnectionAborted
+            | K::BrokenPipe
+            | K::UnexpectedEof => VmError::from(ErrorKind::UnexpectedResponse(
+                CONNECTION_CLOSED.to_string(),
+            )),
+            _ => VmError::from(x),
+        }
+    }
+
+    /// Returns `true` if `e` is caused by QEMU closing the QMP connection.
+    fn is_connection_closed(e: &VmError) -> bool {
+        matches!(
+            e.get_repr(),
+            Repr::Simple(ErrorKind::UnexpectedResponse(x)) if x == CONNECTION_CLOSED
+        )
+    }
+
+    fn handle_error(v: &Value, command: &str) -> VmError {
+        let class = v["class"].as_str().unwrap_or_default();
+        let desc = v["desc"].as_str().unwrap_or_default();
+        if class == "CommandNotFound" {
+            return VmError::from(ErrorKind::unsupported::<Self>(command));
+        }
+        VmError::from(Repr::Unknown(format!("Unknown error: {}", desc)))
+    }
+
+    fn request<S: std::io::Read + Write>(
+        r: &mut BufReader<S>,
+        req: &Value,
+    ) -> VmResult<Value> {
+        writeln!(r.get_mut(), "{}", req).map_err(Self::io_error)?;
+        let resp = Self::read_response(r)?;
+        match resp.get("error") {
+            Some(e) => Err(Self::handle_error(
+                e,
+                req["execute"].as_str().unwrap_or_default(),
+            )),
+            None => Ok(resp["return"].clone()),
+        }
+    }
+
+    /// Executes a QMP command and returns the `return` value.
+    pub fn execute(
+        &self,
+        command: &str,
+        arguments: Option<Value>,
+    ) -> VmResult<Value> {
+        let args = match &arguments {
+            Some(x) => vec![x.to_string()],
+            None => vec![],
+        };
+        self.hooks.run(command, &args, || {
+            let mut r = BufReader::new(self.connect()?);
+            // Greeting.
+            Self::read_response(&mut r)?;
+            Self::request(&mut r, &json!({ "execute": "qmp_capabilities" }))?;
+            let req = match &arguments {
+                Some(x) => json!({ "execute": command, "arguments": x }),
+                None => json!({ "execute": command }),
+            };
+            Self::request(&mut r, &req)
+        })
+    }
+
+    /// Executes a HMP command and returns the output.
+    pub fn human_monitor_command(
+        &self,
+        command_line: &str,
+    ) -> VmResult<String> {
+        let v = self.execute(
+            "human-monitor-command",
+            Some(json!({ "command-line": command_line })),
+        )?;
+        Ok(v.as_str().unwrap_or_default().to_string())
+    }
+
+    /// Gets the status of the VM, e.g., `running`, `paused` or `shutdown`.
+    pub fn query_status(&self) -> VmResult<String> {
+        let v = self.execute("query-status", None)?;
+        match v["status"].as_str() {
+            Some(x) => Ok(x.to_string()),
+            None => vmerr!(ErrorKind::UnexpectedResponse(v.to_string())),
+        }
+    }
+
+    /// Gets the power state of the VM.
+    ///
+    /// If QEMU is not running, returns [`VmPowerState::Stopped`].
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        match self.query_status() {
+            Ok(x) => Ok(to_power_state(&x)),
+            Err(x) if x.get_invalid_state() == Some(VmPowerState::Stopped) => {
+                Ok(VmPowerState::Stopped)
+            }
+            Err(x) => Err(x),
+        }
+    }
+
+    /// Gets the name, the power state, the number of vCPUs and the memory size of the VM.
+    ///
+    /// Uses `query-name`, `query-status`, `query-cpus-fast` and `query-memory-size-summary`.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let name = self.execute("query-name", None)?;
+        let power_state = to_power_state(&self.query_status()?);
+        let cpus = self.execute("query-cpus-fast", None)?;
+        let memory = self.execute("query-memory-size-summary", None)?;
+        Ok(VmInfo {
+            name: name["name"].as_str().map(|x| x.to_string()),
+            power_state: Some(power_state),
+            cpu_count: cpus.as_array().map(|x| x.len() as u32),
+            memory_mb: memory["base-memory"]
+                .as_u64()
+                .map(|x| x / (1024 * 1024)),
+            ..VmInfo::default()
+        })
+    }
+
+    /// Launches QEMU with the arguments set by [`Qmp::launch_args`] and waits for the QMP socket to be ready.
+    ///
+    /// Fails if QEMU exits before the socket is ready, and kills QEMU if the socket isn't ready in 10 seconds.
+    /// QEMU keeps running after this function returns, and a background thread waits for it to exit.
+    pub fn launch(&self) -> VmResult<()> {
+        let mut cmd = Command::new(&self.executable_path);
+        cmd.args(&self.launch_args)
+            .arg("-qmp")
+            .arg(format!("{},server=on,wait=off", self.address))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        crate::dbg_cmd(&cmd);
+        let mut child = cmd.spawn().map_err(|x| {
+            VmError::from(ErrorKind::ExecutionFailed(x.to_string()))
+        })?;
+        let s = Instant::now();
+        while s.elapsed() < Duration::from_secs(10) {
+            if let Some(status) = child.try_wait()? {
+                return vmerr!(ErrorKind::ExecutionFailed(format!(
+                    "QEMU exited with {}",
+                    status
+                )));
+            }
+            if self.connect().is_ok() {
+                // Reap QEMU when it exits so that it doesn't remain as a zombie.
+                std::thread::spawn(move || child.wait());
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        vmerr!(ErrorKind::Timeout)
+    }
+
+    /// Gets a list of snapshots with `info snapshots`.
+    pub fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
+        Ok(parse_snapshots(
+            &self.human_monitor_command("info snapshots")?,
+        ))
+    }
+
+    fn exec_snapshot_command(&self, command: &str, name: &str) -> VmResult<()> {
+        let s = self.human_monitor_command(&format!("{} {}", command, name))?;
+        let s = s.trim();
+        if s.is_empty() {
+            return Ok(());
+        }
+        if s.contains("does not exist") || s.contains("not found") {
+            return vmerr!(ErrorKind::SnapshotNotFound);
+        }
+        vmerr!(Repr::Unknown(format!("Unknown error: {}", s)))
+    }
+
+    /// Saves the VM state to a snapshot named `name` with `savevm`.
+    pub fn save_vm(&self, name: &str) -> VmResult<()> {
+        self.exec_snapshot_command("savevm", name)
+    }
+
+    /// Loads the VM state from a snapshot named `name` with `loadvm`.
+    pub fn load_vm(&self, name: &str) -> VmResult<()> {
+        self.exec_snapshot_command("loadvm", name)
+    }
+
+    /// Deletes a snapshot named `name` with `delvm`.
+    pub fn del_vm(&self, name: &str) -> VmResult<()> {
+        self.exec_snapshot_command("delvm", name)
+    }
+
+    /// Returns the argument that identifies the first removable drive, e.g., `("device", "ide1-cd0")`.
+    fn find_removable_drive(&self) -> VmResult<(&'static str, String)> {
+        let v = self.execute("query-block", None)?;
+        find_removable_drive(&v).ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(
+                "The VM has no removable drive".to_string(),
+            ))
+        })
+    }
+
+    /// Inserts the image at `path` into the first removable drive with `blockdev-change-medium`.
+    pub fn change_medium(&self, path: &str) -> VmResult<()> {
+        let (key, name) = self.find_removable_drive()?;
+        self.execute(
+            "blockdev-change-medium",
+            Some(json!({ key: name, "filename": path, "format": "raw" })),
+        )?;
+        Ok(())
+    }
+
+    /// Ejects the medium from the first removable drive with `eject`.
+    pub fn eject_medium(&self) -> VmResult<()> {
+        let (key, name) = self.find_removable_drive()?;
+        self.execute("eject", Some(json!({ key: name, "force": true })))?;
+        Ok(())
+    }
+
+    fn is_running_result(&self) -> VmResult<()> {
+        match self.get_power_state()? {
+            VmPowerState::Running => Ok(()),
+            x => vmerr!(ErrorKind::InvalidPowerState(x)),
+        }
+    }
+}
+
+/// Parses the output of `info snapshots`.
+///
+/// Snapshots present on all disks have `--` as the ID and are identified by the tag.
+/// Partial snapshots listed after them can't be loaded and are omitted.
+fn parse_snapshots(s: &str) -> Vec<Snapshot> {
+    // ID        TAG               VM SIZE                DATE     VM CLOCK     ICOUNT
+    // --        snap 1             226 MiB 2021-01-01 00:00:00 00:00:10.000
+    // The tag can contain spaces and overflow its column, so the columns are found from the date.
+    let row = regex::Regex::new(
+        r"^(\S+)\s+(.+?)\s+(\d+(?:\.\d+)?)\s?([A-Za-z]*)\s+\d{4}-\d{2}-\d{2} ",
+    )
+    .unwrap();
+    s.lines()
+        .skip_while(|x| !x.trim_start().starts_with("ID"))
+        .skip(1)
+        .take_while(|x| !x.starts_with("List of "))
+        .filter_map(|x| {
+            let c = row.captures(x.trim())?;
+            let id = c.get(1)?.as_str();
+            // `VM SIZE` is 0 if the snapshot has no VM state, e.g., created by `qemu-img snapshot`.
+            let online = c[3].parse::<f64>().ok().map(|x| x > 0.0);
+            Some(Snapshot {
+                id: (id != "--").then(|| id.to_string()),
+                name: Some(c[2].to_string()),
+                detail: None,
+                parent_id: None,
+                created_at: None,
+                is_current: None,
+                online,
+            })
+        })
+        .collect()
+}
+
+impl PowerCmd for Qmp {
+    /// Resumes the VM if QEMU is running, otherwise launches QEMU.
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            match self.get_power_state()? {
+                VmPowerState::Running => {
+                    vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running))
+                }
+                VmPowerState::Stopped if self.query_status().is_err() => {
+                    self.launch()?;
+                    if self.get_power_state()? != VmPowerState::Running {
+                        self.execute("cont", None)?;
+                    }
+                    Ok(())
+                }
+                VmPowerState::Stopped => {
+                    // The guest was shut down with `-no-shutdown`.
+                    self.execute("system_reset", None)?;
+                    self.execute("cont", None)?;
+                    Ok(())
+                }
+                _ => {
+                    self.execute("cont", None)?;
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    /// Sends an ACPI shutdown signal with `system_powerdown`.
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.is_running_result()?;
+            self.execute("system_powerdown", None)?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
+    }
+
+    /// Terminates QEMU with `quit`.
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            // Fails with `InvalidPowerState(Stopped)` if QEMU is not running.
+            self.query_status()?;
+            match self.execute("quit", None) {
+                Ok(_) => {}
+                // QEMU may close the connection before responding.
+                Err(e) if Self::is_connection_closed(&e) => {}
+                Err(e) => return Err(e),
+            }
+            self.wait_for_stop(Some(Duration::from_secs(10)))
+        })
+    }
+
+    /// Due to the specification of QMP, Qmp does not support this function.
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "suspend",
+            "Use pause instead"
+        ))
+    }
+
+    /// Due to the specification of QMP, Qmp does not support this function.
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "resume",
+            "Use unpause instead"
+        ))
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()?.is_running())
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.stop(timeout)?;
+        self.start()
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.is_running_result()?;
+        self.execute("system_reset", None)?;
+        Ok(())
+    }
+
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.is_running_result()?;
+        self.execute("stop", None)?;
+        Ok(())
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        match self.get_power_state()? {
+            VmPowerState::Paused => {
+                self.execute("cont", None)?;
+                Ok(())
+            }
+            x => vmerr!(ErrorKind::InvalidPowerState(x)),
+        }
+    }
+}
+
+/// Uses the first removable drive, e.g., the drive added by `-cdrom`.
+impl MediaCmd for Qmp {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        self.change_medium(path)
+    }
+
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        self.eject_medium()
+    }
+}
+
+impl SnapshotCmd for Qmp {
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        Self::list_snapshots(self)
+    }
+
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        self.save_vm(name)
+    }
+
+    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("revert_snapshot");
+        if !self
+            .list_snapshots()?
+            .iter()
+            .any(|x| x.name.as_deref() == Some(name))
+        {
+            return vmerr!(ErrorKind::SnapshotNotFound);
+        }
+        self.load_vm(name)
+    }
+
+    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
+        self.hooks.settle_deleted(|| {
+            if !self
+                .list_snapshots()?
+                .iter()
+                .any(|x| x.name.as_deref() == Some(name))
+            {
+                return vmerr!(ErrorKind::SnapshotNotFound);
+            }
+            self.del_vm(name)
+        })
+    }
+}
+
+/// Finds the first removable drive in the result of `query-block`.
+///
+/// Drives created with `-blockdev` have no `device` and are identified by `id` of the qdev.
+fn find_removable_drive(v: &Value) -> Option<(&'static str, String)> {
+    let x = v
+        .as_array()?
+        .iter()
+        .find(|x| x["removable"].as_bool() == Some(true))?;
+    match x["device"].as_str() {
+        Some(device) if !device.is_empty() => {
+            Some(("device", device.to_string()))
+        }
+        _ => x["qdev"].as_str().map(|x| ("id", x.to_string())),
+    }
+}
+
+fn to_power_state(status: &str) -> VmPowerState {
+    match status {
+        "running" => VmPowerState::Running,
+        "paused" | "inmigrate" | "prelaunch" => VmPowerState::Paused,
+        "shutdown" => VmPowerState::Stopped,
+        "suspended" => VmPowerState::Suspended,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+#[test]
+fn test_find_removable_drive() {
+    let v = json!([
+        { "device": "ide0-hd0", "qdev": "/machine/unattached/device[22]", "removable": false },
+        { "device": "ide1-cd0", "qdev": "/machine/unattached/device[23]", "removable": true },
+    ]);
+    assert_eq!(
+        find_removable_drive(&v),
+        Some(("device", "ide1-cd0".to_string()))
+    );
+    let v = json!([{ "device": "", "qdev": "cd0", "removable": true }]);
+    assert_eq!(find_removable_drive(&v), Some(("id", "cd0".to_string())));
+    assert_eq!(find_removable_drive(&json!([])), None);
+}
+
+#[test]
+fn test_parse_snapshots() {
+    let s = "List of snapshots present on all disks:\r\n\
+             ID        TAG               VM SIZE                DATE     VM CLOCK     ICOUNT\r\n\
+             --        snap1              226 MiB 2021-01-01 00:00:00 00:00:10.000\r\n\
+             --        snap 2             226 MiB 2021-01-01 00:00:00 00:00:10.000\r\n";
+    let v = parse_snapshots(s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].id, None);
+    assert_eq!(v[0].name.as_deref(), Some("snap1"));
+    assert_eq!(v[1].name.as_deref(), Some("snap 2"));
+    assert_eq!(v[1].online, Some(true));
+    let s = [
+        "List of snapshots present on all disks:",
+        "ID        TAG               VM SIZE                DATE     VM CLOCK",
+        "--        a-very-long-snapshot-name      0 B 2021-01-01 00:00:00 \
+         00:00:00.000",
+        "",
+        "List of partial (non-loadable) snapshots on 'disk1':",
+        "ID        TAG               VM SIZE                DATE     VM CLOCK",
+        "1         partial            0 B 2021-01-01 00:00:00 00:00:00.000",
+    ]
+    .join("\n");
+    let v = parse_snapshots(&s);
+    assert_eq!(v.len(), 1);
+    assert_eq!(v[0].name.as_deref(), Some("a-very-long-snapshot-name"));
+    assert_eq!(v[0].online, Some(false));
+    let s = "ID        TAG                 VM SIZE                DATE       \
+             VM CLOCK\n1         snap1                  226M 2021-01-01 \
+             00:00:00   00:00:10.000\n2         snap2                  226M \
+             2021-01-01 00:00:00   00:00:20.000\n";
+    let v = parse_snapshots(s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].id.as_deref(), Some("1"));
+    assert_eq!(v[1].name.as_deref(), Some("snap2"));
+    assert_eq!(v[1].online, Some(true));
+    assert!(parse_snapshots("There is no snapshot available.").is_empty());
+}
+
+#[test]
+fn test_execute() {
+    use std::net::TcpListener;
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = l.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        for resp in [
+            r#"{"return": {"status": "paused", "running": false}}"#,
+            r#"{"error": {"class": "CommandNotFound", "desc": "not found"}}"#,
+        ] {
+            let (s, _) = l.accept().unwrap();
+            let mut r = BufReader::new(s);
+            writeln!(r.get_mut(), r#"{{"QMP": {{"version": {{}}}}}}"#).unwrap();
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            writeln!(r.get_mut(), r#"{{"return": {{}}}}"#).unwrap();
+            r.read_line(&mut line).unwrap();
+            writeln!(r.get_mut(), r#"{{"event": "STOP"}}"#).unwrap();
+            writeln!(r.get_mut(), "{}", resp).unwrap();
+        }
+    });
+    let mut cmd = Qmp::new();
+    cmd.address(format!("tcp:{}", addr));
+    assert_eq!(cmd.get_power_state(), Ok(VmPowerState::Paused));
+    assert_eq!(
+        cmd.execute("foo", None),
+        vmerr!(ErrorKind::unsupported::<Qmp>("foo"))
+    );
+    server.join().unwrap();
+}
+
+#[test]
+fn test_hard_stop() {
+    use std::net::{TcpListener, TcpStream};
+    // Responds to qmp_capabilities and returns the next command.
+    fn handshake(s: TcpStream) -> BufReader<TcpStream> {
+        let mut r = BufReader::new(s);
+        writeln!(r.get_mut(), r#"{{"QMP": {{"version": {{}}}}}}"#).unwrap();
+        let mut line = String::new();
+        r.read_line(&mut line).unwrap();
+        writeln!(r.get_mut(), r#"{{"return": {{}}}}"#).unwrap();
+        r.read_line(&mut line).unwrap();
+        r
+    }
+    const RUNNING: &str = r#"{"return": {"status": "running"}}"#;
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut cmd = Qmp::new();
+    cmd.address(format!("tcp:{}", l.local_addr().unwrap()));
+    let server = std::thread::spawn(move || {
+        let mut r = handshake(l.accept().unwrap().0);
+        writeln!(r.get_mut(), "{}", RUNNING).unwrap();
+        // QEMU closes the connection without responding to quit.
+        let s = l.accept().unwrap().0;
+        drop(l);
+        handshake(s);
+    });
+    assert_eq!(cmd.hard_stop(), Ok(()));
+    server.join().unwrap();
+
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    cmd.address(format!("tcp:{}", l.local_addr().unwrap()));
+    cmd.on_before_command(|_, args| match args[0].as_str() {
+        "quit" => vmerr!(ErrorKind::PrivilegesRequired),
+        _ => Ok(()),
+    });
+    let server = std::thread::spawn(move || {
+        let mut r = handshake(l.accept().unwrap().0);
+        writeln!(r.get_mut(), "{}", RUNNING).unwrap();
+    });
+    assert_eq!(cmd.hard_stop(), vmerr!(ErrorKind::PrivilegesRequired));
+    server.join().unwrap();
+}
+
+#[test]
+fn test_get_vm_info() {
+    use std::net::TcpListener;
+    let l = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = l.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        for resp in [
+            r#"{"return": {"name": "vm1"}}"#,
+            r#"{"return": {"status": "running", "running": true}}"#,
+            r#"{"return": [{"cpu-index": 0}, {"cpu-index": 1}]}"#,
+            r#"{"return": {"base-memory": 2147483648}}"#,
+        ] {
+            let (s, _) = l.accept().unwrap();
+            let mut r = BufReader::new(s);
+            writeln!(r.get_mut(), r#"{{"QMP": {{"version": {{}}}}}}"#).unwrap();
+            let mut line = String::new();
+            r.read_line(&mut line).unwrap();
+            writeln!(r.get_mut(), r#"{{"return": {{}}}}"#).unwrap();
+            r.read_line(&mut line).unwrap();
+            writeln!(r.get_mut(), "{}", resp).unwrap();
+        }
+    });
+    let mut cmd = Qmp::new();
+    cmd.address(format!("tcp:{}", addr));
+    let info = cmd.get_vm_info().unwrap();
+    server.join().unwrap();
+    assert_eq!(info.name.as_deref(), Some("vm1"));
+    assert_eq!(info.power_state, Some(VmPowerState::Running));
+    assert_eq!(info.cpu_count, Some(2));
+    assert_eq!(info.memory_mb, Some(2048));
+}
@@ -0,0 +1,603 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! An HTTP server which exposes VM operations to remote hosts.
+//!
+//! [`Server`] turns a host into a node which can be driven by an orchestrator over the network.
+//! Requests and responses are JSON, and responses have the same shape as [`Response`] of the daemon.
+//!
+//! # Routes
+//!
+//! | Method | Path | Operation |
+//! |--------|------|-----------|
+//! | GET | `/vms` | `list_vms` |
+//! | GET | `/vms/{vm}/power` | `is_running` |
+//! | POST | `/vms/{vm}/power/{op}` | `start`, `stop`, `hard_stop`, `suspend`, `resume`, `reboot`, `hard_reboot`, `pause` or `unpause` |
+//! | GET | `/vms/{vm}/snapshots` | `list_snapshots` |
+//! | POST | `/vms/{vm}/snapshots` | `take_snapshot` |
+//! | POST | `/vms/{vm}/snapshots/{name}/revert` | `revert_snapshot` |
+//! | DELETE | `/vms/{vm}/snapshots/{name}` | `delete_snapshot` |
+//! | POST | `/vms/{vm}/exec` | `exec_cmd` |
+//! | POST | `/vms/{vm}/copy_from_guest` | `copy_from_guest_to_host` |
+//! | POST | `/vms/{vm}/copy_to_guest` | `copy_from_host_to_guest` |
+//!
+//! Parameters are passed in the JSON body: `name` for `take_snapshot`, `timeout` in seconds for `stop` and `reboot`, `args` for `exec` and `from` and `to` for copies.
+//! The host paths of copies are relative to the directory set by [`Server::host_root`].
+//!
+//! ```text
+//! POST /vms/MyVM/exec HTTP/1.1
+//! Authorization: Bearer secret
+//! Content-Type: application/json
+//! Content-Length: 33
+//!
+//! {"args": ["cmd", "/c", "whoami"]}
+//! ```
+//!
+//! Operations which succeed return 200, and operations which fail return 500.
+//!
+//! # Security
+//!
+//! Every request must send the token set by [`Server::token`], and [`Server::serve`] refuses to start without one.
+//! `POST` and `DELETE` requests must be `application/json`, and requests with an `Origin` header are rejected, so web pages can't send requests to the server.
+//! Copies are refused unless [`Server::host_root`] is set.
+//! Connections are served one at a time, and reading a request or writing a response times out after [`IO_TIMEOUT`], so an idle client can't hold the server.
+//! The server doesn't support TLS, so bind it to a trusted network.
+#![cfg(feature = "server")]
+use crate::{
+    daemon::{Daemon, Request, Response},
+    types::*,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+/// The default address of [`Server`].
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// The maximum size of the request line and each header line.
+const MAX_LINE_SIZE: u64 = 8 * 1024;
+
+/// The read and write timeout of a connection.
+pub const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+const POWER_OPS: &[&str] = &[
+    "start",
+    "stop",
+    "hard_stop",
+    "suspend",
+    "resume",
+    "reboot",
+    "hard_reboot",
+    "pause",
+    "unpause",
+];
+
+#[derive(Debug, Default, Deserialize)]
+struct Body {
+    name: Option<String>,
+    timeout: Option<u64>,
+    args: Option<Vec<String>>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Serves HTTP requests with a controller.
+///
+/// Connections are served one by one, so operations never run concurrently.
+#[derive(Clone, Debug)]
+pub struct Server<C> {
+    cmd: C,
+    daemon: Daemon<C>,
+    address: String,
+    token: Option<String>,
+    host_root: Option<PathBuf>,
+}
+
+impl<C> Server<C>
+where
+    C: VmCmd + PowerCmd + SnapshotCmd + GuestCmd + Clone,
+{
+    /// Creates a server which serves requests with clones of `cmd`.
+    pub fn new(cmd: C) -> Self {
+        Self {
+            daemon: Daemon::new(cmd.clone()),
+            cmd,
+            address: DEFAULT_ADDRESS.to_string(),
+            token: None,
+            host_root: None,
+        }
+    }
+
+    impl_setter!(
+        /// Sets the address to listen on.
+        address: String
+    );
+
+    pub fn get_address(&self) -> &str { &self.address }
+
+    impl_setter!(@opt
+        /// Sets the token which clients must send in the `Authorization: Bearer` header.
+        ///
+        /// The token is required. Requests are rejected while it is `None`.
+        token: String
+    );
+
+    impl_setter!(@opt
+        /// Sets the host directory which the host paths of copies are relative to.
+        ///
+        /// Copies are refused while it is `None`.
+        host_root: PathBuf
+    );
+
+    pub fn get_host_root(&self) -> Option<&Path> { self.host_root.as_deref() }
+
+    /// Handles a request and returns the status code and the response.
+    pub fn handle(
+        &self,
+        method: &str,
+        path: &str,
+        body: &[u8],
+    ) -> (u16, Response) {
+        let body: Body = if body.iter().all(u8::is_ascii_whitespace) {
+            Body::default()
+        } else {
+            match serde_json::from_slice(body) {
+                Ok(x) => x,
+                Err(e) => {
+                    return (
+                        400,
+                        Response::error(format!("Invalid body: {}", e)),
+                    )
+                }
+            }
+        };
+        let path = path.split('?').next().unwrap_or_default();
+        let segs: Vec<String> = path
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(percent_decode)
+            .collect();
+        let segs: Vec<&str> = segs.iter().map(|x| x.as_str()).collect();
+        let req = |op: &str, vm: &str, name: Option<String>| Request {
+            op: op.to_string(),
+            vm: Some(vm.to_string()),
+            name,
+            timeout: body.timeout,
+        };
+        let req = match (method, segs.as_slice()) {
+            ("GET", ["vms"]) => Request {
+                op: "list_vms".to_string(),
+                ..Default::default()
+            },
+            ("GET", ["vms", vm, "power"]) => req("is_running", vm, None),
+            ("POST", ["vms", vm, "power", op]) if POWER_OPS.contains(op) => {
+                req(op, vm, None)
+            }
+            ("GET", ["vms", vm, "snapshots"]) => {
+                req("list_snapshots", vm, None)
+            }
+            ("POST", ["vms", vm, "snapshots"]) => {
+                req("take_snapshot", vm, body.name.clone())
+            }
+            ("POST", ["vms", vm, "snapshots", name, "revert"]) => {
+                req("revert_snapshot", vm, Some(name.to_string()))
+            }
+            ("DELETE", ["vms", vm, "snapshots", name]) => {
+                req("delete_snapshot", vm, Some(name.to_string()))
+            }
+            ("POST", ["vms", vm, "exec"]) => {
+                return self.guest(vm, |cmd| {
+                    let args = body.args.as_ref().ok_or_else(|| {
+                        VmError::from(ErrorKind::InvalidParameter(
+                            "args".to_string(),
+                        ))
+                    })?;
+                    let args: Vec<&str> =
+                        args.iter().map(|x| x.as_str()).collect();
+                    cmd.exec_cmd(&args)
+                });
+            }
+            ("POST", ["vms", vm, "copy_from_guest"]) => {
+                return self.guest(vm, |cmd| {
+                    let (from, to) = from_to(&body)?;
+                    cmd.copy_from_guest_to_host(from, &self.host_path(to)?)
+                });
+            }
+            ("POST", ["vms", vm, "copy_to_guest"]) => {
+                return self.guest(vm, |cmd| {
+                    let (from, to) = from_to(&body)?;
+                    cmd.copy_from_host_to_guest(&self.host_path(from)?, to)
+                });
+            }
+            _ => return (404, Response::error("Not found".to_string())),
+        };
+        with_status(self.daemon.handle(&req))
+    }
+
+    fn guest<F>(&self, vm: &str, f: F) -> (u16, Response)
+    where
+        F: FnOnce(&C) -> VmResult<()>,
+    {
+        let mut cmd = self.cmd.clone();
+        let r = cmd.set_vm_by_name(vm).and_then(|_| f(&cmd));
+        with_status(Response::from_result(r.map(|_| Value::Null)))
+    }
+
+    /// Resolves the host path `path` of a copy in the host root.
+    ///
+    /// `path` must be relative and can't contain `..`, and its parent directory must not leave the host root through symbolic links.
+    fn host_path(&self, path: &str) -> VmResult<String> {
+        let root = self
+            .host_root
+            .as_ref()
+            .ok_or_else(|| VmError::from(ErrorKind::PermissionDenied))?;
+        let invalid =
+            || VmError::from(ErrorKind::InvalidParameter(path.to_string()));
+        let rel = Path::new(path);
+        if rel.as_os_str().is_empty()
+            || !rel
+                .components()
+                .all(|x| matches!(x, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(invalid());
+        }
+        let root = root.canonicalize()?;
+        let ret = root.join(rel);
+        let parent = ret.parent().ok_or_else(invalid)?.canonicalize()?;
+        if !parent.starts_with(&root) {
+            return Err(invalid());
+        }
+        ret.to_str().map(|x| x.to_string()).ok_or_else(invalid)
+    }
+
+    fn is_authorized(&self, authorization: Option<&str>) -> bool {
+        match (&self.token, authorization) {
+            (Some(token), Some(x)) => x
+                .strip_prefix("Bearer ")
+                .map_or(false, |x| constant_time_eq(x.trim(), token)),
+            _ => false,
+        }
+    }
+
+    /// Serves a request read from `stream`.
+    pub fn serve_stream<S: Read + Write>(&self, stream: S) -> VmResult<()> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        read_line(&mut reader, &mut line)?;
+        let mut it = line.split_whitespace();
+        let (method, path) = (
+            it.next().unwrap_or_default().to_string(),
+            it.next().unwrap_or_default().to_string(),
+        );
+        let mut length = 0;
+        let mut authorization = None;
+        let mut content_type = None;
+        let mut has_origin = false;
+        loop {
+            line.clear();
+            if read_line(&mut reader, &mut line)? == 0 || line.trim().is_empty()
+            {
+                break;
+            }
+            if let Some((k, v)) = line.split_once(':') {
+                if k.eq_ignore_ascii_case("content-length") {
+                    length = v.trim().parse().unwrap_or(usize::MAX);
+                } else if k.eq_ignore_ascii_case("authorization") {
+                    authorization = Some(v.trim().to_string());
+                } else if k.eq_ignore_ascii_case("content-type") {
+                    content_type = Some(v.trim().to_ascii_lowercase());
+                } else if k.eq_ignore_ascii_case("origin") {
+                    has_origin = true;
+                }
+            }
+        }
+        let is_json = content_type.as_deref().map_or(false, |x| {
+            x.split(';').next().map(str::trim) == Some("application/json")
+        });
+        let (status, resp) = if method.is_empty() || path.is_empty() {
+            (400, Response::error("Invalid request".to_string()))
+        } else if !self.is_authorized(authorization.as_deref()) {
+            (401, Response::error("Unauthorized".to_string()))
+        } else if has_origin {
+            (
+                403,
+                Response::error(
+                    "Requests from browsers are rejected".to_string(),
+                ),
+            )
+        } else if method != "GET" && !is_json {
+            (
+                415,
+                Response::error(
+                    "Content-Type must be application/json".to_string(),
+                ),
+            )
+        } else if length > MAX_BODY_SIZE {
+            (413, Response::error("Too large body".to_string()))
+        } else {
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            self.handle(&method, &path, &body)
+        };
+        let s = serde_json::to_string(&resp)?;
+        let w = reader.get_mut();
+        write!(
+            w,
+            "HTTP/1.1 {} {}\r\nContent-Type: \
+             application/json\r\nContent-Length: {}\r\nConnection: \
+             close\r\n\r\n{}",
+            status,
+            reason(status),
+            s.len(),
+            s
+        )?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Listens on the address and serves requests forever.
+    ///
+    /// Fails without listening if the token is not set.
+    pub fn serve(&self) -> VmResult<()> {
+        if self.token.as_deref().map_or(true, str::is_empty) {
+            return vmerr!(ErrorKind::InvalidParameter(
+                "token must be set".to_string()
+            ));
+        }
+        let listener = TcpListener::bind(&self.address)?;
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Failed to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = stream
+                .set_read_timeout(Some(IO_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(IO_TIMEOUT)))
+            {
+                warn!("Failed to set the timeout of a connection: {}", e);
+                continue;
+            }
+            if let Err(e) = self.serve_stream(&stream) {
+                warn!("Failed to serve a connection: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a line of up to [`MAX_LINE_SIZE`] bytes.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> VmResult<usize> {
+    let n = reader.take(MAX_LINE_SIZE).read_line(line)?;
+    if n as u64 == MAX_LINE_SIZE && !line.ends_with('\n') {
+        return vmerr!(ErrorKind::InvalidParameter(
+            "Too long line".to_string()
+        ));
+    }
+    Ok(n)
+}
+
+/// Compares `a` and `b` in time which doesn't depend on where they differ.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let diff = (0..a.len().max(b.len())).fold(a.len() ^ b.len(), |d, i| {
+        d | (a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0))
+            as usize
+    });
+    diff == 0
+}
+
+fn from_to(body: &Body) -> VmResult<(&str, &str)> {
+    fn get<'a>(x: &'a Option<String>, name: &str) -> VmResult<&'a str> {
+        x.as_deref().ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(name.to_string()))
+        })
+    }
+    Ok((get(&body.from, "from")?, get(&body.to, "to")?))
+}
+
+fn with_status(resp: Response) -> (u16, Response) {
+    (if resp.ok { 200 } else { 500 }, resp)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Decodes `%XX` sequences in a path segment.
+fn percent_decode(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut ret = Vec::with_capacity(b.len());
+    let mut i = 0;
+    while i < b.len() {
+        let x = b
+            .get(i + 1..i + 3)
+            .filter(|_| b[i] == b'%')
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match x {
+            Some(x) => {
+                ret.push(x);
+                i += 3;
+            }
+            None => {
+                ret.push(b[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&ret).into_owned()
+}
+
+#[cfg(test)]
+impl GuestCmd for crate::daemon::FakeCmd {
+    fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
+        if guest_args.is_empty() {
+            return vmerr!(ErrorKind::InvalidParameter("args".to_string()));
+        }
+        Ok(())
+    }
+
+    fn copy_from_guest_to_host(&self, _: &str, _: &str) -> VmResult<()> {
+        Ok(())
+    }
+
+    fn copy_from_host_to_guest(&self, _: &str, _: &str) -> VmResult<()> {
+        vmerr!(ErrorKind::FileError("not found".to_string()))
+    }
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("My%20VM"), "My VM");
+    assert_eq!(percent_decode("a%2"), "a%2");
+    assert_eq!(percent_decode("%zz"), "%zz");
+}
+
+#[test]
+fn test_constant_time_eq() {
+    assert!(constant_time_eq("secret", "secret"));
+    assert!(!constant_time_eq("secret", "secreT"));
+    assert!(!constant_time_eq("secret", "secret2"));
+    assert!(!constant_time_eq("", "secret"));
+}
+
+#[test]
+fn test_host_path() {
+    let root = std::env::temp_dir()
+        .join(format!("hvctrl-server-test-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("sub")).unwrap();
+    let mut server = Server::new(crate::daemon::FakeCmd::default());
+    assert_eq!(server.host_path("a"), vmerr!(ErrorKind::PermissionDenied));
+    server.host_root(Some(root.clone()));
+    let root = root.canonicalize().unwrap();
+    assert_eq!(
+        server.host_path("sub/a").unwrap(),
+        root.join("sub/a").to_str().unwrap()
+    );
+    for x in ["", "../a", "sub/../../a", "/etc/passwd", "none/a"] {
+        assert!(server.host_path(x).is_err(), "{}", x);
+    }
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_handle() {
+    let mut server = Server::new(crate::daemon::FakeCmd::default());
+    server.host_root(Some(std::env::temp_dir()));
+    let status = |m, p, b: &str| server.handle(m, p, b.as_bytes()).0;
+    assert_eq!(status("GET", "/vms", ""), 200);
+    assert_eq!(status("POST", "/vms/vm1/power/start", ""), 200);
+    let (_, resp) = server.handle("GET", "/vms/vm1/power", b"");
+    assert_eq!(resp.result, Some(Value::Bool(true)));
+    assert_eq!(status("POST", "/vms/vm1/power/unknown", ""), 404);
+    assert_eq!(status("POST", "/vms/vm2/power/start", ""), 500);
+    assert_eq!(
+        status("POST", "/vms/vm1/snapshots", "{\"name\": \"s\"}"),
+        200
+    );
+    assert_eq!(status("POST", "/vms/vm1/snapshots", ""), 500);
+    assert_eq!(status("DELETE", "/vms/vm1/snapshots/s", ""), 500);
+    assert_eq!(status("POST", "/vms/vm1/exec", "{\"args\": [\"ls\"]}"), 200);
+    assert_eq!(status("POST", "/vms/vm1/exec", "{}"), 500);
+    assert_eq!(
+        status(
+            "POST",
+            "/vms/vm1/copy_from_guest",
+            "{\"from\": \"a\", \"to\": \"b\"}"
+        ),
+        200
+    );
+    assert_eq!(
+        status("POST", "/vms/vm1/copy_to_guest", "{\"from\": \"a\"}"),
+        500
+    );
+    assert_eq!(
+        status(
+            "POST",
+            "/vms/vm1/copy_from_guest",
+            "{\"from\": \"a\", \"to\": \"../b\"}"
+        ),
+        500
+    );
+    assert_eq!(status("POST", "/vms/vm1/exec", "not json"), 400);
+}
+
+#[test]
+fn test_serve_stream() {
+    struct Duplex(std::io::Cursor<Vec<u8>>, Vec<u8>);
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+    let serve = |server: &Server<_>, input: &str| {
+        let mut s =
+            Duplex(std::io::Cursor::new(input.as_bytes().to_vec()), vec![]);
+        server.serve_stream(&mut s).unwrap();
+        String::from_utf8(s.1).unwrap()
+    };
+    let mut server = Server::new(crate::daemon::FakeCmd::default());
+    let auth = "Authorization: Bearer secret\r\n";
+    let exec = |headers: &str| {
+        [
+            "POST /vms/vm1/exec HTTP/1.1",
+            headers,
+            "Content-Length: 16\r\n",
+            "{\"args\": [\"ls\"]}",
+        ]
+        .join("\r\n")
+    };
+    let json = "Content-Type: application/json; charset=utf-8";
+    // Requests are rejected without the token.
+    assert!(server.serve().is_err());
+    let out = serve(&server, &exec(json));
+    assert!(out.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    server.token(Some("secret".to_string()));
+    let out = serve(&server, &exec(&format!("{}{}", auth, json)));
+    assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(out.ends_with("\r\n\r\n{\"ok\":true,\"result\":null}"));
+    let out = serve(&server, &exec(json));
+    assert!(out.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    let out =
+        serve(&server, &exec(&format!("{}Content-Type: text/plain", auth)));
+    assert!(out.starts_with("HTTP/1.1 415 Unsupported Media Type\r\n"));
+    let out = serve(
+        &server,
+        &exec(&format!("{}{}\r\nOrigin: https://example.com", auth, json)),
+    );
+    assert!(out.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+    let out = serve(&server, &format!("GET /vms HTTP/1.1\r\n{}\r\n", auth));
+    assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+    let mut s = Duplex(
+        std::io::Cursor::new(
+            format!("GET /{} HTTP/1.1", "a".repeat(10000)).into_bytes(),
+        ),
+        vec![],
+    );
+    assert!(server.serve_stream(&mut s).is_err());
+}
@@ -3,13 +3,21 @@
 #![allow(unused_macros)]
 use crate::vmerr;
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use std::string::FromUtf8Error;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct VmError {
     repr: Repr,
+    exit_code: Option<i32>,
 }
 
 impl std::error::Error for VmError {}
@@ -30,9 +38,25 @@ impl VmError {
 
     pub fn get_repr(&self) -> &Repr { &self.repr }
 
+    /// Gets the exit code of the command that failed, if any.
+    pub fn get_exit_code(&self) -> Option<i32> { self.exit_code }
+
+    pub(crate) fn with_exit_code(mut self, code: Option<i32>) -> Self {
+        self.exit_code = code;
+        self
+    }
+
     pub fn is_invalid_state_running(&self) -> Option<bool> {
         self.get_invalid_state().map(|x| x.is_running())
     }
+
+    /// Returns `true` if the error is [`ErrorKind::UnsupportedCommand`].
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            &self.repr,
+            Repr::Simple(ErrorKind::UnsupportedCommand { .. })
+        )
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -69,14 +93,57 @@ pub enum ErrorKind {
     /// The specified action was not completed in time.
     Timeout,
     UnexpectedResponse(String),
-    UnsupportedCommand,
+    /// The operation is not supported by the controller.
+    UnsupportedCommand {
+        /// The unsupported operation, e.g., `set_vm_by_path`, or the message of the tool if the tool rejected the command.
+        operation: String,
+        /// The controller, e.g., `HyperVCmd`.
+        backend: String,
+        /// The alternative to use instead, if any.
+        hint: Option<String>,
+    },
     VmIsNotSpecified,
     CredentialIsNotSpecified,
     VmNotFound,
 }
 
+impl ErrorKind {
+    /// Creates [`ErrorKind::UnsupportedCommand`] for `operation` of the controller `T`.
+    pub fn unsupported<T: ?Sized>(operation: &str) -> Self {
+        Self::UnsupportedCommand {
+            operation: operation.to_string(),
+            backend: backend_name::<T>(),
+            hint: None,
+        }
+    }
+
+    /// Creates [`ErrorKind::UnsupportedCommand`] for `operation` of the controller `T` with `hint`.
+    pub fn unsupported_with_hint<T: ?Sized>(
+        operation: &str,
+        hint: &str,
+    ) -> Self {
+        Self::UnsupportedCommand {
+            operation: operation.to_string(),
+            backend: backend_name::<T>(),
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+/// Returns the name of the type `T` without the module path and the type parameters.
+fn backend_name<T: ?Sized>() -> String {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name).to_string()
+}
+
 impl From<Repr> for VmError {
-    fn from(repr: Repr) -> Self { Self { repr } }
+    fn from(repr: Repr) -> Self {
+        Self {
+            repr,
+            exit_code: None,
+        }
+    }
 }
 
 impl From<std::io::Error> for VmError {
@@ -91,6 +158,7 @@ impl From<ErrorKind> for VmError {
     fn from(e: ErrorKind) -> Self {
         Self {
             repr: Repr::Simple(e),
+            exit_code: None,
         }
     }
 }
@@ -115,23 +183,405 @@ macro_rules! starts_err {
     };
 }
 
+/// A callback called before a command is executed.
+///
+/// Returning an error prevents the command from being executed.
+pub type BeforeCommandHook =
+    dyn Fn(&str, &[String]) -> VmResult<()> + Send + Sync;
+
+/// A callback called after a command is executed.
+///
+/// The last argument is the error if the command failed.
+pub type AfterCommandHook =
+    dyn Fn(&str, &[String], Option<&VmError>) + Send + Sync;
+
+/// A callback called when a command writes a warning to stderr.
+///
+/// The arguments are the operation name and the warning line.
+pub type WarningHook = dyn Fn(&str, &str) + Send + Sync;
+
+thread_local! {
+    static OPERATION: std::cell::Cell<Option<&'static str>> =
+        std::cell::Cell::new(None);
+    static WARNINGS: std::cell::RefCell<Option<Vec<CommandWarning>>> =
+        std::cell::RefCell::new(None);
+}
+
+/// Represents a warning reported by [`CommandHooks::report_warning`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CommandWarning {
+    /// The operation name. See [`CommandHooks`].
+    pub operation: String,
+    pub message: String,
+}
+
+/// Calls `f` and returns its result with the warnings reported on the current thread while `f` runs.
+///
+/// Controllers return only the result of each operation, so use this to get the non-fatal warnings of an operation, e.g., `collect_warnings(|| cmd.take_snapshot("snap1"))`.
+/// The warnings are also logged and passed to the warning callbacks.
+pub fn collect_warnings<T, F: FnOnce() -> T>(f: F) -> (T, Vec<CommandWarning>) {
+    let outer = WARNINGS.with(|x| x.borrow_mut().replace(vec![]));
+    let ret = f();
+    let warnings = WARNINGS.with(|x| {
+        let mut x = x.borrow_mut();
+        let warnings = x.take().unwrap_or_default();
+        // A nested call also reports the warnings to the outer call.
+        *x = outer.map(|mut outer| {
+            outer.extend(warnings.iter().cloned());
+            outer
+        });
+        warnings
+    });
+    (ret, warnings)
+}
+
+/// Returns the operation set by [`CommandHooks::operation`] on the current thread.
+pub fn current_operation() -> Option<&'static str> {
+    OPERATION.with(|x| x.get())
+}
+
+/// Resets the operation set by [`CommandHooks::operation`] when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct OperationGuard {
+    set: bool,
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        if self.set {
+            OPERATION.with(|x| x.set(None));
+        }
+    }
+}
+
+/// Represents callbacks called before and after each command.
+///
+/// The callbacks receive the operation name and the arguments of the command.
+/// The operation name is the name of the method of the controller, which is the same for all backends (e.g., `start` and `delete_vm`).
+/// The first argument is the command of the underlying tool (e.g., `startvm` for VBoxManage, `Start-VM` for Hyper-V cmdlets and `DELETE /api/vms/{id}` for VMRest).
+/// A command run by a method which does not set an operation uses the command as the operation name.
+/// Passwords in the arguments are masked.
+///
+/// It also holds the idempotent mode of the controller (see [`CommandHooks::idempotent`]) and the patterns to classify stderr (see [`CommandHooks::classify_stderr`]).
+#[derive(Clone, Default)]
+pub struct CommandHooks {
+    before: Vec<Arc<BeforeCommandHook>>,
+    after: Vec<Arc<AfterCommandHook>>,
+    warning: Vec<Arc<WarningHook>>,
+    warning_patterns: Vec<String>,
+    idempotent: bool,
+    validate_power_state: bool,
+}
+
+impl std::fmt::Debug for CommandHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandHooks")
+            .field("before", &self.before.len())
+            .field("after", &self.after.len())
+            .field("warning", &self.warning.len())
+            .field("warning_patterns", &self.warning_patterns)
+            .field("idempotent", &self.idempotent)
+            .field("validate_power_state", &self.validate_power_state)
+            .finish()
+    }
+}
+
+impl CommandHooks {
+    pub fn new() -> Self { Self::default() }
+
+    /// Adds a callback called before a command is executed.
+    pub fn on_before_command<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&str, &[String]) -> VmResult<()> + Send + Sync + 'static,
+    {
+        self.before.push(Arc::new(f));
+        self
+    }
+
+    /// Adds a callback called after a command is executed.
+    pub fn on_after_command<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&str, &[String], Option<&VmError>) + Send + Sync + 'static,
+    {
+        self.after.push(Arc::new(f));
+        self
+    }
+
+    /// Adds a callback called when a command writes a warning to stderr.
+    pub fn on_warning<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&str, &str) + Send + Sync + 'static,
+    {
+        self.warning.push(Arc::new(f));
+        self
+    }
+
+    /// Adds a pattern of a warning line written to stderr.
+    ///
+    /// A line containing `pattern` is treated as a warning. See [`CommandHooks::classify_stderr`].
+    pub fn add_warning_pattern<S: Into<String>>(
+        &mut self,
+        pattern: S,
+    ) -> &mut Self {
+        self.warning_patterns.push(pattern.into());
+        self
+    }
+
+    pub fn get_warning_patterns(&self) -> &[String] { &self.warning_patterns }
+
+    /// Logs `warning` written by `command`, passes it to the warning callbacks and adds it to the warnings collected by [`collect_warnings`].
+    ///
+    /// The callbacks receive the operation set by [`CommandHooks::operation`], or `command` if no operation is set.
+    pub fn report_warning(&self, command: &str, warning: &str) {
+        let operation = current_operation().unwrap_or(command);
+        warn!("{}: {}", operation, warning);
+        for hook in &self.warning {
+            hook(operation, warning);
+        }
+        WARNINGS.with(|x| {
+            if let Some(x) = x.borrow_mut().as_mut() {
+                x.push(CommandWarning {
+                    operation: operation.to_string(),
+                    message: warning.to_string(),
+                });
+            }
+        });
+    }
+
+    /// Splits `stderr` of a command into warnings and errors, and returns the errors.
+    ///
+    /// If the command succeeded, lines matching the warning patterns are reported with [`CommandHooks::report_warning`] and empty lines are dropped instead of being returned.
+    /// If the command failed, the whole `stderr` is returned.
+    /// The warning callbacks receive the operation set by [`CommandHooks::operation`], or `command` if no operation is set.
+    pub fn classify_stderr(
+        &self,
+        command: &str,
+        stderr: &str,
+        success: bool,
+    ) -> String {
+        if !success {
+            return stderr.to_string();
+        }
+        let operation = current_operation().unwrap_or(command);
+        let mut errors = vec![];
+        for line in stderr.lines() {
+            let line = line.trim_end();
+            if line.trim().is_empty() {
+                continue;
+            }
+            if self
+                .warning_patterns
+                .iter()
+                .any(|x| line.contains(x.as_str()))
+            {
+                self.report_warning(operation, line);
+            } else {
+                errors.push(line);
+            }
+        }
+        errors.join("\n")
+    }
+
+    /// Sets the operation name of the commands run on the current thread until the returned guard is dropped.
+    ///
+    /// If an operation has already been set, e.g., [`VmLifecycleCmd::delete_vm`] stops the VM, the outer operation is kept.
+    pub fn operation(&self, name: &'static str) -> OperationGuard {
+        let set = current_operation().is_none();
+        if set {
+            OPERATION.with(|x| x.set(Some(name)));
+        }
+        OperationGuard { set }
+    }
+
+    /// Returns the operation name and the arguments passed to the callbacks for `command` with `args`.
+    fn hook_args(command: &str, args: &[String]) -> (String, Vec<String>) {
+        let op = current_operation().unwrap_or(command).to_string();
+        let mut v = Vec::with_capacity(args.len() + 1);
+        v.push(command.to_string());
+        v.extend_from_slice(args);
+        (op, v)
+    }
+
+    /// Calls the before callbacks, executes `f` and calls the after callbacks.
+    ///
+    /// `command` is the command of the underlying tool. See [`CommandHooks`] for the arguments passed to the callbacks.
+    pub fn run<T, F: FnOnce() -> VmResult<T>>(
+        &self,
+        command: &str,
+        args: &[String],
+        f: F,
+    ) -> VmResult<T> {
+        let (op, args) = Self::hook_args(command, args);
+        for hook in &self.before {
+            hook(&op, &args)?;
+        }
+        let res = f();
+        for hook in &self.after {
+            hook(&op, &args, res.as_ref().err());
+        }
+        res
+    }
+
+    /// An async variant of [`CommandHooks::run`].
+    #[cfg(feature = "async")]
+    ///
+    /// `operation` is the operation name because a future may be polled on other threads.
+    pub async fn run_async<T, F: std::future::Future<Output = VmResult<T>>>(
+        &self,
+        operation: &str,
+        command: &str,
+        args: &[String],
+        f: F,
+    ) -> VmResult<T> {
+        let (_, args) = Self::hook_args(command, args);
+        for hook in &self.before {
+            hook(operation, &args)?;
+        }
+        let res = f.await;
+        for hook in &self.after {
+            hook(operation, &args, res.as_ref().err());
+        }
+        res
+    }
+
+    /// Sets the idempotent mode.
+    ///
+    /// In the idempotent mode, [`PowerCmd::start`] on a running VM, [`PowerCmd::stop`] and [`PowerCmd::hard_stop`] on a stopped VM and [`SnapshotCmd::delete_snapshot`] on a missing snapshot return `Ok(())`.
+    /// Some tools cannot distinguish a stopped VM from a suspended VM, so stopping a suspended VM may also return `Ok(())`.
+    pub fn idempotent(&mut self, idempotent: bool) -> &mut Self {
+        self.idempotent = idempotent;
+        self
+    }
+
+    pub fn is_idempotent(&self) -> bool { self.idempotent }
+
+    /// Sets the power state validation mode.
+    ///
+    /// In the power state validation mode, each operation of [`PowerCmd`] queries the current power state before sending the request, and returns [`ErrorKind::InvalidPowerState`] with the actual state if the operation is not allowed in the state.
+    /// Controllers which cannot get the power state (e.g., VmRun) ignore this mode.
+    pub fn validate_power_state(&mut self, validate: bool) -> &mut Self {
+        self.validate_power_state = validate;
+        self
+    }
+
+    pub fn is_validating_power_state(&self) -> bool {
+        self.validate_power_state
+    }
+
+    /// Returns [`ErrorKind::InvalidPowerState`] with the state got by `get_state` in the power state validation mode if it is not in `allowed`.
+    ///
+    /// [`VmPowerState::Unknown`] is always allowed.
+    pub fn check_power_state<F: FnOnce() -> VmResult<VmPowerState>>(
+        &self,
+        allowed: &[VmPowerState],
+        get_state: F,
+    ) -> VmResult<()> {
+        if !self.validate_power_state {
+            return Ok(());
+        }
+        match get_state()? {
+            VmPowerState::Unknown => Ok(()),
+            x if allowed.contains(&x) => Ok(()),
+            x => vmerr!(ErrorKind::InvalidPowerState(x)),
+        }
+    }
+
+    /// Returns `Ok(())` instead of the result of `f` in the idempotent mode if it means that the VM is already running.
+    pub fn settle_started<F: FnOnce() -> VmResult<()>>(
+        &self,
+        f: F,
+    ) -> VmResult<()> {
+        match f() {
+            Err(e)
+                if self.idempotent
+                    && e.get_invalid_state() == Some(VmPowerState::Running) =>
+            {
+                Ok(())
+            }
+            r => r,
+        }
+    }
+
+    /// Returns `Ok(())` instead of the result of `f` in the idempotent mode if it means that the VM is already stopped.
+    pub fn settle_stopped<F: FnOnce() -> VmResult<()>>(
+        &self,
+        f: F,
+    ) -> VmResult<()> {
+        match f() {
+            Err(e)
+                if self.idempotent
+                    && matches!(
+                        e.get_invalid_state(),
+                        Some(VmPowerState::Stopped | VmPowerState::NotRunning)
+                    ) =>
+            {
+                Ok(())
+            }
+            r => r,
+        }
+    }
+
+    /// Returns `Ok(())` instead of the result of `f` in the idempotent mode if it means that the snapshot does not exist.
+    pub fn settle_deleted<F: FnOnce() -> VmResult<()>>(
+        &self,
+        f: F,
+    ) -> VmResult<()> {
+        match f() {
+            Err(e)
+                if self.idempotent
+                    && e == VmError::from(ErrorKind::SnapshotNotFound) =>
+            {
+                Ok(())
+            }
+            r => r,
+        }
+    }
+}
+
 /// A trait for a VM information.
 pub trait VmCmd {
     /// Get a list of VMs.
     fn list_vms(&self) -> VmResult<Vec<Vm>>;
-    /// Sets the VM specified by the `id` of the VM.
+    /// Sets the VM specified by the `id` of the VM and returns the selected VM.
     /// If the corresponding VM doesn't exist, return [`ErrorKind::VmNotFound`].
     ///
     /// The ID type depends on the tool you are using.
-    fn set_vm_by_id(&mut self, id: &str) -> VmResult<()>;
-    /// Sets the VM specified by the `name` of the VM.
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm>;
+    /// Sets the VM specified by the `name` of the VM and returns the selected VM.
     /// If the corresponding VM doesn't exist, return [`ErrorKind::VmNotFound`].
-    fn set_vm_by_name(&mut self, name: &str) -> VmResult<()>;
-    /// Sets the VM specified by the `path` of the VM file.
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm>;
+    /// Sets the VM specified by the `path` of the VM file and returns the selected VM.
     /// If the corresponding VM doesn't exist, return [`ErrorKind::VmNotFound`].
     ///
     /// The file type depends on the tool you are using.
-    fn set_vm_by_path(&mut self, path: &str) -> VmResult<()>;
+    fn set_vm_by_path(&mut self, path: &str) -> VmResult<Vm>;
+    /// Sets the VM whose name matches `pattern` with `m` and returns the selected VM.
+    ///
+    /// Returns [`ErrorKind::VmNotFound`] if no VM matches, and [`ErrorKind::InvalidParameter`] if several VMs match.
+    fn set_vm_by_name_matching(
+        &mut self,
+        pattern: &str,
+        m: NameMatch,
+    ) -> VmResult<Vm> {
+        if m == NameMatch::Exact {
+            return self.set_vm_by_name(pattern);
+        }
+        let vm = find_vm_by_name(self.list_vms()?, pattern, m)?;
+        self.set_vm_by_name(vm.name.as_deref().unwrap())
+    }
+    /// Returns the detailed information of the VM.
+    ///
+    /// Returns [`ErrorKind::UnsupportedCommand`] if the controller cannot report it.
+    fn get_vm_info(&self) -> VmResult<VmInfo> {
+        vmerr!(ErrorKind::unsupported::<Self>("get_vm_info"))
+    }
+    /// Returns `true` if the guest tools of the hypervisor are running in the guest.
+    ///
+    /// The default implementation checks whether [`VmCmd::get_vm_info`] reports `tools_version`.
+    fn is_tools_running(&self) -> VmResult<bool> {
+        Ok(self.get_vm_info()?.tools_version.is_some())
+    }
 }
 
 /// A trait for managing power state of a VM.
@@ -140,7 +590,8 @@ pub trait PowerCmd {
     fn start(&self) -> VmResult<()>;
     /// Stops the VM softly and waits for the VM to stop.
     ///
-    /// This function usually only sends a ACPI shutdown signal, so there is no guarantee that calling this function will shut down the VM.
+    /// This function usually only sends a ACPI shutdown signal.
+    /// If the guest ignores the signal, this function returns [`ErrorKind::Timeout`] after `timeout`, so `Ok(())` means that the VM has actually stopped.
     fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()>;
     /// Stops the VM hardly and waits for the VM to stop.
     fn hard_stop(&self) -> VmResult<()>;
@@ -150,6 +601,16 @@ pub trait PowerCmd {
     fn resume(&self) -> VmResult<()>;
     /// Returns `true` if the VM is running.
     fn is_running(&self) -> VmResult<bool>;
+    /// Returns the power state of the VM.
+    ///
+    /// The default implementation only distinguishes [`VmPowerState::Running`] from [`VmPowerState::NotRunning`].
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Ok(if self.is_running()? {
+            VmPowerState::Running
+        } else {
+            VmPowerState::NotRunning
+        })
+    }
     /// Reboots the VM softly and waits for the VM to start.
     fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()>;
     /// Reboots the VM hardly and waits for the VM to start.
@@ -158,6 +619,21 @@ pub trait PowerCmd {
     fn pause(&self) -> VmResult<()>;
     /// Unpauses the VM and waits for the VM to unpause.
     fn unpause(&self) -> VmResult<()>;
+    /// Waits for the VM to stop.
+    ///
+    /// If the VM is still running after `timeout`, returns [`ErrorKind::Timeout`].
+    fn wait_for_stop(&self, timeout: Option<Duration>) -> VmResult<()> {
+        let s = Instant::now();
+        while self.is_running()? {
+            if let Some(timeout) = timeout {
+                if s.elapsed() >= timeout {
+                    return vmerr!(ErrorKind::Timeout);
+                }
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        Ok(())
+    }
 }
 
 /// A trait for managing snapshots of a VM.
@@ -170,12 +646,46 @@ pub trait SnapshotCmd {
     fn revert_snapshot(&self, name: &str) -> VmResult<()>;
     /// Deletes a snapshot of a VM.
     fn delete_snapshot(&self, name: &str) -> VmResult<()>;
+    /// Returns the disk space used by each snapshot of a VM.
+    ///
+    /// Returns [`ErrorKind::UnsupportedCommand`] if the controller cannot report it.
+    fn snapshot_disk_usage(&self) -> VmResult<Vec<SnapshotDiskUsage>> {
+        vmerr!(ErrorKind::unsupported::<Self>("snapshot_disk_usage"))
+    }
+    /// Returns the snapshot hierarchy of a VM.
+    ///
+    /// The default implementation builds the tree from the `parent_id`s of [`SnapshotCmd::list_snapshots`] without the current snapshot.
+    fn snapshot_tree(&self) -> VmResult<SnapshotTree> {
+        Ok(SnapshotTree::from_snapshots(self.list_snapshots()?, None))
+    }
 }
 
 /// A trait for controlling a guest OS.
 pub trait GuestCmd {
     /// Executes a command on guest.
     fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()>;
+    /// Executes a command on guest and returns its stdout, stderr and exit code.
+    ///
+    /// Returns [`ErrorKind::UnsupportedCommand`] if the controller cannot capture the output.
+    fn exec_cmd_output(&self, _guest_args: &[&str]) -> VmResult<GuestOutput> {
+        vmerr!(ErrorKind::unsupported::<Self>("exec_cmd_output"))
+    }
+    /// Executes a command on guest with `opts` and returns its stdout, stderr and exit code.
+    ///
+    /// The default implementation only accepts the default options.
+    fn exec_cmd_with(
+        &self,
+        guest_args: &[&str],
+        opts: &GuestExecOptions,
+    ) -> VmResult<GuestOutput> {
+        if *opts != GuestExecOptions::default() {
+            return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "exec_cmd_with",
+                "Use exec_cmd_output without options instead"
+            ));
+        }
+        self.exec_cmd_output(guest_args)
+    }
     /// Copies a file from a guest to a host.
     fn copy_from_guest_to_host(
         &self,
@@ -190,6 +700,375 @@ pub trait GuestCmd {
     ) -> VmResult<()>;
 }
 
+/// A trait for creating temporary directories in a guest OS.
+pub trait GuestTempDirCmd {
+    /// Creates a temporary directory in the guest and returns the path to it.
+    fn create_temp_dir_in_guest(&self) -> VmResult<String>;
+    /// Removes a directory and its contents in the guest.
+    fn remove_dir_in_guest(&self, guest_path: &str) -> VmResult<()>;
+}
+
+/// A trait for getting the network addresses of a guest OS.
+pub trait GuestNetworkCmd {
+    /// Gets the IPv4 and IPv6 addresses of the network interfaces of a guest OS.
+    ///
+    /// Most tools require the guest tools of the hypervisor to be running.
+    fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>>;
+}
+
+/// A trait for managing processes in a guest OS.
+pub trait GuestProcessCmd {
+    /// Returns processes in the guest.
+    fn list_processes_in_guest(&self) -> VmResult<Vec<ProcInfo>>;
+    /// Kills the process of `pid` in the guest.
+    fn kill_process_in_guest(&self, pid: u32) -> VmResult<()>;
+}
+
+/// A trait for manipulating the filesystem of a guest OS.
+///
+/// Temporary directories and removing directories are provided by [`GuestTempDirCmd`].
+pub trait GuestFsCmd: GuestTempDirCmd {
+    /// Creates a directory in the guest.
+    fn make_dir_in_guest(&self, guest_path: &str) -> VmResult<()>;
+    /// Removes a file in the guest.
+    fn remove_file_in_guest(&self, guest_path: &str) -> VmResult<()>;
+    /// Returns the information of a file in the guest, or `None` if it doesn't exist.
+    fn stat_in_guest(
+        &self,
+        guest_path: &str,
+    ) -> VmResult<Option<GuestFileInfo>>;
+    /// Returns `true` if a file or a directory exists in the guest.
+    fn exists_in_guest(&self, guest_path: &str) -> VmResult<bool> {
+        Ok(self.stat_in_guest(guest_path)?.is_some())
+    }
+    /// Moves or renames a file or a directory in the guest.
+    fn move_in_guest(
+        &self,
+        from_guest_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()>;
+    /// Returns the names of the entries in a directory in the guest.
+    fn list_dir_in_guest(&self, guest_path: &str) -> VmResult<Vec<String>>;
+    /// Creates an empty temporary file in the guest and returns the path to it.
+    fn make_temp_file_in_guest(&self) -> VmResult<String>;
+}
+
+/// A temporary directory in a guest OS that is removed on drop.
+///
+/// Errors on drop are ignored. To handle them, use [`GuestTempDir::close`].
+#[derive(Debug)]
+pub struct GuestTempDir<'a, T: GuestTempDirCmd> {
+    cmd: &'a T,
+    path: Option<String>,
+}
+
+impl<'a, T: GuestTempDirCmd> GuestTempDir<'a, T> {
+    /// Creates a temporary directory in the guest controlled by `cmd`.
+    pub fn new(cmd: &'a T) -> VmResult<Self> {
+        let path = cmd.create_temp_dir_in_guest()?;
+        Ok(Self {
+            cmd,
+            path: Some(path),
+        })
+    }
+
+    /// Returns the path to the directory in the guest.
+    pub fn path(&self) -> &str { self.path.as_deref().unwrap() }
+
+    /// Removes the directory and returns the result.
+    pub fn close(mut self) -> VmResult<()> {
+        let path = self.path.take().unwrap();
+        self.cmd.remove_dir_in_guest(&path)
+    }
+
+    /// Keeps the directory and returns the path to it.
+    pub fn keep(mut self) -> String { self.path.take().unwrap() }
+}
+
+impl<T: GuestTempDirCmd> Drop for GuestTempDir<'_, T> {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = self.cmd.remove_dir_in_guest(&path);
+        }
+    }
+}
+
+/// Represents a value in the Windows registry.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum RegistryValue {
+    Sz(String),
+    ExpandSz(String),
+    MultiSz(Vec<String>),
+    Dword(u32),
+    Qword(u64),
+    Binary(Vec<u8>),
+}
+
+impl RegistryValue {
+    /// Returns the type name used by `reg.exe`, such as `REG_SZ`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Sz(_) => "REG_SZ",
+            Self::ExpandSz(_) => "REG_EXPAND_SZ",
+            Self::MultiSz(_) => "REG_MULTI_SZ",
+            Self::Dword(_) => "REG_DWORD",
+            Self::Qword(_) => "REG_QWORD",
+            Self::Binary(_) => "REG_BINARY",
+        }
+    }
+
+    /// Returns the data in the format of `reg add /d`.
+    fn to_reg_data(&self) -> String {
+        match self {
+            Self::Sz(x) | Self::ExpandSz(x) => x.clone(),
+            Self::MultiSz(x) => x.join("\\0"),
+            Self::Dword(x) => x.to_string(),
+            Self::Qword(x) => x.to_string(),
+            Self::Binary(x) => x.iter().map(|b| format!("{:02X}", b)).collect(),
+        }
+    }
+
+    /// Parses the type and the data printed by `reg query`.
+    fn from_reg_data(ty: &str, data: &str) -> Option<Self> {
+        let hex = |x: &str| x.strip_prefix("0x").unwrap_or(x).to_string();
+        Some(match ty {
+            "REG_SZ" => Self::Sz(data.to_string()),
+            "REG_EXPAND_SZ" => Self::ExpandSz(data.to_string()),
+            "REG_MULTI_SZ" if data.is_empty() => Self::MultiSz(vec![]),
+            "REG_MULTI_SZ" => Self::MultiSz(
+                data.split("\\0").map(|x| x.to_string()).collect(),
+            ),
+            "REG_DWORD" => {
+                Self::Dword(u32::from_str_radix(&hex(data), 16).ok()?)
+            }
+            "REG_QWORD" => {
+                Self::Qword(u64::from_str_radix(&hex(data), 16).ok()?)
+            }
+            "REG_BINARY" => Self::Binary(
+                (0..data.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(data.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<_>>()?,
+            ),
+            _ => return None,
+        })
+    }
+}
+
+/// Parses the output of `reg query <key> /v <value>` and returns the first value.
+fn parse_reg_query(s: &str) -> Option<RegistryValue> {
+    s.lines()
+        .filter_map(|x| x.strip_prefix("    "))
+        .find_map(|x| {
+            let v: Vec<&str> = x.splitn(3, "    ").collect();
+            match v.as_slice() {
+                [_, ty, data] => RegistryValue::from_reg_data(ty, data),
+                [_, ty] => RegistryValue::from_reg_data(ty, ""),
+                _ => None,
+            }
+        })
+}
+
+/// Parses `value` of the first key in a file written by `reg export`. An empty `value` is the default value.
+fn parse_reg_export(s: &str, value: &str) -> Option<RegistryValue> {
+    // Joins the lines of long hex data, which end with `\`.
+    let mut lines = vec![];
+    let mut cont = false;
+    for l in s.lines() {
+        let l = if cont { l.trim_start() } else { l };
+        let (l, next) = match l.strip_suffix('\\') {
+            Some(x) => (x, true),
+            None => (l, false),
+        };
+        match lines.last_mut() {
+            Some(last) if cont => *last += l,
+            _ => lines.push(l.to_string()),
+        }
+        cont = next;
+    }
+    lines
+        .iter()
+        .skip_while(|x| !x.starts_with('['))
+        .skip(1)
+        .take_while(|x| !x.starts_with('['))
+        .find_map(|x| {
+            let (name, data) = match x.strip_prefix('@') {
+                Some(rest) => (String::new(), rest),
+                None => parse_reg_string(x)?,
+            };
+            if !name.eq_ignore_ascii_case(value) {
+                return None;
+            }
+            let data = data.strip_prefix('=')?;
+            if data.starts_with('"') {
+                let (x, rest) = parse_reg_string(data)?;
+                return if rest.is_empty() {
+                    Some(RegistryValue::Sz(x))
+                } else {
+                    None
+                };
+            }
+            if let Some(x) = data.strip_prefix("dword:") {
+                return u32::from_str_radix(x, 16)
+                    .ok()
+                    .map(RegistryValue::Dword);
+            }
+            let (ty, bytes) = data.split_once(':')?;
+            let bytes = if bytes.is_empty() {
+                vec![]
+            } else {
+                bytes
+                    .split(',')
+                    .map(|x| u8::from_str_radix(x.trim(), 16).ok())
+                    .collect::<Option<Vec<u8>>>()?
+            };
+            let utf16 = |b: &[u8]| {
+                let v: Vec<u16> = b
+                    .chunks_exact(2)
+                    .map(|x| u16::from_le_bytes([x[0], x[1]]))
+                    .collect();
+                String::from_utf16_lossy(&v)
+            };
+            Some(match ty {
+                "hex" => RegistryValue::Binary(bytes),
+                "hex(2)" => RegistryValue::ExpandSz(
+                    utf16(&bytes).trim_end_matches('\0').to_string(),
+                ),
+                "hex(7)" => {
+                    let x = utf16(&bytes);
+                    let x = x.trim_end_matches('\0');
+                    RegistryValue::MultiSz(if x.is_empty() {
+                        vec![]
+                    } else {
+                        x.split('\0').map(|x| x.to_string()).collect()
+                    })
+                }
+                "hex(b)" => RegistryValue::Qword(u64::from_le_bytes(
+                    bytes.get(..8)?.try_into().ok()?,
+                )),
+                _ => return None,
+            })
+        })
+}
+
+/// Parses a quoted string of a `.reg` file at the start of `s` and returns it and the rest of `s`.
+fn parse_reg_string(s: &str) -> Option<(String, &str)> {
+    let mut ret = String::new();
+    let mut it = s.strip_prefix('"')?.char_indices();
+    while let Some((i, c)) = it.next() {
+        match c {
+            '\\' => ret.push(it.next()?.1),
+            '"' => return Some((ret, &s[i + 2..])),
+            c => ret.push(c),
+        }
+    }
+    None
+}
+
+/// A trait for reading and writing the registry of a Windows guest with `reg.exe`.
+///
+/// This trait is implemented for all controllers which implement [`GuestCmd`].
+pub trait GuestRegistryCmd: GuestCmd {
+    /// Reads `value` of `key` (e.g., `HKLM\Software\Microsoft\Windows NT\CurrentVersion`) in the guest.
+    /// If `value` is empty, reads the default value of `key`.
+    ///
+    /// Runs `reg query` with [`GuestCmd::exec_cmd_output`].
+    /// If the controller cannot capture the output, `key` is exported with `reg export` to a file in `C:\Windows\Temp`, which is copied to the host.
+    /// Note that `reg export` also exports the subkeys of `key`.
+    fn read_guest_registry(
+        &self,
+        key: &str,
+        value: &str,
+    ) -> VmResult<RegistryValue> {
+        check_reg_arg(key)?;
+        check_reg_arg(value)?;
+        let mut args = vec!["reg.exe", "query", key];
+        if value.is_empty() {
+            args.push("/ve");
+        } else {
+            args.extend(["/v", value]);
+        }
+        match self.exec_cmd_output(&args) {
+            Ok(x) if x.exit_code == Some(0) => {
+                return match parse_reg_query(&x.stdout) {
+                    Some(v) => Ok(v),
+                    None => vmerr!(ErrorKind::UnexpectedResponse(x.stdout)),
+                };
+            }
+            Ok(x) => return vmerr!(ErrorKind::ExecutionFailed(x.stderr)),
+            Err(e) if e.is_unsupported() => {}
+            Err(e) => return Err(e),
+        }
+        let name = format!(
+            "hvctrl-reg-{}-{}.reg",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|x| x.as_nanos())
+                .unwrap_or_default()
+        );
+        let guest_path = format!(r"C:\Windows\Temp\{}", name);
+        let host_path = std::env::temp_dir().join(&name);
+        self.exec_cmd(&["reg.exe", "export", key, &guest_path, "/y"])?;
+        let r = self
+            .copy_from_guest_to_host(&guest_path, &host_path.to_string_lossy())
+            .and_then(|_| Ok(std::fs::read(&host_path)?));
+        let _ = std::fs::remove_file(&host_path);
+        if let Err(e) = self.exec_cmd(&["cmd.exe", "/c", "del", &guest_path]) {
+            warn!("Failed to remove {}: {}", guest_path, e);
+        }
+        let s = decode_utf16le(&r?);
+        match parse_reg_export(&s, value) {
+            Some(x) => Ok(x),
+            None => vmerr!(ErrorKind::ExecutionFailed(format!(
+                "{} is not found in {}",
+                value, key
+            ))),
+        }
+    }
+
+    /// Writes `data` to `value` of `key` in the guest with `reg add`, creating `key` if it does not exist.
+    /// If `value` is empty, writes the default value of `key`.
+    fn write_guest_registry(
+        &self,
+        key: &str,
+        value: &str,
+        data: &RegistryValue,
+    ) -> VmResult<()> {
+        check_reg_arg(key)?;
+        check_reg_arg(value)?;
+        let data_str = data.to_reg_data();
+        let mut args = vec!["reg.exe", "add", key];
+        if value.is_empty() {
+            args.push("/ve");
+        } else {
+            args.extend(["/v", value]);
+        }
+        args.extend(["/t", data.type_name(), "/d", &data_str, "/f"]);
+        self.exec_cmd(&args)
+    }
+}
+
+impl<T: GuestCmd> GuestRegistryCmd for T {}
+
+/// Decodes a file written by `reg export`, which is UTF-16LE with a BOM.
+fn decode_utf16le(b: &[u8]) -> String {
+    let b = b.strip_prefix(&[0xff, 0xfe]).unwrap_or(b);
+    let v: Vec<u16> = b
+        .chunks_exact(2)
+        .map(|x| u16::from_le_bytes([x[0], x[1]]))
+        .collect();
+    String::from_utf16_lossy(&v)
+}
+
+/// Rejects `"`, which ends a quoted argument, and `%`, which `cmd.exe` expands even in quotes.
+fn check_reg_arg(s: &str) -> VmResult<()> {
+    if s.contains(|c| c == '"' || c == '%') {
+        return vmerr!(ErrorKind::InvalidParameter(s.to_string()));
+    }
+    Ok(())
+}
+
 /// A trait for managing NICs of a VM.
 pub trait NicCmd {
     /// Returns NICs of a VM.
@@ -214,66 +1093,1101 @@ pub trait SharedFolderCmd {
     fn delete_shared_folder(&self, shfs: &SharedFolder) -> VmResult<()>;
 }
 
-/// Represents a VM information.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Vm {
-    /// Unique ID for the VM.
-    pub id: Option<String>,
-    /// The name of the VM.
-    pub name: Option<String>,
-    /// The path to the VM file.
-    pub path: Option<String>,
+/// A trait for managing the configuration of a VM.
+pub trait ConfigCmd {
+    /// Returns the CPU tuning of a VM.
+    fn get_cpu_tuning(&self) -> VmResult<CpuTuning>;
+    /// Sets the CPU tuning of a VM.
+    ///
+    /// `None` fields are left unchanged.
+    /// If the tool you are using doesn't support a specified field, returns [`ErrorKind::UnsupportedCommand`] without modifying the VM.
+    fn set_cpu_tuning(&self, tuning: &CpuTuning) -> VmResult<()>;
+    /// Returns the number of virtual processors of a VM.
+    fn get_cpu_count(&self) -> VmResult<u32> {
+        self.get_cpu_tuning()?.count.ok_or_else(|| {
+            VmError::from(ErrorKind::unsupported::<Self>("get_cpu_count"))
+        })
+    }
+    /// Sets the number of virtual processors of a VM.
+    ///
+    /// Most tools require the VM to be stopped.
+    fn set_cpu_count(&self, count: u32) -> VmResult<()> {
+        self.set_cpu_tuning(&CpuTuning {
+            count: Some(count),
+            ..CpuTuning::default()
+        })
+    }
+    /// Returns the memory size of a VM in MB.
+    fn get_memory_size(&self) -> VmResult<u64>;
+    /// Sets the memory size of a VM in MB.
+    ///
+    /// Most tools require the VM to be stopped.
+    fn set_memory_size(&self, size_mb: u64) -> VmResult<()>;
 }
 
-impl PartialEq for Vm {
-    fn eq(&self, other: &Self) -> bool {
-        if let (Some(x), Some(x2)) = (&self.id, &other.id) {
-            return x == x2;
-        }
-        if let (Some(x), Some(x2)) = (&self.path, &other.path) {
-            return x == x2;
-        }
-        if let (Some(x), Some(x2)) = (&self.name, &other.name) {
-            return x == x2;
-        }
-        false
-    }
+/// A trait for inserting ISO images into the CD/DVD drive of a VM.
+pub trait MediaCmd {
+    /// Inserts the ISO image at `path` into the CD/DVD drive of a VM.
+    fn attach_iso(&self, path: &str) -> VmResult<()>;
+    /// Ejects the medium from the CD/DVD drive of a VM.
+    fn eject(&self) -> VmResult<()>;
 }
 
-/// Represents a snapshot of a VM.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Snapshot {
-    pub id: Option<String>,
-    pub name: Option<String>,
-    pub detail: Option<String>,
+/// A trait for passing host devices through to a VM.
+pub trait PassthroughCmd {
+    /// Returns host devices passed through to a VM.
+    fn list_passthrough_devices(&self) -> VmResult<Vec<PassthroughDevice>>;
+    /// Passes a host device through to a VM.
+    fn add_passthrough_device(&self, dev: &PassthroughDevice) -> VmResult<()>;
+    /// Detaches a host device from a VM.
+    fn remove_passthrough_device(
+        &self,
+        dev: &PassthroughDevice,
+    ) -> VmResult<()>;
 }
 
-impl PartialEq for Snapshot {
-    fn eq(&self, other: &Self) -> bool {
-        if let (Some(x), Some(x2)) = (&self.id, &other.id) {
-            return x == x2;
-        }
-        if let (Some(x), Some(x2)) = (&self.name, &other.name) {
-            return x == x2;
-        }
-        false
-    }
+/// A trait for managing the port forwarding rules of NAT networks.
+pub trait PortForwardCmd {
+    /// Returns the port forwarding rules.
+    fn list_port_forwards(&self) -> VmResult<Vec<PortForward>>;
+    /// Adds a port forwarding rule.
+    fn add_port_forward(&self, rule: &PortForward) -> VmResult<()>;
+    /// Removes the port forwarding rule of `rule.protocol` and `rule.host_port`.
+    fn remove_port_forward(&self, rule: &PortForward) -> VmResult<()>;
 }
 
-/// Represents a NIC type.
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
-pub enum NicType {
-    Bridge,
-    #[allow(clippy::upper_case_acronyms)]
-    NAT,
-    HostOnly,
-    Custom(String),
+/// A trait for passing USB devices of the host through to a VM.
+pub trait UsbCmd {
+    /// Returns the USB devices of the host.
+    fn list_host_usb_devices(&self) -> VmResult<Vec<UsbDevice>>;
+    /// Attaches the USB device of `id` to a VM.
+    ///
+    /// The ID type depends on the tool you are using.
+    fn attach_usb_device(&self, id: &str) -> VmResult<()>;
+    /// Detaches the USB device of `id` from a VM.
+    fn detach_usb_device(&self, id: &str) -> VmResult<()>;
 }
 
-/// Represents a NIC.
-#[derive(Debug, Clone, Serialize, Deserialize, Default, Hash)]
-pub struct Nic {
-    pub id: Option<String>,
+/// A trait for cloning a VM.
+pub trait CloneCmd {
+    /// Clones a VM and returns the new VM.
+    ///
+    /// If the tool you are using doesn't support a specified option, returns [`ErrorKind::UnsupportedCommand`] without cloning the VM.
+    fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm>;
+}
+
+/// A trait for creating throwaway VMs as linked clones of a template VM.
+pub trait TemplateCmd: CloneCmd {
+    /// Creates the linked clones of `options` from a snapshot of the template VM and returns them in order.
+    ///
+    /// The clones are created one by one because the tools lock the template while cloning.
+    /// If creating a clone fails, destroys the clones created so far and returns the error.
+    fn create_linked_clones(
+        &self,
+        options: &TemplateOptions,
+    ) -> VmResult<Vec<Vm>> {
+        let mut ret = Vec::with_capacity(options.count);
+        for x in options.clone_options() {
+            match self.clone_vm(&x) {
+                Ok(vm) => ret.push(vm),
+                Err(e) => {
+                    if let Err(x) = self.destroy_vms(&ret) {
+                        warn!("Failed to destroy the created clones: {}", x);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(ret)
+    }
+    /// Powers off the running VMs, then unregisters the VMs and deletes their files.
+    ///
+    /// Tries to destroy all the VMs and returns the first error.
+    fn destroy_vms(&self, vms: &[Vm]) -> VmResult<()>;
+    /// Implements [`TemplateCmd::destroy_vms`] with `select`, which returns a controller manipulating the VM.
+    fn destroy_vms_with<C, F>(&self, vms: &[Vm], select: F) -> VmResult<()>
+    where
+        C: PowerCmd + VmLifecycleCmd,
+        F: Fn(&Vm) -> C,
+    {
+        // Destroy all the VMs before returning the first error.
+        let results: Vec<VmResult<()>> = vms
+            .iter()
+            .map(|vm| {
+                let cmd = select(vm);
+                if cmd.is_running()? {
+                    cmd.hard_stop()?;
+                }
+                cmd.delete_vm()
+            })
+            .collect();
+        results.into_iter().collect()
+    }
+}
+
+/// A trait for exporting and importing VMs.
+pub trait ImportExportCmd {
+    /// Exports a VM to `path` in `format`.
+    ///
+    /// If the tool you are using doesn't support `format`, returns [`ErrorKind::UnsupportedCommand`].
+    fn export_vm(&self, path: &str, format: ExportFormat) -> VmResult<()>;
+    /// Imports the VM exported to `path` and returns the imported VM.
+    fn import_vm(&self, path: &str) -> VmResult<Vm>;
+}
+
+/// A trait for creating, registering, unregistering and deleting VMs.
+///
+/// Unlike the other traits, [`VmLifecycleCmd::create_vm`] and [`VmLifecycleCmd::register_vm`] don't require a selected VM.
+pub trait VmLifecycleCmd {
+    /// Creates and registers a VM without disks and returns it.
+    ///
+    /// `None` fields of `spec` are left to the defaults of the tool you are using.
+    fn create_vm(&self, spec: &VmSpec) -> VmResult<Vm>;
+    /// Registers the existing VM whose configuration file is at `path` and returns it.
+    fn register_vm(&self, path: &str) -> VmResult<Vm>;
+    /// Unregisters the VM, leaving its files.
+    fn unregister_vm(&self) -> VmResult<()>;
+    /// Unregisters the VM and deletes its files.
+    fn delete_vm(&self) -> VmResult<()>;
+}
+
+/// A trait for capturing the console of a VM.
+pub trait ScreenshotCmd {
+    /// Captures the console of a running VM and returns it as a PNG image.
+    fn capture_screen(&self) -> VmResult<Vec<u8>>;
+}
+
+/// A trait for sending keyboard input to the console of a VM.
+pub trait KeyboardCmd {
+    /// Types `text` on the console of a running VM.
+    fn type_text(&self, text: &str) -> VmResult<()>;
+    /// Presses `keys` in order and releases them in reverse order, e.g., `[Key::Ctrl, Key::Alt, Key::Delete]`.
+    fn send_keys(&self, keys: &[Key]) -> VmResult<()>;
+}
+
+/// A trait for configuring the serial ports of a VM.
+///
+/// e.g., [`SerialPortMode::File`] with the kernel parameter `console=ttyS0` records the kernel log of a Linux guest.
+pub trait SerialPortCmd {
+    /// Returns the serial ports of a VM.
+    ///
+    /// Ports in the modes which [`SerialPortMode`] doesn't represent, such as a host device, are omitted.
+    fn list_serial_ports(&self) -> VmResult<Vec<SerialPort>>;
+    /// Sets the serial port `n`, i.e., `COM<n>` of the guest, to `mode`.
+    ///
+    /// Most tools require the VM to be stopped.
+    fn set_serial_port(&self, n: usize, mode: &SerialPortMode) -> VmResult<()>;
+}
+
+/// A trait for sampling the resource usage of VMs.
+pub trait MetricsCmd {
+    /// Gets the current resource usage of a running VM.
+    ///
+    /// Fields that the tool you are using doesn't report are `None`.
+    fn get_metrics(&self) -> VmResult<VmMetrics>;
+}
+
+/// Extension functions which poll a VM until it reaches a condition.
+///
+/// Implemented for all controllers; each function is available if the controller implements the traits it requires.
+pub trait WaitCmd {
+    /// Waits for the VM to reach `state`.
+    ///
+    /// [`VmPowerState::NotRunning`] is reached by any state except [`VmPowerState::Running`] and [`VmPowerState::Unknown`].
+    /// Returns [`ErrorKind::UnsupportedCommand`] if the controller cannot distinguish `state` from the other states that are not running.
+    fn wait_for_state(
+        &self,
+        state: VmPowerState,
+        opts: &WaitOptions,
+    ) -> VmResult<()>
+    where
+        Self: PowerCmd,
+    {
+        opts.poll(|| {
+            let current = self.get_power_state()?;
+            if current == VmPowerState::NotRunning
+                && !matches!(
+                    state,
+                    VmPowerState::NotRunning | VmPowerState::Running
+                )
+            {
+                return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                    "wait_for_state",
+                    "Wait for VmPowerState::NotRunning instead"
+                ));
+            }
+            Ok(current.matches(state).then(|| ()))
+        })
+    }
+    /// Waits for the guest to have an IP address and returns all addresses of the guest.
+    ///
+    /// Loopback, unspecified and link-local addresses are not counted.
+    /// Errors are retried until `timeout`, since most tools fail while the guest tools are starting.
+    fn wait_for_ip(&self, opts: &WaitOptions) -> VmResult<Vec<GuestIpAddress>>
+    where
+        Self: GuestNetworkCmd,
+    {
+        opts.poll(|| match self.get_ip_addresses() {
+            Ok(x) if x.iter().any(|x| is_assigned_ip(&x.address)) => {
+                Ok(Some(x))
+            }
+            Err(e) if e.is_unsupported() => Err(e),
+            r => {
+                if let Err(e) = r {
+                    debug!("wait_for_ip: {}", e);
+                }
+                Ok(None)
+            }
+        })
+    }
+    /// Waits for the guest tools of the hypervisor to run in the guest.
+    ///
+    /// Errors are retried until `timeout`.
+    fn wait_for_tools(&self, opts: &WaitOptions) -> VmResult<()>
+    where
+        Self: VmCmd,
+    {
+        opts.poll(|| match self.is_tools_running() {
+            Ok(x) => Ok(x.then(|| ())),
+            Err(e) if e.is_unsupported() => Err(e),
+            Err(e) => {
+                debug!("wait_for_tools: {}", e);
+                Ok(None)
+            }
+        })
+    }
+}
+
+impl<T: ?Sized> WaitCmd for T {}
+
+/// Returns `true` if `addr` is an address assigned to an interface by the network, not by the guest itself.
+fn is_assigned_ip(addr: &IpAddr) -> bool {
+    if addr.is_loopback() || addr.is_unspecified() {
+        return false;
+    }
+    match addr {
+        IpAddr::V4(x) => !x.is_link_local(),
+        IpAddr::V6(x) => x.segments()[0] & 0xffc0 != 0xfe80,
+    }
+}
+
+/// Represents the polling interval and the timeout of [`WaitCmd`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WaitOptions {
+    /// The interval between polls.
+    pub interval: Duration,
+    /// Returns [`ErrorKind::Timeout`] if the condition isn't met in the duration.
+    /// `None` waits forever.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for WaitOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(200),
+            timeout: None,
+        }
+    }
+}
+
+impl WaitOptions {
+    /// Returns the default options with `timeout`.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+
+    /// Calls `f` every `interval` until it returns `Some`.
+    fn poll<T, F: FnMut() -> VmResult<Option<T>>>(
+        &self,
+        mut f: F,
+    ) -> VmResult<T> {
+        let s = Instant::now();
+        loop {
+            if let Some(x) = f()? {
+                return Ok(x);
+            }
+            if let Some(timeout) = self.timeout {
+                if s.elapsed() >= timeout {
+                    return vmerr!(ErrorKind::Timeout);
+                }
+            }
+            std::thread::sleep(self.interval);
+        }
+    }
+}
+
+/// Represents a VM information.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Vm {
+    /// Unique ID for the VM.
+    pub id: Option<String>,
+    /// The name of the VM.
+    pub name: Option<String>,
+    /// The path to the VM file.
+    pub path: Option<String>,
+}
+
+/// Represents a firmware type of a VM.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Firmware {
+    Bios,
+    Efi,
+}
+
+/// Represents the detailed information of a VM returned by [`VmCmd::get_vm_info`].
+///
+/// `None` fields are not reported by the tool you are using.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct VmInfo {
+    pub name: Option<String>,
+    /// The guest OS type in the format of the tool, e.g., `Windows10_64` of VirtualBox or `windows9-64` of VMware.
+    pub guest_os: Option<String>,
+    /// The memory size in MB.
+    pub memory_mb: Option<u64>,
+    /// The number of virtual processors.
+    pub cpu_count: Option<u32>,
+    pub firmware: Option<Firmware>,
+    /// The generation of a Hyper-V VM.
+    pub generation: Option<u32>,
+    pub power_state: Option<VmPowerState>,
+    /// The path to the configuration file of the VM.
+    pub config_path: Option<String>,
+    /// The version of VMware Tools, VirtualBox Guest Additions, Hyper-V integration services or Parallels Tools in the guest.
+    pub tools_version: Option<String>,
+}
+
+/// Represents credentials of a guest user.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct GuestCredential {
+    pub username: String,
+    pub password: String,
+    /// The domain for Windows guests.
+    pub domain: Option<String>,
+}
+
+impl GuestCredential {
+    pub fn new<U: Into<String>, P: Into<String>>(
+        username: U,
+        password: P,
+    ) -> Self {
+        Self {
+            username: username.into(),
+            password: password.into(),
+            domain: None,
+        }
+    }
+}
+
+/// Guest credentials registered per VM name or per guest OS family.
+///
+/// A credential for the VM name takes precedence over one for the OS family.
+/// OS families are matched as case-insensitive prefixes of the guest OS name (e.g., `Windows` matches `Windows 10 (64-bit)`), in the order they were added.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct GuestCredentials {
+    vms: BTreeMap<String, GuestCredential>,
+    os_families: Vec<(String, GuestCredential)>,
+}
+
+impl GuestCredentials {
+    pub fn new() -> Self { Self::default() }
+
+    /// Registers `credential` for the VM named `vm`.
+    pub fn add_for_vm<S: Into<String>>(
+        &mut self,
+        vm: S,
+        credential: GuestCredential,
+    ) -> &mut Self {
+        self.vms.insert(vm.into(), credential);
+        self
+    }
+
+    /// Registers `credential` for guests whose OS name starts with `os_family`.
+    pub fn add_for_os_family<S: Into<String>>(
+        &mut self,
+        os_family: S,
+        credential: GuestCredential,
+    ) -> &mut Self {
+        self.os_families.push((os_family.into(), credential));
+        self
+    }
+
+    /// Returns the credential registered for the VM named `vm`.
+    pub fn get_for_vm(&self, vm: &str) -> Option<&GuestCredential> {
+        self.vms.get(vm)
+    }
+
+    /// Returns the first credential whose OS family matches `guest_os`.
+    pub fn get_for_os(&self, guest_os: &str) -> Option<&GuestCredential> {
+        let guest_os = guest_os.trim().to_lowercase();
+        self.os_families
+            .iter()
+            .find(|(x, _)| guest_os.starts_with(&x.trim().to_lowercase()))
+            .map(|(_, x)| x)
+    }
+
+    pub fn has_os_families(&self) -> bool { !self.os_families.is_empty() }
+
+    pub fn is_empty(&self) -> bool {
+        self.vms.is_empty() && self.os_families.is_empty()
+    }
+
+    /// Applies `f` to the VM names and the credentials, keeping OS families as they are.
+    #[allow(dead_code)]
+    pub(crate) fn map<F: Fn(&str) -> String>(
+        &self,
+        key: F,
+        credential: impl Fn(&GuestCredential) -> GuestCredential,
+    ) -> Self {
+        Self {
+            vms: self
+                .vms
+                .iter()
+                .map(|(k, v)| (key(k), credential(v)))
+                .collect(),
+            os_families: self
+                .os_families
+                .iter()
+                .map(|(k, v)| (k.clone(), credential(v)))
+                .collect(),
+        }
+    }
+}
+
+/// Represents how a VM name is matched by [`VmCmd::set_vm_by_name_matching`].
+///
+/// All modes except [`NameMatch::Exact`] ignore case and surrounding whitespace.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum NameMatch {
+    /// The name is equal to the pattern.
+    Exact,
+    /// The name is equal to the pattern ignoring case.
+    IgnoreCase,
+    /// The name starts with the pattern.
+    Prefix,
+    /// The name matches the pattern, where `*` matches any string and `?` matches any character.
+    Glob,
+}
+
+impl Default for NameMatch {
+    fn default() -> Self { Self::Exact }
+}
+
+impl NameMatch {
+    /// Returns true if `name` matches `pattern`.
+    pub fn is_match(&self, pattern: &str, name: &str) -> bool {
+        if *self == Self::Exact {
+            return pattern == name;
+        }
+        let pattern = pattern.trim().to_lowercase();
+        let name = name.trim().to_lowercase();
+        match self {
+            Self::Exact | Self::IgnoreCase => pattern == name,
+            Self::Prefix => name.starts_with(&pattern),
+            Self::Glob => {
+                let p: Vec<char> = pattern.chars().collect();
+                let n: Vec<char> = name.chars().collect();
+                glob_match(&p, &n)
+            }
+        }
+    }
+}
+
+fn glob_match(p: &[char], n: &[char]) -> bool {
+    match p.split_first() {
+        None => n.is_empty(),
+        Some(('*', rest)) => (0..=n.len()).any(|i| glob_match(rest, &n[i..])),
+        Some((c, rest)) => match n.split_first() {
+            Some((x, n)) if *c == '?' || c == x => glob_match(rest, n),
+            _ => false,
+        },
+    }
+}
+
+/// Finds the VM whose name matches `pattern` with `m` in `vms`.
+///
+/// If several VMs match, the VM whose name is equal to `pattern` ignoring case is preferred.
+pub fn find_vm_by_name<I: IntoIterator<Item = Vm>>(
+    vms: I,
+    pattern: &str,
+    m: NameMatch,
+) -> VmResult<Vm> {
+    let mut found: Vec<Vm> = vms
+        .into_iter()
+        .filter(|x| x.name.as_deref().map_or(false, |x| m.is_match(pattern, x)))
+        .collect();
+    if found.len() > 1 {
+        if let Some(i) = found.iter().position(|x| {
+            NameMatch::IgnoreCase.is_match(pattern, x.name.as_deref().unwrap())
+        }) {
+            return Ok(found.swap_remove(i));
+        }
+    }
+    match found.len() {
+        0 => vmerr!(ErrorKind::VmNotFound),
+        1 => Ok(found.remove(0)),
+        _ => vmerr!(ErrorKind::InvalidParameter(format!(
+            "{} matches multiple VMs",
+            pattern
+        ))),
+    }
+}
+
+/// Returns true if `a` and `b` are the same UUID.
+///
+/// Ignores case and surrounding braces, since tools print UUIDs in different forms.
+pub fn uuid_eq(a: &str, b: &str) -> bool {
+    fn strip(x: &str) -> &str {
+        x.trim().trim_start_matches('{').trim_end_matches('}')
+    }
+    strip(a).eq_ignore_ascii_case(strip(b))
+}
+
+impl PartialEq for Vm {
+    fn eq(&self, other: &Self) -> bool {
+        if let (Some(x), Some(x2)) = (&self.id, &other.id) {
+            return x == x2;
+        }
+        if let (Some(x), Some(x2)) = (&self.path, &other.path) {
+            return x == x2;
+        }
+        if let (Some(x), Some(x2)) = (&self.name, &other.name) {
+            return x == x2;
+        }
+        false
+    }
+}
+
+/// Represents a process in a guest.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProcInfo {
+    pub pid: u32,
+    /// The user running the process. Empty if the controller cannot get it.
+    pub owner: String,
+    /// The command line or the executable path of the process.
+    pub cmd: String,
+}
+
+/// Represents the type of a file in a guest.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum GuestFileType {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// Represents the information of a file in a guest, returned by [`GuestFsCmd::stat_in_guest`].
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct GuestFileInfo {
+    pub file_type: GuestFileType,
+    /// The size in bytes. `None` if the controller cannot get it.
+    pub size: Option<u64>,
+}
+
+/// Represents an IP address of a network interface of a guest OS.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct GuestIpAddress {
+    /// The MAC address or the name of the interface, if the tool reports it.
+    pub interface: Option<String>,
+    pub address: IpAddr,
+}
+
+impl GuestIpAddress {
+    /// Parses `address` such as `192.168.0.2`, `192.168.0.2/24` or `fe80::1%eth0`, ignoring the prefix length and the zone.
+    #[allow(dead_code)]
+    pub(crate) fn parse(
+        interface: Option<&str>,
+        address: &str,
+    ) -> Option<Self> {
+        let address = address.trim();
+        let address = address.split(|c| c == '/' || c == '%').next()?;
+        Some(Self {
+            interface: interface.map(|x| x.to_string()),
+            address: address.parse().ok()?,
+        })
+    }
+}
+
+/// Represents the output of a command executed on a guest.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct GuestOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` if the command was terminated by a signal.
+    pub exit_code: Option<i32>,
+}
+
+/// Represents options for executing a command on a guest with [`GuestCmd::exec_cmd_with`].
+///
+/// Controllers return [`ErrorKind::UnsupportedCommand`] for options they cannot apply.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct GuestExecOptions {
+    /// Environment variables set for the command as `(name, value)`.
+    pub env: Vec<(String, String)>,
+    /// The working directory of the command.
+    pub cwd: Option<String>,
+    /// Terminates the command if it doesn't exit in the duration.
+    pub timeout: Option<Duration>,
+    /// Runs the command with administrative privileges.
+    pub elevated: bool,
+    /// Runs the command in the desktop session of the logged-in user.
+    pub interactive: bool,
+    /// Shows the window of the command.
+    pub active_window: bool,
+}
+
+/// Represents the format of a VM exported with [`ImportExportCmd::export_vm`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// A single OVA archive.
+    Ova,
+    /// An OVF descriptor and the disk images next to it.
+    Ovf,
+    /// The native format of the tool (e.g., the directory written by `Export-VM` for Hyper-V).
+    Native,
+}
+
+/// Represents how [`CloneCmd::clone_vm`] copies the disks of a VM.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum CloneType {
+    /// Copies the disks.
+    Full,
+    /// Creates differencing disks which refer to the disks of the source VM.
+    Linked,
+}
+
+impl Default for CloneType {
+    fn default() -> Self { Self::Full }
+}
+
+/// Represents a mode of a serial port of a VM.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum SerialPortMode {
+    /// Disables the serial port.
+    Off,
+    /// Enables the serial port without connecting it to the host.
+    Disconnected,
+    /// Writes the output of the guest to the raw file at the path on the host.
+    File(String),
+    /// Creates the named pipe (Windows) or the local domain socket (Linux) at the path.
+    PipeServer(String),
+    /// Connects to the existing named pipe or local domain socket at the path.
+    PipeClient(String),
+    /// Listens on the TCP port of the host.
+    TcpServer(u16),
+    /// Connects to the TCP server at `host:port`.
+    TcpClient(String),
+}
+
+impl SerialPortMode {
+    /// Opens the host side of the serial port to read the output of the guest.
+    ///
+    /// Opens the file of [`SerialPortMode::File`], and connects to the pipe of [`SerialPortMode::PipeServer`] or the port of [`SerialPortMode::TcpServer`] on the local host.
+    /// Returns [`ErrorKind::InvalidParameter`] for the other modes, in which the VM is not the server.
+    pub fn open(&self) -> VmResult<Box<dyn Read + Send>> {
+        Ok(match self {
+            Self::File(x) => Box::new(std::fs::File::open(x)?),
+            #[cfg(windows)]
+            Self::PipeServer(x) => Box::new(
+                std::fs::OpenOptions::new().read(true).write(true).open(x)?,
+            ),
+            #[cfg(unix)]
+            Self::PipeServer(x) => {
+                Box::new(std::os::unix::net::UnixStream::connect(x)?)
+            }
+            Self::TcpServer(x) => {
+                Box::new(std::net::TcpStream::connect(("127.0.0.1", *x))?)
+            }
+            x => {
+                return vmerr!(ErrorKind::InvalidParameter(format!(
+                    "{:?} cannot be opened",
+                    x
+                )))
+            }
+        })
+    }
+}
+
+/// Represents a serial port of a VM.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SerialPort {
+    /// The port number from 1, i.e., `COM<number>` of the guest.
+    pub number: usize,
+    pub mode: SerialPortMode,
+}
+
+/// Represents a key of a keyboard for [`KeyboardCmd::send_keys`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Key {
+    /// A character key of the US keyboard layout, e.g., `Key::Char('a')` or `Key::Char('/')`.
+    ///
+    /// Letters are case-insensitive. Use [`KeyboardCmd::type_text`] to type text.
+    Char(char),
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    Space,
+    CapsLock,
+    Shift,
+    Ctrl,
+    Alt,
+    Win,
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    /// A function key from F1 to F12.
+    F(u8),
+}
+
+/// The keys of [`Key::Char`] in the order of their make codes from `0x02`, or `' '` for make codes which are not characters.
+const SCANCODE_CHARS: &str =
+    "1234567890-=  qwertyuiop[]  asdfghjkl;'` \\zxcvbnm,./";
+
+impl Key {
+    /// Returns the make code of the key in the scancode set 1.
+    ///
+    /// Extended keys have the `0xe0` prefix in the upper byte.
+    /// Returns `None` if the key has no scancode.
+    pub fn scancode(&self) -> Option<u16> {
+        Some(match self {
+            Self::Char(' ') => 0x39,
+            Self::Char(c) => {
+                SCANCODE_CHARS.find(c.to_ascii_lowercase())? as u16 + 0x02
+            }
+            Self::Escape => 0x01,
+            Self::Backspace => 0x0e,
+            Self::Tab => 0x0f,
+            Self::Enter => 0x1c,
+            Self::Ctrl => 0x1d,
+            Self::Shift => 0x2a,
+            Self::Alt => 0x38,
+            Self::Space => 0x39,
+            Self::CapsLock => 0x3a,
+            Self::F(n @ 1..=10) => 0x3a + *n as u16,
+            Self::F(11) => 0x57,
+            Self::F(12) => 0x58,
+            Self::F(_) => return None,
+            Self::Home => 0xe047,
+            Self::Up => 0xe048,
+            Self::PageUp => 0xe049,
+            Self::Left => 0xe04b,
+            Self::Right => 0xe04d,
+            Self::End => 0xe04f,
+            Self::Down => 0xe050,
+            Self::PageDown => 0xe051,
+            Self::Insert => 0xe052,
+            Self::Delete => 0xe053,
+            Self::Win => 0xe05b,
+        })
+    }
+
+    /// Returns the Windows virtual-key code of the key.
+    ///
+    /// Returns `None` if the key has no virtual-key code.
+    pub fn virtual_key_code(&self) -> Option<u32> {
+        Some(match self {
+            Self::Char(c) => match c.to_ascii_uppercase() {
+                c @ ('A'..='Z' | '0'..='9' | ' ') => c as u32,
+                ';' => 0xba,
+                '=' => 0xbb,
+                ',' => 0xbc,
+                '-' => 0xbd,
+                '.' => 0xbe,
+                '/' => 0xbf,
+                '`' => 0xc0,
+                '[' => 0xdb,
+                '\\' => 0xdc,
+                ']' => 0xdd,
+                '\'' => 0xde,
+                _ => return None,
+            },
+            Self::Backspace => 0x08,
+            Self::Tab => 0x09,
+            Self::Enter => 0x0d,
+            Self::Shift => 0x10,
+            Self::Ctrl => 0x11,
+            Self::Alt => 0x12,
+            Self::CapsLock => 0x14,
+            Self::Escape => 0x1b,
+            Self::Space => 0x20,
+            Self::PageUp => 0x21,
+            Self::PageDown => 0x22,
+            Self::End => 0x23,
+            Self::Home => 0x24,
+            Self::Left => 0x25,
+            Self::Up => 0x26,
+            Self::Right => 0x27,
+            Self::Down => 0x28,
+            Self::Insert => 0x2d,
+            Self::Delete => 0x2e,
+            Self::Win => 0x5b,
+            Self::F(n @ 1..=12) => 0x6f + *n as u32,
+            Self::F(_) => return None,
+        })
+    }
+
+    /// Converts `keys` into the scancodes which press the keys in order and release them in reverse order.
+    ///
+    /// Returns [`ErrorKind::InvalidParameter`] if a key has no scancode.
+    pub fn to_scancodes(keys: &[Key]) -> VmResult<Vec<u8>> {
+        let codes = keys
+            .iter()
+            .map(|k| {
+                k.scancode().ok_or_else(|| {
+                    VmError::from(ErrorKind::InvalidParameter(format!(
+                        "{:?} has no scancode",
+                        k
+                    )))
+                })
+            })
+            .collect::<VmResult<Vec<u16>>>()?;
+        let mut ret = vec![];
+        let mut push = |code: u16, brk: u8| {
+            if code > 0xff {
+                ret.push((code >> 8) as u8);
+            }
+            ret.push(code as u8 | brk);
+        };
+        for x in &codes {
+            push(*x, 0);
+        }
+        for x in codes.iter().rev() {
+            push(*x, 0x80);
+        }
+        Ok(ret)
+    }
+}
+
+/// Represents options for cloning a VM with [`CloneCmd::clone_vm`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct CloneOptions {
+    /// The name of the new VM.
+    pub name: String,
+    pub ty: CloneType,
+    /// The snapshot to clone from. The current state is cloned if `None`.
+    ///
+    /// Some tools require a snapshot for [`CloneType::Linked`].
+    pub snapshot: Option<String>,
+    /// The directory where the directory of the new VM is created. The default location of the tool is used if `None`.
+    pub path: Option<String>,
+}
+
+impl CloneOptions {
+    pub fn new<S: Into<String>>(name: S, ty: CloneType) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+            ..Default::default()
+        }
+    }
+}
+
+/// Represents options for creating linked clones with [`TemplateCmd::create_linked_clones`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct TemplateOptions {
+    /// The snapshot of the template VM to clone from.
+    pub snapshot: String,
+    /// The prefix of the names of the clones, which are named `<prefix>1`, `<prefix>2`, and so on.
+    pub name_prefix: String,
+    /// The number of the clones.
+    pub count: usize,
+    /// The directory where the directories of the clones are created. The default location of the tool is used if `None`.
+    pub path: Option<String>,
+}
+
+impl TemplateOptions {
+    pub fn new<S: Into<String>, P: Into<String>>(
+        snapshot: S,
+        name_prefix: P,
+        count: usize,
+    ) -> Self {
+        Self {
+            snapshot: snapshot.into(),
+            name_prefix: name_prefix.into(),
+            count,
+            path: None,
+        }
+    }
+
+    /// Returns the options for cloning each VM.
+    pub fn clone_options(&self) -> Vec<CloneOptions> {
+        (1..=self.count)
+            .map(|i| CloneOptions {
+                name: format!("{}{}", self.name_prefix, i),
+                ty: CloneType::Linked,
+                snapshot: Some(self.snapshot.clone()),
+                path: self.path.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Represents a specification of a new VM for [`VmLifecycleCmd::create_vm`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct VmSpec {
+    /// The name of the new VM.
+    pub name: String,
+    /// The directory where the directory of the new VM is created. The default location of the tool is used if `None`.
+    pub path: Option<String>,
+    /// The guest OS type in the format of the tool, e.g., `Ubuntu_64` of VirtualBox.
+    pub guest_os: Option<String>,
+    /// The memory size in MB.
+    pub memory_mb: Option<u64>,
+    /// The number of virtual processors.
+    pub cpu_count: Option<u32>,
+    /// The firmware of the VM. [`Firmware::Efi`] creates a generation 2 VM on Hyper-V.
+    pub firmware: Option<Firmware>,
+}
+
+impl VmSpec {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Represents a snapshot of a VM.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Snapshot {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub detail: Option<String>,
+    /// The ID of the parent snapshot. `None` if the snapshot is a root or the controller cannot report it.
+    pub parent_id: Option<String>,
+    /// The creation time in seconds since the Unix epoch.
+    pub created_at: Option<u64>,
+    /// `true` if the current state of the VM is based on the snapshot.
+    pub is_current: Option<bool>,
+    /// `true` if the snapshot contains the memory state of the running VM.
+    pub online: Option<bool>,
+}
+
+impl PartialEq for Snapshot {
+    fn eq(&self, other: &Self) -> bool {
+        if let (Some(x), Some(x2)) = (&self.id, &other.id) {
+            return x == x2;
+        }
+        if let (Some(x), Some(x2)) = (&self.name, &other.name) {
+            return x == x2;
+        }
+        false
+    }
+}
+
+/// Represents a snapshot and its child snapshots.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct SnapshotNode {
+    pub snapshot: Snapshot,
+    pub children: Vec<SnapshotNode>,
+}
+
+/// Represents the snapshot hierarchy of a VM.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct SnapshotTree {
+    pub roots: Vec<SnapshotNode>,
+    /// The snapshot the current state of the VM is based on.
+    pub current: Option<Snapshot>,
+}
+
+impl SnapshotTree {
+    /// Builds a tree from `snapshots` linked by `parent_id`.
+    ///
+    /// Snapshots whose parent is not in `snapshots` become roots. `current` is the snapshot whose ID is `current_id`.
+    pub fn from_snapshots(
+        snapshots: Vec<Snapshot>,
+        current_id: Option<&str>,
+    ) -> Self {
+        fn children(
+            parent: &Snapshot,
+            snapshots: &[Snapshot],
+        ) -> Vec<SnapshotNode> {
+            let id = match &parent.id {
+                Some(x) => x,
+                None => return vec![],
+            };
+            snapshots
+                .iter()
+                .filter(|x| {
+                    x.parent_id.as_ref() == Some(id) && x.id != parent.id
+                })
+                .map(|x| SnapshotNode {
+                    snapshot: x.clone(),
+                    children: children(x, snapshots),
+                })
+                .collect()
+        }
+        let roots = snapshots
+            .iter()
+            .filter(|x| match &x.parent_id {
+                Some(p) => !snapshots.iter().any(|y| y.id.as_ref() == Some(p)),
+                None => true,
+            })
+            .map(|x| SnapshotNode {
+                snapshot: x.clone(),
+                children: children(x, &snapshots),
+            })
+            .collect();
+        let current = current_id.and_then(|id| {
+            snapshots
+                .iter()
+                .find(|x| x.id.as_deref() == Some(id))
+                .cloned()
+        });
+        Self { roots, current }
+    }
+
+    /// Returns all snapshots in depth-first order.
+    pub fn snapshots(&self) -> Vec<&Snapshot> {
+        fn walk<'a>(nodes: &'a [SnapshotNode], ret: &mut Vec<&'a Snapshot>) {
+            for x in nodes {
+                ret.push(&x.snapshot);
+                walk(&x.children, ret);
+            }
+        }
+        let mut ret = vec![];
+        walk(&self.roots, &mut ret);
+        ret
+    }
+}
+
+/// Represents the disk space used by a snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SnapshotDiskUsage {
+    pub snapshot: Snapshot,
+    /// The paths to the differencing disks of the snapshot.
+    pub disks: Vec<String>,
+    /// The total size of `disks` in bytes.
+    pub size: u64,
+}
+
+/// Represents a NIC type.
+#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+pub enum NicType {
+    Bridge,
+    #[allow(clippy::upper_case_acronyms)]
+    NAT,
+    HostOnly,
+    Custom(String),
+}
+
+/// Represents a NIC.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, Hash)]
+pub struct Nic {
+    pub id: Option<String>,
     pub name: Option<String>,
     pub ty: Option<NicType>,
     pub mac_address: Option<String>,
@@ -289,8 +2203,132 @@ pub struct SharedFolder {
     pub is_readonly: bool,
 }
 
+/// Represents CPU tuning of a VM.
+#[derive(
+    Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default, Hash,
+)]
+pub struct CpuTuning {
+    /// The number of virtual processors.
+    pub count: Option<u32>,
+    /// The percentage of host CPU resources reserved for the VM.
+    pub reserve: Option<u32>,
+    /// The maximum percentage of host CPU resources the VM can use.
+    pub maximum: Option<u32>,
+    /// The weight of the VM relative to other VMs when they compete for host CPU resources.
+    pub relative_weight: Option<u32>,
+}
+
+/// Represents a sample of the resource usage of a VM.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct VmMetrics {
+    /// The CPU usage in percent.
+    pub cpu_percent: Option<f64>,
+    /// The memory used by the VM in bytes.
+    pub memory_used_bytes: Option<u64>,
+    /// The disk read throughput in bytes per second.
+    pub disk_read_bytes_per_sec: Option<u64>,
+    /// The disk write throughput in bytes per second.
+    pub disk_write_bytes_per_sec: Option<u64>,
+    /// The network receive throughput in bytes per second.
+    pub network_rx_bytes_per_sec: Option<u64>,
+    /// The network transmit throughput in bytes per second.
+    pub network_tx_bytes_per_sec: Option<u64>,
+}
+
+/// Represents a passthrough type.
+#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+pub enum PassthroughType {
+    /// A partition of a host GPU (e.g., Hyper-V GPU partitioning).
+    GpuPartition,
+    /// A whole PCI device (e.g., Hyper-V Discrete Device Assignment).
+    Pci,
+}
+
+/// Represents a host device passed through to a VM.
+#[derive(
+    Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default, Hash,
+)]
+pub struct PassthroughDevice {
+    pub id: Option<String>,
+    pub ty: Option<PassthroughType>,
+    /// The location of the device on the host.
+    ///
+    /// The format depends on the tool you are using (e.g., a PCI address `02:00.0` or a location path `PCIROOT(0)#PCI(0300)#PCI(0000)`).
+    pub host_location: Option<String>,
+    /// The location of the device in the guest.
+    pub guest_location: Option<String>,
+}
+
+/// Represents a transport protocol of a [`PortForward`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    /// Returns `tcp` or `udp`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = VmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(Self::Tcp),
+            "udp" => Ok(Self::Udp),
+            _ => vmerr!(ErrorKind::UnexpectedResponse(s.to_string())),
+        }
+    }
+}
+
+/// Represents a port forwarding rule which forwards a port of the host to a port of a guest behind NAT.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PortForward {
+    /// The name or the description of the rule.
+    pub name: Option<String>,
+    pub protocol: Protocol,
+    /// The host address to listen on. `None` listens on all addresses.
+    pub host_ip: Option<String>,
+    pub host_port: u16,
+    /// The guest address. `None` is the address of the VM.
+    pub guest_ip: Option<String>,
+    pub guest_port: u16,
+}
+
+impl PortForward {
+    pub fn new(protocol: Protocol, host_port: u16, guest_port: u16) -> Self {
+        Self {
+            name: None,
+            protocol,
+            host_ip: None,
+            host_port,
+            guest_ip: None,
+            guest_port,
+        }
+    }
+}
+
+/// Represents a USB device of a host.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Default)]
+pub struct UsbDevice {
+    /// The ID for [`UsbCmd::attach_usb_device`].
+    pub id: String,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
 /// Represents a VM power state.
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum VmPowerState {
     /// The VM is running.
     Running,
@@ -313,6 +2351,13 @@ pub enum VmPowerState {
 impl VmPowerState {
     #[inline]
     pub fn is_running(&self) -> bool { *self == Self::Running }
+
+    /// Returns `true` if the state is `state`, or `state` is [`VmPowerState::NotRunning`] and the VM is in a known state other than [`VmPowerState::Running`].
+    pub fn matches(&self, state: VmPowerState) -> bool {
+        *self == state
+            || (state == Self::NotRunning
+                && !matches!(self, Self::Running | Self::Unknown))
+    }
 }
 
 macro_rules! impl_setter {
@@ -331,3 +2376,551 @@ macro_rules! impl_setter {
         }
     };
 }
+
+macro_rules! impl_hooks {
+    () => {
+        /// Adds a callback called before a command is executed.
+        ///
+        /// See [`CommandHooks`] for details.
+        pub fn on_before_command<F>(&mut self, f: F) -> &mut Self
+        where
+            F: Fn(&str, &[String]) -> VmResult<()> + Send + Sync + 'static,
+        {
+            self.hooks.on_before_command(f);
+            self
+        }
+
+        /// Adds a callback called after a command is executed.
+        ///
+        /// See [`CommandHooks`] for details.
+        pub fn on_after_command<F>(&mut self, f: F) -> &mut Self
+        where
+            F: Fn(&str, &[String], Option<&VmError>) + Send + Sync + 'static,
+        {
+            self.hooks.on_after_command(f);
+            self
+        }
+
+        pub fn get_hooks(&self) -> &CommandHooks { &self.hooks }
+
+        /// Adds a callback called when a command writes a warning to stderr.
+        ///
+        /// See [`CommandHooks::classify_stderr`] for details.
+        pub fn on_warning<F>(&mut self, f: F) -> &mut Self
+        where
+            F: Fn(&str, &str) + Send + Sync + 'static,
+        {
+            self.hooks.on_warning(f);
+            self
+        }
+
+        /// Adds a pattern of a warning line written to stderr.
+        ///
+        /// See [`CommandHooks::classify_stderr`] for details.
+        pub fn add_warning_pattern<S: Into<String>>(
+            &mut self,
+            pattern: S,
+        ) -> &mut Self {
+            self.hooks.add_warning_pattern(pattern);
+            self
+        }
+
+        /// Sets the idempotent mode.
+        ///
+        /// See [`CommandHooks::idempotent`] for details.
+        pub fn idempotent(&mut self, idempotent: bool) -> &mut Self {
+            self.hooks.idempotent(idempotent);
+            self
+        }
+
+        /// Sets the power state validation mode.
+        ///
+        /// See [`CommandHooks::validate_power_state`] for details.
+        pub fn validate_power_state(&mut self, validate: bool) -> &mut Self {
+            self.hooks.validate_power_state(validate);
+            self
+        }
+    };
+}
+
+#[test]
+fn test_unsupported() {
+    struct Foo;
+    let e = VmError::from(ErrorKind::unsupported_with_hint::<Foo>(
+        "set_vm_by_path",
+        "Use set_vm_by_name instead",
+    ));
+    assert!(e.is_unsupported());
+    assert_eq!(
+        e.get_repr(),
+        &Repr::Simple(ErrorKind::UnsupportedCommand {
+            operation: "set_vm_by_path".to_string(),
+            backend: "Foo".to_string(),
+            hint: Some("Use set_vm_by_name instead".to_string()),
+        })
+    );
+    assert!(!VmError::from(ErrorKind::VmNotFound).is_unsupported());
+    assert_eq!(backend_name::<Option<Foo>>(), "Option");
+}
+
+#[test]
+fn test_command_hooks() {
+    let mut hooks = CommandHooks::new();
+    hooks.on_before_command(|op, _| {
+        if op == "unregistervm" {
+            vmerr!(ErrorKind::PermissionDenied)
+        } else {
+            Ok(())
+        }
+    });
+    assert_eq!(Ok(1), hooks.run("startvm", &[], || Ok(1)));
+    assert_eq!(
+        vmerr!(ErrorKind::PermissionDenied),
+        hooks.run("unregistervm", &[], || Ok(1))
+    );
+    hooks.on_before_command(|op, args| {
+        if op == "delete_vm" {
+            assert_eq!(args, ["controlvm", "vm1", "poweroff"]);
+            vmerr!(ErrorKind::PermissionDenied)
+        } else {
+            Ok(())
+        }
+    });
+    {
+        let _op = hooks.operation("delete_vm");
+        let _inner = hooks.operation("hard_stop");
+        assert_eq!(current_operation(), Some("delete_vm"));
+        let args = ["vm1".to_string(), "poweroff".to_string()];
+        assert_eq!(
+            vmerr!(ErrorKind::PermissionDenied),
+            hooks.run("controlvm", &args, || Ok(1))
+        );
+    }
+    assert_eq!(current_operation(), None);
+}
+
+#[test]
+fn test_classify_stderr() {
+    use std::sync::Mutex;
+    let warnings = Arc::new(Mutex::new(vec![]));
+    let mut hooks = CommandHooks::new();
+    hooks.add_warning_pattern("%...");
+    let w = warnings.clone();
+    hooks.on_warning(move |op, line| {
+        w.lock().unwrap().push(format!("{}: {}", op, line))
+    });
+    let stderr = "0%...10%...100%\n\nerror: failed\n";
+    assert_eq!(
+        hooks.classify_stderr("snapshot", stderr, true),
+        "error: failed"
+    );
+    assert_eq!(
+        *warnings.lock().unwrap(),
+        vec!["snapshot: 0%...10%...100%".to_string()]
+    );
+    assert_eq!(hooks.classify_stderr("snapshot", "0%...100%\n", true), "");
+    assert_eq!(hooks.classify_stderr("snapshot", stderr, false), stderr);
+
+    let warning = |message: &str| CommandWarning {
+        operation: "take_snapshot".to_string(),
+        message: message.to_string(),
+    };
+    let ((r, inner), outer) = collect_warnings(|| {
+        let _op = hooks.operation("take_snapshot");
+        hooks.report_warning("snapshot", "a");
+        collect_warnings(|| {
+            hooks.classify_stderr("snapshot", "0%...100%\nerror\n", true)
+        })
+    });
+    assert_eq!(r, "error");
+    assert_eq!(inner, [warning("0%...100%")]);
+    assert_eq!(outer, [warning("a"), warning("0%...100%")]);
+    assert_eq!(collect_warnings(|| ()).1, []);
+}
+
+#[test]
+fn test_idempotent() {
+    let mut hooks = CommandHooks::new();
+    let running =
+        || vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
+    let stopped =
+        || vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Stopped));
+    let not_found = || vmerr!(ErrorKind::SnapshotNotFound);
+    assert_eq!(running(), hooks.settle_started(running));
+    assert_eq!(stopped(), hooks.settle_stopped(stopped));
+    assert_eq!(not_found(), hooks.settle_deleted(not_found));
+    hooks.idempotent(true);
+    assert_eq!(Ok(()), hooks.settle_started(running));
+    assert_eq!(stopped(), hooks.settle_started(stopped));
+    assert_eq!(Ok(()), hooks.settle_stopped(stopped));
+    assert_eq!(running(), hooks.settle_stopped(running));
+    assert_eq!(Ok(()), hooks.settle_deleted(not_found));
+    assert_eq!(
+        vmerr!(ErrorKind::PermissionDenied),
+        hooks.settle_deleted(|| vmerr!(ErrorKind::PermissionDenied))
+    );
+}
+
+#[test]
+fn test_snapshot_tree() {
+    let sn = |id: &str, parent_id: Option<&str>| Snapshot {
+        id: Some(id.to_string()),
+        name: Some(id.to_string()),
+        detail: None,
+        parent_id: parent_id.map(|x| x.to_string()),
+        created_at: None,
+        is_current: None,
+        online: None,
+    };
+    let tree = SnapshotTree::from_snapshots(
+        vec![
+            sn("a", None),
+            sn("b", Some("a")),
+            sn("c", Some("b")),
+            sn("d", Some("a")),
+            sn("e", Some("deleted")),
+        ],
+        Some("c"),
+    );
+    assert_eq!(tree.current, Some(sn("c", Some("b"))));
+    assert_eq!(tree.roots.len(), 2);
+    assert_eq!(tree.roots[0].children.len(), 2);
+    assert_eq!(
+        tree.roots[0].children[0].children[0].snapshot,
+        sn("c", None)
+    );
+    assert_eq!(tree.roots[1].snapshot, sn("e", None));
+    let ids: Vec<_> = tree
+        .snapshots()
+        .iter()
+        .map(|x| x.id.as_deref().unwrap())
+        .collect();
+    assert_eq!(ids, ["a", "b", "c", "d", "e"]);
+    let tree = SnapshotTree::from_snapshots(vec![], Some("a"));
+    assert!(tree.roots.is_empty());
+    assert_eq!(tree.current, None);
+}
+
+#[test]
+fn test_guest_ip_address() {
+    let parse = |x| GuestIpAddress::parse(Some("eth0"), x).map(|x| x.address);
+    assert_eq!(parse("192.168.0.2"), Some([192, 168, 0, 2].into()));
+    assert_eq!(parse("192.168.0.2/24"), Some([192, 168, 0, 2].into()));
+    assert_eq!(
+        parse("fe80::1%eth0/64"),
+        Some("fe80::1".parse::<IpAddr>().unwrap())
+    );
+    assert_eq!(parse("unknown"), None);
+    assert_eq!(
+        GuestIpAddress::parse(Some("eth0"), "::1")
+            .unwrap()
+            .interface,
+        Some("eth0".to_string())
+    );
+}
+
+#[test]
+fn test_check_power_state() {
+    use VmPowerState::*;
+    let mut hooks = CommandHooks::new();
+    assert_eq!(
+        Ok(()),
+        hooks.check_power_state(&[Running], || panic!("not validating"))
+    );
+    hooks.validate_power_state(true);
+    assert_eq!(Ok(()), hooks.check_power_state(&[Running], || Ok(Running)));
+    assert_eq!(Ok(()), hooks.check_power_state(&[Running], || Ok(Unknown)));
+    assert_eq!(
+        vmerr!(ErrorKind::InvalidPowerState(Suspended)),
+        hooks.check_power_state(&[Running, Paused], || Ok(Suspended))
+    );
+    // The error is settled in the idempotent mode.
+    hooks.idempotent(true);
+    assert_eq!(
+        Ok(()),
+        hooks.settle_stopped(|| {
+            hooks.check_power_state(&[Running], || Ok(Stopped))?;
+            vmerr!(ErrorKind::ExecutionFailed("unreachable".to_string()))
+        })
+    );
+}
+
+#[test]
+fn test_name_match() {
+    assert!(NameMatch::Exact.is_match("Win10", "Win10"));
+    assert!(!NameMatch::Exact.is_match("win10", "Win10"));
+    assert!(NameMatch::IgnoreCase.is_match(" win10 ", "Win10"));
+    assert!(NameMatch::Prefix.is_match("win", "Win10"));
+    assert!(!NameMatch::Prefix.is_match("10", "Win10"));
+    assert!(NameMatch::Glob.is_match("w*1?", "Win10"));
+    assert!(NameMatch::Glob.is_match("*", ""));
+    assert!(!NameMatch::Glob.is_match("w*1", "Win10"));
+    let vm = |name: &str| Vm {
+        id: Some(name.to_string()),
+        name: Some(name.to_string()),
+        path: None,
+    };
+    let vms = vec![vm("Ubuntu"), vm("Ubuntu 22.04"), vm("Win10")];
+    let f = |p, m| find_vm_by_name(vms.clone(), p, m);
+    assert_eq!(f("ubuntu", NameMatch::Prefix), Ok(vm("Ubuntu")));
+    assert_eq!(f("ubuntu?22*", NameMatch::Glob), Ok(vm("Ubuntu 22.04")));
+    assert_eq!(
+        f("ubuntu ", NameMatch::Exact),
+        vmerr!(ErrorKind::VmNotFound)
+    );
+    assert_eq!(f("*2*", NameMatch::Glob), Ok(vm("Ubuntu 22.04")));
+    assert_eq!(
+        f("*u*", NameMatch::Glob),
+        vmerr!(ErrorKind::InvalidParameter(
+            "*u* matches multiple VMs".to_string()
+        ))
+    );
+    assert!(uuid_eq(
+        "{0F6E5A6C-1B2B-4C3D-8E9F-A0B1C2D3E4F5}",
+        "0f6e5a6c-1b2b-4c3d-8e9f-a0b1c2d3e4f5"
+    ));
+}
+
+#[test]
+fn test_guest_credentials() {
+    let mut c = GuestCredentials::new();
+    assert!(c.is_empty());
+    c.add_for_vm("win10", GuestCredential::new("vm", "pw1"))
+        .add_for_os_family("Windows", GuestCredential::new("admin", "pw2"))
+        .add_for_os_family("Ubuntu", GuestCredential::new("ubuntu", "pw3"));
+    assert_eq!(c.get_for_vm("win10").unwrap().username, "vm");
+    assert_eq!(c.get_for_vm("Win10"), None);
+    assert_eq!(
+        c.get_for_os("Windows 10 (64-bit)").unwrap().username,
+        "admin"
+    );
+    assert_eq!(c.get_for_os("ubuntu (64-bit)").unwrap().username, "ubuntu");
+    assert_eq!(c.get_for_os("Debian"), None);
+    let m = c.map(
+        |x| x.to_uppercase(),
+        |x| GuestCredential {
+            password: "***".to_string(),
+            ..x.clone()
+        },
+    );
+    assert_eq!(m.get_for_vm("WIN10").unwrap().password, "***");
+    assert_eq!(m.get_for_os("Windows").unwrap().password, "***");
+}
+
+#[test]
+fn test_guest_temp_dir() {
+    use std::cell::RefCell;
+    #[derive(Default)]
+    struct Dummy {
+        removed: RefCell<Vec<String>>,
+    }
+    impl GuestTempDirCmd for Dummy {
+        fn create_temp_dir_in_guest(&self) -> VmResult<String> {
+            Ok("/tmp/hvctrl".to_string())
+        }
+
+        fn remove_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+            self.removed.borrow_mut().push(guest_path.to_string());
+            Ok(())
+        }
+    }
+    let d = Dummy::default();
+    {
+        let t = GuestTempDir::new(&d).unwrap();
+        assert_eq!(t.path(), "/tmp/hvctrl");
+    }
+    assert_eq!(d.removed.borrow().len(), 1);
+    GuestTempDir::new(&d).unwrap().close().unwrap();
+    assert_eq!(d.removed.borrow().len(), 2);
+    assert_eq!(GuestTempDir::new(&d).unwrap().keep(), "/tmp/hvctrl");
+    assert_eq!(d.removed.borrow().len(), 2);
+}
+
+#[test]
+fn test_parse_reg_query() {
+    let q = |ty: &str, data: &str| {
+        parse_reg_query(&format!(
+            "\r\nHKEY_LOCAL_MACHINE\\Software\\hvctrl\r\n    Name    {}    \
+             {}\r\n\r\n",
+            ty, data
+        ))
+    };
+    assert_eq!(
+        Some(RegistryValue::Sz("a  b".to_string())),
+        q("REG_SZ", "a  b")
+    );
+    assert_eq!(Some(RegistryValue::Dword(26)), q("REG_DWORD", "0x1a"));
+    assert_eq!(Some(RegistryValue::Qword(1)), q("REG_QWORD", "0x1"));
+    assert_eq!(
+        Some(RegistryValue::MultiSz(vec![
+            "a".to_string(),
+            "b".to_string()
+        ])),
+        q("REG_MULTI_SZ", "a\\0b")
+    );
+    assert_eq!(
+        Some(RegistryValue::Binary(vec![0x01, 0xab])),
+        q("REG_BINARY", "01AB")
+    );
+    assert_eq!(None, q("REG_BINARY", "01A"));
+    assert_eq!(
+        None,
+        parse_reg_query("ERROR: The system was unable to find")
+    );
+    assert_eq!(
+        "1\\02",
+        RegistryValue::MultiSz(vec!["1".to_string(), "2".to_string()])
+            .to_reg_data()
+    );
+}
+
+#[test]
+fn test_parse_reg_export() {
+    let s = [
+        "Windows Registry Editor Version 5.00",
+        "",
+        r"[HKEY_LOCAL_MACHINE\Software\hvctrl]",
+        r#"@="default""#,
+        r#""Name"="a \"b\" C:\\x ü""#,
+        r#""Dw"=dword:0000001a"#,
+        r#""Qw"=hex(b):01,00,00,00,00,00,00,00"#,
+        r#""Ms"=hex(7):61,00,00,00,62,00,00,00,00,00"#,
+        r#""Ex"=hex(2):25,00,00,00"#,
+        r#""Bin"=hex:01,ab,\"#,
+        "  02",
+        "",
+        r"[HKEY_LOCAL_MACHINE\Software\hvctrl\sub]",
+        r#""Sub"="x""#,
+    ]
+    .join("\r\n");
+    let bom: Vec<u8> = [0xfeff]
+        .iter()
+        .copied()
+        .chain(s.encode_utf16())
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let s = decode_utf16le(&bom);
+    let q = |x: &str| parse_reg_export(&s, x);
+    assert_eq!(Some(RegistryValue::Sz("default".to_string())), q(""));
+    assert_eq!(
+        Some(RegistryValue::Sz(r#"a "b" C:\x ü"#.to_string())),
+        q("name")
+    );
+    assert_eq!(Some(RegistryValue::Dword(26)), q("Dw"));
+    assert_eq!(Some(RegistryValue::Qword(1)), q("Qw"));
+    assert_eq!(
+        Some(RegistryValue::MultiSz(vec![
+            "a".to_string(),
+            "b".to_string()
+        ])),
+        q("Ms")
+    );
+    assert_eq!(Some(RegistryValue::ExpandSz("%".to_string())), q("Ex"));
+    assert_eq!(
+        Some(RegistryValue::Binary(vec![0x01, 0xab, 0x02])),
+        q("Bin")
+    );
+    assert_eq!(None, q("Sub"));
+    assert!(check_reg_arg("%PATH%").is_err());
+    assert!(check_reg_arg(r"HKLM\Software").is_ok());
+}
+
+#[test]
+fn test_wait() {
+    use VmPowerState::*;
+    assert!(Running.matches(Running));
+    assert!(Suspended.matches(NotRunning));
+    assert!(!Running.matches(NotRunning));
+    assert!(!Unknown.matches(NotRunning));
+    assert!(!NotRunning.matches(Stopped));
+    let ip = |s: &str| is_assigned_ip(&s.parse().unwrap());
+    assert!(ip("192.168.0.2"));
+    assert!(ip("2001:db8::1"));
+    assert!(!ip("127.0.0.1"));
+    assert!(!ip("169.254.1.1"));
+    assert!(!ip("fe80::1"));
+    assert!(!ip("::"));
+    let opts = WaitOptions {
+        interval: Duration::ZERO,
+        timeout: None,
+    };
+    let mut n = 0;
+    assert_eq!(
+        opts.poll(|| {
+            n += 1;
+            Ok((n == 3).then(|| n))
+        }),
+        Ok(3)
+    );
+    let opts = WaitOptions {
+        interval: Duration::from_millis(1),
+        timeout: Some(Duration::from_millis(5)),
+    };
+    assert_eq!(opts.poll(|| Ok(None::<()>)), vmerr!(ErrorKind::Timeout));
+}
+
+#[test]
+fn test_template() {
+    use std::cell::RefCell;
+    struct Template {
+        fail_at: usize,
+        destroyed: RefCell<Vec<String>>,
+    }
+    impl CloneCmd for Template {
+        fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+            assert_eq!(options.ty, CloneType::Linked);
+            assert_eq!(options.snapshot.as_deref(), Some("base"));
+            if options.name == format!("lab{}", self.fail_at) {
+                return vmerr!(ErrorKind::FileError("disk".to_string()));
+            }
+            Ok(Vm {
+                name: Some(options.name.clone()),
+                ..Default::default()
+            })
+        }
+    }
+    impl TemplateCmd for Template {
+        fn destroy_vms(&self, vms: &[Vm]) -> VmResult<()> {
+            self.destroyed
+                .borrow_mut()
+                .extend(vms.iter().filter_map(|x| x.name.clone()));
+            Ok(())
+        }
+    }
+    let options = TemplateOptions::new("base", "lab", 3);
+    let t = Template {
+        fail_at: 0,
+        destroyed: RefCell::new(vec![]),
+    };
+    let vms = t.create_linked_clones(&options).unwrap();
+    let names: Vec<_> = vms.iter().filter_map(|x| x.name.as_deref()).collect();
+    assert_eq!(names, ["lab1", "lab2", "lab3"]);
+    assert!(t.destroyed.borrow().is_empty());
+    let t = Template {
+        fail_at: 3,
+        destroyed: RefCell::new(vec![]),
+    };
+    assert!(t.create_linked_clones(&options).is_err());
+    assert_eq!(*t.destroyed.borrow(), ["lab1", "lab2"]);
+}
+
+#[test]
+fn test_key() {
+    use Key::*;
+    assert_eq!(Char('a').scancode(), Some(0x1e));
+    assert_eq!(Char('Z').scancode(), Some(0x2c));
+    assert_eq!(Char('1').scancode(), Some(0x02));
+    assert_eq!(Char('\\').scancode(), Some(0x2b));
+    assert_eq!(Char('/').scancode(), Some(0x35));
+    assert_eq!(Char('あ').scancode(), None);
+    assert_eq!(F(10).scancode(), Some(0x44));
+    assert_eq!(F(13).scancode(), None);
+    assert_eq!(
+        Key::to_scancodes(&[Ctrl, Alt, Delete]),
+        Ok(vec![0x1d, 0x38, 0xe0, 0x53, 0xe0, 0xd3, 0xb8, 0x9d])
+    );
+    assert!(Key::to_scancodes(&[Char('!')]).is_err());
+    assert_eq!(Char('a').virtual_key_code(), Some(0x41));
+    assert_eq!(Char(' ').virtual_key_code(), Some(0x20));
+    assert_eq!(F(12).virtual_key_code(), Some(0x7b));
+}
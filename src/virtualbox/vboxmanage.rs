@@ -1,853 +1,7728 @@
 // Copyright takubokudori.
 // This source code is licensed under the MIT or Apache-2.0 license.
 //! [VBoxManage](https://www.virtualbox.org/manual/ch08.html) controller.
-use crate::{exec_cmd, types::*};
+use crate::{
+    exec_cmd, exec_cmd_lines, exec_cmd_with_input, exec_cmd_with_stderr,
+    hostnet::{self, HostNetwork, HostNetworkCmd, HostNetworkKind},
+    parse_timestamp, read_host_temp_file, sanitized_args,
+    tool_path_from_env_or_dirs,
+    types::*,
+    CmdOutput,
+};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
     process::Command,
-    time::{Duration, Instant},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
 
-#[derive(Clone, Debug)]
-pub struct VBoxManage {
-    executable_path: String,
-    vm_name: Option<String>,
-    guest_username: Option<String>,
-    guest_password: Option<String>,
-    guest_password_file: Option<String>,
-    guest_domain: Option<String>,
+/// Represents a host network interface listed by `VBoxManage list bridgedifs` or `VBoxManage list hostonlyifs`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HostInterface {
+    pub name: String,
+    pub guid: Option<String>,
+    pub dhcp: bool,
+    pub ip_address: Option<String>,
+    pub network_mask: Option<String>,
+    pub hardware_address: Option<String>,
+    pub status: Option<String>,
 }
 
-impl Default for VBoxManage {
-    fn default() -> Self { Self::new() }
+/// Represents a NAT network listed by `VBoxManage list natnets`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NatNetwork {
+    pub name: String,
+    /// The network in CIDR notation, e.g., `10.0.2.0/24`.
+    pub network: Option<String>,
+    pub gateway: Option<String>,
+    pub dhcp: bool,
+    pub enabled: bool,
 }
 
-#[cfg(windows)]
-pub const DEFAULT_VBOXMANAGE_PATH: &str =
-    r"C:\Program Files\Oracle\VirtualBox\VBoxManage.exe";
-#[cfg(not(windows))]
-pub const DEFAULT_VBOXMANAGE_PATH: &str = "vboxmanage";
+/// Represents a DHCP server listed by `VBoxManage list dhcpservers`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DhcpServer {
+    /// The network name, e.g., `HostInterfaceNetworking-vboxnet0` for the host-only interface `vboxnet0`.
+    pub network_name: String,
+    /// The address of the server.
+    pub ip: Option<String>,
+    pub netmask: Option<String>,
+    pub lower_ip: Option<String>,
+    pub upper_ip: Option<String>,
+    pub enabled: bool,
+}
 
-#[cfg(windows)]
-const LINE_FEED: &str = "\r\n";
-#[cfg(not(windows))]
-const LINE_FEED: &str = "\n";
+/// Represents settings of a DHCP server set by `VBoxManage dhcpserver modify`.
+///
+/// `None` fields are left unchanged.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DhcpServerSettings {
+    /// The address of the server with `--ip`.
+    pub ip: Option<String>,
+    /// The netmask with `--netmask`.
+    pub netmask: Option<String>,
+    /// The lowest address to lease with `--lowerip`.
+    pub lower_ip: Option<String>,
+    /// The highest address to lease with `--upperip`.
+    pub upper_ip: Option<String>,
+    /// Enables the server with `--enable` or disables it with `--disable`.
+    pub enabled: Option<bool>,
+}
 
-impl VBoxManage {
-    pub fn new() -> Self {
-        Self {
-            executable_path: DEFAULT_VBOXMANAGE_PATH.to_string(),
-            vm_name: None,
-            guest_username: None,
-            guest_password: None,
-            guest_password_file: None,
-            guest_domain: None,
+impl DhcpServerSettings {
+    fn to_args(&self) -> Vec<String> {
+        let opts = [
+            ("ip", &self.ip),
+            ("netmask", &self.netmask),
+            ("lowerip", &self.lower_ip),
+            ("upperip", &self.upper_ip),
+        ];
+        let mut ret = vec![];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.push(format!("--{}", opt));
+                ret.push(value.clone());
+            }
         }
+        match self.enabled {
+            Some(true) => ret.push("--enable".to_string()),
+            Some(false) => ret.push("--disable".to_string()),
+            None => {}
+        }
+        ret
     }
+}
 
-    impl_setter!(
-        /// Sets the path to VBoxManage.
-        executable_path: String
-    );
-
-    pub fn get_executable_path(&self) -> &str { &self.executable_path }
+/// Represents what a global USB device filter does with the matched devices.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum UsbFilterAction {
+    /// Makes the host ignore the devices.
+    Ignore,
+    /// Holds the devices for VMs.
+    Hold,
+}
 
-    /// Sets the VM name to be manipulated.
-    pub fn vm_name<T: Into<Option<String>>>(
-        &mut self,
-        vm_name: T,
-    ) -> &mut Self {
-        self.vm_name = vm_name.into();
-        self
+impl UsbFilterAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ignore => "ignore",
+            Self::Hold => "hold",
+        }
     }
+}
 
-    pub fn get_vm_name(&self) -> Option<&str> { self.vm_name.as_deref() }
-
-    impl_setter!(@opt
-    /// Sets the guest username for login.
-        guest_username: String
-    );
+/// Represents a USB device filter set by `VBoxManage usbfilter`.
+///
+/// The matched host devices are attached to the VM whenever it is running. `None` fields match any device.
+/// The ID fields are hexadecimal, e.g., `046d`, and may be patterns such as `0000-00ff`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UsbFilter {
+    pub name: String,
+    pub active: bool,
+    pub vendor_id: Option<String>,
+    pub product_id: Option<String>,
+    pub revision: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+    /// Matches remote devices of VRDE clients if `Some(true)` and local devices if `Some(false)`. VM filters only.
+    pub remote: Option<bool>,
+    /// Global filters only, which must have an action.
+    pub action: Option<UsbFilterAction>,
+}
 
-    pub fn get_guest_username(&self) -> Option<&str> {
-        self.guest_username.as_deref()
+impl UsbFilter {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            active: true,
+            ..Default::default()
+        }
     }
 
-    impl_setter!(@opt
-    /// Sets the guest password for login.
-        guest_password: String
-    );
+    /// Creates an active filter matching the vendor ID, the product ID and the serial number of `device`.
+    pub fn from_device<S: Into<String>>(name: S, device: &UsbDevice) -> Self {
+        Self {
+            vendor_id: device.vendor_id.map(|x| format!("{:04x}", x)),
+            product_id: device.product_id.map(|x| format!("{:04x}", x)),
+            serial_number: device.serial_number.clone(),
+            ..Self::new(name)
+        }
+    }
 
-    pub fn get_guest_password(&self) -> Option<&str> {
-        self.guest_password.as_deref()
+    fn to_args(&self) -> Vec<String> {
+        let yes_no = |x: bool| if x { "yes" } else { "no" }.to_string();
+        let opts = [
+            ("name", Some(self.name.clone())),
+            ("active", Some(yes_no(self.active))),
+            ("vendorid", self.vendor_id.clone()),
+            ("productid", self.product_id.clone()),
+            ("revision", self.revision.clone()),
+            ("manufacturer", self.manufacturer.clone()),
+            ("product", self.product.clone()),
+            ("serialnumber", self.serial_number.clone()),
+            ("remote", self.remote.map(yes_no)),
+            ("action", self.action.map(|x| x.as_str().to_string())),
+        ];
+        let mut ret = vec![];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.push(format!("--{}", opt));
+                ret.push(value);
+            }
+        }
+        ret
     }
+}
 
-    impl_setter!(@opt
-    /// Sets the absolute path to the guest password file for login.
-        guest_password_file: String
-    );
+/// Represents what a bandwidth group limits.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BandwidthGroupType {
+    Disk,
+    Network,
+}
 
-    pub fn get_guest_password_file(&self) -> Option<&str> {
-        self.guest_password_file.as_deref()
+impl BandwidthGroupType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disk => "disk",
+            Self::Network => "network",
+        }
     }
+}
 
-    impl_setter!(@opt
-    /// Sets the guest domain for Windows guests.
-        guest_domain: String
-    );
+/// Represents a bandwidth group of a VM listed by `VBoxManage bandwidthctl list`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthGroup {
+    pub name: String,
+    pub ty: BandwidthGroupType,
+    /// The limit in bytes per second. `0` disables the limit.
+    pub max_bytes_per_sec: u64,
+}
 
-    pub fn get_guest_domain(&self) -> Option<&str> {
-        self.guest_domain.as_deref()
-    }
+/// Represents how VRDE clients are authenticated.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum VrdeAuthType {
+    /// No authentication.
+    Null,
+    /// Authentication by the host with the external authentication library.
+    External,
+    /// Authentication by the guest with the Guest Additions.
+    Guest,
+}
 
-    fn build_auth(&self) -> Vec<&str> {
-        let mut v = Vec::with_capacity(8);
-        if let Some(x) = &self.guest_username {
-            v.extend(&["--username", x]);
-        }
-        if let Some(x) = &self.guest_password {
-            v.extend(&["--password", x]);
-        }
-        if let Some(x) = &self.guest_password_file {
-            v.extend(&["--passwordfile", x]);
+impl VrdeAuthType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::External => "external",
+            Self::Guest => "guest",
         }
-        if let Some(x) = &self.guest_domain {
-            v.extend(&["--domain", x]);
-        }
-        v
     }
 
-    #[inline]
-    fn handle_error(s: &str) -> VmError {
-        use ErrorKind::*;
-        use VmPowerState::*;
-        starts_err!(s, "Could not find a registered machine named", VmNotFound);
-        starts_err!(s, "Could not find a snapshot named ", SnapshotNotFound);
-        starts_err!(
-            s,
-            "This machine does not have any snapshots",
-            SnapshotNotFound
-        );
-        starts_err!(
-            s,
-            "The specified user was not able to logon on guest",
-            GuestAuthenticationFailed
-        );
-        starts_err!(
-            s,
-            "Waiting for guest process failed: The guest execution service is \
-             not ready (yet)",
-            ServiceIsNotRunning
-        );
-        starts_err!(
-            s,
-            "Error starting guest session (current status is:",
-            ServiceIsNotRunning
-        );
-        if s.starts_with("FsObjQueryInfo failed on") || s.starts_with("File ") {
-            let s = s.lines().last().unwrap();
-            return VmError::from(FileError(
-                s[s.rfind(':').unwrap() + 2..].to_string(),
-            ));
-        }
+    fn from_name(s: &str) -> Option<Self> {
+        Some(match s {
+            "null" => Self::Null,
+            "external" => Self::External,
+            "guest" => Self::Guest,
+            _ => return None,
+        })
+    }
+}
 
-        if let Some(s) = s.strip_prefix("RTPathQueryInfo failed on ") {
-            return VmError::from(FileError(s.to_string()));
+/// Represents settings of the VRDE server (remote display) of a VM set by `VBoxManage modifyvm`.
+///
+/// `None` fields are left unchanged.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VrdeSettings {
+    /// Enables the server with `--vrde`.
+    pub enabled: Option<bool>,
+    /// The candidate ports with `--vrdeport`, e.g., `3389` or `5000-5050,5100`. The server listens on the first free one.
+    pub ports: Option<String>,
+    /// The address the server binds to with `--vrdeaddress`.
+    pub address: Option<String>,
+    pub auth_type: Option<VrdeAuthType>,
+    /// Allows several clients to connect at the same time with `--vrdemulticon`.
+    pub multi_connection: Option<bool>,
+}
+
+impl VrdeSettings {
+    fn to_args(&self) -> Vec<String> {
+        let on_off = |x: bool| if x { "on" } else { "off" }.to_string();
+        let opts = [
+            ("vrde", self.enabled.map(on_off)),
+            ("vrdeport", self.ports.clone()),
+            ("vrdeaddress", self.address.clone()),
+            (
+                "vrdeauthtype",
+                self.auth_type.map(|x| x.as_str().to_string()),
+            ),
+            ("vrdemulticon", self.multi_connection.map(on_off)),
+        ];
+        let mut ret = vec![];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.push(format!("--{}", opt));
+                ret.push(value);
+            }
         }
+        ret
+    }
+}
 
-        if let Some(s) = s.strip_prefix("Invalid machine state: ") {
-            starts_err!(s, "PoweredOff", InvalidPowerState(Stopped));
-            starts_err!(s, "Paused", InvalidPowerState(Paused));
+/// Represents the state of the VRDE server of a VM read from `VBoxManage showvminfo`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VrdeInfo {
+    pub enabled: bool,
+    /// The configured candidate ports.
+    pub ports: Option<String>,
+    pub address: Option<String>,
+    pub auth_type: Option<VrdeAuthType>,
+    pub multi_connection: bool,
+    /// The port the server listens on. `None` if the VM is not running or the server is not listening.
+    pub active_port: Option<u16>,
+}
+
+/// Represents what the autostart service does with a VM when the host shuts down.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum AutostopType {
+    Disabled,
+    SaveState,
+    PowerOff,
+    AcpiShutdown,
+}
+
+impl AutostopType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disabled => "disabled",
+            Self::SaveState => "savestate",
+            Self::PowerOff => "poweroff",
+            Self::AcpiShutdown => "acpishutdown",
         }
-        if let Some(s) = s.strip_prefix("Machine in invalid state ") {
-            starts_err!(s, "1 -- powered off", InvalidPowerState(Stopped));
-            starts_err!(s, "2 -- saved", InvalidPowerState(Suspended));
+    }
+}
+
+/// Represents autostart settings of a VM set by `VBoxManage modifyvm`.
+///
+/// `None` fields are left unchanged.
+/// The host also needs the autostart service and, on Linux and macOS, the autostart database set by [`VBoxManage::set_autostart_db_path`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AutostartSettings {
+    /// Starts the VM when the host boots with `--autostart-enabled`.
+    pub enabled: Option<bool>,
+    /// The delay before the VM starts with `--autostart-delay`, in seconds.
+    pub delay: Option<Duration>,
+    /// What happens to the VM when the host shuts down with `--autostop-type`.
+    pub stop_type: Option<AutostopType>,
+}
+
+impl AutostartSettings {
+    fn to_args(&self) -> Vec<String> {
+        let on_off = |x: bool| if x { "on" } else { "off" }.to_string();
+        let opts = [
+            ("autostart-enabled", self.enabled.map(on_off)),
+            (
+                "autostart-delay",
+                self.delay.map(|x| x.as_secs().to_string()),
+            ),
+            (
+                "autostop-type",
+                self.stop_type.map(|x| x.as_str().to_string()),
+            ),
+        ];
+        let mut ret = vec![];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.push(format!("--{}", opt));
+                ret.push(value);
+            }
         }
-        if s.ends_with(" is not currently running")
-            || s.contains("is not running")
-        {
-            return VmError::from(ErrorKind::InvalidPowerState(NotRunning));
+        ret
+    }
+}
+
+/// Represents a guest OS type listed by `VBoxManage list ostypes`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OsType {
+    /// The ID for `--ostype`, e.g., `Ubuntu_64`.
+    pub id: String,
+    pub description: Option<String>,
+    pub family_id: Option<String>,
+    pub family_description: Option<String>,
+    pub is_64bit: bool,
+}
+
+/// Represents the host information listed by `VBoxManage list hostinfo`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    /// The description of the first processor.
+    pub cpu_description: Option<String>,
+    pub cpu_count: Option<u32>,
+    pub cpu_core_count: Option<u32>,
+    pub memory_mb: Option<u64>,
+    pub memory_available_mb: Option<u64>,
+    pub hw_virtualization: bool,
+    pub nested_hw_virtualization: bool,
+}
+
+/// Represents the global settings and limits listed by `VBoxManage list systemproperties`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SystemProperties {
+    pub api_version: Option<String>,
+    pub min_guest_memory_mb: Option<u64>,
+    pub max_guest_memory_mb: Option<u64>,
+    pub min_guest_vram_mb: Option<u64>,
+    pub max_guest_vram_mb: Option<u64>,
+    pub min_guest_cpus: Option<u32>,
+    pub max_guest_cpus: Option<u32>,
+    pub max_guest_monitors: Option<u32>,
+    pub max_disk_size_bytes: Option<u64>,
+    /// The directory where the directories of new VMs are created.
+    pub default_machine_folder: Option<String>,
+    pub default_hard_disk_format: Option<String>,
+    pub guest_additions_iso: Option<String>,
+    /// The directory of the autostart database. `None` if autostart is not set up.
+    pub autostart_db_path: Option<String>,
+}
+
+/// Represents a cloud profile listed by `VBoxManage list cloudprofiles`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CloudProfile {
+    pub name: String,
+    pub provider_id: Option<String>,
+    /// The profile properties, e.g., `("region", "us-ashburn-1")`.
+    pub properties: Vec<(String, String)>,
+}
+
+/// Represents a medium type set by `VBoxManage modifymedium --type`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum MediumType {
+    Normal,
+    /// Not affected by snapshots.
+    Writethrough,
+    /// Changes are written to a differencing image, which is discarded when the VM starts if autoreset is on.
+    Immutable,
+    Shareable,
+    Readonly,
+    MultiAttach,
+}
+
+impl MediumType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Writethrough => "writethrough",
+            Self::Immutable => "immutable",
+            Self::Shareable => "shareable",
+            Self::Readonly => "readonly",
+            Self::MultiAttach => "multiattach",
         }
-        if s.lines().next().unwrap().ends_with(
-            "is already locked by a session (or being locked or unlocked)",
-        ) {
-            return VmError::from(ErrorKind::InvalidPowerState(Running));
+    }
+
+    fn from_name(s: &str) -> Option<Self> {
+        Some(match s {
+            "normal" => Self::Normal,
+            "writethrough" => Self::Writethrough,
+            "immutable" => Self::Immutable,
+            "shareable" => Self::Shareable,
+            "readonly" => Self::Readonly,
+            "multiattach" => Self::MultiAttach,
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for MediumType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+/// Represents a kind of media registered to VirtualBox.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum MediumKind {
+    /// Disk images listed by `list hdds`.
+    Disk,
+    /// Optical disc images listed by `list dvds`.
+    Dvd,
+    /// Floppy images listed by `list floppies`.
+    Floppy,
+}
+
+impl MediumKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Disk => "disk",
+            Self::Dvd => "dvd",
+            Self::Floppy => "floppy",
         }
-        VmError::from(Repr::Unknown(format!("Unknown error: {}", s)))
     }
 
-    /// Checks `s` UUID.
-    pub fn is_uuid(s: &str) -> bool {
-        regex::Regex::new(
-            r#"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$"#
-        )
-            .unwrap()
-            .is_match(s)
+    fn list_arg(&self) -> &'static str {
+        match self {
+            Self::Disk => "hdds",
+            Self::Dvd => "dvds",
+            Self::Floppy => "floppies",
+        }
     }
+}
 
-    #[inline]
-    fn check(s: String) -> VmResult<String> {
-        const ERROR_STR: &str = "vboxmanage.exe: error: ";
-        if (&s[..ERROR_STR.len()])
-            .to_ascii_lowercase()
-            .starts_with(ERROR_STR)
-        {
-            Err(Self::handle_error(s[ERROR_STR.len()..].trim()))
-        } else {
-            Ok(s)
+/// Represents a medium listed by `VBoxManage list hdds`, `VBoxManage list dvds` or `VBoxManage list floppies`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Medium {
+    pub id: String,
+    /// The UUID of the parent of a differencing disk image. `None` for base images.
+    pub parent_id: Option<String>,
+    /// e.g., `created` or `inaccessible`.
+    pub state: Option<String>,
+    pub ty: Option<MediumType>,
+    /// The path to the image.
+    pub location: Option<String>,
+    /// e.g., `VDI` or `RAW`.
+    pub format: Option<String>,
+    pub capacity_mb: Option<u64>,
+    pub encrypted: bool,
+    /// The UUIDs of the VMs using the medium.
+    pub vm_ids: Vec<String>,
+}
+
+/// Represents options of [`VBoxManage::create_medium`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CreateMediumOptions {
+    /// The size with `--size`. Not needed for a differencing image.
+    pub size_mb: Option<u64>,
+    /// The format with `--format`, e.g., `VDI`, `VMDK` or `VHD`. Chosen from the extension of the path if `None`.
+    pub format: Option<String>,
+    /// Allocates the whole size on creation with `--variant Fixed`.
+    pub fixed: bool,
+    /// Creates a differencing image of this image, which is a UUID or a path, with `--diffparent`.
+    pub diff_parent: Option<String>,
+}
+
+impl CreateMediumOptions {
+    fn to_args(&self) -> Vec<String> {
+        let opts = [
+            ("size", self.size_mb.map(|x| x.to_string())),
+            ("format", self.format.clone()),
+            ("variant", self.fixed.then(|| "Fixed".to_string())),
+            ("diffparent", self.diff_parent.clone()),
+        ];
+        let mut ret = vec![];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.push(format!("--{}", opt));
+                ret.push(value);
+            }
         }
+        ret
     }
+}
+
+/// Represents flags of the NAT engine aliasing set by `VBoxManage modifyvm --nataliasmode`.
+///
+/// All flags off means the default mode.
+#[derive(
+    Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub struct NatAliasMode {
+    /// Logs the aliasing.
+    pub log: bool,
+    /// Switches off the aliasing and makes the NAT transparent.
+    pub proxy_only: bool,
+    /// Tries not to change the source ports.
+    pub same_ports: bool,
+}
 
-    fn exec(cmd: &mut Command) -> VmResult<String> {
-        let (stdout, stderr) = exec_cmd(cmd)?;
-        if !stderr.is_empty() {
-            Self::check(stderr)
+impl NatAliasMode {
+    fn to_arg(self) -> String {
+        let flags: Vec<&str> = [
+            (self.log, "log"),
+            (self.proxy_only, "proxyonly"),
+            (self.same_ports, "sameports"),
+        ]
+        .iter()
+        .filter(|x| x.0)
+        .map(|x| x.1)
+        .collect();
+        if flags.is_empty() {
+            "default".to_string()
         } else {
-            Ok(stdout)
+            flags.join(",")
         }
     }
+}
 
-    #[inline]
-    fn cmd(&self) -> Command { Command::new(&self.executable_path) }
+/// Represents settings of the NAT engine of a NIC set by `VBoxManage modifyvm`.
+///
+/// `None` fields are left unchanged.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NatEngineSettings {
+    /// The NAT network in CIDR notation with `--natnet`, e.g., `10.0.3.0/24`, or `default`.
+    pub network: Option<String>,
+    /// The host IP address the NAT engine binds to with `--natbindip`.
+    pub bind_ip: Option<String>,
+    /// Passes the domain name of the host to the guest with `--natdnspassdomain`.
+    pub dns_pass_domain: Option<bool>,
+    /// Makes the NAT engine proxy DNS requests to the host DNS servers with `--natdnsproxy`.
+    pub dns_proxy: Option<bool>,
+    /// Makes the NAT engine resolve DNS requests with the host resolver API with `--natdnshostresolver`.
+    pub dns_host_resolver: Option<bool>,
+    /// The aliasing mode with `--nataliasmode`.
+    pub alias_mode: Option<NatAliasMode>,
+    /// The prefix of the PXE boot file path with `--nattftpprefix`.
+    pub tftp_prefix: Option<String>,
+    /// The PXE boot file name with `--nattftpfile`.
+    pub tftp_file: Option<String>,
+    /// The TFTP server address with `--nattftpserver`.
+    pub tftp_server: Option<String>,
+    /// Makes the host loopback interface reachable from the guest as `10.0.2.2` with `--natlocalhostreachable`.
+    pub localhost_reachable: Option<bool>,
+}
 
-    /// Gets the VBoxManage version.
-    pub fn version(&self) -> VmResult<String> {
-        Ok(Self::exec(self.cmd().arg("-v"))?.trim().to_string())
+impl NatEngineSettings {
+    fn to_args(&self, index: usize) -> Vec<String> {
+        let on_off = |x: bool| if x { "on" } else { "off" }.to_string();
+        let opts = [
+            ("natnet", self.network.clone()),
+            ("natbindip", self.bind_ip.clone()),
+            ("natdnspassdomain", self.dns_pass_domain.map(on_off)),
+            ("natdnsproxy", self.dns_proxy.map(on_off)),
+            ("natdnshostresolver", self.dns_host_resolver.map(on_off)),
+            ("nataliasmode", self.alias_mode.map(NatAliasMode::to_arg)),
+            ("nattftpprefix", self.tftp_prefix.clone()),
+            ("nattftpfile", self.tftp_file.clone()),
+            ("nattftpserver", self.tftp_server.clone()),
+            (
+                "natlocalhostreachable",
+                self.localhost_reachable.map(on_off),
+            ),
+        ];
+        let mut ret = vec![];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.push(format!("--{}{}", opt, index));
+                ret.push(value);
+            }
+        }
+        ret
     }
+}
 
-    /// Gets a list of VMs.
-    pub fn list_vms(&self) -> VmResult<Vec<Vm>> {
-        let s = Self::exec(self.cmd().args(&["list", "vms"]))?;
-        // "vm name" {uuid}
-        Ok(s.lines()
-            .map(|x| {
-                let v = x.rsplitn(2, ' ').collect::<Vec<&str>>();
-                Vm {
-                    id: Some(v[0].to_string()),
-                    name: Some(v[1][1..v[1].len() - 1].to_string()),
-                    path: None,
-                }
-            })
-            .collect())
+/// Represents a paravirtualization interface set by `VBoxManage modifyvm --paravirtprovider`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ParavirtProvider {
+    None,
+    /// Chosen by VirtualBox from the guest OS type.
+    Default,
+    Legacy,
+    Minimal,
+    HyperV,
+    Kvm,
+}
+
+impl ParavirtProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Default => "default",
+            Self::Legacy => "legacy",
+            Self::Minimal => "minimal",
+            Self::HyperV => "hyperv",
+            Self::Kvm => "kvm",
+        }
     }
+}
 
-    pub fn show_vm_info(&self) -> VmResult<String> {
-        self.show_vm_info2(self.get_vm()?)
+/// Represents a boot device set by `VBoxManage modifyvm --boot<n>`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum BootDevice {
+    None,
+    Floppy,
+    Dvd,
+    Disk,
+    Net,
+}
+
+impl BootDevice {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Floppy => "floppy",
+            Self::Dvd => "dvd",
+            Self::Disk => "disk",
+            Self::Net => "net",
+        }
     }
+}
 
-    pub fn get_os_version(&self) -> VmResult<String> {
-        let s = self.show_vm_info()?;
-        let hm = Self::parse_info(&s, Some("Guest OS"));
-        Ok(hm["ostype"].to_string())
+/// Represents a graphics controller set by `VBoxManage modifyvm --graphicscontroller`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum GraphicsController {
+    None,
+    VBoxVga,
+    VmSvga,
+    VBoxSvga,
+}
+
+impl GraphicsController {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::VBoxVga => "vboxvga",
+            Self::VmSvga => "vmsvga",
+            Self::VBoxSvga => "vboxsvga",
+        }
     }
+}
 
-    fn parse_info<'a>(
-        s: &'a str,
-        stopper: Option<&str>,
-    ) -> HashMap<&'a str, &'a str> {
-        let l = s.lines();
-        let mut hm = HashMap::new();
-        for x in l {
-            let x: Vec<&str> = x.splitn(2, '=').collect();
-            if x.len() != 2 {
-                continue;
-            }
-            let (key, value) = (x[0].trim(), x[1].trim());
-            let value = if value.len() >= 2 {
-                if value.starts_with('"') && value.ends_with('"') {
-                    // strip ""
-                    &value[1..value.len() - 1]
-                } else {
-                    value
-                }
-            } else {
-                value
-            };
-            hm.insert(key, value);
-            if let Some(stopper) = stopper {
-                if key == stopper {
-                    break;
-                }
-            }
+/// Represents a bus of a storage controller added by `VBoxManage storagectl --add`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum StorageBus {
+    Ide,
+    Sata,
+    Scsi,
+    Sas,
+    Floppy,
+    Usb,
+    /// NVMe.
+    PciE,
+    VirtioScsi,
+}
+
+impl StorageBus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ide => "ide",
+            Self::Sata => "sata",
+            Self::Scsi => "scsi",
+            Self::Sas => "sas",
+            Self::Floppy => "floppy",
+            Self::Usb => "usb",
+            Self::PciE => "pcie",
+            Self::VirtioScsi => "virtio",
         }
-        hm
     }
+}
 
-    fn show_vm_info2(&self, id: &str) -> VmResult<String> {
-        Self::exec(self.cmd().args(&["showvminfo", id, "--machinereadable"]))
-    }
+/// Represents a version of OVF written by `VBoxManage export`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum OvfVersion {
+    /// OVF 0.9 with `--ovf09`.
+    V0_9,
+    /// OVF 1.0 with `--ovf10`.
+    V1_0,
+    /// OVF 2.0 with `--ovf20`.
+    V2_0,
+    /// Oracle Public Cloud format with `--opc10`.
+    Opc1_0,
+}
 
-    fn get_vm(&self) -> VmResult<&str> {
-        self.vm_name
-            .as_deref()
-            .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
+impl OvfVersion {
+    fn to_arg(self) -> &'static str {
+        match self {
+            Self::V0_9 => "--ovf09",
+            Self::V1_0 => "--ovf10",
+            Self::V2_0 => "--ovf20",
+            Self::Opc1_0 => "--opc10",
+        }
     }
+}
 
-    pub fn start_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&["startvm", self.get_vm()?]))?;
-        Ok(())
-    }
+/// Represents options of [`VBoxManage::export_appliance`].
+///
+/// `None` fields are left to the defaults of VBoxManage.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ExportOptions {
+    pub ovf_version: Option<OvfVersion>,
+    /// Writes a manifest of the files with `--manifest`.
+    pub manifest: bool,
+    /// Includes the ISO images attached to the VM with `--iso`.
+    pub include_isos: bool,
+    /// The name of the virtual system in the appliance with `--vmname`.
+    pub vm_name: Option<String>,
+    /// The product name with `--product`.
+    pub product: Option<String>,
+    /// The vendor name with `--vendor`.
+    pub vendor: Option<String>,
+    /// The product version with `--version`.
+    pub version: Option<String>,
+    /// The description with `--description`.
+    pub description: Option<String>,
+}
 
-    pub fn poweroff_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
-            "controlvm",
-            self.get_vm()?,
-            "poweroff",
-        ]))?;
-        Ok(())
+impl ExportOptions {
+    fn to_args(&self) -> Vec<String> {
+        let mut ret = vec![];
+        if let Some(x) = self.ovf_version {
+            ret.push(x.to_arg().to_string());
+        }
+        if self.manifest {
+            ret.push("--manifest".to_string());
+        }
+        if self.include_isos {
+            ret.push("--iso".to_string());
+        }
+        let vsys = [
+            ("vmname", &self.vm_name),
+            ("product", &self.product),
+            ("vendor", &self.vendor),
+            ("version", &self.version),
+            ("description", &self.description),
+        ];
+        if vsys.iter().any(|x| x.1.is_some()) {
+            ret.extend(["--vsys".to_string(), "0".to_string()]);
+        }
+        for (opt, value) in vsys {
+            if let Some(value) = value {
+                ret.push(format!("--{}", opt));
+                ret.push(value.clone());
+            }
+        }
+        ret
     }
+}
 
-    /// Sends ACPI shutdown signal.
-    ///
-    /// If the VM is running, this function returns Ok(()) regardless of whether the VM was shut down.
-    pub fn acpi_power_button_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
-            "controlvm",
-            self.get_vm()?,
-            "acpipowerbutton",
-        ]))?;
-        Ok(())
+/// Represents a virtual system of an appliance listed by `VBoxManage import --dry-run`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VirtualSystem {
+    /// The index for `--vsys`.
+    pub index: u32,
+    pub os_type: Option<String>,
+    pub name: Option<String>,
+    pub cpus: Option<u32>,
+    pub memory_mb: Option<u64>,
+    /// The configuration items of the virtual system.
+    pub units: Vec<ApplianceUnit>,
+}
+
+/// Represents a configuration item of a virtual system, e.g., a NIC or a disk image.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ApplianceUnit {
+    /// The index for `--unit`.
+    pub index: u32,
+    /// The description, e.g., `Hard disk image: source image=a-disk001.vmdk, ...`.
+    pub description: String,
+    /// `true` if the item can be excluded from the import with [`VirtualSystemOptions::ignored_units`].
+    pub ignorable: bool,
+}
+
+/// Represents options of a virtual system for [`VBoxManage::import_appliance`].
+///
+/// `None` fields are left as suggested by the appliance.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VirtualSystemOptions {
+    /// The index of the virtual system.
+    pub index: u32,
+    pub name: Option<String>,
+    pub os_type: Option<String>,
+    pub cpus: Option<u32>,
+    pub memory_mb: Option<u64>,
+    /// The directory where the directory of the VM is created.
+    pub base_folder: Option<String>,
+    /// The units excluded from the import.
+    pub ignored_units: Vec<u32>,
+    /// The target paths of the disk image units.
+    pub disk_paths: Vec<(u32, String)>,
+}
+
+impl VirtualSystemOptions {
+    pub fn new(index: u32) -> Self {
+        Self {
+            index,
+            ..Default::default()
+        }
     }
 
-    pub fn reset_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&["controlvm", self.get_vm()?, "reset"]))?;
-        Ok(())
+    fn to_args(&self) -> Vec<String> {
+        let vsys = self.index.to_string();
+        let mut ret = vec![];
+        let opts = [
+            ("vmname", self.name.clone()),
+            ("ostype", self.os_type.clone()),
+            ("cpus", self.cpus.map(|x| x.to_string())),
+            ("memory", self.memory_mb.map(|x| x.to_string())),
+            ("basefolder", self.base_folder.clone()),
+        ];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.extend(["--vsys".to_string(), vsys.clone()]);
+                ret.push(format!("--{}", opt));
+                ret.push(value);
+            }
+        }
+        for x in &self.ignored_units {
+            ret.extend(["--vsys".to_string(), vsys.clone()]);
+            ret.extend(["--unit".to_string(), x.to_string()]);
+            ret.push("--ignore".to_string());
+        }
+        for (unit, path) in &self.disk_paths {
+            ret.extend(["--vsys".to_string(), vsys.clone()]);
+            ret.extend(["--unit".to_string(), unit.to_string()]);
+            ret.extend(["--disk".to_string(), path.clone()]);
+        }
+        ret
     }
+}
 
-    pub fn pause_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&["controlvm", self.get_vm()?, "pause"]))?;
-        Ok(())
+/// Represents options of [`VBoxManage::import_appliance`].
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ImportOptions {
+    pub systems: Vec<VirtualSystemOptions>,
+    /// Keeps the MAC addresses of all the NICs with `--options keepallmacs`. New MAC addresses are generated otherwise.
+    pub keep_all_macs: bool,
+    /// Keeps the MAC addresses of the NAT NICs with `--options keepnatmacs`.
+    pub keep_nat_macs: bool,
+    /// Converts the disk images to VDI with `--options importtovdi`.
+    pub import_to_vdi: bool,
+}
+
+impl ImportOptions {
+    fn to_args(&self) -> Vec<String> {
+        let mut ret: Vec<String> =
+            self.systems.iter().flat_map(|x| x.to_args()).collect();
+        let options: Vec<&str> = [
+            (self.keep_all_macs, "keepallmacs"),
+            (self.keep_nat_macs, "keepnatmacs"),
+            (self.import_to_vdi, "importtovdi"),
+        ]
+        .iter()
+        .filter(|x| x.0)
+        .map(|x| x.1)
+        .collect();
+        if !options.is_empty() {
+            ret.extend(["--options".to_string(), options.join(",")]);
+        }
+        ret
     }
+}
 
-    pub fn resume_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&["controlvm", self.get_vm()?, "resume"]))?;
-        Ok(())
+/// Represents the samples of a metric listed by `VBoxManage metrics query` or `VBoxManage metrics collect`.
+///
+/// The values are converted to percent, bytes or bytes per second.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricSample {
+    /// The time of the sample written by `metrics collect`, e.g., `14:58:10.123`.
+    pub time: Option<String>,
+    /// The name of the VM or `host`.
+    pub object: String,
+    /// The name of the metric, e.g., `CPU/Load/User` or `Guest/RAM/Usage/Free`.
+    pub metric: String,
+    /// The values from the oldest to the latest.
+    pub values: Vec<f64>,
+}
+
+/// Represents a flag of a guest property.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum GuestPropertyFlag {
+    /// The property is deleted when the VM is powered off.
+    Transient,
+    /// The property is deleted when the VM is reset.
+    TransReset,
+    /// The guest cannot change the property.
+    ReadOnlyGuest,
+    /// The host cannot change the property.
+    ReadOnlyHost,
+    /// Neither the guest nor the host can change the property.
+    ReadOnly,
+}
+
+impl GuestPropertyFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Transient => "TRANSIENT",
+            Self::TransReset => "TRANSRESET",
+            Self::ReadOnlyGuest => "RDONLYGUEST",
+            Self::ReadOnlyHost => "RDONLYHOST",
+            Self::ReadOnly => "READONLY",
+        }
     }
 
-    pub fn save_state_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
-            "controlvm",
-            self.get_vm()?,
-            "savestate",
-        ]))?;
-        Ok(())
+    fn from_name(s: &str) -> Option<Self> {
+        Some(match s.trim().to_ascii_uppercase().as_str() {
+            "TRANSIENT" => Self::Transient,
+            "TRANSRESET" => Self::TransReset,
+            "RDONLYGUEST" => Self::ReadOnlyGuest,
+            "RDONLYHOST" => Self::ReadOnlyHost,
+            "READONLY" => Self::ReadOnly,
+            _ => return None,
+        })
     }
+}
 
-    /// Gets a list of snapshots.
-    pub fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
-        const SN_NAME: &str = "SnapshotName";
-        const SN_UUID: &str = "SnapshotUUID";
-        const SN_DESC: &str = "SnapshotDescription";
-        #[derive(Eq, PartialEq)]
-        enum State {
-            Init,
-            Name,
-            Uuid,
-            Desc,
-            DescCont,
-        }
-        let s = Self::exec(self.cmd().args(&[
-            "snapshot",
-            self.get_vm()?,
-            "list",
-            "--machinereadable",
-        ]))?;
-        let mut ret = vec![];
-        if s.trim() == "This machine does not have any snapshots" {
-            return Ok(ret);
-        }
-        let mut last_state = State::Init;
+/// Represents a guest property listed by `VBoxManage guestproperty`.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GuestProperty {
+    pub name: String,
+    pub value: String,
+    /// The time of the last change in seconds since the Unix epoch.
+    pub timestamp: Option<u64>,
+    pub flags: Vec<GuestPropertyFlag>,
+}
 
-        let mut sn = Snapshot {
-            id: None,
-            name: None,
-            detail: None,
-        };
-        let mut cur_detail = "".to_string();
-        for x in s.lines() {
-            let now_data = if x.starts_with(SN_NAME) {
-                State::Name
-            } else if x.starts_with(SN_UUID) {
-                State::Uuid
-            } else if x.starts_with(SN_DESC) {
-                State::Desc
-            } else if x.starts_with("CurrentSnapshotName=\"") {
-                // End
-                return if last_state == State::Desc
-                    || last_state == State::DescCont
-                {
-                    cur_detail.pop(); // Remove last "
-                    Ok(ret)
-                } else {
-                    vmerr!(ErrorKind::UnexpectedResponse(x.to_string()))
-                };
-            } else {
-                State::DescCont
-            };
-            match last_state {
-                State::Init => match now_data {
-                    State::Name => {
-                        let p = x.find('=').expect("Invalid name");
-                        sn.name = Some(x[p + 2..x.len() - 1].to_string());
-                        last_state = State::Name;
-                    }
-                    _ => {
-                        return vmerr!(ErrorKind::UnexpectedResponse(
-                            x.to_string()
-                        ))
-                    }
-                },
-                State::Name => match now_data {
-                    State::Uuid => {
-                        let p = x.find('=').expect("Invalid UUID");
-                        sn.id = Some(x[p + 2..x.len() - 1].to_string());
-                        last_state = State::Uuid;
-                    }
-                    _ => {
-                        return vmerr!(ErrorKind::UnexpectedResponse(
-                            x.to_string()
-                        ))
-                    }
-                },
-                State::Uuid => match now_data {
-                    State::Desc => {
-                        let p = x.find('=').expect("Invalid description");
-                        cur_detail = x[p + 2..].to_string();
-                        last_state = State::Desc;
-                    }
-                    _ => {
-                        return vmerr!(ErrorKind::UnexpectedResponse(
-                            x.to_string()
-                        ))
-                    }
-                },
-                State::Desc => match now_data {
-                    State::Name => {
-                        sn.detail = Some(
-                            cur_detail[..cur_detail.len() - 1].to_string(),
-                        );
-                        ret.push(sn.clone());
-                        cur_detail = "".to_string();
-                        let p = x.find('=').expect("Invalid name");
-                        sn.name = Some(x[p + 2..x.len() - 1].to_string());
-                        last_state = State::Name;
-                    }
-                    State::DescCont => {
-                        cur_detail += LINE_FEED;
-                        cur_detail += x;
-                        last_state = State::DescCont;
-                    }
-                    _ => {
-                        return vmerr!(ErrorKind::UnexpectedResponse(
-                            x.to_string()
-                        ))
-                    }
-                },
-                State::DescCont => match now_data {
-                    State::Name => {
-                        sn.detail = Some(
-                            cur_detail[..cur_detail.len() - 1].to_string(),
-                        );
-                        ret.push(sn.clone());
-                        cur_detail = "".to_string();
-                        let p = x.find('=').expect("Invalid name");
-                        sn.name = Some(x[p + 2..x.len() - 1].to_string());
-                        last_state = State::Name;
-                    }
-                    State::DescCont => {
-                        cur_detail += LINE_FEED;
-                        cur_detail += x;
-                        last_state = State::DescCont;
-                    }
-                    _ => {
-                        return vmerr!(ErrorKind::UnexpectedResponse(
-                            x.to_string()
-                        ))
-                    }
-                },
-            };
+/// Represents a cipher of VM and disk encryption.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum EncryptionCipher {
+    Aes128,
+    Aes256,
+}
+
+impl EncryptionCipher {
+    /// Returns the name for `encryptvm --cipher`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Aes128 => "AES-128",
+            Self::Aes256 => "AES-256",
         }
-        Ok(ret)
     }
 
-    pub fn take_snapshot(
-        &self,
-        name: &str,
-        description: Option<&str>,
-        is_live: bool,
-    ) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["snapshot", self.get_vm()?, "take", name]);
-        if let Some(x) = description {
-            cmd.args(&["--description", x]);
-        }
-        if is_live {
-            cmd.arg("--live");
+    /// Returns the name for `encryptmedium --cipher`.
+    pub fn as_medium_str(&self) -> &'static str {
+        match self {
+            Self::Aes128 => "AES-XTS128-PLAIN64",
+            Self::Aes256 => "AES-XTS256-PLAIN64",
         }
-        Self::exec(&mut cmd)?;
-        Ok(())
     }
+}
 
-    pub fn delete_snapshot(&self, name: &str) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
-            "snapshot",
-            self.get_vm()?,
-            "delete",
-            name,
-        ]))?;
-        Ok(())
-    }
+/// Represents which snapshots `VBoxManage clonevm --mode` clones.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum CloneMode {
+    /// Clones the current state, or the state of the specified snapshot, without snapshots.
+    Machine,
+    /// Clones the specified snapshot and its children.
+    MachineAndChildren,
+    /// Clones all the snapshots.
+    All,
+}
 
-    pub fn restore_snapshot(&self, name: &str) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
-            "snapshot",
-            self.get_vm()?,
-            "restore",
-            name,
-        ]))?;
-        Ok(())
+impl CloneMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Machine => "machine",
+            Self::MachineAndChildren => "machineandchildren",
+            Self::All => "all",
+        }
     }
+}
 
-    pub fn restore_current_snapshot(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
-            "snapshot",
-            self.get_vm()?,
-            "restorecurrent",
-        ]))?;
-        Ok(())
-    }
+/// Represents a type of a storage device attached by `VBoxManage storageattach --type`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum StorageDeviceType {
+    Hdd,
+    DvdDrive,
+    Fdd,
+}
 
-    pub fn run(&self, guest_args: &[&str]) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["guestcontrol", self.get_vm()?, "run"]);
-        cmd.args(self.build_auth());
-        cmd.args(guest_args);
-        Self::exec(&mut cmd)?;
-        Ok(())
+impl StorageDeviceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hdd => "hdd",
+            Self::DvdDrive => "dvddrive",
+            Self::Fdd => "fdd",
+        }
     }
+}
 
-    /// Copies files from guest to host.
-    pub fn copy_from(
-        &self,
-        follow: bool,
-        recursive: bool,
-        from_guest_paths: &[&str],
-        to_host_path: &str,
-    ) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["guestcontrol", self.get_vm()?, "copyfrom"]);
-        cmd.args(self.build_auth());
-        if follow {
-            cmd.arg("--follow");
+/// The number of boot device slots of a VM.
+const MAX_BOOT_DEVICES: usize = 4;
+
+/// Represents settings of a VM set by `VBoxManage modifyvm`.
+///
+/// `None` fields are left unchanged.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VmSettings {
+    /// Renames the VM with `--name`.
+    pub name: Option<String>,
+    /// The guest OS type with `--ostype`, e.g., `Ubuntu_64`.
+    pub os_type: Option<String>,
+    /// The description with `--description`.
+    pub description: Option<String>,
+    /// The memory size in MB with `--memory`.
+    pub memory_mb: Option<u64>,
+    /// The video memory size in MB with `--vram`.
+    pub vram_mb: Option<u32>,
+    /// The number of virtual CPUs with `--cpus`.
+    pub cpus: Option<u32>,
+    /// The percentage of the host CPU time a virtual CPU can use with `--cpuexecutioncap`.
+    pub cpu_execution_cap: Option<u32>,
+    /// The firmware with `--firmware`.
+    pub firmware: Option<Firmware>,
+    /// Exposes the hardware virtualization to the guest with `--nested-hw-virt`.
+    pub nested_hw_virt: Option<bool>,
+    /// The paravirtualization interface with `--paravirtprovider`.
+    pub paravirt_provider: Option<ParavirtProvider>,
+    /// The boot devices in order with `--boot1` to `--boot4`. The remaining slots are set to [`BootDevice::None`].
+    pub boot_order: Option<Vec<BootDevice>>,
+    /// The graphics controller with `--graphicscontroller`.
+    pub graphics_controller: Option<GraphicsController>,
+    /// Enables the I/O APIC with `--ioapic`, which is required for more than one virtual CPU.
+    pub ioapic: Option<bool>,
+    /// Enables PAE with `--pae`.
+    pub pae: Option<bool>,
+    /// Makes the real-time clock use UTC with `--rtcuseutc`.
+    pub rtc_use_utc: Option<bool>,
+}
+
+impl VmSettings {
+    fn to_args(&self) -> VmResult<Vec<String>> {
+        let on_off = |x: bool| if x { "on" } else { "off" }.to_string();
+        let opts = [
+            ("name", self.name.clone()),
+            ("ostype", self.os_type.clone()),
+            ("description", self.description.clone()),
+            ("memory", self.memory_mb.map(|x| x.to_string())),
+            ("vram", self.vram_mb.map(|x| x.to_string())),
+            ("cpus", self.cpus.map(|x| x.to_string())),
+            (
+                "cpuexecutioncap",
+                self.cpu_execution_cap.map(|x| x.to_string()),
+            ),
+            (
+                "firmware",
+                self.firmware.map(|x| {
+                    match x {
+                        Firmware::Bios => "bios",
+                        Firmware::Efi => "efi",
+                    }
+                    .to_string()
+                }),
+            ),
+            ("nested-hw-virt", self.nested_hw_virt.map(on_off)),
+            (
+                "paravirtprovider",
+                self.paravirt_provider.map(|x| x.as_str().to_string()),
+            ),
+            (
+                "graphicscontroller",
+                self.graphics_controller.map(|x| x.as_str().to_string()),
+            ),
+            ("ioapic", self.ioapic.map(on_off)),
+            ("pae", self.pae.map(on_off)),
+            ("rtcuseutc", self.rtc_use_utc.map(on_off)),
+        ];
+        let mut ret = vec![];
+        for (opt, value) in opts {
+            if let Some(value) = value {
+                ret.push(format!("--{}", opt));
+                ret.push(value);
+            }
         }
-        if recursive {
-            cmd.arg("-R");
+        if let Some(order) = &self.boot_order {
+            if order.len() > MAX_BOOT_DEVICES {
+                return vmerr!(ErrorKind::InvalidParameter(format!(
+                    "boot_order can have up to {} devices",
+                    MAX_BOOT_DEVICES
+                )));
+            }
+            for i in 0..MAX_BOOT_DEVICES {
+                ret.push(format!("--boot{}", i + 1));
+                ret.push(
+                    order
+                        .get(i)
+                        .unwrap_or(&BootDevice::None)
+                        .as_str()
+                        .to_string(),
+                );
+            }
         }
-
-        cmd.args(from_guest_paths);
-        cmd.arg(to_host_path);
-        Self::exec(&mut cmd)?;
-        Ok(())
+        Ok(ret)
     }
+}
 
-    /// Copies files from host to guest.
-    pub fn copy_to(
-        &self,
-        follow: bool,
-        recursive: bool,
-        from_host_paths: &[&str],
-        to_guest_path: &str,
-    ) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["guestcontrol", self.get_vm()?, "copyto"]);
-        cmd.args(self.build_auth());
-        if follow {
-            cmd.arg("--follow");
+/// Returns the arguments of `modifyvm` which set the serial port `n` to `mode`.
+///
+/// Enabled ports use the standard I/O base and IRQ of `COM<n>`.
+fn uart_args(mode: &SerialPortMode, n: usize) -> Vec<String> {
+    let mode: Vec<String> = match mode {
+        SerialPortMode::Off => {
+            return vec![format!("--uart{}", n), "off".to_string()]
         }
-        if recursive {
-            cmd.arg("-R");
+        SerialPortMode::Disconnected => vec!["disconnected".to_string()],
+        SerialPortMode::File(x) => vec!["file".to_string(), x.clone()],
+        SerialPortMode::PipeServer(x) => vec!["server".to_string(), x.clone()],
+        SerialPortMode::PipeClient(x) => vec!["client".to_string(), x.clone()],
+        SerialPortMode::TcpServer(x) => {
+            vec!["tcpserver".to_string(), x.to_string()]
         }
-        cmd.args(from_host_paths);
-        cmd.arg(to_guest_path);
-        Self::exec(&mut cmd)?;
-        Ok(())
-    }
+        SerialPortMode::TcpClient(x) => {
+            vec!["tcpclient".to_string(), x.clone()]
+        }
+    };
+    let (io_base, irq) = COM_PORTS[n - 1];
+    let mut ret = vec![
+        format!("--uart{}", n),
+        io_base.to_string(),
+        irq.to_string(),
+        format!("--uartmode{}", n),
+    ];
+    ret.extend(mode);
+    ret
+}
 
-    /// Remove files from guest.
-    pub fn remove_file(&self, guest_paths: &[&str]) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["guestcontrol", self.get_vm()?, "rm"]);
-        cmd.args(self.build_auth());
-        cmd.arg("-f");
-        cmd.args(guest_paths);
-        Self::exec(&mut cmd)?;
-        Ok(())
-    }
-
-    /// Sends keyboard scancodes to the guest.
-    pub fn keyboard_put_scancode<T: Iterator<Item = u8>>(
-        &self,
-        v: T,
-    ) -> VmResult<()> {
-        use std::fmt::Write;
-        let mut cmd = self.cmd();
-        cmd.args(&["controlvm", self.get_vm()?, "keyboardputscancode"]);
-        cmd.args(self.build_auth());
+/// Parses `uart<n>` and `uartmode<n>` of `showvminfo --machinereadable`.
+///
+/// Ports in the modes which [`SerialPortMode`] doesn't represent, such as a host device, are omitted.
+fn parse_serial_ports(hm: &HashMap<&str, &str>) -> Vec<SerialPort> {
+    (1..=COM_PORTS.len())
+        .filter_map(|n| {
+            let uart = hm.get(format!("uart{}", n).as_str())?;
+            let mode = if *uart == "off" {
+                SerialPortMode::Off
+            } else {
+                let v = hm.get(format!("uartmode{}", n).as_str())?;
+                let (kind, arg) = v.split_once(',').unwrap_or((v, ""));
+                match kind {
+                    "disconnected" => SerialPortMode::Disconnected,
+                    "file" => SerialPortMode::File(arg.to_string()),
+                    "server" => SerialPortMode::PipeServer(arg.to_string()),
+                    "client" => SerialPortMode::PipeClient(arg.to_string()),
+                    "tcpserver" => SerialPortMode::TcpServer(arg.parse().ok()?),
+                    "tcpclient" => SerialPortMode::TcpClient(arg.to_string()),
+                    _ => return None,
+                }
+            };
+            Some(SerialPort { number: n, mode })
+        })
+        .collect()
+}
 
-        cmd.args(
-            v.into_iter()
-                .map(|x| {
-                    let mut ret = String::new();
-                    ret.write_fmt(format_args!("{:x}", x)).unwrap();
-                    ret
-                })
-                .collect::<Vec<String>>(),
-        );
-        Self::exec(&mut cmd)?;
-        Ok(())
-    }
+/// Returns the rule of `--natpf<n>` and `--port-forward-4`, e.g., `ssh,tcp,,2222,,22`.
+fn to_natpf_rule(rule: &PortForward) -> String {
+    format!(
+        "{},{},{},{},{},{}",
+        natpf_rule_name(rule),
+        rule.protocol.as_str(),
+        rule.host_ip.as_deref().unwrap_or(""),
+        rule.host_port,
+        rule.guest_ip.as_deref().unwrap_or(""),
+        rule.guest_port
+    )
+}
 
-    pub fn keyboard_put_string(&self, v: &[&str]) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["controlvm", self.get_vm()?, "keyboardputstring"]);
-        cmd.args(self.build_auth());
-        cmd.args(v);
-        Self::exec(&mut cmd)?;
-        Ok(())
-    }
+/// Returns the name of `rule`, or `<protocol>_<host_port>` if it has no name, since VirtualBox requires a name.
+fn natpf_rule_name(rule: &PortForward) -> String {
+    rule.name.clone().unwrap_or_else(|| {
+        format!("{}_{}", rule.protocol.as_str(), rule.host_port)
+    })
+}
 
-    pub fn install_ext_pack(
-        &self,
-        replace: bool,
-        accept_license: bool,
-        ext_pack_path: &str,
-    ) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["extpack", "install"]);
-        if replace {
-            cmd.arg("--replace");
+/// Parses `Forwarding(<i>)` of the NAT NIC `nic` in `showvminfo --machinereadable`.
+///
+/// The rules of a NIC follow its `natnet<nic>` entry.
+fn parse_port_forwards(s: &str, nic: usize) -> Vec<PortForward> {
+    let natnet = format!("natnet{}=", nic);
+    let mut in_nic = false;
+    let mut ret = vec![];
+    for l in s.lines() {
+        if l.starts_with("natnet") {
+            in_nic = l.starts_with(&natnet);
+            continue;
         }
-        if accept_license {
-            cmd.arg("--accept-license=sha256");
+        if !in_nic || !l.starts_with("Forwarding(") {
+            continue;
         }
-        cmd.arg(ext_pack_path);
-        Self::exec(&mut cmd)?;
-        Ok(())
-    }
-
-    pub fn uninstall_ext_pack(
-        &self,
-        force: bool,
-        ext_pack_path: &str,
-    ) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["extpack", "uninstall"]);
-        if force {
-            cmd.arg("--force");
+        let v = match l.split_once('=') {
+            Some(x) => x.1.trim_matches('"'),
+            None => continue,
+        };
+        let v: Vec<&str> = v.split(',').collect();
+        if v.len() != 6 {
+            continue;
+        }
+        let opt = |x: &str| Some(x.to_string()).filter(|x| !x.is_empty());
+        if let (Ok(protocol), Ok(host_port), Ok(guest_port)) =
+            (v[1].parse(), v[3].parse(), v[5].parse())
+        {
+            ret.push(PortForward {
+                name: opt(v[0]),
+                protocol,
+                host_ip: opt(v[2]),
+                host_port,
+                guest_ip: opt(v[4]),
+                guest_port,
+            });
         }
-        cmd.arg(ext_pack_path);
-        Self::exec(&mut cmd)?;
-        Ok(())
     }
+    ret
+}
 
-    pub fn cleanup_ext_pack(&self) -> VmResult<()> {
-        let mut cmd = self.cmd();
-        cmd.args(&["extpack", "cleanup"]);
-        Self::exec(&mut cmd)?;
-        Ok(())
-    }
+/// Parses the output of `list usbhost`.
+fn parse_usb_hosts(s: &str) -> Vec<UsbDevice> {
+    // e.g., `0x046d (046D)`
+    let hex = |x: &str| {
+        u16::from_str_radix(x.split(' ').next()?.trim_start_matches("0x"), 16)
+            .ok()
+    };
+    parse_list_blocks(s)
+        .iter()
+        .filter_map(|x| {
+            let get = |key| x.get(key).map(|x| x.to_string());
+            Some(UsbDevice {
+                id: get("UUID")?,
+                vendor_id: x.get("VendorId").and_then(|x| hex(x)),
+                product_id: x.get("ProductId").and_then(|x| hex(x)),
+                manufacturer: get("Manufacturer"),
+                product: get("Product"),
+                serial_number: get("SerialNumber"),
+            })
+        })
+        .collect()
 }
 
-impl VmCmd for VBoxManage {
-    fn list_vms(&self) -> VmResult<Vec<Vm>> { self.list_vms() }
+/// Parses `USBFilterName<n>` and the other `USBFilter*<n>` entries of `showvminfo --machinereadable`, where `n` starts from 1.
+fn parse_usb_filters(hm: &HashMap<&str, &str>) -> Vec<UsbFilter> {
+    (1..)
+        .map_while(|n| {
+            let get = |key: &str| {
+                hm.get(format!("USBFilter{}{}", key, n).as_str())
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string())
+            };
+            Some(UsbFilter {
+                name: hm
+                    .get(format!("USBFilterName{}", n).as_str())?
+                    .to_string(),
+                active: get("Active").as_deref() == Some("on"),
+                vendor_id: get("VendorId"),
+                product_id: get("ProductId"),
+                revision: get("Revision"),
+                manufacturer: get("Manufacturer"),
+                product: get("Product"),
+                serial_number: get("SerialNumber"),
+                remote: get("Remote").map(|x| x == "yes" || x == "on"),
+                action: None,
+            })
+        })
+        .collect()
+}
 
-    fn set_vm_by_id(&mut self, id: &str) -> VmResult<()> {
-        // VBoxManage can be passed an ID.
-        self.set_vm_by_name(id)
-    }
+/// Parses the output of `list usbfilters`.
+fn parse_global_usb_filters(s: &str) -> Vec<UsbFilter> {
+    parse_list_blocks(s)
+        .iter()
+        .filter(|x| x.contains_key("Index"))
+        .map(|x| {
+            let get = |key| {
+                x.get(key).filter(|x| !x.is_empty()).map(|x| x.to_string())
+            };
+            UsbFilter {
+                name: get("Name").unwrap_or_default(),
+                active: x.get("Active") == Some(&"yes"),
+                vendor_id: get("VendorId"),
+                product_id: get("ProductId"),
+                revision: get("Revision"),
+                manufacturer: get("Manufacturer"),
+                product: get("Product"),
+                serial_number: get("Serial Number"),
+                remote: None,
+                action: match x.get("Action").map(|x| x.to_ascii_lowercase()) {
+                    Some(x) if x == "ignore" => Some(UsbFilterAction::Ignore),
+                    Some(x) if x == "hold" => Some(UsbFilterAction::Hold),
+                    _ => None,
+                },
+            }
+        })
+        .collect()
+}
 
-    fn set_vm_by_name(&mut self, name: &str) -> VmResult<()> {
-        self.show_vm_info2(name)?; // Checks if the corresponding VM exists.
-        self.vm_name = Some(name.to_string());
-        Ok(())
+/// Parses the `vrde*` entries of `showvminfo --machinereadable`.
+///
+/// `vrdeport` is the port the server listens on, which is `-1` or `0` if it is not listening, and `vrdeports` is the configured ports.
+fn parse_vrde_info(hm: &HashMap<&str, &str>) -> VrdeInfo {
+    let get =
+        |key| hm.get(key).filter(|x| !x.is_empty()).map(|x| x.to_string());
+    VrdeInfo {
+        enabled: hm.get("vrde") == Some(&"on"),
+        ports: get("vrdeports"),
+        address: get("vrdeaddress"),
+        auth_type: hm
+            .get("vrdeauthtype")
+            .and_then(|x| VrdeAuthType::from_name(x)),
+        multi_connection: hm.get("vrdemulticon") == Some(&"on"),
+        active_port: hm
+            .get("vrdeport")
+            .and_then(|x| x.parse::<u16>().ok())
+            .filter(|x| *x != 0),
     }
+}
 
-    /// `path` is the absolute path of a `vbox` file.
-    fn set_vm_by_path(&mut self, path: &str) -> VmResult<()> {
-        use ErrorKind::UnexpectedResponse;
-        // `\` in CfgFile of show_vm_info is escaped, So `path` also needs to be escaped.
-        let path = path.replace('\\', "\\\\");
-        let vms = self.list_vms()?;
-        // VBoxManage's machine readable format collapses if the snapshot detail contains `"` or `\`.
-        // To avoid this, call show_vm_info multiple times (it takes time).
-        for vm in vms {
-            let id = vm.id.as_ref().unwrap();
-            let s = self.show_vm_info2(id)?;
-            let s2 = s.clone();
-            let cfg_path = s
-                .lines()
-                .nth(4)
-                .ok_or_else(|| VmError::from(UnexpectedResponse(s2.clone())))?
-                .strip_prefix("CfgFile=\"")
-                .ok_or_else(|| VmError::from(UnexpectedResponse(s2.clone())))?;
-            let cfg_path = &cfg_path[..cfg_path.len() - 1];
-            if path == cfg_path {
-                self.vm_name = Some(id.to_string());
-                return Ok(());
-            }
-        }
-        vmerr!(ErrorKind::VmNotFound)
+/// Formats a limit for `bandwidthctl --limit`, where the suffixes `K`, `M` and `G` are in bytes per second.
+fn bandwidth_limit_arg(max_bytes_per_sec: u64) -> String {
+    let k = (max_bytes_per_sec + 1023) / 1024;
+    if k == 0 {
+        "0".to_string()
+    } else if k % (1024 * 1024) == 0 {
+        format!("{}G", k / (1024 * 1024))
+    } else if k % 1024 == 0 {
+        format!("{}M", k / 1024)
+    } else {
+        format!("{}K", k)
     }
 }
 
-impl PowerCmd for VBoxManage {
-    fn start(&self) -> VmResult<()> { self.start_vm() }
-
-    /// Sends ACPI shutdown signals.
-    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
-        let timeout = timeout.into();
-        let s = Instant::now();
-        let mut ok_flag = false;
-        loop {
-            match self.acpi_power_button_vm() {
-                Ok(()) => {
-                    ok_flag = true;
-                }
-                Err(x) => {
-                    if let Some(is_running) = x.is_invalid_state_running() {
-                        if !is_running {
-                            // !InvalidVmState(Running)
-                            return if ok_flag { Ok(()) } else { Err(x) };
-                        }
-                    } else {
-                        return Err(x);
-                    }
+/// Parses the output of `bandwidthctl list --machinereadable`, which is `name`, `type` and `maxbytespersec` entries for each group.
+fn parse_bandwidth_groups(s: &str) -> Vec<BandwidthGroup> {
+    let mut ret: Vec<BandwidthGroup> = vec![];
+    for l in s.lines() {
+        let (key, value) = match l.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim().trim_matches('"')),
+            None => continue,
+        };
+        match (key, ret.last_mut()) {
+            ("name", _) => ret.push(BandwidthGroup {
+                name: value.to_string(),
+                ty: BandwidthGroupType::Disk,
+                max_bytes_per_sec: 0,
+            }),
+            ("type", Some(x)) => {
+                x.ty = if value.eq_ignore_ascii_case("network") {
+                    BandwidthGroupType::Network
+                } else {
+                    BandwidthGroupType::Disk
                 }
             }
-
-            if let Some(timeout) = timeout {
-                if s.elapsed() >= timeout {
-                    return vmerr!(ErrorKind::Timeout);
-                }
+            ("maxbytespersec", Some(x)) => {
+                x.max_bytes_per_sec = value.parse().unwrap_or_default()
             }
-            std::thread::sleep(Duration::from_millis(200));
+            _ => {}
         }
     }
+    ret
+}
 
-    fn hard_stop(&self) -> VmResult<()> {
-        let mut ok_flag = false;
-        loop {
-            match self.poweroff_vm() {
-                Ok(()) => {
-                    ok_flag = true;
+/// Parses the progress `0%...10%...20%...` written by VBoxManage.
+#[derive(Debug, Default)]
+struct ProgressParser {
+    digits: Option<u32>,
+}
+
+impl ProgressParser {
+    /// Feeds a chunk of the output and calls `f` with each completed percentage.
+    ///
+    /// A percentage may be split across chunks.
+    fn feed<F: FnMut(u32)>(&mut self, s: &[u8], f: &mut F) {
+        for &c in s {
+            match c {
+                b'0'..=b'9' => {
+                    let n = self.digits.unwrap_or(0);
+                    self.digits = Some(
+                        n.saturating_mul(10)
+                            .saturating_add(u32::from(c - b'0')),
+                    );
                 }
-                Err(x) => {
-                    match x.get_invalid_state() {
-                        Some(VmPowerState::Stopped) => { /* Does nothing */ }
-                        Some(VmPowerState::NotRunning) => {
-                            return if ok_flag { Ok(()) } else { Err(x) }
-                        }
-                        _ => return Err(x),
+                b'%' => {
+                    if let Some(x) = self.digits.take().filter(|x| *x <= 100) {
+                        f(x);
                     }
                 }
+                _ => self.digits = None,
             }
-            std::thread::sleep(Duration::from_millis(200));
         }
     }
+}
 
-    fn suspend(&self) -> VmResult<()> {
-        let mut ok_flag = false;
-        loop {
-            let status = self.save_state_vm();
-            match status {
-                Ok(_) => {
-                    ok_flag = true;
-                }
-                Err(x) => {
-                    match x.get_invalid_state() {
-                        Some(VmPowerState::Suspended) => { /* Does nothing */ }
-                        Some(VmPowerState::NotRunning) => {
-                            return if ok_flag { Ok(()) } else { Err(x) }
-                        }
-                        _ => return Err(x),
-                    }
-                }
+/// Parses the name of the interface from the output of `hostonlyif create`, e.g., `Interface 'vboxnet0' was successfully created`.
+fn parse_created_host_only_if(s: &str) -> Option<String> {
+    let s = &s[s.find("Interface '")? + "Interface '".len()..];
+    Some(s[..s.find('\'')?].to_string())
+}
+
+/// Parses the output of `list dhcpservers`.
+fn parse_dhcp_servers(s: &str) -> Vec<DhcpServer> {
+    parse_list_blocks(s)
+        .iter()
+        .filter_map(|x| {
+            let get = |key| x.get(key).map(|x| x.to_string());
+            Some(DhcpServer {
+                network_name: get("NetworkName")?,
+                ip: get("Dhcpd IP").or_else(|| get("IP")),
+                netmask: get("NetworkMask"),
+                lower_ip: get("LowerIPAddress"),
+                upper_ip: get("UpperIPAddress"),
+                enabled: x.get("Enabled") == Some(&"Yes"),
+            })
+        })
+        .collect()
+}
+
+/// Parses `key: value` blocks separated by empty lines.
+fn parse_list_blocks(s: &str) -> Vec<HashMap<&str, &str>> {
+    let mut ret = vec![];
+    let mut hm = HashMap::new();
+    for l in s.lines() {
+        if l.trim().is_empty() {
+            if !hm.is_empty() {
+                ret.push(std::mem::take(&mut hm));
             }
-            std::thread::sleep(Duration::from_millis(200));
+            continue;
+        }
+        if let Some((key, value)) = l.split_once(':') {
+            hm.entry(key.trim()).or_insert_with(|| value.trim());
         }
     }
+    if !hm.is_empty() {
+        ret.push(hm);
+    }
+    ret
+}
 
-    fn resume(&self) -> VmResult<()> { self.start_vm() }
+/// Represents a frontend that `VBoxManage startvm` runs a VM with.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum StartType {
+    /// A VM window.
+    Gui,
+    /// No window, for servers and CI. The display is available via VRDE.
+    Headless,
+    /// A VM window in a separate process from the VM, which can be closed while the VM keeps running.
+    Separate,
+}
 
-    fn is_running(&self) -> VmResult<bool> {
-        const VMS: &str = "VMState=\"";
-        let s = self.show_vm_info()?;
-        for x in s.lines() {
-            if x.starts_with(VMS) {
-                return Ok(&x[VMS.len()..x.len() - 1] == "running");
-            }
+impl StartType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Gui => "gui",
+            Self::Headless => "headless",
+            Self::Separate => "separate",
         }
-        vmerr!(ErrorKind::UnexpectedResponse(s))
     }
+}
 
-    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
-        self.stop(timeout)?;
-        loop {
-            match self.start() {
-                Ok(()) => return Ok(()),
-                Err(x) => {
-                    if x.is_invalid_state_running() == Some(true) { /* Does nothing */
-                    } else {
-                        return Err(x);
-                    }
-                }
-            }
-            std::thread::sleep(Duration::from_millis(200));
+/// Represents a version of VirtualBox reported by `VBoxManage -v`, e.g., `7.0.14r161095`.
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize,
+)]
+pub struct VBoxVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+}
+
+impl VBoxVersion {
+    pub fn new(major: u32, minor: u32, build: u32) -> Self {
+        Self {
+            major,
+            minor,
+            build,
         }
     }
 
-    fn hard_reboot(&self) -> VmResult<()> { self.reset_vm() }
+    /// Parses the leading `major.minor.build` of `s`, ignoring suffixes such as `_Ubuntu` and `r161095`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let end = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let mut v = s[..end].split('.').map(|x| x.parse::<u32>().ok());
+        Some(Self::new(v.next()??, v.next()??, v.next()??))
+    }
+}
+
+impl std::fmt::Display for VBoxVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.build)
+    }
+}
 
-    fn pause(&self) -> VmResult<()> { Self::pause_vm(self) }
+/// The `modifyvm` options renamed in VirtualBox 7.0, without the NIC, serial port or parallel port number.
+///
+/// The table covers the options of VirtualBox 6.1 which have a new name in the `modifyvm` section of the VirtualBox 7.0 manual.
+/// Other options, such as ones added in 7.0, are passed as is.
+/// VirtualBox 7.0 still accepts the old names, but they are deprecated.
+const MODIFYVM_OPTIONS_V7: &[(&str, &str)] = &[
+    ("ostype", "os-type"),
+    ("iconfile", "icon-file"),
+    ("pagefusion", "page-fusion"),
+    ("hardwareuuid", "hardware-uuid"),
+    ("cpuhotplug", "cpu-hotplug"),
+    ("plugcpu", "plug-cpu"),
+    ("unplugcpu", "unplug-cpu"),
+    ("cpuexecutioncap", "cpu-execution-cap"),
+    ("longmode", "long-mode"),
+    ("cpuidset", "cpuid-set"),
+    ("cpuidremove", "cpuid-remove"),
+    ("cpuidremoveall", "cpuid-remove-all"),
+    ("triplefaultreset", "triple-fault-reset"),
+    ("paravirtprovider", "paravirt-provider"),
+    ("paravirtdebug", "paravirt-debug"),
+    ("nestedpaging", "nested-paging"),
+    ("largepages", "large-pages"),
+    ("vtxvpid", "vtx-vpid"),
+    ("vtxux", "vtx-ux"),
+    ("accelerate3d", "accelerate-3d"),
+    ("accelerate2dvideo", "accelerate-2d-video"),
+    ("bioslogofadein", "bios-logo-fade-in"),
+    ("bioslogofadeout", "bios-logo-fade-out"),
+    ("bioslogodisplaytime", "bios-logo-display-time"),
+    ("bioslogoimagepath", "bios-logo-image-path"),
+    ("biosbootmenu", "bios-boot-menu"),
+    ("biosapic", "bios-apic"),
+    ("biossystemtimeoffset", "bios-system-time-offset"),
+    ("biospxedebug", "bios-pxe-debug"),
+    ("rtcuseutc", "rtc-use-utc"),
+    ("snapshotfolder", "snapshot-folder"),
+    ("guestmemoryballoon", "guest-memory-balloon"),
+    ("defaultfrontend", "default-frontend"),
+    ("pciattach", "pci-attach"),
+    ("pcidetach", "pci-detach"),
+    ("nictype", "nic-type"),
+    ("cableconnected", "cable-connected"),
+    ("nictrace", "nic-trace"),
+    ("nictracefile", "nic-trace-file"),
+    ("nicproperty", "nic-property"),
+    ("nicspeed", "nic-speed"),
+    ("nicbootprio", "nic-boot-prio"),
+    ("nicpromisc", "nic-promisc"),
+    ("nicbandwidthgroup", "nic-bandwidth-group"),
+    ("nicgenericdrv", "nic-generic-drv"),
+    ("bridgeadapter", "bridge-adapter"),
+    ("hostonlyadapter", "host-only-adapter"),
+    ("macaddress", "mac-address"),
+    ("natnet", "nat-net"),
+    ("natsettings", "nat-settings"),
+    ("natpf", "nat-pf"),
+    ("natbindip", "nat-bind-ip"),
+    ("natdnspassdomain", "nat-dns-pass-domain"),
+    ("natdnsproxy", "nat-dns-proxy"),
+    ("natdnshostresolver", "nat-dns-host-resolver"),
+    ("nataliasmode", "nat-alias-mode"),
+    ("nattftpprefix", "nat-tftp-prefix"),
+    ("nattftpfile", "nat-tftp-file"),
+    ("nattftpserver", "nat-tftp-server"),
+    ("natlocalhostreachable", "nat-localhost-reachable"),
+    ("uartmode", "uart-mode"),
+    ("uarttype", "uart-type"),
+    ("lptmode", "lpt-mode"),
+    ("audiocontroller", "audio-controller"),
+    ("audiocodec", "audio-codec"),
+    ("audioin", "audio-in"),
+    ("audioout", "audio-out"),
+    ("clipboard", "clipboard-mode"),
+    ("draganddrop", "drag-and-drop"),
+    ("vrdeextpack", "vrde-extpack"),
+    ("vrdeproperty", "vrde-property"),
+    ("vrdeport", "vrde-port"),
+    ("vrdeaddress", "vrde-address"),
+    ("vrdeauthtype", "vrde-auth-type"),
+    ("vrdeauthlibrary", "vrde-auth-library"),
+    ("vrdemulticon", "vrde-multi-con"),
+    ("vrdereusecon", "vrde-reuse-con"),
+    ("vrdevideochannel", "vrde-video-channel"),
+    ("vrdevideochannelquality", "vrde-video-channel-quality"),
+    ("usbohci", "usb-ohci"),
+    ("usbehci", "usb-ehci"),
+    ("usbxhci", "usb-xhci"),
+    ("usbcardreader", "usb-card-reader"),
+    ("teleporterport", "teleporter-port"),
+    ("teleporteraddress", "teleporter-address"),
+    ("teleporterpassword", "teleporter-password"),
+    ("teleporterpasswordfile", "teleporter-password-file"),
+    ("recordingscreens", "recording-screens"),
+    ("recordingfile", "recording-file"),
+    ("recordingmaxsize", "recording-max-size"),
+    ("recordingmaxtime", "recording-max-time"),
+    ("recordingopts", "recording-opts"),
+    ("recordingvideofps", "recording-video-fps"),
+    ("recordingvideorate", "recording-video-rate"),
+    ("recordingvideores", "recording-video-res"),
+];
 
-    fn unpause(&self) -> VmResult<()> { self.resume_vm() }
+/// Returns the name of the `modifyvm` option `arg` without `--` and the NIC, serial port or parallel port number.
+fn modifyvm_option_base(arg: &str) -> Option<&str> {
+    // `--natpf1` is `natpf` for the NIC 1.
+    arg.strip_prefix("--")
+        .map(|x| x.trim_end_matches(|c: char| c.is_ascii_digit()))
 }
 
-impl GuestCmd for VBoxManage {
-    fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
-        self.run(guest_args)
+/// Returns the VirtualBox 7.0 name of the `modifyvm` option `arg`, or `arg` if it is not renamed.
+fn modifyvm_option_v7(arg: &str) -> String {
+    let base = match modifyvm_option_base(arg) {
+        Some(x) => x,
+        None => return arg.to_string(),
+    };
+    match MODIFYVM_OPTIONS_V7.iter().find(|(old, _)| *old == base) {
+        Some((_, new)) => format!("--{}{}", new, &arg[2 + base.len()..]),
+        None => arg.to_string(),
     }
+}
 
-    fn copy_from_guest_to_host(
-        &self,
-        from_guest_path: &str,
-        to_host_path: &str,
-    ) -> VmResult<()> {
-        self.copy_from(false, true, &[from_guest_path], to_host_path)
+/// Returns the number of the values following the `modifyvm` option whose base name is `base`.
+///
+/// `next` is the argument following the option.
+fn modifyvm_value_count(base: &str, next: Option<&OsStr>) -> usize {
+    match base {
+        "cpuidremoveall" | "cpuid-remove-all" => 0,
+        // `--cpuidset <leaf> <eax> <ebx> <ecx> <edx>`
+        "cpuidset" | "cpuid-set" => 5,
+        // `--natpf<N> delete <name>`
+        "natpf" | "nat-pf" if next == Some(OsStr::new("delete")) => 2,
+        _ => 1,
     }
+}
 
-    fn copy_from_host_to_guest(
-        &self,
-        from_host_path: &str,
-        to_guest_path: &str,
-    ) -> VmResult<()> {
-        self.copy_to(false, true, &[from_host_path], to_guest_path)
+/// Returns `args` of `modifyvm <vm> <options>` with the option names of VirtualBox 7.0.
+///
+/// Only options are renamed, so the VM name and the values such as a description are passed as is.
+fn modifyvm_args_v7(args: &[&OsStr]) -> Vec<OsString> {
+    // `modifyvm <vm>`
+    let mut ret: Vec<OsString> =
+        args.iter().take(2).map(|x| x.to_os_string()).collect();
+    let mut i = ret.len();
+    while i < args.len() {
+        let arg = args[i];
+        i += 1;
+        let s = match arg.to_str() {
+            Some(x) => x,
+            None => {
+                ret.push(arg.to_os_string());
+                continue;
+            }
+        };
+        // `--option=value` has no following values.
+        if let Some((name, value)) = s.split_once('=') {
+            ret.push(format!("{}={}", modifyvm_option_v7(name), value).into());
+            continue;
+        }
+        ret.push(modifyvm_option_v7(s).into());
+        if let Some(base) = modifyvm_option_base(s) {
+            let n = modifyvm_value_count(base, args.get(i).copied())
+                .min(args.len() - i);
+            ret.extend(args[i..i + n].iter().map(|x| x.to_os_string()));
+            i += n;
+        }
     }
+    ret
 }
 
-impl SnapshotCmd for VBoxManage {
-    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
-        Self::list_snapshots(self)
-    }
+#[derive(Clone, Debug)]
+pub struct VBoxManage {
+    executable_path: String,
+    vm_name: Option<String>,
+    guest_username: Option<String>,
+    guest_password: Option<String>,
+    guest_password_file: Option<String>,
+    guest_domain: Option<String>,
+    guest_timeout: Option<Duration>,
+    guest_credentials: GuestCredentials,
+    start_type: Option<StartType>,
+    /// The version detected by [`VBoxManage::get_vbox_version`], shared among clones.
+    vbox_version: Arc<Mutex<Option<VBoxVersion>>>,
+    hooks: CommandHooks,
+}
 
-    fn take_snapshot(&self, name: &str) -> VmResult<()> {
-        Self::take_snapshot(self, name, None, true)
-    }
+impl Default for VBoxManage {
+    fn default() -> Self { Self::new() }
+}
 
-    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
-        self.restore_snapshot(name)
-    }
+#[cfg(windows)]
+pub const DEFAULT_VBOXMANAGE_PATH: &str =
+    r"C:\Program Files\Oracle\VirtualBox\VBoxManage.exe";
+#[cfg(not(windows))]
+pub const DEFAULT_VBOXMANAGE_PATH: &str = "vboxmanage";
 
-    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
-        Self::delete_snapshot(self, name)
-    }
+/// The file name of VBoxManage in the directories set in `VBOX_MSI_INSTALL_PATH` and `VBOX_INSTALL_PATH`.
+#[cfg(windows)]
+const VBOXMANAGE_FILE_NAME: &str = "VBoxManage.exe";
+#[cfg(not(windows))]
+const VBOXMANAGE_FILE_NAME: &str = "VBoxManage";
+
+/// The maximum number of NICs of a VM.
+const MAX_NICS: usize = 8;
+
+/// The I/O base and IRQ of COM1 to COM4.
+const COM_PORTS: [(&str, &str); 4] = [
+    ("0x3F8", "4"),
+    ("0x2F8", "3"),
+    ("0x3E8", "4"),
+    ("0x2E8", "3"),
+];
+
+#[cfg(windows)]
+const LINE_FEED: &str = "\r\n";
+#[cfg(not(windows))]
+const LINE_FEED: &str = "\n";
+
+impl VBoxManage {
+    pub fn new() -> Self {
+        Self {
+            executable_path: tool_path_from_env_or_dirs(
+                "HVCTRL_VBOXMANAGE_PATH",
+                &["VBOX_MSI_INSTALL_PATH", "VBOX_INSTALL_PATH"],
+                VBOXMANAGE_FILE_NAME,
+                DEFAULT_VBOXMANAGE_PATH,
+            ),
+            vm_name: None,
+            guest_username: None,
+            guest_password: None,
+            guest_password_file: None,
+            guest_domain: None,
+            guest_timeout: None,
+            guest_credentials: GuestCredentials::new(),
+            start_type: None,
+            vbox_version: Arc::new(Mutex::new(None)),
+            hooks: Self::default_hooks(),
+        }
+    }
+
+    /// Treats progress (e.g., `0%...10%...`) and warnings on stderr as warnings.
+    fn default_hooks() -> CommandHooks {
+        let mut hooks = CommandHooks::new();
+        hooks.add_warning_pattern("%...");
+        hooks.add_warning_pattern(": warning: ");
+        hooks
+    }
+
+    /// Sets the path to VBoxManage.
+    pub fn executable_path<T: Into<String>>(
+        &mut self,
+        executable_path: T,
+    ) -> &mut Self {
+        self.executable_path = executable_path.into();
+        // The version is detected again for the new executable.
+        self.vbox_version = Arc::new(Mutex::new(None));
+        self
+    }
+
+    pub fn get_executable_path(&self) -> &str { &self.executable_path }
+
+    /// Sets the VM name to be manipulated.
+    pub fn vm_name<T: Into<Option<String>>>(
+        &mut self,
+        vm_name: T,
+    ) -> &mut Self {
+        self.vm_name = vm_name.into();
+        self
+    }
+
+    pub fn get_vm_name(&self) -> Option<&str> { self.vm_name.as_deref() }
+
+    impl_setter!(@opt
+    /// Sets the guest username for login.
+        guest_username: String
+    );
+
+    pub fn get_guest_username(&self) -> Option<&str> {
+        self.guest_username.as_deref()
+    }
+
+    impl_setter!(@opt
+    /// Sets the guest password for login.
+        guest_password: String
+    );
+
+    pub fn get_guest_password(&self) -> Option<&str> {
+        self.guest_password.as_deref()
+    }
+
+    impl_setter!(@opt
+    /// Sets the absolute path to the guest password file for login.
+        guest_password_file: String
+    );
+
+    pub fn get_guest_password_file(&self) -> Option<&str> {
+        self.guest_password_file.as_deref()
+    }
+
+    impl_setter!(@opt
+    /// Sets the guest domain for Windows guests.
+        guest_domain: String
+    );
+
+    pub fn get_guest_domain(&self) -> Option<&str> {
+        self.guest_domain.as_deref()
+    }
+
+    impl_setter!(@opt
+    /// Sets the maximum time that a guest program run by [`VBoxManage::run`] can take.
+    ///
+    /// The program is terminated when it times out.
+        guest_timeout: Duration
+    );
+
+    pub fn get_guest_timeout(&self) -> Option<Duration> { self.guest_timeout }
+
+    impl_setter!(
+    /// Sets the guest credentials per VM name or guest OS family.
+    ///
+    /// A matching credential is used instead of the guest username, password, password file and domain.
+    /// Matching an OS family queries the guest OS type of the VM before each guest command.
+        guest_credentials: GuestCredentials
+    );
+
+    pub fn get_guest_credentials(&self) -> &GuestCredentials {
+        &self.guest_credentials
+    }
+
+    impl_setter!(@opt
+    /// Sets the frontend that [`VBoxManage::start_vm`] starts VMs with.
+    ///
+    /// `None` uses the default frontend of VBoxManage.
+        start_type: StartType
+    );
+
+    pub fn get_start_type(&self) -> Option<StartType> { self.start_type }
+
+    impl_hooks!();
+
+    /// Returns the credential registered for the current VM in [`Self::guest_credentials`].
+    fn find_guest_credential(&self) -> Option<&GuestCredential> {
+        if self.guest_credentials.is_empty() {
+            return None;
+        }
+        let vm = self.vm_name.as_deref()?;
+        self.guest_credentials.get_for_vm(vm).or_else(|| {
+            if !self.guest_credentials.has_os_families() {
+                return None;
+            }
+            // If the VM cannot be queried, the guest command reports the error.
+            let os = self.get_vm_info().ok()?.guest_os?;
+            self.guest_credentials.get_for_os(&os)
+        })
+    }
+
+    fn build_auth(&self) -> Vec<&str> {
+        let mut v = Vec::with_capacity(8);
+        if let Some(x) = self.find_guest_credential() {
+            v.extend(&["--username", &x.username, "--password", &x.password]);
+            if let Some(x) = &x.domain {
+                v.extend(&["--domain", x]);
+            }
+            return v;
+        }
+        if let Some(x) = &self.guest_username {
+            v.extend(&["--username", x]);
+        }
+        if let Some(x) = &self.guest_password {
+            v.extend(&["--password", x]);
+        }
+        if let Some(x) = &self.guest_password_file {
+            v.extend(&["--passwordfile", x]);
+        }
+        if let Some(x) = &self.guest_domain {
+            v.extend(&["--domain", x]);
+        }
+        v
+    }
+
+    #[inline]
+    fn handle_error(s: &str) -> VmError {
+        use ErrorKind::*;
+        use VmPowerState::*;
+        starts_err!(s, "Could not find a registered machine named", VmNotFound);
+        starts_err!(
+            s,
+            "Could not find a registered machine with UUID",
+            VmNotFound
+        );
+        starts_err!(
+            s,
+            "Cannot unregister the machine",
+            InvalidPowerState(Running)
+        );
+        starts_err!(s, "Could not find a snapshot named ", SnapshotNotFound);
+        starts_err!(
+            s,
+            "This machine does not have any snapshots",
+            SnapshotNotFound
+        );
+        starts_err!(
+            s,
+            "The specified user was not able to logon on guest",
+            GuestAuthenticationFailed
+        );
+        starts_err!(
+            s,
+            "Waiting for guest process failed: The guest execution service is \
+             not ready (yet)",
+            ServiceIsNotRunning
+        );
+        starts_err!(
+            s,
+            "Error starting guest session (current status is:",
+            ServiceIsNotRunning
+        );
+        starts_err!(s, "Cannot stat for element", GuestFileNotFound);
+        if s.starts_with("FsObjQueryInfo failed on") || s.starts_with("File ") {
+            let s = s.lines().last().unwrap();
+            return VmError::from(FileError(
+                s[s.rfind(':').unwrap() + 2..].to_string(),
+            ));
+        }
+
+        if let Some(s) = s.strip_prefix("RTPathQueryInfo failed on ") {
+            return VmError::from(FileError(s.to_string()));
+        }
+
+        if let Some(s) = s.strip_prefix("Invalid machine state: ") {
+            starts_err!(s, "PoweredOff", InvalidPowerState(Stopped));
+            starts_err!(s, "Paused", InvalidPowerState(Paused));
+        }
+        if let Some(s) = s.strip_prefix("Machine in invalid state ") {
+            starts_err!(s, "1 -- powered off", InvalidPowerState(Stopped));
+            starts_err!(s, "2 -- saved", InvalidPowerState(Suspended));
+        }
+        if s.ends_with(" is not currently running")
+            || s.contains("is not running")
+        {
+            return VmError::from(ErrorKind::InvalidPowerState(NotRunning));
+        }
+        if s.lines().next().unwrap().contains("password is incorrect") {
+            return VmError::from(ErrorKind::AuthenticationFailed);
+        }
+        if s.lines().next().unwrap().ends_with(
+            "is already locked by a session (or being locked or unlocked)",
+        ) {
+            return VmError::from(ErrorKind::InvalidPowerState(Running));
+        }
+        VmError::from(Repr::Unknown(format!("Unknown error: {}", s)))
+    }
+
+    /// Checks `s` UUID.
+    pub fn is_uuid(s: &str) -> bool {
+        regex::Regex::new(
+            r#"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$"#
+        )
+            .unwrap()
+            .is_match(s)
+    }
+
+    #[inline]
+    fn check(s: String) -> VmResult<String> {
+        const ERROR_STR: &str = "vboxmanage.exe: error: ";
+        if s.get(..ERROR_STR.len())
+            .map_or(false, |x| x.eq_ignore_ascii_case(ERROR_STR))
+        {
+            Err(Self::handle_error(s[ERROR_STR.len()..].trim()))
+        } else {
+            Ok(s)
+        }
+    }
+
+    fn exec(&self, cmd: &mut Command) -> VmResult<String> {
+        self.exec_with(cmd, |op, o| self.check_output(op, o))
+    }
+
+    /// Executes `cmd` like [`Self::exec`], writing `password` to stdin.
+    ///
+    /// `cmd` takes the password from the password file named `stdin`.
+    fn exec_password(
+        &self,
+        cmd: &mut Command,
+        password: &str,
+    ) -> VmResult<String> {
+        self.exec_run(
+            cmd,
+            |cmd| exec_cmd_with_input(cmd, password.as_bytes()),
+            |op, o| self.check_output(op, o),
+        )
+    }
+
+    /// Executes `cmd` like [`Self::exec`], calling `on_progress` with each percentage of the progress written to stderr.
+    fn exec_progress<F: FnMut(u32)>(
+        &self,
+        cmd: &mut Command,
+        mut on_progress: F,
+    ) -> VmResult<String> {
+        let mut progress = ProgressParser::default();
+        self.exec_run(
+            cmd,
+            |cmd| {
+                exec_cmd_with_stderr(cmd, |x| {
+                    progress.feed(x, &mut on_progress)
+                })
+            },
+            |op, o| self.check_output(op, o),
+        )
+    }
+
+    /// Returns stdout of `o`, or the error written to stderr.
+    fn check_output(&self, op: &str, o: CmdOutput) -> VmResult<String> {
+        let errors =
+            self.hooks
+                .classify_stderr(op, &o.stderr, o.status.success());
+        o.check_status(if !errors.is_empty() {
+            Self::check(errors)
+        } else {
+            Ok(o.stdout.clone())
+        })
+    }
+
+    /// Executes `cmd` with the hooks and passes the output to `f`.
+    fn exec_with<T, F: FnOnce(&str, CmdOutput) -> VmResult<T>>(
+        &self,
+        cmd: &mut Command,
+        f: F,
+    ) -> VmResult<T> {
+        self.exec_run(cmd, exec_cmd, f)
+    }
+
+    /// Executes `cmd` with `run` and the hooks, and passes the output to `f`.
+    fn exec_run<T, R, F>(&self, cmd: &mut Command, run: R, f: F) -> VmResult<T>
+    where
+        R: FnOnce(&mut Command) -> VmResult<CmdOutput>,
+        F: FnOnce(&str, CmdOutput) -> VmResult<T>,
+    {
+        let mut versioned = self.versioned_cmd(cmd)?;
+        let cmd = match versioned.as_mut() {
+            Some(x) => x,
+            None => cmd,
+        };
+        let mut args = sanitized_args(cmd, &["--password", "--passphrase"]);
+        let op = if args.is_empty() {
+            String::new()
+        } else {
+            args.remove(0)
+        };
+        self.hooks.run(&op, &args, || f(&op, run(cmd)?))
+    }
+
+    /// Returns `cmd` with the option names of the detected VirtualBox version, or `None` if `cmd` can be run as is.
+    ///
+    /// The option names are selected for each command run by [`VBoxManage`].
+    /// `modifyvm` is the only command run by [`VBoxManage`] whose options were renamed in VirtualBox 7.0, so it is rewritten with [`MODIFYVM_OPTIONS_V7`] on 7.0 or later and the other commands are run as is.
+    /// The environment variables and the working directory of `cmd` are copied. The standard I/O is not copied because `Command` doesn't expose it, so it must be set by the caller of this function.
+    fn versioned_cmd(&self, cmd: &Command) -> VmResult<Option<Command>> {
+        if cmd.get_args().next() != Some(OsStr::new("modifyvm"))
+            || self.get_vbox_version()?.major < 7
+        {
+            return Ok(None);
+        }
+        let mut ret = self.cmd();
+        ret.args(modifyvm_args_v7(&cmd.get_args().collect::<Vec<_>>()));
+        for (k, v) in cmd.get_envs() {
+            match v {
+                Some(v) => ret.env(k, v),
+                None => ret.env_remove(k),
+            };
+        }
+        if let Some(x) = cmd.get_current_dir() {
+            ret.current_dir(x);
+        }
+        Ok(Some(ret))
+    }
+
+    #[inline]
+    fn cmd(&self) -> Command { Command::new(&self.executable_path) }
+
+    /// Gets the VBoxManage version.
+    pub fn version(&self) -> VmResult<String> {
+        Ok(self.exec(self.cmd().arg("-v"))?.trim().to_string())
+    }
+
+    /// Gets the VirtualBox version with `-v`.
+    ///
+    /// The version is detected once and cached, and selects the option names of `modifyvm`.
+    pub fn get_vbox_version(&self) -> VmResult<VBoxVersion> {
+        if let Some(x) = *self.vbox_version.lock().unwrap() {
+            return Ok(x);
+        }
+        let s = self.version()?;
+        // Warnings such as a missing kernel module can precede the version.
+        let version = s
+            .lines()
+            .rev()
+            .find_map(VBoxVersion::parse)
+            .ok_or_else(|| VmError::from(ErrorKind::UnexpectedResponse(s)))?;
+        *self.vbox_version.lock().unwrap() = Some(version);
+        Ok(version)
+    }
+
+    /// Gets the guest OS types with `list ostypes`.
+    pub fn list_os_types(&self) -> VmResult<Vec<OsType>> {
+        let s = self.exec(self.cmd().args(["list", "ostypes"]))?;
+        Ok(parse_os_types(&s))
+    }
+
+    /// Gets the host information with `list hostinfo`.
+    pub fn get_host_info(&self) -> VmResult<HostInfo> {
+        let s = self.exec(self.cmd().args(["list", "hostinfo"]))?;
+        Ok(parse_host_info(&s))
+    }
+
+    /// Gets the global settings and limits with `list systemproperties`.
+    pub fn get_system_properties(&self) -> VmResult<SystemProperties> {
+        let s = self.exec(self.cmd().args(["list", "systemproperties"]))?;
+        Ok(parse_system_properties(&s))
+    }
+
+    /// Gets a list of VMs.
+    pub fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        let s = self.exec(self.cmd().args(["list", "vms"]))?;
+        Ok(parse_vm_list(&s))
+    }
+
+    /// Gets a list of running VMs with `list runningvms`.
+    ///
+    /// This checks the power state of all VMs with one command instead of `showvminfo` per VM.
+    pub fn list_running_vms(&self) -> VmResult<Vec<Vm>> {
+        let s = self.exec(self.cmd().args(["list", "runningvms"]))?;
+        Ok(parse_vm_list(&s))
+    }
+
+    /// Returns true if a VM whose name or UUID is `name_or_uuid` is registered.
+    pub fn is_registered(&self, name_or_uuid: &str) -> VmResult<bool> {
+        Ok(contains_vm(&self.list_vms()?, name_or_uuid))
+    }
+
+    /// Gets the groups of the VM from `groups` of `showvminfo`, e.g., `/lab/web`.
+    pub fn get_groups(&self) -> VmResult<Vec<String>> {
+        let s = self.show_vm_info()?;
+        Ok(Self::parse_info(&s, None)
+            .get("groups")
+            .map_or_else(Vec::new, |x| split_groups(x)))
+    }
+
+    /// Sets the groups of the VM with `modifyvm --groups`.
+    ///
+    /// An empty `groups` puts the VM in the root group `/`.
+    pub fn set_groups(&self, groups: &[&str]) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "modifyvm",
+            self.get_vm()?,
+            "--groups",
+            &groups.join(","),
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets a list of the groups that contain VMs with `list groups`.
+    pub fn list_groups(&self) -> VmResult<Vec<String>> {
+        let s = self.exec(self.cmd().args(["list", "groups"]))?;
+        Ok(s.lines()
+            .map(|x| x.trim().trim_matches('"'))
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect())
+    }
+
+    /// Gets the VMs per group with `list --long vms`.
+    ///
+    /// A VM in several groups appears in each of them.
+    pub fn list_vms_by_group(&self) -> VmResult<BTreeMap<String, Vec<Vm>>> {
+        let s = self.exec(self.cmd().args(["list", "--long", "vms"]))?;
+        let mut ret: BTreeMap<String, Vec<Vm>> = BTreeMap::new();
+        for (vm, groups) in parse_long_vm_list(&s) {
+            for group in groups {
+                ret.entry(group).or_default().push(vm.clone());
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Gets the VMs in `group`, e.g., `/lab`.
+    ///
+    /// VMs in the subgroups of `group` are not included.
+    pub fn list_group_vms(&self, group: &str) -> VmResult<Vec<Vm>> {
+        Ok(self.list_vms_by_group()?.remove(group).unwrap_or_default())
+    }
+
+    /// Starts the VMs in `group` that are not running with the frontend set by [`VBoxManage::start_type`].
+    pub fn start_group(&self, group: &str) -> VmResult<()> {
+        self.for_each_group_vm(group, false, Self::start_vm)
+    }
+
+    /// Powers off the running VMs in `group` with `controlvm poweroff`.
+    pub fn poweroff_group(&self, group: &str) -> VmResult<()> {
+        self.for_each_group_vm(group, true, Self::poweroff_vm)
+    }
+
+    /// Sends ACPI shutdown signal to the running VMs in `group`.
+    ///
+    /// This function returns without waiting for the VMs to shut down.
+    pub fn acpi_power_button_group(&self, group: &str) -> VmResult<()> {
+        self.for_each_group_vm(group, true, Self::acpi_power_button_vm)
+    }
+
+    /// Calls `f` for each VM in `group` whose running state is `running`.
+    fn for_each_group_vm<F>(
+        &self,
+        group: &str,
+        running: bool,
+        f: F,
+    ) -> VmResult<()>
+    where
+        F: Fn(&Self) -> VmResult<()>,
+    {
+        let running_vms = self.list_running_vms()?;
+        // Handle all the VMs before returning the first error.
+        let results: Vec<VmResult<()>> = self
+            .list_group_vms(group)?
+            .into_iter()
+            .filter(|vm| {
+                let id = vm.id.as_deref().unwrap_or_default();
+                contains_vm(&running_vms, id) == running
+            })
+            .map(|vm| {
+                let mut cmd = self.clone();
+                cmd.vm_name(vm.id.or(vm.name));
+                f(&cmd)
+            })
+            .collect();
+        results.into_iter().collect()
+    }
+
+    /// Gets the power state of the VM from `VMState` of `showvminfo`.
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        const VMS: &str = "VMState=\"";
+        let s = self.show_vm_info()?;
+        for x in s.lines() {
+            if let Some(state) = x.strip_prefix(VMS) {
+                return Ok(to_power_state(state.trim_end_matches('"')));
+            }
+        }
+        vmerr!(ErrorKind::UnexpectedResponse(s))
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+
+    pub fn show_vm_info(&self) -> VmResult<String> {
+        self.show_vm_info2(self.get_vm()?)
+    }
+
+    #[deprecated(note = "Use `get_vm_info().guest_os` instead")]
+    pub fn get_os_version(&self) -> VmResult<String> {
+        self.get_vm_info()?.guest_os.ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse("ostype".to_string()))
+        })
+    }
+
+    /// Gets the detailed information of the VM.
+    ///
+    /// `tools_version` is reported while the Guest Additions are running.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        Ok(Self::parse_vm_info(&self.show_vm_info()?))
+    }
+
+    fn parse_info<'a>(
+        s: &'a str,
+        stopper: Option<&str>,
+    ) -> HashMap<&'a str, &'a str> {
+        let l = s.lines();
+        let mut hm = HashMap::new();
+        for x in l {
+            let x: Vec<&str> = x.splitn(2, '=').collect();
+            if x.len() != 2 {
+                continue;
+            }
+            let (key, value) = (x[0].trim(), x[1].trim());
+            let value = if value.len() >= 2 {
+                if value.starts_with('"') && value.ends_with('"') {
+                    // strip ""
+                    &value[1..value.len() - 1]
+                } else {
+                    value
+                }
+            } else {
+                value
+            };
+            hm.insert(key, value);
+            if let Some(stopper) = stopper {
+                if key == stopper {
+                    break;
+                }
+            }
+        }
+        hm
+    }
+
+    /// Parses the output of `showvminfo --machinereadable` into [`Vm`] in the same format as [`VBoxManage::list_vms`].
+    fn parse_vm(s: &str) -> Vm {
+        let hm = Self::parse_info(s, Some("CfgFile"));
+        Vm {
+            id: hm.get("UUID").map(|x| format!("{{{}}}", x)),
+            name: hm.get("name").map(|x| x.to_string()),
+            path: hm.get("CfgFile").map(|x| x.replace("\\\\", "\\")),
+        }
+    }
+
+    /// Parses the output of `showvminfo --machinereadable` into [`VmInfo`].
+    fn parse_vm_info(s: &str) -> VmInfo {
+        let vm = Self::parse_vm(s);
+        let hm = Self::parse_info(s, None);
+        VmInfo {
+            name: vm.name,
+            guest_os: hm.get("ostype").map(|x| x.to_string()),
+            memory_mb: hm.get("memory").and_then(|x| x.parse().ok()),
+            cpu_count: hm.get("cpus").and_then(|x| x.parse().ok()),
+            firmware: hm.get("firmware").map(|x| {
+                if x.starts_with("EFI") {
+                    Firmware::Efi
+                } else {
+                    Firmware::Bios
+                }
+            }),
+            generation: None,
+            power_state: hm.get("VMState").map(|x| to_power_state(x)),
+            config_path: vm.path,
+            tools_version: hm
+                .get("GuestAdditionsVersion")
+                .map(|x| x.to_string()),
+        }
+    }
+
+    fn list_host_interfaces(&self, ty: &str) -> VmResult<Vec<HostInterface>> {
+        let s = self.exec(self.cmd().args(["list", ty]))?;
+        Ok(parse_list_blocks(&s)
+            .iter()
+            .map(|x| {
+                let get = |key| x.get(key).map(|x| x.to_string());
+                HostInterface {
+                    name: get("Name").unwrap_or_default(),
+                    guid: get("GUID"),
+                    dhcp: x.get("DHCP") == Some(&"Enabled"),
+                    ip_address: get("IPAddress"),
+                    network_mask: get("NetworkMask"),
+                    hardware_address: get("HardwareAddress"),
+                    status: get("Status"),
+                }
+            })
+            .collect())
+    }
+
+    /// Gets a list of host interfaces which can be used for bridged networking.
+    pub fn list_bridged_ifs(&self) -> VmResult<Vec<HostInterface>> {
+        self.list_host_interfaces("bridgedifs")
+    }
+
+    /// Gets a list of host-only interfaces.
+    pub fn list_host_only_ifs(&self) -> VmResult<Vec<HostInterface>> {
+        self.list_host_interfaces("hostonlyifs")
+    }
+
+    /// Gets a list of NAT networks.
+    pub fn list_nat_networks(&self) -> VmResult<Vec<NatNetwork>> {
+        let s = self.exec(self.cmd().args(["list", "natnets"]))?;
+        Ok(parse_list_blocks(&s)
+            .iter()
+            .map(|x| {
+                let get = |key| x.get(key).map(|x| x.to_string());
+                NatNetwork {
+                    // VirtualBox 7 prints `Name` instead of `NetworkName`.
+                    name: get("NetworkName")
+                        .or_else(|| get("Name"))
+                        .unwrap_or_default(),
+                    network: get("Network"),
+                    gateway: get("IP").or_else(|| get("Gateway")),
+                    dhcp: x.get("DHCP Enabled") == Some(&"Yes")
+                        || x.get("DHCP Server") == Some(&"Yes"),
+                    enabled: x.get("Enabled") == Some(&"Yes"),
+                }
+            })
+            .collect())
+    }
+
+    /// Creates a host-only interface with `hostonlyif create` and returns its name.
+    pub fn create_host_only_if(&self) -> VmResult<String> {
+        let s = self.exec(self.cmd().args(["hostonlyif", "create"]))?;
+        parse_created_host_only_if(&s)
+            .ok_or_else(|| VmError::from(ErrorKind::UnexpectedResponse(s)))
+    }
+
+    /// Sets the IPv4 address and the netmask of the host on the host-only interface `name`.
+    pub fn config_host_only_if(
+        &self,
+        name: &str,
+        ip: &str,
+        netmask: &str,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "hostonlyif",
+            "ipconfig",
+            name,
+            "--ip",
+            ip,
+            "--netmask",
+            netmask,
+        ]))?;
+        Ok(())
+    }
+
+    /// Removes the host-only interface `name`.
+    pub fn remove_host_only_if(&self, name: &str) -> VmResult<()> {
+        self.exec(self.cmd().args(["hostonlyif", "remove", name]))?;
+        Ok(())
+    }
+
+    /// Adds an enabled DHCP server to the host-only interface `if_name`, which leases `lower_ip` to `upper_ip`.
+    pub fn add_dhcp_server(
+        &self,
+        if_name: &str,
+        server_ip: &str,
+        netmask: &str,
+        lower_ip: &str,
+        upper_ip: &str,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "dhcpserver",
+            "add",
+            "--ifname",
+            if_name,
+            "--ip",
+            server_ip,
+            "--netmask",
+            netmask,
+            "--lowerip",
+            lower_ip,
+            "--upperip",
+            upper_ip,
+            "--enable",
+        ]))?;
+        Ok(())
+    }
+
+    /// Changes the settings of the DHCP server of the host-only interface `if_name` with `dhcpserver modify`.
+    pub fn modify_dhcp_server(
+        &self,
+        if_name: &str,
+        settings: &DhcpServerSettings,
+    ) -> VmResult<()> {
+        self.exec(
+            self.cmd()
+                .args(["dhcpserver", "modify", "--ifname", if_name])
+                .args(settings.to_args()),
+        )?;
+        Ok(())
+    }
+
+    /// Gets the DHCP servers of the host-only interfaces and the NAT networks with `list dhcpservers`.
+    pub fn list_dhcp_servers(&self) -> VmResult<Vec<DhcpServer>> {
+        let s = self.exec(self.cmd().args(["list", "dhcpservers"]))?;
+        Ok(parse_dhcp_servers(&s))
+    }
+
+    /// Removes the DHCP server of the host-only interface `if_name`.
+    pub fn remove_dhcp_server(&self, if_name: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "dhcpserver",
+            "remove",
+            "--ifname",
+            if_name,
+        ]))?;
+        Ok(())
+    }
+
+    /// Adds an enabled NAT network `name` of `network` in CIDR notation, e.g., `10.0.2.0/24`.
+    ///
+    /// `dhcp` turns the DHCP server of the network on or off, or leaves the default if `None`.
+    pub fn add_nat_network(
+        &self,
+        name: &str,
+        network: &str,
+        dhcp: Option<bool>,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args([
+            "natnetwork",
+            "add",
+            "--netname",
+            name,
+            "--network",
+            network,
+            "--enable",
+        ]);
+        if let Some(x) = dhcp {
+            cmd.args(["--dhcp", if x { "on" } else { "off" }]);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Removes the NAT network `name`.
+    pub fn remove_nat_network(&self, name: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "natnetwork",
+            "remove",
+            "--netname",
+            name,
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets a list of USB devices of the host with `list usbhost`.
+    pub fn list_usb_hosts(&self) -> VmResult<Vec<UsbDevice>> {
+        let s = self.exec(self.cmd().args(["list", "usbhost"]))?;
+        Ok(parse_usb_hosts(&s))
+    }
+
+    /// Attaches the host USB device of `id` (the UUID or the address) to the running VM.
+    pub fn usb_attach(&self, id: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "controlvm",
+            self.get_vm()?,
+            "usbattach",
+            id,
+        ]))?;
+        Ok(())
+    }
+
+    /// Detaches the host USB device of `id` (the UUID or the address) from the running VM.
+    pub fn usb_detach(&self, id: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "controlvm",
+            self.get_vm()?,
+            "usbdetach",
+            id,
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets the USB device filters of the VM in the order of their indices.
+    pub fn list_usb_filters(&self) -> VmResult<Vec<UsbFilter>> {
+        let s = self.show_vm_info()?;
+        Ok(parse_usb_filters(&Self::parse_info(&s, None)))
+    }
+
+    /// Inserts `filter` at `index` of the USB device filters of the VM with `usbfilter add`.
+    pub fn add_usb_filter(
+        &self,
+        index: usize,
+        filter: &UsbFilter,
+    ) -> VmResult<()> {
+        let vm = self.get_vm()?;
+        self.usb_filter("add", index, vm, filter.to_args())
+    }
+
+    /// Replaces the USB device filter at `index` of the VM with `filter` with `usbfilter modify`.
+    pub fn modify_usb_filter(
+        &self,
+        index: usize,
+        filter: &UsbFilter,
+    ) -> VmResult<()> {
+        let vm = self.get_vm()?;
+        self.usb_filter("modify", index, vm, filter.to_args())
+    }
+
+    /// Removes the USB device filter at `index` of the VM with `usbfilter remove`.
+    pub fn remove_usb_filter(&self, index: usize) -> VmResult<()> {
+        self.usb_filter("remove", index, self.get_vm()?, vec![])
+    }
+
+    /// Gets the global USB device filters of the host with `list usbfilters`.
+    pub fn list_global_usb_filters(&self) -> VmResult<Vec<UsbFilter>> {
+        let s = self.exec(self.cmd().args(["list", "usbfilters"]))?;
+        Ok(parse_global_usb_filters(&s))
+    }
+
+    /// Inserts `filter` at `index` of the global USB device filters.
+    ///
+    /// `filter.action` must be specified.
+    pub fn add_global_usb_filter(
+        &self,
+        index: usize,
+        filter: &UsbFilter,
+    ) -> VmResult<()> {
+        self.usb_filter("add", index, "global", filter.to_args())
+    }
+
+    /// Replaces the global USB device filter at `index` with `filter`.
+    pub fn modify_global_usb_filter(
+        &self,
+        index: usize,
+        filter: &UsbFilter,
+    ) -> VmResult<()> {
+        self.usb_filter("modify", index, "global", filter.to_args())
+    }
+
+    /// Removes the global USB device filter at `index`.
+    pub fn remove_global_usb_filter(&self, index: usize) -> VmResult<()> {
+        self.usb_filter("remove", index, "global", vec![])
+    }
+
+    fn usb_filter(
+        &self,
+        op: &str,
+        index: usize,
+        target: &str,
+        args: Vec<String>,
+    ) -> VmResult<()> {
+        self.exec(
+            self.cmd()
+                .args(["usbfilter", op, &index.to_string(), "--target", target])
+                .args(args),
+        )?;
+        Ok(())
+    }
+
+    /// Gets the port forwarding rules of the NAT NIC `nic` (1 to 8) of the VM.
+    pub fn list_nat_port_forwards(
+        &self,
+        nic: usize,
+    ) -> VmResult<Vec<PortForward>> {
+        Ok(parse_port_forwards(&self.show_vm_info()?, nic))
+    }
+
+    /// Forwards `host_port` on all host addresses to `guest_port` of the VM through the NAT NIC `nic` (1 to 8).
+    pub fn add_nat_port_forward(
+        &self,
+        nic: usize,
+        name: &str,
+        protocol: Protocol,
+        host_port: u16,
+        guest_port: u16,
+    ) -> VmResult<()> {
+        let mut rule = PortForward::new(protocol, host_port, guest_port);
+        rule.name = Some(name.to_string());
+        self.add_nat_port_forward_rule(nic, &rule)
+    }
+
+    /// Adds a port forwarding rule to the NAT NIC `nic` (1 to 8) of the VM with `natpf<nic>`.
+    ///
+    /// A rule without a name is named `<protocol>_<host_port>`.
+    pub fn add_nat_port_forward_rule(
+        &self,
+        nic: usize,
+        rule: &PortForward,
+    ) -> VmResult<()> {
+        self.natpf(nic, &[&to_natpf_rule(rule)])
+    }
+
+    /// Deletes the port forwarding rule `name` from the NAT NIC `nic` (1 to 8) of the VM.
+    pub fn delete_nat_port_forward(
+        &self,
+        nic: usize,
+        name: &str,
+    ) -> VmResult<()> {
+        self.natpf(nic, &["delete", name])
+    }
+
+    /// Runs `controlvm natpf<nic>` if the VM is running, or `modifyvm --natpf<nic>` otherwise.
+    fn natpf(&self, nic: usize, args: &[&str]) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        if self.get_power_state()?.is_running() {
+            cmd.args(["controlvm", self.get_vm()?])
+                .arg(format!("natpf{}", nic));
+        } else {
+            cmd.args(["modifyvm", self.get_vm()?])
+                .arg(format!("--natpf{}", nic));
+        }
+        cmd.args(args);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Adds a port forwarding rule to the NAT network `net_name`.
+    ///
+    /// A rule whose addresses are IPv6 addresses is added with `--port-forward-6`.
+    pub fn add_nat_network_port_forward(
+        &self,
+        net_name: &str,
+        rule: &PortForward,
+    ) -> VmResult<()> {
+        let ipv6 = [&rule.host_ip, &rule.guest_ip]
+            .iter()
+            .any(|x| x.as_deref().map_or(false, |x| x.contains(':')));
+        let opt = if ipv6 {
+            "--port-forward-6"
+        } else {
+            "--port-forward-4"
+        };
+        self.exec(self.cmd().args([
+            "natnetwork",
+            "modify",
+            "--netname",
+            net_name,
+            opt,
+            &to_natpf_rule(rule),
+        ]))?;
+        Ok(())
+    }
+
+    /// Removes the IPv4 port forwarding rule `name` from the NAT network `net_name`.
+    pub fn remove_nat_network_port_forward(
+        &self,
+        net_name: &str,
+        name: &str,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "natnetwork",
+            "modify",
+            "--netname",
+            net_name,
+            "--port-forward-4",
+            "delete",
+            name,
+        ]))?;
+        Ok(())
+    }
+
+    /// Returns the number of the first NAT NIC of the VM.
+    fn first_nat_nic(&self) -> VmResult<usize> {
+        self.list_nics()?
+            .into_iter()
+            .find(|x| x.ty == Some(NicType::NAT))
+            .and_then(|x| x.id?.parse().ok())
+            .ok_or_else(|| {
+                VmError::from(ErrorKind::InvalidParameter(
+                    "The VM has no NAT NIC".to_string(),
+                ))
+            })
+    }
+
+    /// Gets a list of internal networks used by VMs.
+    pub fn list_internal_networks(&self) -> VmResult<Vec<String>> {
+        let s = self.exec(self.cmd().args(["list", "intnets"]))?;
+        Ok(parse_list_blocks(&s)
+            .iter()
+            .filter_map(|x| x.get("Name").map(|x| x.to_string()))
+            .collect())
+    }
+
+    /// Gets NICs of the VM.
+    pub fn list_nics(&self) -> VmResult<Vec<Nic>> {
+        let _op = self.hooks.operation("list_nics");
+        let s = self.show_vm_info()?;
+        let hm = Self::parse_info(&s, None);
+        let mut ret = vec![];
+        for i in 1..=MAX_NICS {
+            let ty = match hm.get(format!("nic{}", i).as_str()) {
+                Some(&"none") | None => continue,
+                Some(x) => *x,
+            };
+            let (ty, name_key) = match ty {
+                "nat" => (NicType::NAT, None),
+                "bridged" => (NicType::Bridge, Some("bridgeadapter")),
+                "hostonly" => (NicType::HostOnly, Some("hostonlyadapter")),
+                "intnet" => (NicType::Custom(ty.to_string()), Some("intnet")),
+                "natnetwork" => {
+                    (NicType::Custom(ty.to_string()), Some("nat-network"))
+                }
+                x => (NicType::Custom(x.to_string()), None),
+            };
+            ret.push(Nic {
+                id: Some(i.to_string()),
+                name: name_key.and_then(|key| {
+                    hm.get(format!("{}{}", key, i).as_str())
+                        .map(|x| x.to_string())
+                }),
+                ty: Some(ty),
+                mac_address: hm
+                    .get(format!("macaddress{}", i).as_str())
+                    .map(|x| x.to_string()),
+            });
+        }
+        Ok(ret)
+    }
+
+    /// Checks that the network `nic` is attached to exists on the host.
+    fn validate_network(&self, nic: &Nic) -> VmResult<()> {
+        let name = match &nic.name {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let found = match &nic.ty {
+            Some(NicType::Bridge) => {
+                self.list_bridged_ifs()?.iter().any(|x| &x.name == name)
+            }
+            Some(NicType::HostOnly) => {
+                self.list_host_only_ifs()?.iter().any(|x| &x.name == name)
+            }
+            Some(NicType::Custom(x)) if x == "natnetwork" => {
+                self.list_nat_networks()?.iter().any(|x| &x.name == name)
+            }
+            // Internal networks are created on demand.
+            _ => true,
+        };
+        if found {
+            Ok(())
+        } else {
+            vmerr!(ErrorKind::NetworkNotFound)
+        }
+    }
+
+    /// Attaches the NIC in the slot `nic.id` to the network specified by `nic.ty` and `nic.name`.
+    ///
+    /// If the network doesn't exist, returns [`ErrorKind::NetworkNotFound`] without modifying the VM.
+    pub fn modify_nic(&self, nic: &Nic) -> VmResult<()> {
+        let index = match nic.id.as_deref().map(str::parse::<usize>) {
+            Some(Ok(x)) if (1..=MAX_NICS).contains(&x) => x,
+            _ => {
+                return vmerr!(ErrorKind::InvalidParameter(
+                    "id must be the NIC slot number".to_string()
+                ))
+            }
+        };
+        let ty = nic.ty.as_ref().ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(
+                "ty is required".to_string(),
+            ))
+        })?;
+        self.validate_network(nic)?;
+        let (ty, name_opt) = match ty {
+            NicType::NAT => ("nat", None),
+            NicType::Bridge => ("bridged", Some("bridgeadapter")),
+            NicType::HostOnly => ("hostonly", Some("hostonlyadapter")),
+            NicType::Custom(x) => match x.as_str() {
+                "intnet" => ("intnet", Some("intnet")),
+                "natnetwork" => ("natnetwork", Some("nat-network")),
+                x => (x, None),
+            },
+        };
+        let mut cmd = self.cmd();
+        cmd.args(["modifyvm", self.get_vm()?]);
+        cmd.arg(format!("--nic{}", index)).arg(ty);
+        if let (Some(opt), Some(name)) = (name_opt, &nic.name) {
+            cmd.arg(format!("--{}{}", opt, index)).arg(name);
+        }
+        if let Some(mac) = &nic.mac_address {
+            cmd.arg(format!("--macaddress{}", index))
+                .arg(mac.replace(':', ""));
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Detaches the NIC in the slot `index`.
+    pub fn remove_nic(&self, index: usize) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_nic");
+        self.exec(self.cmd().args([
+            "modifyvm",
+            self.get_vm()?,
+            &format!("--nic{}", index),
+            "none",
+        ]))?;
+        Ok(())
+    }
+
+    /// Sets the NAT engine settings of the NIC in the slot `index`.
+    ///
+    /// The settings take effect when the NIC is attached to NAT.
+    pub fn set_nat_engine(
+        &self,
+        index: usize,
+        settings: &NatEngineSettings,
+    ) -> VmResult<()> {
+        if !(1..=MAX_NICS).contains(&index) {
+            return vmerr!(ErrorKind::InvalidParameter(
+                "index must be the NIC slot number".to_string()
+            ));
+        }
+        let args = settings.to_args(index);
+        if args.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["modifyvm", self.get_vm()?]).args(args);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Changes the settings of the VM with `modifyvm`.
+    ///
+    /// Most settings can't be changed while the VM is running.
+    pub fn modify_vm(&self, settings: &VmSettings) -> VmResult<()> {
+        let args = settings.to_args()?;
+        if args.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["modifyvm", self.get_vm()?]).args(args);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the settings and the listening port of the VRDE server of the VM.
+    pub fn get_vrde_info(&self) -> VmResult<VrdeInfo> {
+        let s = self.show_vm_info()?;
+        Ok(parse_vrde_info(&Self::parse_info(&s, None)))
+    }
+
+    /// Gets the port the VRDE server of the running VM listens on.
+    ///
+    /// Returns `None` if the server is not listening.
+    pub fn get_vrde_port(&self) -> VmResult<Option<u16>> {
+        Ok(self.get_vrde_info()?.active_port)
+    }
+
+    /// Changes the settings of the VRDE server of the powered-off VM with `modifyvm`.
+    ///
+    /// Use [`VBoxManage::set_vrde_enabled`] and [`VBoxManage::set_vrde_ports`] for running VMs.
+    pub fn configure_vrde(&self, settings: &VrdeSettings) -> VmResult<()> {
+        let args = settings.to_args();
+        if args.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["modifyvm", self.get_vm()?]).args(args);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Enables or disables the VRDE server with `controlvm vrde` if the VM is running, or `modifyvm --vrde` otherwise.
+    pub fn set_vrde_enabled(&self, enabled: bool) -> VmResult<()> {
+        self.control_or_modify("vrde", if enabled { "on" } else { "off" })
+    }
+
+    /// Sets the candidate ports of the VRDE server, e.g., `5000-5050`, with `controlvm vrdeport` if the VM is running, or `modifyvm --vrdeport` otherwise.
+    pub fn set_vrde_ports(&self, ports: &str) -> VmResult<()> {
+        self.control_or_modify("vrdeport", ports)
+    }
+
+    /// Changes the autostart settings of the powered-off VM with `modifyvm`.
+    pub fn configure_autostart(
+        &self,
+        settings: &AutostartSettings,
+    ) -> VmResult<()> {
+        let args = settings.to_args();
+        if args.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["modifyvm", self.get_vm()?]).args(args);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Sets the directory of the autostart database with `setproperty autostartdbpath`.
+    ///
+    /// The directory must be writable by the users whose VMs start automatically.
+    /// `None` disables the database.
+    pub fn set_autostart_db_path(&self, path: Option<&str>) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "setproperty",
+            "autostartdbpath",
+            path.unwrap_or("null"),
+        ]))?;
+        Ok(())
+    }
+
+    /// Runs `controlvm <setting> <value>` if the VM is running, or `modifyvm --<setting> <value>` otherwise.
+    fn control_or_modify(&self, setting: &str, value: &str) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        if self.get_power_state()?.is_running() {
+            cmd.args(["controlvm", self.get_vm()?, setting]);
+        } else {
+            cmd.args(["modifyvm", self.get_vm()?])
+                .arg(format!("--{}", setting));
+        }
+        cmd.arg(value);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the serial ports of the VM from `uart<n>` and `uartmode<n>` of `showvminfo`.
+    pub fn get_serial_ports(&self) -> VmResult<Vec<SerialPort>> {
+        let s = self.show_vm_info()?;
+        Ok(parse_serial_ports(&Self::parse_info(&s, None)))
+    }
+
+    /// Configures the serial port `n` (1 to 4) of the VM with `--uart<n>` and `--uartmode<n>`.
+    ///
+    /// e.g., `SerialPortMode::File` with the kernel parameter `console=ttyS0` records the kernel log of a Linux guest.
+    pub fn set_serial_port(
+        &self,
+        n: usize,
+        mode: &SerialPortMode,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("set_serial_port");
+        if !(1..=COM_PORTS.len()).contains(&n) {
+            return vmerr!(ErrorKind::InvalidParameter(
+                "n must be the serial port number".to_string()
+            ));
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["modifyvm", self.get_vm()?])
+            .args(uart_args(mode, n));
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the number of CPUs and the CPU execution cap of the VM.
+    pub fn get_cpu_tuning(&self) -> VmResult<CpuTuning> {
+        let _op = self.hooks.operation("get_cpu_tuning");
+        let s = self.show_vm_info()?;
+        let hm = Self::parse_info(&s, None);
+        let get = |key: &str| hm.get(key).and_then(|x| x.parse().ok());
+        Ok(CpuTuning {
+            count: get("cpus"),
+            maximum: get("cpuexecutioncap"),
+            ..Default::default()
+        })
+    }
+
+    /// Sets the number of CPUs with `--cpus` and the CPU execution cap with `--cpuexecutioncap`.
+    ///
+    /// VirtualBox doesn't support `reserve` and `relative_weight`.
+    pub fn set_cpu_tuning(&self, tuning: &CpuTuning) -> VmResult<()> {
+        let _op = self.hooks.operation("set_cpu_tuning");
+        if tuning.reserve.is_some() || tuning.relative_weight.is_some() {
+            return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "set_cpu_tuning",
+                "Set only count and maximum"
+            ));
+        }
+        if tuning.count.is_none() && tuning.maximum.is_none() {
+            return Ok(());
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["modifyvm", self.get_vm()?]);
+        if let Some(x) = tuning.count {
+            cmd.arg("--cpus").arg(x.to_string());
+        }
+        if let Some(x) = tuning.maximum {
+            cmd.arg("--cpuexecutioncap").arg(x.to_string());
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the memory size of the VM in MB.
+    pub fn get_memory_size(&self) -> VmResult<u64> {
+        let _op = self.hooks.operation("get_memory_size");
+        let s = self.show_vm_info()?;
+        let hm = Self::parse_info(&s, None);
+        hm.get("memory")
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| {
+                VmError::from(ErrorKind::UnexpectedResponse(s.clone()))
+            })
+    }
+
+    /// Sets the memory size of the VM in MB with `--memory`.
+    pub fn set_memory_size(&self, size_mb: u64) -> VmResult<()> {
+        let _op = self.hooks.operation("set_memory_size");
+        self.exec(self.cmd().args([
+            "modifyvm",
+            self.get_vm()?,
+            "--memory",
+            &size_mb.to_string(),
+        ]))?;
+        Ok(())
+    }
+
+    /// Inserts the medium at `medium` into the first DVD drive of the VM with `storageattach`.
+    ///
+    /// `medium` is the path to an ISO image, `emptydrive` to eject the medium or `additions` for the Guest Additions ISO.
+    pub fn set_dvd_medium(&self, medium: &str) -> VmResult<()> {
+        let s = self.show_vm_info()?;
+        let (ctl, port, device) = find_dvd_drive(&s).ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(
+                "The VM has no DVD drive".to_string(),
+            ))
+        })?;
+        self.exec(self.cmd().args([
+            "storageattach",
+            self.get_vm()?,
+            "--storagectl",
+            &ctl,
+            "--port",
+            &port,
+            "--device",
+            &device,
+            "--type",
+            "dvddrive",
+            "--medium",
+            medium,
+        ]))?;
+        Ok(())
+    }
+
+    /// Adds a storage controller `name` on `bus` to the VM with `storagectl --add`.
+    ///
+    /// `port_count` is the number of ports of a SATA, SAS or NVMe controller. The default of the tool is used if `None`.
+    pub fn add_storage_controller(
+        &self,
+        name: &str,
+        bus: StorageBus,
+        port_count: Option<u32>,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args([
+            "storagectl",
+            self.get_vm()?,
+            "--name",
+            name,
+            "--add",
+            bus.as_str(),
+        ]);
+        if let Some(x) = port_count {
+            cmd.arg("--portcount").arg(x.to_string());
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Removes the storage controller `name` and detaches its devices with `storagectl --remove`.
+    pub fn remove_storage_controller(&self, name: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "storagectl",
+            self.get_vm()?,
+            "--name",
+            name,
+            "--remove",
+        ]))?;
+        Ok(())
+    }
+
+    /// Attaches `medium` as a device of `ty` to `port` and `device` of the storage controller `controller` with `storageattach`.
+    ///
+    /// `medium` is the path or the UUID of a disk image or an ISO image, `emptydrive` for an empty drive or `none` to detach the device.
+    pub fn storage_attach(
+        &self,
+        controller: &str,
+        port: u32,
+        device: u32,
+        ty: StorageDeviceType,
+        medium: &str,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "storageattach",
+            self.get_vm()?,
+            "--storagectl",
+            controller,
+            "--port",
+            &port.to_string(),
+            "--device",
+            &device.to_string(),
+            "--type",
+            ty.as_str(),
+            "--medium",
+            medium,
+        ]))?;
+        Ok(())
+    }
+
+    /// Creates a dynamically allocated disk image of `size_mb` at `path` with `createmedium disk` and returns its UUID.
+    ///
+    /// The format is chosen from the extension of `path`, e.g., `.vdi` or `.vmdk`.
+    pub fn create_disk(&self, path: &str, size_mb: u64) -> VmResult<String> {
+        self.create_medium(
+            path,
+            &CreateMediumOptions {
+                size_mb: Some(size_mb),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Creates a disk image at `path` with `createmedium disk` and returns its UUID.
+    pub fn create_medium(
+        &self,
+        path: &str,
+        options: &CreateMediumOptions,
+    ) -> VmResult<String> {
+        let s = self.exec(
+            self.cmd()
+                .args(["createmedium", "disk", "--filename", path])
+                .args(options.to_args()),
+        )?;
+        parse_created_medium_uuid(&s)
+            .map(|x| x.to_string())
+            .ok_or_else(|| VmError::from(ErrorKind::UnexpectedResponse(s)))
+    }
+
+    /// Gets the registered media of `kind` with `list hdds`, `list dvds` or `list floppies`.
+    pub fn list_media(&self, kind: MediumKind) -> VmResult<Vec<Medium>> {
+        let s = self.exec(self.cmd().args(["list", kind.list_arg()]))?;
+        Ok(parse_media(&s))
+    }
+
+    /// Copies the disk image `source`, which is a UUID or a path, to `target` with `clonemedium disk` and returns the UUID of the copy.
+    ///
+    /// `format` is, e.g., `VDI`, `VMDK` or `VHD`. The format of `source` is kept if `None`.
+    pub fn clone_medium(
+        &self,
+        source: &str,
+        target: &str,
+        format: Option<&str>,
+    ) -> VmResult<String> {
+        let mut cmd = self.cmd();
+        cmd.args(["clonemedium", "disk", source, target]);
+        if let Some(x) = format {
+            cmd.args(["--format", x]);
+        }
+        let s = self.exec(&mut cmd)?;
+        parse_created_medium_uuid(&s)
+            .map(|x| x.to_string())
+            .ok_or_else(|| VmError::from(ErrorKind::UnexpectedResponse(s)))
+    }
+
+    /// Resizes the disk image `medium` to `size_mb` with `modifymedium --resize`.
+    ///
+    /// Only dynamically allocated VDI and VHD images can grow, and no image can shrink. The partitions in the image are not resized.
+    pub fn resize_medium(&self, medium: &str, size_mb: u64) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "modifymedium",
+            "disk",
+            medium,
+            "--resize",
+            &size_mb.to_string(),
+        ]))?;
+        Ok(())
+    }
+
+    /// Unregisters the medium of `kind` with `closemedium`, and deletes its file if `delete`.
+    ///
+    /// The medium must be detached from all the VMs.
+    pub fn close_medium(
+        &self,
+        kind: MediumKind,
+        medium: &str,
+        delete: bool,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["closemedium", kind.as_str(), medium]);
+        if delete {
+            cmd.arg("--delete");
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the bandwidth groups of the VM with `bandwidthctl list`.
+    pub fn list_bandwidth_groups(&self) -> VmResult<Vec<BandwidthGroup>> {
+        let s = self.exec(self.cmd().args([
+            "bandwidthctl",
+            self.get_vm()?,
+            "list",
+            "--machinereadable",
+        ]))?;
+        Ok(parse_bandwidth_groups(&s))
+    }
+
+    /// Adds a bandwidth group `name` limited to `max_bytes_per_sec` to the VM with `bandwidthctl add`.
+    ///
+    /// The limit is rounded up to a multiple of 1 KiB. NICs and disks share the limit of the group they are assigned to with [`VBoxManage::set_nic_bandwidth_group`] and [`VBoxManage::set_disk_bandwidth_group`].
+    pub fn add_bandwidth_group(
+        &self,
+        name: &str,
+        ty: BandwidthGroupType,
+        max_bytes_per_sec: u64,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "bandwidthctl",
+            self.get_vm()?,
+            "add",
+            name,
+            "--type",
+            ty.as_str(),
+            "--limit",
+            &bandwidth_limit_arg(max_bytes_per_sec),
+        ]))?;
+        Ok(())
+    }
+
+    /// Changes the limit of the bandwidth group `name` with `bandwidthctl set`, which also takes effect on the running VM.
+    ///
+    /// The limit is rounded up to a multiple of 1 KiB. `0` disables the limit.
+    pub fn set_bandwidth_limit(
+        &self,
+        name: &str,
+        max_bytes_per_sec: u64,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "bandwidthctl",
+            self.get_vm()?,
+            "set",
+            name,
+            "--limit",
+            &bandwidth_limit_arg(max_bytes_per_sec),
+        ]))?;
+        Ok(())
+    }
+
+    /// Removes the bandwidth group `name`, which must not be assigned to NICs or disks, with `bandwidthctl remove`.
+    pub fn remove_bandwidth_group(&self, name: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "bandwidthctl",
+            self.get_vm()?,
+            "remove",
+            name,
+        ]))?;
+        Ok(())
+    }
+
+    /// Assigns the NIC in the slot `index` to the network bandwidth group `group`, or unassigns it if `None`.
+    pub fn set_nic_bandwidth_group(
+        &self,
+        index: usize,
+        group: Option<&str>,
+    ) -> VmResult<()> {
+        if !(1..=MAX_NICS).contains(&index) {
+            return vmerr!(ErrorKind::InvalidParameter(
+                "index must be the NIC slot number".to_string()
+            ));
+        }
+        self.exec(self.cmd().args([
+            "modifyvm",
+            self.get_vm()?,
+            &format!("--nicbandwidthgroup{}", index),
+            group.unwrap_or("none"),
+        ]))?;
+        Ok(())
+    }
+
+    /// Assigns the disk attached to `port` and `device` of the storage controller `controller` to the disk bandwidth group `group`, or unassigns it if `None`.
+    pub fn set_disk_bandwidth_group(
+        &self,
+        controller: &str,
+        port: u32,
+        device: u32,
+        group: Option<&str>,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "storageattach",
+            self.get_vm()?,
+            "--storagectl",
+            controller,
+            "--port",
+            &port.to_string(),
+            "--device",
+            &device.to_string(),
+            "--bandwidthgroup",
+            group.unwrap_or("none"),
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets host PCI devices attached to the VM.
+    pub fn list_pci_devices(&self) -> VmResult<Vec<PassthroughDevice>> {
+        let s = self.show_vm_info()?;
+        Ok(parse_attached_pci(&s))
+    }
+
+    /// Attaches a host PCI device to the VM with `--pciattach`.
+    ///
+    /// `host_location` is the host PCI address (e.g., `02:00.0`) and `guest_location` is the optional guest PCI address.
+    pub fn attach_pci_device(
+        &self,
+        host_location: &str,
+        guest_location: Option<&str>,
+    ) -> VmResult<()> {
+        let addr = match guest_location {
+            Some(x) => format!("{}@{}", host_location, x),
+            None => host_location.to_string(),
+        };
+        self.exec(self.cmd().args([
+            "modifyvm",
+            self.get_vm()?,
+            "--pciattach",
+            &addr,
+        ]))?;
+        Ok(())
+    }
+
+    /// Detaches a host PCI device from the VM with `--pcidetach`.
+    pub fn detach_pci_device(&self, host_location: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "modifyvm",
+            self.get_vm()?,
+            "--pcidetach",
+            host_location,
+        ]))?;
+        Ok(())
+    }
+
+    fn show_vm_info2(&self, id: &str) -> VmResult<String> {
+        self.exec(self.cmd().args(["showvminfo", id, "--machinereadable"]))
+    }
+
+    fn get_vm(&self) -> VmResult<&str> {
+        self.vm_name
+            .as_deref()
+            .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
+    }
+
+    /// Starts the VM with the frontend set by [`VBoxManage::start_type`].
+    pub fn start_vm(&self) -> VmResult<()> {
+        self.start_vm_with_type(self.start_type)
+    }
+
+    /// Starts the VM with `startvm --type`.
+    ///
+    /// `None` uses the default frontend of VBoxManage.
+    pub fn start_vm_with_type<T: Into<Option<StartType>>>(
+        &self,
+        start_type: T,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["startvm", self.get_vm()?]);
+        if let Some(x) = start_type.into() {
+            cmd.args(["--type", x.as_str()]);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    pub fn poweroff_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["controlvm", self.get_vm()?, "poweroff"]))?;
+        Ok(())
+    }
+
+    /// Sends ACPI shutdown signal.
+    ///
+    /// If the VM is running, this function returns Ok(()) regardless of whether the VM was shut down.
+    pub fn acpi_power_button_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "controlvm",
+            self.get_vm()?,
+            "acpipowerbutton",
+        ]))?;
+        Ok(())
+    }
+
+    pub fn reset_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["controlvm", self.get_vm()?, "reset"]))?;
+        Ok(())
+    }
+
+    pub fn pause_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["controlvm", self.get_vm()?, "pause"]))?;
+        Ok(())
+    }
+
+    pub fn resume_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["controlvm", self.get_vm()?, "resume"]))?;
+        Ok(())
+    }
+
+    pub fn save_state_vm(&self) -> VmResult<()> {
+        self.exec(self.cmd().args(["controlvm", self.get_vm()?, "savestate"]))?;
+        Ok(())
+    }
+
+    /// Takes a screenshot of the VM console and returns it as a PNG image.
+    ///
+    /// The image is saved to a temporary file in the host temp directory with [`VBoxManage::screenshot_png`] and read back.
+    pub fn screenshot(&self) -> VmResult<Vec<u8>> {
+        read_host_temp_file("screenshot.png", |p| self.screenshot_png(p))
+    }
+
+    /// Saves a screenshot of the VM console to `host_path` as a PNG image.
+    pub fn screenshot_png(&self, host_path: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "controlvm",
+            self.get_vm()?,
+            "screenshotpng",
+            host_path,
+        ]))?;
+        Ok(())
+    }
+
+    /// Starts collecting the metrics of the VM every `period` seconds, keeping the last `samples` samples.
+    ///
+    /// VirtualBox doesn't collect the metrics of a VM until this is called.
+    pub fn setup_metrics(&self, period: u32, samples: u32) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "metrics",
+            "setup",
+            "--period",
+            &period.to_string(),
+            "--samples",
+            &samples.to_string(),
+            self.get_vm()?,
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets the latest metrics of the VM with `metrics query`.
+    ///
+    /// The metrics are empty unless [`VBoxManage::setup_metrics`] has been called.
+    /// VirtualBox doesn't report the disk throughput of VMs.
+    pub fn get_metrics(&self) -> VmResult<VmMetrics> {
+        let _op = self.hooks.operation("get_metrics");
+        let s = self.exec(self.cmd().args([
+            "metrics",
+            "query",
+            self.get_vm()?,
+            "CPU/Load/User,CPU/Load/Kernel,RAM/Usage/Used,Net/Rate/Rx,Net/\
+             Rate/Tx",
+        ]))?;
+        Ok(parse_metrics(&s))
+    }
+
+    /// Gets the samples of `metrics` of the VM kept by VirtualBox with `metrics query`.
+    ///
+    /// `metrics` may contain `*`, e.g., `Guest/RAM/*`. Gets all the metrics if `metrics` is empty.
+    /// The samples are empty unless [`VBoxManage::setup_metrics`] has been called.
+    pub fn query_metrics(
+        &self,
+        metrics: &[&str],
+    ) -> VmResult<Vec<MetricSample>> {
+        let mut cmd = self.cmd();
+        cmd.args(["metrics", "query", self.get_vm()?]);
+        if !metrics.is_empty() {
+            cmd.arg(metrics.join(","));
+        }
+        Ok(parse_metric_samples(&self.exec(&mut cmd)?, false))
+    }
+
+    /// Collects `metrics` of the VM every `period` seconds with `metrics collect`, and calls `on_sample` with each sample until it returns `false`.
+    ///
+    /// `metrics` may contain `*`. Collects all the metrics if `metrics` is empty.
+    /// Each sample has one value, which is the average of the last `samples` values.
+    pub fn collect_metrics<F: FnMut(MetricSample) -> bool>(
+        &self,
+        period: u32,
+        samples: u32,
+        metrics: &[&str],
+        mut on_sample: F,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args([
+            "metrics",
+            "collect",
+            "--period",
+            &period.to_string(),
+            "--samples",
+            &samples.to_string(),
+            self.get_vm()?,
+        ]);
+        if !metrics.is_empty() {
+            cmd.arg(metrics.join(","));
+        }
+        let stopped = Cell::new(false);
+        self.exec_run(
+            &mut cmd,
+            |cmd| {
+                exec_cmd_lines(cmd, |l| {
+                    let cont =
+                        parse_metric_line(l, true).map_or(true, &mut on_sample);
+                    stopped.set(!cont);
+                    cont
+                })
+            },
+            |op, o| {
+                if stopped.get() {
+                    return Ok(());
+                }
+                self.check_output(op, o).map(|_| ())
+            },
+        )
+    }
+
+    fn exec_snapshot_list(&self) -> VmResult<String> {
+        self.exec(self.cmd().args([
+            "snapshot",
+            self.get_vm()?,
+            "list",
+            "--machinereadable",
+        ]))
+    }
+
+    /// Gets the snapshots and the UUID of the current snapshot.
+    ///
+    /// The creation times and whether the snapshots have saved states are read from the `.vbox` file if it is readable.
+    fn list_snapshots_with_current(
+        &self,
+    ) -> VmResult<(Vec<Snapshot>, Option<String>)> {
+        let (mut snapshots, current_id) =
+            parse_snapshot_list(&self.exec_snapshot_list()?)?;
+        if snapshots.is_empty() {
+            return Ok((snapshots, current_id));
+        }
+        if let Some(path) = Self::parse_vm(&self.show_vm_info()?).path {
+            if let Ok(xml) = std::fs::read_to_string(path) {
+                apply_snapshot_times(&mut snapshots, &xml);
+            }
+        }
+        Ok((snapshots, current_id))
+    }
+
+    /// Gets a list of snapshots.
+    pub fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
+        Ok(self.list_snapshots_with_current()?.0)
+    }
+
+    /// Gets the snapshot hierarchy and the current snapshot.
+    pub fn snapshot_tree(&self) -> VmResult<SnapshotTree> {
+        let _op = self.hooks.operation("snapshot_tree");
+        let (snapshots, current_id) = self.list_snapshots_with_current()?;
+        Ok(SnapshotTree::from_snapshots(
+            snapshots,
+            current_id.as_deref(),
+        ))
+    }
+
+    pub fn take_snapshot(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        is_live: bool,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        let mut cmd = self.cmd();
+        cmd.args(["snapshot", self.get_vm()?, "take", name]);
+        if let Some(x) = description {
+            cmd.args(["--description", x]);
+        }
+        if is_live {
+            cmd.arg("--live");
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Clones the VM with `clonevm` and registers the new VM.
+    ///
+    /// [`CloneType::Linked`] requires `options.snapshot`.
+    pub fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+        let _op = self.hooks.operation("clone_vm");
+        self.clone_vm_with_progress(options, CloneMode::Machine, |_| {})
+    }
+
+    /// Clones the VM like [`Self::clone_vm`] with `--mode`, calling `on_progress` with the percentage of the progress.
+    pub fn clone_vm_with_progress<F: FnMut(u32)>(
+        &self,
+        options: &CloneOptions,
+        mode: CloneMode,
+        on_progress: F,
+    ) -> VmResult<Vm> {
+        let mut cmd = self.cmd();
+        cmd.args(["clonevm", self.get_vm()?, "--name", &options.name]);
+        cmd.arg("--register");
+        if mode != CloneMode::Machine {
+            cmd.args(["--mode", mode.as_str()]);
+        }
+        if let Some(x) = &options.snapshot {
+            cmd.args(["--snapshot", x]);
+        }
+        if options.ty == CloneType::Linked {
+            if options.snapshot.is_none() {
+                return vmerr!(ErrorKind::InvalidParameter(
+                    "A linked clone requires a snapshot".to_string()
+                ));
+            }
+            cmd.args(["--options", "link"]);
+        }
+        if let Some(x) = &options.path {
+            cmd.args(["--basefolder", x]);
+        }
+        self.exec_progress(&mut cmd, on_progress)?;
+        self.list_vms()?
+            .into_iter()
+            .find(|x| x.name.as_deref() == Some(options.name.as_str()))
+            .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+
+    /// Creates and registers a VM with `createvm` and returns it.
+    ///
+    /// The memory size, the number of processors and the firmware are set with `modifyvm`.
+    pub fn create_vm(&self, spec: &VmSpec) -> VmResult<Vm> {
+        let _op = self.hooks.operation("create_vm");
+        let mut cmd = self.cmd();
+        cmd.args(["createvm", "--name", &spec.name, "--register"]);
+        if let Some(x) = &spec.path {
+            cmd.args(["--basefolder", x]);
+        }
+        if let Some(x) = &spec.guest_os {
+            cmd.args(["--ostype", x]);
+        }
+        let s = self.exec(&mut cmd)?;
+        let uuid = parse_created_vm_uuid(&s).ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse(s.clone()))
+        })?;
+        let mut args = vec![];
+        if let Some(x) = spec.memory_mb {
+            args.extend(["--memory".to_string(), x.to_string()]);
+        }
+        if let Some(x) = spec.cpu_count {
+            args.extend(["--cpus".to_string(), x.to_string()]);
+        }
+        if let Some(x) = spec.firmware {
+            let firmware = match x {
+                Firmware::Bios => "bios",
+                Firmware::Efi => "efi",
+            };
+            args.extend(["--firmware".to_string(), firmware.to_string()]);
+        }
+        if !args.is_empty() {
+            self.exec(self.cmd().args(["modifyvm", uuid]).args(args))?;
+        }
+        Ok(Self::parse_vm(&self.show_vm_info2(uuid)?))
+    }
+
+    /// Registers the VM whose `.vbox` file is at `path` with `registervm` and returns it.
+    pub fn register_vm(&self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("register_vm");
+        let xml = std::fs::read_to_string(path)?;
+        let uuid = parse_machine_uuid(&xml).ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(
+                "path must be a .vbox file".to_string(),
+            ))
+        })?;
+        self.exec(self.cmd().args(["registervm", path]))?;
+        Ok(Self::parse_vm(&self.show_vm_info2(uuid)?))
+    }
+
+    /// Unregisters the VM with `unregistervm`, and deletes its files and disks with `--delete` if `delete_files` is true.
+    ///
+    /// Returns [`ErrorKind::InvalidPowerState`] if the VM is running or locked by another session, and [`ErrorKind::VmNotFound`] if the VM is not registered.
+    pub fn unregister_vm(&self, delete_files: bool) -> VmResult<()> {
+        let _op = self.hooks.operation("unregister_vm");
+        let mut cmd = self.cmd();
+        cmd.args(["unregistervm", self.get_vm()?]);
+        if delete_files {
+            cmd.arg("--delete");
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Unregisters the VM and deletes its files and disks with `unregistervm --delete`.
+    pub fn delete_vm(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_vm");
+        self.unregister_vm(true)
+    }
+
+    /// Exports the VM to an appliance at `path` with `export`.
+    ///
+    /// VBoxManage chooses the format by the extension of `path`, so it must be `.ova` for [`ExportFormat::Ova`] and `.ovf` for [`ExportFormat::Ovf`].
+    /// VirtualBox doesn't support [`ExportFormat::Native`].
+    pub fn export_vm(&self, path: &str, format: ExportFormat) -> VmResult<()> {
+        let _op = self.hooks.operation("export_vm");
+        let ext = match format {
+            ExportFormat::Ova => "ova",
+            ExportFormat::Ovf => "ovf",
+            ExportFormat::Native => {
+                return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                    "export_vm",
+                    "Use ExportFormat::Ova or ExportFormat::Ovf instead"
+                ))
+            }
+        };
+        if !std::path::Path::new(path)
+            .extension()
+            .map_or(false, |x| x.eq_ignore_ascii_case(ext))
+        {
+            return vmerr!(ErrorKind::InvalidParameter(format!(
+                "path must end with .{}",
+                ext
+            )));
+        }
+        self.exec(self.cmd().args([
+            "export",
+            self.get_vm()?,
+            "--output",
+            path,
+        ]))?;
+        Ok(())
+    }
+
+    /// Exports the VM to an appliance at `path` with `export`.
+    ///
+    /// The format is chosen from the extension of `path`, `.ova` or `.ovf`.
+    pub fn export_appliance(
+        &self,
+        vm: &str,
+        path: &str,
+        options: &ExportOptions,
+    ) -> VmResult<()> {
+        self.exec(
+            self.cmd()
+                .args(["export", vm, "--output", path])
+                .args(options.to_args()),
+        )?;
+        Ok(())
+    }
+
+    /// Lists the virtual systems of the appliance at `path` with `import --dry-run`.
+    ///
+    /// The indices of the virtual systems and their units are used in [`ImportOptions`].
+    pub fn inspect_appliance(
+        &self,
+        path: &str,
+    ) -> VmResult<Vec<VirtualSystem>> {
+        let s = self.exec(self.cmd().args(["import", path, "--dry-run"]))?;
+        Ok(parse_virtual_systems(&s))
+    }
+
+    /// Imports the appliance at `path` with `import` and returns the imported VMs.
+    pub fn import_appliance(
+        &self,
+        path: &str,
+        options: &ImportOptions,
+    ) -> VmResult<Vec<Vm>> {
+        let s = self
+            .exec(self.cmd().args(["import", path]).args(options.to_args()))?;
+        let systems = parse_virtual_systems(&s);
+        let vms = self.list_vms()?;
+        systems
+            .iter()
+            .map(|x| {
+                vms.iter()
+                    .find(|vm| vm.name.is_some() && vm.name == x.name)
+                    .cloned()
+                    .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+            })
+            .collect()
+    }
+
+    /// Imports the appliance at `path` with `import` and returns the imported VM.
+    pub fn import_vm(&self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("import_vm");
+        let s = self.exec(self.cmd().args(["import", path]))?;
+        let name = parse_imported_vm_name(&s).ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse(s.clone()))
+        })?;
+        self.list_vms()?
+            .into_iter()
+            .find(|x| x.name.as_deref() == Some(name))
+            .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+
+    pub fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
+        self.exec(self.cmd().args([
+            "snapshot",
+            self.get_vm()?,
+            "delete",
+            name,
+        ]))?;
+        Ok(())
+    }
+
+    /// Renames the snapshot `name` and changes its description with `snapshot edit`.
+    ///
+    /// `name` can be a snapshot name or a UUID. `None` arguments are left unchanged.
+    pub fn edit_snapshot(
+        &self,
+        name: &str,
+        new_name: Option<&str>,
+        new_description: Option<&str>,
+    ) -> VmResult<()> {
+        if new_name.is_none() && new_description.is_none() {
+            return Ok(());
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["snapshot", self.get_vm()?, "edit", name]);
+        if let Some(x) = new_name {
+            cmd.args(["--name", x]);
+        }
+        if let Some(x) = new_description {
+            cmd.args(["--description", x]);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the snapshot `name`, which can be a snapshot name or a UUID, with its description, parent and creation time.
+    ///
+    /// Returns [`ErrorKind::SnapshotNotFound`] if no snapshot matches, and [`ErrorKind::InvalidParameter`] if `name` matches several snapshots.
+    pub fn snapshot_info(&self, name: &str) -> VmResult<Snapshot> {
+        find_snapshot(self.list_snapshots()?, name)
+    }
+
+    pub fn restore_snapshot(&self, name: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "snapshot",
+            self.get_vm()?,
+            "restore",
+            name,
+        ]))?;
+        Ok(())
+    }
+
+    pub fn restore_current_snapshot(&self) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "snapshot",
+            self.get_vm()?,
+            "restorecurrent",
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets the disk space used by each snapshot.
+    ///
+    /// The differencing disks of snapshots are found with `VBoxManage list hdds` and measured by their file sizes in the snapshot folder.
+    pub fn snapshot_disk_usage(&self) -> VmResult<Vec<SnapshotDiskUsage>> {
+        let _op = self.hooks.operation("snapshot_disk_usage");
+        let snapshots = self.list_snapshots()?;
+        let s = self.exec(self.cmd().args(["list", "hdds"]))?;
+        let hdds = parse_list_blocks(&s);
+        snapshots
+            .into_iter()
+            .map(|snapshot| {
+                let disks = snapshot
+                    .id
+                    .as_deref()
+                    .map(|x| snapshot_disks(&hdds, x))
+                    .unwrap_or_default();
+                let mut size = 0;
+                for x in &disks {
+                    size += std::fs::metadata(x)?.len();
+                }
+                Ok(SnapshotDiskUsage {
+                    snapshot,
+                    disks,
+                    size,
+                })
+            })
+            .collect()
+    }
+
+    /// Sets the type of a disk image with `modifymedium --type`.
+    ///
+    /// `medium` is the UUID or the path of the disk image. The type of a disk attached to a VM cannot be changed.
+    pub fn set_medium_type(
+        &self,
+        medium: &str,
+        ty: MediumType,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "modifymedium",
+            "disk",
+            medium,
+            "--type",
+            ty.as_str(),
+        ]))?;
+        Ok(())
+    }
+
+    /// Sets whether the changes to an immutable disk image are discarded on VM start with `modifymedium --autoreset`.
+    pub fn set_medium_autoreset(
+        &self,
+        medium: &str,
+        autoreset: bool,
+    ) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "modifymedium",
+            "disk",
+            medium,
+            "--autoreset",
+            if autoreset { "on" } else { "off" },
+        ]))?;
+        Ok(())
+    }
+
+    /// Gets the paths to the base disk images attached to the VM.
+    pub fn list_vm_base_disks(&self) -> VmResult<Vec<String>> {
+        let vm = Self::parse_vm(&self.show_vm_info()?);
+        let uuid = vm
+            .id
+            .as_deref()
+            .unwrap_or_default()
+            .trim_matches(['{', '}']);
+        let s = self.exec(self.cmd().args(["list", "hdds"]))?;
+        Ok(vm_base_disks(&parse_list_blocks(&s), uuid))
+    }
+
+    /// Sets autoreset of all the base disk images attached to the VM.
+    ///
+    /// Autoreset only takes effect on immutable disk images.
+    pub fn set_vm_disks_autoreset(&self, autoreset: bool) -> VmResult<()> {
+        for x in self.list_vm_base_disks()? {
+            self.set_medium_autoreset(&x, autoreset)?;
+        }
+        Ok(())
+    }
+
+    /// Encrypts the powered-off VM with `encryptvm setencryption`, which needs VirtualBox 7.0 or later.
+    ///
+    /// `password_id` identifies the password, e.g., in [`VBoxManage::start_vm_with_password`].
+    /// The password is passed through stdin.
+    pub fn encrypt_vm(
+        &self,
+        cipher: EncryptionCipher,
+        password_id: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        self.exec_password(
+            self.cmd().args([
+                "encryptvm",
+                self.get_vm()?,
+                "setencryption",
+                "--cipher",
+                cipher.as_str(),
+                "--new-password",
+                "stdin",
+                "--new-password-id",
+                password_id,
+            ]),
+            password,
+        )?;
+        Ok(())
+    }
+
+    /// Checks the password of the encrypted VM with `encryptvm checkpassword`.
+    ///
+    /// Returns [`ErrorKind::AuthenticationFailed`] if the password is incorrect.
+    pub fn check_vm_password(&self, password: &str) -> VmResult<()> {
+        self.exec_password(
+            self.cmd().args([
+                "encryptvm",
+                self.get_vm()?,
+                "checkpassword",
+                "stdin",
+            ]),
+            password,
+        )?;
+        Ok(())
+    }
+
+    /// Unlocks the encrypted VM for the running session with `encryptvm addpassword`.
+    pub fn add_vm_password(
+        &self,
+        password_id: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        self.exec_password(
+            self.cmd().args([
+                "encryptvm",
+                self.get_vm()?,
+                "addpassword",
+                "--password",
+                "stdin",
+                "--password-id",
+                password_id,
+            ]),
+            password,
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the password `password_id` of the encrypted VM added with [`VBoxManage::add_vm_password`].
+    pub fn remove_vm_password(&self, password_id: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "encryptvm",
+            self.get_vm()?,
+            "removepassword",
+            password_id,
+        ]))?;
+        Ok(())
+    }
+
+    /// Starts the encrypted VM with `startvm --password`.
+    ///
+    /// The password is passed through stdin, so the VM starts without prompting.
+    /// The VM starts with the frontend set by [`VBoxManage::start_type`].
+    pub fn start_vm_with_password(
+        &self,
+        password_id: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args([
+            "startvm",
+            self.get_vm()?,
+            "--password",
+            "stdin",
+            "--password-id",
+            password_id,
+        ]);
+        if let Some(x) = self.start_type {
+            cmd.args(["--type", x.as_str()]);
+        }
+        self.exec_password(&mut cmd, password)?;
+        Ok(())
+    }
+
+    /// Passes the password `password_id` of the encrypted disks to the running VM with `controlvm addencpassword`.
+    ///
+    /// A VM whose disks are encrypted is paused at startup until the passwords are given.
+    /// The password is forgotten when the VM is suspended if `remove_on_suspend`.
+    pub fn add_disk_password(
+        &self,
+        password_id: &str,
+        password: &str,
+        remove_on_suspend: bool,
+    ) -> VmResult<()> {
+        self.exec_password(
+            self.cmd().args([
+                "controlvm",
+                self.get_vm()?,
+                "addencpassword",
+                password_id,
+                "stdin",
+                "--removeonsuspend",
+                if remove_on_suspend { "yes" } else { "no" },
+            ]),
+            password,
+        )?;
+        Ok(())
+    }
+
+    /// Removes the password `password_id` of the encrypted disks from the running VM with `controlvm removeencpassword`.
+    pub fn remove_disk_password(&self, password_id: &str) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "controlvm",
+            self.get_vm()?,
+            "removeencpassword",
+            password_id,
+        ]))?;
+        Ok(())
+    }
+
+    /// Removes all the passwords of the encrypted disks from the running VM with `controlvm removeallencpasswords`.
+    pub fn remove_all_disk_passwords(&self) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "controlvm",
+            self.get_vm()?,
+            "removeallencpasswords",
+        ]))?;
+        Ok(())
+    }
+
+    /// Encrypts the disk image `medium`, which is a UUID or a path, with `encryptmedium`.
+    ///
+    /// The VMs using the image must be powered off.
+    pub fn encrypt_medium(
+        &self,
+        medium: &str,
+        cipher: EncryptionCipher,
+        password_id: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        self.exec_password(
+            self.cmd().args([
+                "encryptmedium",
+                medium,
+                "--newpassword",
+                "stdin",
+                "--cipher",
+                cipher.as_medium_str(),
+                "--newpasswordid",
+                password_id,
+            ]),
+            password,
+        )?;
+        Ok(())
+    }
+
+    /// Checks the password of the encrypted disk image `medium` with `checkmediumpwd`.
+    ///
+    /// Returns [`ErrorKind::AuthenticationFailed`] if the password is incorrect.
+    pub fn check_medium_password(
+        &self,
+        medium: &str,
+        password: &str,
+    ) -> VmResult<()> {
+        self.exec_password(
+            self.cmd().args(["checkmediumpwd", medium, "stdin"]),
+            password,
+        )?;
+        Ok(())
+    }
+
+    pub fn run(&self, guest_args: &[&str]) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "run"]);
+        cmd.args(self.build_auth());
+        if let Some(x) = self.guest_timeout {
+            cmd.arg(format!("--timeout={}", x.as_millis()));
+        }
+        cmd.args(guest_args);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Runs a program in the guest with `guestcontrol run` and returns its output.
+    ///
+    /// The exit code of VBoxManage is the exit code of the guest program.
+    pub fn run_output(&self, guest_args: &[&str]) -> VmResult<GuestOutput> {
+        self.run_with(guest_args, &GuestExecOptions::default())
+    }
+
+    /// Runs a program in the guest with `guestcontrol run` and `opts`, and returns its output.
+    ///
+    /// `opts.timeout` overrides [`Self::guest_timeout`]. `--cwd` requires VirtualBox 7.0 or later.
+    /// Returns [`ErrorKind::UnsupportedCommand`] if `elevated`, `interactive` or `active_window` is set.
+    pub fn run_with(
+        &self,
+        guest_args: &[&str],
+        opts: &GuestExecOptions,
+    ) -> VmResult<GuestOutput> {
+        if opts.elevated || opts.interactive || opts.active_window {
+            return vmerr!(ErrorKind::unsupported::<Self>("run_with"));
+        }
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "run"]);
+        cmd.args(self.build_auth());
+        if let Some(x) = opts.timeout.or(self.guest_timeout) {
+            cmd.arg(format!("--timeout={}", x.as_millis()));
+        }
+        for (k, v) in &opts.env {
+            cmd.arg(format!("--putenv={}={}", k, v));
+        }
+        if let Some(x) = &opts.cwd {
+            cmd.arg(format!("--cwd={}", x));
+        }
+        cmd.args(guest_args);
+        self.exec_with(&mut cmd, |_, o| {
+            // Errors of VBoxManage itself are written to stderr too.
+            Self::check(o.stderr.clone())?;
+            Ok(GuestOutput {
+                stdout: o.stdout,
+                stderr: o.stderr,
+                exit_code: o.status.code(),
+            })
+        })
+    }
+
+    /// Lists the guest processes started by VBoxManage with `guestcontrol list processes`.
+    ///
+    /// Processes not started through the guest control service are not listed.
+    pub fn list_guest_processes(&self) -> VmResult<Vec<ProcInfo>> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "list", "processes"]);
+        cmd.args(self.build_auth());
+        let s = self.exec(&mut cmd)?;
+        Ok(parse_guest_processes(&s))
+    }
+
+    /// Terminates a guest process with `guestcontrol closeprocess`.
+    ///
+    /// The process is searched for in all guest sessions.
+    pub fn terminate_guest_process(&self, pid: u32) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "closeprocess"]);
+        cmd.args(["--session-name", "*"]);
+        cmd.arg(pid.to_string());
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the value of a guest property with `guestproperty get`.
+    ///
+    /// Returns `None` if the property is not set.
+    pub fn get_guest_property(&self, name: &str) -> VmResult<Option<String>> {
+        let s = self.exec(self.cmd().args([
+            "guestproperty",
+            "get",
+            self.get_vm()?,
+            name,
+        ]))?;
+        Ok(s.trim().strip_prefix("Value: ").map(|x| x.to_string()))
+    }
+
+    /// Gets a guest property with its timestamp and flags with `guestproperty get --verbose`.
+    ///
+    /// Returns `None` if the property is not set.
+    pub fn get_guest_property_entry(
+        &self,
+        name: &str,
+    ) -> VmResult<Option<GuestProperty>> {
+        let s = self.exec(self.cmd().args([
+            "guestproperty",
+            "get",
+            self.get_vm()?,
+            name,
+            "--verbose",
+        ]))?;
+        Ok(parse_guest_property_verbose(name, &s))
+    }
+
+    /// Gets all the guest properties with `guestproperty enumerate`.
+    pub fn list_guest_properties(&self) -> VmResult<Vec<(String, String)>> {
+        Ok(self
+            .list_guest_property_entries()?
+            .into_iter()
+            .map(|x| (x.name, x.value))
+            .collect())
+    }
+
+    /// Gets all the guest properties with their timestamps and flags with `guestproperty enumerate`.
+    pub fn list_guest_property_entries(&self) -> VmResult<Vec<GuestProperty>> {
+        let s = self.exec(self.cmd().args([
+            "guestproperty",
+            "enumerate",
+            self.get_vm()?,
+        ]))?;
+        Ok(parse_guest_property_entries(&s))
+    }
+
+    /// Waits for a guest property whose name matches `patterns` to change with `guestproperty wait`, and returns the changed property.
+    ///
+    /// `patterns` are separated by `|` and may contain `*` and `?`, e.g., `/VirtualBox/GuestInfo/Net/*`.
+    /// Returns `None` if `timeout` elapses. The returned property has no timestamp.
+    pub fn wait_guest_property<D: Into<Option<Duration>>>(
+        &self,
+        patterns: &str,
+        timeout: D,
+    ) -> VmResult<Option<GuestProperty>> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestproperty", "wait", self.get_vm()?, patterns]);
+        if let Some(x) = timeout.into() {
+            cmd.args(["--timeout".to_string(), x.as_millis().to_string()]);
+        }
+        let s = self.exec(&mut cmd)?;
+        Ok(parse_guest_property_entries(&s).into_iter().next())
+    }
+
+    /// Gets the users logged in to the guest, reported by the Guest Additions in `/VirtualBox/GuestInfo/OS/LoggedInUsersList`.
+    pub fn get_logged_in_users(&self) -> VmResult<Vec<String>> {
+        Ok(self
+            .get_guest_property("/VirtualBox/GuestInfo/OS/LoggedInUsersList")?
+            .map(|x| {
+                x.split(',')
+                    .map(|x| x.trim())
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Gets the IP addresses of the guest reported by the Guest Additions in `/VirtualBox/GuestInfo/Net/<n>/`.
+    ///
+    /// The interfaces are the MAC addresses of the NICs.
+    pub fn get_guest_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        Ok(parse_guest_ip_addresses(&self.list_guest_properties()?))
+    }
+
+    /// Checks whether users are logged in to the guest and all of them are idle.
+    ///
+    /// It is determined by `/VirtualBox/GuestInfo/User/<user>/UsageState` reported by the Guest Additions.
+    /// Returns `false` if no users are logged in.
+    pub fn is_guest_idle(&self) -> VmResult<bool> {
+        Ok(is_idle(&self.list_guest_properties()?))
+    }
+
+    /// Sets a guest property with `guestproperty set`.
+    ///
+    /// Deletes the property if `value` is `None`.
+    pub fn set_guest_property(
+        &self,
+        name: &str,
+        value: Option<&str>,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestproperty", "set", self.get_vm()?, name]);
+        cmd.args(value);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Sets a guest property with `flags` with `guestproperty set --flags`.
+    pub fn set_guest_property_with_flags(
+        &self,
+        name: &str,
+        value: &str,
+        flags: &[GuestPropertyFlag],
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestproperty", "set", self.get_vm()?, name, value]);
+        if !flags.is_empty() {
+            let flags: Vec<&str> = flags.iter().map(|x| x.as_str()).collect();
+            cmd.args(["--flags".to_string(), flags.join(",")]);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the offset of the guest clock from the host clock in milliseconds, set by `--biossystemtimeoffset`.
+    pub fn get_bios_time_offset(&self) -> VmResult<i64> {
+        let s = self.show_vm_info()?;
+        let hm = Self::parse_info(&s, None);
+        match hm.get("biossystemtimeoffset") {
+            Some(x) => x.parse().map_err(|_| {
+                VmError::from(ErrorKind::UnexpectedResponse(x.to_string()))
+            }),
+            None => Ok(0),
+        }
+    }
+
+    /// Sets the offset of the guest clock from the host clock in milliseconds with `modifyvm --biossystemtimeoffset`.
+    ///
+    /// The offset takes effect at the next boot.
+    pub fn set_bios_time_offset(&self, offset_ms: i64) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "modifyvm",
+            self.get_vm()?,
+            "--biossystemtimeoffset",
+            &offset_ms.to_string(),
+        ]))?;
+        Ok(())
+    }
+
+    /// Enables or disables the synchronization of the guest clock with the host clock by the Guest Additions.
+    ///
+    /// Sets the `VBoxInternal/Devices/VMMDev/0/Config/GetHostTimeDisabled` extra data, which takes effect at the next boot.
+    pub fn set_host_time_sync(&self, enabled: bool) -> VmResult<()> {
+        self.exec(self.cmd().args([
+            "setextradata",
+            self.get_vm()?,
+            "VBoxInternal/Devices/VMMDev/0/Config/GetHostTimeDisabled",
+            if enabled { "0" } else { "1" },
+        ]))?;
+        Ok(())
+    }
+
+    /// Makes the guest clock start at `time` at the next boot.
+    ///
+    /// Sets the BIOS time offset and disables the host time synchronization so that the Guest Additions don't reset the clock.
+    pub fn set_guest_clock(&self, time: SystemTime) -> VmResult<()> {
+        self.set_bios_time_offset(time_offset_ms(time, SystemTime::now()))?;
+        self.set_host_time_sync(false)
+    }
+
+    /// Copies files from guest to host.
+    pub fn copy_from(
+        &self,
+        follow: bool,
+        recursive: bool,
+        from_guest_paths: &[&str],
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "copyfrom"]);
+        cmd.args(self.build_auth());
+        if follow {
+            cmd.arg("--follow");
+        }
+        if recursive {
+            cmd.arg("-R");
+        }
+
+        cmd.args(from_guest_paths);
+        cmd.arg(to_host_path);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Copies files from host to guest.
+    pub fn copy_to(
+        &self,
+        follow: bool,
+        recursive: bool,
+        from_host_paths: &[&str],
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "copyto"]);
+        cmd.args(self.build_auth());
+        if follow {
+            cmd.arg("--follow");
+        }
+        if recursive {
+            cmd.arg("-R");
+        }
+        cmd.args(from_host_paths);
+        cmd.arg(to_guest_path);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Remove files from guest.
+    pub fn remove_file(&self, guest_paths: &[&str]) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "rm"]);
+        cmd.args(self.build_auth());
+        cmd.arg("-f");
+        cmd.args(guest_paths);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Creates a temporary directory in the guest's temp directory with `mktemp --directory`.
+    ///
+    /// `template` must contain at least three consecutive `X`.
+    /// Returns the path to the directory.
+    pub fn make_temp_dir(&self, template: &str) -> VmResult<String> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "mktemp"]);
+        cmd.args(self.build_auth());
+        cmd.args(["--directory", template]);
+        let s = self.exec(&mut cmd)?;
+        match s.trim().strip_prefix("Directory name: ") {
+            Some(x) => Ok(x.to_string()),
+            None => vmerr!(ErrorKind::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Creates a temporary file in the guest's temp directory with `mktemp`.
+    ///
+    /// `template` must contain at least three consecutive `X`.
+    /// Returns the path to the file.
+    pub fn make_temp_file(&self, template: &str) -> VmResult<String> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "mktemp"]);
+        cmd.args(self.build_auth());
+        cmd.arg(template);
+        let s = self.exec(&mut cmd)?;
+        match s.trim().strip_prefix("File name: ") {
+            Some(x) => Ok(x.to_string()),
+            None => vmerr!(ErrorKind::UnexpectedResponse(s)),
+        }
+    }
+
+    /// Creates directories in the guest with `guestcontrol mkdir`.
+    pub fn make_dir(
+        &self,
+        parents: bool,
+        guest_paths: &[&str],
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "mkdir"]);
+        cmd.args(self.build_auth());
+        if parents {
+            cmd.arg("--parents");
+        }
+        cmd.args(guest_paths);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Moves files and directories in the guest with `guestcontrol mv`.
+    pub fn move_files(
+        &self,
+        from_guest_paths: &[&str],
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "mv"]);
+        cmd.args(self.build_auth());
+        cmd.args(from_guest_paths);
+        cmd.arg(to_guest_path);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the information of a file in the guest with `guestcontrol stat`.
+    ///
+    /// Returns [`ErrorKind::GuestFileNotFound`] if the file doesn't exist.
+    pub fn stat(&self, guest_path: &str) -> VmResult<GuestFileInfo> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "stat"]);
+        cmd.args(self.build_auth());
+        cmd.arg(guest_path);
+        let s = self.exec(&mut cmd)?;
+        parse_stat(&s).ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse(s.to_string()))
+        })
+    }
+
+    /// Removes directories from guest.
+    pub fn remove_dir(
+        &self,
+        recursive: bool,
+        guest_paths: &[&str],
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["guestcontrol", self.get_vm()?, "rmdir"]);
+        cmd.args(self.build_auth());
+        if recursive {
+            cmd.arg("--recursive");
+        }
+        cmd.args(guest_paths);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Sends keyboard scancodes to the guest.
+    pub fn keyboard_put_scancode<T: Iterator<Item = u8>>(
+        &self,
+        v: T,
+    ) -> VmResult<()> {
+        use std::fmt::Write;
+        let mut cmd = self.cmd();
+        cmd.args(["controlvm", self.get_vm()?, "keyboardputscancode"]);
+        cmd.args(self.build_auth());
+
+        cmd.args(
+            v.into_iter()
+                .map(|x| {
+                    let mut ret = String::new();
+                    ret.write_fmt(format_args!("{:x}", x)).unwrap();
+                    ret
+                })
+                .collect::<Vec<String>>(),
+        );
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    pub fn keyboard_put_string(&self, v: &[&str]) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["controlvm", self.get_vm()?, "keyboardputstring"]);
+        cmd.args(self.build_auth());
+        cmd.args(v);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    pub fn install_ext_pack(
+        &self,
+        replace: bool,
+        accept_license: bool,
+        ext_pack_path: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["extpack", "install"]);
+        if replace {
+            cmd.arg("--replace");
+        }
+        if accept_license {
+            cmd.arg("--accept-license=sha256");
+        }
+        cmd.arg(ext_pack_path);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    pub fn uninstall_ext_pack(
+        &self,
+        force: bool,
+        ext_pack_path: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["extpack", "uninstall"]);
+        if force {
+            cmd.arg("--force");
+        }
+        cmd.arg(ext_pack_path);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    pub fn cleanup_ext_pack(&self) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["extpack", "cleanup"]);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets a list of cloud profiles.
+    pub fn list_cloud_profiles(&self) -> VmResult<Vec<CloudProfile>> {
+        let s = self.exec(self.cmd().args(["list", "cloudprofiles"]))?;
+        Ok(parse_cloud_profiles(&s))
+    }
+
+    /// Adds a cloud profile or updates an existing one.
+    ///
+    /// `properties` are passed as `--key value`, e.g., `("clouduser", "ocid1.user...")` or `("region", "us-ashburn-1")`.
+    pub fn set_cloud_profile(
+        &self,
+        provider: &str,
+        profile: &str,
+        properties: &[(&str, &str)],
+        update: bool,
+    ) -> VmResult<()> {
+        let mut cmd = self.cloud_profile_cmd(provider, profile);
+        cmd.arg(if update { "update" } else { "add" });
+        for (key, value) in properties {
+            cmd.args([format!("--{}", key), value.to_string()]);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Deletes a cloud profile.
+    pub fn delete_cloud_profile(
+        &self,
+        provider: &str,
+        profile: &str,
+    ) -> VmResult<()> {
+        self.exec(self.cloud_profile_cmd(provider, profile).arg("delete"))?;
+        Ok(())
+    }
+
+    /// Shows the properties of a cloud profile.
+    pub fn show_cloud_profile(
+        &self,
+        provider: &str,
+        profile: &str,
+    ) -> VmResult<String> {
+        self.exec(self.cloud_profile_cmd(provider, profile).arg("show"))
+    }
+
+    fn cloud_profile_cmd(&self, provider: &str, profile: &str) -> Command {
+        let mut cmd = self.cmd();
+        cmd.args([
+            "cloudprofile",
+            &format!("--provider={}", provider),
+            &format!("--profile={}", profile),
+        ]);
+        cmd
+    }
+
+    /// Executes `VBoxManage cloud --provider=<provider> --profile=<profile> <args>` and returns its output.
+    pub fn cloud(
+        &self,
+        provider: &str,
+        profile: &str,
+        args: &[&str],
+    ) -> VmResult<String> {
+        let mut cmd = self.cmd();
+        cmd.args([
+            "cloud",
+            &format!("--provider={}", provider),
+            &format!("--profile={}", profile),
+        ]);
+        cmd.args(args);
+        self.exec(&mut cmd)
+    }
+
+    /// Gets a list of cloud instances, optionally filtered by `state`, e.g., `running`.
+    pub fn list_cloud_instances(
+        &self,
+        provider: &str,
+        profile: &str,
+        state: Option<&str>,
+    ) -> VmResult<String> {
+        let state = state.map(|x| format!("--state={}", x));
+        let mut args = vec!["list", "instances"];
+        args.extend(state.as_deref());
+        self.cloud(provider, profile, &args)
+    }
+
+    /// Shows the information of a cloud instance.
+    pub fn cloud_instance_info(
+        &self,
+        provider: &str,
+        profile: &str,
+        id: &str,
+    ) -> VmResult<String> {
+        self.cloud_instance(provider, profile, "info", id)
+    }
+
+    /// Starts a cloud instance.
+    pub fn start_cloud_instance(
+        &self,
+        provider: &str,
+        profile: &str,
+        id: &str,
+    ) -> VmResult<()> {
+        self.cloud_instance(provider, profile, "start", id)?;
+        Ok(())
+    }
+
+    /// Pauses a cloud instance.
+    pub fn pause_cloud_instance(
+        &self,
+        provider: &str,
+        profile: &str,
+        id: &str,
+    ) -> VmResult<()> {
+        self.cloud_instance(provider, profile, "pause", id)?;
+        Ok(())
+    }
+
+    /// Terminates a cloud instance.
+    pub fn terminate_cloud_instance(
+        &self,
+        provider: &str,
+        profile: &str,
+        id: &str,
+    ) -> VmResult<()> {
+        self.cloud_instance(provider, profile, "terminate", id)?;
+        Ok(())
+    }
+
+    fn cloud_instance(
+        &self,
+        provider: &str,
+        profile: &str,
+        command: &str,
+        id: &str,
+    ) -> VmResult<String> {
+        self.cloud(
+            provider,
+            profile,
+            &["instance", command, &format!("--id={}", id)],
+        )
+    }
+
+    /// Exports the VM to a cloud using `VBoxManage export --cloud`.
+    ///
+    /// `options` are passed as `--key value`, e.g., `("cloudshape", "VM.Standard2.1")`.
+    pub fn export_to_cloud(
+        &self,
+        provider: &str,
+        profile: &str,
+        options: &[(&str, &str)],
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args([
+            "export",
+            self.get_vm()?,
+            &format!("--output={}://", provider),
+            "--cloud=0",
+            &format!("--cloudprofile={}", profile),
+        ]);
+        for (key, value) in options {
+            cmd.args([format!("--{}", key), value.to_string()]);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+}
+
+impl VmCmd for VBoxManage {
+    fn list_vms(&self) -> VmResult<Vec<Vm>> { self.list_vms() }
+
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
+        // VBoxManage can be passed an ID.
+        self.set_vm_by_name(id)
+    }
+
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
+        // Checks if the corresponding VM exists.
+        let s = self.show_vm_info2(name)?;
+        self.vm_name = Some(name.to_string());
+        Ok(Self::parse_vm(&s))
+    }
+
+    /// `path` is the absolute path of a `vbox` file.
+    fn set_vm_by_path(&mut self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        use ErrorKind::UnexpectedResponse;
+        // `\` in CfgFile of show_vm_info is escaped, So `path` also needs to be escaped.
+        let path = path.replace('\\', "\\\\");
+        let vms = self.list_vms()?;
+        // VBoxManage's machine readable format collapses if the snapshot detail contains `"` or `\`.
+        // To avoid this, call show_vm_info multiple times (it takes time).
+        for vm in vms {
+            let id = vm.id.as_ref().unwrap();
+            let s = self.show_vm_info2(id)?;
+            let s2 = s.clone();
+            let cfg_path = s
+                .lines()
+                .nth(4)
+                .ok_or_else(|| VmError::from(UnexpectedResponse(s2.clone())))?
+                .strip_prefix("CfgFile=\"")
+                .ok_or_else(|| VmError::from(UnexpectedResponse(s2.clone())))?;
+            let cfg_path = &cfg_path[..cfg_path.len() - 1];
+            if path == cfg_path {
+                self.vm_name = Some(id.to_string());
+                return Ok(Self::parse_vm(&s2));
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
+}
+
+impl PowerCmd for VBoxManage {
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            self.check_power_state(&[
+                VmPowerState::Stopped,
+                VmPowerState::Suspended,
+            ])?;
+            self.start_vm()
+        })
+    }
+
+    /// Sends ACPI shutdown signals.
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[VmPowerState::Running])?;
+            let timeout = timeout.into();
+            let s = Instant::now();
+            let mut ok_flag = false;
+            loop {
+                match self.acpi_power_button_vm() {
+                    Ok(()) => {
+                        ok_flag = true;
+                    }
+                    Err(x) => {
+                        if let Some(is_running) = x.is_invalid_state_running() {
+                            if !is_running {
+                                // !InvalidVmState(Running)
+                                return if ok_flag {
+                                    self.wait_for_stop(
+                                        timeout.map(|x| {
+                                            x.saturating_sub(s.elapsed())
+                                        }),
+                                    )
+                                } else {
+                                    Err(x)
+                                };
+                            }
+                        } else {
+                            return Err(x);
+                        }
+                    }
+                }
+
+                if let Some(timeout) = timeout {
+                    if s.elapsed() >= timeout {
+                        return vmerr!(ErrorKind::Timeout);
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        })
+    }
+
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Paused,
+            ])?;
+            let mut ok_flag = false;
+            loop {
+                match self.poweroff_vm() {
+                    Ok(()) => {
+                        ok_flag = true;
+                    }
+                    Err(x) => {
+                        match x.get_invalid_state() {
+                            Some(VmPowerState::Stopped) => { /* Does nothing */
+                            }
+                            Some(VmPowerState::NotRunning) => {
+                                return if ok_flag { Ok(()) } else { Err(x) }
+                            }
+                            _ => return Err(x),
+                        }
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        })
+    }
+
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        self.check_power_state(&[VmPowerState::Running, VmPowerState::Paused])?;
+        let mut ok_flag = false;
+        loop {
+            let status = self.save_state_vm();
+            match status {
+                Ok(_) => {
+                    ok_flag = true;
+                }
+                Err(x) => {
+                    match x.get_invalid_state() {
+                        Some(VmPowerState::Suspended) => { /* Does nothing */ }
+                        Some(VmPowerState::NotRunning) => {
+                            return if ok_flag { Ok(()) } else { Err(x) }
+                        }
+                        _ => return Err(x),
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        self.check_power_state(&[VmPowerState::Suspended])?;
+        self.start_vm()
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()?.is_running())
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.stop(timeout)?;
+        loop {
+            match self.start() {
+                Ok(()) => return Ok(()),
+                Err(x) => {
+                    if x.is_invalid_state_running() == Some(true) { /* Does nothing */
+                    } else {
+                        return Err(x);
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.reset_vm()
+    }
+
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.check_power_state(&[VmPowerState::Running])?;
+        Self::pause_vm(self)
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.check_power_state(&[VmPowerState::Paused])?;
+        self.resume_vm()
+    }
+}
+
+impl GuestCmd for VBoxManage {
+    fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
+        let _op = self.hooks.operation("exec_cmd");
+        self.run(guest_args)
+    }
+
+    fn exec_cmd_output(&self, guest_args: &[&str]) -> VmResult<GuestOutput> {
+        let _op = self.hooks.operation("exec_cmd_output");
+        self.run_output(guest_args)
+    }
+
+    fn exec_cmd_with(
+        &self,
+        guest_args: &[&str],
+        opts: &GuestExecOptions,
+    ) -> VmResult<GuestOutput> {
+        let _op = self.hooks.operation("exec_cmd_with");
+        self.run_with(guest_args, opts)
+    }
+
+    fn copy_from_guest_to_host(
+        &self,
+        from_guest_path: &str,
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_guest_to_host");
+        self.copy_from(false, true, &[from_guest_path], to_host_path)
+    }
+
+    fn copy_from_host_to_guest(
+        &self,
+        from_host_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_host_to_guest");
+        self.copy_to(false, true, &[from_host_path], to_guest_path)
+    }
+}
+
+impl GuestTempDirCmd for VBoxManage {
+    fn create_temp_dir_in_guest(&self) -> VmResult<String> {
+        let _op = self.hooks.operation("create_temp_dir_in_guest");
+        self.make_temp_dir("hvctrl.XXXXXXXX")
+    }
+
+    fn remove_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_dir_in_guest");
+        self.remove_dir(true, &[guest_path])
+    }
+}
+
+/// Only processes started by VBoxManage can be listed. See [`VBoxManage::list_guest_processes`].
+impl GuestProcessCmd for VBoxManage {
+    fn list_processes_in_guest(&self) -> VmResult<Vec<ProcInfo>> {
+        let _op = self.hooks.operation("list_processes_in_guest");
+        self.list_guest_processes()
+    }
+
+    fn kill_process_in_guest(&self, pid: u32) -> VmResult<()> {
+        let _op = self.hooks.operation("kill_process_in_guest");
+        self.terminate_guest_process(pid)
+    }
+}
+
+impl GuestNetworkCmd for VBoxManage {
+    fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        let _op = self.hooks.operation("get_ip_addresses");
+        self.get_guest_ip_addresses()
+    }
+}
+
+impl GuestFsCmd for VBoxManage {
+    fn make_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("make_dir_in_guest");
+        self.make_dir(false, &[guest_path])
+    }
+
+    fn remove_file_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_file_in_guest");
+        self.remove_file(&[guest_path])
+    }
+
+    fn stat_in_guest(
+        &self,
+        guest_path: &str,
+    ) -> VmResult<Option<GuestFileInfo>> {
+        let _op = self.hooks.operation("stat_in_guest");
+        match self.stat(guest_path) {
+            Ok(x) => Ok(Some(x)),
+            Err(e) if e == VmError::from(ErrorKind::GuestFileNotFound) => {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn move_in_guest(
+        &self,
+        from_guest_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("move_in_guest");
+        self.move_files(&[from_guest_path], to_guest_path)
+    }
+
+    /// VBoxManage has no command to list a directory, so this always returns [`ErrorKind::UnsupportedCommand`].
+    fn list_dir_in_guest(&self, _guest_path: &str) -> VmResult<Vec<String>> {
+        let _op = self.hooks.operation("list_dir_in_guest");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "list_dir_in_guest",
+            "Use exec_cmd_output with ls or dir instead"
+        ))
+    }
+
+    fn make_temp_file_in_guest(&self) -> VmResult<String> {
+        let _op = self.hooks.operation("make_temp_file_in_guest");
+        self.make_temp_file("hvctrl.XXXXXXXX")
+    }
+}
+
+impl NicCmd for VBoxManage {
+    fn list_nics(&self) -> VmResult<Vec<Nic>> { Self::list_nics(self) }
+
+    /// Attaches `nic` to the first unused slot if `nic.id` is `None`.
+    fn add_nic(&self, nic: &Nic) -> VmResult<()> {
+        let _op = self.hooks.operation("add_nic");
+        if nic.id.is_some() {
+            return self.modify_nic(nic);
+        }
+        let used: Vec<String> =
+            self.list_nics()?.into_iter().filter_map(|x| x.id).collect();
+        match (1..=MAX_NICS).find(|x| !used.contains(&x.to_string())) {
+            Some(index) => self.modify_nic(&Nic {
+                id: Some(index.to_string()),
+                ..nic.clone()
+            }),
+            None => vmerr!(ErrorKind::InvalidParameter(
+                "No free NIC slot".to_string()
+            )),
+        }
+    }
+
+    fn update_nic(&self, nic: &Nic) -> VmResult<()> {
+        let _op = self.hooks.operation("update_nic");
+        self.modify_nic(nic)
+    }
+
+    fn remove_nic(&self, nic: &Nic) -> VmResult<()> {
+        match nic.id.as_deref().map(str::parse::<usize>) {
+            Some(Ok(index)) => Self::remove_nic(self, index),
+            _ => {
+                vmerr!(ErrorKind::InvalidParameter(
+                    "id is required".to_string()
+                ))
+            }
+        }
+    }
+}
+
+impl ConfigCmd for VBoxManage {
+    fn get_cpu_tuning(&self) -> VmResult<CpuTuning> {
+        Self::get_cpu_tuning(self)
+    }
+
+    fn set_cpu_tuning(&self, tuning: &CpuTuning) -> VmResult<()> {
+        Self::set_cpu_tuning(self, tuning)
+    }
+
+    fn get_memory_size(&self) -> VmResult<u64> { Self::get_memory_size(self) }
+
+    fn set_memory_size(&self, size_mb: u64) -> VmResult<()> {
+        Self::set_memory_size(self, size_mb)
+    }
+}
+
+impl MediaCmd for VBoxManage {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        self.set_dvd_medium(path)
+    }
+
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        self.set_dvd_medium("emptydrive")
+    }
+}
+
+impl CloneCmd for VBoxManage {
+    fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+        Self::clone_vm(self, options)
+    }
+}
+
+/// Destroys the clones with `controlvm poweroff` and `unregistervm --delete`.
+impl TemplateCmd for VBoxManage {
+    fn destroy_vms(&self, vms: &[Vm]) -> VmResult<()> {
+        let _op = self.hooks.operation("destroy_vms");
+        self.destroy_vms_with(vms, |vm| {
+            let mut cmd = self.clone();
+            cmd.vm_name(vm.id.clone().or_else(|| vm.name.clone()));
+            cmd
+        })
+    }
+}
+
+impl VmLifecycleCmd for VBoxManage {
+    fn create_vm(&self, spec: &VmSpec) -> VmResult<Vm> {
+        Self::create_vm(self, spec)
+    }
+
+    fn register_vm(&self, path: &str) -> VmResult<Vm> {
+        Self::register_vm(self, path)
+    }
+
+    fn unregister_vm(&self) -> VmResult<()> { Self::unregister_vm(self, false) }
+
+    fn delete_vm(&self) -> VmResult<()> { Self::delete_vm(self) }
+}
+
+impl ImportExportCmd for VBoxManage {
+    fn export_vm(&self, path: &str, format: ExportFormat) -> VmResult<()> {
+        Self::export_vm(self, path, format)
+    }
+
+    fn import_vm(&self, path: &str) -> VmResult<Vm> {
+        Self::import_vm(self, path)
+    }
+}
+
+impl MetricsCmd for VBoxManage {
+    fn get_metrics(&self) -> VmResult<VmMetrics> { Self::get_metrics(self) }
+}
+
+impl ScreenshotCmd for VBoxManage {
+    fn capture_screen(&self) -> VmResult<Vec<u8>> {
+        let _op = self.hooks.operation("capture_screen");
+        Self::screenshot(self)
+    }
+}
+
+impl KeyboardCmd for VBoxManage {
+    fn type_text(&self, text: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("type_text");
+        self.keyboard_put_string(&[text])
+    }
+
+    fn send_keys(&self, keys: &[Key]) -> VmResult<()> {
+        let _op = self.hooks.operation("send_keys");
+        self.keyboard_put_scancode(Key::to_scancodes(keys)?.into_iter())
+    }
+}
+
+/// Manages the rules of the first NAT NIC of the VM.
+impl PortForwardCmd for VBoxManage {
+    fn list_port_forwards(&self) -> VmResult<Vec<PortForward>> {
+        let _op = self.hooks.operation("list_port_forwards");
+        self.list_nat_port_forwards(self.first_nat_nic()?)
+    }
+
+    fn add_port_forward(&self, rule: &PortForward) -> VmResult<()> {
+        let _op = self.hooks.operation("add_port_forward");
+        self.add_nat_port_forward_rule(self.first_nat_nic()?, rule)
+    }
+
+    fn remove_port_forward(&self, rule: &PortForward) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_port_forward");
+        let nic = self.first_nat_nic()?;
+        let name = match &rule.name {
+            Some(x) => x.clone(),
+            None => self
+                .list_nat_port_forwards(nic)?
+                .into_iter()
+                .find(|x| {
+                    x.protocol == rule.protocol && x.host_port == rule.host_port
+                })
+                .and_then(|x| x.name)
+                .unwrap_or_else(|| natpf_rule_name(rule)),
+        };
+        self.delete_nat_port_forward(nic, &name)
+    }
+}
+
+/// Manages host-only interfaces, NAT networks and internal networks.
+///
+/// Internal networks are listed but exist only while VMs are attached to them, so they can't be created or deleted.
+impl HostNetworkCmd for VBoxManage {
+    fn list_host_networks(&self) -> VmResult<Vec<HostNetwork>> {
+        let _op = self.hooks.operation("list_host_networks");
+        let mut ret: Vec<HostNetwork> = self
+            .list_host_only_ifs()?
+            .into_iter()
+            .map(|x| {
+                let subnet = match (&x.ip_address, &x.network_mask) {
+                    (Some(ip), Some(mask)) => {
+                        match (ip.parse(), mask.parse().ok()) {
+                            (Ok(ip), Some(mask)) => {
+                                hostnet::netmask_to_prefix(mask)
+                                    .map(|x| hostnet::to_cidr(ip, x))
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                HostNetwork {
+                    name: x.name,
+                    kind: HostNetworkKind::HostOnly,
+                    subnet,
+                    host_ip: x.ip_address,
+                    dhcp: Some(x.dhcp),
+                }
+            })
+            .collect();
+        ret.extend(self.list_nat_networks()?.into_iter().map(|x| {
+            HostNetwork {
+                name: x.name,
+                kind: HostNetworkKind::Nat,
+                subnet: x.network,
+                host_ip: x.gateway,
+                dhcp: Some(x.dhcp),
+            }
+        }));
+        ret.extend(
+            self.list_internal_networks()?
+                .into_iter()
+                .map(|x| HostNetwork::new(x, HostNetworkKind::Internal)),
+        );
+        Ok(ret)
+    }
+
+    fn create_host_network(&self, net: &HostNetwork) -> VmResult<HostNetwork> {
+        let _op = self.hooks.operation("create_host_network");
+        match net.kind {
+            HostNetworkKind::HostOnly => {
+                let name = self.create_host_only_if()?;
+                let mut ret = HostNetwork {
+                    name,
+                    dhcp: Some(false),
+                    ..net.clone()
+                };
+                if net.subnet.is_none() {
+                    return Ok(ret);
+                }
+                let (subnet, prefix) = net.parse_subnet()?;
+                let host_ip = net.host_ip_or_default()?.to_string();
+                let mask = hostnet::prefix_to_netmask(prefix).to_string();
+                self.config_host_only_if(&ret.name, &host_ip, &mask)?;
+                if net.dhcp == Some(true) {
+                    let (server, lower, upper) = hostnet::dhcp_range(
+                        subnet, prefix,
+                    )
+                    .ok_or_else(|| {
+                        VmError::from(ErrorKind::InvalidParameter(
+                            "subnet is too small for DHCP".to_string(),
+                        ))
+                    })?;
+                    self.add_dhcp_server(
+                        &ret.name,
+                        &server.to_string(),
+                        &mask,
+                        &lower.to_string(),
+                        &upper.to_string(),
+                    )?;
+                    ret.dhcp = Some(true);
+                }
+                ret.subnet = Some(hostnet::to_cidr(subnet, prefix));
+                ret.host_ip = Some(host_ip);
+                Ok(ret)
+            }
+            HostNetworkKind::Nat => {
+                let (subnet, prefix) = net.parse_subnet()?;
+                let subnet = hostnet::to_cidr(subnet, prefix);
+                self.add_nat_network(&net.name, &subnet, net.dhcp)?;
+                Ok(HostNetwork {
+                    subnet: Some(subnet),
+                    ..net.clone()
+                })
+            }
+            HostNetworkKind::Internal => {
+                vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                    "create_host_network",
+                    "An internal network is created when a NIC is attached to \
+                     it"
+                ))
+            }
+            HostNetworkKind::Bridged => {
+                vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                    "create_host_network",
+                    "Bridge a NIC to an interface of list_bridged_ifs instead"
+                ))
+            }
+        }
+    }
+
+    /// Removes the DHCP server of a host-only interface together with the interface.
+    fn delete_host_network(&self, net: &HostNetwork) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_host_network");
+        match net.kind {
+            HostNetworkKind::HostOnly => {
+                if net.dhcp == Some(true) {
+                    self.remove_dhcp_server(&net.name)?;
+                }
+                self.remove_host_only_if(&net.name)
+            }
+            HostNetworkKind::Nat => self.remove_nat_network(&net.name),
+            _ => vmerr!(ErrorKind::unsupported::<Self>("delete_host_network")),
+        }
+    }
+}
+
+/// The IDs are the UUIDs of `list usbhost`, and the VM must be running.
+impl UsbCmd for VBoxManage {
+    fn list_host_usb_devices(&self) -> VmResult<Vec<UsbDevice>> {
+        let _op = self.hooks.operation("list_host_usb_devices");
+        self.list_usb_hosts()
+    }
+
+    fn attach_usb_device(&self, id: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_usb_device");
+        self.usb_attach(id)
+    }
+
+    fn detach_usb_device(&self, id: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("detach_usb_device");
+        self.usb_detach(id)
+    }
+}
+
+impl SerialPortCmd for VBoxManage {
+    fn list_serial_ports(&self) -> VmResult<Vec<SerialPort>> {
+        let _op = self.hooks.operation("list_serial_ports");
+        self.get_serial_ports()
+    }
+
+    fn set_serial_port(&self, n: usize, mode: &SerialPortMode) -> VmResult<()> {
+        Self::set_serial_port(self, n, mode)
+    }
+}
+
+/// VirtualBox supports only [`PassthroughType::Pci`].
+impl PassthroughCmd for VBoxManage {
+    fn list_passthrough_devices(&self) -> VmResult<Vec<PassthroughDevice>> {
+        let _op = self.hooks.operation("list_passthrough_devices");
+        self.list_pci_devices()
+    }
+
+    fn add_passthrough_device(&self, dev: &PassthroughDevice) -> VmResult<()> {
+        let _op = self.hooks.operation("add_passthrough_device");
+        match (&dev.ty, &dev.host_location) {
+            (Some(PassthroughType::Pci), Some(host)) => {
+                self.attach_pci_device(host, dev.guest_location.as_deref())
+            }
+            (Some(PassthroughType::Pci), None) => {
+                vmerr!(ErrorKind::InvalidParameter(
+                    "host_location is required".to_string()
+                ))
+            }
+            _ => vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "add_passthrough_device",
+                "Use PassthroughType::Pci instead"
+            )),
+        }
+    }
+
+    fn remove_passthrough_device(
+        &self,
+        dev: &PassthroughDevice,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_passthrough_device");
+        match (&dev.ty, &dev.host_location) {
+            (Some(PassthroughType::Pci), Some(host)) => {
+                self.detach_pci_device(host)
+            }
+            (Some(PassthroughType::Pci), None) => {
+                vmerr!(ErrorKind::InvalidParameter(
+                    "host_location is required".to_string()
+                ))
+            }
+            _ => vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "remove_passthrough_device",
+                "Use PassthroughType::Pci instead"
+            )),
+        }
+    }
+}
+
+impl SnapshotCmd for VBoxManage {
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        Self::list_snapshots(self)
+    }
+
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        Self::take_snapshot(self, name, None, true)
+    }
+
+    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("revert_snapshot");
+        self.restore_snapshot(name)
+    }
+
+    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        self.hooks
+            .settle_deleted(|| Self::delete_snapshot(self, name))
+    }
+
+    fn snapshot_disk_usage(&self) -> VmResult<Vec<SnapshotDiskUsage>> {
+        Self::snapshot_disk_usage(self)
+    }
+
+    fn snapshot_tree(&self) -> VmResult<SnapshotTree> {
+        Self::snapshot_tree(self)
+    }
+}
+
+/// Returns the locations of the differencing disks used by the snapshot whose UUID is `snapshot_id`.
+///
+/// `hdds` are the blocks of `VBoxManage list hdds`.
+fn snapshot_disks(
+    hdds: &[HashMap<&str, &str>],
+    snapshot_id: &str,
+) -> Vec<String> {
+    let uuid = format!("(UUID: {})", snapshot_id);
+    hdds.iter()
+        .filter(|x| x.get("Parent UUID").map_or(false, |x| *x != "base"))
+        .filter(|x| x.get("In use by VMs").map_or(false, |x| x.contains(&uuid)))
+        .filter_map(|x| x.get("Location").map(|x| x.to_string()))
+        .collect()
+}
+
+/// Returns the locations of the base disks in `hdds` used by the VM whose UUID is `vm_id`.
+fn vm_base_disks(hdds: &[HashMap<&str, &str>], vm_id: &str) -> Vec<String> {
+    let uuid = format!("(UUID: {})", vm_id);
+    hdds.iter()
+        .filter(|x| x.get("Parent UUID") == Some(&"base"))
+        .filter(|x| x.get("In use by VMs").map_or(false, |x| x.contains(&uuid)))
+        .filter_map(|x| x.get("Location").map(|x| x.to_string()))
+        .collect()
+}
+
+/// Parses the output of `list hdds`, `list dvds` and `list floppies`.
+fn parse_media(s: &str) -> Vec<Medium> {
+    parse_list_blocks(s)
+        .iter()
+        .filter_map(|x| {
+            let get = |key| x.get(key).map(|x| x.to_string());
+            Some(Medium {
+                id: get("UUID")?,
+                parent_id: get("Parent UUID").filter(|x| x != "base"),
+                state: get("State"),
+                ty: x
+                    .get("Type")
+                    .and_then(|x| x.split_whitespace().next())
+                    .and_then(MediumType::from_name),
+                location: get("Location"),
+                format: get("Storage format"),
+                capacity_mb: parse_leading_number(x.get("Capacity")),
+                encrypted: x
+                    .get("Encryption")
+                    .map_or(false, |x| *x != "disabled"),
+                vm_ids: x
+                    .get("In use by VMs")
+                    .map_or(vec![], |x| parse_in_use_vm_ids(x)),
+            })
+        })
+        .collect()
+}
+
+/// Parses the UUIDs of the VMs in `vm (UUID: <uuid>) [snapshot (UUID: <uuid>)], ...`, skipping those of the snapshots in brackets.
+fn parse_in_use_vm_ids(s: &str) -> Vec<String> {
+    let mut ret = vec![];
+    let mut depth = 0;
+    let mut rest = s;
+    while let Some(i) = rest.find(|c| c == '[' || c == ']' || c == '(') {
+        let c = rest.as_bytes()[i];
+        rest = &rest[i + 1..];
+        match c {
+            b'[' => depth += 1,
+            b']' => depth -= 1,
+            _ => {
+                if let Some(x) = rest.strip_prefix("UUID: ") {
+                    let id = &x[..x.find(')').unwrap_or(x.len())];
+                    if depth == 0 {
+                        ret.push(id.trim().to_string());
+                    }
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Parses the output of `guestproperty enumerate`.
+#[cfg(test)]
+fn parse_guest_properties(s: &str) -> Vec<(String, String)> {
+    parse_guest_property_entries(s)
+        .into_iter()
+        .map(|x| (x.name, x.value))
+        .collect()
+}
+
+/// Parses the output of `guestproperty enumerate` and `guestproperty wait`.
+///
+/// Supports both `Name: <name>, value: <value>, timestamp: <ns>, flags: <flags>` (VirtualBox 6.1 and earlier) and `<name> = '<value>' @ <time> [<flags>]` (VirtualBox 7.0 and later).
+fn parse_guest_property_entries(s: &str) -> Vec<GuestProperty> {
+    s.lines()
+        .filter_map(|x| {
+            if let Some(x) = x.strip_prefix("Name: ") {
+                let (name, x) = x.split_once(", value: ")?;
+                // `wait` omits the timestamp.
+                let (x, flags) = match x.rfind(", flags: ") {
+                    Some(i) => (&x[..i], &x[i + ", flags: ".len()..]),
+                    None => (x, ""),
+                };
+                let (value, timestamp) = match x.rfind(", timestamp: ") {
+                    Some(i) => (&x[..i], Some(&x[i + ", timestamp: ".len()..])),
+                    None => (x, None),
+                };
+                Some(GuestProperty {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                    timestamp: timestamp.and_then(parse_guest_property_time),
+                    flags: parse_guest_property_flags(flags),
+                })
+            } else {
+                let (name, x) = x.split_once(" = '")?;
+                let i = x.rfind('\'')?;
+                let rest = x[i + 1..].trim();
+                let (rest, flags) = match rest.find('[') {
+                    Some(i) => {
+                        (&rest[..i], rest[i + 1..].trim_end_matches(']'))
+                    }
+                    None => (rest, ""),
+                };
+                Some(GuestProperty {
+                    name: name.trim().to_string(),
+                    value: x[..i].to_string(),
+                    timestamp: rest
+                        .strip_prefix('@')
+                        .and_then(parse_guest_property_time),
+                    flags: parse_guest_property_flags(flags),
+                })
+            }
+        })
+        .collect()
+}
+
+/// Parses the output of `guestproperty get --verbose`, which is `Value: <value>`, `Timestamp: <time>` and `Flags: <flags>` lines.
+fn parse_guest_property_verbose(name: &str, s: &str) -> Option<GuestProperty> {
+    let mut ret = GuestProperty {
+        name: name.to_string(),
+        ..Default::default()
+    };
+    let mut found = false;
+    for l in s.lines() {
+        if let Some(x) = l.strip_prefix("Value: ") {
+            ret.value = x.to_string();
+            found = true;
+        } else if let Some(x) = l.strip_prefix("Timestamp: ") {
+            ret.timestamp = parse_guest_property_time(x);
+        } else if let Some(x) = l.strip_prefix("Flags: ") {
+            ret.flags = parse_guest_property_flags(x);
+        }
+    }
+    found.then(|| ret)
+}
+
+/// Parses a timestamp of a guest property, which is nanoseconds since the Unix epoch in VirtualBox 6.1 and earlier and, e.g., `2023-01-01T00:00:00.000000000Z` in VirtualBox 7.0 and later.
+fn parse_guest_property_time(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.parse::<u64>() {
+        Ok(x) => Some(x / 1_000_000_000),
+        Err(_) => parse_timestamp(s),
+    }
+}
+
+fn parse_guest_property_flags(s: &str) -> Vec<GuestPropertyFlag> {
+    s.split(',')
+        .filter_map(GuestPropertyFlag::from_name)
+        .collect()
+}
+
+/// Gets the addresses in `/VirtualBox/GuestInfo/Net/<n>/V4/IP` and `/V6/IP` with `/VirtualBox/GuestInfo/Net/<n>/MAC`.
+fn parse_guest_ip_addresses(props: &[(String, String)]) -> Vec<GuestIpAddress> {
+    let mut nics: BTreeMap<u32, HashMap<&str, &str>> = BTreeMap::new();
+    for (name, value) in props {
+        let x = match name.strip_prefix("/VirtualBox/GuestInfo/Net/") {
+            Some(x) => x,
+            None => continue,
+        };
+        if let Some((index, key)) = x.split_once('/') {
+            if let Ok(index) = index.parse() {
+                nics.entry(index).or_default().insert(key, value);
+            }
+        }
+    }
+    nics.values()
+        .flat_map(|x| {
+            let mac = x.get("MAC").copied();
+            ["V4/IP", "V6/IP"]
+                .iter()
+                .filter_map(move |key| GuestIpAddress::parse(mac, x.get(key)?))
+        })
+        .collect()
+}
+
+/// Parses the output of `VBoxManage snapshot <vm> list --machinereadable` into the snapshots and the UUID of the current snapshot.
+///
+/// The parent of `SnapshotName-1-2` is `SnapshotName-1`.
+fn parse_snapshot_list(s: &str) -> VmResult<(Vec<Snapshot>, Option<String>)> {
+    let mut ret: Vec<Snapshot> = vec![];
+    let mut current_id = None;
+    if s.trim() == "This machine does not have any snapshots" {
+        return Ok((ret, current_id));
+    }
+    // Maps node suffixes such as `-1-2` to snapshot UUIDs.
+    let mut ids: HashMap<&str, String> = HashMap::new();
+    let unquote = |x: &str| x.strip_suffix('"').unwrap_or(x).to_string();
+    for x in s.lines() {
+        let (key, value) = match x.split_once("=\"") {
+            Some((k, v))
+                if k.starts_with("Snapshot")
+                    || k.starts_with("CurrentSnapshot") =>
+            {
+                (k, v)
+            }
+            _ => match ret.last_mut().and_then(|x| x.detail.as_mut()) {
+                // A continuation of a multi-line description.
+                Some(detail) => {
+                    *detail += LINE_FEED;
+                    *detail += x;
+                    continue;
+                }
+                None => {
+                    return vmerr!(ErrorKind::UnexpectedResponse(x.to_string()))
+                }
+            },
+        };
+        let (name, node) = key.split_at(key.find('-').unwrap_or(key.len()));
+        match name {
+            "SnapshotName" => ret.push(Snapshot {
+                id: None,
+                name: Some(unquote(value)),
+                detail: None,
+                parent_id: node
+                    .rfind('-')
+                    .and_then(|p| ids.get(&node[..p]).cloned()),
+                created_at: None,
+                is_current: None,
+                online: None,
+            }),
+            "SnapshotUUID" | "SnapshotDescription" => {
+                let sn = match ret.last_mut() {
+                    Some(x) => x,
+                    None => {
+                        return vmerr!(ErrorKind::UnexpectedResponse(
+                            x.to_string()
+                        ))
+                    }
+                };
+                if name == "SnapshotUUID" {
+                    sn.id = Some(unquote(value));
+                    ids.insert(node, unquote(value));
+                } else {
+                    // The closing quote is removed after the whole description is read.
+                    sn.detail = Some(value.to_string());
+                }
+            }
+            "CurrentSnapshotUUID" => current_id = Some(unquote(value)),
+            _ => {}
+        }
+    }
+    for x in ret.iter_mut() {
+        if let Some(detail) = x.detail.as_mut() {
+            if detail.ends_with('"') {
+                detail.pop();
+            }
+        }
+        x.is_current = Some(x.id.is_some() && x.id == current_id);
+    }
+    Ok((ret, current_id))
+}
+
+/// Parses the output of `list vms` and `list runningvms`.
+fn parse_vm_list(s: &str) -> Vec<Vm> {
+    // "vm name" {uuid}
+    s.lines()
+        .filter_map(|x| {
+            let (name, id) = x.trim_end().rsplit_once(' ')?;
+            Some(Vm {
+                id: Some(id.to_string()),
+                name: Some(
+                    name.strip_prefix('"')?.strip_suffix('"')?.to_string(),
+                ),
+                path: None,
+            })
+        })
+        .collect()
+}
+
+/// Parses `VBoxManage list --long vms` into the VMs and their groups.
+///
+/// Only the top-level `Name`, `Groups`, `UUID` and `Config file` of each VM are read.
+fn parse_long_vm_list(s: &str) -> Vec<(Vm, Vec<String>)> {
+    let mut ret: Vec<(Vm, Vec<String>)> = vec![];
+    for l in s.lines() {
+        let (key, value) = match l.split_once(':') {
+            Some((key, value)) if !key.starts_with(' ') => (key, value.trim()),
+            _ => continue,
+        };
+        // Shared folders also have `Name: 'name', Host path: ...` lines.
+        if key == "Name" && !value.starts_with('\'') {
+            ret.push((
+                Vm {
+                    id: None,
+                    name: Some(value.to_string()),
+                    path: None,
+                },
+                vec!["/".to_string()],
+            ));
+            continue;
+        }
+        let (vm, groups) = match ret.last_mut() {
+            Some(x) => x,
+            None => continue,
+        };
+        match key {
+            "Groups" => *groups = split_groups(value),
+            "UUID" if vm.id.is_none() => {
+                vm.id = Some(format!("{{{}}}", value));
+            }
+            "Config file" if vm.path.is_none() => {
+                vm.path = Some(value.to_string());
+            }
+            _ => {}
+        }
+    }
+    ret
+}
+
+/// Splits comma-separated VM groups. Empty groups mean the root group `/`.
+fn split_groups(s: &str) -> Vec<String> {
+    let groups: Vec<String> = s
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect();
+    if groups.is_empty() {
+        vec!["/".to_string()]
+    } else {
+        groups
+    }
+}
+
+/// Returns true if `vms` contains a VM whose name or UUID is `name_or_uuid`.
+fn contains_vm(vms: &[Vm], name_or_uuid: &str) -> bool {
+    let uuid = name_or_uuid.trim_start_matches('{').trim_end_matches('}');
+    vms.iter().any(|x| {
+        x.name.as_deref() == Some(name_or_uuid)
+            || x.id.as_deref().map_or(false, |x| {
+                x.trim_start_matches('{')
+                    .trim_end_matches('}')
+                    .eq_ignore_ascii_case(uuid)
+            })
+    })
+}
+
+/// Finds the snapshot whose UUID or name is `name` in `snapshots`.
+fn find_snapshot(snapshots: Vec<Snapshot>, name: &str) -> VmResult<Snapshot> {
+    let uuid = name.trim_start_matches('{').trim_end_matches('}');
+    if let Some(x) = snapshots.iter().find(|x| {
+        x.id.as_deref()
+            .map_or(false, |x| x.eq_ignore_ascii_case(uuid))
+    }) {
+        return Ok(x.clone());
+    }
+    let mut found = snapshots
+        .into_iter()
+        .filter(|x| x.name.as_deref() == Some(name));
+    match (found.next(), found.next()) {
+        (Some(x), None) => Ok(x),
+        (Some(_), Some(_)) => vmerr!(ErrorKind::InvalidParameter(format!(
+            "{} matches several snapshots",
+            name
+        ))),
+        _ => vmerr!(ErrorKind::SnapshotNotFound),
+    }
+}
+
+/// Sets `created_at` and `online` of `snapshots` from the `timeStamp` and `stateFile` attributes of the `<Snapshot>` elements in the `.vbox` file `xml`.
+fn apply_snapshot_times(snapshots: &mut [Snapshot], xml: &str) {
+    let tag = regex::Regex::new(r"<Snapshot\s[^>]*>").unwrap();
+    let attr = regex::Regex::new(r#"(\w+)="([^"]*)""#).unwrap();
+    for x in tag.find_iter(xml) {
+        let attrs: HashMap<&str, &str> = attr
+            .captures_iter(x.as_str())
+            .filter_map(|c| Some((c.get(1)?.as_str(), c.get(2)?.as_str())))
+            .collect();
+        let uuid = match attrs.get("uuid") {
+            Some(x) => x.trim_start_matches('{').trim_end_matches('}'),
+            None => continue,
+        };
+        if let Some(sn) = snapshots.iter_mut().find(|x| {
+            x.id.as_deref()
+                .map_or(false, |x| x.eq_ignore_ascii_case(uuid))
+        }) {
+            sn.created_at =
+                attrs.get("timeStamp").and_then(|x| parse_timestamp(x));
+            sn.online = Some(attrs.contains_key("stateFile"));
+        }
+    }
+}
+
+/// Checks whether users are logged in and all of them are idle.
+fn is_idle(props: &[(String, String)]) -> bool {
+    let mut states = props
+        .iter()
+        .filter(|(name, _)| {
+            name.starts_with("/VirtualBox/GuestInfo/User/")
+                && name.ends_with("/UsageState")
+        })
+        .map(|(_, value)| value.as_str())
+        .peekable();
+    states.peek().is_some() && states.all(|x| x == "Idle")
+}
+
+/// Returns `time - now` in milliseconds.
+fn time_offset_ms(time: SystemTime, now: SystemTime) -> i64 {
+    match time.duration_since(now) {
+        Ok(x) => x.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+/// Parses the output of `list ostypes`.
+fn parse_os_types(s: &str) -> Vec<OsType> {
+    parse_list_blocks(s)
+        .iter()
+        .filter_map(|x| {
+            let get = |key| x.get(key).map(|x| x.to_string());
+            Some(OsType {
+                id: get("ID")?,
+                description: get("Description"),
+                family_id: get("Family ID"),
+                family_description: get("Family Desc"),
+                is_64bit: x.get("64 bit") == Some(&"true"),
+            })
+        })
+        .collect()
+}
+
+/// Parses `key: value` lines into a map, keeping the first value of each key.
+fn parse_colon_values(s: &str) -> HashMap<&str, &str> {
+    let mut hm = HashMap::new();
+    for l in s.lines() {
+        if let Some((key, value)) = l.split_once(':') {
+            hm.entry(key.trim()).or_insert_with(|| value.trim());
+        }
+    }
+    hm
+}
+
+/// Parses the leading number of a value such as `16384 MByte`.
+fn parse_leading_number<T: std::str::FromStr>(s: Option<&&str>) -> Option<T> {
+    s?.split_whitespace().next()?.parse().ok()
+}
+
+/// Parses the output of `list hostinfo`.
+fn parse_host_info(s: &str) -> HostInfo {
+    let hm = parse_colon_values(s);
+    let get = |key| hm.get(key).map(|x| x.to_string());
+    let yes = |key| hm.get(key) == Some(&"yes");
+    HostInfo {
+        os: get("Operating system"),
+        os_version: get("Operating system version"),
+        cpu_description: get("Processor#0 description"),
+        cpu_count: parse_leading_number(hm.get("Processor count")),
+        cpu_core_count: parse_leading_number(hm.get("Processor core count")),
+        memory_mb: parse_leading_number(hm.get("Memory size")),
+        memory_available_mb: parse_leading_number(hm.get("Memory available")),
+        hw_virtualization: yes("Processor supports HW virtualization"),
+        nested_hw_virtualization: yes(
+            "Processor supports nested HW virtualization"
+        ),
+    }
+}
+
+/// Parses the output of `list systemproperties`.
+fn parse_system_properties(s: &str) -> SystemProperties {
+    let hm = parse_colon_values(s);
+    let get =
+        |key| hm.get(key).map(|x| x.to_string()).filter(|x| !x.is_empty());
+    SystemProperties {
+        api_version: get("API version"),
+        min_guest_memory_mb: parse_leading_number(
+            hm.get("Minimum guest RAM size"),
+        ),
+        max_guest_memory_mb: parse_leading_number(
+            hm.get("Maximum guest RAM size"),
+        ),
+        min_guest_vram_mb: parse_leading_number(
+            hm.get("Minimum video RAM size"),
+        ),
+        max_guest_vram_mb: parse_leading_number(
+            hm.get("Maximum video RAM size"),
+        ),
+        min_guest_cpus: parse_leading_number(hm.get("Minimum guest CPU count")),
+        max_guest_cpus: parse_leading_number(hm.get("Maximum guest CPU count")),
+        max_guest_monitors: parse_leading_number(
+            hm.get("Maximum guest monitor count"),
+        ),
+        max_disk_size_bytes: parse_leading_number(
+            hm.get("Virtual disk limit (info)"),
+        ),
+        default_machine_folder: get("Default machine folder"),
+        default_hard_disk_format: get("Default hard disk format"),
+        guest_additions_iso: get("Default Guest Additions ISO"),
+        autostart_db_path: get("Autostart database path"),
+    }
+}
+
+/// Parses `VBoxManage list cloudprofiles`.
+fn parse_cloud_profiles(s: &str) -> Vec<CloudProfile> {
+    let mut ret: Vec<CloudProfile> = vec![];
+    for l in s.lines() {
+        let (key, value) = match l.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        match (key, ret.last_mut()) {
+            ("Name", _) => ret.push(CloudProfile {
+                name: value.to_string(),
+                ..Default::default()
+            }),
+            ("Provider GUID", Some(x)) => {
+                x.provider_id = Some(value.to_string())
+            }
+            ("Property", Some(x)) => {
+                if let Some((k, v)) = value.split_once('=') {
+                    x.properties
+                        .push((k.trim().to_string(), v.trim().to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+    ret
+}
+
+/// Finds the storage controller, port and device of the first DVD drive in `showvminfo --machinereadable`.
+fn find_dvd_drive(s: &str) -> Option<(String, String, String)> {
+    s.lines().find_map(|l| {
+        let (key, value) = l.split_once('=')?;
+        let (key, value) = (key.trim_matches('"'), value.trim_matches('"'));
+        let mut it = key.rsplitn(3, '-');
+        let (device, port, ctl) = (it.next()?, it.next()?, it.next()?);
+        if device.parse::<u32>().is_err() || port.parse::<u32>().is_err() {
+            return None;
+        }
+        // `<controller>-IsEjected-<port>-<device>` exists only for DVD drives.
+        let ctl = match ctl.strip_suffix("-IsEjected") {
+            Some(x) => x,
+            None if value == "emptydrive"
+                || value.to_ascii_lowercase().ends_with(".iso") =>
+            {
+                ctl
+            }
+            None => return None,
+        };
+        Some((ctl.to_string(), port.to_string(), device.to_string()))
+    })
+}
+
+/// Parses the output of `guestcontrol list processes`.
+///
+/// Each process is printed as `Process #0    PID=1234   Status=started  Name=/bin/sleep`.
+fn parse_guest_processes(s: &str) -> Vec<ProcInfo> {
+    let mut ret = vec![];
+    for line in s.lines() {
+        let line = line.trim();
+        if !line.starts_with("Process #") {
+            continue;
+        }
+        let pid = line
+            .split_whitespace()
+            .find_map(|x| x.strip_prefix("PID="))
+            .and_then(|x| x.parse().ok());
+        let cmd = line.find("Name=").map(|x| line[x + 5..].to_string());
+        if let (Some(pid), Some(cmd)) = (pid, cmd) {
+            ret.push(ProcInfo {
+                pid,
+                owner: String::new(),
+                cmd,
+            });
+        }
+    }
+    ret
+}
+
+/// Parses the output of `guestcontrol stat`.
+///
+/// Older versions print `Element "..." found: Is a file`, newer ones print `Type:` and `Size:` fields.
+fn parse_stat(s: &str) -> Option<GuestFileInfo> {
+    let to_type = |x: &str| match x.trim() {
+        // `Type:` is followed by `regular file` or `symbolic link` in some versions.
+        "file" | "regular" => GuestFileType::File,
+        "directory" => GuestFileType::Directory,
+        "symlink" | "symbolic" => GuestFileType::Symlink,
+        _ => GuestFileType::Other,
+    };
+    let mut file_type = None;
+    let mut size = None;
+    for line in s.lines() {
+        if let Some((_, x)) = line.split_once(" found: Is a ") {
+            file_type = Some(to_type(x));
+        }
+        let mut words = line.split_whitespace();
+        while let Some(w) = words.next() {
+            match w {
+                "Type:" => {
+                    file_type = words.next().map(to_type);
+                }
+                "Size:" => {
+                    size = words.next().and_then(|x| x.parse().ok());
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(GuestFileInfo {
+        file_type: file_type?,
+        size,
+    })
+}
+
+/// Parses the latest values written by `metrics query`, e.g., `ubuntu  RAM/Usage/Used  1024 kB, 2048 kB`.
+fn parse_metrics(s: &str) -> VmMetrics {
+    let values: HashMap<String, f64> = parse_metric_samples(s, false)
+        .into_iter()
+        .filter_map(|x| Some((x.metric, *x.values.last()?)))
+        .collect();
+    let bytes = |name: &str| values.get(name).map(|x| *x as u64);
+    let cpu = ["CPU/Load/User", "CPU/Load/Kernel"]
+        .iter()
+        .filter_map(|x| values.get(*x))
+        .fold(None, |acc: Option<f64>, x| Some(acc.unwrap_or(0.0) + x));
+    VmMetrics {
+        cpu_percent: cpu,
+        memory_used_bytes: bytes("RAM/Usage/Used"),
+        disk_read_bytes_per_sec: None,
+        disk_write_bytes_per_sec: None,
+        network_rx_bytes_per_sec: bytes("Net/Rate/Rx"),
+        network_tx_bytes_per_sec: bytes("Net/Rate/Tx"),
+    }
+}
+
+/// Parses the output of `metrics query`, or that of `metrics collect` if `timed`.
+fn parse_metric_samples(s: &str, timed: bool) -> Vec<MetricSample> {
+    s.lines()
+        .filter_map(|x| parse_metric_line(x, timed))
+        .collect()
+}
+
+/// Parses a line such as `my vm CPU/Load/User 1.00%, 2.50%` of `metrics query`, or `14:58:10.123 my vm CPU/Load/User 1.00%` of `metrics collect` if `timed`.
+fn parse_metric_line(l: &str, timed: bool) -> Option<MetricSample> {
+    let tokens: Vec<&str> = l.split_whitespace().collect();
+    let start = if timed { 1 } else { 0 };
+    let i =
+        start + tokens.get(start..)?.iter().position(|x| x.contains('/'))?;
+    let values = tokens[i + 1..]
+        .join(" ")
+        .split(',')
+        .map(|x| {
+            let x = x.trim();
+            let (value, unit) = x.split_at(
+                x.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                    .unwrap_or(x.len()),
+            );
+            let scale = match unit.trim() {
+                "" | "%" | "B" | "B/s" => 1.0,
+                "kB" | "kB/s" => 1024.0,
+                "MB" | "MB/s" => 1024.0 * 1024.0,
+                _ => return None,
+            };
+            Some(value.parse::<f64>().ok()? * scale)
+        })
+        .collect::<Option<Vec<f64>>>()?;
+    Some(MetricSample {
+        time: timed.then(|| tokens[0].to_string()),
+        object: tokens[start..i].join(" "),
+        metric: tokens[i].to_string(),
+        values,
+    })
+}
+
+/// Parses the UUID of the VM created by `createvm`.
+fn parse_created_vm_uuid(s: &str) -> Option<&str> {
+    s.lines()
+        .find_map(|x| x.trim().strip_prefix("UUID:"))
+        .map(|x| x.trim())
+}
+
+/// Parses the UUID of the disk image created by `createmedium` or `clonemedium`, e.g., `Medium created. UUID: <uuid>`.
+fn parse_created_medium_uuid(s: &str) -> Option<&str> {
+    s.lines()
+        .find_map(|x| x.split_once("UUID:"))
+        .map(|x| x.1.trim())
+}
+
+/// Parses the UUID of the `<Machine>` element in the `.vbox` file `xml`.
+fn parse_machine_uuid(xml: &str) -> Option<&str> {
+    let machine = &xml[xml.find("<Machine ")?..];
+    let machine = &machine[..machine.find('>')?];
+    let uuid = &machine[machine.find(" uuid=\"")? + 7..];
+    Some(uuid[..uuid.find('"')?].trim_matches(|x| x == '{' || x == '}'))
+}
+
+/// Parses the virtual systems listed by `import`.
+///
+/// The values changed by the options are written as, e.g., `VM name specified with --vmname: "name"` instead of `Suggested VM name "name"`.
+fn parse_virtual_systems(s: &str) -> Vec<VirtualSystem> {
+    let quoted = |x: &str| {
+        let x = &x[x.find('"')? + 1..];
+        Some(x[..x.rfind('"')?].to_string())
+    };
+    let value = |x: &str| x.rsplit(':').next().map(|x| x.trim().to_string());
+    let mut ret: Vec<VirtualSystem> = vec![];
+    for l in s.lines() {
+        if let Some(x) = l.strip_prefix("Virtual system ") {
+            if let Ok(index) = x.trim().trim_end_matches(':').parse() {
+                ret.push(VirtualSystem {
+                    index,
+                    ..Default::default()
+                });
+            }
+            continue;
+        }
+        let sys = match ret.last_mut() {
+            Some(x) => x,
+            None => continue,
+        };
+        let l = l.trim();
+        let unit = l
+            .split_once(':')
+            .and_then(|(i, d)| Some((i.parse().ok()?, d.trim())));
+        let (index, desc) = match unit {
+            Some(x) => x,
+            None => {
+                // The hints follow the unit, e.g., `(disable with "--vsys 0 --unit 8 --ignore")`.
+                if l.contains("--ignore") {
+                    if let Some(x) = sys.units.last_mut() {
+                        x.ignorable = true;
+                    }
+                }
+                continue;
+            }
+        };
+        if desc.starts_with("Suggested OS type") || desc.starts_with("OS type")
+        {
+            sys.os_type = quoted(desc);
+        } else if desc.starts_with("Suggested VM name")
+            || desc.starts_with("VM name")
+        {
+            sys.name = quoted(desc);
+        } else if desc.starts_with("Number of CPUs") {
+            sys.cpus = value(desc).and_then(|x| x.parse().ok());
+        } else if desc.starts_with("Guest memory") {
+            sys.memory_mb = value(desc)
+                .and_then(|x| x.trim_end_matches("MB").trim().parse().ok());
+        }
+        sys.units.push(ApplianceUnit {
+            index,
+            description: desc.to_string(),
+            ignorable: false,
+        });
+    }
+    ret
+}
+
+/// Gets the VM name from `Suggested VM name "name"` written by `import`.
+fn parse_imported_vm_name(s: &str) -> Option<&str> {
+    s.lines().find_map(|x| {
+        let (_, name) = x.split_once("Suggested VM name ")?;
+        Some(name.trim().trim_matches('"'))
+    })
+}
+
+fn to_power_state(s: &str) -> VmPowerState {
+    match s {
+        "running" => VmPowerState::Running,
+        "poweroff" | "aborted" => VmPowerState::Stopped,
+        "saved" => VmPowerState::Suspended,
+        "paused" => VmPowerState::Paused,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+/// Parses `AttachedHostPCI=<host>,<guest>` lines of `showvminfo --machinereadable`.
+fn parse_attached_pci(s: &str) -> Vec<PassthroughDevice> {
+    s.lines()
+        .filter_map(|x| x.trim().strip_prefix("AttachedHostPCI="))
+        .filter_map(|x| {
+            let (host, guest) = x.trim_matches('"').split_once(',')?;
+            Some(PassthroughDevice {
+                id: None,
+                ty: Some(PassthroughType::Pci),
+                host_location: Some(host.to_string()),
+                guest_location: Some(guest.to_string()),
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_vm_settings() {
+    assert!(VmSettings::default().to_args().unwrap().is_empty());
+    let settings = VmSettings {
+        memory_mb: Some(4096),
+        cpus: Some(2),
+        firmware: Some(Firmware::Efi),
+        nested_hw_virt: Some(true),
+        paravirt_provider: Some(ParavirtProvider::Kvm),
+        boot_order: Some(vec![BootDevice::Disk, BootDevice::Dvd]),
+        ..Default::default()
+    };
+    assert_eq!(
+        settings.to_args().unwrap(),
+        [
+            "--memory",
+            "4096",
+            "--cpus",
+            "2",
+            "--firmware",
+            "efi",
+            "--nested-hw-virt",
+            "on",
+            "--paravirtprovider",
+            "kvm",
+            "--boot1",
+            "disk",
+            "--boot2",
+            "dvd",
+            "--boot3",
+            "none",
+            "--boot4",
+            "none",
+        ]
+    );
+    let settings = VmSettings {
+        boot_order: Some(vec![BootDevice::Net; 5]),
+        ..Default::default()
+    };
+    assert!(settings.to_args().is_err());
+}
+
+#[test]
+fn test_nat_engine_settings() {
+    assert!(NatEngineSettings::default().to_args(1).is_empty());
+    let settings = NatEngineSettings {
+        dns_host_resolver: Some(true),
+        alias_mode: Some(NatAliasMode {
+            proxy_only: true,
+            same_ports: true,
+            ..Default::default()
+        }),
+        tftp_file: Some("pxelinux.0".to_string()),
+        localhost_reachable: Some(false),
+        ..Default::default()
+    };
+    assert_eq!(
+        settings.to_args(2),
+        [
+            "--natdnshostresolver2",
+            "on",
+            "--nataliasmode2",
+            "proxyonly,sameports",
+            "--nattftpfile2",
+            "pxelinux.0",
+            "--natlocalhostreachable2",
+            "off",
+        ]
+    );
+    assert_eq!(NatAliasMode::default().to_arg(), "default");
+}
+
+#[test]
+fn test_serial_port_mode() {
+    assert_eq!(uart_args(&SerialPortMode::Off, 2), ["--uart2", "off"]);
+    assert_eq!(
+        uart_args(&SerialPortMode::File("/tmp/com1.log".to_string()), 1),
+        [
+            "--uart1",
+            "0x3F8",
+            "4",
+            "--uartmode1",
+            "file",
+            "/tmp/com1.log"
+        ]
+    );
+    assert_eq!(
+        uart_args(&SerialPortMode::TcpServer(2023), 2),
+        ["--uart2", "0x2F8", "3", "--uartmode2", "tcpserver", "2023"]
+    );
+    assert_eq!(
+        uart_args(&SerialPortMode::PipeServer(r"\\.\pipe\vm".to_string()), 4),
+        [
+            "--uart4",
+            "0x2E8",
+            "3",
+            "--uartmode4",
+            "server",
+            r"\\.\pipe\vm"
+        ]
+    );
+    let hm: HashMap<&str, &str> = [
+        ("uart1", "0x03f8,4"),
+        ("uartmode1", r"server,\\.\pipe\vm"),
+        ("uart2", "0x02f8,3"),
+        ("uartmode2", "tcpserver,2023"),
+        ("uart3", "off"),
+        ("uart4", "0x02e8,3"),
+        ("uartmode4", "hostdevice,COM1"),
+    ]
+    .iter()
+    .copied()
+    .collect();
+    assert_eq!(
+        parse_serial_ports(&hm),
+        [
+            SerialPort {
+                number: 1,
+                mode: SerialPortMode::PipeServer(r"\\.\pipe\vm".to_string())
+            },
+            SerialPort {
+                number: 2,
+                mode: SerialPortMode::TcpServer(2023)
+            },
+            SerialPort {
+                number: 3,
+                mode: SerialPortMode::Off
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parse_metrics() {
+    let s = [
+        "Object          Metric                   Values",
+        "--------------- ------------------------ ----------------",
+        "my vm           CPU/Load/User            1.00%, 2.50%",
+        "my vm           CPU/Load/Kernel          0.50%",
+        "my vm           RAM/Usage/Used           2048 kB",
+        "my vm           Net/Rate/Rx              100 B/s",
+    ]
+    .join("\n");
+    assert_eq!(
+        parse_metrics(&s),
+        VmMetrics {
+            cpu_percent: Some(3.0),
+            memory_used_bytes: Some(2 * 1024 * 1024),
+            network_rx_bytes_per_sec: Some(100),
+            ..VmMetrics::default()
+        }
+    );
+    assert_eq!(parse_metrics(""), VmMetrics::default());
+
+    let v = parse_metric_samples(&s, false);
+    assert_eq!(v.len(), 4);
+    assert_eq!(
+        v[0],
+        MetricSample {
+            time: None,
+            object: "my vm".to_string(),
+            metric: "CPU/Load/User".to_string(),
+            values: vec![1.0, 2.5],
+        }
+    );
+    let s = [
+        "Time stamp   Object     Metric               Value",
+        "14:58:10.123 my vm      Guest/RAM/Usage/Free 512 kB",
+        "14:58:10.123 my vm      Net/Rate/Rx          1.5 MB/s",
+    ]
+    .join("\n");
+    let v = parse_metric_samples(&s, true);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].time.as_deref(), Some("14:58:10.123"));
+    assert_eq!(v[0].object, "my vm");
+    assert_eq!(v[0].metric, "Guest/RAM/Usage/Free");
+    assert_eq!(v[0].values, [512.0 * 1024.0]);
+    assert_eq!(v[1].values, [1.5 * 1024.0 * 1024.0]);
+}
+
+#[test]
+fn test_parse_imported_vm_name() {
+    let s = "Interpreting /tmp/ubuntu.ova...\nOK.\n0: Suggested OS type: \
+             \"Ubuntu_64\"\n    (change with \"--vsys 0 --ostype <type>\"; \
+             use \"list ostypes\" to list all possible values)\n1: Suggested \
+             VM name \"ubuntu_1\"\n    (change with \"--vsys 0 --vmname \
+             <name>\")\n";
+    assert_eq!(Some("ubuntu_1"), parse_imported_vm_name(s));
+    assert_eq!(None, parse_imported_vm_name(""));
+}
+
+#[test]
+fn test_parse_virtual_systems() {
+    let s = "Interpreting /tmp/ubuntu.ova...\nOK.\nDisks:\n  \
+             vmdisk1\t10240\t-1\nVirtual system 0:\n 0: Suggested OS type: \
+             \"Ubuntu_64\"\n    (change with \"--vsys 0 --ostype <type>\"; \
+             use \"list ostypes\" to list all possible values)\n 1: VM name \
+             specified with --vmname: \"web\"\n 2: Number of CPUs: 2\n    \
+             (change with \"--vsys 0 --cpus <n>\")\n 3: Guest memory: 2048 \
+             MB\n    (change with \"--vsys 0 --memory <MB>\")\n 4: Network \
+             adapter: orig NAT, config 3, extra type=NAT\n 5: Hard disk \
+             image: source image=ubuntu-disk001.vmdk, target \
+             path=/vms/web/disk001.vmdk, controller=6;channel=0\n    (change \
+             target path with \"--vsys 0 --unit 5 --disk path\";\n    disable \
+             with \"--vsys 0 --unit 5 --ignore\")\nVirtual system 1:\n 0: \
+             Suggested VM name \"db\"\n";
+    let systems = parse_virtual_systems(s);
+    assert_eq!(systems.len(), 2);
+    assert_eq!(systems[0].index, 0);
+    assert_eq!(systems[0].os_type.as_deref(), Some("Ubuntu_64"));
+    assert_eq!(systems[0].name.as_deref(), Some("web"));
+    assert_eq!(systems[0].cpus, Some(2));
+    assert_eq!(systems[0].memory_mb, Some(2048));
+    assert_eq!(systems[0].units.len(), 6);
+    assert!(!systems[0].units[4].ignorable);
+    assert!(systems[0].units[5].ignorable);
+    assert!(systems[0].units[5]
+        .description
+        .starts_with("Hard disk image"));
+    assert_eq!(systems[1].index, 1);
+    assert_eq!(systems[1].name.as_deref(), Some("db"));
+    assert!(parse_virtual_systems("").is_empty());
+}
+
+#[test]
+fn test_appliance_options() {
+    let opts = ExportOptions {
+        ovf_version: Some(OvfVersion::V2_0),
+        manifest: true,
+        vm_name: Some("web".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        opts.to_args(),
+        ["--ovf20", "--manifest", "--vsys", "0", "--vmname", "web"]
+    );
+    assert!(ExportOptions::default().to_args().is_empty());
+    let mut sys = VirtualSystemOptions::new(1);
+    sys.cpus = Some(4);
+    sys.ignored_units = vec![7];
+    sys.disk_paths = vec![(5, "/vms/d.vmdk".to_string())];
+    let opts = ImportOptions {
+        systems: vec![sys],
+        keep_all_macs: true,
+        import_to_vdi: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        opts.to_args(),
+        [
+            "--vsys",
+            "1",
+            "--cpus",
+            "4",
+            "--vsys",
+            "1",
+            "--unit",
+            "7",
+            "--ignore",
+            "--vsys",
+            "1",
+            "--unit",
+            "5",
+            "--disk",
+            "/vms/d.vmdk",
+            "--options",
+            "keepallmacs,importtovdi"
+        ]
+    );
+}
+
+#[test]
+fn test_progress_parser() {
+    let mut v = vec![];
+    let mut p = ProgressParser::default();
+    let mut f = |x| v.push(x);
+    p.feed(b"0%...10%...2", &mut f);
+    p.feed(b"0%...", &mut f);
+    p.feed(
+        b"\n100%\nMachine has been successfully cloned as \"a1\"",
+        &mut f,
+    );
+    p.feed(b"99999999999999%", &mut f);
+    assert_eq!(v, [0, 10, 20, 100]);
+}
+
+#[test]
+fn test_handle_error() {
+    let err = |s| VBoxManage::handle_error(s);
+    assert_eq!(
+        err("Cannot unregister the machine 'test' while it is \
+             locked\nVBoxManage: error: Details: code \
+             VBOX_E_INVALID_OBJECT_STATE"),
+        VmError::from(ErrorKind::InvalidPowerState(VmPowerState::Running))
+    );
+    assert_eq!(
+        err("Could not find a registered machine named 'test'"),
+        VmError::from(ErrorKind::VmNotFound)
+    );
+    assert_eq!(
+        err("Could not find a registered machine with UUID \
+             {6a8e0c1b-7f3d-4c52-9b1e-2f4a5d6c7e8f}"),
+        VmError::from(ErrorKind::VmNotFound)
+    );
+    assert_eq!(
+        err(
+            "The given password is incorrect\nVBoxManage: error: Details: \
+             code VBOX_E_PASSWORD_INCORRECT"
+        ),
+        VmError::from(ErrorKind::AuthenticationFailed)
+    );
+}
+
+#[test]
+fn test_parse_created_vm_uuid() {
+    let s = "Virtual machine 'test' is created and registered.\nUUID: \
+             6a8e0c1b-7f3d-4c52-9b1e-2f4a5d6c7e8f\nSettings file: \
+             '/home/user/VirtualBox VMs/test/test.vbox'\n";
+    assert_eq!(
+        Some("6a8e0c1b-7f3d-4c52-9b1e-2f4a5d6c7e8f"),
+        parse_created_vm_uuid(s)
+    );
+    assert_eq!(None, parse_created_vm_uuid(""));
+    assert_eq!(
+        Some("1c2d3e4f-5a6b-4c7d-8e9f-0a1b2c3d4e5f"),
+        parse_created_medium_uuid(
+            "0%...50%...100%\nMedium created. UUID: \
+             1c2d3e4f-5a6b-4c7d-8e9f-0a1b2c3d4e5f\n"
+        )
+    );
+    assert_eq!(None, parse_created_medium_uuid(""));
+    let xml = r#"<?xml version="1.0"?>
+<VirtualBox xmlns="http://www.virtualbox.org/" version="1.16-linux">
+  <Machine uuid="{6a8e0c1b-7f3d-4c52-9b1e-2f4a5d6c7e8f}" name="test" OSType="Ubuntu_64">
+    <Snapshot uuid="{00000000-0000-0000-0000-000000000000}" name="s"/>
+  </Machine>
+</VirtualBox>"#;
+    assert_eq!(
+        Some("6a8e0c1b-7f3d-4c52-9b1e-2f4a5d6c7e8f"),
+        parse_machine_uuid(xml)
+    );
+    assert_eq!(None, parse_machine_uuid("<VirtualBox/>"));
+}
+
+#[test]
+fn test_to_power_state() {
+    assert_eq!(VmPowerState::Running, to_power_state("running"));
+    assert_eq!(VmPowerState::Stopped, to_power_state("aborted"));
+    assert_eq!(VmPowerState::Suspended, to_power_state("saved"));
+    assert_eq!(VmPowerState::Paused, to_power_state("paused"));
+    assert_eq!(VmPowerState::Unknown, to_power_state("stopping"));
+}
+
+#[test]
+fn test_parse_guest_processes() {
+    let s = "Session ID: 1   Name: hvctrl  User: vbox  Status: \
+             started\n\tProcess #0    PID=1234   Status=started            \
+             Name=/bin/sleep 100\n\n";
+    assert_eq!(
+        parse_guest_processes(s),
+        [ProcInfo {
+            pid: 1234,
+            owner: String::new(),
+            cmd: "/bin/sleep 100".to_string(),
+        }]
+    );
+    assert!(parse_guest_processes("").is_empty());
+}
+
+#[test]
+fn test_parse_stat() {
+    assert_eq!(
+        parse_stat("Element \"/etc\" found: Is a directory\n"),
+        Some(GuestFileInfo {
+            file_type: GuestFileType::Directory,
+            size: None,
+        })
+    );
+    assert_eq!(
+        parse_stat(
+            "  File: '/etc/hosts'\n  Size: 221  Alloc: 4096\n  Type: regular \
+             file\n"
+        ),
+        Some(GuestFileInfo {
+            file_type: GuestFileType::File,
+            size: Some(221),
+        })
+    );
+    assert_eq!(parse_stat(""), None);
+}
+
+#[test]
+fn test_parse_attached_pci() {
+    let s = "ostype=\"Ubuntu \
+             (64-bit)\"\nAttachedHostPCI=02:00.0,01:05.0\nAttachedHostPCI=03:\
+             00.1,01:06.0\n";
+    let v = parse_attached_pci(s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].host_location.as_deref(), Some("02:00.0"));
+    assert_eq!(v[1].guest_location.as_deref(), Some("01:06.0"));
+    assert!(parse_attached_pci("ostype=\"Windows\"\n").is_empty());
+}
+
+#[test]
+fn test_find_dvd_drive() {
+    let s = [
+        r#"storagecontrollername0="SATA Controller""#,
+        r#""SATA Controller-0-0"="/vms/a/a.vdi""#,
+        r#""SATA Controller-1-0"="/iso/ubuntu.iso""#,
+        r#""SATA Controller-IsEjected-1-0"="off""#,
+    ]
+    .join("\n");
+    assert_eq!(
+        find_dvd_drive(&s),
+        Some((
+            "SATA Controller".to_string(),
+            "1".to_string(),
+            "0".to_string()
+        ))
+    );
+    let s = r#""IDE-0-0"="/vms/a/a.vdi"
+"IDE-1-0"="emptydrive""#;
+    assert_eq!(
+        find_dvd_drive(s),
+        Some(("IDE".to_string(), "1".to_string(), "0".to_string()))
+    );
+    assert_eq!(find_dvd_drive(r#""IDE-0-0"="/vms/a/a.vdi""#), None);
+}
+
+#[test]
+fn test_time_offset_ms() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let day = Duration::from_secs(86400);
+    assert_eq!(time_offset_ms(now, now), 0);
+    assert_eq!(time_offset_ms(now + day, now), 86_400_000);
+    assert_eq!(time_offset_ms(now - day, now), -86_400_000);
+}
+
+#[test]
+fn test_parse_host_properties() {
+    let s = [
+        "ID:          Other",
+        "Description: Other/Unknown",
+        "Family ID:   Other",
+        "Family Desc: Other",
+        "64 bit:      false",
+        "",
+        "ID:          Ubuntu_64",
+        "Description: Ubuntu (64-bit)",
+        "Family ID:   Linux",
+        "Family Desc: Linux",
+        "64 bit:      true",
+    ]
+    .join("\n");
+    let v = parse_os_types(&s);
+    assert_eq!(v.len(), 2);
+    assert!(!v[0].is_64bit);
+    assert_eq!(
+        v[1],
+        OsType {
+            id: "Ubuntu_64".to_string(),
+            description: Some("Ubuntu (64-bit)".to_string()),
+            family_id: Some("Linux".to_string()),
+            family_description: Some("Linux".to_string()),
+            is_64bit: true,
+        }
+    );
+
+    let s = [
+        "Host Information:",
+        "",
+        "Host time: 2023-01-01T00:00:00.000000000Z",
+        "Processor online count: 8",
+        "Processor count: 8",
+        "Processor online core count: 4",
+        "Processor core count: 4",
+        "Processor supports HW virtualization: yes",
+        "Processor supports nested HW virtualization: no",
+        "Processor#0 speed: 2800 MHz",
+        "Processor#0 description: Intel(R) Core(TM) i7 CPU",
+        "Memory size: 16384 MByte",
+        "Memory available: 8000 MByte",
+        "Operating system: Linux",
+        "Operating system version: 5.15.0-91-generic",
+    ]
+    .join("\n");
+    let info = parse_host_info(&s);
+    assert_eq!(info.cpu_count, Some(8));
+    assert_eq!(info.cpu_core_count, Some(4));
+    assert_eq!(
+        info.cpu_description.as_deref(),
+        Some("Intel(R) Core(TM) i7 CPU")
+    );
+    assert_eq!(info.memory_mb, Some(16384));
+    assert_eq!(info.memory_available_mb, Some(8000));
+    assert!(info.hw_virtualization);
+    assert!(!info.nested_hw_virtualization);
+    assert_eq!(info.os.as_deref(), Some("Linux"));
+    assert_eq!(info.os_version.as_deref(), Some("5.15.0-91-generic"));
+
+    let s = [
+        "API version:                     7_0",
+        "Minimum guest RAM size:          4 Megabytes",
+        "Maximum guest RAM size:          2097152 Megabytes",
+        "Minimum video RAM size:          0 Megabytes",
+        "Maximum video RAM size:          256 Megabytes",
+        "Maximum guest monitor count:     64",
+        "Minimum guest CPU count:         1",
+        "Maximum guest CPU count:         64",
+        "Virtual disk limit (info):       2199022206976 Bytes",
+        "Default machine folder:          C:\\Users\\user\\VirtualBox VMs",
+        "Default hard disk format:        VDI",
+        "Default Guest Additions ISO:     ",
+        "Autostart database path:         /etc/vbox",
+    ]
+    .join("\n");
+    let props = parse_system_properties(&s);
+    assert_eq!(props.api_version.as_deref(), Some("7_0"));
+    assert_eq!(props.min_guest_memory_mb, Some(4));
+    assert_eq!(props.max_guest_memory_mb, Some(2097152));
+    assert_eq!(props.max_guest_vram_mb, Some(256));
+    assert_eq!(props.max_guest_cpus, Some(64));
+    assert_eq!(props.max_guest_monitors, Some(64));
+    assert_eq!(props.max_disk_size_bytes, Some(2199022206976));
+    assert_eq!(
+        props.default_machine_folder.as_deref(),
+        Some("C:\\Users\\user\\VirtualBox VMs")
+    );
+    assert_eq!(props.default_hard_disk_format.as_deref(), Some("VDI"));
+    assert_eq!(props.guest_additions_iso, None);
+    assert_eq!(props.autostart_db_path.as_deref(), Some("/etc/vbox"));
+}
+
+#[test]
+fn test_parse_cloud_profiles() {
+    let s = [
+        "Name:          default",
+        "Provider GUID: 5ab4b8ae-d8c6-4d48-a5de-1b7b1ab3e5d8",
+        "Property:      region = us-ashburn-1",
+        "Property:      tenancy = ocid1.tenancy.oc1..aaaa",
+        "",
+        "Name:          lab",
+        "Provider GUID: 5ab4b8ae-d8c6-4d48-a5de-1b7b1ab3e5d8",
+        "",
+    ]
+    .join("\n");
+    let v = parse_cloud_profiles(&s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].name, "default");
+    assert_eq!(
+        v[0].provider_id.as_deref(),
+        Some("5ab4b8ae-d8c6-4d48-a5de-1b7b1ab3e5d8")
+    );
+    assert_eq!(v[0].properties.len(), 2);
+    assert_eq!(
+        v[0].properties[0],
+        ("region".to_string(), "us-ashburn-1".to_string())
+    );
+    assert_eq!(v[1].name, "lab");
+    assert!(v[1].properties.is_empty());
+    assert!(parse_cloud_profiles("").is_empty());
+}
+
+#[test]
+fn test_parse_snapshot_list() {
+    let s = r#"SnapshotName="base"
+SnapshotUUID="11111111-1111-1111-1111-111111111111"
+SnapshotDescription="first line
+second line"
+SnapshotName-1="child"
+SnapshotUUID-1="22222222-2222-2222-2222-222222222222"
+SnapshotDescription-1=""
+SnapshotName-1-1="grandchild"
+SnapshotUUID-1-1="33333333-3333-3333-3333-333333333333"
+SnapshotDescription-1-1="x"
+SnapshotName-2="sibling"
+SnapshotUUID-2="44444444-4444-4444-4444-444444444444"
+SnapshotDescription-2=""
+CurrentSnapshotName="grandchild"
+CurrentSnapshotUUID="33333333-3333-3333-3333-333333333333"
+CurrentSnapshotNode="SnapshotName-1-1""#;
+    let (v, current_id) = parse_snapshot_list(s).unwrap();
+    assert_eq!(v.len(), 4);
+    assert_eq!(
+        v[0].detail.as_deref(),
+        Some(format!("first line{}second line", LINE_FEED).as_str())
+    );
+    assert_eq!(v[0].parent_id, None);
+    assert_eq!(v[1].parent_id, v[0].id);
+    assert_eq!(v[2].parent_id, v[1].id);
+    assert_eq!(v[2].detail.as_deref(), Some("x"));
+    assert_eq!(v[3].parent_id, v[0].id);
+    assert_eq!(v[0].is_current, Some(false));
+    assert_eq!(v[2].is_current, Some(true));
+    let mut v = v;
+    apply_snapshot_times(
+        &mut v,
+        r#"<Snapshots>
+  <Snapshot uuid="{11111111-1111-1111-1111-111111111111}" name="base" timeStamp="2021-01-02T03:04:05Z">
+    <Snapshot uuid="{22222222-2222-2222-2222-222222222222}" name="child" timeStamp="2021-01-03T03:04:05Z" stateFile="Snapshots/{22222222-2222-2222-2222-222222222222}.sav">
+    </Snapshot>
+  </Snapshot>
+</Snapshots>"#,
+    );
+    assert_eq!(v[0].created_at, Some(1609556645));
+    assert_eq!(v[0].online, Some(false));
+    assert_eq!(v[1].created_at, Some(1609556645 + 86400));
+    assert_eq!(v[1].online, Some(true));
+    assert_eq!(v[3].created_at, None);
+    assert_eq!(
+        find_snapshot(v.clone(), "{22222222-2222-2222-2222-222222222222}")
+            .unwrap()
+            .name
+            .as_deref(),
+        Some("child")
+    );
+    assert_eq!(find_snapshot(v.clone(), "grandchild").unwrap().id, v[2].id);
+    assert_eq!(
+        find_snapshot(v.clone(), "none"),
+        vmerr!(ErrorKind::SnapshotNotFound)
+    );
+    let mut dup = v.clone();
+    dup.push(Snapshot {
+        id: Some("55555555-5555-5555-5555-555555555555".to_string()),
+        ..v[1].clone()
+    });
+    assert!(find_snapshot(dup, "child").is_err());
+    let tree = SnapshotTree::from_snapshots(v, current_id.as_deref());
+    assert_eq!(tree.roots.len(), 1);
+    assert_eq!(tree.roots[0].children.len(), 2);
+    assert_eq!(tree.current.unwrap().name.as_deref(), Some("grandchild"));
+    let (v, current_id) =
+        parse_snapshot_list("This machine does not have any snapshots\n")
+            .unwrap();
+    assert!(v.is_empty());
+    assert_eq!(current_id, None);
+}
+
+#[test]
+fn test_parse_guest_ip_addresses() {
+    let props: Vec<(String, String)> = [
+        ("/VirtualBox/GuestInfo/Net/Count", "2"),
+        ("/VirtualBox/GuestInfo/Net/0/V4/IP", "10.0.2.15"),
+        (
+            "/VirtualBox/GuestInfo/Net/0/V6/IP",
+            "fe80::a00:27ff:fe4e:66a1",
+        ),
+        ("/VirtualBox/GuestInfo/Net/0/MAC", "0800274E66A1"),
+        ("/VirtualBox/GuestInfo/Net/1/V4/IP", "192.168.56.101"),
+    ]
+    .iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+    let v = parse_guest_ip_addresses(&props);
+    assert_eq!(v.len(), 3);
+    assert_eq!(v[0].interface.as_deref(), Some("0800274E66A1"));
+    assert_eq!(
+        v[0].address,
+        "10.0.2.15".parse::<std::net::IpAddr>().unwrap()
+    );
+    assert!(v[1].address.is_ipv6());
+    assert_eq!(v[2].interface, None);
+}
+
+#[test]
+fn test_parse_guest_properties() {
+    let s = [
+        "Name: /VirtualBox/GuestInfo/OS/LoggedInUsersList, value: alice, \
+         timestamp: 1600000000000000000, flags: TRANSIENT, TRANSRESET",
+        "Name: /VirtualBox/GuestInfo/User/alice/UsageState, value: Idle, \
+         timestamp: 1600000000000000000, flags: TRANSIENT, TRANSRESET",
+    ]
+    .join("\n");
+    let v = parse_guest_properties(&s);
+    assert_eq!(
+        v[0],
+        (
+            "/VirtualBox/GuestInfo/OS/LoggedInUsersList".to_string(),
+            "alice".to_string()
+        )
+    );
+    assert!(is_idle(&v));
+
+    let s = [
+        "/VirtualBox/GuestInfo/User/alice/UsageState = 'Idle' @ \
+         2023-01-01T00:00:00.000000000Z [TRANSIENT, TRANSRESET]",
+        "/VirtualBox/GuestInfo/User/bob/UsageState = 'InUse' @ \
+         2023-01-01T00:00:00.000000000Z [TRANSIENT, TRANSRESET]",
+    ]
+    .join("\n");
+    let v = parse_guest_properties(&s);
+    assert_eq!(v[1].1, "InUse");
+    assert!(!is_idle(&v));
+    assert!(!is_idle(&[]));
+}
+
+#[test]
+fn test_parse_guest_property_entries() {
+    use GuestPropertyFlag::*;
+    let s = "Name: /VirtualBox/GuestInfo/Net/0/V4/IP, value: 10.0.2.15, \
+             timestamp: 1600000000123456789, flags: TRANSIENT, \
+             TRANSRESET\nName: /hvctrl/ready, value: a, b, timestamp: \
+             1600000000000000000, flags: ";
+    let v = parse_guest_property_entries(s);
+    assert_eq!(
+        v[0],
+        GuestProperty {
+            name: "/VirtualBox/GuestInfo/Net/0/V4/IP".to_string(),
+            value: "10.0.2.15".to_string(),
+            timestamp: Some(1600000000),
+            flags: vec![Transient, TransReset],
+        }
+    );
+    assert_eq!(v[1].value, "a, b");
+    assert!(v[1].flags.is_empty());
+
+    let s = "/VirtualBox/GuestInfo/Net/0/V4/IP = '10.0.2.15' @ \
+             2020-09-13T12:26:40.123456789Z [TRANSIENT, \
+             TRANSRESET]\n/hvctrl/ready = 'it's' @ \
+             2020-09-13T12:26:40.000000000Z\n/hvctrl/plain = 'x'";
+    let v = parse_guest_property_entries(s);
+    assert_eq!(v.len(), 3);
+    assert_eq!(v[0].timestamp, Some(1600000000));
+    assert_eq!(v[0].flags, [Transient, TransReset]);
+    assert_eq!(v[1].value, "it's");
+    assert!(v[1].flags.is_empty());
+    assert_eq!(v[2].timestamp, None);
+
+    // `guestproperty wait`
+    let v = parse_guest_property_entries(
+        "Name: /hvctrl/ready, value: 1, flags: RDONLYGUEST",
+    );
+    assert_eq!(v[0].value, "1");
+    assert_eq!(v[0].timestamp, None);
+    assert_eq!(v[0].flags, [ReadOnlyGuest]);
+    assert!(parse_guest_property_entries(
+        "Time out or interruption while waiting for a notification."
+    )
+    .is_empty());
+
+    let s =
+        "Value: 10.0.2.15\nTimestamp: 1600000000000000000\nFlags: TRANSIENT";
+    let p = parse_guest_property_verbose("/a", s).unwrap();
+    assert_eq!(p.name, "/a");
+    assert_eq!(p.value, "10.0.2.15");
+    assert_eq!(p.timestamp, Some(1600000000));
+    assert_eq!(p.flags, [Transient]);
+    assert_eq!(parse_guest_property_verbose("/a", "No value set!"), None);
+}
+
+#[test]
+fn test_vbox_version() {
+    assert_eq!(
+        VBoxVersion::parse("7.0.14r161095"),
+        Some(VBoxVersion::new(7, 0, 14))
+    );
+    assert_eq!(
+        VBoxVersion::parse("6.1.50_Ubuntur161033"),
+        Some(VBoxVersion::new(6, 1, 50))
+    );
+    assert_eq!(
+        VBoxVersion::parse("WARNING: The vboxdrv kernel module"),
+        None
+    );
+    assert_eq!(VBoxVersion::parse("7.0"), None);
+    assert!(VBoxVersion::new(7, 0, 0) > VBoxVersion::new(6, 1, 50));
+    assert_eq!(VBoxVersion::new(7, 1, 2).to_string(), "7.1.2");
+
+    assert_eq!(modifyvm_option_v7("--ostype"), "--os-type");
+    assert_eq!(modifyvm_option_v7("--natpf1"), "--nat-pf1");
+    assert_eq!(modifyvm_option_v7("--uartmode2"), "--uart-mode2");
+    assert_eq!(modifyvm_option_v7("--boot1"), "--boot1");
+    assert_eq!(modifyvm_option_v7("--memory"), "--memory");
+    assert_eq!(modifyvm_option_v7("ostype"), "ostype");
+    assert_eq!(modifyvm_option_v7("--nictype1"), "--nic-type1");
+    assert_eq!(modifyvm_option_v7("--clipboard"), "--clipboard-mode");
+
+    let args = [
+        "modifyvm",
+        "--ostype",
+        "--description",
+        "--ostype",
+        "--natpf1",
+        "delete",
+        "--natpf1",
+        "--cableconnected1=on",
+        "--cpuidremoveall",
+        "--cpuidset",
+        "1",
+        "2",
+        "3",
+        "4",
+        "5",
+        "--memory",
+        "512",
+        "--draganddrop",
+        "bidirectional",
+    ]
+    .map(OsStr::new);
+    assert_eq!(
+        modifyvm_args_v7(&args),
+        [
+            "modifyvm",
+            "--ostype",
+            "--description",
+            "--ostype",
+            "--nat-pf1",
+            "delete",
+            "--natpf1",
+            "--cable-connected1=on",
+            "--cpuid-remove-all",
+            "--cpuid-set",
+            "1",
+            "2",
+            "3",
+            "4",
+            "5",
+            "--memory",
+            "512",
+            "--drag-and-drop",
+            "bidirectional",
+        ]
+    );
+}
+
+#[test]
+fn test_parse_vm_list() {
+    let s = [
+        r#""My VM" {aaaaaaaa-0000-0000-0000-000000000000}"#,
+        r#""a "b" c" {bbbbbbbb-0000-0000-0000-000000000000}"#,
+    ]
+    .join("\n");
+    let vms = parse_vm_list(&s);
+    assert_eq!(vms.len(), 2);
+    assert_eq!(
+        vms[0].id.as_deref(),
+        Some("{aaaaaaaa-0000-0000-0000-000000000000}")
+    );
+    assert_eq!(vms[0].name.as_deref(), Some("My VM"));
+    assert_eq!(vms[1].name.as_deref(), Some(r#"a "b" c"#));
+    assert!(parse_vm_list("").is_empty());
+    assert!(contains_vm(&vms, "My VM"));
+    assert!(contains_vm(&vms, "BBBBBBBB-0000-0000-0000-000000000000"));
+    assert!(contains_vm(&vms, "{aaaaaaaa-0000-0000-0000-000000000000}"));
+    assert!(!contains_vm(&vms, "my vm"));
+}
+
+#[test]
+fn test_parse_long_vm_list() {
+    let s = [
+        "Name:                        web",
+        "Groups:                      /lab,/lab/web",
+        "Guest OS:                    Ubuntu (64-bit)",
+        "UUID:                        aaaaaaaa-0000-0000-0000-000000000000",
+        "Config file:                 /vms/web/web.vbox",
+        "",
+        "Shared folders:",
+        "",
+        "Name: 'share', Host path: '/srv' (machine mapping), writable",
+        "",
+        "Snapshots:",
+        "",
+        "   Name: snap (UUID: cccccccc-0000-0000-0000-000000000000)",
+        "",
+        "Name:                        db",
+        "Groups:                      /",
+        "UUID:                        bbbbbbbb-0000-0000-0000-000000000000",
+    ]
+    .join("\n");
+    let vms = parse_long_vm_list(&s);
+    assert_eq!(vms.len(), 2);
+    assert_eq!(
+        vms[0].0.id.as_deref(),
+        Some("{aaaaaaaa-0000-0000-0000-000000000000}")
+    );
+    assert_eq!(vms[0].0.name.as_deref(), Some("web"));
+    assert_eq!(vms[0].0.path.as_deref(), Some("/vms/web/web.vbox"));
+    assert_eq!(vms[0].1, ["/lab", "/lab/web"]);
+    assert_eq!(vms[1].0.name.as_deref(), Some("db"));
+    assert_eq!(vms[1].1, ["/"]);
+    assert_eq!(split_groups(""), ["/"]);
+    assert!(parse_long_vm_list("").is_empty());
+}
+
+#[test]
+fn test_parse_vm() {
+    let s = [
+        r#"name="My VM""#,
+        r#"ostype="Windows 10 (64-bit)""#,
+        r#"UUID="aaaaaaaa-0000-0000-0000-000000000000""#,
+        r#"CfgFile="C:\\VMs\\My VM\\My VM.vbox""#,
+        r#"name="other""#,
+    ]
+    .join("\n");
+    let vm = VBoxManage::parse_vm(&s);
+    assert_eq!(
+        vm.id.as_deref(),
+        Some("{aaaaaaaa-0000-0000-0000-000000000000}")
+    );
+    assert_eq!(vm.name.as_deref(), Some("My VM"));
+    assert_eq!(vm.path.as_deref(), Some(r"C:\VMs\My VM\My VM.vbox"));
+}
+
+#[test]
+fn test_parse_vm_info() {
+    let s = [
+        r#"name="My VM""#,
+        r#"ostype="Windows10_64""#,
+        r#"UUID="aaaaaaaa-0000-0000-0000-000000000000""#,
+        r#"CfgFile="C:\\VMs\\My VM\\My VM.vbox""#,
+        r#"memory=4096"#,
+        r#"cpus=2"#,
+        r#"firmware="EFI64""#,
+        r#"VMState="running""#,
+        r#"SharedFolderNameMachineMapping1="other""#,
+        r#"GuestAdditionsVersion="6.1.30 r148432""#,
+    ]
+    .join("\n");
+    let info = VBoxManage::parse_vm_info(&s);
+    assert_eq!(
+        info,
+        VmInfo {
+            name: Some("My VM".to_string()),
+            guest_os: Some("Windows10_64".to_string()),
+            memory_mb: Some(4096),
+            cpu_count: Some(2),
+            firmware: Some(Firmware::Efi),
+            generation: None,
+            power_state: Some(VmPowerState::Running),
+            config_path: Some(r"C:\VMs\My VM\My VM.vbox".to_string()),
+            tools_version: Some("6.1.30 r148432".to_string()),
+        }
+    );
+    assert_eq!(VBoxManage::parse_vm_info(""), VmInfo::default());
+}
+
+#[test]
+fn test_vm_base_disks() {
+    let s = "UUID:           11111111-0000-0000-0000-000000000000\nParent \
+             UUID:    base\nLocation:       /vms/vm/vm.vdi\nIn use by VMs:  \
+             vm (UUID: aaaaaaaa-0000-0000-0000-000000000000)\n\nUUID:           \
+             22222222-0000-0000-0000-000000000000\nParent UUID:    \
+             11111111-0000-0000-0000-000000000000\nLocation:       \
+             /vms/vm/Snapshots/diff.vdi\nIn use by VMs:  vm (UUID: \
+             aaaaaaaa-0000-0000-0000-000000000000)\n\nUUID:           \
+             33333333-0000-0000-0000-000000000000\nParent UUID:    \
+             base\nLocation:       /vms/other/other.vdi\nIn use by VMs:  \
+             other (UUID: bbbbbbbb-0000-0000-0000-000000000000)\n";
+    let hdds = parse_list_blocks(s);
+    assert_eq!(
+        vec!["/vms/vm/vm.vdi"],
+        vm_base_disks(&hdds, "aaaaaaaa-0000-0000-0000-000000000000")
+    );
+    assert!(vm_base_disks(&hdds, "cccccccc").is_empty());
+}
+
+#[test]
+fn test_parse_media() {
+    let s = [
+        "UUID:           11111111-0000-0000-0000-000000000000",
+        "Parent UUID:    base",
+        "State:          created",
+        "Type:           normal (base)",
+        "Location:       /vms/vm/vm.vdi",
+        "Storage format: VDI",
+        "Capacity:       20480 MBytes",
+        "Encryption:     disabled",
+        "In use by VMs:  vm (UUID: aaaaaaaa-0000-0000-0000-000000000000) \
+         [snap1 (UUID: bbbbbbbb-0000-0000-0000-000000000000)], other (UUID: \
+         cccccccc-0000-0000-0000-000000000000)",
+        "",
+        "UUID:           22222222-0000-0000-0000-000000000000",
+        "Parent UUID:    11111111-0000-0000-0000-000000000000",
+        "State:          inaccessible",
+        "Type:           normal (differencing)",
+        "Location:       /vms/vm/Snapshots/diff.vdi",
+        "Encryption:     enabled",
+    ]
+    .join("\n");
+    let v = parse_media(&s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(
+        v[0],
+        Medium {
+            id: "11111111-0000-0000-0000-000000000000".to_string(),
+            parent_id: None,
+            state: Some("created".to_string()),
+            ty: Some(MediumType::Normal),
+            location: Some("/vms/vm/vm.vdi".to_string()),
+            format: Some("VDI".to_string()),
+            capacity_mb: Some(20480),
+            encrypted: false,
+            vm_ids: vec![
+                "aaaaaaaa-0000-0000-0000-000000000000".to_string(),
+                "cccccccc-0000-0000-0000-000000000000".to_string()
+            ],
+        }
+    );
+    assert_eq!(
+        v[1].parent_id.as_deref(),
+        Some("11111111-0000-0000-0000-000000000000")
+    );
+    assert!(v[1].encrypted);
+    assert!(v[1].vm_ids.is_empty());
+
+    let options = CreateMediumOptions {
+        size_mb: Some(1024),
+        fixed: true,
+        ..Default::default()
+    };
+    assert_eq!(options.to_args(), ["--size", "1024", "--variant", "Fixed"]);
+}
+
+#[test]
+fn test_snapshot_disks() {
+    let s = "UUID:           11111111-0000-0000-0000-000000000000\nParent \
+             UUID:    base\nLocation:       /vms/vm/vm.vdi\nIn use by VMs:  \
+             vm (UUID: aaaaaaaa-0000-0000-0000-000000000000) [snap1 (UUID: \
+             bbbbbbbb-0000-0000-0000-000000000000)]\n\nUUID:           \
+             22222222-0000-0000-0000-000000000000\nParent UUID:    \
+             11111111-0000-0000-0000-000000000000\nLocation:       \
+             /vms/vm/Snapshots/{22222222-0000-0000-0000-000000000000}.vdi\nIn \
+             use by VMs:  vm (UUID: aaaaaaaa-0000-0000-0000-000000000000) \
+             [snap2 (UUID: cccccccc-0000-0000-0000-000000000000)]\n";
+    let hdds = parse_list_blocks(s);
+    assert!(
+        snapshot_disks(&hdds, "bbbbbbbb-0000-0000-0000-000000000000")
+            .is_empty()
+    );
+    assert_eq!(
+        vec!["/vms/vm/Snapshots/{22222222-0000-0000-0000-000000000000}.vdi"],
+        snapshot_disks(&hdds, "cccccccc-0000-0000-0000-000000000000")
+    );
+}
+
+#[test]
+fn test_parse_list_blocks() {
+    let s = "Name:            eth0\nGUID:            \
+             00000000-0000-0000-0000-000000000001\nDHCP:            \
+             Disabled\nIPAddress:       192.168.1.2\n\nName:            \
+             eth1\nDHCP:            Enabled\n";
+    let v = parse_list_blocks(s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0]["Name"], "eth0");
+    assert_eq!(v[0]["IPAddress"], "192.168.1.2");
+    assert_eq!(v[1]["Name"], "eth1");
+    assert_eq!(v[1]["DHCP"], "Enabled");
+    assert!(parse_list_blocks("").is_empty());
+}
+
+#[test]
+fn test_parse_usb_hosts() {
+    let s = r"Host USB Devices:
+
+UUID:               3ae8b5a4-2b1c-4f6e-9b1a-9f0c2b6f1d2e
+VendorId:           0x046d (046D)
+ProductId:          0xc52b (C52B)
+Revision:           18.1 (1801)
+Port:               1
+USB version/speed:  0/Full
+Manufacturer:       Logitech
+Product:            USB Receiver
+Address:            {36fc9e60-c465-11cf-8056-444553540000}\0011
+Current State:      Busy
+
+UUID:               8d1f0a2c-5e3b-4c7d-a1f2-3b4c5d6e7f80
+VendorId:           0x0781 (0781)
+ProductId:          0x5581 (5581)
+Revision:           1.0 (0100)
+Port:               2
+Product:            Ultra
+SerialNumber:       4C530001
+Current State:      Available
+";
+    let v = parse_usb_hosts(s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].id, "3ae8b5a4-2b1c-4f6e-9b1a-9f0c2b6f1d2e");
+    assert_eq!(
+        (v[0].vendor_id, v[0].product_id),
+        (Some(0x046d), Some(0xc52b))
+    );
+    assert_eq!(v[0].manufacturer.as_deref(), Some("Logitech"));
+    assert_eq!(v[1].manufacturer, None);
+    assert_eq!(v[1].serial_number.as_deref(), Some("4C530001"));
+
+    let filter = UsbFilter::from_device("stick", &v[1]);
+    assert_eq!(
+        filter.to_args(),
+        [
+            "--name",
+            "stick",
+            "--active",
+            "yes",
+            "--vendorid",
+            "0781",
+            "--productid",
+            "5581",
+            "--serialnumber",
+            "4C530001"
+        ]
+    );
+}
+
+#[test]
+fn test_vrde() {
+    let s = r#"vrde="on"
+vrdeport=5001
+vrdeports="5000-5050"
+vrdeaddress="127.0.0.1"
+vrdeauthtype="external"
+vrdemulticon="off"
+"#;
+    assert_eq!(
+        parse_vrde_info(&VBoxManage::parse_info(s, None)),
+        VrdeInfo {
+            enabled: true,
+            ports: Some("5000-5050".to_string()),
+            address: Some("127.0.0.1".to_string()),
+            auth_type: Some(VrdeAuthType::External),
+            multi_connection: false,
+            active_port: Some(5001),
+        }
+    );
+    let info = parse_vrde_info(&VBoxManage::parse_info(
+        "vrde=\"on\"\nvrdeport=-1\nvrdeports=\"3389\"",
+        None,
+    ));
+    assert_eq!(info.active_port, None);
+    assert_eq!(
+        parse_vrde_info(&VBoxManage::parse_info("vrde=\"off\"", None)),
+        VrdeInfo::default()
+    );
+
+    let settings = VrdeSettings {
+        enabled: Some(true),
+        ports: Some("5000-5050".to_string()),
+        auth_type: Some(VrdeAuthType::Null),
+        ..Default::default()
+    };
+    assert_eq!(
+        settings.to_args(),
+        [
+            "--vrde",
+            "on",
+            "--vrdeport",
+            "5000-5050",
+            "--vrdeauthtype",
+            "null"
+        ]
+    );
+}
+
+#[test]
+fn test_autostart_settings() {
+    assert!(AutostartSettings::default().to_args().is_empty());
+    let settings = AutostartSettings {
+        enabled: Some(true),
+        delay: Some(Duration::from_secs(30)),
+        stop_type: Some(AutostopType::AcpiShutdown),
+    };
+    assert_eq!(
+        settings.to_args(),
+        [
+            "--autostart-enabled",
+            "on",
+            "--autostart-delay",
+            "30",
+            "--autostop-type",
+            "acpishutdown"
+        ]
+    );
+}
+
+#[test]
+fn test_bandwidth_groups() {
+    assert_eq!(bandwidth_limit_arg(0), "0");
+    assert_eq!(bandwidth_limit_arg(1), "1K");
+    assert_eq!(bandwidth_limit_arg(1536), "2K");
+    assert_eq!(bandwidth_limit_arg(20 * 1024 * 1024), "20M");
+    assert_eq!(bandwidth_limit_arg(2 * 1024 * 1024 * 1024), "2G");
+    let s = r#"name="Limit"
+type="Disk"
+maxbytespersec=20971520
+name="Net"
+type="Network"
+maxbytespersec=0
+"#;
+    assert_eq!(
+        parse_bandwidth_groups(s),
+        [
+            BandwidthGroup {
+                name: "Limit".to_string(),
+                ty: BandwidthGroupType::Disk,
+                max_bytes_per_sec: 20 * 1024 * 1024,
+            },
+            BandwidthGroup {
+                name: "Net".to_string(),
+                ty: BandwidthGroupType::Network,
+                max_bytes_per_sec: 0,
+            },
+        ]
+    );
+    assert!(parse_bandwidth_groups("").is_empty());
+}
+
+#[test]
+fn test_parse_usb_filters() {
+    let s = r#"USBFilterActive1="on"
+USBFilterName1="Logitech USB Receiver"
+USBFilterVendorId1="046D"
+USBFilterProductId1="C52B"
+USBFilterRevision1=""
+USBFilterManufacturer1="Logitech"
+USBFilterProduct1=""
+USBFilterRemote1="no"
+USBFilterSerialNumber1=""
+USBFilterActive2="off"
+USBFilterName2="any"
+"#;
+    let v = parse_usb_filters(&VBoxManage::parse_info(s, None));
+    assert_eq!(v.len(), 2);
+    assert_eq!(
+        v[0],
+        UsbFilter {
+            vendor_id: Some("046D".to_string()),
+            product_id: Some("C52B".to_string()),
+            manufacturer: Some("Logitech".to_string()),
+            remote: Some(false),
+            ..UsbFilter::new("Logitech USB Receiver")
+        }
+    );
+    assert_eq!(v[1].name, "any");
+    assert!(!v[1].active);
+    assert_eq!(v[1].vendor_id, None);
+
+    let s = "Global USB Device Filters:
+
+Index:            0
+Active:           yes
+Name:             ignore receiver
+Action:           Ignore
+VendorId:         046D
+ProductId:
+Revision:
+Manufacturer:
+Product:
+Serial Number:
+";
+    let v = parse_global_usb_filters(s);
+    assert_eq!(v.len(), 1);
+    assert_eq!(
+        v[0],
+        UsbFilter {
+            vendor_id: Some("046D".to_string()),
+            action: Some(UsbFilterAction::Ignore),
+            ..UsbFilter::new("ignore receiver")
+        }
+    );
+    assert!(
+        parse_global_usb_filters("Global USB Device Filters:\n\n<none>")
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_port_forwards() {
+    let s = r#"nic1="nat"
+nic2="nat"
+natnet1="nat"
+mtu="0"
+Forwarding(0)="ssh,tcp,,2222,,22"
+Forwarding(1)="dns,udp,127.0.0.1,5353,10.0.2.15,53"
+natnet2="nat"
+Forwarding(0)="http,tcp,,8080,,80"
+"#;
+    let v = parse_port_forwards(s, 1);
+    assert_eq!(v.len(), 2);
+    assert_eq!(
+        v[0],
+        PortForward {
+            name: Some("ssh".to_string()),
+            ..PortForward::new(Protocol::Tcp, 2222, 22)
+        }
+    );
+    assert_eq!(v[1].host_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(v[1].guest_ip.as_deref(), Some("10.0.2.15"));
+    assert_eq!(to_natpf_rule(&v[1]), "dns,udp,127.0.0.1,5353,10.0.2.15,53");
+    assert_eq!(parse_port_forwards(s, 2)[0].host_port, 8080);
+    assert!(parse_port_forwards(s, 3).is_empty());
+    assert_eq!(
+        to_natpf_rule(&PortForward::new(Protocol::Udp, 5000, 50)),
+        "udp_5000,udp,,5000,,50"
+    );
+}
+
+#[test]
+fn test_dhcp_server() {
+    let s = [
+        "NetworkName:    HostInterfaceNetworking-vboxnet0",
+        "Dhcpd IP:       192.168.56.100",
+        "LowerIPAddress: 192.168.56.101",
+        "UpperIPAddress: 192.168.56.254",
+        "NetworkMask:    255.255.255.0",
+        "Enabled:        Yes",
+        "Global Configuration:",
+        "    minLeaseTime:     default",
+        "",
+        "NetworkName:    NatNetwork",
+        "Dhcpd IP:       10.0.2.3",
+        "Enabled:        No",
+    ]
+    .join("\n");
+    let v = parse_dhcp_servers(&s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(
+        v[0],
+        DhcpServer {
+            network_name: "HostInterfaceNetworking-vboxnet0".to_string(),
+            ip: Some("192.168.56.100".to_string()),
+            netmask: Some("255.255.255.0".to_string()),
+            lower_ip: Some("192.168.56.101".to_string()),
+            upper_ip: Some("192.168.56.254".to_string()),
+            enabled: true,
+        }
+    );
+    assert!(!v[1].enabled);
+    assert_eq!(v[1].lower_ip, None);
+
+    let settings = DhcpServerSettings {
+        upper_ip: Some("192.168.56.200".to_string()),
+        enabled: Some(false),
+        ..Default::default()
+    };
+    assert_eq!(
+        settings.to_args(),
+        ["--upperip", "192.168.56.200", "--disable"]
+    );
+    assert!(DhcpServerSettings::default().to_args().is_empty());
+}
+
+#[test]
+fn test_parse_created_host_only_if() {
+    let s = "0%...50%...100%\nInterface 'VirtualBox Host-Only Ethernet \
+             Adapter #2' was successfully created\n";
+    assert_eq!(
+        parse_created_host_only_if(s).as_deref(),
+        Some("VirtualBox Host-Only Ethernet Adapter #2")
+    );
+    assert_eq!(
+        parse_created_host_only_if(
+            "Interface 'vboxnet0' was successfully created"
+        )
+        .as_deref(),
+        Some("vboxnet0")
+    );
+    assert!(parse_created_host_only_if("").is_none());
 }
@@ -1,20 +1,219 @@
 // Copyright takubokudori.
 // This source code is licensed under the MIT or Apache-2.0 license.
 //! VMware controllers.
+#[cfg(feature = "vmcli")]
+pub mod vmcli;
 #[cfg(feature = "vmrest")]
 pub mod vmrest;
 #[cfg(feature = "vmrun")]
 pub mod vmrun;
+#[cfg(feature = "vsphere")]
+pub mod vsphere;
 
-use crate::types::Vm;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
     io::{BufRead, BufReader},
 };
+#[cfg(feature = "vmcli")]
+pub use vmcli::*;
 #[cfg(feature = "vmrest")]
 pub use vmrest::*;
 #[cfg(feature = "vmrun")]
 pub use vmrun::*;
+#[cfg(feature = "vsphere")]
+pub use vsphere::*;
+
+/// The default path to the autostart configuration of VMware Workstation.
+pub const DEFAULT_AUTOSTART_CONFIG_PATH: &str =
+    r"C:\ProgramData\VMware\VMware Workstation\vmAutoStart.xml";
+
+/// The default NAT host network of VMware Workstation.
+pub const NAT_HOST_NETWORK: &str = "vmnet8";
+
+/// Represents a VM started by the VMware Autostart Service when the host boots.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AutoStartEntry {
+    /// The path to the .vmx file.
+    pub vmx_path: String,
+    /// The order in which the VM is started, starting from 1.
+    pub start_order: u32,
+    /// The delay in seconds before the next VM is started. `-1` means the system default.
+    pub start_delay: i32,
+}
+
+/// Reads the autostart configuration in the `vmAutoStart.xml` format.
+///
+/// Returns an empty list if the file does not exist.
+pub fn read_autostart_config(
+    file_path: &str,
+) -> std::io::Result<Vec<AutoStartEntry>> {
+    match std::fs::read_to_string(file_path) {
+        Ok(s) => Ok(parse_autostart_config(&s)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+    }
+}
+
+/// Writes the autostart configuration in the `vmAutoStart.xml` format.
+///
+/// Writing to the default path requires administrator privileges, and the changes take effect when the VMware Autostart Service starts.
+pub fn write_autostart_config(
+    file_path: &str,
+    entries: &[AutoStartEntry],
+) -> std::io::Result<()> {
+    std::fs::write(file_path, autostart_config_to_xml(entries))
+}
+
+/// Represents options of VMware Tools for power operations in the .vmx file.
+///
+/// Analysis environments often disable them so that the guest is not changed by the host.
+/// `None` fields are left unchanged.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ToolsScriptSettings {
+    /// Synchronizes the guest clock with the host with `tools.syncTime`.
+    pub sync_time: Option<bool>,
+    /// Runs the script after the VM powers on with `toolScripts.afterPowerOn`.
+    pub after_power_on: Option<bool>,
+    /// Runs the script after the VM resumes with `toolScripts.afterResume`.
+    pub after_resume: Option<bool>,
+    /// Runs the script before the VM suspends with `toolScripts.beforeSuspend`.
+    pub before_suspend: Option<bool>,
+    /// Runs the script before the VM powers off with `toolScripts.beforePowerOff`.
+    pub before_power_off: Option<bool>,
+}
+
+impl ToolsScriptSettings {
+    pub fn new() -> Self { Self::default() }
+
+    /// Returns the settings that disable the time synchronization and all scripts.
+    pub fn disabled() -> Self {
+        Self {
+            sync_time: Some(false),
+            after_power_on: Some(false),
+            after_resume: Some(false),
+            before_suspend: Some(false),
+            before_power_off: Some(false),
+        }
+    }
+
+    impl_setter!(@opt sync_time: bool);
+    impl_setter!(@opt after_power_on: bool);
+    impl_setter!(@opt after_resume: bool);
+    impl_setter!(@opt before_suspend: bool);
+    impl_setter!(@opt before_power_off: bool);
+
+    /// Returns the .vmx entries to be set.
+    #[allow(dead_code)]
+    fn to_entries(&self) -> Vec<(&'static str, &'static str)> {
+        let opts = [
+            ("tools.syncTime", self.sync_time),
+            ("toolScripts.afterPowerOn", self.after_power_on),
+            ("toolScripts.afterResume", self.after_resume),
+            ("toolScripts.beforeSuspend", self.before_suspend),
+            ("toolScripts.beforePowerOff", self.before_power_off),
+        ];
+        opts.iter()
+            .filter_map(|(key, value)| {
+                value.map(|x| (*key, if x { "TRUE" } else { "FALSE" }))
+            })
+            .collect()
+    }
+}
+
+/// Returns the text of the first `<tag>` element in `s`.
+fn xml_element<'a>(s: &'a str, tag: &str) -> Option<&'a str> {
+    let start = s.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + s[start..].find(&format!("</{}>", tag))?;
+    Some(&s[start..end])
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_autostart_config(s: &str) -> Vec<AutoStartEntry> {
+    let mut ret: Vec<AutoStartEntry> = s
+        .split("<e id=")
+        .skip(1)
+        .filter_map(|x| {
+            let x = &x[..x.find("</e>")?];
+            Some(AutoStartEntry {
+                vmx_path: unescape_xml(xml_element(x, "moid")?.trim()),
+                start_order: xml_element(x, "startOrder")?
+                    .trim()
+                    .parse()
+                    .ok()?,
+                start_delay: xml_element(x, "startDelay")
+                    .and_then(|x| x.trim().parse().ok())
+                    .unwrap_or(-1),
+            })
+        })
+        .collect();
+    ret.sort_by_key(|x| x.start_order);
+    ret
+}
+
+fn autostart_config_to_xml(entries: &[AutoStartEntry]) -> String {
+    let mut s = format!(
+        "<ConfigRoot>\n  <AutoStartOrder>\n    <_length>{}</_length>\n    \
+         <_type>vim.host.AutoStartManager.AutoPowerInfo[]</_type>\n",
+        entries.len()
+    );
+    for (i, x) in entries.iter().enumerate() {
+        s.push_str(&format!(
+            "    <e id=\"{}\">\n      \
+             <_type>vim.host.AutoStartManager.AutoPowerInfo</_type>\n      \
+             <key>\n        <_type>vim.VirtualMachine</_type>\n        \
+             <moid>{}</moid>\n      </key>\n      \
+             <startAction>PowerOn</startAction>\n      \
+             <startDelay>{}</startDelay>\n      \
+             <startOrder>{}</startOrder>\n      \
+             <stopAction>SystemDefault</stopAction>\n      \
+             <stopDelay>-1</stopDelay>\n      \
+             <waitForHeartbeat>systemDefault</waitForHeartbeat>\n    </e>\n",
+            i,
+            escape_xml(&x.vmx_path),
+            x.start_delay,
+            x.start_order
+        ));
+    }
+    s.push_str("  </AutoStartOrder>\n</ConfigRoot>\n");
+    s
+}
+
+#[test]
+fn test_autostart_config() {
+    let v = vec![
+        AutoStartEntry {
+            vmx_path: r"C:\VMs\b&c\b.vmx".to_string(),
+            start_order: 2,
+            start_delay: 30,
+        },
+        AutoStartEntry {
+            vmx_path: r"C:\VMs\a\a.vmx".to_string(),
+            start_order: 1,
+            start_delay: -1,
+        },
+    ];
+    let s = autostart_config_to_xml(&v);
+    assert!(s.contains(r"<moid>C:\VMs\b&amp;c\b.vmx</moid>"));
+    let parsed = parse_autostart_config(&s);
+    assert_eq!(parsed, vec![v[1].clone(), v[0].clone()]);
+    assert!(parse_autostart_config("<ConfigRoot></ConfigRoot>").is_empty());
+}
 
 fn get_key_value(s: &str) -> Option<(&str, &str)> {
     let kv: Vec<&str> = s.splitn(2, '=').collect();
@@ -28,6 +227,393 @@ fn get_key_value(s: &str) -> Option<(&str, &str)> {
     Some((key, value))
 }
 
+/// Inserts the ISO image at `iso_path` into the CD/DVD drive in the .vmx file, or ejects the medium if `iso_path` is `None`.
+///
+/// Returns [`ErrorKind::InvalidPowerState`] if the VM is running because VMware overwrites the .vmx file.
+#[allow(dead_code)]
+pub(crate) fn write_vmx_cdrom<C: PowerCmd>(
+    cmd: &C,
+    vmx_path: &str,
+    iso_path: Option<&str>,
+) -> VmResult<()> {
+    if cmd.is_running()? {
+        return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
+    }
+    let s = std::fs::read_to_string(vmx_path)?;
+    std::fs::write(vmx_path, set_vmx_cdrom(&s, iso_path))?;
+    Ok(())
+}
+
+/// Returns the .vmx content `s` whose CD/DVD drive is set to `iso_path`.
+///
+/// Adds a drive at `ide1:0` if `s` has none.
+#[allow(dead_code)]
+fn set_vmx_cdrom(s: &str, iso_path: Option<&str>) -> String {
+    let device = s.lines().find_map(|l| {
+        let (key, value) = get_key_value(l)?;
+        let device = key.strip_suffix(".deviceType")?;
+        if value.starts_with("cdrom") || value == "atapi-cdrom" {
+            Some(device.to_string())
+        } else {
+            None
+        }
+    });
+    let (device, entries) = match (device, iso_path) {
+        (Some(device), Some(path)) => (
+            device,
+            vec![
+                ("present", "TRUE"),
+                ("deviceType", "cdrom-image"),
+                ("fileName", path),
+                ("startConnected", "TRUE"),
+            ],
+        ),
+        (None, Some(path)) => (
+            "ide1:0".to_string(),
+            vec![
+                ("present", "TRUE"),
+                ("deviceType", "cdrom-image"),
+                ("fileName", path),
+                ("startConnected", "TRUE"),
+            ],
+        ),
+        (Some(device), None) => {
+            (device, vec![("fileName", ""), ("startConnected", "FALSE")])
+        }
+        (None, None) => return s.to_string(),
+    };
+    let entries: Vec<(String, &str)> = entries
+        .into_iter()
+        .map(|(name, value)| (format!("{}.{}", device, name), value))
+        .collect();
+    set_vmx_entries(s, &entries)
+}
+
+/// Writes `entries` of `key = "value"` to the .vmx file.
+///
+/// VMware overwrites the .vmx file while the VM is running, so the caller must check the power state.
+#[allow(dead_code)]
+pub(crate) fn write_vmx_entries(
+    vmx_path: &str,
+    entries: &[(&str, &str)],
+) -> VmResult<()> {
+    let s = std::fs::read_to_string(vmx_path)?;
+    std::fs::write(vmx_path, set_vmx_entries(&s, entries))?;
+    Ok(())
+}
+
+/// Reads the value of `key` in the .vmx file.
+#[allow(dead_code)]
+pub(crate) fn read_vmx_entry(
+    vmx_path: &str,
+    key: &str,
+) -> std::io::Result<Option<String>> {
+    let s = std::fs::read_to_string(vmx_path)?;
+    Ok(get_vmx_entry(&s, key).map(|x| x.to_string()))
+}
+
+/// Returns the value of `key` in the .vmx content `s`.
+///
+/// Keys are case-insensitive.
+#[allow(dead_code)]
+fn get_vmx_entry<'a>(s: &'a str, key: &str) -> Option<&'a str> {
+    s.lines()
+        .filter_map(get_key_value)
+        .find(|x| x.0.eq_ignore_ascii_case(key))
+        .map(|x| x.1)
+}
+
+/// Returns the .vmx content `s` with `entries` of `key = "value"`.
+///
+/// Keys are case-insensitive, and missing entries are appended.
+#[allow(dead_code)]
+fn set_vmx_entries<K: AsRef<str>>(s: &str, entries: &[(K, &str)]) -> String {
+    let mut lines: Vec<String> = s.lines().map(|x| x.to_string()).collect();
+    for (key, value) in entries {
+        let key = key.as_ref();
+        let line = format!("{} = \"{}\"", key, value);
+        match lines.iter_mut().find(|l| {
+            get_key_value(l).map_or(false, |x| x.0.eq_ignore_ascii_case(key))
+        }) {
+            Some(l) => *l = line,
+            None => lines.push(line),
+        }
+    }
+    let mut ret = lines.join("\n");
+    ret.push('\n');
+    ret
+}
+
+/// Reads the detailed information of the VM from the .vmx file.
+///
+/// `power_state` and `tools_version` are not recorded in the .vmx file.
+#[allow(dead_code)]
+pub(crate) fn read_vmx_info(vmx_path: &str) -> std::io::Result<VmInfo> {
+    let mut info = parse_vmx_info(&std::fs::read_to_string(vmx_path)?);
+    info.config_path = Some(vmx_path.to_string());
+    Ok(info)
+}
+
+/// Parses the .vmx content `s` into [`VmInfo`].
+///
+/// Omitted `numvcpus` and `firmware` mean one processor and BIOS.
+#[allow(dead_code)]
+fn parse_vmx_info(s: &str) -> VmInfo {
+    VmInfo {
+        name: get_vmx_entry(s, "displayName").map(|x| x.to_string()),
+        guest_os: get_vmx_entry(s, "guestOS").map(|x| x.to_string()),
+        memory_mb: get_vmx_entry(s, "memsize").and_then(|x| x.parse().ok()),
+        cpu_count: get_vmx_entry(s, "numvcpus")
+            .map_or(Some(1), |x| x.parse().ok()),
+        firmware: Some(match get_vmx_entry(s, "firmware") {
+            Some(x) if x.eq_ignore_ascii_case("efi") => Firmware::Efi,
+            _ => Firmware::Bios,
+        }),
+        ..Default::default()
+    }
+}
+
+/// Reads the shared folders in the .vmx file.
+#[allow(dead_code)]
+pub(crate) fn read_vmx_shared_folders(
+    vmx_path: &str,
+) -> std::io::Result<Vec<SharedFolder>> {
+    Ok(parse_vmx_shared_folders(&std::fs::read_to_string(
+        vmx_path,
+    )?))
+}
+
+/// Parses `sharedFolderN.*` entries of the .vmx content `s`.
+#[allow(dead_code)]
+fn parse_vmx_shared_folders(s: &str) -> Vec<SharedFolder> {
+    let mut folders: BTreeMap<u32, BTreeMap<String, &str>> = BTreeMap::new();
+    for (key, value) in s.lines().filter_map(get_key_value) {
+        let key = key.to_ascii_lowercase();
+        let (index, name) = match key
+            .strip_prefix("sharedfolder")
+            .and_then(|x| x.split_once('.'))
+        {
+            Some(x) => x,
+            None => continue,
+        };
+        if let Ok(index) = index.parse() {
+            folders
+                .entry(index)
+                .or_default()
+                .insert(name.to_string(), value);
+        }
+    }
+    folders
+        .values()
+        .filter(|x| {
+            x.get("present").map(|x| x.eq_ignore_ascii_case("TRUE"))
+                == Some(true)
+        })
+        .map(|x| {
+            let name = x.get("guestname").map(|x| x.to_string());
+            SharedFolder {
+                id: name.clone(),
+                name,
+                guest_path: None,
+                host_path: x.get("hostpath").map(|x| x.to_string()),
+                is_readonly: !x
+                    .get("writeaccess")
+                    .map_or(false, |x| x.eq_ignore_ascii_case("TRUE")),
+            }
+        })
+        .collect()
+}
+
+/// Adds the autoconnect rule of the USB device of `id` (`VID:PID` in hexadecimal, e.g., `046d:c52b`) to the .vmx file, or removes it if `connect` is `false`.
+///
+/// VMware overwrites the .vmx file while the VM is running, so the caller must check the power state.
+#[allow(dead_code)]
+pub(crate) fn write_vmx_usb_autoconnect(
+    vmx_path: &str,
+    id: &str,
+    connect: bool,
+) -> VmResult<()> {
+    let (vid, pid) = parse_usb_id(id).ok_or_else(|| {
+        VmError::from(ErrorKind::InvalidParameter(format!(
+            "{} is not VID:PID",
+            id
+        )))
+    })?;
+    let s = std::fs::read_to_string(vmx_path)?;
+    std::fs::write(vmx_path, set_vmx_usb_autoconnect(&s, vid, pid, connect))?;
+    Ok(())
+}
+
+/// Parses `VID:PID` in hexadecimal.
+#[allow(dead_code)]
+fn parse_usb_id(id: &str) -> Option<(u16, u16)> {
+    let (vid, pid) = id.split_once(':')?;
+    let hex =
+        |x: &str| u16::from_str_radix(x.trim_start_matches("0x"), 16).ok();
+    Some((hex(vid)?, hex(pid)?))
+}
+
+/// Returns the .vmx content `s` with the `usb.autoConnect.deviceN` rule of `vid:pid` added or removed.
+#[allow(dead_code)]
+fn set_vmx_usb_autoconnect(
+    s: &str,
+    vid: u16,
+    pid: u16,
+    connect: bool,
+) -> String {
+    const PREFIX: &str = "usb.autoconnect.device";
+    let rule = format!("vid:0x{:04x} pid:0x{:04x}", vid, pid);
+    let rules: Vec<(usize, &str)> = s
+        .lines()
+        .filter_map(get_key_value)
+        .filter_map(|(key, value)| {
+            let n = key
+                .to_ascii_lowercase()
+                .strip_prefix(PREFIX)?
+                .parse()
+                .ok()?;
+            Some((n, value))
+        })
+        .collect();
+    let exists = rules.iter().any(|x| x.1.eq_ignore_ascii_case(&rule));
+    if !connect {
+        let mut ret: String = s
+            .lines()
+            .filter(|l| {
+                get_key_value(l).map_or(true, |(key, value)| {
+                    !(key.to_ascii_lowercase().starts_with(PREFIX)
+                        && value.eq_ignore_ascii_case(&rule))
+                })
+            })
+            .collect::<Vec<&str>>()
+            .join("\n");
+        ret.push('\n');
+        return ret;
+    }
+    if exists {
+        return s.to_string();
+    }
+    let n = rules.iter().map(|x| x.0 + 1).max().unwrap_or(0);
+    set_vmx_entries(
+        s,
+        &[
+            ("usb.present".to_string(), "TRUE"),
+            (format!("usb.autoConnect.device{}", n), &rule),
+        ],
+    )
+}
+
+/// The maximum number of serial ports of a VM.
+#[allow(dead_code)]
+pub(crate) const MAX_VMX_SERIAL_PORTS: usize = 4;
+
+/// Reads the serial ports in the .vmx file.
+#[allow(dead_code)]
+pub(crate) fn read_vmx_serial_ports(
+    vmx_path: &str,
+) -> std::io::Result<Vec<SerialPort>> {
+    Ok(parse_vmx_serial_ports(&std::fs::read_to_string(vmx_path)?))
+}
+
+/// Parses `serialN.*` entries of the .vmx content `s`.
+///
+/// `serialN` is `COM<N + 1>` of the guest. Ports connected to a host device or a network are omitted.
+#[allow(dead_code)]
+fn parse_vmx_serial_ports(s: &str) -> Vec<SerialPort> {
+    let mut ports: BTreeMap<usize, BTreeMap<String, &str>> = BTreeMap::new();
+    for (key, value) in s.lines().filter_map(get_key_value) {
+        let key = key.to_ascii_lowercase();
+        let (index, name) =
+            match key.strip_prefix("serial").and_then(|x| x.split_once('.')) {
+                Some(x) => x,
+                None => continue,
+            };
+        if let Ok(index) = index.parse() {
+            ports
+                .entry(index)
+                .or_default()
+                .insert(name.to_string(), value);
+        }
+    }
+    let is_true =
+        |x: Option<&&str>| x.map_or(false, |x| x.eq_ignore_ascii_case("TRUE"));
+    ports
+        .into_iter()
+        .filter_map(|(index, x)| {
+            let mode = if !is_true(x.get("present")) {
+                SerialPortMode::Off
+            } else if x
+                .get("startconnected")
+                .map_or(false, |x| x.eq_ignore_ascii_case("FALSE"))
+            {
+                SerialPortMode::Disconnected
+            } else {
+                let path = x.get("filename")?.to_string();
+                match x.get("filetype")?.to_ascii_lowercase().as_str() {
+                    "file" => SerialPortMode::File(path),
+                    "pipe" if x.get("pipe.endpoint") == Some(&"client") => {
+                        SerialPortMode::PipeClient(path)
+                    }
+                    "pipe" => SerialPortMode::PipeServer(path),
+                    _ => return None,
+                }
+            };
+            Some(SerialPort {
+                number: index + 1,
+                mode,
+            })
+        })
+        .collect()
+}
+
+/// Returns the .vmx entries which set the serial port `n` to `mode`.
+///
+/// Returns `None` for the TCP modes, which VMware Workstation doesn't support.
+#[allow(dead_code)]
+pub(crate) fn vmx_serial_port_entries(
+    n: usize,
+    mode: &SerialPortMode,
+) -> Option<Vec<(String, String)>> {
+    let mut entries = match mode {
+        SerialPortMode::Off => vec![("present", "FALSE")],
+        SerialPortMode::Disconnected => {
+            vec![("present", "TRUE"), ("startConnected", "FALSE")]
+        }
+        SerialPortMode::File(x) => vec![
+            ("present", "TRUE"),
+            ("startConnected", "TRUE"),
+            ("fileType", "file"),
+            ("fileName", x),
+        ],
+        SerialPortMode::PipeServer(x) | SerialPortMode::PipeClient(x) => vec![
+            ("present", "TRUE"),
+            ("startConnected", "TRUE"),
+            ("fileType", "pipe"),
+            ("fileName", x),
+        ],
+        SerialPortMode::TcpServer(_) | SerialPortMode::TcpClient(_) => {
+            return None
+        }
+    };
+    match mode {
+        SerialPortMode::PipeServer(_) => {
+            entries.push(("pipe.endPoint", "server"))
+        }
+        SerialPortMode::PipeClient(_) => {
+            entries.push(("pipe.endPoint", "client"))
+        }
+        _ => {}
+    }
+    Some(
+        entries
+            .into_iter()
+            .map(|(key, value)| {
+                (format!("serial{}.{}", n - 1, key), value.to_string())
+            })
+            .collect(),
+    )
+}
+
 /// Gets all VMs from preferences.ini.
 ///
 /// Due to the specification of vmrun, the vmrun command cannot get all VMs.
@@ -78,7 +664,7 @@ fn parse_preferences<R: BufRead>(mut f: R) -> Option<Vec<Vm>> {
                 continue;
             }
             let n = n.unwrap();
-            vm_list.entry(n).or_insert_with(Vm::default);
+            vm_list.entry(n).or_default();
             let vm = vm_list.get_mut(&n).unwrap();
             match key_names[2] {
                 "filename" => vm.path = Some(value.to_string()),
@@ -135,6 +721,22 @@ pref.mruVM1.filename = "C:\Users\user\Documents\Virtual Machines\Ubuntu 64 ビ
     assert_eq!(parse_preferences(s), None);
 }
 
+/// Returns the path to inventory.vmls of VMware Workstation.
+///
+/// It is `%APPDATA%\VMware\inventory.vmls` on Windows and `~/.vmware/inventory.vmls` on other OSes.
+#[allow(dead_code)]
+pub(crate) fn vmware_inventory_path() -> VmResult<std::path::PathBuf> {
+    let (var, dir) = if cfg!(windows) {
+        ("APPDATA", "VMware")
+    } else {
+        ("HOME", ".vmware")
+    };
+    let base = std::env::var_os(var).ok_or_else(|| {
+        VmError::from(ErrorKind::FileError(format!("{} is not set", var)))
+    })?;
+    Ok(std::path::Path::new(&base).join(dir).join("inventory.vmls"))
+}
+
 /// Gets all VMs from inventory.vmls.
 ///
 /// Due to the specification of vmrun, the vmrun command cannot get all VMs.
@@ -183,7 +785,7 @@ fn parse_inventory<R: BufRead>(mut f: R) -> Option<Vec<Vm>> {
                 continue;
             }
             let n = n.unwrap();
-            vm_list.entry(n).or_insert_with(Vm::default);
+            vm_list.entry(n).or_default();
             let vm = vm_list.get_mut(&n).unwrap();
             match key_names[1] {
                 "config" => vm.path = Some(value.to_string()),
@@ -244,3 +846,175 @@ vimlist1.config = "C:\Users\user\Documents\Virtual Machines\Ubuntu 64 ビット\
 "#.as_bytes();
     assert_eq!(parse_inventory(s), None);
 }
+
+#[test]
+fn test_set_vmx_cdrom() {
+    let s = [
+        r#"displayName = "a""#,
+        r#"sata0:1.present = "TRUE""#,
+        r#"sata0:1.deviceType = "cdrom-raw""#,
+        r#"sata0:1.fileName = "auto detect""#,
+        "",
+    ]
+    .join("\n");
+    let attached = set_vmx_cdrom(&s, Some(r"C:\iso\tools.iso"));
+    assert_eq!(
+        attached,
+        [
+            r#"displayName = "a""#,
+            r#"sata0:1.present = "TRUE""#,
+            r#"sata0:1.deviceType = "cdrom-image""#,
+            r#"sata0:1.fileName = "C:\iso\tools.iso""#,
+            r#"sata0:1.startConnected = "TRUE""#,
+            "",
+        ]
+        .join("\n")
+    );
+    let ejected = set_vmx_cdrom(&attached, None);
+    assert!(ejected.contains(r#"sata0:1.fileName = """#));
+    assert!(ejected.contains(r#"sata0:1.startConnected = "FALSE""#));
+    let added = set_vmx_cdrom(r#"displayName = "a""#, Some("/iso/a.iso"));
+    assert!(added.contains(r#"ide1:0.deviceType = "cdrom-image""#));
+    assert!(added.contains(r#"ide1:0.fileName = "/iso/a.iso""#));
+    assert_eq!(
+        set_vmx_cdrom(r#"displayName = "a""#, None),
+        r#"displayName = "a""#
+    );
+}
+
+#[test]
+fn test_set_vmx_entries() {
+    let s =
+        [r#"displayName = "a""#, r#"tools.synctime = "TRUE""#, ""].join("\n");
+    let entries = ToolsScriptSettings::new()
+        .sync_time(false)
+        .before_suspend(false)
+        .to_entries();
+    assert_eq!(
+        set_vmx_entries(&s, &entries),
+        [
+            r#"displayName = "a""#,
+            r#"tools.syncTime = "FALSE""#,
+            r#"toolScripts.beforeSuspend = "FALSE""#,
+            "",
+        ]
+        .join("\n")
+    );
+    assert_eq!(get_vmx_entry(&s, "tools.syncTime"), Some("TRUE"));
+    assert_eq!(get_vmx_entry(&s, "memsize"), None);
+    assert_eq!(ToolsScriptSettings::disabled().to_entries().len(), 5);
+    assert!(ToolsScriptSettings::new().to_entries().is_empty());
+}
+
+#[test]
+fn test_parse_vmx_info() {
+    let s = [
+        r#"displayName = "Windows 10 x64""#,
+        r#"guestOS = "windows9-64""#,
+        r#"memsize = "4096""#,
+        r#"numvcpus = "2""#,
+        r#"firmware = "efi""#,
+    ]
+    .join("\n");
+    let info = parse_vmx_info(&s);
+    assert_eq!(info.name.as_deref(), Some("Windows 10 x64"));
+    assert_eq!(info.guest_os.as_deref(), Some("windows9-64"));
+    assert_eq!(info.memory_mb, Some(4096));
+    assert_eq!(info.cpu_count, Some(2));
+    assert_eq!(info.firmware, Some(Firmware::Efi));
+    let info = parse_vmx_info(r#"memsize = "512""#);
+    assert_eq!(info.cpu_count, Some(1));
+    assert_eq!(info.firmware, Some(Firmware::Bios));
+    assert_eq!(info.name, None);
+}
+
+#[test]
+fn test_parse_vmx_shared_folders() {
+    let s = [
+        r#"sharedFolder0.present = "TRUE""#,
+        r#"sharedFolder0.enabled = "TRUE""#,
+        r#"sharedFolder0.readAccess = "TRUE""#,
+        r#"sharedFolder0.writeAccess = "TRUE""#,
+        r#"sharedFolder0.hostPath = "C:\share""#,
+        r#"sharedFolder0.guestName = "share""#,
+        r#"sharedFolder1.present = "TRUE""#,
+        r#"sharedFolder1.writeAccess = "FALSE""#,
+        r#"sharedFolder1.hostPath = "D:\iso""#,
+        r#"sharedFolder1.guestName = "iso""#,
+        r#"sharedFolder2.present = "FALSE""#,
+        r#"sharedFolder2.guestName = "removed""#,
+        r#"sharedFolder.maxNum = "3""#,
+    ]
+    .join("\n");
+    let v = parse_vmx_shared_folders(&s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].name.as_deref(), Some("share"));
+    assert_eq!(v[0].host_path.as_deref(), Some(r"C:\share"));
+    assert!(!v[0].is_readonly);
+    assert_eq!(v[1].name.as_deref(), Some("iso"));
+    assert!(v[1].is_readonly);
+}
+
+#[test]
+fn test_vmx_serial_ports() {
+    let entries = vmx_serial_port_entries(
+        2,
+        &SerialPortMode::PipeClient(r"\\.\pipe\vm".to_string()),
+    )
+    .unwrap();
+    let entries: Vec<(&str, &str)> = entries
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    assert!(entries.contains(&("serial1.pipe.endPoint", "client")));
+    let s = set_vmx_entries("serial0.present = \"TRUE\"\n", &entries);
+    let s = set_vmx_entries(
+        &s,
+        &[
+            ("serial0.fileType", "file"),
+            ("serial0.fileName", "/tmp/com1.log"),
+            ("serial2.present", "FALSE"),
+            ("serial3.present", "TRUE"),
+            ("serial3.fileType", "device"),
+            ("serial3.fileName", "COM1"),
+        ],
+    );
+    assert_eq!(
+        parse_vmx_serial_ports(&s),
+        [
+            SerialPort {
+                number: 1,
+                mode: SerialPortMode::File("/tmp/com1.log".to_string())
+            },
+            SerialPort {
+                number: 2,
+                mode: SerialPortMode::PipeClient(r"\\.\pipe\vm".to_string())
+            },
+            SerialPort {
+                number: 3,
+                mode: SerialPortMode::Off
+            },
+        ]
+    );
+    assert!(
+        vmx_serial_port_entries(1, &SerialPortMode::TcpServer(2023)).is_none()
+    );
+}
+
+#[test]
+fn test_set_vmx_usb_autoconnect() {
+    assert_eq!(parse_usb_id("046d:C52B"), Some((0x046d, 0xc52b)));
+    assert_eq!(parse_usb_id("0x0781:0x5581"), Some((0x0781, 0x5581)));
+    assert_eq!(parse_usb_id("046d"), None);
+    let s = "usb.autoConnect.device0 = \"vid:0x0781 pid:0x5581\"\n";
+    let s = set_vmx_usb_autoconnect(s, 0x046d, 0xc52b, true);
+    assert_eq!(
+        get_vmx_entry(&s, "usb.autoConnect.device1"),
+        Some("vid:0x046d pid:0xc52b")
+    );
+    assert_eq!(get_vmx_entry(&s, "usb.present"), Some("TRUE"));
+    assert_eq!(set_vmx_usb_autoconnect(&s, 0x046d, 0xc52b, true), s);
+    let s = set_vmx_usb_autoconnect(&s, 0x0781, 0x5581, false);
+    assert_eq!(get_vmx_entry(&s, "usb.autoConnect.device0"), None);
+    assert!(get_vmx_entry(&s, "usb.autoConnect.device1").is_some());
+}
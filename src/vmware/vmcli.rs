@@ -0,0 +1,575 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! vmcli controller.
+//!
+//! vmcli ships with VMware Workstation 17 and Fusion 13 and can also create VMs and edit their configuration, which vmrun and VMRest cannot.
+use crate::{
+    exec_cmd_utf8, sanitized_args, tool_path_from_env,
+    types::*,
+    vmware::{
+        read_vmware_inventory, read_vmx_info, vmware_inventory_path,
+        write_vmx_cdrom, ToolsScriptSettings,
+    },
+};
+use std::{
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// Represents vmcli command executor.
+#[derive(Debug, Clone)]
+pub struct VmCli {
+    executable_path: String,
+    vm_path: Option<String>,
+    guest_username: Option<String>,
+    guest_password: Option<String>,
+    hooks: CommandHooks,
+}
+
+impl Default for VmCli {
+    fn default() -> Self { Self::new() }
+}
+
+/// Returns the values of `key: value` lines of vmcli output in order.
+fn parse_values<'a>(s: &'a str, key: &str) -> Vec<&'a str> {
+    s.lines()
+        .filter_map(|x| {
+            let (k, v) = x.trim().trim_start_matches("- ").split_once(':')?;
+            if k.trim().eq_ignore_ascii_case(key) {
+                Some(v.trim().trim_matches('"'))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parses the output of `vmcli <vmx> Power Query`.
+fn parse_power_state(s: &str) -> VmPowerState {
+    match parse_values(s, "PowerState").first().copied() {
+        Some("on" | "poweredOn") => VmPowerState::Running,
+        Some("off" | "poweredOff") => VmPowerState::Stopped,
+        Some("suspended") => VmPowerState::Suspended,
+        Some("paused") => VmPowerState::Paused,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+/// Parses the output of `vmcli <vmx> Snapshot Query`.
+fn parse_snapshots(s: &str) -> Vec<Snapshot> {
+    let mut ret: Vec<Snapshot> = vec![];
+    for l in s.lines() {
+        let (k, v) = match l.trim().trim_start_matches("- ").split_once(':') {
+            Some(x) => x,
+            None => continue,
+        };
+        let v = v.trim().trim_matches('"').to_string();
+        match k.trim() {
+            // `uid` starts a new snapshot.
+            "uid" => ret.push(Snapshot {
+                id: Some(v),
+                name: None,
+                detail: None,
+                parent_id: None,
+                created_at: None,
+                is_current: None,
+                online: None,
+            }),
+            "displayName" => {
+                if let Some(x) = ret.last_mut() {
+                    x.name = Some(v);
+                }
+            }
+            "description" => {
+                if let Some(x) = ret.last_mut() {
+                    x.detail = Some(v);
+                }
+            }
+            _ => { /* Does nothing */ }
+        }
+    }
+    ret
+}
+
+impl VmCli {
+    pub fn new() -> Self {
+        Self {
+            executable_path: tool_path_from_env("HVCTRL_VMCLI_PATH", "vmcli"),
+            vm_path: None,
+            guest_username: None,
+            guest_password: None,
+            hooks: CommandHooks::new(),
+        }
+    }
+
+    impl_setter!(
+        /// Sets the path to vmcli.
+        executable_path: String
+    );
+
+    pub fn get_executable_path(&self) -> &str { &self.executable_path }
+
+    impl_setter!(@opt
+        /// Sets the path to the .vmx file of the VM to be manipulated.
+        vm_path: String
+    );
+
+    pub fn get_vm_path(&self) -> Option<&str> { self.vm_path.as_deref() }
+
+    impl_setter!(@opt guest_username: String);
+    impl_setter!(@opt guest_password: String);
+    impl_hooks!();
+
+    fn get_vm(&self) -> VmResult<&str> {
+        self.vm_path
+            .as_deref()
+            .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
+    }
+
+    fn build_auth(&self) -> Vec<&str> {
+        let mut v = Vec::with_capacity(4);
+        if let Some(x) = &self.guest_username {
+            v.extend(&["--username", x]);
+        }
+        if let Some(x) = &self.guest_password {
+            v.extend(&["--password", x]);
+        }
+        v
+    }
+
+    fn handle_error(s: &str) -> VmError {
+        use ErrorKind::*;
+        starts_err!(
+            s,
+            "The virtual machine is not powered on",
+            InvalidPowerState(VmPowerState::NotRunning)
+        );
+        starts_err!(
+            s,
+            "The virtual machine is already powered on",
+            InvalidPowerState(VmPowerState::Running)
+        );
+        starts_err!(
+            s,
+            "A snapshot with the name already exists",
+            SnapshotExists
+        );
+        starts_err!(
+            s,
+            "The specified snapshot does not exist",
+            SnapshotNotFound
+        );
+        starts_err!(
+            s,
+            "Invalid user name or password",
+            GuestAuthenticationFailed
+        );
+        starts_err!(s, "The VMware Tools are not running", ServiceIsNotRunning);
+        if s.starts_with("Unrecognized command") {
+            return VmError::from(ErrorKind::unsupported::<Self>(s));
+        }
+        starts_err!(s, "Could not open", VmNotFound);
+        VmError::from(Repr::Unknown(format!("Unknown error: {}", s)))
+    }
+
+    /// Executes `vmcli [vmx] <module> <command> [args]`.
+    fn exec(
+        &self,
+        vmx: Option<&str>,
+        module: &str,
+        command: &str,
+        args: &[&str],
+    ) -> VmResult<String> {
+        let mut cmd = Command::new(&self.executable_path);
+        if let Some(x) = vmx {
+            cmd.arg(x);
+        }
+        cmd.args([module, command]).args(args);
+        let op = format!("{} {}", module, command);
+        // Skips `<module> <command>`.
+        let mut hook_args = sanitized_args(&cmd, &["--password"]);
+        let skip = vmx.is_some() as usize;
+        hook_args.drain(skip..skip + 2);
+        self.hooks.run(&op, &hook_args, || {
+            let o = exec_cmd_utf8(&mut cmd)?;
+            let errors =
+                self.hooks
+                    .classify_stderr(&op, &o.stderr, o.status.success());
+            let stderr = errors.trim();
+            o.check_status(if stderr.is_empty() {
+                Ok(o.stdout.clone())
+            } else {
+                Err(Self::handle_error(
+                    stderr.strip_prefix("Error: ").unwrap_or(stderr),
+                ))
+            })
+        })
+    }
+
+    fn exec_vm(
+        &self,
+        module: &str,
+        command: &str,
+        args: &[&str],
+    ) -> VmResult<String> {
+        self.exec(Some(self.get_vm()?), module, command, args)
+    }
+
+    /// Gets VMs registered in `%APPDATA%\VMware\inventory.vmls` on Windows or `~/.vmware/inventory.vmls` on Linux.
+    pub fn list_all_vms(&self) -> VmResult<Vec<Vm>> {
+        let p = vmware_inventory_path()?;
+        read_vmware_inventory(&p.to_string_lossy())?.ok_or_else(|| {
+            VmError::from(Repr::Unknown(
+                "Cannot parse inventory file".to_string(),
+            ))
+        })
+    }
+
+    /// Creates a VM named `name` in `dir` and returns the path to its .vmx file.
+    ///
+    /// `guest_os` is a guest OS identifier, e.g., `windows9-64` or `ubuntu-64`.
+    pub fn create_vm(
+        &self,
+        name: &str,
+        dir: &str,
+        guest_os: &str,
+    ) -> VmResult<String> {
+        let _op = self.hooks.operation("create_vm");
+        self.exec(
+            None,
+            "VM",
+            "Create",
+            &["-n", name, "-d", dir, "-g", guest_os],
+        )?;
+        Ok(std::path::Path::new(dir)
+            .join(format!("{}.vmx", name))
+            .to_string_lossy()
+            .into_owned())
+    }
+
+    /// Gets the configuration entries of the VM.
+    pub fn query_config_params(&self) -> VmResult<String> {
+        self.exec_vm("ConfigParams", "Query", &[])
+    }
+
+    /// Sets a configuration entry of the VM, e.g., `("memsize", "2048")`.
+    pub fn set_config_param(&self, key: &str, value: &str) -> VmResult<()> {
+        self.exec_vm("ConfigParams", "SetEntry", &[key, value])?;
+        Ok(())
+    }
+
+    /// Sets the options of VMware Tools for power operations.
+    pub fn set_tools_scripts(
+        &self,
+        settings: &ToolsScriptSettings,
+    ) -> VmResult<()> {
+        for (key, value) in settings.to_entries() {
+            self.set_config_param(key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Gets the power state of the VM.
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        Ok(parse_power_state(&self.exec_vm("Power", "Query", &[])?))
+    }
+
+    /// Gets the detailed information of the VM from the .vmx file and its power state.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let mut info = read_vmx_info(self.get_vm()?)?;
+        info.power_state = Some(self.get_power_state()?);
+        Ok(info)
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+
+    /// Executes `vmcli <vmx> Power <command>`.
+    ///
+    /// `op_type` is `hard`, `soft` or `trySoft` for `Stop`, `Reset` and `Suspend`.
+    pub fn power(&self, command: &str, op_type: Option<&str>) -> VmResult<()> {
+        match op_type {
+            Some(x) => self.exec_vm("Power", command, &["--opType", x])?,
+            None => self.exec_vm("Power", command, &[])?,
+        };
+        Ok(())
+    }
+
+    pub fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
+        Ok(parse_snapshots(&self.exec_vm("Snapshot", "Query", &[])?))
+    }
+
+    fn is_snapshot_exists(&self, name: &str) -> VmResult<bool> {
+        Ok(self
+            .list_snapshots()?
+            .iter()
+            .any(|x| x.name.as_deref() == Some(name)))
+    }
+
+    /// Runs a program in the guest and waits for it to exit.
+    pub fn run_program_in_guest(&self, guest_args: &[&str]) -> VmResult<()> {
+        let mut args = self.build_auth();
+        args.extend(guest_args);
+        self.exec_vm("Guest", "run", &args)?;
+        Ok(())
+    }
+
+    pub fn copy_file_from_guest_to_host(
+        &self,
+        from_guest_path: &str,
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let mut args = self.build_auth();
+        args.extend([from_guest_path, to_host_path]);
+        self.exec_vm("Guest", "copyFrom", &args)?;
+        Ok(())
+    }
+
+    pub fn copy_file_from_host_to_guest(
+        &self,
+        from_host_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let mut args = self.build_auth();
+        args.extend([from_host_path, to_guest_path]);
+        self.exec_vm("Guest", "copyTo", &args)?;
+        Ok(())
+    }
+}
+
+impl VmCmd for VmCli {
+    fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        self.list_all_vms()
+    }
+
+    /// Due to the specification of vmcli, VmCli does not support this function.
+    fn set_vm_by_id(&mut self, _id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_vm_by_id",
+            "Use set_vm_by_name or set_vm_by_path instead"
+        ))
+    }
+
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
+        for vm in self.list_vms()? {
+            if vm.name.as_deref() == Some(name) {
+                self.vm_path = vm.path.clone();
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    /// Sets the VM without looking it up in the inventory, since vmcli can manipulate unregistered VMs.
+    fn set_vm_by_path(&mut self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        if !std::path::Path::new(path).is_file() {
+            return vmerr!(ErrorKind::VmNotFound);
+        }
+        self.vm_path = Some(path.to_string());
+        let vm = self
+            .list_vms()?
+            .into_iter()
+            .find(|x| x.path.as_deref() == Some(path));
+        Ok(vm.unwrap_or_else(|| Vm {
+            id: None,
+            name: None,
+            path: Some(path.to_string()),
+        }))
+    }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
+}
+
+impl PowerCmd for VmCli {
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            if self.is_running()? {
+                return vmerr!(ErrorKind::InvalidPowerState(
+                    VmPowerState::Running
+                ));
+            }
+            self.power("Start", None)
+        })
+    }
+
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.check_power_state(&[VmPowerState::Running])?;
+            self.power("Stop", Some("soft"))?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
+    }
+
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Paused,
+            ])?;
+            self.power("Stop", Some("hard"))
+        })
+    }
+
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.power("Suspend", Some("soft"))
+    }
+
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        self.start()
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()? == VmPowerState::Running)
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, _timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.power("Reset", Some("soft"))
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.check_power_state(&[VmPowerState::Running, VmPowerState::Paused])?;
+        self.power("Reset", Some("hard"))
+    }
+
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.power("Pause", None)
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.check_power_state(&[VmPowerState::Paused])?;
+        self.power("Unpause", None)
+    }
+}
+
+impl SnapshotCmd for VmCli {
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        Self::list_snapshots(self)
+    }
+
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        if self.is_snapshot_exists(name)? {
+            return vmerr!(ErrorKind::SnapshotExists);
+        }
+        self.exec_vm("Snapshot", "Take", &[name])?;
+        Ok(())
+    }
+
+    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("revert_snapshot");
+        if !self.is_snapshot_exists(name)? {
+            return vmerr!(ErrorKind::SnapshotNotFound);
+        }
+        self.exec_vm("Snapshot", "Revert", &[name])?;
+        Ok(())
+    }
+
+    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
+        self.hooks.settle_deleted(|| {
+            if !self.is_snapshot_exists(name)? {
+                return vmerr!(ErrorKind::SnapshotNotFound);
+            }
+            self.exec_vm("Snapshot", "Delete", &[name])?;
+            Ok(())
+        })
+    }
+}
+
+/// Edits the .vmx file, so the VM must not be running.
+impl MediaCmd for VmCli {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        write_vmx_cdrom(self, self.get_vm()?, Some(path))
+    }
+
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        write_vmx_cdrom(self, self.get_vm()?, None)
+    }
+}
+
+impl GuestCmd for VmCli {
+    fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
+        let _op = self.hooks.operation("exec_cmd");
+        self.run_program_in_guest(guest_args)
+    }
+
+    fn copy_from_guest_to_host(
+        &self,
+        from_guest_path: &str,
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_guest_to_host");
+        self.copy_file_from_guest_to_host(from_guest_path, to_host_path)
+    }
+
+    fn copy_from_host_to_guest(
+        &self,
+        from_host_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_host_to_guest");
+        self.copy_file_from_host_to_guest(from_host_path, to_guest_path)
+    }
+}
+
+#[test]
+fn test_parse_power_state() {
+    assert_eq!(
+        VmPowerState::Running,
+        parse_power_state("PowerQuery:\n  PowerState: on\n")
+    );
+    assert_eq!(
+        VmPowerState::Stopped,
+        parse_power_state("PowerQuery:\n  PowerState: off\n")
+    );
+    assert_eq!(
+        VmPowerState::Suspended,
+        parse_power_state("powerState: \"suspended\"\n")
+    );
+    assert_eq!(VmPowerState::Unknown, parse_power_state(""));
+}
+
+#[test]
+fn test_parse_snapshots() {
+    let s = "SnapshotQuery:\n  snapshots:\n    - uid: 1\n      displayName: \
+             snap1\n      description: first\n    - uid: 2\n      \
+             displayName: snap2\n";
+    let v = parse_snapshots(s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].id.as_deref(), Some("1"));
+    assert_eq!(v[0].name.as_deref(), Some("snap1"));
+    assert_eq!(v[0].detail.as_deref(), Some("first"));
+    assert_eq!(v[1].name.as_deref(), Some("snap2"));
+    assert_eq!(v[1].detail, None);
+    assert!(parse_snapshots("SnapshotQuery:\n  snapshots: []\n").is_empty());
+}
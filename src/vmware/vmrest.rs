@@ -1,12 +1,23 @@
 // Copyright takubokudori.
 // This source code is licensed under the MIT or Apache-2.0 license.
 //! VMRest controller.
-use crate::{deserialize, exec_cmd, types::*};
+#[cfg(feature = "async")]
+use crate::asynchronous::{self, BoxFuture};
+use crate::{
+    deserialize,
+    hostnet::{self, HostNetwork, HostNetworkCmd, HostNetworkKind},
+    tool_path_from_env,
+    types::*,
+    vmware::{read_vmx_info, write_vmx_cdrom, NAT_HOST_NETWORK},
+};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::{
-    io::Write,
-    process::Command,
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    io::{BufRead, BufReader, Write},
+    process::{Child, Command, Stdio},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
@@ -27,10 +38,23 @@ impl VmRestPowerCommand {
             Self::Suspend => "suspend",
         }
     }
+
+    /// Returns the name of the [`PowerCmd`] operation passed to the hooks.
+    #[cfg(feature = "async")]
+    fn operation(&self) -> &'static str {
+        match self {
+            Self::On => "start",
+            Self::Off => "hard_stop",
+            Self::Shutdown => "stop",
+            Self::Suspend => "suspend",
+        }
+    }
 }
 
-impl ToString for VmRestPowerCommand {
-    fn to_string(&self) -> String { self.to_command().to_string() }
+impl std::fmt::Display for VmRestPowerCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_command().fmt(f)
+    }
 }
 
 #[derive(Deserialize)]
@@ -58,6 +82,144 @@ impl<T: AsRef<str>> From<T> for NicType {
     }
 }
 
+#[derive(Deserialize)]
+struct Swagger {
+    info: SwaggerInfo,
+    #[serde(alias = "basePath", default)]
+    base_path: String,
+    #[serde(default)]
+    paths: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct SwaggerInfo {
+    #[serde(default)]
+    description: String,
+    version: String,
+}
+
+/// Represents the API version and the endpoints available on a vmrest server, read from `swagger.json`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct VmRestCapabilities {
+    /// The API version (e.g., `1.2.1`).
+    pub api_version: String,
+    /// The paths of the available endpoints (e.g., `/api/vms/{id}/power`).
+    pub paths: Vec<String>,
+}
+
+impl VmRestCapabilities {
+    /// Returns `true` if the endpoint `path` is available.
+    pub fn has_path(&self, path: &str) -> bool {
+        self.paths.iter().any(|x| x == path)
+    }
+
+    /// Returns `true` if the virtual network (`/api/vmnet`) endpoints are available.
+    pub fn has_vmnet(&self) -> bool {
+        self.paths.iter().any(|x| x.starts_with("/api/vmnet"))
+    }
+
+    /// Returns `true` if the NIC endpoints are available.
+    pub fn has_nic(&self) -> bool { self.has_path("/api/vms/{id}/nic") }
+
+    /// Returns `true` if the shared folder endpoints are available.
+    pub fn has_shared_folders(&self) -> bool {
+        self.has_path("/api/vms/{id}/sharedfolders")
+    }
+
+    /// Returns `true` if the IP address endpoint is available.
+    pub fn has_ip_address(&self) -> bool { self.has_path("/api/vms/{id}/ip") }
+}
+
+impl From<Swagger> for VmRestCapabilities {
+    fn from(s: Swagger) -> Self {
+        Self {
+            api_version: s.info.version,
+            paths: s
+                .paths
+                .into_keys()
+                .map(|x| format!("{}{}", s.base_path, x))
+                .collect(),
+        }
+    }
+}
+
+/// The minimum polling interval of [`VmRest::watch_power_state`].
+///
+/// Shorter intervals are raised to this so that many watchers don't overload the vmrest server.
+pub const MIN_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An iterator over the power state changes of a VM, created by [`VmRest::watch_power_state`].
+///
+/// Yields the current power state first, and then blocks until the power state changes.
+/// The iterator never ends; an error is yielded and polling continues on the next call.
+#[derive(Debug)]
+pub struct PowerStateWatcher<'a> {
+    cmd: &'a VmRest,
+    interval: Duration,
+    last: Option<VmPowerState>,
+    polled: bool,
+    rng: u64,
+}
+
+impl PowerStateWatcher<'_> {
+    /// Returns `interval` plus a random jitter of up to 25%.
+    fn next_delay(&mut self) -> Duration {
+        // xorshift64
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        jittered(self.interval, self.rng)
+    }
+}
+
+impl Iterator for PowerStateWatcher<'_> {
+    type Item = VmResult<VmPowerState>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.polled {
+                let delay = self.next_delay();
+                std::thread::sleep(delay);
+            }
+            self.polled = true;
+            match self.cmd.get_power_state() {
+                Ok(x) if Some(x) == self.last => continue,
+                Ok(x) => {
+                    self.last = Some(x);
+                    return Some(Ok(x));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Returns `interval` plus a jitter of up to 25% chosen by `r`.
+fn jittered(interval: Duration, r: u64) -> Duration {
+    interval + interval / 4000 * (r % 1000) as u32
+}
+
+/// Holds the vmrest server started by [`VmRest::start_vmrest_server`] and kills it on drop.
+#[derive(Debug, Default)]
+struct ServerProcess(Mutex<Option<Child>>);
+
+impl ServerProcess {
+    fn kill(&self) -> VmResult<()> {
+        if let Some(mut x) = self.0.lock().unwrap().take() {
+            x.kill()?;
+            x.wait()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) { let _ = self.kill(); }
+}
+
+/// Represents VMRest client.
+///
+/// The vmrest server started by [`VmRest::start_vmrest_server`] is shared between clones and killed when the last of them is dropped.
 #[derive(Clone, Debug)]
 pub struct VmRest {
     executable_path: String,
@@ -67,6 +229,8 @@ pub struct VmRest {
     encoding: String,
     username: Option<String>,
     password: Option<String>,
+    server: Arc<ServerProcess>,
+    hooks: CommandHooks,
 }
 
 impl Default for VmRest {
@@ -76,13 +240,15 @@ impl Default for VmRest {
 impl VmRest {
     pub fn new() -> Self {
         Self {
-            executable_path: "vmrest".to_string(),
+            executable_path: tool_path_from_env("HVCTRL_VMREST_PATH", "vmrest"),
             url: "http://127.0.0.1:8697".to_string(),
             encoding: "utf-8".to_string(),
             vm_id: None,
             proxy: None,
             username: None,
             password: None,
+            server: Arc::new(ServerProcess::default()),
+            hooks: CommandHooks::new(),
         }
     }
 
@@ -102,23 +268,44 @@ impl VmRest {
     impl_setter!(@opt password: String);
     impl_setter!(@opt proxy: String);
     impl_setter!(encoding: String);
+    impl_hooks!();
 
-    /// Starts vmrest server.
+    /// Starts vmrest server and uses its URL.
+    ///
+    /// The server runs until [`VmRest::close`] is called or the controller and its clones are dropped.
     pub fn start_vmrest_server(&mut self, port: Option<u16>) -> VmResult<()> {
+        self.server.kill()?;
         let mut cmd = Command::new(&self.executable_path);
         if let Some(port) = port {
-            cmd.args(&["-p", &port.to_string()]);
+            cmd.args(["-p", &port.to_string()]);
         }
-        let (stdout, _) = exec_cmd(&mut cmd)?;
-        for d in stdout.lines() {
+        crate::dbg_cmd(&cmd);
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|x| {
+                VmError::from(ErrorKind::ExecutionFailed(x.to_string()))
+            })?;
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        for d in lines.by_ref().map_while(Result::ok) {
             if let Some(url) = d.strip_prefix("Serving HTTP on ") {
-                self.url = format!("http://{}", url);
+                self.url = format!("http://{}", url.trim());
+                // Keeps reading the log so that the server doesn't fail to write to the pipe.
+                std::thread::spawn(move || lines.for_each(drop));
+                *self.server.0.lock().unwrap() = Some(child);
                 return Ok(());
             }
         }
+        let _ = child.kill();
+        let _ = child.wait();
         vmerr!(Repr::Unknown("Failed to start a server".to_string()))
     }
 
+    /// Kills the vmrest server started by [`VmRest::start_vmrest_server`], if any.
+    pub fn close(&self) -> VmResult<()> { self.server.kill() }
+
     /// Creates a vmrest API server account using `vmrest -C`.
     pub fn setup_user(&self, username: &str, password: &str) -> VmResult<()> {
         match Command::new(&self.executable_path).arg("-C").spawn() {
@@ -149,10 +336,14 @@ impl VmRest {
         } else {
             v
         };
-        match v.send() {
+        let command = match v.try_clone().and_then(|x| x.build().ok()) {
+            Some(req) => format!("{} {}", req.method(), req.url().path()),
+            None => String::new(),
+        };
+        self.hooks.run(&command, &[], || match v.send() {
             Ok(x) => Self::handle_response(x, &self.encoding),
             Err(x) => vmerr!(ErrorKind::ExecutionFailed(x.to_string())),
-        }
+        })
     }
 
     pub fn get_client(&self) -> VmResult<reqwest::blocking::Client> {
@@ -175,7 +366,7 @@ impl VmRest {
             Err(x) => {
                 return vmerr!(Repr::Unknown(format!(
                     "Failed to convert error: {}",
-                    x.to_string()
+                    x
                 )));
             }
         };
@@ -197,7 +388,8 @@ impl VmRest {
 
         let ts = s.trim();
         if ts == "404 page not found" {
-            return vmerr!(ErrorKind::UnsupportedCommand);
+            // The endpoint doesn't exist in this version of vmrest.
+            return vmerr!(ErrorKind::unsupported::<Self>(ts));
         }
         match serde_json::from_str::<VmRestFailedResponse>(ts) {
             Ok(x) => Err(Self::handle_json_error(&x.message)),
@@ -249,47 +441,140 @@ impl VmRest {
         vmerr!(ErrorKind::VmNotFound)
     }
 
+    /// Gets the path to the .vmx file of the VM.
+    fn get_vm_path(&self) -> VmResult<String> {
+        let id = self.get_vm_id()?;
+        self.get_vms()?
+            .into_iter()
+            .find(|x| x.id.as_deref() == Some(id))
+            .and_then(|x| x.path)
+            .ok_or_else(|| VmError::from(ErrorKind::VmNotFound))
+    }
+
     fn get_vm_id(&self) -> VmResult<&str> {
         self.vm_id
             .as_deref()
             .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
     }
 
+    /// Gets the vmrest version (e.g., `vmrest 1.2.1 build-18811640`).
     pub fn version(&self) -> VmResult<String> {
+        Ok(self.get_swagger()?.info.description)
+    }
+
+    /// Probes the API version and the endpoints available on the server.
+    pub fn capabilities(&self) -> VmResult<VmRestCapabilities> {
+        Ok(VmRestCapabilities::from(self.get_swagger()?))
+    }
+
+    fn get_swagger(&self) -> VmResult<Swagger> {
         let cli = self.get_client()?;
-        let v = cli.get(&format!("{}/json/swagger.json", self.url));
+        let v = cli.get(format!("{}/json/swagger.json", self.url));
         let s = self.execute(v)?;
-
-        fn find<'a>(s: &'a str, pat: &str) -> VmResult<&'a str> {
-            match s.find(pat) {
-                Some(x) => Ok(&s[x + pat.len()..]),
-                None => vmerr!(ErrorKind::UnexpectedResponse(s.to_string())),
-            }
-        }
-        let s = find(&s, "description\"")?;
-        let s = find(s, "\"")?;
-        let m = s.find(',').unwrap();
-        Ok(s[..m - 1].to_string())
+        deserialize(&s)
     }
 
     pub fn get_vms(&self) -> VmResult<Vec<Vm>> {
         let cli = self.get_client()?;
-        let v = cli.get(&format!("{}/api/vms", self.url));
+        let v = cli.get(format!("{}/api/vms", self.url));
         let s = self.execute(v)?;
         deserialize(&s)
     }
 
+    /// Creates a full clone of the VM with `POST /api/vms` and returns it.
+    ///
+    /// VMRest doesn't support linked clones, snapshots and the path of the new VM.
+    pub fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+        let _op = self.hooks.operation("clone_vm");
+        if options.ty != CloneType::Full
+            || options.snapshot.is_some()
+            || options.path.is_some()
+        {
+            return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "clone_vm",
+                "Use CloneType::Full without snapshot and path instead"
+            ));
+        }
+        let cli = self.get_client()?;
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Req<'a> {
+            name: &'a str,
+            parent_id: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct Resp {
+            id: String,
+        }
+        let v = cli
+            .post(format!("{}/api/vms", self.url))
+            .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
+            .body(Self::serialize(&Req {
+                name: &options.name,
+                parent_id: self.get_vm_id()?,
+            })?);
+        let s = self.execute(v)?;
+        let r: Resp = deserialize(&s)?;
+        let path = self
+            .get_vms()?
+            .into_iter()
+            .find(|x| x.id.as_deref() == Some(r.id.as_str()))
+            .and_then(|x| x.path);
+        Ok(Vm {
+            id: Some(r.id),
+            name: Some(options.name.clone()),
+            path,
+        })
+    }
+
+    /// Registers the VM whose .vmx file is at `path` with `POST /api/vms/registration` and returns it.
+    ///
+    /// The VM is registered with the display name in the .vmx file, or the file name if it has none.
+    pub fn register_vm(&self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("register_vm");
+        let name = Self::get_display_name_from_vmx(path).unwrap_or_else(|| {
+            std::path::Path::new(path)
+                .file_stem()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        let cli = self.get_client()?;
+        #[derive(Serialize)]
+        struct Req<'a> {
+            name: &'a str,
+            path: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct Resp {
+            id: String,
+            path: String,
+        }
+        let v = cli
+            .post(format!("{}/api/vms/registration", self.url))
+            .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
+            .body(Self::serialize(&Req { name: &name, path })?);
+        let r: Resp = deserialize(&self.execute(v)?)?;
+        Ok(Vm {
+            id: Some(r.id),
+            name: Some(name),
+            path: Some(r.path),
+        })
+    }
+
+    /// Deletes the VM and its files with `DELETE /api/vms/{id}`.
     pub fn delete_vm(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_vm");
         let cli = self.get_client()?;
         let v =
-            cli.delete(&format!("{}/api/vms/{}", self.url, self.get_vm_id()?));
+            cli.delete(format!("{}/api/vms/{}", self.url, self.get_vm_id()?));
         let s = self.execute(v)?;
         deserialize(&s)
     }
 
     pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
         let cli = self.get_client()?;
-        let v = cli.get(&format!(
+        let v = cli.get(format!(
             "{}/api/vms/{}/power",
             self.url,
             self.get_vm_id()?
@@ -300,11 +585,26 @@ impl VmRest {
             power_state: String,
         }
         let r: Resp = deserialize(&s)?;
-        match r.power_state.as_str() {
-            "poweredOn" => Ok(VmPowerState::Running),
-            "poweredOff" => Ok(VmPowerState::Stopped),
-            "suspended" => Ok(VmPowerState::Suspended),
-            x => vmerr!(ErrorKind::UnexpectedResponse(x.to_string())),
+        to_power_state(&r.power_state).ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse(r.power_state))
+        })
+    }
+
+    /// Watches the power state of the VM by polling `/power` every `interval`.
+    ///
+    /// `interval` is raised to [`MIN_WATCH_INTERVAL`] and a random jitter is added to each poll, so watchers started together don't poll in lockstep.
+    /// See [`PowerStateWatcher`] for details.
+    pub fn watch_power_state(
+        &self,
+        interval: Duration,
+    ) -> PowerStateWatcher<'_> {
+        PowerStateWatcher {
+            cmd: self,
+            interval: interval.max(MIN_WATCH_INTERVAL),
+            last: None,
+            polled: false,
+            // xorshift requires a non-zero seed.
+            rng: RandomState::new().build_hasher().finish() | 1,
         }
     }
 
@@ -314,7 +614,7 @@ impl VmRest {
     ) -> VmResult<VmPowerState> {
         let cli = self.get_client()?;
         let v = cli
-            .put(&format!("{}/api/vms/{}/power", self.url, self.get_vm_id()?))
+            .put(format!("{}/api/vms/{}/power", self.url, self.get_vm_id()?))
             .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
             .body(state.to_command());
         let s = self.execute(v)?;
@@ -323,23 +623,76 @@ impl VmRest {
             power_state: String,
         }
         let r: Resp = deserialize(&s)?;
-        match r.power_state.as_str() {
-            "poweredOn" => Ok(VmPowerState::Running),
-            "poweredOff" => Ok(VmPowerState::Stopped),
-            "suspended" => Ok(VmPowerState::Suspended),
-            x => {
-                vmerr!(ErrorKind::UnexpectedResponse(format!(
-                    "set_power_state: {}",
-                    x
-                )))
-            }
+        to_power_state(&r.power_state).ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse(format!(
+                "set_power_state: {}",
+                r.power_state
+            )))
+        })
+    }
+
+    /// Gets the number of processors and the memory size in MB of the VM with `GET /api/vms/{id}`.
+    pub fn get_vm_settings(&self) -> VmResult<(u32, u64)> {
+        let cli = self.get_client()?;
+        let v = cli.get(format!("{}/api/vms/{}", self.url, self.get_vm_id()?));
+        let s = self.execute(v)?;
+        #[derive(Deserialize)]
+        struct Cpu {
+            processors: u32,
+        }
+        #[derive(Deserialize)]
+        struct Resp {
+            cpu: Cpu,
+            memory: u64,
+        }
+        let r: Resp = deserialize(&s)?;
+        Ok((r.cpu.processors, r.memory))
+    }
+
+    /// Gets the detailed information of the VM.
+    ///
+    /// The number of processors, the memory size and the power state come from the API, and the others from the .vmx file.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let mut info = read_vmx_info(&self.get_vm_path()?)?;
+        let (processors, memory) = self.get_vm_settings()?;
+        info.cpu_count = Some(processors);
+        info.memory_mb = Some(memory);
+        info.power_state = Some(self.get_power_state()?);
+        Ok(info)
+    }
+
+    /// Changes the number of processors and the memory size in MB of the VM with `PUT /api/vms/{id}`.
+    ///
+    /// `None` parameters are left unchanged.
+    pub fn update_vm_settings(
+        &self,
+        processors: Option<u32>,
+        memory_mb: Option<u64>,
+    ) -> VmResult<()> {
+        let cli = self.get_client()?;
+        #[derive(Serialize)]
+        struct Req {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            processors: Option<u32>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            memory: Option<u64>,
         }
+        let v = cli
+            .put(format!("{}/api/vms/{}", self.url, self.get_vm_id()?))
+            .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
+            .body(Self::serialize(&Req {
+                processors,
+                memory: memory_mb,
+            })?);
+        self.execute(v)?;
+        Ok(())
     }
 
     pub fn get_ip_address(&self) -> VmResult<String> {
         let cli = self.get_client()?;
         let v =
-            cli.get(&format!("{}/api/vms/{}/ip", self.url, self.get_vm_id()?));
+            cli.get(format!("{}/api/vms/{}/ip", self.url, self.get_vm_id()?));
         let s = self.execute(v)?;
         #[derive(Deserialize)]
         struct Resp {
@@ -349,10 +702,23 @@ impl VmRest {
         Ok(r.ip)
     }
 
+    /// Gets the IPv4 and IPv6 addresses of all NICs of the VM.
+    pub fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        let _op = self.hooks.operation("get_ip_addresses");
+        let cli = self.get_client()?;
+        let v = cli.get(format!(
+            "{}/api/vms/{}/nicips",
+            self.url,
+            self.get_vm_id()?
+        ));
+        parse_nic_ips(&self.execute(v)?)
+    }
+
     pub fn list_nics(&self) -> VmResult<Vec<Nic>> {
+        let _op = self.hooks.operation("list_nics");
         let cli = self.get_client()?;
         let v =
-            cli.get(&format!("{}/api/vms/{}/nic", self.url, self.get_vm_id()?));
+            cli.get(format!("{}/api/vms/{}/nic", self.url, self.get_vm_id()?));
         let s = self.execute(v)?;
 
         #[derive(Deserialize)]
@@ -382,7 +748,7 @@ impl VmRest {
             vmnet: Option<String>,
         }
         let v = cli
-            .post(&format!("{}/api/vms/{}/nic", self.url, self.get_vm_id()?))
+            .post(format!("{}/api/vms/{}/nic", self.url, self.get_vm_id()?))
             .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
             .body(Self::serialize({
                 let (ty, vmnet) = match ty {
@@ -408,6 +774,7 @@ impl VmRest {
     }
 
     pub fn update_nic(&self, index: i32, ty: &NicType) -> VmResult<()> {
+        let _op = self.hooks.operation("update_nic");
         let cli = self.get_client()?;
         #[derive(Serialize)]
         struct Req {
@@ -416,7 +783,7 @@ impl VmRest {
             vmnet: Option<String>,
         }
         let v = cli
-            .put(&format!(
+            .put(format!(
                 "{}/api/vms/{}/nic/{}",
                 self.url,
                 self.get_vm_id()?,
@@ -448,7 +815,7 @@ impl VmRest {
 
     pub fn delete_nic(&self, index: i32) -> VmResult<()> {
         let cli = self.get_client()?;
-        let v = cli.delete(&format!(
+        let v = cli.delete(format!(
             "{}/api/vms/{}/nic/{}",
             self.url,
             self.get_vm_id()?,
@@ -459,8 +826,9 @@ impl VmRest {
     }
 
     pub fn list_shared_folders(&self) -> VmResult<Vec<SharedFolder>> {
+        let _op = self.hooks.operation("list_shared_folders");
         let cli = self.get_client()?;
-        let v = cli.get(&format!(
+        let v = cli.get(format!(
             "{}/api/vms/{}/sharedfolders",
             self.url,
             self.get_vm_id()?
@@ -495,7 +863,7 @@ impl VmRest {
             flags: i32,
         }
         let v = cli
-            .post(&format!(
+            .post(format!(
                 "{}/api/vms/{}/sharedfolders",
                 self.url,
                 self.get_vm_id()?
@@ -521,6 +889,7 @@ impl VmRest {
         host_path: &str,
         is_readonly: bool,
     ) -> VmResult<()> {
+        let _op = self.hooks.operation("mount_shared_folder");
         self.mount_shared_folders(&[&SharedFolder {
             id: Some(folder_id.to_string()),
             name: None,
@@ -531,8 +900,9 @@ impl VmRest {
     }
 
     pub fn delete_shared_folder(&self, folder_id: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_shared_folder");
         let cli = self.get_client()?;
-        let v = cli.delete(&format!(
+        let v = cli.delete(format!(
             "{}/api/vms/{}/sharedfolders/{}",
             self.url,
             self.get_vm_id()?,
@@ -542,6 +912,94 @@ impl VmRest {
         Ok(())
     }
 
+    /// Gets the virtual networks of the host.
+    pub fn get_vmnets(&self) -> VmResult<Vec<HostNetwork>> {
+        let cli = self.get_client()?;
+        let v = cli.get(format!("{}/api/vmnets", self.url));
+        parse_vmnets(&self.execute(v)?)
+    }
+
+    /// Creates the virtual network `name`, e.g., `vmnet2`, of `ty` (`hostOnly` or `nat`).
+    ///
+    /// VMware assigns the subnet of the network.
+    pub fn create_vmnet(&self, name: &str, ty: &str) -> VmResult<()> {
+        let cli = self.get_client()?;
+        #[derive(Serialize)]
+        struct Req<'a> {
+            name: &'a str,
+            #[serde(rename(serialize = "type"))]
+            ty: &'a str,
+        }
+        let v = cli
+            .post(format!("{}/api/vmnets", self.url))
+            .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
+            .body(Self::serialize(&Req { name, ty })?);
+        self.execute(v)?;
+        Ok(())
+    }
+
+    /// Gets the port forwarding rules of the virtual network `vmnet`, e.g., `vmnet8`.
+    pub fn get_port_forwards(&self, vmnet: &str) -> VmResult<Vec<PortForward>> {
+        let cli = self.get_client()?;
+        let v =
+            cli.get(format!("{}/api/vmnet/{}/portforward", self.url, vmnet));
+        parse_port_forwards(&self.execute(v)?)
+    }
+
+    /// Adds or updates the port forwarding rule of `rule.protocol` and `rule.host_port` in the virtual network `vmnet`.
+    ///
+    /// `rule.name` is used as the description of the rule.
+    pub fn update_port_forward(
+        &self,
+        vmnet: &str,
+        rule: &PortForward,
+        guest_ip: &str,
+    ) -> VmResult<()> {
+        let cli = self.get_client()?;
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Req<'a> {
+            guest_ip: &'a str,
+            guest_port: u16,
+            desc: &'a str,
+        }
+        let v = cli
+            .put(format!(
+                "{}/api/vmnet/{}/portforward/{}/{}",
+                self.url,
+                vmnet,
+                rule.protocol.as_str(),
+                rule.host_port
+            ))
+            .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
+            .body(Self::serialize(&Req {
+                guest_ip,
+                guest_port: rule.guest_port,
+                desc: rule.name.as_deref().unwrap_or(""),
+            })?);
+        self.execute(v)?;
+        Ok(())
+    }
+
+    /// Deletes the port forwarding rule of `protocol` and `host_port` from the virtual network `vmnet`.
+    pub fn delete_port_forward(
+        &self,
+        vmnet: &str,
+        protocol: Protocol,
+        host_port: u16,
+    ) -> VmResult<()> {
+        let cli = self.get_client()?;
+        let v = cli.delete(format!(
+            "{}/api/vmnet/{}/portforward/{}/{}",
+            self.url,
+            vmnet,
+            protocol.as_str(),
+            host_port
+        ));
+        self.execute(v)?;
+        Ok(())
+    }
+
     pub fn get_display_name(&self) -> VmResult<String> {
         self.get_display_name_by_id(self.get_vm_id()?)
     }
@@ -561,7 +1019,7 @@ impl VmRest {
         use std::io::{BufRead, BufReader};
         // Return `None` if the vmx file cannot be opened.
         if let Ok(f) = std::fs::File::open(path) {
-            for l in BufReader::new(f).lines().flatten() {
+            for l in BufReader::new(f).lines().map_while(Result::ok) {
                 if let Some(dn) = l.strip_prefix("displayName = \"") {
                     if dn.is_empty() {
                         // broken?
@@ -576,12 +1034,234 @@ impl VmRest {
     }
 
     fn is_running_result(&self) -> VmResult<()> {
-        if !self.get_power_state()?.is_running() {
-            vmerr!(ErrorKind::InvalidPowerState(VmPowerState::NotRunning))
+        self.running_result(self.get_power_state()?)
+    }
+
+    /// Returns [`ErrorKind::InvalidPowerState`] if `state` is not running.
+    ///
+    /// The error contains `state` in the power state validation mode, and [`VmPowerState::NotRunning`] otherwise.
+    fn running_result(&self, state: VmPowerState) -> VmResult<()> {
+        match state {
+            VmPowerState::Running => Ok(()),
+            x if self.hooks.is_validating_power_state() => {
+                vmerr!(ErrorKind::InvalidPowerState(x))
+            }
+            _ => vmerr!(ErrorKind::InvalidPowerState(VmPowerState::NotRunning)),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl VmRest {
+    pub fn get_async_client(&self) -> VmResult<reqwest::Client> {
+        match self.proxy {
+            Some(ref x) => Ok(reqwest::Client::builder()
+                .proxy(reqwest::Proxy::http(x).unwrap())
+                .build()
+                .unwrap()),
+            None => Ok(reqwest::Client::new()),
+        }
+    }
+
+    async fn execute_async(
+        &self,
+        operation: &str,
+        v: reqwest::RequestBuilder,
+    ) -> VmResult<String> {
+        let v = v.header("Accept", "application/vnd.vmware.vmw.rest-v1+json");
+        let v = if let Some(x) = &self.username {
+            v.basic_auth(x, self.password.as_ref())
         } else {
-            Ok(())
+            v
+        };
+        let command = match v.try_clone().and_then(|x| x.build().ok()) {
+            Some(req) => format!("{} {}", req.method(), req.url().path()),
+            None => String::new(),
+        };
+        self.hooks
+            .run_async(operation, &command, &[], async {
+                let resp = v.send().await.map_err(|x| {
+                    VmError::from(ErrorKind::ExecutionFailed(x.to_string()))
+                })?;
+                let is_success = resp.status() == StatusCode::OK;
+                let text = resp
+                    .text_with_charset(&self.encoding)
+                    .await
+                    .map_err(|x| {
+                        VmError::from(Repr::Unknown(format!(
+                            "Failed to convert error: {}",
+                            x
+                        )))
+                    })?;
+                if is_success {
+                    Ok(text)
+                } else {
+                    Self::handle_error(text)
+                }
+            })
+            .await
+    }
+
+    /// An async variant of [`VmRest::get_power_state`].
+    pub async fn get_power_state_async(&self) -> VmResult<VmPowerState> {
+        let cli = self.get_async_client()?;
+        let v = cli.get(format!(
+            "{}/api/vms/{}/power",
+            self.url,
+            self.get_vm_id()?
+        ));
+        let s = self.execute_async("get_power_state", v).await?;
+        #[derive(Deserialize)]
+        struct Resp {
+            power_state: String,
+        }
+        let r: Resp = deserialize(&s)?;
+        to_power_state(&r.power_state).ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse(r.power_state))
+        })
+    }
+
+    /// An async variant of [`VmRest::set_power_state`].
+    pub async fn set_power_state_async(
+        &self,
+        state: &VmRestPowerCommand,
+    ) -> VmResult<VmPowerState> {
+        let cli = self.get_async_client()?;
+        let v = cli
+            .put(format!("{}/api/vms/{}/power", self.url, self.get_vm_id()?))
+            .header("Content-Type", "application/vnd.vmware.vmw.rest-v1+json")
+            .body(state.to_command());
+        let s = self.execute_async(state.operation(), v).await?;
+        #[derive(Deserialize)]
+        struct Resp {
+            power_state: String,
         }
+        let r: Resp = deserialize(&s)?;
+        to_power_state(&r.power_state).ok_or_else(|| {
+            VmError::from(ErrorKind::UnexpectedResponse(format!(
+                "set_power_state: {}",
+                r.power_state
+            )))
+        })
+    }
+
+    async fn is_running_result_async(&self) -> VmResult<()> {
+        self.running_result(self.get_power_state_async().await?)
+    }
+}
+
+fn to_power_state(s: &str) -> Option<VmPowerState> {
+    match s {
+        "poweredOn" => Some(VmPowerState::Running),
+        "poweredOff" => Some(VmPowerState::Stopped),
+        "suspended" => Some(VmPowerState::Suspended),
+        _ => None,
+    }
+}
+
+/// Parses the response of `GET /api/vms/{id}/nicips`.
+fn parse_nic_ips(s: &str) -> VmResult<Vec<GuestIpAddress>> {
+    #[derive(Deserialize)]
+    struct NicIp {
+        mac: Option<String>,
+        #[serde(default)]
+        ip: Vec<String>,
+    }
+    #[derive(Deserialize)]
+    struct Resp {
+        #[serde(default)]
+        nics: Vec<NicIp>,
     }
+    let r: Resp = deserialize(s)?;
+    Ok(r.nics
+        .iter()
+        .flat_map(|nic| {
+            nic.ip.iter().filter_map(move |ip| {
+                GuestIpAddress::parse(nic.mac.as_deref(), ip)
+            })
+        })
+        .collect())
+}
+
+/// Parses the response of `GET /api/vmnets`.
+fn parse_vmnets(s: &str) -> VmResult<Vec<HostNetwork>> {
+    #[derive(Deserialize)]
+    struct Vmnet {
+        name: String,
+        #[serde(rename(deserialize = "type"))]
+        ty: String,
+        dhcp: Option<String>,
+        subnet: Option<String>,
+        mask: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Resp {
+        #[serde(default)]
+        vmnets: Vec<Vmnet>,
+    }
+    let r: Resp = deserialize(s)?;
+    Ok(r.vmnets
+        .into_iter()
+        .filter_map(|x| {
+            let kind = match x.ty.as_str() {
+                "hostOnly" => HostNetworkKind::HostOnly,
+                "nat" => HostNetworkKind::Nat,
+                "bridged" => HostNetworkKind::Bridged,
+                _ => return None,
+            };
+            let subnet = match (&x.subnet, &x.mask) {
+                (Some(ip), Some(mask)) => match (ip.parse(), mask.parse()) {
+                    (Ok(ip), Ok(mask)) => hostnet::netmask_to_prefix(mask)
+                        .map(|x| hostnet::to_cidr(ip, x)),
+                    _ => None,
+                },
+                _ => None,
+            };
+            Some(HostNetwork {
+                name: x.name,
+                kind,
+                subnet,
+                host_ip: None,
+                dhcp: x.dhcp.map(|x| x == "true"),
+            })
+        })
+        .collect())
+}
+
+/// Parses the response of `GET /api/vmnet/{vmnet}/portforward`.
+fn parse_port_forwards(s: &str) -> VmResult<Vec<PortForward>> {
+    #[derive(Deserialize)]
+    struct Guest {
+        ip: String,
+        port: u16,
+    }
+    #[derive(Deserialize)]
+    struct Rule {
+        port: u16,
+        protocol: String,
+        #[serde(default)]
+        desc: String,
+        guest: Guest,
+    }
+    #[derive(Deserialize)]
+    struct Resp {
+        #[serde(default)]
+        port_forwardings: Vec<Rule>,
+    }
+    let r: Resp = deserialize(s)?;
+    r.port_forwardings
+        .into_iter()
+        .map(|x| {
+            Ok(PortForward {
+                name: Some(x.desc).filter(|x| !x.is_empty()),
+                protocol: x.protocol.parse()?,
+                host_ip: None,
+                host_port: x.port,
+                guest_ip: Some(x.guest.ip),
+                guest_port: x.guest.port,
+            })
+        })
+        .collect()
 }
 
 fn expected_power_state(
@@ -596,80 +1276,132 @@ fn expected_power_state(
 }
 
 impl VmCmd for VmRest {
-    fn list_vms(&self) -> VmResult<Vec<Vm>> { self.get_vms() }
+    fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        self.get_vms()
+    }
 
-    fn set_vm_by_id(&mut self, id: &str) -> VmResult<()> {
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
         for vm in self.get_vms()? {
             if id == vm.id.as_deref().expect("Failed to get id") {
-                self.vm_id = vm.id;
-                return Ok(());
+                self.vm_id = vm.id.clone();
+                return Ok(vm);
             }
         }
         vmerr!(ErrorKind::VmNotFound)
     }
 
     /// `name` is the name of a VM as displayed in the GUI, not the `.vmx` file name.
-    fn set_vm_by_name(&mut self, name: &str) -> VmResult<()> {
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
         for vm in self.get_vms()? {
             let path = vm.path.as_deref().unwrap();
             // Ignore if the vmx file cannot be opened.
             if let Some(display_name) = Self::get_display_name_from_vmx(path) {
                 if name == display_name {
-                    self.vm_id = vm.id;
-                    return Ok(());
+                    self.vm_id = vm.id.clone();
+                    return Ok(Vm {
+                        name: Some(display_name),
+                        ..vm
+                    });
                 }
             }
         }
         vmerr!(ErrorKind::VmNotFound)
     }
 
-    fn set_vm_by_path(&mut self, path: &str) -> VmResult<()> {
-        self.vm_id = Some(self.get_vm_id_by_path(path)?);
-        Ok(())
+    /// Matches the display names of VMs, which are read from their `.vmx` files.
+    fn set_vm_by_name_matching(
+        &mut self,
+        pattern: &str,
+        m: NameMatch,
+    ) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name_matching");
+        let vms = self.get_vms()?.into_iter().filter_map(|vm| {
+            let name =
+                Self::get_display_name_from_vmx(vm.path.as_deref().unwrap())?;
+            Some(Vm {
+                name: Some(name),
+                ..vm
+            })
+        });
+        let vm = find_vm_by_name(vms, pattern, m)?;
+        self.vm_id = vm.id.clone();
+        Ok(vm)
+    }
+
+    fn set_vm_by_path(&mut self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        let id = self.get_vm_id_by_path(path)?;
+        self.vm_id = Some(id.clone());
+        Ok(Vm {
+            id: Some(id),
+            name: Self::get_display_name_from_vmx(path),
+            path: Some(path.to_string()),
+        })
     }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
 }
 
 impl PowerCmd for VmRest {
     fn start(&self) -> VmResult<()> {
-        if self.get_power_state()?.is_running() {
-            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
-        }
-        expected_power_state(
-            self.set_power_state(&VmRestPowerCommand::On),
-            VmPowerState::Running,
-        )
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            if self.get_power_state()?.is_running() {
+                return vmerr!(ErrorKind::InvalidPowerState(
+                    VmPowerState::Running
+                ));
+            }
+            expected_power_state(
+                self.set_power_state(&VmRestPowerCommand::On),
+                VmPowerState::Running,
+            )
+        })
     }
 
     fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
-        let timeout = timeout.into();
-        let s = Instant::now();
-        self.is_running_result()?;
-        loop {
-            match self.set_power_state(&VmRestPowerCommand::Shutdown) {
-                Ok(VmPowerState::Stopped) => return Ok(()),
-                Ok(VmPowerState::Running) => { /* Does nothing */ }
-                Ok(x) => return vmerr!(ErrorKind::InvalidPowerState(x)),
-                Err(x) => return Err(x),
-            }
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.is_running_result()?;
+            loop {
+                match self.set_power_state(&VmRestPowerCommand::Shutdown) {
+                    Ok(VmPowerState::Stopped) => {
+                        return self.wait_for_stop(
+                            timeout.map(|x| x.saturating_sub(s.elapsed())),
+                        )
+                    }
+                    Ok(VmPowerState::Running) => { /* Does nothing */ }
+                    Ok(x) => return vmerr!(ErrorKind::InvalidPowerState(x)),
+                    Err(x) => return Err(x),
+                }
 
-            if let Some(timeout) = timeout {
-                if s.elapsed() >= timeout {
-                    return vmerr!(ErrorKind::Timeout);
+                if let Some(timeout) = timeout {
+                    if s.elapsed() >= timeout {
+                        return vmerr!(ErrorKind::Timeout);
+                    }
                 }
+                std::thread::sleep(Duration::from_millis(200));
             }
-            std::thread::sleep(Duration::from_millis(200));
-        }
+        })
     }
 
     fn hard_stop(&self) -> VmResult<()> {
-        self.is_running_result()?;
-        expected_power_state(
-            self.set_power_state(&VmRestPowerCommand::Off),
-            VmPowerState::Stopped,
-        )
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.is_running_result()?;
+            expected_power_state(
+                self.set_power_state(&VmRestPowerCommand::Off),
+                VmPowerState::Stopped,
+            )
+        })
     }
 
     fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
         self.is_running_result()?;
         expected_power_state(
             self.set_power_state(&VmRestPowerCommand::Suspend),
@@ -677,33 +1409,258 @@ impl PowerCmd for VmRest {
         )
     }
 
-    fn resume(&self) -> VmResult<()> { self.start() }
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        self.start()
+    }
 
     fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
         Ok(self.get_power_state()? == VmPowerState::Running)
     }
 
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
     fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
         self.is_running_result()?;
         self.stop(timeout)?;
         self.start()
     }
 
     fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
         self.is_running_result()?;
         let _ = self.hard_stop();
         self.start()
     }
 
-    fn pause(&self) -> VmResult<()> { vmerr!(ErrorKind::UnsupportedCommand) }
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "pause",
+            "Use suspend instead"
+        ))
+    }
+
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        vmerr!(ErrorKind::unsupported::<Self>("unpause"))
+    }
+}
+
+#[cfg(feature = "async")]
+impl asynchronous::AsyncPowerCmd for VmRest {
+    fn start(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            let r = async {
+                if self.get_power_state_async().await?.is_running() {
+                    return vmerr!(ErrorKind::InvalidPowerState(
+                        VmPowerState::Running
+                    ));
+                }
+                expected_power_state(
+                    self.set_power_state_async(&VmRestPowerCommand::On).await,
+                    VmPowerState::Running,
+                )
+            }
+            .await;
+            self.hooks.settle_started(|| r)
+        })
+    }
+
+    fn stop(&self, timeout: Option<Duration>) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            let r = async {
+                let s = Instant::now();
+                self.is_running_result_async().await?;
+                loop {
+                    match self
+                        .set_power_state_async(&VmRestPowerCommand::Shutdown)
+                        .await
+                    {
+                        Ok(VmPowerState::Stopped) => {
+                            return asynchronous::AsyncPowerCmd::wait_for_stop(
+                                self,
+                                timeout.map(|x| x.saturating_sub(s.elapsed())),
+                            )
+                            .await
+                        }
+                        Ok(VmPowerState::Running) => { /* Does nothing */ }
+                        Ok(x) => {
+                            return vmerr!(ErrorKind::InvalidPowerState(x))
+                        }
+                        Err(x) => return Err(x),
+                    }
+
+                    if let Some(timeout) = timeout {
+                        if s.elapsed() >= timeout {
+                            return vmerr!(ErrorKind::Timeout);
+                        }
+                    }
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+            }
+            .await;
+            self.hooks.settle_stopped(|| r)
+        })
+    }
+
+    fn hard_stop(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            let r = async {
+                self.is_running_result_async().await?;
+                expected_power_state(
+                    self.set_power_state_async(&VmRestPowerCommand::Off).await,
+                    VmPowerState::Stopped,
+                )
+            }
+            .await;
+            self.hooks.settle_stopped(|| r)
+        })
+    }
+
+    fn suspend(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            self.is_running_result_async().await?;
+            expected_power_state(
+                self.set_power_state_async(&VmRestPowerCommand::Suspend)
+                    .await,
+                VmPowerState::Suspended,
+            )
+        })
+    }
+
+    fn resume(&self) -> BoxFuture<'_, VmResult<()>> {
+        asynchronous::AsyncPowerCmd::start(self)
+    }
+
+    fn is_running(&self) -> BoxFuture<'_, VmResult<bool>> {
+        Box::pin(async move {
+            Ok(self.get_power_state_async().await? == VmPowerState::Running)
+        })
+    }
+
+    fn reboot(&self, timeout: Option<Duration>) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            self.is_running_result_async().await?;
+            asynchronous::AsyncPowerCmd::stop(self, timeout).await?;
+            asynchronous::AsyncPowerCmd::start(self).await
+        })
+    }
+
+    fn hard_reboot(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async move {
+            self.is_running_result_async().await?;
+            let _ = asynchronous::AsyncPowerCmd::hard_stop(self).await;
+            asynchronous::AsyncPowerCmd::start(self).await
+        })
+    }
+
+    fn pause(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async {
+            vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "pause",
+                "Use suspend instead"
+            ))
+        })
+    }
+
+    fn unpause(&self) -> BoxFuture<'_, VmResult<()>> {
+        Box::pin(async { vmerr!(ErrorKind::unsupported::<Self>("unpause")) })
+    }
+}
+
+/// VMRest supports only the number of processors.
+impl ConfigCmd for VmRest {
+    fn get_cpu_tuning(&self) -> VmResult<CpuTuning> {
+        let _op = self.hooks.operation("get_cpu_tuning");
+        Ok(CpuTuning {
+            count: Some(self.get_vm_settings()?.0),
+            ..CpuTuning::default()
+        })
+    }
 
-    fn unpause(&self) -> VmResult<()> { vmerr!(ErrorKind::UnsupportedCommand) }
+    fn set_cpu_tuning(&self, tuning: &CpuTuning) -> VmResult<()> {
+        let _op = self.hooks.operation("set_cpu_tuning");
+        if tuning.reserve.is_some()
+            || tuning.maximum.is_some()
+            || tuning.relative_weight.is_some()
+        {
+            return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "set_cpu_tuning",
+                "Set only count"
+            ));
+        }
+        self.update_vm_settings(tuning.count, None)
+    }
+
+    fn get_memory_size(&self) -> VmResult<u64> {
+        let _op = self.hooks.operation("get_memory_size");
+        Ok(self.get_vm_settings()?.1)
+    }
+
+    fn set_memory_size(&self, size_mb: u64) -> VmResult<()> {
+        let _op = self.hooks.operation("set_memory_size");
+        self.update_vm_settings(None, Some(size_mb))
+    }
+}
+
+/// VMRest has no API for the CD/DVD drive, so this edits the .vmx file and the VM must not be running.
+impl MediaCmd for VmRest {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        write_vmx_cdrom(self, &self.get_vm_path()?, Some(path))
+    }
+
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        write_vmx_cdrom(self, &self.get_vm_path()?, None)
+    }
+}
+
+impl GuestNetworkCmd for VmRest {
+    fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        Self::get_ip_addresses(self)
+    }
+}
+
+impl CloneCmd for VmRest {
+    fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+        Self::clone_vm(self, options)
+    }
+}
+
+impl VmLifecycleCmd for VmRest {
+    /// Due to the specification of VMRest, VmRest does not support this function.
+    fn create_vm(&self, _spec: &VmSpec) -> VmResult<Vm> {
+        let _op = self.hooks.operation("create_vm");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "create_vm",
+            "Use VmCli::create_vm and register_vm instead"
+        ))
+    }
+
+    fn register_vm(&self, path: &str) -> VmResult<Vm> {
+        Self::register_vm(self, path)
+    }
+
+    /// Due to the specification of VMRest, VmRest does not support this function.
+    fn unregister_vm(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unregister_vm");
+        vmerr!(ErrorKind::unsupported::<Self>("unregister_vm"))
+    }
+
+    fn delete_vm(&self) -> VmResult<()> { Self::delete_vm(self) }
 }
 
 impl NicCmd for VmRest {
     fn list_nics(&self) -> VmResult<Vec<Nic>> { VmRest::list_nics(self) }
 
     fn add_nic(&self, nic: &Nic) -> VmResult<()> {
+        let _op = self.hooks.operation("add_nic");
         if let Some(ty) = &nic.ty {
             VmRest::create_nic(self, ty)?;
         } else {
@@ -725,6 +1682,7 @@ impl NicCmd for VmRest {
     }
 
     fn remove_nic(&self, nic: &Nic) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_nic");
         if let Some(index) = &nic.id {
             self.delete_nic(index.parse().unwrap_or(0))
         } else {
@@ -733,6 +1691,73 @@ impl NicCmd for VmRest {
     }
 }
 
+/// Manages the rules of the NAT virtual network [`NAT_HOST_NETWORK`], which are shared by all the VMs on the host.
+///
+/// A rule without `guest_ip` forwards to the IP address of the VM.
+impl PortForwardCmd for VmRest {
+    fn list_port_forwards(&self) -> VmResult<Vec<PortForward>> {
+        let _op = self.hooks.operation("list_port_forwards");
+        self.get_port_forwards(NAT_HOST_NETWORK)
+    }
+
+    fn add_port_forward(&self, rule: &PortForward) -> VmResult<()> {
+        let _op = self.hooks.operation("add_port_forward");
+        let guest_ip = match &rule.guest_ip {
+            Some(x) => x.clone(),
+            None => self.get_ip_address()?,
+        };
+        self.update_port_forward(NAT_HOST_NETWORK, rule, &guest_ip)
+    }
+
+    fn remove_port_forward(&self, rule: &PortForward) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_port_forward");
+        self.delete_port_forward(
+            NAT_HOST_NETWORK,
+            rule.protocol,
+            rule.host_port,
+        )
+    }
+}
+
+/// Manages vmnets.
+///
+/// VMware assigns the subnets and the DHCP servers of new networks, and the REST API can't delete networks.
+impl HostNetworkCmd for VmRest {
+    fn list_host_networks(&self) -> VmResult<Vec<HostNetwork>> {
+        let _op = self.hooks.operation("list_host_networks");
+        self.get_vmnets()
+    }
+
+    fn create_host_network(&self, net: &HostNetwork) -> VmResult<HostNetwork> {
+        let _op = self.hooks.operation("create_host_network");
+        let ty = match net.kind {
+            HostNetworkKind::HostOnly => "hostOnly",
+            HostNetworkKind::Nat => "nat",
+            _ => {
+                return vmerr!(ErrorKind::unsupported::<Self>(
+                    "create_host_network"
+                ))
+            }
+        };
+        self.create_vmnet(&net.name, ty)?;
+        Ok(self
+            .get_vmnets()?
+            .into_iter()
+            .find(|x| x.name == net.name)
+            .unwrap_or_else(|| net.clone()))
+    }
+
+    /// Due to the specification of the VMware REST API, VmRest does not support this function.
+    fn delete_host_network(&self, _net: &HostNetwork) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_host_network");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "delete_host_network",
+            "Remove the network with the Virtual Network Editor or `vnetlib64 \
+             -- remove adapter <vmnet>`"
+        ))
+    }
+}
+
 impl SharedFolderCmd for VmRest {
     fn list_shared_folders(&self) -> VmResult<Vec<SharedFolder>> {
         VmRest::list_shared_folders(self)
@@ -743,6 +1768,7 @@ impl SharedFolderCmd for VmRest {
     }
 
     fn unmount_shared_folder(&self, shfs: &SharedFolder) -> VmResult<()> {
+        let _op = self.hooks.operation("unmount_shared_folder");
         SharedFolderCmd::delete_shared_folder(self, shfs)
     }
 
@@ -754,3 +1780,147 @@ impl SharedFolderCmd for VmRest {
         }
     }
 }
+
+#[test]
+fn test_capabilities() {
+    let s = r#"{
+  "swagger": "2.0",
+  "info": {
+    "description": "vmrest 1.2.1 build-18811640",
+    "title": "VMware Workstation REST API",
+    "version": "1.2.1"
+  },
+  "basePath": "/api",
+  "paths": {
+    "/vmnet": {},
+    "/vmnets": {},
+    "/vms": {},
+    "/vms/{id}/ip": {},
+    "/vms/{id}/nic": {},
+    "/vms/{id}/power": {}
+  }
+}"#;
+    let sw: Swagger = deserialize(s).unwrap();
+    assert_eq!(sw.info.description, "vmrest 1.2.1 build-18811640");
+    let c = VmRestCapabilities::from(sw);
+    assert_eq!(c.api_version, "1.2.1");
+    assert!(c.has_vmnet());
+    assert!(c.has_nic());
+    assert!(c.has_ip_address());
+    assert!(c.has_path("/api/vms/{id}/power"));
+    assert!(!c.has_shared_folders());
+}
+
+#[test]
+fn test_parse_nic_ips() {
+    let s = r#"{
+  "nics": [
+    {
+      "mac": "00:0c:29:12:34:56",
+      "ip": ["192.168.10.128/24", "fe80::20c:29ff:fe12:3456/64"],
+      "dns": {}
+    },
+    { "mac": "00:0c:29:12:34:60", "ip": [] }
+  ]
+}"#;
+    let v = parse_nic_ips(s).unwrap();
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].interface.as_deref(), Some("00:0c:29:12:34:56"));
+    assert_eq!(
+        v[0].address,
+        "192.168.10.128".parse::<std::net::IpAddr>().unwrap()
+    );
+    assert!(v[1].address.is_ipv6());
+    assert!(parse_nic_ips("{}").unwrap().is_empty());
+}
+
+#[test]
+fn test_jittered() {
+    let d = Duration::from_secs(4);
+    assert_eq!(jittered(d, 0), d);
+    assert_eq!(jittered(d, 1000), d);
+    assert_eq!(jittered(d, 500), Duration::from_millis(4500));
+    assert!(jittered(d, 999) < Duration::from_secs(5));
+    let vm = VmRest::new();
+    let w = vm.watch_power_state(Duration::from_millis(10));
+    assert_eq!(w.interval, MIN_WATCH_INTERVAL);
+}
+
+#[test]
+fn test_parse_port_forwards() {
+    let s = r#"{
+  "num": 2,
+  "port_forwardings": [
+    {
+      "port": 8080,
+      "protocol": "tcp",
+      "desc": "web",
+      "guest": { "ip": "192.168.80.128", "port": 80 }
+    },
+    {
+      "port": 5353,
+      "protocol": "udp",
+      "desc": "",
+      "guest": { "ip": "192.168.80.129", "port": 53 }
+    }
+  ]
+}"#;
+    let v = parse_port_forwards(s).unwrap();
+    assert_eq!(v.len(), 2);
+    assert_eq!(
+        v[0],
+        PortForward {
+            name: Some("web".to_string()),
+            guest_ip: Some("192.168.80.128".to_string()),
+            ..PortForward::new(Protocol::Tcp, 8080, 80)
+        }
+    );
+    assert_eq!(v[1].protocol, Protocol::Udp);
+    assert_eq!(v[1].name, None);
+    assert!(parse_port_forwards(r#"{"num": 0}"#).unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_vmnets() {
+    let s = r#"{
+  "num": 3,
+  "vmnets": [
+    {
+      "name": "vmnet0",
+      "type": "bridged",
+      "dhcp": "false",
+      "subnet": "",
+      "mask": ""
+    },
+    {
+      "name": "vmnet1",
+      "type": "hostOnly",
+      "dhcp": "true",
+      "subnet": "192.168.56.0",
+      "mask": "255.255.255.0"
+    },
+    {
+      "name": "vmnet8",
+      "type": "nat",
+      "dhcp": "true",
+      "subnet": "192.168.80.0",
+      "mask": "255.255.255.0"
+    }
+  ]
+}"#;
+    let v = parse_vmnets(s).unwrap();
+    assert_eq!(v.len(), 3);
+    assert_eq!(v[0].kind, HostNetworkKind::Bridged);
+    assert_eq!(v[0].subnet, None);
+    assert_eq!(
+        v[1],
+        HostNetwork {
+            name: "vmnet1".to_string(),
+            kind: HostNetworkKind::HostOnly,
+            subnet: Some("192.168.56.0/24".to_string()),
+            host_ip: None,
+            dhcp: Some(true),
+        }
+    );
+    assert_eq!(v[2].kind, HostNetworkKind::Nat);
+}
@@ -1,9 +1,22 @@
 use crate::{
-    exec_cmd_utf8, get_filename,
+    exec_cmd_utf8, get_filename, join_sh_args, join_windows_args,
+    read_host_temp_file, sanitized_args, tool_path_from_env,
     types::*,
-    vmware::{read_vmware_inventory, read_vmware_preferences},
+    vmware::{
+        read_autostart_config, read_vmware_inventory, read_vmware_preferences,
+        read_vmx_entry, read_vmx_info, read_vmx_serial_ports,
+        read_vmx_shared_folders, vmx_serial_port_entries,
+        write_autostart_config, write_vmx_cdrom, write_vmx_entries,
+        write_vmx_usb_autoconnect, AutoStartEntry, ToolsScriptSettings,
+        DEFAULT_AUTOSTART_CONFIG_PATH, MAX_VMX_SERIAL_PORTS, NAT_HOST_NETWORK,
+    },
+};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
 };
-use std::{borrow::Cow, process::Command, time::Duration};
 
 pub enum HostType {
     Player,
@@ -21,8 +34,10 @@ impl HostType {
     }
 }
 
-impl ToString for HostType {
-    fn to_string(&self) -> String { self.as_str().to_string() }
+impl std::fmt::Display for HostType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
 }
 
 impl<T: AsRef<str>> From<T> for HostType {
@@ -48,13 +63,6 @@ pub enum ReadVar<'a> {
     GuestEnv(&'a str),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-pub struct ProcInfo {
-    pub pid: u32,
-    pub owner: String,
-    pub cmd: String,
-}
-
 #[derive(Debug, Clone)]
 pub struct VmRun {
     host_type: &'static str,
@@ -65,6 +73,8 @@ pub struct VmRun {
     guest_username: Option<String>,
     guest_password: Option<String>,
     gui: bool,
+    autostart_config_path: String,
+    hooks: CommandHooks,
 }
 
 impl Default for VmRun {
@@ -75,13 +85,15 @@ impl VmRun {
     pub fn new() -> Self {
         Self {
             host_type: "ws",
-            executable_path: "vmrun".to_string(),
+            executable_path: tool_path_from_env("HVCTRL_VMRUN_PATH", "vmrun"),
             use_inventory: true,
             vm_path: None,
             vm_password: None,
             guest_username: None,
             guest_password: None,
             gui: true,
+            autostart_config_path: DEFAULT_AUTOSTART_CONFIG_PATH.to_string(),
+            hooks: CommandHooks::new(),
         }
     }
 
@@ -107,6 +119,17 @@ impl VmRun {
     impl_setter!(use_inventory: bool);
     impl_setter!(gui: bool);
 
+    impl_setter!(
+        /// Sets the path to the autostart configuration of VMware Workstation.
+        autostart_config_path: String
+    );
+
+    pub fn get_autostart_config_path(&self) -> &str {
+        &self.autostart_config_path
+    }
+
+    impl_hooks!();
+
     #[inline]
     fn build_auth(&self) -> Vec<&str> {
         let mut v = Vec::with_capacity(6);
@@ -131,8 +154,8 @@ impl VmRun {
     #[inline]
     fn cmd(&self) -> Command {
         let mut cmd = Command::new(&self.executable_path);
-        cmd.args(&["-T", self.host_type]);
-        cmd.args(&self.build_auth());
+        cmd.args(["-T", self.host_type]);
+        cmd.args(self.build_auth());
         cmd
     }
 
@@ -162,7 +185,9 @@ impl VmRun {
             "The VMware Tools are not running in the virtual machine: ",
             ServiceIsNotRunning
         );
-        starts_err!(s, "Unrecognized command: ", UnsupportedCommand);
+        if let Some(x) = s.strip_prefix("Unrecognized command: ") {
+            return VmError::from(ErrorKind::unsupported::<Self>(x.trim()));
+        }
         VmError::from(Repr::Unknown(format!("Unknown error: {}", s)))
     }
 
@@ -174,18 +199,36 @@ impl VmRun {
         }
     }
 
-    fn exec(cmd: &mut Command) -> VmResult<String> {
-        let (stdout, stderr) = exec_cmd_utf8(cmd)?;
-        if !stderr.is_empty() {
-            Self::check(stderr)
+    fn exec(&self, cmd: &mut Command) -> VmResult<String> {
+        // Skips `-T host_type` and the authentication options.
+        let mut args = sanitized_args(cmd, &["-gp", "-vp"])
+            .split_off(2 + self.build_auth().len());
+        let op = if args.is_empty() {
+            String::new()
         } else {
-            Self::check(stdout)
-        }
+            args.remove(0)
+        };
+        self.hooks.run(&op, &args, || {
+            let o = exec_cmd_utf8(cmd)?;
+            let errors =
+                self.hooks
+                    .classify_stderr(&op, &o.stderr, o.status.success());
+            let r = if !errors.is_empty() {
+                Self::check(errors)
+            } else {
+                Self::check(o.stdout.clone())
+            };
+            // Some queries such as `fileExistsInGuest` report a negative answer with a non-zero exit code.
+            match r {
+                Ok(s) => Ok(s),
+                Err(e) => o.check_status(Err(e)),
+            }
+        })
     }
 
     /// Gets vmrun version, e.g., `vmrun version 1.17.0 build-17801498`.
     pub fn version(&self) -> VmResult<String> {
-        let s = Self::exec(&mut self.cmd())?;
+        let s = self.exec(&mut self.cmd())?;
         let v = s
             .lines()
             .nth(2)
@@ -197,55 +240,95 @@ impl VmRun {
 
     pub fn start_vm(&self, gui: bool) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["start", self.get_vm()?]);
+        cmd.args(["start", self.get_vm()?]);
         if !gui {
             cmd.arg("nogui");
         }
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn stop_vm(&self, hard_stop: Option<bool>) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["stop", self.get_vm()?]);
+        cmd.args(["stop", self.get_vm()?]);
         if let Some(hard_stop) = hard_stop {
-            cmd.arg(if hard_stop { "soft" } else { "hard" });
+            cmd.arg(if hard_stop { "hard" } else { "soft" });
         }
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn reset_vm(&self, hard_stop: Option<bool>) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["reset", self.get_vm()?]);
+        cmd.args(["reset", self.get_vm()?]);
         if let Some(hard_stop) = hard_stop {
-            cmd.arg(if hard_stop { "soft" } else { "hard" });
+            cmd.arg(if hard_stop { "hard" } else { "soft" });
         }
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn suspend_vm(&self, hard_stop: Option<bool>) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["suspend", self.get_vm()?]);
+        cmd.args(["suspend", self.get_vm()?]);
         if let Some(hard_stop) = hard_stop {
-            cmd.arg(if hard_stop { "soft" } else { "hard" });
+            cmd.arg(if hard_stop { "hard" } else { "soft" });
         }
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn pause_vm(&self) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["pause", self.get_vm()?]);
-        Self::exec(&mut cmd)?;
+        cmd.args(["pause", self.get_vm()?]);
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn unpause_vm(&self) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["unpause", self.get_vm()?]);
-        Self::exec(&mut cmd)?;
+        cmd.args(["unpause", self.get_vm()?]);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the VMs started when the host boots in the order in which they are started.
+    pub fn list_autostart_vms(&self) -> VmResult<Vec<AutoStartEntry>> {
+        Ok(read_autostart_config(&self.autostart_config_path)?)
+    }
+
+    /// Gets the autostart setting of the VM, or `None` if the VM is not started when the host boots.
+    pub fn get_autostart(&self) -> VmResult<Option<AutoStartEntry>> {
+        let vm = self.get_vm()?;
+        Ok(self
+            .list_autostart_vms()?
+            .into_iter()
+            .find(|x| x.vmx_path.eq_ignore_ascii_case(vm)))
+    }
+
+    /// Adds the VM to the end of the autostart order, or removes the VM from it.
+    ///
+    /// The VMware Autostart Service must be enabled for the VM to start when the host boots.
+    pub fn set_autostart(&self, enabled: bool) -> VmResult<()> {
+        let vm = self.get_vm()?;
+        let mut v = self.list_autostart_vms()?;
+        let current =
+            v.iter().position(|x| x.vmx_path.eq_ignore_ascii_case(vm));
+        match (current, enabled) {
+            (Some(_), true) | (None, false) => return Ok(()),
+            (Some(i), false) => {
+                v.remove(i);
+            }
+            (None, true) => v.push(AutoStartEntry {
+                vmx_path: vm.to_string(),
+                start_order: 0,
+                start_delay: -1,
+            }),
+        }
+        for (i, x) in v.iter_mut().enumerate() {
+            x.start_order = i as u32 + 1;
+        }
+        write_autostart_config(&self.autostart_config_path, &v)?;
         Ok(())
     }
 
@@ -268,7 +351,7 @@ impl VmRun {
     pub fn list_running_vms(&self) -> VmResult<Vec<Vm>> {
         let mut cmd = self.cmd();
         cmd.arg("list");
-        let s = Self::exec(&mut cmd)?;
+        let s = self.exec(&mut cmd)?;
         let mut l = s.lines();
         let n = match l.next() {
             Some(s) => s
@@ -290,9 +373,10 @@ impl VmRun {
     }
 
     pub fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
         let mut cmd = self.cmd();
-        cmd.args(&["listSnapshots", self.get_vm()?]);
-        let s = Self::exec(&mut cmd)?;
+        cmd.args(["listSnapshots", self.get_vm()?]);
+        let s = self.exec(&mut cmd)?;
         let mut l = s.lines();
         let n = match l.next() {
             Some(s) => s
@@ -308,20 +392,83 @@ impl VmRun {
                 id: None,
                 name: Some(s.to_string()),
                 detail: None,
+                parent_id: None,
+                created_at: None,
+                is_current: None,
+                online: None,
             });
         }
         Ok(ret)
     }
 
+    /// Gets the paths of all snapshots, e.g., `base/child`, using `listSnapshots showTree`.
+    pub fn list_snapshot_paths(&self) -> VmResult<Vec<String>> {
+        let mut cmd = self.cmd();
+        cmd.args(["listSnapshots", self.get_vm()?, "showTree"]);
+        let s = self.exec(&mut cmd)?;
+        Ok(parse_snapshot_tree(&s))
+    }
+
+    /// Gets the snapshot hierarchy using `listSnapshots showTree`.
+    ///
+    /// vmrun reports neither the IDs nor the current snapshot, so the snapshots only have names and `current` is `None`.
+    pub fn snapshot_tree(&self) -> VmResult<SnapshotTree> {
+        let _op = self.hooks.operation("snapshot_tree");
+        let mut cmd = self.cmd();
+        cmd.args(["listSnapshots", self.get_vm()?, "showTree"]);
+        let s = self.exec(&mut cmd)?;
+        Ok(SnapshotTree {
+            roots: parse_snapshot_nodes(&s),
+            current: None,
+        })
+    }
+
+    /// Gets the paths of the snapshots named `name`.
+    pub fn find_snapshot_paths(&self, name: &str) -> VmResult<Vec<String>> {
+        Ok(self
+            .list_snapshot_paths()?
+            .into_iter()
+            .filter(|x| x.rsplit('/').next() == Some(name))
+            .collect())
+    }
+
+    /// Resolves `name` to the unique snapshot path.
+    ///
+    /// `name` can be a snapshot name or a path such as `snapshotName/childName`.
+    /// Returns [`ErrorKind::InvalidParameter`] if `name` matches several snapshots.
+    pub fn find_snapshot_path(&self, name: &str) -> VmResult<String> {
+        let paths = self.list_snapshot_paths()?;
+        if name.contains('/') {
+            return match paths.into_iter().find(|x| x == name) {
+                Some(x) => Ok(x),
+                None => vmerr!(ErrorKind::SnapshotNotFound),
+            };
+        }
+        let mut found = paths
+            .into_iter()
+            .filter(|x| x.rsplit('/').next() == Some(name));
+        match (found.next(), found.next()) {
+            (Some(x), None) => Ok(x),
+            (None, _) => vmerr!(ErrorKind::SnapshotNotFound),
+            (Some(_), Some(_)) => vmerr!(ErrorKind::InvalidParameter(format!(
+                "Snapshot name `{}` is ambiguous",
+                name
+            ))),
+        }
+    }
+
     pub fn is_snapshot_exists(&self, name: &str) -> VmResult<bool> {
+        if name.contains('/') {
+            return Ok(self.list_snapshot_paths()?.iter().any(|x| x == name));
+        }
         let ss = self.list_snapshots()?;
         Ok(ss.iter().any(|x| x.name.as_deref().unwrap() == name))
     }
 
     pub fn snapshot(&self, name: &str) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["snapshot", self.get_vm()?, name]);
-        Self::exec(&mut cmd)?;
+        cmd.args(["snapshot", self.get_vm()?, name]);
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
@@ -330,22 +477,100 @@ impl VmRun {
         name: &str,
         delete_children: bool,
     ) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
         let mut cmd = self.cmd();
-        cmd.args(&["deleteSnapshot", self.get_vm()?, name]);
+        cmd.args(["deleteSnapshot", self.get_vm()?, name]);
         if delete_children {
             cmd.arg("andDeleteChildren");
         }
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
+    /// Clones the VM with `clone`.
+    ///
+    /// The new VM is created at `<options.path>/<name>/<name>.vmx`, where `options.path` defaults to the parent directory of the directory of the VM.
+    pub fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+        let _op = self.hooks.operation("clone_vm");
+        let src = self.get_vm()?;
+        let dst = clone_dst_path(src, options);
+        let dst = dst.to_string_lossy();
+        let mut cmd = self.cmd();
+        cmd.args([
+            "clone",
+            src,
+            &dst,
+            match options.ty {
+                CloneType::Full => "full",
+                CloneType::Linked => "linked",
+            },
+        ]);
+        if let Some(x) = &options.snapshot {
+            cmd.arg(format!("-snapshot={}", x));
+        }
+        cmd.arg(format!("-cloneName={}", options.name));
+        self.exec(&mut cmd)?;
+        Ok(Vm {
+            id: None,
+            name: Some(options.name.clone()),
+            path: Some(dst.into_owned()),
+        })
+    }
+
     pub fn revert_to_snapshot(&self, name: &str) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["revertToSnapshot", self.get_vm()?, name]);
-        Self::exec(&mut cmd)?;
+        cmd.args(["revertToSnapshot", self.get_vm()?, name]);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Gets the network adapters of the VM with `listNetworkAdapters`.
+    pub fn list_network_adapters(&self) -> VmResult<Vec<Nic>> {
+        let mut cmd = self.cmd();
+        cmd.args(["listNetworkAdapters", self.get_vm()?]);
+        let s = self.exec(&mut cmd)?;
+        Ok(parse_network_adapters(&s))
+    }
+
+    /// Adds a network adapter of type `ty` to the VM with `addNetworkAdapter`.
+    pub fn add_network_adapter(&self, ty: &NicType) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["addNetworkAdapter", self.get_vm()?]);
+        cmd.args(Self::nic_type_args(ty));
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
+    /// Changes the type of the network adapter at `index` with `setNetworkAdapter`.
+    pub fn set_network_adapter(
+        &self,
+        index: usize,
+        ty: &NicType,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["setNetworkAdapter", self.get_vm()?, &index.to_string()]);
+        cmd.args(Self::nic_type_args(ty));
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Removes the network adapter at `index` with `deleteNetworkAdapter`.
+    pub fn delete_network_adapter(&self, index: usize) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["deleteNetworkAdapter", self.get_vm()?, &index.to_string()]);
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    fn nic_type_args(ty: &NicType) -> Vec<&str> {
+        match ty {
+            NicType::Bridge => vec!["bridged"],
+            NicType::NAT => vec!["nat"],
+            NicType::HostOnly => vec!["hostonly"],
+            NicType::Custom(x) => vec!["custom", x],
+        }
+    }
+
     pub fn run_program_in_guest(
         &self,
         no_wait: bool,
@@ -354,7 +579,7 @@ impl VmRun {
         program_args: &[&str],
     ) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["runProgramInGuest", self.get_vm()?]);
+        cmd.args(["runProgramInGuest", self.get_vm()?]);
         if no_wait {
             cmd.arg("-noWait");
         }
@@ -365,12 +590,93 @@ impl VmRun {
             cmd.arg("-interactive");
         }
         cmd.args(program_args);
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
+    /// Runs a program in the guest and returns its output.
+    ///
+    /// vmrun cannot capture the output of a guest program, so the output and the exit code are redirected to temporary files in the guest with `cmd.exe` or `/bin/sh` and copied to the host.
+    pub fn run_program_output(
+        &self,
+        guest_args: &[&str],
+    ) -> VmResult<GuestOutput> {
+        self.run_program_with(guest_args, &GuestExecOptions::default())
+    }
+
+    /// Runs a program in the guest with `opts` and returns its output.
+    ///
+    /// `env` and `cwd` are applied by the shell which redirects the output. See [`Self::run_program_output`].
+    /// Returns [`ErrorKind::UnsupportedCommand`] if `timeout` or `elevated` is set.
+    pub fn run_program_with(
+        &self,
+        guest_args: &[&str],
+        opts: &GuestExecOptions,
+    ) -> VmResult<GuestOutput> {
+        if opts.timeout.is_some() || opts.elevated {
+            return vmerr!(ErrorKind::unsupported::<Self>("run_program_with"));
+        }
+        if let Some((k, _)) = opts.env.iter().find(|(k, _)| !is_env_name(k)) {
+            return vmerr!(ErrorKind::InvalidParameter(k.to_string()));
+        }
+        let guest_os = self
+            .read_variable(ReadVar::RuntimeConfig("guestOS"))?
+            .unwrap_or_default();
+        let mut paths = Vec::with_capacity(3);
+        for _ in 0..3 {
+            paths.push(self.create_temp_file_in_guest()?.trim().to_string());
+        }
+        let r = self.run_redirected(
+            is_windows_guest(guest_os.trim()),
+            guest_args,
+            opts,
+            &paths,
+        );
+        for p in &paths {
+            let _ = self.delete_file_in_guest(p);
+        }
+        r
+    }
+
+    fn run_redirected(
+        &self,
+        windows: bool,
+        guest_args: &[&str],
+        opts: &GuestExecOptions,
+        paths: &[String],
+    ) -> VmResult<GuestOutput> {
+        let args = redirected_command(
+            windows, guest_args, opts, &paths[0], &paths[1], &paths[2],
+        );
+        let args: Vec<&str> = args.iter().map(|x| x.as_str()).collect();
+        self.run_program_in_guest(
+            false,
+            opts.active_window,
+            opts.interactive,
+            &args,
+        )?;
+        let mut outputs = Vec::with_capacity(paths.len());
+        for p in paths {
+            let host_path = std::env::temp_dir().join(format!(
+                "hvctrl-{}-{}",
+                std::process::id(),
+                get_filename(p)
+            ));
+            let host_path = host_path.to_string_lossy();
+            self.copy_file_from_guest_to_host(p, &host_path)?;
+            let data = std::fs::read(host_path.as_ref());
+            let _ = std::fs::remove_file(host_path.as_ref());
+            outputs.push(String::from_utf8_lossy(&data?).into_owned());
+        }
+        Ok(GuestOutput {
+            exit_code: outputs[2].trim().parse().ok(),
+            stderr: outputs.remove(1),
+            stdout: outputs.remove(0),
+        })
+    }
+
     pub fn file_exists_in_guest(&self, guest_path: &str) -> VmResult<bool> {
-        let s = Self::exec(self.cmd().args(&[
+        let s = self.exec(self.cmd().args([
             "fileExistsInGuest",
             self.get_vm()?,
             guest_path,
@@ -386,7 +692,7 @@ impl VmRun {
         &self,
         guest_path: &str,
     ) -> VmResult<bool> {
-        let s = Self::exec(self.cmd().args(&[
+        let s = self.exec(self.cmd().args([
             "directoryExistsInGuest",
             self.get_vm()?,
             guest_path,
@@ -405,62 +711,76 @@ impl VmRun {
         writable: bool,
     ) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["setSharedFolderState", name, host_path]);
+        cmd.args(["setSharedFolderState", self.get_vm()?, name, host_path]);
         cmd.arg(if writable { "writable" } else { "readonly" });
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
+    /// Gets the detailed information of the VM from the .vmx file.
+    ///
+    /// `power_state` is either [`VmPowerState::Running`] or [`VmPowerState::NotRunning`].
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let mut info = read_vmx_info(self.get_vm()?)?;
+        info.power_state = Some(if self.is_running()? {
+            VmPowerState::Running
+        } else {
+            VmPowerState::NotRunning
+        });
+        Ok(info)
+    }
+
+    /// Gets the shared folders of the VM from the .vmx file.
+    ///
+    /// Shared folders added only at runtime are not listed.
+    pub fn list_shared_folders(&self) -> VmResult<Vec<SharedFolder>> {
+        let _op = self.hooks.operation("list_shared_folders");
+        Ok(read_vmx_shared_folders(self.get_vm()?)?)
+    }
+
     pub fn add_shared_folder(
         &self,
         name: &str,
         host_path: &str,
     ) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["addSharedFolder", name, host_path]);
-        Self::exec(&mut cmd)?;
+        cmd.args(["addSharedFolder", self.get_vm()?, name, host_path]);
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn remove_shared_folder(&self, name: &str) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["removeSharedFolder", name]);
-        Self::exec(&mut cmd)?;
+        cmd.args(["removeSharedFolder", self.get_vm()?, name]);
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
-    pub fn enable_shared_folders(
-        &self,
-        name: &str,
-        only_runtime: bool,
-    ) -> VmResult<()> {
+    pub fn enable_shared_folders(&self, only_runtime: bool) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["enableSharedFolders", name]);
+        cmd.args(["enableSharedFolders", self.get_vm()?]);
         if only_runtime {
             cmd.arg("runtime");
         }
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
-    pub fn disable_shared_folders(
-        &self,
-        name: &str,
-        only_runtime: bool,
-    ) -> VmResult<()> {
+    pub fn disable_shared_folders(&self, only_runtime: bool) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["disableSharedFolders", name]);
+        cmd.args(["disableSharedFolders", self.get_vm()?]);
         if only_runtime {
             cmd.arg("runtime");
         }
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn list_processes_in_guest(&self) -> VmResult<Vec<ProcInfo>> {
-        let s = Self::exec(
-            self.cmd().args(&["listProcessesInGuest", self.get_vm()?]),
-        )?;
+        let _op = self.hooks.operation("list_processes_in_guest");
+        let s = self
+            .exec(self.cmd().args(["listProcessesInGuest", self.get_vm()?]))?;
         let mut l = s.lines();
         let n = match l.next() {
             Some(s) => s
@@ -491,7 +811,8 @@ impl VmRun {
     }
 
     pub fn kill_process_in_guest(&self, pid: u32) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        let _op = self.hooks.operation("kill_process_in_guest");
+        self.exec(self.cmd().args([
             "killProcessInGuest",
             self.get_vm()?,
             &pid.to_string(),
@@ -500,7 +821,7 @@ impl VmRun {
     }
 
     pub fn delete_file_in_guest(&self, guest_path: &str) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        self.exec(self.cmd().args([
             "deleteFileInGuest",
             self.get_vm()?,
             guest_path,
@@ -509,7 +830,7 @@ impl VmRun {
     }
 
     pub fn create_directory_in_guest(&self, guest_path: &str) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        self.exec(self.cmd().args([
             "createDirectoryInGuest",
             self.get_vm()?,
             guest_path,
@@ -518,7 +839,7 @@ impl VmRun {
     }
 
     pub fn delete_directory_in_guest(&self, guest_path: &str) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        self.exec(self.cmd().args([
             "deleteDirectoryInGuest",
             self.get_vm()?,
             guest_path,
@@ -530,9 +851,8 @@ impl VmRun {
     ///
     /// Returns the path to the temp file.
     pub fn create_temp_file_in_guest(&self) -> VmResult<String> {
-        let s = Self::exec(
-            self.cmd().args(&["createTempFileInGuest", self.get_vm()?]),
-        )?;
+        let s = self
+            .exec(self.cmd().args(["createTempFileInGuest", self.get_vm()?]))?;
         Ok(s)
     }
 
@@ -540,7 +860,7 @@ impl VmRun {
         &self,
         guest_path: &str,
     ) -> VmResult<Vec<String>> {
-        let s = Self::exec(self.cmd().args(&[
+        let s = self.exec(self.cmd().args([
             "listDirectoryInGuest",
             self.get_vm()?,
             guest_path,
@@ -553,7 +873,7 @@ impl VmRun {
         host_path: &str,
         guest_path: &str,
     ) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        self.exec(self.cmd().args([
             "CopyFileFromHostToGuest",
             self.get_vm()?,
             host_path,
@@ -567,7 +887,7 @@ impl VmRun {
         guest_path: &str,
         host_path: &str,
     ) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        self.exec(self.cmd().args([
             "CopyFileFromGuestToHost",
             self.get_vm()?,
             guest_path,
@@ -581,7 +901,7 @@ impl VmRun {
         old_path: &str,
         new_path: &str,
     ) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        self.exec(self.cmd().args([
             "renameFileInGuest",
             self.get_vm()?,
             old_path,
@@ -591,7 +911,7 @@ impl VmRun {
     }
 
     pub fn type_keystrokes_in_guest(&self, keystroke: &str) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        self.exec(self.cmd().args([
             "typeKeystrokesInGuest",
             self.get_vm()?,
             keystroke,
@@ -600,7 +920,8 @@ impl VmRun {
     }
 
     pub fn capture_screen(&self, host_path: &str) -> VmResult<()> {
-        Self::exec(self.cmd().args(&[
+        let _op = self.hooks.operation("capture_screen");
+        self.exec(self.cmd().args([
             "captureScreen",
             self.get_vm()?,
             host_path,
@@ -610,52 +931,143 @@ impl VmRun {
 
     pub fn write_variable(&self, variable: WriteVar) -> VmResult<()> {
         let mut cmd = self.cmd();
-        cmd.args(&["writeVariable", self.get_vm()?]);
+        cmd.args(["writeVariable", self.get_vm()?]);
         match variable {
             WriteVar::GuestVar(name, value) => {
-                cmd.args(&["guestVar", name, value])
+                cmd.args(["guestVar", name, value])
             }
             WriteVar::RuntimeConfig(name, value) => {
-                cmd.args(&["runtimeConfig", name, value])
+                cmd.args(["runtimeConfig", name, value])
             }
             WriteVar::GuestEnv(name, value) => {
-                cmd.args(&["guestEnv", name, value])
+                cmd.args(["guestEnv", name, value])
             }
         };
-        Self::exec(&mut cmd)?;
+        self.exec(&mut cmd)?;
         Ok(())
     }
 
     pub fn read_variable(&self, variable: ReadVar) -> VmResult<Option<String>> {
         let mut cmd = self.cmd();
-        cmd.args(&["readVariable", self.get_vm()?]);
+        cmd.args(["readVariable", self.get_vm()?]);
         match variable {
-            ReadVar::GuestVar(name) => cmd.args(&["guestVar", name]),
-            ReadVar::RuntimeConfig(name) => cmd.args(&["runtimeConfig", name]),
-            ReadVar::GuestEnv(name) => cmd.args(&["guestEnv", name]),
+            ReadVar::GuestVar(name) => cmd.args(["guestVar", name]),
+            ReadVar::RuntimeConfig(name) => cmd.args(["runtimeConfig", name]),
+            ReadVar::GuestEnv(name) => cmd.args(["guestEnv", name]),
         };
-        let s = Self::exec(&mut cmd)?;
+        let s = self.exec(&mut cmd)?;
         Ok(if s.is_empty() { None } else { Some(s) })
     }
 
+    /// Writes `entries` to the .vmx file of the VM, which must not be running.
+    fn edit_vmx(&self, entries: &[(&str, &str)]) -> VmResult<()> {
+        if self.is_running()? {
+            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
+        }
+        write_vmx_entries(self.get_vm()?, entries)
+    }
+
+    /// Sets the options of VMware Tools for power operations.
+    ///
+    /// Writes the runtime config if the VM is running, or the .vmx file otherwise.
+    pub fn set_tools_scripts(
+        &self,
+        settings: &ToolsScriptSettings,
+    ) -> VmResult<()> {
+        let entries = settings.to_entries();
+        if !self.is_running()? {
+            return write_vmx_entries(self.get_vm()?, &entries);
+        }
+        for (key, value) in entries {
+            self.write_variable(WriteVar::RuntimeConfig(key, value))?;
+        }
+        Ok(())
+    }
+
+    /// Sets the resolution of the guest display with `vmware-resolutionSet` of VMware Tools.
+    ///
+    /// The path to the program depends on the OS family read from `guestOS` of the runtime config.
+    pub fn set_guest_resolution(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> VmResult<()> {
+        let guest_os = self
+            .read_variable(ReadVar::RuntimeConfig("guestOS"))?
+            .unwrap_or_default();
+        let (width, height) = (width.to_string(), height.to_string());
+        self.run_program_in_guest(
+            false,
+            false,
+            true,
+            &resolution_set_args(guest_os.trim(), &width, &height),
+        )
+    }
+
     pub fn get_guest_ip_address(&self, wait: bool) -> VmResult<String> {
         let mut cmd = self.cmd();
-        cmd.args(&["getGuestIPAddress", self.get_vm()?]);
+        cmd.args(["getGuestIPAddress", self.get_vm()?]);
         if wait {
             cmd.arg("-wait");
         }
-        let s = Self::exec(&mut cmd)?;
+        let s = self.exec(&mut cmd)?;
         Ok(s)
     }
 
+    /// Gets the port forwarding rules of the host network `host_network`, e.g., `vmnet8`, with `listPortForwardings`.
+    pub fn list_port_forwardings(
+        &self,
+        host_network: &str,
+    ) -> VmResult<Vec<PortForward>> {
+        let s =
+            self.exec(self.cmd().args(["listPortForwardings", host_network]))?;
+        Ok(parse_port_forwardings(&s))
+    }
+
+    /// Adds a port forwarding rule to the host network `host_network` with `setPortForwarding`.
+    ///
+    /// `rule.name` is used as the description of the rule.
+    pub fn set_port_forwarding(
+        &self,
+        host_network: &str,
+        rule: &PortForward,
+        guest_ip: &str,
+    ) -> VmResult<()> {
+        let mut cmd = self.cmd();
+        cmd.args(["setPortForwarding", host_network, rule.protocol.as_str()])
+            .arg(rule.host_port.to_string())
+            .arg(guest_ip)
+            .arg(rule.guest_port.to_string());
+        if let Some(x) = &rule.name {
+            cmd.arg(x);
+        }
+        self.exec(&mut cmd)?;
+        Ok(())
+    }
+
+    /// Removes the port forwarding rule of `protocol` and `host_port` from the host network `host_network` with `deletePortForwarding`.
+    pub fn delete_port_forwarding(
+        &self,
+        host_network: &str,
+        protocol: Protocol,
+        host_port: u16,
+    ) -> VmResult<()> {
+        self.exec(
+            self.cmd()
+                .args(["deletePortForwarding", host_network, protocol.as_str()])
+                .arg(host_port.to_string()),
+        )?;
+        Ok(())
+    }
+
     pub fn install_tools(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&["installTools", self.get_vm()?]))?;
+        self.exec(self.cmd().args(["installTools", self.get_vm()?]))?;
         Ok(())
     }
 
     pub fn check_tools_state(&self) -> VmResult<bool> {
         let s =
-            Self::exec(self.cmd().args(&["checkToolsState", self.get_vm()?]))?;
+            self.exec(self.cmd().args(["checkToolsState", self.get_vm()?]))?;
         match s.as_str() {
             "installed" => Ok(true),
             "unknown" => Ok(false),
@@ -664,60 +1076,101 @@ impl VmRun {
         }
     }
 
+    /// Deletes the VM and its files with `deleteVM`.
     pub fn delete_vm(&self) -> VmResult<()> {
-        Self::exec(self.cmd().args(&["deleteVM", self.get_vm()?]))?;
+        let _op = self.hooks.operation("delete_vm");
+        self.exec(self.cmd().args(["deleteVM", self.get_vm()?]))?;
         Ok(())
     }
 }
 
 impl VmCmd for VmRun {
-    fn list_vms(&self) -> VmResult<Vec<Vm>> { self.list_all_vms() }
+    fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        self.list_all_vms()
+    }
 
     /// Due to the specification of vmrun, VmRun does not support this function.
-    fn set_vm_by_id(&mut self, _id: &str) -> VmResult<()> {
-        vmerr!(ErrorKind::UnsupportedCommand)
+    fn set_vm_by_id(&mut self, _id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_vm_by_id",
+            "Use set_vm_by_name or set_vm_by_path instead"
+        ))
     }
 
-    fn set_vm_by_name(&mut self, name: &str) -> VmResult<()> {
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
         for vm in self.list_vms()? {
             if vm.name.as_deref() == Some(name) {
-                self.vm_path = vm.path;
-                return Ok(());
+                self.vm_path = vm.path.clone();
+                return Ok(vm);
             }
         }
         vmerr!(ErrorKind::VmNotFound)
     }
 
-    fn set_vm_by_path(&mut self, path: &str) -> VmResult<()> {
+    fn set_vm_by_path(&mut self, path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
         for vm in self.list_vms()? {
             if vm.path.as_deref() == Some(path) {
-                self.vm_path = vm.path;
-                return Ok(());
+                self.vm_path = vm.path.clone();
+                return Ok(vm);
             }
         }
         vmerr!(ErrorKind::VmNotFound)
     }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
+
+    fn is_tools_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_tools_running");
+        let s =
+            self.exec(self.cmd().args(["checkToolsState", self.get_vm()?]))?;
+        Ok(s == "running")
+    }
 }
 
 impl PowerCmd for VmRun {
     fn start(&self) -> VmResult<()> {
-        if self.is_running()? {
-            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
-        }
-        self.start_vm(self.gui)
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            if self.is_running()? {
+                return vmerr!(ErrorKind::InvalidPowerState(
+                    VmPowerState::Running
+                ));
+            }
+            self.start_vm(self.gui)
+        })
     }
 
-    fn stop<D: Into<Option<Duration>>>(&self, _timeout: D) -> VmResult<()> {
-        self.stop_vm(Some(false))
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.stop_vm(Some(false))?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
     }
 
-    fn hard_stop(&self) -> VmResult<()> { self.stop_vm(Some(true)) }
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| self.stop_vm(Some(true)))
+    }
 
-    fn suspend(&self) -> VmResult<()> { self.suspend_vm(Some(true)) }
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        self.suspend_vm(Some(false))
+    }
 
-    fn resume(&self) -> VmResult<()> { self.start() }
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        self.start()
+    }
 
     fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
         let vm_path = self.get_vm()?;
         Ok(self
             .list_running_vms()?
@@ -726,14 +1179,24 @@ impl PowerCmd for VmRun {
     }
 
     fn reboot<D: Into<Option<Duration>>>(&self, _timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
         self.reset_vm(Some(false))
     }
 
-    fn hard_reboot(&self) -> VmResult<()> { self.reset_vm(Some(true)) }
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.reset_vm(Some(true))
+    }
 
-    fn pause(&self) -> VmResult<()> { self.pause_vm() }
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        self.pause_vm()
+    }
 
-    fn unpause(&self) -> VmResult<()> { self.unpause_vm() }
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        self.unpause_vm()
+    }
 }
 
 impl SnapshotCmd for VmRun {
@@ -741,33 +1204,438 @@ impl SnapshotCmd for VmRun {
         Self::list_snapshots(self)
     }
 
-    fn take_snapshot(&self, name: &str) -> VmResult<()> { self.snapshot(name) }
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        self.snapshot(name)
+    }
 
     fn revert_snapshot(&self, name: &str) -> VmResult<()> {
-        if !self.is_snapshot_exists(name)? {
-            return vmerr!(ErrorKind::SnapshotNotFound);
-        }
-        self.revert_to_snapshot(name)
+        let _op = self.hooks.operation("revert_snapshot");
+        let path = self.find_snapshot_path(name)?;
+        self.revert_to_snapshot(&path)
     }
 
     fn delete_snapshot(&self, name: &str) -> VmResult<()> {
-        if !self.is_snapshot_exists(name)? {
-            return vmerr!(ErrorKind::SnapshotNotFound);
+        self.hooks.settle_deleted(|| {
+            let path = self.find_snapshot_path(name)?;
+            self.delete_snapshot(&path, true)
+        })
+    }
+
+    fn snapshot_tree(&self) -> VmResult<SnapshotTree> {
+        Self::snapshot_tree(self)
+    }
+}
+
+impl NicCmd for VmRun {
+    fn list_nics(&self) -> VmResult<Vec<Nic>> {
+        let _op = self.hooks.operation("list_nics");
+        self.list_network_adapters()
+    }
+
+    fn add_nic(&self, nic: &Nic) -> VmResult<()> {
+        let _op = self.hooks.operation("add_nic");
+        match &nic.ty {
+            Some(ty) => self.add_network_adapter(ty),
+            None => vmerr!(ErrorKind::InvalidParameter(
+                "ty is required".to_string()
+            )),
+        }
+    }
+
+    fn update_nic(&self, nic: &Nic) -> VmResult<()> {
+        let _op = self.hooks.operation("update_nic");
+        match (nic_index(nic)?, &nic.ty) {
+            (index, Some(ty)) => self.set_network_adapter(index, ty),
+            _ => vmerr!(ErrorKind::InvalidParameter(
+                "id and ty are required".to_string()
+            )),
+        }
+    }
+
+    fn remove_nic(&self, nic: &Nic) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_nic");
+        self.delete_network_adapter(nic_index(nic)?)
+    }
+}
+
+/// The name of a shared folder is taken from `name`, or `id` if `name` is `None`.
+impl SharedFolderCmd for VmRun {
+    fn list_shared_folders(&self) -> VmResult<Vec<SharedFolder>> {
+        Self::list_shared_folders(self)
+    }
+
+    fn mount_shared_folder(&self, shfs: &SharedFolder) -> VmResult<()> {
+        let _op = self.hooks.operation("mount_shared_folder");
+        let (name, host_path) =
+            match (shared_folder_name(shfs), &shfs.host_path) {
+                (Some(name), Some(host_path)) => (name, host_path),
+                _ => {
+                    return vmerr!(ErrorKind::InvalidParameter(
+                        "name and host_path are required".to_string()
+                    ))
+                }
+            };
+        self.add_shared_folder(name, host_path)?;
+        self.set_shared_folder_state(name, host_path, !shfs.is_readonly)
+    }
+
+    fn unmount_shared_folder(&self, shfs: &SharedFolder) -> VmResult<()> {
+        let _op = self.hooks.operation("unmount_shared_folder");
+        SharedFolderCmd::delete_shared_folder(self, shfs)
+    }
+
+    fn delete_shared_folder(&self, shfs: &SharedFolder) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_shared_folder");
+        match shared_folder_name(shfs) {
+            Some(name) => self.remove_shared_folder(name),
+            None => vmerr!(ErrorKind::InvalidParameter(
+                "name is required".to_string()
+            )),
+        }
+    }
+}
+
+fn shared_folder_name(shfs: &SharedFolder) -> Option<&str> {
+    shfs.name.as_deref().or(shfs.id.as_deref())
+}
+
+/// Edits `numvcpus` and `memsize` in the .vmx file, so the VM must not be running to change them.
+///
+/// vmrun supports only the number of processors.
+impl ConfigCmd for VmRun {
+    fn get_cpu_tuning(&self) -> VmResult<CpuTuning> {
+        let _op = self.hooks.operation("get_cpu_tuning");
+        let count = read_vmx_entry(self.get_vm()?, "numvcpus")?
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(1);
+        Ok(CpuTuning {
+            count: Some(count),
+            ..CpuTuning::default()
+        })
+    }
+
+    fn set_cpu_tuning(&self, tuning: &CpuTuning) -> VmResult<()> {
+        let _op = self.hooks.operation("set_cpu_tuning");
+        if tuning.reserve.is_some()
+            || tuning.maximum.is_some()
+            || tuning.relative_weight.is_some()
+        {
+            return vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+                "set_cpu_tuning",
+                "Set only count"
+            ));
+        }
+        match tuning.count {
+            Some(x) => self.edit_vmx(&[("numvcpus", &x.to_string())]),
+            None => Ok(()),
+        }
+    }
+
+    fn get_memory_size(&self) -> VmResult<u64> {
+        let _op = self.hooks.operation("get_memory_size");
+        read_vmx_entry(self.get_vm()?, "memsize")?
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| {
+                VmError::from(ErrorKind::UnexpectedResponse(
+                    "memsize is not set".to_string(),
+                ))
+            })
+    }
+
+    fn set_memory_size(&self, size_mb: u64) -> VmResult<()> {
+        let _op = self.hooks.operation("set_memory_size");
+        self.edit_vmx(&[("memsize", &size_mb.to_string())])
+    }
+}
+
+/// Edits the .vmx file, so the VM must not be running.
+impl MediaCmd for VmRun {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        write_vmx_cdrom(self, self.get_vm()?, Some(path))
+    }
+
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        write_vmx_cdrom(self, self.get_vm()?, None)
+    }
+}
+
+impl ScreenshotCmd for VmRun {
+    fn capture_screen(&self) -> VmResult<Vec<u8>> {
+        read_host_temp_file("screenshot.png", |p| Self::capture_screen(self, p))
+    }
+}
+
+/// Edits the autoconnect rules `usb.autoConnect.deviceN` in the .vmx file, so the VM must not be running.
+///
+/// The IDs are `VID:PID` in hexadecimal, e.g., `046d:c52b`, and the device is connected when the VM starts or the device is plugged in.
+impl UsbCmd for VmRun {
+    /// Due to the specification of vmrun, VmRun does not support this function.
+    fn list_host_usb_devices(&self) -> VmResult<Vec<UsbDevice>> {
+        let _op = self.hooks.operation("list_host_usb_devices");
+        vmerr!(ErrorKind::unsupported::<Self>("list_host_usb_devices"))
+    }
+
+    fn attach_usb_device(&self, id: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_usb_device");
+        if self.is_running()? {
+            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
         }
-        self.delete_snapshot(name, true)
+        write_vmx_usb_autoconnect(self.get_vm()?, id, true)
+    }
+
+    fn detach_usb_device(&self, id: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("detach_usb_device");
+        if self.is_running()? {
+            return vmerr!(ErrorKind::InvalidPowerState(VmPowerState::Running));
+        }
+        write_vmx_usb_autoconnect(self.get_vm()?, id, false)
+    }
+}
+
+/// Manages the rules of the NAT host network [`NAT_HOST_NETWORK`], which are shared by all the VMs on the host.
+///
+/// A rule without `guest_ip` forwards to the IP address of the VM.
+impl PortForwardCmd for VmRun {
+    fn list_port_forwards(&self) -> VmResult<Vec<PortForward>> {
+        let _op = self.hooks.operation("list_port_forwards");
+        self.list_port_forwardings(NAT_HOST_NETWORK)
+    }
+
+    fn add_port_forward(&self, rule: &PortForward) -> VmResult<()> {
+        let _op = self.hooks.operation("add_port_forward");
+        let guest_ip = match &rule.guest_ip {
+            Some(x) => x.clone(),
+            None => self.get_guest_ip_address(false)?,
+        };
+        self.set_port_forwarding(NAT_HOST_NETWORK, rule, &guest_ip)
+    }
+
+    fn remove_port_forward(&self, rule: &PortForward) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_port_forward");
+        self.delete_port_forwarding(
+            NAT_HOST_NETWORK,
+            rule.protocol,
+            rule.host_port,
+        )
+    }
+}
+
+/// Edits `serialN.*` in the .vmx file, so the VM must not be running to change them.
+impl SerialPortCmd for VmRun {
+    fn list_serial_ports(&self) -> VmResult<Vec<SerialPort>> {
+        let _op = self.hooks.operation("list_serial_ports");
+        Ok(read_vmx_serial_ports(self.get_vm()?)?)
+    }
+
+    fn set_serial_port(&self, n: usize, mode: &SerialPortMode) -> VmResult<()> {
+        let _op = self.hooks.operation("set_serial_port");
+        if !(1..=MAX_VMX_SERIAL_PORTS).contains(&n) {
+            return vmerr!(ErrorKind::InvalidParameter(
+                "n must be the serial port number".to_string()
+            ));
+        }
+        let entries = vmx_serial_port_entries(n, mode).ok_or_else(|| {
+            VmError::from(ErrorKind::unsupported_with_hint::<Self>(
+                "set_serial_port",
+                "Use SerialPortMode::PipeServer instead of TCP",
+            ))
+        })?;
+        let entries: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        self.edit_vmx(&entries)
+    }
+}
+
+impl KeyboardCmd for VmRun {
+    fn type_text(&self, text: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("type_text");
+        self.type_keystrokes_in_guest(text)
+    }
+
+    /// Due to the specification of vmrun, VmRun does not support this function.
+    fn send_keys(&self, _keys: &[Key]) -> VmResult<()> {
+        let _op = self.hooks.operation("send_keys");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "send_keys",
+            "Use type_text instead"
+        ))
+    }
+}
+
+impl CloneCmd for VmRun {
+    fn clone_vm(&self, options: &CloneOptions) -> VmResult<Vm> {
+        Self::clone_vm(self, options)
+    }
+}
+
+/// Destroys the clones of [`Vm::path`] with `stop hard` and `deleteVM`.
+impl TemplateCmd for VmRun {
+    fn destroy_vms(&self, vms: &[Vm]) -> VmResult<()> {
+        let _op = self.hooks.operation("destroy_vms");
+        self.destroy_vms_with(vms, |vm| {
+            let mut cmd = self.clone();
+            cmd.vm_path(vm.path.clone());
+            cmd
+        })
+    }
+}
+
+impl VmLifecycleCmd for VmRun {
+    /// Due to the specification of vmrun, VmRun does not support this function.
+    fn create_vm(&self, _spec: &VmSpec) -> VmResult<Vm> {
+        let _op = self.hooks.operation("create_vm");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "create_vm",
+            "Use VmCli::create_vm instead"
+        ))
+    }
+
+    /// Due to the specification of vmrun, VmRun does not support this function.
+    fn register_vm(&self, _path: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("register_vm");
+        vmerr!(ErrorKind::unsupported::<Self>("register_vm"))
+    }
+
+    /// Due to the specification of vmrun, VmRun does not support this function.
+    fn unregister_vm(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unregister_vm");
+        vmerr!(ErrorKind::unsupported::<Self>("unregister_vm"))
+    }
+
+    fn delete_vm(&self) -> VmResult<()> { Self::delete_vm(self) }
+}
+
+/// Gets the adapter index from `nic.id`.
+fn nic_index(nic: &Nic) -> VmResult<usize> {
+    nic.id
+        .as_deref()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| {
+            VmError::from(ErrorKind::InvalidParameter(
+                "id is required".to_string(),
+            ))
+        })
+}
+
+/// Creates a temp file with `createTempfileInGuest` and replaces it with a directory.
+impl GuestTempDirCmd for VmRun {
+    fn create_temp_dir_in_guest(&self) -> VmResult<String> {
+        let _op = self.hooks.operation("create_temp_dir_in_guest");
+        let path = self.create_temp_file_in_guest()?.trim().to_string();
+        self.delete_file_in_guest(&path)?;
+        self.create_directory_in_guest(&path)?;
+        Ok(path)
+    }
+
+    fn remove_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_dir_in_guest");
+        self.delete_directory_in_guest(guest_path)
+    }
+}
+
+/// vmrun reports only one address of the guest.
+impl GuestNetworkCmd for VmRun {
+    fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        let _op = self.hooks.operation("get_ip_addresses");
+        let s = self.get_guest_ip_address(false)?;
+        match GuestIpAddress::parse(None, s.trim()) {
+            Some(x) => Ok(vec![x]),
+            None => vmerr!(ErrorKind::UnexpectedResponse(s)),
+        }
+    }
+}
+
+impl GuestProcessCmd for VmRun {
+    fn list_processes_in_guest(&self) -> VmResult<Vec<ProcInfo>> {
+        Self::list_processes_in_guest(self)
+    }
+
+    fn kill_process_in_guest(&self, pid: u32) -> VmResult<()> {
+        Self::kill_process_in_guest(self, pid)
+    }
+}
+
+impl GuestFsCmd for VmRun {
+    fn make_dir_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("make_dir_in_guest");
+        self.create_directory_in_guest(guest_path)
+    }
+
+    fn remove_file_in_guest(&self, guest_path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("remove_file_in_guest");
+        self.delete_file_in_guest(guest_path)
+    }
+
+    /// vmrun cannot get the size of a file, so `size` is always `None`.
+    fn stat_in_guest(
+        &self,
+        guest_path: &str,
+    ) -> VmResult<Option<GuestFileInfo>> {
+        let _op = self.hooks.operation("stat_in_guest");
+        let file_type = if self.directory_exists_in_guest(guest_path)? {
+            GuestFileType::Directory
+        } else if self.file_exists_in_guest(guest_path)? {
+            GuestFileType::File
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(GuestFileInfo {
+            file_type,
+            size: None,
+        }))
+    }
+
+    fn move_in_guest(
+        &self,
+        from_guest_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("move_in_guest");
+        self.rename_file_in_guest(from_guest_path, to_guest_path)
+    }
+
+    fn list_dir_in_guest(&self, guest_path: &str) -> VmResult<Vec<String>> {
+        let _op = self.hooks.operation("list_dir_in_guest");
+        self.list_directory_in_guest(guest_path)
+    }
+
+    fn make_temp_file_in_guest(&self) -> VmResult<String> {
+        let _op = self.hooks.operation("make_temp_file_in_guest");
+        Ok(self.create_temp_file_in_guest()?.trim().to_string())
     }
 }
 
 impl GuestCmd for VmRun {
     fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
+        let _op = self.hooks.operation("exec_cmd");
         self.run_program_in_guest(true, true, false, guest_args)
     }
 
+    fn exec_cmd_output(&self, guest_args: &[&str]) -> VmResult<GuestOutput> {
+        let _op = self.hooks.operation("exec_cmd_output");
+        self.run_program_output(guest_args)
+    }
+
+    fn exec_cmd_with(
+        &self,
+        guest_args: &[&str],
+        opts: &GuestExecOptions,
+    ) -> VmResult<GuestOutput> {
+        let _op = self.hooks.operation("exec_cmd_with");
+        self.run_program_with(guest_args, opts)
+    }
+
     fn copy_from_guest_to_host(
         &self,
         from_guest_path: &str,
         to_host_path: &str,
     ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_guest_to_host");
         if std::path::Path::new(to_host_path).is_dir() {
             let to_host_path = format!(
                 "{}{}{}",
@@ -786,6 +1654,7 @@ impl GuestCmd for VmRun {
         from_host_path: &str,
         to_guest_path: &str,
     ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_host_to_guest");
         fn get_file_name<'a>(
             p: &'a std::path::Path,
             from_host_path: &str,
@@ -829,3 +1698,381 @@ impl GuestCmd for VmRun {
         }
     }
 }
+
+/// Returns true if `guest_os`, the `guestOS` of a VM, is a Windows family.
+fn is_windows_guest(guest_os: &str) -> bool {
+    guest_os.starts_with("win") || guest_os.starts_with("longhorn")
+}
+
+/// Returns true if `name` can be set as an environment variable by both `set` of cmd.exe and sh.
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns the guest command line which runs `guest_args` with its stdout, stderr and exit code redirected to `out`, `err` and `code`.
+///
+/// `env` and `cwd` of `opts` are applied before running `guest_args`.
+fn redirected_command(
+    windows: bool,
+    guest_args: &[&str],
+    opts: &GuestExecOptions,
+    out: &str,
+    err: &str,
+    code: &str,
+) -> Vec<String> {
+    if windows {
+        let mut prefix = String::new();
+        if let Some(x) = &opts.cwd {
+            prefix += &format!(r#"cd /d "{}" && "#, x);
+        }
+        for (k, v) in &opts.env {
+            prefix += &format!(r#"set "{}={}" && "#, k, v);
+        }
+        // `/s` makes cmd.exe strip only the outermost quotes.
+        let script = format!(
+            r#""{}{} > "{}" 2> "{}" & (echo !errorlevel!)> "{}"""#,
+            prefix,
+            join_windows_args(guest_args),
+            out,
+            err,
+            code
+        );
+        vec![
+            r"C:\Windows\System32\cmd.exe".to_string(),
+            "/v:on".to_string(),
+            "/s".to_string(),
+            "/c".to_string(),
+            script,
+        ]
+    } else {
+        let mut prefix = String::new();
+        if let Some(x) = &opts.cwd {
+            prefix += &format!("cd {} && ", join_sh_args(&[x]));
+        }
+        for (k, v) in &opts.env {
+            prefix += &format!("{}={} ", k, join_sh_args(&[v]));
+        }
+        let script = format!(
+            "{}{} > {} 2> {}; echo $? > {}",
+            prefix,
+            join_sh_args(guest_args),
+            join_sh_args(&[out]),
+            join_sh_args(&[err]),
+            join_sh_args(&[code])
+        );
+        vec![
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            join_sh_args(&[&script]),
+        ]
+    }
+}
+
+/// Returns the `vmware-resolutionSet` command line for a guest whose `guestOS` is `guest_os`.
+fn resolution_set_args<'a>(
+    guest_os: &str,
+    width: &'a str,
+    height: &'a str,
+) -> Vec<&'a str> {
+    if is_windows_guest(guest_os) {
+        // Sets the topology of a single display at (0, 0).
+        vec![
+            r"C:\Program Files\VMware\VMware Tools\VMwareResolutionSet.exe",
+            "0",
+            "1",
+            ",",
+            "0",
+            "0",
+            width,
+            height,
+        ]
+    } else if guest_os.starts_with("darwin") {
+        vec![
+            "/Library/Application Support/VMware Tools/vmware-resolutionSet",
+            width,
+            height,
+        ]
+    } else {
+        vec!["/usr/bin/vmware-resolutionSet", width, height]
+    }
+}
+
+/// Parses `listNetworkAdapters`.
+fn parse_network_adapters(s: &str) -> Vec<Nic> {
+    s.lines()
+        .filter_map(|l| {
+            let mut it = l.split_whitespace();
+            let index = it.next()?.parse::<usize>().ok()?;
+            let ty = it.next()?;
+            let vmnet = it.next().map(|x| x.to_string());
+            let ty = match ty {
+                "bridged" => NicType::Bridge,
+                "nat" => NicType::NAT,
+                "hostonly" | "hostOnly" => NicType::HostOnly,
+                _ => NicType::Custom(vmnet.clone().unwrap_or_default()),
+            };
+            Some(Nic {
+                id: Some(index.to_string()),
+                name: vmnet,
+                ty: Some(ty),
+                mac_address: None,
+            })
+        })
+        .collect()
+}
+
+/// Parses the output of `listPortForwardings`.
+///
+/// Each rule is a line containing the protocol, the host port, `<guest IP>:<guest port>` and an optional quoted description.
+fn parse_port_forwardings(s: &str) -> Vec<PortForward> {
+    s.lines()
+        .filter_map(|l| {
+            let (l, desc) = match l.split_once('"') {
+                Some((l, d)) => (l, Some(d.trim_end_matches('"').to_string())),
+                None => (l, None),
+            };
+            let mut it = l
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|x| !x.is_empty() && *x != "->")
+                .skip_while(|x| x.parse::<Protocol>().is_err());
+            let protocol = it.next()?.parse().ok()?;
+            let host_port = it.next()?.parse().ok()?;
+            let (guest_ip, guest_port) = it.next()?.rsplit_once(':')?;
+            Some(PortForward {
+                name: desc.filter(|x| !x.is_empty()),
+                protocol,
+                host_ip: None,
+                host_port,
+                guest_ip: Some(guest_ip.to_string()),
+                guest_port: guest_port.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Returns the path to the .vmx file of a clone of the VM at `src`.
+fn clone_dst_path(src: &str, options: &CloneOptions) -> PathBuf {
+    let dir = match &options.path {
+        Some(x) => PathBuf::from(x),
+        None => Path::new(src)
+            .parent()
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+    dir.join(&options.name)
+        .join(format!("{}.vmx", options.name))
+}
+
+/// Parses `listSnapshots showTree` into snapshot paths.
+///
+/// Children are indented with a tab per depth.
+fn parse_snapshot_tree(s: &str) -> Vec<String> {
+    let mut stack: Vec<&str> = vec![];
+    let mut ret = vec![];
+    for l in s.lines().skip(1) {
+        let name = l.trim_start_matches('\t');
+        if name.is_empty() {
+            continue;
+        }
+        stack.truncate(l.len() - name.len());
+        stack.push(name);
+        ret.push(stack.join("/"));
+    }
+    ret
+}
+
+/// Parses the output of `listSnapshots showTree` into nodes, where each tab of the indentation is a level of the tree.
+fn parse_snapshot_nodes(s: &str) -> Vec<SnapshotNode> {
+    let mut roots: Vec<SnapshotNode> = vec![];
+    for l in s.lines().skip(1) {
+        let name = l.trim_start_matches('\t');
+        if name.is_empty() {
+            continue;
+        }
+        let mut nodes = &mut roots;
+        for _ in 0..l.len() - name.len() {
+            if nodes.is_empty() {
+                break;
+            }
+            let last = nodes.len() - 1;
+            nodes = &mut nodes[last].children;
+        }
+        nodes.push(SnapshotNode {
+            snapshot: Snapshot {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            children: vec![],
+        });
+    }
+    roots
+}
+
+#[test]
+fn test_clone_dst_path() {
+    let mut options = CloneOptions::new("b", CloneType::Linked);
+    assert_eq!(
+        clone_dst_path("/vms/a/a.vmx", &options),
+        Path::new("/vms/b/b.vmx")
+    );
+    options.path = Some("/clones".to_string());
+    assert_eq!(
+        clone_dst_path("/vms/a/a.vmx", &options),
+        Path::new("/clones/b/b.vmx")
+    );
+}
+
+#[test]
+fn test_parse_snapshot_tree() {
+    let s = [
+        "Total snapshots: 4",
+        "base",
+        "\tclean",
+        "\t\tclean",
+        "\tdirty",
+    ]
+    .join("\n");
+    assert_eq!(
+        parse_snapshot_tree(&s),
+        ["base", "base/clean", "base/clean/clean", "base/dirty"]
+    );
+    assert!(parse_snapshot_tree("Total snapshots: 0").is_empty());
+    let nodes = parse_snapshot_nodes(&s);
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0].children.len(), 2);
+    assert_eq!(
+        nodes[0].children[0].children[0].snapshot.name.as_deref(),
+        Some("clean")
+    );
+    assert_eq!(nodes[0].children[1].snapshot.name.as_deref(), Some("dirty"));
+    assert!(parse_snapshot_nodes("Total snapshots: 0").is_empty());
+}
+
+#[test]
+fn test_parse_port_forwardings() {
+    let s = r#"Total port forwardings: 2
+Port forwarding 0: tcp 8080 -> 192.168.80.128:80 "web"
+Port forwarding 1: udp 5353 -> 192.168.80.129:53
+"#;
+    let v = parse_port_forwardings(s);
+    assert_eq!(v.len(), 2);
+    assert_eq!(
+        v[0],
+        PortForward {
+            name: Some("web".to_string()),
+            guest_ip: Some("192.168.80.128".to_string()),
+            ..PortForward::new(Protocol::Tcp, 8080, 80)
+        }
+    );
+    assert_eq!(v[1].protocol, Protocol::Udp);
+    assert_eq!(v[1].name, None);
+    assert_eq!(v[1].guest_port, 53);
+}
+
+#[test]
+fn test_parse_network_adapters() {
+    let s = [
+        "Total network adapters: 3",
+        "INDEX  TYPE         VMNET",
+        "0      nat          vmnet8",
+        "1      hostonly     vmnet1",
+        "2      custom       vmnet2",
+    ]
+    .join("\n");
+    let v = parse_network_adapters(&s);
+    assert_eq!(v.len(), 3);
+    assert_eq!(v[0].id.as_deref(), Some("0"));
+    assert_eq!(v[0].ty, Some(NicType::NAT));
+    assert_eq!(v[1].ty, Some(NicType::HostOnly));
+    assert_eq!(v[2].ty, Some(NicType::Custom("vmnet2".to_string())));
+    assert_eq!(v[2].name.as_deref(), Some("vmnet2"));
+    assert!(parse_network_adapters("Total network adapters: 0").is_empty());
+}
+
+#[test]
+fn test_resolution_set_args() {
+    assert_eq!(
+        resolution_set_args("windows9-64", "1920", "1080"),
+        [
+            r"C:\Program Files\VMware\VMware Tools\VMwareResolutionSet.exe",
+            "0",
+            "1",
+            ",",
+            "0",
+            "0",
+            "1920",
+            "1080"
+        ]
+    );
+    assert_eq!(
+        resolution_set_args("darwin19-64", "1280", "800")[0],
+        "/Library/Application Support/VMware Tools/vmware-resolutionSet"
+    );
+    assert_eq!(
+        resolution_set_args("ubuntu-64", "1024", "768"),
+        ["/usr/bin/vmware-resolutionSet", "1024", "768"]
+    );
+}
+
+#[test]
+fn test_redirected_command() {
+    assert_eq!(
+        redirected_command(
+            true,
+            &["ipconfig", "/all"],
+            &GuestExecOptions::default(),
+            "o",
+            "e",
+            "c"
+        ),
+        [
+            r"C:\Windows\System32\cmd.exe",
+            "/v:on",
+            "/s",
+            "/c",
+            r#""ipconfig /all > "o" 2> "e" & (echo !errorlevel!)> "c"""#
+        ]
+    );
+    let v = redirected_command(
+        false,
+        &["echo", "a b"],
+        &GuestExecOptions::default(),
+        "/o",
+        "/e",
+        "/c",
+    );
+    assert_eq!(v[..2], ["/bin/sh", "-c"]);
+    assert_eq!(
+        v[2],
+        join_sh_args(&["'echo' 'a b' > '/o' 2> '/e'; echo $? > '/c'"])
+    );
+    let opts = GuestExecOptions {
+        env: vec![("LANG".to_string(), "C".to_string())],
+        cwd: Some("/tmp".to_string()),
+        ..GuestExecOptions::default()
+    };
+    let v = redirected_command(false, &["pwd"], &opts, "/o", "/e", "/c");
+    assert_eq!(
+        v[2],
+        join_sh_args(&[
+            "cd '/tmp' && LANG='C' 'pwd' > '/o' 2> '/e'; echo $? > '/c'"
+        ])
+    );
+    let v = redirected_command(true, &["cd"], &opts, "o", "e", "c");
+    assert_eq!(
+        v[4],
+        r#""cd /d "/tmp" && set "LANG=C" && cd > "o" 2> "e" & (echo !errorlevel!)> "c"""#
+    );
+}
+
+#[test]
+fn test_is_env_name() {
+    assert!(is_env_name("PATH"));
+    assert!(is_env_name("_a1"));
+    assert!(!is_env_name("1A"));
+    assert!(!is_env_name("A=B"));
+    assert!(!is_env_name(""));
+}
@@ -0,0 +1,1282 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! [vSphere Automation REST API](https://developer.vmware.com/apis/vsphere-automation/latest/) controller for vCenter and ESXi.
+//!
+//! Snapshots are not exposed by the Automation API, so they are managed with the [VI/JSON API](https://developer.vmware.com/apis/vi-json/latest/) (vSphere 8.0 or later).
+use crate::{deserialize, parse_timestamp, types::*};
+use reqwest::{
+    blocking::RequestBuilder,
+    header::{HeaderMap, HeaderValue},
+    Method,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const SESSION_HEADER: &str = "vmware-api-session-id";
+
+/// Represents vSphere REST API client.
+///
+/// Sessions are created on the first request and shared between clones.
+/// An expired session is created again once per request.
+#[derive(Clone, Debug)]
+pub struct VSphere {
+    url: String,
+    vm_id: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    guest_username: Option<String>,
+    guest_password: Option<String>,
+    proxy: Option<String>,
+    accept_invalid_certs: bool,
+    vim_release: String,
+    task_timeout: Duration,
+    session: Arc<Mutex<Option<String>>>,
+    vim_session: Arc<Mutex<Option<String>>>,
+    hooks: CommandHooks,
+}
+
+/// Deletes the sessions on a separate thread when the last clone sharing them is dropped.
+///
+/// The blocking client of reqwest can't be used in an async runtime, which drops [`crate::asynchronous::Blocking`].
+impl Drop for VSphere {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.session) != 1 {
+            return;
+        }
+        let take = |x: &Mutex<Option<String>>| x.lock().ok()?.take();
+        let (session, vim_session) =
+            (take(&self.session), take(&self.vim_session));
+        if session.is_none() && vim_session.is_none() {
+            return;
+        }
+        let mut this = Self::new();
+        this.url = self.url.clone();
+        this.proxy = self.proxy.clone();
+        this.accept_invalid_certs = self.accept_invalid_certs;
+        this.vim_release = self.vim_release.clone();
+        this.session = Arc::new(Mutex::new(session));
+        this.vim_session = Arc::new(Mutex::new(vim_session));
+        this.hooks = self.hooks.clone();
+        std::thread::spawn(move || {
+            let _ = this.close();
+        });
+    }
+}
+
+impl Default for VSphere {
+    fn default() -> Self { Self::new() }
+}
+
+impl VSphere {
+    pub fn new() -> Self {
+        Self {
+            url: "https://127.0.0.1".to_string(),
+            vm_id: None,
+            username: None,
+            password: None,
+            guest_username: None,
+            guest_password: None,
+            proxy: None,
+            accept_invalid_certs: false,
+            vim_release: "8.0.1.0".to_string(),
+            task_timeout: Duration::from_secs(30 * 60),
+            session: Arc::new(Mutex::new(None)),
+            vim_session: Arc::new(Mutex::new(None)),
+            hooks: CommandHooks::new(),
+        }
+    }
+
+    /// Sets the URL of vCenter or ESXi, e.g., `https://vcenter.example.com`.
+    pub fn url<T: Into<String>>(&mut self, url: T) -> &mut Self {
+        self.url = url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn get_url(&self) -> &str { &self.url }
+
+    impl_setter!(@opt
+        /// Sets the VM ID (e.g., `vm-123`) to be manipulated.
+        vm_id: String
+    );
+
+    pub fn get_vm_id(&self) -> Option<&str> { self.vm_id.as_deref() }
+
+    impl_setter!(@opt
+        /// Sets the username for vCenter or ESXi.
+        username: String
+    );
+    impl_setter!(@opt
+        /// Sets the password for vCenter or ESXi.
+        password: String
+    );
+    impl_setter!(@opt
+        /// Sets the guest username for guest operations.
+        guest_username: String
+    );
+    impl_setter!(@opt
+        /// Sets the guest password for guest operations.
+        guest_password: String
+    );
+    impl_setter!(@opt proxy: String);
+    impl_setter!(
+        /// Accepts self-signed certificates.
+        accept_invalid_certs: bool
+    );
+    impl_setter!(
+        /// Sets the release of the VI/JSON API, e.g., `8.0.1.0`.
+        vim_release: String
+    );
+    impl_setter!(
+        /// Sets the maximum time to wait for a task of the VI/JSON API, such as taking a snapshot. The default is 30 minutes.
+        task_timeout: Duration
+    );
+
+    pub fn get_task_timeout(&self) -> Duration { self.task_timeout }
+    impl_hooks!();
+
+    pub fn get_client(&self) -> VmResult<reqwest::blocking::Client> {
+        let mut b = reqwest::blocking::Client::builder()
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(x) = &self.proxy {
+            b = b.proxy(reqwest::Proxy::all(x).map_err(|x| {
+                VmError::from(ErrorKind::InvalidParameter(x.to_string()))
+            })?);
+        }
+        b.build().map_err(|x| {
+            VmError::from(ErrorKind::ExecutionFailed(x.to_string()))
+        })
+    }
+
+    /// Sends `v`, and sends it again with a new session once if its session has expired.
+    fn send(&self, v: RequestBuilder) -> VmResult<reqwest::blocking::Response> {
+        let retry = v.try_clone();
+        match self.send_once(v) {
+            Err(e) if e == VmError::from(ErrorKind::AuthenticationFailed) => {
+                match retry.map(|x| self.renew_session(x)).transpose()? {
+                    Some(Some(v)) => self.send_once(v),
+                    _ => Err(e),
+                }
+            }
+            x => x,
+        }
+    }
+
+    /// Returns `v` with a new session if `v` was sent with the cached session.
+    ///
+    /// Returns `None` if `v` was sent without a session, e.g., to log in.
+    fn renew_session(
+        &self,
+        v: RequestBuilder,
+    ) -> VmResult<Option<RequestBuilder>> {
+        let req = match v.try_clone().and_then(|x| x.build().ok()) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let sent = match req.headers().get(SESSION_HEADER) {
+            Some(x) => x.to_str().unwrap_or_default().to_string(),
+            None => return Ok(None),
+        };
+        let vim = req.url().path().starts_with("/sdk/vim25/");
+        let cache = if vim {
+            &self.vim_session
+        } else {
+            &self.session
+        };
+        let cached = cache.lock().unwrap().clone();
+        let token = match cached {
+            // Another request has already created a new session.
+            Some(x) if x != sent => x,
+            Some(_) => {
+                *cache.lock().unwrap() = None;
+                if vim {
+                    self.vim_login()?
+                } else {
+                    self.login()?
+                }
+            }
+            // The session was deleted by logout.
+            None => return Ok(None),
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            SESSION_HEADER,
+            HeaderValue::from_str(&token).map_err(|_| {
+                VmError::from(ErrorKind::UnexpectedResponse(token.clone()))
+            })?,
+        );
+        Ok(Some(v.headers(headers)))
+    }
+
+    fn send_once(
+        &self,
+        v: RequestBuilder,
+    ) -> VmResult<reqwest::blocking::Response> {
+        let command = match v.try_clone().and_then(|x| x.build().ok()) {
+            Some(req) => format!("{} {}", req.method(), req.url().path()),
+            None => String::new(),
+        };
+        self.hooks.run(&command, &[], || {
+            let resp = v.send().map_err(|x| {
+                VmError::from(ErrorKind::ExecutionFailed(x.to_string()))
+            })?;
+            if resp.status().is_success() {
+                Ok(resp)
+            } else {
+                Err(Self::handle_error(resp.text().unwrap_or_default()))
+            }
+        })
+    }
+
+    fn execute(&self, v: RequestBuilder) -> VmResult<String> {
+        self.send(v)?.text().map_err(|x| {
+            VmError::from(ErrorKind::UnexpectedResponse(x.to_string()))
+        })
+    }
+
+    /// Converts an error response of the Automation API or the VI/JSON API.
+    pub fn handle_error(s: String) -> VmError {
+        #[derive(Deserialize)]
+        struct Message {
+            default_message: String,
+        }
+        #[derive(Deserialize)]
+        struct ErrorResponse {
+            #[serde(alias = "_typeName")]
+            error_type: String,
+            #[serde(default)]
+            messages: Vec<Message>,
+        }
+        let e = match serde_json::from_str::<ErrorResponse>(&s) {
+            Ok(x) => x,
+            Err(_) => {
+                return VmError::from(Repr::Unknown(format!(
+                    "Unknown error: {}",
+                    s
+                )))
+            }
+        };
+        let msg = e
+            .messages
+            .first()
+            .map(|x| x.default_message.clone())
+            .unwrap_or_default();
+        match e.error_type.as_str() {
+            "NOT_FOUND" | "ManagedObjectNotFound" => {
+                VmError::from(ErrorKind::VmNotFound)
+            }
+            "UNAUTHENTICATED" | "InvalidLogin" | "NotAuthenticated" => {
+                VmError::from(ErrorKind::AuthenticationFailed)
+            }
+            "UNAUTHORIZED" | "NoPermission" => {
+                VmError::from(ErrorKind::PermissionDenied)
+            }
+            "ALREADY_IN_DESIRED_STATE"
+            | "NOT_ALLOWED_IN_CURRENT_STATE"
+            | "InvalidPowerState" => VmError::from(
+                ErrorKind::InvalidPowerState(VmPowerState::Unknown),
+            ),
+            "SERVICE_UNAVAILABLE" | "ToolsUnavailable" => {
+                VmError::from(ErrorKind::ServiceIsNotRunning)
+            }
+            "INVALID_ARGUMENT" | "InvalidArgument" => {
+                VmError::from(ErrorKind::InvalidParameter(msg))
+            }
+            "UNSUPPORTED" | "NotSupported" => {
+                VmError::from(ErrorKind::unsupported::<Self>(&msg))
+            }
+            "GuestPermissionDenied" | "InvalidGuestLogin" => {
+                VmError::from(ErrorKind::GuestAuthenticationFailed)
+            }
+            x => VmError::from(Repr::Unknown(format!(
+                "Unknown error: {}: {}",
+                x, msg
+            ))),
+        }
+    }
+
+    /// Creates a session of the Automation API.
+    pub fn login(&self) -> VmResult<String> {
+        let v = self
+            .get_client()?
+            .post(format!("{}/api/session", self.url))
+            .basic_auth(self.retrieve_username()?, self.password.as_ref());
+        let token: String = deserialize(&self.execute(v)?)?;
+        *self.session.lock().unwrap() = Some(token.clone());
+        Ok(token)
+    }
+
+    /// Deletes the session of the Automation API.
+    pub fn logout(&self) -> VmResult<()> {
+        let token = self.session.lock().unwrap().take();
+        if let Some(token) = token {
+            let v = self
+                .get_client()?
+                .delete(format!("{}/api/session", self.url))
+                .header(SESSION_HEADER, token);
+            self.execute(v)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the session of the VI/JSON API.
+    pub fn vim_logout(&self) -> VmResult<()> {
+        let token = self.vim_session.lock().unwrap().take();
+        if let Some(token) = token {
+            let v = self
+                .get_client()?
+                .post(format!(
+                    "{}/sdk/vim25/{}/SessionManager/SessionManager/Logout",
+                    self.url, self.vim_release
+                ))
+                .header(SESSION_HEADER, token);
+            self.execute(v)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the sessions of the Automation API and the VI/JSON API.
+    ///
+    /// The sessions are also deleted on a separate thread when the last clone sharing them is dropped.
+    pub fn close(&self) -> VmResult<()> {
+        let r = self.logout();
+        self.vim_logout().and(r)
+    }
+
+    fn retrieve_username(&self) -> VmResult<&str> {
+        self.username
+            .as_deref()
+            .ok_or_else(|| VmError::from(ErrorKind::CredentialIsNotSpecified))
+    }
+
+    fn retrieve_vm(&self) -> VmResult<&str> {
+        self.vm_id
+            .as_deref()
+            .ok_or_else(|| VmError::from(ErrorKind::VmIsNotSpecified))
+    }
+
+    fn request(&self, method: Method, path: &str) -> VmResult<RequestBuilder> {
+        let token = self.session.lock().unwrap().clone();
+        let token = match token {
+            Some(x) => x,
+            None => self.login()?,
+        };
+        Ok(self
+            .get_client()?
+            .request(method, format!("{}{}", self.url, path))
+            .header(SESSION_HEADER, token))
+    }
+
+    fn vim_request(
+        &self,
+        method: Method,
+        path: &str,
+    ) -> VmResult<RequestBuilder> {
+        let token = self.vim_session.lock().unwrap().clone();
+        let token = match token {
+            Some(x) => x,
+            None => self.vim_login()?,
+        };
+        Ok(self
+            .get_client()?
+            .request(
+                method,
+                format!("{}/sdk/vim25/{}/{}", self.url, self.vim_release, path),
+            )
+            .header(SESSION_HEADER, token))
+    }
+
+    fn vim_login(&self) -> VmResult<String> {
+        let v = self
+            .get_client()?
+            .post(format!(
+                "{}/sdk/vim25/{}/SessionManager/SessionManager/Login",
+                self.url, self.vim_release
+            ))
+            .json(&json!({
+                "userName": self.retrieve_username()?,
+                "password": self.password.as_deref().unwrap_or_default(),
+            }));
+        let resp = self.send(v)?;
+        let token = match resp.headers().get(SESSION_HEADER) {
+            Some(x) => x.to_str().unwrap_or_default().to_string(),
+            None => {
+                return vmerr!(ErrorKind::UnexpectedResponse(
+                    "The session ID was not returned".to_string()
+                ))
+            }
+        };
+        *self.vim_session.lock().unwrap() = Some(token.clone());
+        Ok(token)
+    }
+
+    /// Gets a list of VMs.
+    pub fn list_vms(&self) -> VmResult<Vec<Vm>> {
+        let _op = self.hooks.operation("list_vms");
+        #[derive(Deserialize)]
+        struct Resp {
+            vm: String,
+            name: String,
+        }
+        let s = self.execute(self.request(Method::GET, "/api/vcenter/vm")?)?;
+        Ok(deserialize::<Vec<Resp>>(&s)?
+            .into_iter()
+            .map(|x| Vm {
+                id: Some(x.vm),
+                name: Some(x.name),
+                path: None,
+            })
+            .collect())
+    }
+
+    pub fn get_power_state(&self) -> VmResult<VmPowerState> {
+        let _op = self.hooks.operation("get_power_state");
+        #[derive(Deserialize)]
+        struct Resp {
+            state: String,
+        }
+        let s = self.execute(self.request(
+            Method::GET,
+            &format!("/api/vcenter/vm/{}/power", self.retrieve_vm()?),
+        )?)?;
+        Ok(to_power_state(&deserialize::<Resp>(&s)?.state))
+    }
+
+    /// Gets the detailed information of the VM with `GET /api/vcenter/vm/{vm}`.
+    pub fn get_vm_info(&self) -> VmResult<VmInfo> {
+        let _op = self.hooks.operation("get_vm_info");
+        let s = self.execute(self.request(
+            Method::GET,
+            &format!("/api/vcenter/vm/{}", self.retrieve_vm()?),
+        )?)?;
+        Ok(parse_vm_info(&deserialize(&s)?))
+    }
+
+    fn check_power_state(&self, allowed: &[VmPowerState]) -> VmResult<()> {
+        self.hooks
+            .check_power_state(allowed, || self.get_power_state())
+    }
+
+    /// Changes the power state of the VM.
+    ///
+    /// `action` is one of `start`, `stop`, `suspend` and `reset`.
+    pub fn power(&self, action: &str) -> VmResult<()> {
+        self.execute(self.request(
+            Method::POST,
+            &format!(
+                "/api/vcenter/vm/{}/power?action={}",
+                self.retrieve_vm()?,
+                action
+            ),
+        )?)?;
+        Ok(())
+    }
+
+    /// Requests the guest OS to change its power state. Requires VMware Tools.
+    ///
+    /// `action` is one of `shutdown`, `reboot` and `standby`.
+    pub fn guest_power(&self, action: &str) -> VmResult<()> {
+        self.execute(self.request(
+            Method::POST,
+            &format!(
+                "/api/vcenter/vm/{}/guest/power?action={}",
+                self.retrieve_vm()?,
+                action
+            ),
+        )?)?;
+        Ok(())
+    }
+
+    fn guest_credentials(&self) -> VmResult<Value> {
+        match (&self.guest_username, &self.guest_password) {
+            (Some(u), Some(p)) => Ok(json!({
+                "interactive_session": false,
+                "type": "USERNAME_PASSWORD",
+                "user_name": u,
+                "password": p,
+            })),
+            _ => vmerr!(ErrorKind::CredentialIsNotSpecified),
+        }
+    }
+
+    /// Starts a program in the guest and returns the PID.
+    pub fn create_process_in_guest(
+        &self,
+        path: &str,
+        arguments: &str,
+    ) -> VmResult<u64> {
+        let v = self
+            .request(
+                Method::POST,
+                &format!(
+                    "/api/vcenter/vm/{}/guest/processes?action=create",
+                    self.retrieve_vm()?
+                ),
+            )?
+            .json(&json!({
+                "credentials": self.guest_credentials()?,
+                "spec": { "path": path, "arguments": arguments },
+            }));
+        deserialize(&self.execute(v)?)
+    }
+
+    /// Gets the exit code of a process in the guest.
+    ///
+    /// Returns `None` if the process is still running.
+    pub fn get_process_exit_code_in_guest(
+        &self,
+        pid: u64,
+    ) -> VmResult<Option<i64>> {
+        #[derive(Deserialize)]
+        struct Resp {
+            exit_code: Option<i64>,
+            finished: Option<String>,
+        }
+        let v = self
+            .request(
+                Method::POST,
+                &format!(
+                    "/api/vcenter/vm/{}/guest/processes/{}?action=get",
+                    self.retrieve_vm()?,
+                    pid
+                ),
+            )?
+            .json(&json!({ "credentials": self.guest_credentials()? }));
+        let r: Resp = deserialize(&self.execute(v)?)?;
+        Ok(r.finished.and(r.exit_code))
+    }
+
+    /// Replaces the `*` host in a guest file transfer URL with the host of `self.url`.
+    fn fix_transfer_url(&self, url: &str) -> String {
+        let host = self
+            .url
+            .split("://")
+            .nth(1)
+            .unwrap_or_default()
+            .split(['/', ':'])
+            .next()
+            .unwrap_or_default();
+        url.replacen("://*", &format!("://{}", host), 1)
+    }
+
+    fn create_transfer(&self, spec: Value) -> VmResult<String> {
+        let v = self
+            .request(
+                Method::POST,
+                &format!(
+                    "/api/vcenter/vm/{}/guest/filesystem?action=create",
+                    self.retrieve_vm()?
+                ),
+            )?
+            .json(&json!({
+                "credentials": self.guest_credentials()?,
+                "spec": spec,
+            }));
+        let url: String = deserialize(&self.execute(v)?)?;
+        Ok(self.fix_transfer_url(&url))
+    }
+
+    /// Copies a file from the host to the guest.
+    pub fn upload_file(
+        &self,
+        from_host_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let data = std::fs::read(from_host_path)
+            .map_err(|_| VmError::from(ErrorKind::HostFileNotFound))?;
+        let url = self.create_transfer(json!({
+            "path": to_guest_path,
+            "attributes": { "overwrite": true, "size": data.len() },
+        }))?;
+        self.send(self.get_client()?.put(url).body(data))?;
+        Ok(())
+    }
+
+    /// Copies a file from the guest to the host.
+    pub fn download_file(
+        &self,
+        from_guest_path: &str,
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let url = self.create_transfer(json!({ "path": from_guest_path }))?;
+        let data =
+            self.send(self.get_client()?.get(url))?
+                .bytes()
+                .map_err(|x| {
+                    VmError::from(ErrorKind::UnexpectedResponse(x.to_string()))
+                })?;
+        std::fs::write(to_host_path, data)?;
+        Ok(())
+    }
+
+    fn wait_for_task(&self, task: &Value) -> VmResult<()> {
+        #[derive(Deserialize)]
+        struct TaskInfo {
+            state: String,
+            error: Option<Value>,
+        }
+        let id = match task["value"].as_str() {
+            Some(x) => x,
+            None => {
+                return vmerr!(ErrorKind::UnexpectedResponse(task.to_string()))
+            }
+        };
+        let start = Instant::now();
+        loop {
+            let s = self.execute(
+                self.vim_request(Method::GET, &format!("Task/{}/info", id))?,
+            )?;
+            let info: TaskInfo = deserialize(&s)?;
+            match info.state.as_str() {
+                "success" => return Ok(()),
+                "error" => {
+                    return Err(Self::handle_error(
+                        info.error
+                            .map(|x| x["fault"].to_string())
+                            .unwrap_or_default(),
+                    ))
+                }
+                _ if start.elapsed() >= self.task_timeout => {
+                    return vmerr!(ErrorKind::Timeout)
+                }
+                _ => std::thread::sleep(Duration::from_millis(500)),
+            }
+        }
+    }
+
+    /// Gets the ID of the first CD-ROM device of the VM.
+    fn get_cdrom_id(&self) -> VmResult<String> {
+        #[derive(Deserialize)]
+        struct Resp {
+            cdrom: String,
+        }
+        let s = self.execute(self.request(
+            Method::GET,
+            &format!("/api/vcenter/vm/{}/hardware/cdrom", self.retrieve_vm()?),
+        )?)?;
+        deserialize::<Vec<Resp>>(&s)?
+            .into_iter()
+            .next()
+            .map(|x| x.cdrom)
+            .ok_or_else(|| {
+                VmError::from(ErrorKind::InvalidParameter(
+                    "The VM has no CD-ROM device".to_string(),
+                ))
+            })
+    }
+
+    /// Changes the backing of the first CD-ROM device of the VM.
+    ///
+    /// `backing` is a `Vcenter.Vm.Hardware.Cdrom.BackingSpec`, e.g., `{"type": "ISO_FILE", "iso_file": "[datastore1] iso/a.iso"}`.
+    pub fn update_cdrom(
+        &self,
+        backing: Value,
+        start_connected: bool,
+    ) -> VmResult<()> {
+        let v = self
+            .request(
+                Method::PATCH,
+                &format!(
+                    "/api/vcenter/vm/{}/hardware/cdrom/{}",
+                    self.retrieve_vm()?,
+                    self.get_cdrom_id()?
+                ),
+            )?
+            .json(&json!({
+                "backing": backing,
+                "start_connected": start_connected,
+            }));
+        self.execute(v)?;
+        Ok(())
+    }
+
+    /// Gets the IP addresses of the guest reported by VMware Tools.
+    ///
+    /// The interfaces are the MAC addresses of the NICs.
+    pub fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        let _op = self.hooks.operation("get_ip_addresses");
+        let s = self.execute(self.request(
+            Method::GET,
+            &format!(
+                "/api/vcenter/vm/{}/guest/networking/interfaces",
+                self.retrieve_vm()?
+            ),
+        )?)?;
+        Ok(parse_guest_interfaces(&deserialize(&s)?))
+    }
+
+    /// Gets a list of snapshots with the VI/JSON API.
+    pub fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        let _op = self.hooks.operation("list_snapshots");
+        let s = self.execute(self.vim_request(
+            Method::GET,
+            &format!("VirtualMachine/{}/snapshot", self.retrieve_vm()?),
+        )?)?;
+        if s.trim().is_empty() || s.trim() == "null" {
+            // No snapshot.
+            return Ok(vec![]);
+        }
+        let v = deserialize::<Value>(&s)?;
+        let current_id = v["currentSnapshot"]["value"].as_str();
+        Ok(parse_snapshot_tree(
+            &v["rootSnapshotList"],
+            None,
+            current_id,
+        ))
+    }
+
+    fn get_snapshot_id(&self, name: &str) -> VmResult<String> {
+        self.list_snapshots()?
+            .into_iter()
+            .find(|x| x.name.as_deref() == Some(name))
+            .and_then(|x| x.id)
+            .ok_or_else(|| VmError::from(ErrorKind::SnapshotNotFound))
+    }
+
+    /// Takes a snapshot with the VI/JSON API.
+    pub fn take_snapshot(
+        &self,
+        name: &str,
+        description: Option<&str>,
+        memory: bool,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("take_snapshot");
+        let v = self
+            .vim_request(
+                Method::POST,
+                &format!(
+                    "VirtualMachine/{}/CreateSnapshot_Task",
+                    self.retrieve_vm()?
+                ),
+            )?
+            .json(&json!({
+                "name": name,
+                "description": description.unwrap_or_default(),
+                "memory": memory,
+                "quiesce": false,
+            }));
+        self.wait_for_task(&deserialize(&self.execute(v)?)?)
+    }
+
+    /// Reverts the VM to a snapshot with the VI/JSON API.
+    pub fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("revert_snapshot");
+        let id = self.get_snapshot_id(name)?;
+        let v = self
+            .vim_request(
+                Method::POST,
+                &format!("VirtualMachineSnapshot/{}/RevertToSnapshot_Task", id),
+            )?
+            .json(&json!({}));
+        self.wait_for_task(&deserialize(&self.execute(v)?)?)
+    }
+
+    /// Deletes a snapshot with the VI/JSON API.
+    pub fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("delete_snapshot");
+        let id = self.get_snapshot_id(name)?;
+        let v = self
+            .vim_request(
+                Method::POST,
+                &format!("VirtualMachineSnapshot/{}/RemoveSnapshot_Task", id),
+            )?
+            .json(&json!({ "removeChildren": false }));
+        self.wait_for_task(&deserialize(&self.execute(v)?)?)
+    }
+}
+
+/// Flattens `VirtualMachineSnapshotTree`s whose parent is `parent_id`.
+fn parse_snapshot_tree(
+    v: &Value,
+    parent_id: Option<&str>,
+    current_id: Option<&str>,
+) -> Vec<Snapshot> {
+    let mut ret = vec![];
+    if let Some(v) = v.as_array() {
+        for x in v {
+            let id = x["snapshot"]["value"].as_str();
+            ret.push(Snapshot {
+                id: id.map(|x| x.to_string()),
+                name: x["name"].as_str().map(|x| x.to_string()),
+                detail: x["description"].as_str().map(|x| x.to_string()),
+                parent_id: parent_id.map(|x| x.to_string()),
+                created_at: x["createTime"].as_str().and_then(parse_timestamp),
+                is_current: Some(id.is_some() && id == current_id),
+                online: x["state"].as_str().map(|x| x != "poweredOff"),
+            });
+            ret.extend(parse_snapshot_tree(
+                &x["childSnapshotList"],
+                id,
+                current_id,
+            ));
+        }
+    }
+    ret
+}
+
+/// Collects the addresses in `Vcenter.Vm.Guest.Networking.Interfaces.Info`s.
+fn to_power_state(s: &str) -> VmPowerState {
+    match s {
+        "POWERED_ON" => VmPowerState::Running,
+        "POWERED_OFF" => VmPowerState::Stopped,
+        "SUSPENDED" => VmPowerState::Suspended,
+        _ => VmPowerState::Unknown,
+    }
+}
+
+/// Parses the VM information of `GET /api/vcenter/vm/{vm}`.
+fn parse_vm_info(v: &Value) -> VmInfo {
+    VmInfo {
+        name: v["name"].as_str().map(|x| x.to_string()),
+        guest_os: v["guest_OS"].as_str().map(|x| x.to_string()),
+        memory_mb: v["memory"]["size_MiB"].as_u64(),
+        cpu_count: v["cpu"]["count"].as_u64().map(|x| x as u32),
+        firmware: v["boot"]["type"].as_str().map(|x| match x {
+            "EFI" => Firmware::Efi,
+            _ => Firmware::Bios,
+        }),
+        power_state: v["power_state"].as_str().map(to_power_state),
+        ..Default::default()
+    }
+}
+
+fn parse_guest_interfaces(v: &Value) -> Vec<GuestIpAddress> {
+    let mut ret = vec![];
+    if let Some(v) = v.as_array() {
+        for x in v {
+            let mac = x["mac_address"].as_str();
+            if let Some(ips) = x["ip"]["ip_addresses"].as_array() {
+                ret.extend(ips.iter().filter_map(|ip| {
+                    GuestIpAddress::parse(mac, ip["ip_address"].as_str()?)
+                }));
+            }
+        }
+    }
+    ret
+}
+
+impl VmCmd for VSphere {
+    fn list_vms(&self) -> VmResult<Vec<Vm>> { Self::list_vms(self) }
+
+    /// `id` is the VM ID such as `vm-123`.
+    fn set_vm_by_id(&mut self, id: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_id");
+        for vm in self.list_vms()? {
+            if vm.id.as_deref() == Some(id) {
+                self.vm_id = vm.id.clone();
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    fn set_vm_by_name(&mut self, name: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_name");
+        for vm in self.list_vms()? {
+            if vm.name.as_deref() == Some(name) {
+                self.vm_id = vm.id.clone();
+                return Ok(vm);
+            }
+        }
+        vmerr!(ErrorKind::VmNotFound)
+    }
+
+    /// Due to the specification of the vSphere Automation API, VSphere does not support this function.
+    fn set_vm_by_path(&mut self, _: &str) -> VmResult<Vm> {
+        let _op = self.hooks.operation("set_vm_by_path");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "set_vm_by_path",
+            "Use set_vm_by_name or set_vm_by_id instead"
+        ))
+    }
+
+    fn get_vm_info(&self) -> VmResult<VmInfo> { Self::get_vm_info(self) }
+}
+
+impl PowerCmd for VSphere {
+    fn start(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("start");
+        self.hooks.settle_started(|| {
+            if self.get_power_state()?.is_running() {
+                return vmerr!(ErrorKind::InvalidPowerState(
+                    VmPowerState::Running
+                ));
+            }
+            self.power("start")
+        })
+    }
+
+    /// Shuts down the guest OS. Requires VMware Tools.
+    fn stop<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("stop");
+        self.hooks.settle_stopped(|| {
+            let timeout = timeout.into();
+            let s = Instant::now();
+            self.check_power_state(&[VmPowerState::Running])?;
+            self.guest_power("shutdown")?;
+            self.wait_for_stop(timeout.map(|x| x.saturating_sub(s.elapsed())))
+        })
+    }
+
+    fn hard_stop(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_stop");
+        self.hooks.settle_stopped(|| {
+            self.check_power_state(&[
+                VmPowerState::Running,
+                VmPowerState::Suspended,
+            ])?;
+            self.power("stop")
+        })
+    }
+
+    fn suspend(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("suspend");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.power("suspend")
+    }
+
+    fn resume(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("resume");
+        self.check_power_state(&[VmPowerState::Suspended])?;
+        self.power("start")
+    }
+
+    fn is_running(&self) -> VmResult<bool> {
+        let _op = self.hooks.operation("is_running");
+        Ok(self.get_power_state()?.is_running())
+    }
+
+    fn get_power_state(&self) -> VmResult<VmPowerState> {
+        Self::get_power_state(self)
+    }
+
+    fn reboot<D: Into<Option<Duration>>>(&self, timeout: D) -> VmResult<()> {
+        let _op = self.hooks.operation("reboot");
+        self.stop(timeout)?;
+        self.start()
+    }
+
+    fn hard_reboot(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("hard_reboot");
+        self.check_power_state(&[VmPowerState::Running])?;
+        self.power("reset")
+    }
+
+    /// Due to the specification of vSphere, VSphere does not support this function.
+    fn pause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("pause");
+        vmerr!(ErrorKind::unsupported_with_hint::<Self>(
+            "pause",
+            "Use suspend instead"
+        ))
+    }
+
+    /// Due to the specification of vSphere, VSphere does not support this function.
+    fn unpause(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("unpause");
+        vmerr!(ErrorKind::unsupported::<Self>("unpause"))
+    }
+}
+
+/// `path` is a datastore path, e.g., `[datastore1] iso/a.iso`.
+impl MediaCmd for VSphere {
+    fn attach_iso(&self, path: &str) -> VmResult<()> {
+        let _op = self.hooks.operation("attach_iso");
+        self.update_cdrom(json!({ "type": "ISO_FILE", "iso_file": path }), true)
+    }
+
+    /// Replaces the ISO image with the emulated client device.
+    fn eject(&self) -> VmResult<()> {
+        let _op = self.hooks.operation("eject");
+        self.update_cdrom(
+            json!({ "type": "CLIENT_DEVICE", "device_access_type": "EMULATION" }),
+            false,
+        )
+    }
+}
+
+impl SnapshotCmd for VSphere {
+    fn list_snapshots(&self) -> VmResult<Vec<Snapshot>> {
+        Self::list_snapshots(self)
+    }
+
+    fn take_snapshot(&self, name: &str) -> VmResult<()> {
+        Self::take_snapshot(self, name, None, false)
+    }
+
+    fn revert_snapshot(&self, name: &str) -> VmResult<()> {
+        Self::revert_snapshot(self, name)
+    }
+
+    fn delete_snapshot(&self, name: &str) -> VmResult<()> {
+        self.hooks
+            .settle_deleted(|| Self::delete_snapshot(self, name))
+    }
+}
+
+impl GuestNetworkCmd for VSphere {
+    fn get_ip_addresses(&self) -> VmResult<Vec<GuestIpAddress>> {
+        Self::get_ip_addresses(self)
+    }
+}
+
+impl GuestCmd for VSphere {
+    /// Runs a program in the guest and waits for it to exit.
+    fn exec_cmd(&self, guest_args: &[&str]) -> VmResult<()> {
+        let _op = self.hooks.operation("exec_cmd");
+        let (path, args) = match guest_args.split_first() {
+            Some(x) => x,
+            None => {
+                return vmerr!(ErrorKind::InvalidParameter(
+                    "guest_args".to_string()
+                ))
+            }
+        };
+        let pid = self.create_process_in_guest(path, &args.join(" "))?;
+        while self.get_process_exit_code_in_guest(pid)?.is_none() {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        Ok(())
+    }
+
+    fn copy_from_guest_to_host(
+        &self,
+        from_guest_path: &str,
+        to_host_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_guest_to_host");
+        self.download_file(from_guest_path, to_host_path)
+    }
+
+    fn copy_from_host_to_guest(
+        &self,
+        from_host_path: &str,
+        to_guest_path: &str,
+    ) -> VmResult<()> {
+        let _op = self.hooks.operation("copy_from_host_to_guest");
+        self.upload_file(from_host_path, to_guest_path)
+    }
+}
+
+#[test]
+fn test_parse_snapshot_tree() {
+    let s = r#"{
+  "_typeName": "VirtualMachineSnapshotInfo",
+  "currentSnapshot": { "_typeName": "ManagedObjectReference", "type": "VirtualMachineSnapshot", "value": "snapshot-2" },
+  "rootSnapshotList": [
+    {
+      "snapshot": { "_typeName": "ManagedObjectReference", "type": "VirtualMachineSnapshot", "value": "snapshot-1" },
+      "name": "base",
+      "description": "",
+      "createTime": "2021-01-02T03:04:05.123Z",
+      "state": "poweredOff",
+      "childSnapshotList": [
+        {
+          "snapshot": { "_typeName": "ManagedObjectReference", "type": "VirtualMachineSnapshot", "value": "snapshot-2" },
+          "name": "child",
+          "description": "desc",
+          "state": "poweredOn"
+        }
+      ]
+    }
+  ]
+}"#;
+    let v: Value = deserialize(s).unwrap();
+    let sn = parse_snapshot_tree(
+        &v["rootSnapshotList"],
+        None,
+        v["currentSnapshot"]["value"].as_str(),
+    );
+    assert_eq!(sn.len(), 2);
+    assert_eq!(sn[0].id.as_deref(), Some("snapshot-1"));
+    assert_eq!(sn[1].name.as_deref(), Some("child"));
+    assert_eq!(sn[1].detail.as_deref(), Some("desc"));
+    assert_eq!(sn[0].parent_id, None);
+    assert_eq!(sn[1].parent_id.as_deref(), Some("snapshot-1"));
+    assert_eq!(sn[0].created_at, Some(1609556645));
+    assert_eq!(sn[0].is_current, Some(false));
+    assert_eq!(sn[0].online, Some(false));
+    assert_eq!(sn[1].created_at, None);
+    assert_eq!(sn[1].is_current, Some(true));
+    assert_eq!(sn[1].online, Some(true));
+    assert!(parse_snapshot_tree(&Value::Null, None, None).is_empty());
+}
+
+#[test]
+fn test_parse_guest_interfaces() {
+    let s = r#"[
+  {
+    "mac_address": "00:50:56:aa:bb:cc",
+    "nic": "4000",
+    "ip": {
+      "ip_addresses": [
+        { "ip_address": "192.168.1.20", "prefix_length": 24, "state": "PREFERRED" },
+        { "ip_address": "fe80::250:56ff:feaa:bbcc", "prefix_length": 64, "state": "UNKNOWN" }
+      ]
+    }
+  },
+  { "mac_address": "00:50:56:aa:bb:cd" }
+]"#;
+    let v = parse_guest_interfaces(&deserialize(s).unwrap());
+    assert_eq!(v.len(), 2);
+    assert_eq!(v[0].interface.as_deref(), Some("00:50:56:aa:bb:cc"));
+    assert!(v[0].address.is_ipv4());
+    assert!(v[1].address.is_ipv6());
+    assert!(parse_guest_interfaces(&Value::Null).is_empty());
+}
+
+#[test]
+fn test_parse_vm_info() {
+    let s = r#"{
+  "name": "vm1",
+  "guest_OS": "UBUNTU_64",
+  "power_state": "POWERED_ON",
+  "cpu": {"count": 2, "cores_per_socket": 1},
+  "memory": {"size_MiB": 2048},
+  "boot": {"type": "EFI", "delay": 0}
+}"#;
+    let info = parse_vm_info(&serde_json::from_str(s).unwrap());
+    assert_eq!(
+        info,
+        VmInfo {
+            name: Some("vm1".to_string()),
+            guest_os: Some("UBUNTU_64".to_string()),
+            memory_mb: Some(2048),
+            cpu_count: Some(2),
+            firmware: Some(Firmware::Efi),
+            power_state: Some(VmPowerState::Running),
+            ..Default::default()
+        }
+    );
+}
+
+/// Serves `responses` as `(status, body)` in order and returns the request line and the session header of each request.
+#[cfg(test)]
+fn serve_mock(
+    responses: Vec<(u16, &'static str)>,
+) -> (String, std::thread::JoinHandle<Vec<(String, String)>>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", l.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut ret = vec![];
+        for (status, body) in responses {
+            let (s, _) = l.accept().unwrap();
+            let mut r = BufReader::new(s);
+            let (mut request, mut session, mut length) =
+                (String::new(), String::new(), 0);
+            r.read_line(&mut request).unwrap();
+            loop {
+                let mut line = String::new();
+                r.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                let (k, v) = line.split_once(':').unwrap();
+                if k.eq_ignore_ascii_case(SESSION_HEADER) {
+                    session = v.trim().to_string();
+                } else if k.eq_ignore_ascii_case("content-length") {
+                    length = v.trim().parse().unwrap();
+                }
+            }
+            r.read_exact(&mut vec![0; length]).unwrap();
+            write!(
+                r.get_mut(),
+                "HTTP/1.1 {} X\r\nContent-Type: \
+                 application/json\r\nContent-Length: {}\r\nConnection: \
+                 close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            ret.push((request.trim().to_string(), session));
+        }
+        ret
+    });
+    (url, server)
+}
+
+#[test]
+fn test_renew_session() {
+    let (url, server) = serve_mock(vec![
+        (401, r#"{"error_type":"UNAUTHENTICATED","messages":[]}"#),
+        (201, r#""new""#),
+        (200, r#"[{"vm":"vm-1","name":"a"}]"#),
+        (401, r#"{"error_type":"UNAUTHENTICATED","messages":[]}"#),
+    ]);
+    let mut cmd = VSphere::new();
+    cmd.url(url).username(Some("user".to_string()));
+    *cmd.session.lock().unwrap() = Some("old".to_string());
+    let vms = cmd.list_vms().unwrap();
+    assert_eq!(vms[0].id.as_deref(), Some("vm-1"));
+    assert_eq!(cmd.session.lock().unwrap().as_deref(), Some("new"));
+    // A failed login is not retried.
+    *cmd.session.lock().unwrap() = None;
+    assert_eq!(cmd.list_vms(), vmerr!(ErrorKind::AuthenticationFailed));
+    let requests = server.join().unwrap();
+    let requests: Vec<(&str, &str)> = requests
+        .iter()
+        .map(|(r, s)| (r.as_str(), s.as_str()))
+        .collect();
+    assert_eq!(
+        requests,
+        [
+            ("GET /api/vcenter/vm HTTP/1.1", "old"),
+            ("POST /api/session HTTP/1.1", ""),
+            ("GET /api/vcenter/vm HTTP/1.1", "new"),
+            ("POST /api/session HTTP/1.1", ""),
+        ]
+    );
+    *cmd.session.lock().unwrap() = None;
+}
+
+#[test]
+fn test_task_timeout() {
+    let (url, server) =
+        serve_mock(vec![(200, r#"{"state":"running","error":null}"#)]);
+    let mut cmd = VSphere::new();
+    cmd.url(url).task_timeout(Duration::from_secs(0));
+    *cmd.vim_session.lock().unwrap() = Some("s".to_string());
+    assert_eq!(
+        cmd.wait_for_task(&json!({"value": "task-1"})),
+        vmerr!(ErrorKind::Timeout)
+    );
+    server.join().unwrap();
+    *cmd.vim_session.lock().unwrap() = None;
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_drop_in_runtime() {
+    // Dropping the last clone with a session must not use the blocking client in the runtime.
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let mut cmd = VSphere::new();
+        cmd.url("http://127.0.0.1:1");
+        *cmd.session.lock().unwrap() = Some("token".to_string());
+        drop(cmd);
+    });
+}
+
+#[test]
+fn test_handle_error() {
+    let e = VSphere::handle_error(
+        r#"{"error_type":"NOT_FOUND","messages":[{"args":[],"default_message":"Virtual machine with identifier 'vm-1' does not exist.","id":"x"}]}"#
+            .to_string(),
+    );
+    assert_eq!(e, VmError::from(ErrorKind::VmNotFound));
+    let e = VSphere::handle_error(
+        r#"{"error_type":"UNAUTHENTICATED","messages":[]}"#.to_string(),
+    );
+    assert_eq!(e, VmError::from(ErrorKind::AuthenticationFailed));
+    let e = VSphere::handle_error(
+        r#"{"_typeName":"InvalidLogin","faultMessage":[]}"#.to_string(),
+    );
+    assert_eq!(e, VmError::from(ErrorKind::AuthenticationFailed));
+}
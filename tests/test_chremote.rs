@@ -0,0 +1,72 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! If you want to run tests, please write your VM configuration to `tests/config.toml`.
+//!
+//! # config.toml example
+//!
+//! ```toml
+//! [chremote]
+//! executable_path = "/usr/local/bin/ch-remote"
+//! api_socket = "/tmp/cloud-hypervisor.sock"
+//! snapshot_dir = "/var/lib/hvctrl/snapshots"
+//! ```
+#![cfg(feature = "chremote")]
+
+mod test_cmd_util;
+
+#[cfg(test)]
+mod test_chremote {
+    use crate::test_cmd_util;
+    use hvctrl::cloudhypervisor::ChRemote;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct ChRemoteConfig {
+        executable_path: Option<String>,
+        api_socket: Option<String>,
+        snapshot_dir: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ConfigToml {
+        chremote: Option<ChRemoteConfig>,
+    }
+
+    fn get_cmd() -> ChRemote {
+        let x = std::fs::read_to_string("tests/config.toml")
+            .expect("Failed to read config.toml");
+        let config: ConfigToml =
+            toml::from_str(&x).expect("Failed to parse config.toml");
+        let mut cmd = ChRemote::new();
+        let config = config
+            .chremote
+            .as_ref()
+            .expect("The configuration of ChRemote doesn't exist");
+        if let Some(x) = &config.executable_path {
+            cmd.executable_path(x);
+        }
+        if let Some(x) = &config.api_socket {
+            cmd.api_socket(x);
+        }
+        cmd.snapshot_dir(config.snapshot_dir.clone());
+        cmd
+    }
+
+    #[test]
+    fn test() {
+        let cmd = get_cmd();
+        cmd.get_power_state().unwrap();
+    }
+
+    #[test]
+    fn test_power_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_power(&cmd);
+    }
+
+    #[test]
+    fn test_snapshot_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_snapshot_cmd(&cmd);
+    }
+}
@@ -4,24 +4,25 @@
 use hvctrl::{types::*, vmerr};
 use std::time::Duration;
 
+fn is_not_found_or_unsupported(x: &VmResult<Vm>) -> bool {
+    match x {
+        Ok(_) => false,
+        Err(e) => *e == vmerr!(@r ErrorKind::VmNotFound) || e.is_unsupported(),
+    }
+}
+
 pub fn test_vm_not_found(cmd: &mut impl VmCmd) {
     cmd.list_vms().unwrap();
     let x = cmd.set_vm_by_name("hvctrlDoesNotExistVmName");
-    if x != vmerr!(ErrorKind::VmNotFound)
-        && x != vmerr!(ErrorKind::UnsupportedCommand)
-    {
+    if !is_not_found_or_unsupported(&x) {
         panic!("Unexpected response: {:?}", x);
     }
     let x = cmd.set_vm_by_path(r"C:\hvctrl\does\not\exist\vm.vmx");
-    if x != vmerr!(ErrorKind::VmNotFound)
-        && x != vmerr!(ErrorKind::UnsupportedCommand)
-    {
+    if !is_not_found_or_unsupported(&x) {
         panic!("Unexpected response: {:?}", x);
     }
     let x = cmd.set_vm_by_id("0HVCTRL1DOES2NOT3EXIST4VM5ID4649");
-    if x != vmerr!(ErrorKind::VmNotFound)
-        && x != vmerr!(ErrorKind::UnsupportedCommand)
-    {
+    if !is_not_found_or_unsupported(&x) {
         panic!("Unexpected response: {:?}", x);
     }
 }
@@ -31,7 +32,7 @@ fn is_invalid_state_running<T>(x: VmResult<T>) -> bool {
         Ok(_) => panic!("The function succeeded unexpectedly"),
         Err(x) => match x.is_invalid_state_running() {
             Some(x) => x,
-            None => panic!("Unexpected error: {}", x.to_string()),
+            None => panic!("Unexpected error: {}", x),
         },
     }
 }
@@ -43,18 +44,16 @@ fn assert_ok_stop(cmd: &impl PowerCmd, timeout: Duration) {
     } else if status == vmerr!(ErrorKind::Timeout) {
         assert_eq!(Ok(true), cmd.is_running());
     } else if let Err(x) = status {
-        panic!("Unexpected error: {}", x.to_string());
+        panic!("Unexpected error: {}", x);
     }
 }
 
 fn assert_ok_reboot(cmd: &impl PowerCmd, timeout: Duration) {
     let status = cmd.reboot(timeout);
-    if status == Ok(()) {
-        assert_eq!(Ok(true), cmd.is_running());
-    } else if status == vmerr!(ErrorKind::Timeout) {
+    if status == Ok(()) || status == vmerr!(ErrorKind::Timeout) {
         assert_eq!(Ok(true), cmd.is_running());
     } else if let Err(x) = status {
-        panic!("Unexpected error: {}", x.to_string());
+        panic!("Unexpected error: {}", x);
     }
 }
 
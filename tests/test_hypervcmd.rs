@@ -8,6 +8,7 @@
 //! [hypervcmd]
 //! vm_name = "MyVM"
 //! ```
+#![cfg(all(windows, feature = "hypervcmd"))]
 
 mod test_cmd_util;
 #[cfg(test)]
@@ -43,7 +44,7 @@ mod test_hypervcmd {
         if let Some(x) = &config.executable_path {
             cmd.executable_path(x);
         }
-        cmd.vm_name(config.vm_name.as_ref().map(|x| x.clone()));
+        cmd.vm_name(config.vm_name.clone());
         cmd
     }
 
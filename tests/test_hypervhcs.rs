@@ -0,0 +1,56 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! If you want to run tests, please write your VM configuration to `tests/config.toml`.
+//!
+//! # config.toml example
+//!
+//! ```toml
+//! [hypervhcs]
+//! vm_id = "01234567-89AB-CDEF-0123-456789ABCDEF"
+//! ```
+#![cfg(all(windows, feature = "hypervhcs"))]
+
+mod test_cmd_util;
+#[cfg(test)]
+mod test_hypervhcs {
+    use crate::test_cmd_util;
+    use hvctrl::{hyperv::HyperVHcs, types::VmCmd};
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct HyperVHcsConfig {
+        vm_id: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ConfigToml {
+        hypervhcs: Option<HyperVHcsConfig>,
+    }
+
+    fn get_cmd() -> HyperVHcs {
+        let x = std::fs::read_to_string("tests/config.toml")
+            .expect("Failed to read config.toml");
+        let config: ConfigToml =
+            toml::from_str(&x).expect("Failed to parse config.toml");
+        let config = config
+            .hypervhcs
+            .as_ref()
+            .expect("The configuration of HyperVHcs doesn't exist");
+        let mut cmd = HyperVHcs::new();
+        cmd.vm_id(config.vm_id.clone());
+        cmd
+    }
+
+    #[test]
+    fn test() {
+        let cmd = get_cmd();
+        cmd.list_vms().unwrap();
+        cmd.get_power_state().unwrap();
+    }
+
+    #[test]
+    fn test_vm_cmd() {
+        let mut cmd = get_cmd();
+        test_cmd_util::test_vm_not_found(&mut cmd);
+    }
+}
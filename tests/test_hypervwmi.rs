@@ -0,0 +1,71 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! If you want to run tests, please write your VM configuration to `tests/config.toml`.
+//!
+//! # config.toml example
+//!
+//! ```toml
+//! [hypervwmi]
+//! vm_id = "01234567-89AB-CDEF-0123-456789ABCDEF"
+//! ```
+#![cfg(all(windows, feature = "hypervwmi"))]
+
+mod test_cmd_util;
+#[cfg(test)]
+mod test_hypervwmi {
+    use crate::test_cmd_util;
+    use hvctrl::{
+        hyperv::HyperVWmi,
+        types::{SnapshotCmd, VmCmd},
+    };
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct HyperVWmiConfig {
+        vm_id: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ConfigToml {
+        hypervwmi: Option<HyperVWmiConfig>,
+    }
+
+    fn get_cmd() -> HyperVWmi {
+        let x = std::fs::read_to_string("tests/config.toml")
+            .expect("Failed to read config.toml");
+        let config: ConfigToml =
+            toml::from_str(&x).expect("Failed to parse config.toml");
+        let config = config
+            .hypervwmi
+            .as_ref()
+            .expect("The configuration of HyperVWmi doesn't exist");
+        let mut cmd = HyperVWmi::new();
+        cmd.vm_id(config.vm_id.clone());
+        cmd
+    }
+
+    #[test]
+    fn test() {
+        let cmd = get_cmd();
+        cmd.list_vms().unwrap();
+        cmd.list_snapshots().unwrap();
+    }
+
+    #[test]
+    fn test_vm_cmd() {
+        let mut cmd = get_cmd();
+        test_cmd_util::test_vm_not_found(&mut cmd);
+    }
+
+    #[test]
+    fn test_power_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_power(&cmd);
+    }
+
+    #[test]
+    fn test_snapshot_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_snapshot_cmd(&cmd);
+    }
+}
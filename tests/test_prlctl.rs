@@ -0,0 +1,89 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! If you want to run tests, please write your VM configuration to `tests/config.toml`.
+//!
+//! # config.toml example
+//!
+//! ```toml
+//! [prlctl]
+//! executable_path = "/usr/local/bin/prlctl"
+//! vm_name = "MyVM"
+//! guest_username = "user"
+//! guest_password = "password"
+//! ```
+#![cfg(feature = "prlctl")]
+
+mod test_cmd_util;
+
+#[cfg(test)]
+mod test_prlctl {
+    use crate::test_cmd_util;
+    use hvctrl::parallels::Prlctl;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct PrlctlConfig {
+        executable_path: Option<String>,
+        vm_name: Option<String>,
+        guest_username: Option<String>,
+        guest_password: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ConfigToml {
+        prlctl: Option<PrlctlConfig>,
+    }
+
+    fn get_cmd() -> Prlctl {
+        let x = std::fs::read_to_string("tests/config.toml")
+            .expect("Failed to read config.toml");
+        let config: ConfigToml =
+            toml::from_str(&x).expect("Failed to parse config.toml");
+        let mut cmd = Prlctl::new();
+        let config = config
+            .prlctl
+            .as_ref()
+            .expect("The configuration of Prlctl doesn't exist");
+        if let Some(x) = &config.executable_path {
+            cmd.executable_path(x);
+        }
+        cmd.vm_name(config.vm_name.clone())
+            .guest_username(config.guest_username.clone())
+            .guest_password(config.guest_password.clone());
+        cmd
+    }
+
+    #[test]
+    fn test() {
+        let cmd = get_cmd();
+        cmd.version().unwrap();
+        cmd.list_vms().unwrap();
+        cmd.list_snapshots().unwrap();
+    }
+
+    #[test]
+    fn test_vm_cmd() {
+        let mut cmd = get_cmd();
+        test_cmd_util::test_vm_not_found(&mut cmd);
+    }
+
+    #[test]
+    fn test_power_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_power(&cmd);
+    }
+
+    #[test]
+    fn test_snapshot_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_snapshot_cmd(&cmd);
+    }
+
+    #[test]
+    fn test_guest_cmd() {
+        use hvctrl::types::GuestCmd;
+        let cmd = get_cmd();
+        cmd.copy_from_host_to_guest("rustfmt.toml", "/tmp/rustfmt.toml")
+            .unwrap();
+    }
+}
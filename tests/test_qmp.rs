@@ -0,0 +1,74 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! If you want to run tests, please write your VM configuration to `tests/config.toml`.
+//!
+//! # config.toml example
+//!
+//! ```toml
+//! [qmp]
+//! executable_path = "/usr/bin/qemu-system-x86_64"
+//! address = "tcp:127.0.0.1:4444"
+//! launch_args = ["-m", "1024", "-hda", "/path/to/disk.qcow2"]
+//! ```
+#![cfg(feature = "qmp")]
+
+mod test_cmd_util;
+
+#[cfg(test)]
+mod test_qmp {
+    use crate::test_cmd_util;
+    use hvctrl::qemu::Qmp;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct QmpConfig {
+        executable_path: Option<String>,
+        address: Option<String>,
+        launch_args: Option<Vec<String>>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ConfigToml {
+        qmp: Option<QmpConfig>,
+    }
+
+    fn get_cmd() -> Qmp {
+        let x = std::fs::read_to_string("tests/config.toml")
+            .expect("Failed to read config.toml");
+        let config: ConfigToml =
+            toml::from_str(&x).expect("Failed to parse config.toml");
+        let mut cmd = Qmp::new();
+        let config = config
+            .qmp
+            .as_ref()
+            .expect("The configuration of Qmp doesn't exist");
+        if let Some(x) = &config.executable_path {
+            cmd.executable_path(x);
+        }
+        if let Some(x) = &config.address {
+            cmd.address(x);
+        }
+        if let Some(x) = &config.launch_args {
+            cmd.launch_args(x);
+        }
+        cmd
+    }
+
+    #[test]
+    fn test() {
+        let cmd = get_cmd();
+        cmd.get_power_state().unwrap();
+    }
+
+    #[test]
+    fn test_power_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_power(&cmd);
+    }
+
+    #[test]
+    fn test_snapshot_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_snapshot_cmd(&cmd);
+    }
+}
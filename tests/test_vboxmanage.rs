@@ -11,13 +11,14 @@
 //! guest_username = "user"
 //! guest_password = "password"
 //! ```
+#![cfg(feature = "vboxmanage")]
 
 mod test_cmd_util;
 
 #[cfg(test)]
 mod test_vboxmanage {
     use crate::test_cmd_util;
-    use hvctrl::{types::ErrorKind::FileError, virtualbox::VBoxManage};
+    use hvctrl::virtualbox::VBoxManage;
     use serde::Deserialize;
 
     #[derive(Debug, Deserialize)]
@@ -48,13 +49,11 @@ mod test_vboxmanage {
         if let Some(x) = &config.executable_path {
             cmd.executable_path(x);
         }
-        cmd.vm_name(config.vm_name.as_ref().map(|x| x.clone()))
-            .guest_username(config.guest_username.as_ref().map(|x| x.clone()))
-            .guest_password(config.guest_password.as_ref().map(|x| x.clone()))
-            .guest_domain(config.guest_domain.as_ref().map(|x| x.clone()))
-            .guest_password_file(
-                config.guest_password_file.as_ref().map(|x| x.clone()),
-            );
+        cmd.vm_name(config.vm_name.clone())
+            .guest_username(config.guest_username.clone())
+            .guest_password(config.guest_password.clone())
+            .guest_domain(config.guest_domain.clone())
+            .guest_password_file(config.guest_password_file.clone());
         cmd
     }
 
@@ -87,16 +86,19 @@ mod test_vboxmanage {
 
     #[test]
     fn test_guest_cmd() {
-        use hvctrl::types::{GuestCmd, PowerCmd};
+        use hvctrl::types::GuestCmd;
         let cmd = get_cmd();
         // assert_eq!(Ok(()), cmd.start());
-        let os = cmd.get_os_version().expect("Failed to get os version");
+        let os = cmd
+            .get_vm_info()
+            .expect("Failed to get VM info")
+            .guest_os
+            .expect("Failed to get os version");
         if os.contains("Windows") {
             cmd.copy_from_host_to_guest("rustfmt.toml", "AAAAA")
                 .unwrap();
         } else {
-            cmd.copy_from_host_to_guest("rustfmt.toml", "/tmp")
-                .unwrap();
+            cmd.copy_from_host_to_guest("rustfmt.toml", "/tmp").unwrap();
         }
     }
 }
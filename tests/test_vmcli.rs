@@ -0,0 +1,80 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! If you want to run tests, please write your VM configuration to `tests/config.toml`.
+//!
+//! # config.toml example
+//!
+//! ```toml
+//! [vmcli]
+//! executable_path = "C:\\Program Files (x86)\\VMware\\VMware Workstation\\vmcli.exe"
+//! vm_path = "C:\\VMs\\MyVM\\MyVM.vmx"
+//! guest_username = "user"
+//! guest_password = "password"
+//! ```
+#![cfg(feature = "vmcli")]
+
+mod test_cmd_util;
+
+#[cfg(test)]
+mod test_vmcli {
+    use crate::test_cmd_util;
+    use hvctrl::vmware::VmCli;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct VmCliConfig {
+        executable_path: Option<String>,
+        vm_path: Option<String>,
+        guest_username: Option<String>,
+        guest_password: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ConfigToml {
+        vmcli: Option<VmCliConfig>,
+    }
+
+    fn get_cmd() -> VmCli {
+        let x = std::fs::read_to_string("tests/config.toml")
+            .expect("Failed to read config.toml");
+        let config: ConfigToml =
+            toml::from_str(&x).expect("Failed to parse config.toml");
+        let mut cmd = VmCli::new();
+        let config = config
+            .vmcli
+            .as_ref()
+            .expect("The configuration of vmcli doesn't exist");
+        if let Some(x) = &config.executable_path {
+            cmd.executable_path(x);
+        }
+        cmd.vm_path(config.vm_path.clone())
+            .guest_username(config.guest_username.clone())
+            .guest_password(config.guest_password.clone());
+        cmd
+    }
+
+    #[test]
+    fn test() {
+        let cmd = get_cmd();
+        cmd.list_all_vms().unwrap();
+        cmd.query_config_params().unwrap();
+    }
+
+    #[test]
+    fn test_vm_cmd() {
+        let mut cmd = get_cmd();
+        test_cmd_util::test_vm_not_found(&mut cmd);
+    }
+
+    #[test]
+    fn test_power_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_power(&cmd);
+    }
+
+    #[test]
+    fn test_snapshot_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_snapshot_cmd(&cmd);
+    }
+}
@@ -12,6 +12,7 @@
 //! username = "user"
 //! password = "password"
 //! ```
+#![cfg(feature = "vmrest")]
 
 mod test_cmd_util;
 
@@ -48,7 +49,7 @@ mod test_vmrest {
             .as_ref()
             .expect("The configuration of VMRest doesn't exist");
         if let Some(x) = &config.executable_path {
-            cmd.vmrest_path(x);
+            cmd.executable_path(x);
         }
         if let Some(x) = &config.url {
             cmd.url(x);
@@ -56,11 +57,11 @@ mod test_vmrest {
         if let Some(x) = &config.encoding {
             cmd.encoding(x);
         }
-        cmd.proxy(config.proxy.as_ref().map(|x| x.clone()))
-            .username(config.username.as_ref().map(|x| x.clone()))
-            .password(config.password.as_ref().map(|x| x.clone()));
+        cmd.proxy(config.proxy.clone())
+            .username(config.username.clone())
+            .password(config.password.clone());
         if let Some(x) = &config.vm_path {
-            cmd.set_vm_by_path(&x).expect("VM Not Found");
+            cmd.set_vm_by_path(x).expect("VM Not Found");
         }
         cmd
     }
@@ -12,6 +12,7 @@
 //! guest_username = "user"
 //! guest_password = "password"
 //! ```
+#![cfg(feature = "vmrun")]
 
 mod test_cmd_util;
 
@@ -51,9 +52,9 @@ mod test_vmrun {
         if let Some(x) = &config.host_type {
             cmd.host_type(x);
         }
-        cmd.vm_path(config.vm_path.as_ref().map(|x| x.clone()))
-            .guest_username(config.guest_username.as_ref().map(|x| x.clone()))
-            .guest_password(config.guest_password.as_ref().map(|x| x.clone()))
+        cmd.vm_path(config.vm_path.clone())
+            .guest_username(config.guest_username.clone())
+            .guest_password(config.guest_password.clone())
             .gui(true);
         cmd
     }
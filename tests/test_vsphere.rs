@@ -0,0 +1,91 @@
+// Copyright takubokudori.
+// This source code is licensed under the MIT or Apache-2.0 license.
+//! If you want to run tests, please write your VM configuration to `tests/config.toml`.
+//!
+//! # config.toml example
+//!
+//! ```toml
+//! [vsphere]
+//! url = "https://vcenter.example.com"
+//! username = "administrator@vsphere.local"
+//! password = "password"
+//! accept_invalid_certs = true
+//! vm_id = "vm-123"
+//! guest_username = "user"
+//! guest_password = "password"
+//! ```
+#![cfg(feature = "vsphere")]
+
+mod test_cmd_util;
+
+#[cfg(test)]
+mod test_vsphere {
+    use crate::test_cmd_util;
+    use hvctrl::vmware::VSphere;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct VSphereConfig {
+        url: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+        accept_invalid_certs: Option<bool>,
+        vm_id: Option<String>,
+        guest_username: Option<String>,
+        guest_password: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ConfigToml {
+        vsphere: Option<VSphereConfig>,
+    }
+
+    fn get_cmd() -> VSphere {
+        let x = std::fs::read_to_string("tests/config.toml")
+            .expect("Failed to read config.toml");
+        let config: ConfigToml =
+            toml::from_str(&x).expect("Failed to parse config.toml");
+        let mut cmd = VSphere::new();
+        let config = config
+            .vsphere
+            .as_ref()
+            .expect("The configuration of VSphere doesn't exist");
+        if let Some(x) = &config.url {
+            cmd.url(x);
+        }
+        if let Some(x) = config.accept_invalid_certs {
+            cmd.accept_invalid_certs(x);
+        }
+        cmd.vm_id(config.vm_id.clone())
+            .username(config.username.clone())
+            .password(config.password.clone())
+            .guest_username(config.guest_username.clone())
+            .guest_password(config.guest_password.clone());
+        cmd
+    }
+
+    #[test]
+    fn test() {
+        let cmd = get_cmd();
+        cmd.list_vms().unwrap();
+        cmd.get_power_state().unwrap();
+    }
+
+    #[test]
+    fn test_vm_cmd() {
+        let mut cmd = get_cmd();
+        test_cmd_util::test_vm_not_found(&mut cmd);
+    }
+
+    #[test]
+    fn test_power_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_power(&cmd);
+    }
+
+    #[test]
+    fn test_snapshot_cmd() {
+        let cmd = get_cmd();
+        test_cmd_util::test_snapshot_cmd(&cmd);
+    }
+}